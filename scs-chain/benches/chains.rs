@@ -36,6 +36,13 @@ fn chain_benchmarks(c: &mut Criterion) {
       chain
     })
   });
+  group.bench_function("(own-chain): feed_parallel 9.2MB on 4 threads", |b| {
+    b.iter(|| {
+      let mut chain = chain::Chain::<2>::new();
+      chain.feed_parallel(&logs, 4);
+      chain
+    })
+  });
   group.bench_function("(markov-chain): feed 9.2MB", |b| {
     b.iter(|| {
       let mut chain = markov::Chain::of_order(2);
@@ -0,0 +1,78 @@
+use ahash::AHashMap;
+
+/// A fixed-size summary of a chain: the frequencies of its most common bigrams, normalized to unit length.
+///
+/// Fingerprints of different chains can be compared with [`Fingerprint::similarity`].
+#[derive(Debug, Clone, Default)]
+pub struct Fingerprint {
+  bigrams: AHashMap<(String, String), f64>,
+}
+
+impl Fingerprint {
+  pub fn from_counts(counts: impl IntoIterator<Item = ((String, String), u64)>) -> Self {
+    let mut bigrams = counts
+      .into_iter()
+      .map(|(bigram, count)| (bigram, count as f64))
+      .collect::<AHashMap<_, _>>();
+    let norm = bigrams.values().map(|v| v * v).sum::<f64>().sqrt();
+    if norm > 0.0 {
+      for value in bigrams.values_mut() {
+        *value /= norm;
+      }
+    }
+    Self { bigrams }
+  }
+
+  pub fn len(&self) -> usize {
+    self.bigrams.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.bigrams.is_empty()
+  }
+
+  pub fn get(&self, first: &str, second: &str) -> Option<f64> {
+    self.bigrams.get(&(first.to_owned(), second.to_owned())).copied()
+  }
+
+  /// The bigrams and their normalized frequencies, in no particular order
+  pub fn iter(&self) -> impl Iterator<Item = (&(String, String), f64)> + '_ {
+    self.bigrams.iter().map(|(bigram, value)| (bigram, *value))
+  }
+
+  /// Cosine similarity, from `0.0` (no bigrams in common) to `1.0` (identical frequencies)
+  pub fn similarity(&self, other: &Fingerprint) -> f64 {
+    let (smaller, larger) = if self.len() <= other.len() {
+      (self, other)
+    } else {
+      (other, self)
+    };
+    smaller
+      .bigrams
+      .iter()
+      .filter_map(|(bigram, a)| larger.bigrams.get(bigram).map(|b| a * b))
+      .sum()
+  }
+}
+
+/// Collects bigrams with frequencies which are already normalized, such as the ones of [`Fingerprint::iter`]
+impl FromIterator<((String, String), f64)> for Fingerprint {
+  fn from_iter<I: IntoIterator<Item = ((String, String), f64)>>(iter: I) -> Self {
+    Self {
+      bigrams: iter.into_iter().collect(),
+    }
+  }
+}
+
+/// Pairwise similarities between `fingerprints`, where `matrix[i][j]` is the similarity of `i` and `j`
+pub fn similarity_matrix(fingerprints: &[Fingerprint]) -> Vec<Vec<f64>> {
+  let mut matrix = vec![vec![0.0; fingerprints.len()]; fingerprints.len()];
+  for i in 0..fingerprints.len() {
+    for j in i..fingerprints.len() {
+      let similarity = fingerprints[i].similarity(&fingerprints[j]);
+      matrix[i][j] = similarity;
+      matrix[j][i] = similarity;
+    }
+  }
+  matrix
+}
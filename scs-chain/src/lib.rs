@@ -14,7 +14,15 @@ use rand::Rng;
 use rand::SeedableRng;
 use string_interner::{backend::BufferBackend, DefaultSymbol, StringInterner};
 
+pub use fingerprint::Fingerprint;
+pub use limits::GenerationOptions;
+pub use memory::MemoryEstimate;
+
+pub mod fingerprint;
+pub mod limits;
+pub mod memory;
 pub mod ser;
+pub mod text;
 
 type WordId = DefaultSymbol;
 pub type Token = Option<WordId>;
@@ -47,23 +55,104 @@ pub struct Chain<const ORDER: usize> {
   // TODO: arena allocate the hashmaps for extra perf?
   nodes: AHashMap<[Token; ORDER], EdgeId>,
   edges: Vec<EdgeMap>,
+  // Changes made since the last checkpoint or delta, only recorded after `track_changes` is called.
+  journal: Option<Journal<ORDER>>,
+  // Number of deltas applied to or saved from the chain since its checkpoint
+  deltas: u64,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Journal<const ORDER: usize> {
+  /// Length of the dictionary when recording started, words past it are new
+  base_words: usize,
+  /// Weight added to each edge
+  increments: AHashMap<([Token; ORDER], Token), u64>,
+}
+
+impl<const ORDER: usize> Journal<ORDER> {
+  fn new(base_words: usize) -> Self {
+    Self {
+      base_words,
+      increments: AHashMap::new(),
+    }
+  }
 }
 
+/// Weight of the fallback probability of transitions the chain has never seen, as in "stupid backoff"
+const BACKOFF_WEIGHT: f64 = 0.4;
+/// Fewest lines each thread of [`Chain::feed_parallel`] is given, smaller corpora are fed on fewer threads
+const MIN_LINES_PER_THREAD: usize = 10_000;
+
 type NextOrder<const ORDER: usize> = <Token as OrderOf<{ ORDER + 1 }>>::Order;
 
+/// A completion being extended by [`Chain::generate_beam`]
+struct Beam<const ORDER: usize> {
+  curs: [Token; ORDER],
+  words: Vec<WordId>,
+  /// Sum of the log-probabilities of the transitions to `words`
+  log_prob: f64,
+  /// Contexts the completion went through, which it may not go back to
+  visited: Vec<[Token; ORDER]>,
+}
+
+impl<const ORDER: usize> Beam<ORDER> {
+  fn average(&self) -> f64 {
+    self.log_prob / self.words.len().max(1) as f64
+  }
+}
+
 #[derive(Debug, Clone)]
 struct EdgeMap {
   sum: u64,
   edges: AHashMap<Token, u64>,
 }
 
-pub trait TextGenerator {
+impl EdgeMap {
+  /// `sum`, or the sum of the weights if it's zero, which only happens for maps edited by hand or read from a corrupted
+  /// model. Zero if the map has no edges, or if they all have a weight of zero.
+  fn total(&self) -> u64 {
+    if self.sum > 0 {
+      return self.sum;
+    }
+    self.weight_sum()
+  }
+
+  /// Sum of the weights, saturating at `u64::MAX`
+  fn weight_sum(&self) -> u64 {
+    self.edges.values().fold(0, |sum, &weight| sum.saturating_add(weight))
+  }
+
+  /// Picks the token at `cap` in the cumulative weights, or `None` if `cap` is past the sum of the weights.
+  ///
+  /// The weights are accumulated in the map's iteration order, so if `cap` is always drawn below a sum smaller than
+  /// the actual one, the tokens iterated last can never be picked.
+  fn token_at(&self, cap: u64) -> Option<Token> {
+    let mut sum = 0u64;
+    for (key, &value) in self.edges.iter() {
+      sum = sum.saturating_add(value);
+      if sum > cap {
+        return Some(*key);
+      }
+    }
+    None
+  }
+}
+
+pub trait TextGenerator: Send + Sync {
   fn order(&self) -> usize;
   fn generate_text(&self) -> String;
   fn generate_text_from_token(&self, word: &str) -> String;
   fn try_generate_text_from_token_sequence(&self, words: &[&str]) -> anyhow::Result<String>;
+  /// Same as [`TextGenerator::generate_text`], with the given `rng`, e.g. to generate the same text for the same seed
+  fn generate_text_with_rng(&self, rng: &mut StdRng) -> String;
+  fn generate_text_from_token_with_rng(&self, rng: &mut StdRng, word: &str) -> String;
+  fn try_generate_text_from_token_sequence_with_rng(&self, rng: &mut StdRng, words: &[&str]) -> anyhow::Result<String>;
   fn model_meta_data(&self) -> &str;
   fn phrase_meta_data(&self, words: &[&str]) -> String;
+  fn memory_estimate(&self) -> MemoryEstimate;
+  fn score_text(&self, text: &str) -> f64;
+  fn generate_text_greedy(&self, seed: &str, max_tokens: usize) -> String;
+  fn generate_text_beam(&self, seed: &str, max_tokens: usize, width: usize) -> String;
 }
 
 impl TextGenerator for Box<dyn TextGenerator> {
@@ -79,12 +168,33 @@ impl TextGenerator for Box<dyn TextGenerator> {
   fn try_generate_text_from_token_sequence(&self, words: &[&str]) -> anyhow::Result<String> {
     (**self).try_generate_text_from_token_sequence(words)
   }
+  fn generate_text_with_rng(&self, rng: &mut StdRng) -> String {
+    (**self).generate_text_with_rng(rng)
+  }
+  fn generate_text_from_token_with_rng(&self, rng: &mut StdRng, word: &str) -> String {
+    (**self).generate_text_from_token_with_rng(rng, word)
+  }
+  fn try_generate_text_from_token_sequence_with_rng(&self, rng: &mut StdRng, words: &[&str]) -> anyhow::Result<String> {
+    (**self).try_generate_text_from_token_sequence_with_rng(rng, words)
+  }
   fn model_meta_data(&self) -> &str {
     (**self).model_meta_data()
   }
   fn phrase_meta_data(&self, words: &[&str]) -> String {
     (**self).phrase_meta_data(words)
   }
+  fn memory_estimate(&self) -> MemoryEstimate {
+    (**self).memory_estimate()
+  }
+  fn score_text(&self, text: &str) -> f64 {
+    (**self).score_text(text)
+  }
+  fn generate_text_greedy(&self, seed: &str, max_tokens: usize) -> String {
+    (**self).generate_text_greedy(seed, max_tokens)
+  }
+  fn generate_text_beam(&self, seed: &str, max_tokens: usize, width: usize) -> String {
+    (**self).generate_text_beam(seed, max_tokens, width)
+  }
 }
 
 impl<const ORDER: usize> TextGenerator for Chain<ORDER>
@@ -104,11 +214,23 @@ where
   }
 
   fn try_generate_text_from_token_sequence(&self, words: &[&str]) -> anyhow::Result<String> {
+    self.try_generate_text_from_token_sequence_with_rng(&mut StdRng::from_entropy(), words)
+  }
+
+  fn generate_text_with_rng(&self, rng: &mut StdRng) -> String {
+    self.generate_with_rng(rng)
+  }
+
+  fn generate_text_from_token_with_rng(&self, rng: &mut StdRng, word: &str) -> String {
+    self.generate_from_token_with_rng(rng, word)
+  }
+
+  fn try_generate_text_from_token_sequence_with_rng(&self, rng: &mut StdRng, words: &[&str]) -> anyhow::Result<String> {
     let seq = words
       .get(..ORDER)
       .ok_or_else(|| anyhow::anyhow!(format!("Expected {} words, got {}", ORDER, words.len())))?;
     let seq: [&str; ORDER] = seq.try_into()?;
-    Ok(self.generate_from_token_seq(seq))
+    Ok(self.generate_from_token_seq_with_rng(rng, seq))
   }
 
   fn model_meta_data(&self) -> &str {
@@ -118,13 +240,45 @@ where
   fn phrase_meta_data(&self, words: &[&str]) -> String {
     self.stats_for_phrase(words)
   }
+
+  fn memory_estimate(&self) -> MemoryEstimate {
+    Chain::memory_estimate(self)
+  }
+
+  fn score_text(&self, text: &str) -> f64 {
+    Chain::score_text(self, text)
+  }
+
+  fn generate_text_greedy(&self, seed: &str, max_tokens: usize) -> String {
+    self.generate_greedy(seed, max_tokens)
+  }
+
+  fn generate_text_beam(&self, seed: &str, max_tokens: usize, width: usize) -> String {
+    self.generate_beam(seed, max_tokens, width)
+  }
 }
 
-pub fn load_chain_of_any_supported_order<P: AsRef<std::path::Path>>(path: P) -> anyhow::Result<Box<dyn TextGenerator>> {
+/// Whether `name` may be used as the name of a model, which is also the name of its file.
+/// Names are restricted so that they can't be used for path traversal when they're received by the user API.
+pub fn is_valid_model_name(name: &str) -> bool {
+  !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Reads the order of the chain saved at `path` without loading it.
+pub fn order_of<P: AsRef<std::path::Path>>(path: P) -> anyhow::Result<usize> {
   let mut file = std::fs::File::open(path)?;
-  let mut buf = Vec::new();
-  file.read_to_end(&mut buf)?;
-  load_chain_of_any_supported_order_with_reader(&mut std::io::Cursor::new(&buf))
+  let (order, _) = ser::read_header(&mut file)?;
+  Ok(order as usize)
+}
+
+/// Loads the chain at `path`, along with any deltas saved next to it.
+pub fn load_chain_of_any_supported_order<P: AsRef<std::path::Path>>(path: P) -> anyhow::Result<Box<dyn TextGenerator>> {
+  match order_of(&path)? {
+    1 => Ok(Box::new(Chain::<1>::load_with_deltas(path)?.0)),
+    2 => Ok(Box::new(Chain::<2>::load_with_deltas(path)?.0)),
+    3 => Ok(Box::new(Chain::<3>::load_with_deltas(path)?.0)),
+    order => anyhow::bail!(format!("Unsupported chain order: {}", order)),
+  }
 }
 
 pub fn load_chain_of_any_supported_order_with_reader<R: Read + Seek>(
@@ -143,42 +297,183 @@ pub fn load_chain_of_any_supported_order_with_reader<R: Read + Seek>(
 
 #[inline]
 pub fn sample(generator: &dyn TextGenerator, token: impl AsRef<str>, max_samples: usize) -> String {
-  _sample(generator, token, max_samples).0
+  _sample(generator, &mut StdRng::from_entropy(), token, max_samples).0
 }
 
 #[inline]
 pub fn sample_seq(generator: &dyn TextGenerator, words: &[&str], max_samples: usize) -> String {
-  _sample_seq(generator, words, max_samples).0
+  _sample_seq(generator, &mut StdRng::from_entropy(), words, max_samples).0
+}
+
+/// Same as [`sample`], with the given `rng`, e.g. to generate the same text for the same seed
+#[inline]
+pub fn sample_with_rng(
+  generator: &dyn TextGenerator,
+  rng: &mut StdRng,
+  token: impl AsRef<str>,
+  max_samples: usize,
+) -> String {
+  _sample(generator, rng, token, max_samples).0
+}
+
+/// Same as [`sample_seq`], with the given `rng`
+#[inline]
+pub fn sample_seq_with_rng(
+  generator: &dyn TextGenerator,
+  rng: &mut StdRng,
+  words: &[&str],
+  max_samples: usize,
+) -> String {
+  _sample_seq(generator, rng, words, max_samples).0
+}
+
+/// Completes `text`, seeding the chain with its trailing `order` words.
+///
+/// If the chain doesn't know that exact context, it backs off to seeding with just the last word.
+/// Returns `text` followed by the completion, or an empty string if no completion could be generated.
+pub fn sample_continuation(generator: &dyn TextGenerator, text: &str, max_samples: usize) -> String {
+  sample_continuation_with_rng(generator, &mut StdRng::from_entropy(), text, max_samples)
+}
+
+/// Same as [`sample_continuation`], with the given `rng`
+pub fn sample_continuation_with_rng(
+  generator: &dyn TextGenerator,
+  rng: &mut StdRng,
+  text: &str,
+  max_samples: usize,
+) -> String {
+  let words = text.split_whitespace().collect::<Vec<_>>();
+  if words.is_empty() {
+    return String::new();
+  }
+
+  let order = generator.order();
+  if words.len() >= order {
+    let (prefix, seed) = words.split_at(words.len() - order);
+    let output = sample_seq_with_rng(generator, rng, seed, max_samples);
+    if !output.is_empty() {
+      return prefix.iter().copied().chain(std::iter::once(&output[..])).join(" ");
+    }
+  }
+
+  let (prefix, last) = words.split_at(words.len() - 1);
+  let output = sample_with_rng(generator, rng, last[0], max_samples);
+  if output.is_empty() {
+    return String::new();
+  }
+  prefix.iter().copied().chain(std::iter::once(&output[..])).join(" ")
+}
+
+/// Returns the tag which marks the messages of `channel` in a model trained with channel tags.
+///
+/// Tags are prepended to each message as its first word, e.g. `#forsen hello`.
+pub fn channel_tag(channel: &str) -> String {
+  format!("#{channel}")
+}
+
+/// Returns `true` if `word` is a channel tag, see [`channel_tag`]
+pub fn is_channel_tag(word: &str) -> bool {
+  word.strip_prefix('#').map_or(false, |channel| {
+    !channel.is_empty() && channel.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_')
+  })
+}
+
+/// Removes every channel tag from `text`. Tags usually only start a message, but the chain may also produce one which
+/// a chatter sent in the middle of a message.
+pub fn strip_channel_tag(text: &str) -> String {
+  text.split_whitespace().filter(|word| !is_channel_tag(word)).join(" ")
+}
+
+/// Samples a message in the style of `channel` from a model trained with channel tags, seeded with `words`.
+///
+/// The chain is seeded with the channel tag followed by as many of `words` as fit in its order.
+/// If the channel doesn't know that context, it falls back to seeding with just `words`.
+/// The tags are removed from the output.
+///
+/// Returns `None` if the model doesn't know the channel's tag, e.g. because it wasn't trained on the channel.
+pub fn sample_tagged(
+  generator: &dyn TextGenerator,
+  channel: &str,
+  words: &[&str],
+  max_samples: usize,
+) -> Option<String> {
+  sample_tagged_with_rng(generator, &mut StdRng::from_entropy(), channel, words, max_samples)
+}
+
+/// Same as [`sample_tagged`], with the given `rng`
+pub fn sample_tagged_with_rng(
+  generator: &dyn TextGenerator,
+  rng: &mut StdRng,
+  channel: &str,
+  words: &[&str],
+  max_samples: usize,
+) -> Option<String> {
+  let tag = channel_tag(channel);
+  let output = match words.len() {
+    0 => sample_with_rng(generator, rng, &tag, max_samples),
+    _ if generator.order() >= 2 && words.len() >= generator.order() - 1 => {
+      let seq = std::iter::once(&tag[..])
+        .chain(words[..generator.order() - 1].iter().copied())
+        .collect::<Vec<_>>();
+      sample_seq_with_rng(generator, rng, &seq, max_samples)
+    }
+    _ => String::new(),
+  };
+  let stripped = strip_channel_tag(&output);
+  if !stripped.is_empty() {
+    return Some(stripped);
+  }
+  // Text generated from a known word starts with it, so only an unknown tag generates nothing
+  if output.is_empty() && (words.is_empty() || generator.generate_text_from_token_with_rng(rng, &tag).is_empty()) {
+    return None;
+  }
+
+  let output = match words.len() {
+    0 => sample_with_rng(generator, rng, "", max_samples),
+    1 => sample_with_rng(generator, rng, words[0], max_samples),
+    _ => sample_seq_with_rng(generator, rng, words, max_samples),
+  };
+  Some(strip_channel_tag(&output))
 }
 
-pub fn _sample(generator: &dyn TextGenerator, token: impl AsRef<str>, max_samples: usize) -> (String, usize) {
+pub fn _sample(
+  generator: &dyn TextGenerator,
+  rng: &mut StdRng,
+  token: impl AsRef<str>,
+  max_samples: usize,
+) -> (String, usize) {
   let mut count = 0;
   let token = token.as_ref().trim();
   let mut output = if token.is_empty() {
-    generator.generate_text()
+    generator.generate_text_with_rng(rng)
   } else {
-    generator.generate_text_from_token(token)
+    generator.generate_text_from_token_with_rng(rng, token)
   };
   while output.trim() == token && count < max_samples {
     output = if token.is_empty() {
-      generator.generate_text()
+      generator.generate_text_with_rng(rng)
     } else {
-      generator.generate_text_from_token(token)
+      generator.generate_text_from_token_with_rng(rng, token)
     };
     count += 1;
   }
   (output, count)
 }
 
-pub fn _sample_seq(generator: &dyn TextGenerator, words: &[&str], max_samples: usize) -> (String, usize) {
+pub fn _sample_seq(
+  generator: &dyn TextGenerator,
+  rng: &mut StdRng,
+  words: &[&str],
+  max_samples: usize,
+) -> (String, usize) {
   let mut count = 0;
   let mut output = generator
-    .try_generate_text_from_token_sequence(words)
+    .try_generate_text_from_token_sequence_with_rng(rng, words)
     .ok()
     .unwrap_or_default();
   while (output.split_whitespace().count() <= 1 || output.trim() == words.join(" ")) && count < max_samples {
     output = generator
-      .try_generate_text_from_token_sequence(words)
+      .try_generate_text_from_token_sequence_with_rng(rng, words)
       .ok()
       .unwrap_or_default();
     count += 1;
@@ -193,6 +488,8 @@ impl<const ORDER: usize> Chain<ORDER> {
       dict: StringInterner::new(),
       nodes: AHashMap::new(),
       edges: Vec::with_capacity(3),
+      journal: None,
+      deltas: 0,
     }
   }
 
@@ -203,6 +500,8 @@ impl<const ORDER: usize> Chain<ORDER> {
       // words don't pair combinatorially, so we use size * 1.2 as a heuristic (absolutely ungrounded)
       nodes: AHashMap::with_capacity((size as f64 * 1.2) as usize),
       edges: Vec::with_capacity((size as f64 * 1.2) as usize),
+      journal: None,
+      deltas: 0,
     }
   }
 
@@ -215,6 +514,25 @@ impl<const ORDER: usize> Chain<ORDER> {
     ORDER
   }
 
+  /// Number of distinct words in the chain
+  pub fn word_count(&self) -> usize {
+    self.dict.len()
+  }
+
+  /// Estimates the heap memory used by the chain, see [`memory`]
+  pub fn memory_estimate(&self) -> MemoryEstimate {
+    let mut estimator = memory::Estimator::new(ORDER);
+    estimator.words(
+      self.dict.len(),
+      (&self.dict).into_iter().map(|(_, word)| word.len()).sum(),
+    );
+    estimator.nodes(self.nodes.len());
+    for edge_map in &self.edges {
+      estimator.edge_map(edge_map.edges.len());
+    }
+    estimator.finish()
+  }
+
   pub fn save<P: AsRef<std::path::Path>>(&self, path: P) -> anyhow::Result<()> {
     let mut file = std::fs::File::create(&path)?;
     let buf = self.save_to_bytes()?;
@@ -240,6 +558,136 @@ impl<const ORDER: usize> Chain<ORDER> {
     self::ser::ChainDeserializer::new().deserialize(&mut std::io::Cursor::new(&bytes))
   }
 
+  /// Loads the checkpoint at `path` and applies its deltas, see [`ser::find_deltas`].
+  ///
+  /// Deltas saved against another checkpoint are skipped, they're left behind when a compaction is interrupted after
+  /// the compacted checkpoint replaced the one they were saved against, and already part of it.
+  ///
+  /// Returns the chain, with changes tracked from that point, and the hash of the checkpoint to save further deltas against.
+  pub fn load_with_deltas<P: AsRef<std::path::Path>>(path: P) -> anyhow::Result<(Self, u64)> {
+    use anyhow::Context;
+
+    let bytes = std::fs::read(&path)?;
+    let base_hash = ser::checkpoint_hash(&bytes);
+    let mut chain = Self::load_from_bytes(&bytes)?;
+    drop(bytes);
+
+    for delta in ser::find_deltas(path.as_ref())? {
+      let bytes = std::fs::read(&delta)?;
+      if ser::delta_base_hash(&bytes).map_or(false, |hash| hash != base_hash) {
+        continue;
+      }
+      chain
+        .apply_delta_bytes(&bytes, base_hash)
+        .with_context(|| format!("Failed to apply {}", delta.display()))?;
+    }
+    chain.track_changes();
+    Ok((chain, base_hash))
+  }
+
+  /// Starts recording changes made to the chain, so that they can be saved as a delta.
+  ///
+  /// Changes are recorded relative to the current state of the chain,
+  /// which must be its checkpoint with all of its previous deltas applied.
+  pub fn track_changes(&mut self) {
+    self.journal = Some(Journal::new(self.dict.len()));
+  }
+
+  /// Saves the changes made since the last checkpoint or delta next to `checkpoint`, and returns the path of the delta.
+  ///
+  /// `base_hash` is the [`ser::checkpoint_hash`] of the checkpoint.
+  pub fn save_delta<P: AsRef<std::path::Path>>(
+    &mut self,
+    checkpoint: P,
+    base_hash: u64,
+  ) -> anyhow::Result<std::path::PathBuf> {
+    let buf = self.serialize_delta(base_hash)?;
+    let path = ser::delta_path(checkpoint.as_ref(), self.deltas + 1);
+    std::fs::write(&path, buf)?;
+    self.commit_delta();
+    Ok(path)
+  }
+
+  pub fn save_delta_to_bytes(&mut self, base_hash: u64) -> anyhow::Result<Vec<u8>> {
+    let buf = self.serialize_delta(base_hash)?;
+    self.commit_delta();
+    Ok(buf)
+  }
+
+  /// Applies a delta saved against the checkpoint with the hash `base_hash`.
+  ///
+  /// Deltas must be applied in the order they were saved in. The chain is left unchanged if the delta is invalid.
+  pub fn apply_delta_bytes(&mut self, bytes: &[u8], base_hash: u64) -> anyhow::Result<()> {
+    self::ser::ChainDeserializer::new().apply_delta(self, &mut std::io::Cursor::new(bytes), base_hash)
+  }
+
+  /// Adds the edges of `other` to the chain, as if it was also fed everything `other` was fed.
+  ///
+  /// The metadata of the chain is kept. If changes are tracked, the merged edges are recorded as well.
+  pub fn merge(&mut self, other: &Chain<ORDER>) {
+    let word_ids = (&other.dict)
+      .into_iter()
+      .map(|(word_id, word)| (word_id, self.dict.get_or_intern(word)))
+      .collect::<AHashMap<_, _>>();
+    let translate = |token: Token| token.map(|word_id| word_ids[&word_id]);
+
+    for (key, edge_id) in &other.nodes {
+      let key = key.map(translate);
+      let node_id = self.add_node(key);
+      for (&token, &weight) in &other.get_edge(*edge_id).edges {
+        let token = translate(token);
+        let map = &mut self.edges[node_id.0];
+        map.sum += weight;
+        *map.edges.entry(token).or_insert(0) += weight;
+        if let Some(journal) = &mut self.journal {
+          *journal.increments.entry((key, token)).or_insert(0) += weight;
+        }
+      }
+    }
+  }
+
+  fn serialize_delta(&self, base_hash: u64) -> anyhow::Result<Vec<u8>> {
+    let journal = self
+      .journal
+      .as_ref()
+      .ok_or_else(|| anyhow::anyhow!("Changes to the chain aren't tracked"))?;
+    let mut buf = Vec::new();
+    self::ser::ChainSerializer::new(self).serialize_delta(&mut buf, journal, base_hash, self.deltas + 1)?;
+    Ok(buf)
+  }
+
+  fn commit_delta(&mut self) {
+    self.deltas += 1;
+    self.track_changes();
+  }
+
+  /// Returns a fingerprint made from the `size` most frequent bigrams in the chain.
+  ///
+  /// Bigrams are counted from the last word of each node and the words which follow it,
+  /// so chains of different orders trained on the same text have the same fingerprint.
+  pub fn bigram_fingerprint(&self, size: usize) -> Fingerprint {
+    let mut counts = AHashMap::<(WordId, WordId), u64>::new();
+    for (key, edge_id) in &self.nodes {
+      if let Some(Some(first)) = key.last() {
+        for (second, count) in self.edges[edge_id.0].edges.iter() {
+          if let Some(second) = second {
+            *counts.entry((*first, *second)).or_default() += count;
+          }
+        }
+      }
+    }
+
+    let resolve = |word_id| self.dict.resolve(word_id).unwrap().to_owned();
+    Fingerprint::from_counts(
+      counts
+        .into_iter()
+        .map(|((first, second), count)| ((resolve(first), resolve(second)), count))
+        // sort ties by the bigram, so that the fingerprint is deterministic
+        .sorted_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then_with(|| a.cmp(b)))
+        .take(size),
+    )
+  }
+
   pub fn stats_for_phrase(&self, words: &[&str]) -> String {
     use std::fmt::Write;
 
@@ -299,6 +747,58 @@ impl<const ORDER: usize> Chain<ORDER> {
     output
   }
 
+  /// Returns the average natural log-probability of the transitions between the words of `text`, from the start of a
+  /// message to its end, so that texts of different lengths are comparable. The closer it is to 0, the more likely the
+  /// chain is to generate `text`.
+  ///
+  /// Transitions the chain has never seen, including every transition out of a context with an unknown word, back off
+  /// to a uniform distribution over its words, weighted by [`BACKOFF_WEIGHT`]. Text generated by the chain never
+  /// backs off.
+  pub fn score_text(&self, text: &str) -> f64 {
+    // The end of the message is one more possible next token
+    let fallback = (BACKOFF_WEIGHT / (self.dict.len() + 1) as f64).ln();
+    let mut curs = [Token::None; ORDER];
+    // Number of upcoming transitions whose context contains an unknown word
+    let mut unknown: usize = 0;
+    let mut total = 0.0;
+    let mut transitions = 0;
+
+    // `None` marks the end of the message
+    for word in text.split_whitespace().map(Some).chain(std::iter::once(None)) {
+      // `None` if the word is unknown
+      let next = match word {
+        Some(word) => self.dict.get(word).map(Token::Some),
+        None => Some(Token::None),
+      };
+      let probability = match next {
+        Some(token) if unknown == 0 => self.nodes.get(&curs).and_then(|id| {
+          let map = self.get_edge(*id);
+          let total = map.total();
+          map
+            .edges
+            .get(&token)
+            .filter(|&&count| count > 0 && total > 0)
+            .map(|&count| count as f64 / total as f64)
+        }),
+        _ => None,
+      };
+      total += probability.map_or(fallback, f64::ln);
+      transitions += 1;
+
+      for i in 0..ORDER - 1 {
+        curs[i] = curs[i + 1];
+      }
+      curs[ORDER - 1] = next.unwrap_or(Token::None);
+      unknown = if next.is_some() {
+        unknown.saturating_sub(1)
+      } else {
+        ORDER
+      };
+    }
+
+    total / transitions as f64
+  }
+
   fn find_edge_stats(&self, key: [Token; ORDER], top_n: usize) -> Option<WordStats<ORDER>> {
     if let Some(edge_id) = self.nodes.get(&key) {
       let edge_map = &self.edges[edge_id.0];
@@ -354,18 +854,35 @@ impl<const ORDER: usize> Chain<ORDER> {
     unsafe { self.edges.get_unchecked(edge.0) }
   }
 
-  fn choose_next_word(&self, map: &EdgeMap, rng: &mut StdRng) -> Token {
-    let cap = rng.gen_range(0..map.sum);
-    let mut sum = 0;
-
-    for (key, &value) in map.edges.iter() {
-      sum += value;
-      if sum > cap {
-        return *key;
+  /// Picks the next token out of `map`, with a probability proportional to its weight.
+  ///
+  /// Maps whose `sum` is larger than the sum of their weights fall back to the sum of the weights, and maps whose
+  /// weights are all zero to a uniform choice, so that a broken map never stops generation. Fails only if `map` has no
+  /// edges.
+  ///
+  /// A `sum` smaller than the sum of the weights isn't detected, since it would take summing the weights on every
+  /// pick. The choice is then biased toward the tokens iterated first: the ones whose cumulative weight is past `sum`,
+  /// the last ones in iteration order, are never picked. The same goes for the weights past `u64::MAX`, where the sum
+  /// saturates. Models built by feeding text keep their sums in sync with their weights, so this only affects maps
+  /// edited by hand or read from a corrupted model.
+  fn choose_next_word(&self, map: &EdgeMap, rng: &mut StdRng) -> anyhow::Result<Token> {
+    if map.edges.is_empty() {
+      anyhow::bail!("The edge map has no edges");
+    }
+    if map.sum > 0 {
+      if let Some(token) = map.token_at(rng.gen_range(0..map.sum)) {
+        return Ok(token);
       }
     }
-
-    unreachable!("The random number generator failed.")
+    // `sum` is larger than the sum of the weights
+    let total = map.weight_sum();
+    if total > 0 {
+      if let Some(token) = map.token_at(rng.gen_range(0..total)) {
+        return Ok(token);
+      }
+    }
+    let index = rng.gen_range(0..map.edges.len());
+    Ok(*map.edges.keys().nth(index).unwrap())
   }
 
   #[inline]
@@ -450,10 +967,150 @@ impl<const ORDER: usize> Chain<ORDER> {
     output
   }
 
+  /// Completes `seed` by always following the most likely next word, for at most `max_tokens` words.
+  ///
+  /// Words which lead back to a context the completion already went through are skipped, since it would loop forever.
+  /// Returns `seed` followed by the completion, or an empty string if the chain doesn't know how `seed` ends.
+  /// An empty `seed` completes a whole message.
+  pub fn generate_greedy(&self, seed: &str, max_tokens: usize) -> String {
+    let Some(mut curs) = self.seed_context(seed) else {
+      return String::new();
+    };
+    let mut visited = vec![curs];
+    let mut output = Vec::new();
+    while output.len() < max_tokens {
+      let next = self
+        .ranked_edges(&curs)
+        .into_iter()
+        .map(|(token, _)| token)
+        .find(|&token| token.is_none() || !visited.contains(&Self::advance(curs, token)));
+      let Some(Some(word)) = next else {
+        break;
+      };
+      curs = Self::advance(curs, Some(word));
+      visited.push(curs);
+      output.push(word);
+    }
+    self.join_completion(seed, output)
+  }
+
+  /// Completes `seed` with a beam search which keeps the `width` most likely completions at each step, for at most
+  /// `max_tokens` words.
+  ///
+  /// Completions are ranked by the average log-probability of their transitions, like [`Self::score_text`], so that
+  /// longer ones aren't penalized. Otherwise the same as [`Self::generate_greedy`], which is a beam of width 1.
+  ///
+  /// The search is approximate: completions which fall out of the beam are never extended again, so the result is
+  /// the best completion the beam found, not necessarily the most likely one overall. Wider beams miss fewer.
+  pub fn generate_beam(&self, seed: &str, max_tokens: usize, width: usize) -> String {
+    let Some(curs) = self.seed_context(seed) else {
+      return String::new();
+    };
+    let width = width.max(1);
+    let mut beams = vec![Beam {
+      curs,
+      words: Vec::new(),
+      log_prob: 0.0,
+      visited: vec![curs],
+    }];
+    // Average log-probability of each completion which reached the end of a message, including that transition
+    let mut finished = Vec::<(f64, Vec<WordId>)>::new();
+
+    for _ in 0..max_tokens {
+      let mut candidates = Vec::new();
+      for beam in &beams {
+        let sum = self.nodes.get(&beam.curs).map_or(0, |id| self.get_edge(*id).total());
+        for (token, weight) in self.ranked_edges(&beam.curs).into_iter().take(width) {
+          // Transitions which are never sampled would have a log-probability of -inf
+          if weight == 0 {
+            continue;
+          }
+          let log_prob = beam.log_prob + (weight as f64 / sum as f64).ln();
+          let Some(word) = token else {
+            finished.push((log_prob / (beam.words.len() + 1) as f64, beam.words.clone()));
+            continue;
+          };
+          let curs = Self::advance(beam.curs, token);
+          if beam.visited.contains(&curs) {
+            continue;
+          }
+          let mut words = beam.words.clone();
+          words.push(word);
+          let mut visited = beam.visited.clone();
+          visited.push(curs);
+          candidates.push(Beam {
+            curs,
+            words,
+            log_prob,
+            visited,
+          });
+        }
+      }
+      candidates.sort_by(|a, b| b.average().total_cmp(&a.average()));
+      candidates.truncate(width);
+      beams = candidates;
+      if beams.is_empty() {
+        break;
+      }
+    }
+
+    // Completions cut off by `max_tokens` compete with the finished ones
+    let best = finished
+      .into_iter()
+      .chain(beams.into_iter().map(|beam| (beam.average(), beam.words)))
+      .reduce(|best, candidate| if candidate.0 > best.0 { candidate } else { best });
+    self.join_completion(seed, best.map(|(_, words)| words).unwrap_or_default())
+  }
+
+  /// Returns the longest trailing context of `seed` which the chain knows, or `None` if it doesn't know its last word
+  fn seed_context(&self, seed: &str) -> Option<[Token; ORDER]> {
+    let words = seed.split_whitespace().collect::<Vec<_>>();
+    if words.is_empty() {
+      return Some([Token::None; ORDER]);
+    }
+    (1..=words.len().min(ORDER)).rev().find_map(|len| {
+      let mut curs = [Token::None; ORDER];
+      for (token, word) in curs[ORDER - len..].iter_mut().zip(&words[words.len() - len..]) {
+        *token = Some(self.dict.get(word)?);
+      }
+      self.nodes.contains_key(&curs).then_some(curs)
+    })
+  }
+
+  /// Edges out of `curs`, most likely first. Ties are broken by the word, so that completions are deterministic.
+  fn ranked_edges(&self, curs: &[Token; ORDER]) -> Vec<(Token, u64)> {
+    let Some(id) = self.nodes.get(curs) else {
+      return Vec::new();
+    };
+    let resolve = |token: &Token| token.map(|word_id| self.dict.resolve(word_id).unwrap());
+    self
+      .get_edge(*id)
+      .edges
+      .iter()
+      .map(|(&token, &weight)| (token, weight))
+      .sorted_by(|(a, a_weight), (b, b_weight)| b_weight.cmp(a_weight).then_with(|| resolve(a).cmp(&resolve(b))))
+      .collect()
+  }
+
+  /// Shifts the context to the left and appends `next`
+  fn advance(mut curs: [Token; ORDER], next: Token) -> [Token; ORDER] {
+    for i in 0..ORDER - 1 {
+      curs[i] = curs[i + 1];
+    }
+    curs[ORDER - 1] = next;
+    curs
+  }
+
+  fn join_completion(&self, seed: &str, words: Vec<WordId>) -> String {
+    let completion = words.into_iter().map(|word| self.dict.resolve(word).unwrap());
+    seed.split_whitespace().chain(completion).join(" ")
+  }
+
   fn traverse_word_graph(&self, rng: &mut StdRng, output: &mut Vec<WordId>, mut curs: [Token; ORDER]) {
     while let Some(id) = self.nodes.get(&curs).copied() {
       let edge = self.get_edge(id);
-      let next = self.choose_next_word(edge, rng);
+      // A context without edges can't be continued, so it ends the message
+      let next = self.choose_next_word(edge, rng).unwrap_or(Token::None);
 
       // Shift the word sequence to the left and insert the next word.
       for i in 0..ORDER - 1 {
@@ -499,6 +1156,9 @@ macro_rules! chain_of_order {
           let (key, token) = <Token as KeyMaker<NextOrder<$order>>>::make_key(ngram);
           let node_id = self.add_node(key);
           self.add_edge(node_id, token);
+          if let Some(journal) = &mut self.journal {
+            *journal.increments.entry((key, token)).or_insert(0) += 1;
+          }
         }
 
         self.dict = interner;
@@ -508,6 +1168,42 @@ macro_rules! chain_of_order {
       pub fn feed_str<S: AsRef<str>>(&mut self, s: S) {
         self.feed(s.as_ref().split(' '))
       }
+
+      /// Feeds each line of `corpus` with [`Self::feed_str`], using up to `n_threads` threads.
+      ///
+      /// The corpus is split into one shard per thread, each of which trains its own chain,
+      /// and the chains are merged into this one in order. Small corpora are fed on fewer threads,
+      /// since merging costs about as much as feeding.
+      pub fn feed_parallel<S: AsRef<str> + Sync>(&mut self, corpus: &[S], n_threads: usize) {
+        let n_threads = n_threads.min(corpus.len() / MIN_LINES_PER_THREAD);
+        if n_threads <= 1 {
+          for line in corpus {
+            self.feed_str(line);
+          }
+          return;
+        }
+
+        let shards = std::thread::scope(|scope| {
+          corpus
+            .chunks((corpus.len() + n_threads - 1) / n_threads)
+            .map(|shard| {
+              scope.spawn(move || {
+                let mut chain = Self::new();
+                for line in shard {
+                  chain.feed_str(line);
+                }
+                chain
+              })
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|handle| handle.join().expect("A training thread panicked"))
+            .collect::<Vec<_>>()
+        });
+        for shard in &shards {
+          self.merge(shard);
+        }
+      }
     }
   };
 }
@@ -538,6 +1234,7 @@ impl<const ORDER: usize> WordStats<ORDER> {
 
 chain_of_order!(1);
 chain_of_order!(2);
+chain_of_order!(3);
 chain_of_order!(4);
 chain_of_order!(5);
 chain_of_order!(6);
@@ -674,4 +1371,403 @@ Rust has great documentation, a friendly compiler with useful error messages, an
         .collect::<Vec<_>>(),
     );
   }
+  #[test]
+  fn test_validation() {
+    let chain_2 = train!(2, TEXT).with_metadata("test");
+    let bytes = Chain::save_to_bytes(&chain_2).unwrap();
+
+    let summary = ser::validate(&mut std::io::Cursor::new(&bytes)).unwrap();
+    assert_eq!(summary.order, 2);
+    assert_eq!(summary.metadata, "test");
+    assert_eq!(summary.words, chain_2.dict.len());
+    assert_eq!(summary.nodes, chain_2.nodes.len());
+    assert_eq!(
+      summary.edges,
+      chain_2.edges.iter().map(|edge_map| edge_map.edges.len()).sum::<usize>()
+    );
+    assert_eq!(
+      summary.total_weight,
+      chain_2.edges.iter().map(|edge_map| edge_map.sum).sum::<u64>()
+    );
+    assert_eq!(summary.memory_estimate, chain_2.memory_estimate());
+
+    assert!(ser::validate(&mut std::io::Cursor::new(&bytes[..bytes.len() - 1])).is_err());
+    let mut trailing = bytes.clone();
+    trailing.push(0);
+    assert!(ser::validate(&mut std::io::Cursor::new(&trailing)).is_err());
+  }
+  #[test]
+  fn test_memory_estimate() {
+    assert_eq!(Chain::<2>::new().memory_estimate().total(), 0);
+
+    let chain_1 = train!(1, TEXT);
+    let chain_3 = train!(3, TEXT);
+    let (estimate_1, estimate_3) = (chain_1.memory_estimate(), chain_3.memory_estimate());
+    assert_eq!(estimate_1.dict, estimate_3.dict);
+    assert!(estimate_1.dict >= TEXT.split_whitespace().map(str::len).max().unwrap());
+    // Longer keys and more distinct nodes
+    assert!(estimate_3.nodes > estimate_1.nodes);
+    assert!(estimate_1.total() > estimate_1.dict);
+
+    // The estimate of the key and `EdgeId` must match their layout
+    assert_eq!(
+      (3 * std::mem::size_of::<Token>()).next_multiple_of(std::mem::size_of::<usize>()) + std::mem::size_of::<usize>(),
+      std::mem::size_of::<([Token; 3], EdgeId)>()
+    );
+  }
+  #[test]
+  fn test_fingerprint() {
+    let mut a = Chain::<1>::new();
+    let mut b = Chain::<2>::new();
+    for line in TEXT.lines() {
+      a.feed_str(line.trim());
+      b.feed_str(line.trim());
+    }
+    let a = a.bigram_fingerprint(usize::MAX);
+    let b = b.bigram_fingerprint(usize::MAX);
+    assert!((a.similarity(&b) - 1.0).abs() < 1e-9);
+    assert!(a.get("Rust", "is").is_some());
+
+    let mut c = Chain::<2>::new();
+    c.feed_str("completely unrelated words");
+    c.feed_str("completely unrelated words again");
+    let c = c.bigram_fingerprint(1);
+    assert_eq!(c.len(), 1);
+    assert!((c.get("completely", "unrelated").unwrap() - 1.0).abs() < 1e-9);
+    assert_eq!(a.similarity(&c), 0.0);
+
+    let matrix = fingerprint::similarity_matrix(&[a, c, Fingerprint::default()]);
+    assert_eq!(matrix[0][1], matrix[1][0]);
+    assert!((matrix[1][1] - 1.0).abs() < 1e-9);
+    assert_eq!(matrix[2][2], 0.0);
+  }
+
+  #[test]
+  fn test_generate_greedy() {
+    let mut chain = Chain::<1>::new();
+    for line in ["a b c", "a b c", "a b d", "x y x y x y"] {
+      chain.feed_str(line);
+    }
+    assert_eq!(chain.generate_greedy("a", 10), "a b c");
+    assert_eq!(chain.generate_greedy("a", 1), "a b");
+    assert_eq!(chain.generate_greedy("", 10), "a b c");
+    // `y x` would go back to the context `x`, so it stops at the end of the message instead
+    assert_eq!(chain.generate_greedy("x", 10), "x y");
+    // Only the last word has to be known
+    assert_eq!(chain.generate_greedy("unknown a", 10), "unknown a b c");
+    assert_eq!(chain.generate_greedy("unknown", 10), "");
+
+    let chain_2 = train!(2, TEXT);
+    let output = chain_2.generate_greedy("Rust is", 50);
+    assert_eq!(output, chain_2.generate_greedy("Rust is", 50));
+    assert!(output.starts_with("Rust is "));
+  }
+
+  #[test]
+  fn test_generate_beam() {
+    let mut chain = Chain::<1>::new();
+    // `a b` is more likely than `a c` after `a`, but `c` is always followed by the end of the message
+    for line in ["a b e", "a b f", "a b g", "a c", "a c"] {
+      chain.feed_str(line);
+    }
+    assert_eq!(chain.generate_greedy("a", 10), "a b e");
+    assert_eq!(chain.generate_beam("a", 10, 1), chain.generate_greedy("a", 10));
+    assert_eq!(chain.generate_beam("a", 10, 3), "a c");
+    assert_eq!(chain.generate_beam("unknown", 10, 3), "");
+  }
+
+  /// Every edge of the chain, with its words resolved
+  fn resolved_edges<const ORDER: usize>(chain: &Chain<ORDER>) -> Vec<(Vec<Option<&str>>, Option<&str>, u64)> {
+    let resolve = |token: &Token| token.map(|word_id| chain.dict.resolve(word_id).unwrap());
+    chain
+      .nodes
+      .iter()
+      .flat_map(|(key, edge_id)| {
+        chain.edges[edge_id.0]
+          .edges
+          .iter()
+          .map(move |(token, weight)| (key.iter().map(resolve).collect(), resolve(token), *weight))
+      })
+      .sorted()
+      .collect()
+  }
+
+  #[test]
+  fn test_delta() {
+    let lines = TEXT.lines().map(str::trim).collect::<Vec<_>>();
+    let mut full = Chain::<2>::new();
+    for line in &lines {
+      full.feed_str(line);
+    }
+
+    let mut chain = Chain::<2>::new();
+    for line in &lines[..2] {
+      chain.feed_str(line);
+    }
+    let checkpoint = chain.save_to_bytes().unwrap();
+    let base_hash = ser::checkpoint_hash(&checkpoint);
+
+    chain.track_changes();
+    for line in &lines[2..4] {
+      chain.feed_str(line);
+    }
+    let delta_1 = chain.save_delta_to_bytes(base_hash).unwrap();
+    for line in &lines[4..] {
+      chain.feed_str(line);
+    }
+    let delta_2 = chain.save_delta_to_bytes(base_hash).unwrap();
+
+    let mut loaded = Chain::<2>::load_from_bytes(&checkpoint).unwrap();
+    // out of order, or against the wrong checkpoint
+    assert!(loaded.apply_delta_bytes(&delta_2, base_hash).is_err());
+    assert!(loaded.apply_delta_bytes(&delta_1, base_hash + 1).is_err());
+    assert!(loaded
+      .apply_delta_bytes(&delta_1[..delta_1.len() - 1], base_hash)
+      .is_err());
+    assert_eq!(loaded.deltas, 0);
+
+    loaded.apply_delta_bytes(&delta_1, base_hash).unwrap();
+    loaded.apply_delta_bytes(&delta_2, base_hash).unwrap();
+    assert!(loaded.apply_delta_bytes(&delta_2, base_hash).is_err());
+
+    assert_eq!(loaded.dict.len(), full.dict.len());
+    assert_eq!(resolved_edges(&loaded), resolved_edges(&full));
+    assert_eq!(
+      loaded.edges.iter().map(|edge_map| edge_map.sum).sum::<u64>(),
+      full.edges.iter().map(|edge_map| edge_map.sum).sum::<u64>()
+    );
+  }
+
+  #[test]
+  fn test_interrupted_compaction() {
+    let lines = TEXT.lines().map(str::trim).collect::<Vec<_>>();
+    let dir = std::env::temp_dir().join(format!("scs-chain-compaction-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("forsen.chain");
+
+    let mut chain = Chain::<2>::new();
+    for line in &lines[..2] {
+      chain.feed_str(line);
+    }
+    chain.save(&path).unwrap();
+    let (mut chain, base_hash) = Chain::<2>::load_with_deltas(&path).unwrap();
+    for line in &lines[2..] {
+      chain.feed_str(line);
+    }
+    let delta = chain.save_delta(&path, base_hash).unwrap();
+    assert_eq!(ser::delta_base_hash(&std::fs::read(&delta).unwrap()), Some(base_hash));
+    assert_eq!(ser::delta_base_hash(&std::fs::read(&path).unwrap()), None);
+
+    // The compacted model replaced the checkpoint, but the delta wasn't removed
+    let (compacted, _) = Chain::<2>::load_with_deltas(&path).unwrap();
+    compacted.save(&path).unwrap();
+    let (loaded, _) = Chain::<2>::load_with_deltas(&path).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(resolved_edges(&loaded), resolved_edges(&compacted));
+    assert_eq!(
+      loaded.edges.iter().map(|edge_map| edge_map.sum).sum::<u64>(),
+      compacted.edges.iter().map(|edge_map| edge_map.sum).sum::<u64>()
+    );
+  }
+
+  #[test]
+  fn test_continuation() {
+    let chain_2 = train!(2, TEXT);
+
+    // known context
+    let output = sample_continuation(&chain_2, "Performance Rust is", 4);
+    assert!(output.starts_with("Performance Rust is"), "{}", output);
+
+    // unknown context, backs off to the last word
+    let output = sample_continuation(&chain_2, "the quick brown Rust", 4);
+    assert!(output.starts_with("the quick brown Rust"), "{}", output);
+
+    // unknown last word
+    assert_eq!(sample_continuation(&chain_2, "definitely not in the dictionary", 4), "");
+  }
+  #[test]
+  fn test_channel_tags() {
+    let mut chain = Chain::<2>::new();
+    chain.feed_str("#a apples are red");
+    chain.feed_str("#b bananas are yellow");
+
+    assert_eq!(sample_tagged(&chain, "a", &[], 4).as_deref(), Some("apples are red"));
+    assert_eq!(
+      sample_tagged(&chain, "b", &["bananas"], 4).as_deref(),
+      Some("bananas are yellow")
+    );
+    // a known channel with an unknown context falls back to untagged sampling
+    assert_eq!(
+      sample_tagged(&chain, "a", &["bananas"], 4).as_deref(),
+      Some("bananas are yellow")
+    );
+    assert_eq!(sample_tagged(&chain, "c", &[], 4), None);
+    assert_eq!(sample_tagged(&chain, "c", &["apples"], 4), None);
+
+    assert_eq!(strip_channel_tag("#a hello"), "hello");
+    assert_eq!(strip_channel_tag("#a"), "");
+    assert_eq!(strip_channel_tag("hello #a"), "hello");
+    assert_eq!(strip_channel_tag("#a hello #b_2 world #a"), "hello world");
+    assert_eq!(strip_channel_tag("# #1 hello"), "# hello");
+    assert!(!is_channel_tag("#"));
+    assert!(!is_channel_tag("#a!"));
+  }
+
+  #[test]
+  fn test_score_text() {
+    let mut chain = Chain::<1>::new();
+    chain.feed_str("a b c");
+    chain.feed_str("a b d");
+
+    // Every transition is certain, except for `b` which is followed by `c` half of the time
+    let score = chain.score_text("a b c");
+    assert!((score - 0.5f64.ln() / 4.0).abs() < 1e-9, "{}", score);
+    assert_eq!(chain.score_text("a b c"), chain.score_text("a b d"));
+
+    // Unseen transitions and unknown words back off
+    let fallback = (BACKOFF_WEIGHT / 5.0).ln();
+    assert!((chain.score_text("c a") - fallback).abs() < 1e-9);
+    assert!(chain.score_text("a x c") < chain.score_text("a b c"));
+    assert!((chain.score_text("x") - fallback).abs() < 1e-9);
+
+    let chain_2 = train!(2, TEXT);
+    let line = TEXT.lines().nth(1).unwrap().trim();
+    let shuffled = line.split(' ').rev().join(" ");
+    assert!(chain_2.score_text(line) > chain_2.score_text(&shuffled));
+  }
+
+  #[test]
+  fn test_order_of() {
+    let chain_3 = train!(3, TEXT);
+    let path = std::env::temp_dir().join(format!("scs-chain-order-{}.chain", std::process::id()));
+    chain_3.save(&path).unwrap();
+    let order = order_of(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(order.unwrap(), 3);
+  }
+
+  #[test]
+  fn test_feed_parallel() {
+    let lines = TEXT
+      .lines()
+      .map(str::trim)
+      .cycle()
+      .take(MIN_LINES_PER_THREAD * 3)
+      .collect::<Vec<_>>();
+    let mut sequential = Chain::<2>::new();
+    for line in &lines {
+      sequential.feed_str(line);
+    }
+
+    let mut parallel = Chain::<2>::new();
+    parallel.feed_str("an extra line");
+    parallel.track_changes();
+    parallel.feed_parallel(&lines, 4);
+    assert_eq!(parallel.journal.as_ref().unwrap().base_words, 3);
+    let mut extra = Chain::<2>::new();
+    extra.feed_str("an extra line");
+    sequential.merge(&extra);
+
+    assert_eq!(parallel.dict.len(), sequential.dict.len());
+    assert_eq!(resolved_edges(&parallel), resolved_edges(&sequential));
+    let recorded = parallel.journal.as_ref().unwrap().increments.values().sum::<u64>();
+    let fed = parallel.edges.iter().map(|edge_map| edge_map.sum).sum::<u64>();
+    assert_eq!(recorded, fed - 4);
+  }
+
+  /// An edge map with random weights, including zero and huge ones, and a `sum` which is either right, zero, too large
+  /// or too small
+  fn adversarial_edge_map(rng: &mut StdRng, tokens: &[Token]) -> EdgeMap {
+    let edges = tokens
+      .iter()
+      .take(rng.gen_range(1..=tokens.len()))
+      .map(|&token| {
+        let weight = match rng.gen_range(0..4) {
+          0 => 0,
+          1 => u64::MAX - rng.gen_range(0..10),
+          _ => rng.gen_range(1..100),
+        };
+        (token, weight)
+      })
+      .collect::<AHashMap<_, _>>();
+    let mut map = EdgeMap { sum: 0, edges };
+    let weights = map.weight_sum();
+    map.sum = match rng.gen_range(0..4) {
+      0 => 0,
+      1 => weights.saturating_mul(2),
+      2 => weights / 2,
+      _ => weights,
+    };
+    map
+  }
+
+  #[test]
+  fn test_sampling_adversarial_edge_maps() {
+    let mut chain = Chain::<1>::new();
+    chain.feed_str("a b c d e f g");
+    let tokens = "a b c d e f g"
+      .split(' ')
+      .map(|word| chain.dict.get(word))
+      .chain(std::iter::once(Token::None))
+      .collect::<Vec<_>>();
+
+    let mut rng = StdRng::seed_from_u64(0);
+    for _ in 0..10_000 {
+      let map = adversarial_edge_map(&mut rng, &tokens);
+      let token = chain.choose_next_word(&map, &mut rng).unwrap();
+      // Edges with a weight of zero are only chosen if every weight is zero
+      assert!(
+        map.edges[&token] > 0 || map.weight_sum() == 0,
+        "{:?} from {:?}",
+        token,
+        map
+      );
+    }
+
+    let empty = EdgeMap {
+      sum: 1,
+      edges: AHashMap::new(),
+    };
+    assert!(chain.choose_next_word(&empty, &mut rng).is_err());
+  }
+
+  #[test]
+  fn test_seeded_sampling() {
+    let chain = train!(2, TEXT);
+    let samples = |seed| {
+      let mut rng = StdRng::seed_from_u64(seed);
+      (0..10)
+        .map(|_| sample_with_rng(&chain, &mut rng, "", 4))
+        .collect::<Vec<_>>()
+    };
+    assert_eq!(samples(1), samples(1));
+
+    let continuation = |seed| sample_continuation_with_rng(&chain, &mut StdRng::seed_from_u64(seed), "Rust is", 4);
+    assert_eq!(continuation(1), continuation(1));
+  }
+
+  #[test]
+  fn test_zero_sum_edge_maps() {
+    let mut chain = Chain::<1>::new();
+    chain.feed_str("a b c");
+    let mut rng = StdRng::seed_from_u64(0);
+
+    chain.edges.iter_mut().for_each(|map| map.sum = 0);
+    assert_eq!(chain.generate_with_rng(&mut rng), "a b c");
+    assert!(chain.score_text("a b c").is_finite());
+
+    // Maps whose weights are all zero are sampled uniformly, but their transitions aren't scored
+    for map in &mut chain.edges {
+      map.edges.values_mut().for_each(|weight| *weight = 0);
+    }
+    assert_eq!(chain.generate_with_rng(&mut rng), "a b c");
+    assert!(chain.score_text("a b c").is_finite());
+    assert_eq!(chain.generate_beam("a", 10, 2), "a");
+
+    // A context without edges ends the message
+    chain.edges.iter_mut().for_each(|map| map.edges.clear());
+    assert_eq!(chain.generate_with_rng(&mut rng), "");
+  }
 }
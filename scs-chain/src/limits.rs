@@ -0,0 +1,115 @@
+//! Length limits for generated text.
+//!
+//! Text over a limit is cut at the last whitespace which fits, so that no word is split. If the first word alone is
+//! too long, it's cut at the last grapheme cluster which fits instead, so that e.g. emoji sequences stay intact.
+
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Maximum length of a Twitch chat message, in characters
+pub const TWITCH_MAX_CHARS: usize = 500;
+
+/// Post-processing applied to generated text
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GenerationOptions {
+  /// Maximum length of the output, in UTF-8 bytes
+  pub max_bytes: Option<usize>,
+  /// Maximum length of the output, in characters
+  pub max_chars: Option<usize>,
+}
+
+impl GenerationOptions {
+  /// Limits which fit in a single Twitch chat message
+  pub fn twitch() -> Self {
+    Self {
+      max_bytes: None,
+      max_chars: Some(TWITCH_MAX_CHARS),
+    }
+  }
+
+  pub fn with_max_bytes(mut self, max_bytes: usize) -> Self {
+    self.max_bytes = Some(max_bytes);
+    self
+  }
+
+  pub fn with_max_chars(mut self, max_chars: usize) -> Self {
+    self.max_chars = Some(max_chars);
+    self
+  }
+
+  fn fits(&self, bytes: usize, chars: usize) -> bool {
+    self.max_bytes.map_or(true, |max| bytes <= max) && self.max_chars.map_or(true, |max| chars <= max)
+  }
+
+  /// Returns the longest prefix of `text` which fits within the limits, without trailing whitespace
+  pub fn apply<'a>(&self, text: &'a str) -> &'a str {
+    if self.fits(text.len(), text.chars().count()) {
+      return text;
+    }
+
+    // End of the longest prefix made of whole grapheme clusters which fits
+    let mut end = 0;
+    let mut chars = 0;
+    for (start, grapheme) in text.grapheme_indices(true) {
+      chars += grapheme.chars().count();
+      if !self.fits(start + grapheme.len(), chars) {
+        break;
+      }
+      end = start + grapheme.len();
+    }
+
+    let prefix = &text[..end];
+    if prefix.ends_with(char::is_whitespace) || text[end..].starts_with(char::is_whitespace) {
+      return prefix.trim_end();
+    }
+    match prefix.rfind(char::is_whitespace) {
+      Some(boundary) => prefix[..boundary].trim_end(),
+      None => prefix,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn text_within_limits_is_unchanged() {
+    let options = GenerationOptions::default().with_max_chars(11).with_max_bytes(11);
+    assert_eq!(options.apply("hello world"), "hello world");
+    assert_eq!(GenerationOptions::default().apply("hello world"), "hello world");
+  }
+
+  #[test]
+  fn cuts_at_word_boundaries() {
+    let options = GenerationOptions::default().with_max_chars(14);
+    assert_eq!(options.apply("hello world again"), "hello world");
+    assert_eq!(options.apply("hello world  again"), "hello world");
+    assert_eq!(options.apply("hello worldly again"), "hello worldly");
+    assert_eq!(options.apply("hello worldwide"), "hello");
+  }
+
+  #[test]
+  fn cuts_long_words_at_grapheme_boundaries() {
+    // The family emoji is 7 chars and 25 bytes
+    let family = "👨‍👩‍👧‍👦";
+    let text = format!("{family}{family}");
+    assert_eq!(GenerationOptions::default().with_max_chars(10).apply(&text), family);
+    assert_eq!(GenerationOptions::default().with_max_bytes(40).apply(&text), family);
+    assert_eq!(GenerationOptions::default().with_max_chars(6).apply(&text), "");
+  }
+
+  #[test]
+  fn byte_limit_does_not_split_chars() {
+    let options = GenerationOptions::default().with_max_bytes(5);
+    assert_eq!(options.apply("ääää"), "ää");
+    assert_eq!(options.apply("ä ääää"), "ä");
+  }
+
+  #[test]
+  fn twitch_limit() {
+    let text = "ä ".repeat(400);
+    let output = GenerationOptions::twitch().apply(&text);
+    assert!(output.chars().count() <= TWITCH_MAX_CHARS);
+    assert_eq!(output, "ä ".repeat(250).trim_end());
+  }
+}
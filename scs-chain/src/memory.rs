@@ -0,0 +1,78 @@
+//! Estimates of the heap memory used by a chain.
+//!
+//! A loaded [`Chain`](crate::Chain) and a serialized one (see [`crate::ser::validate`]) are estimated from the same
+//! counts, so the estimate of a file is what loading it would use. Hash maps are assumed to use hashbrown's layout:
+//! a power of two number of buckets which are at most 7/8 full, and one control byte per bucket.
+
+use crate::{EdgeMap, Token, WordId};
+use std::mem::size_of;
+
+/// Estimated heap usage of a chain, in bytes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryEstimate {
+  /// The words, and the interner's lookup table
+  pub dict: usize,
+  /// The map from each `ORDER`-gram to its edges
+  pub nodes: usize,
+  /// The edge map of every node
+  pub edges: usize,
+}
+
+impl MemoryEstimate {
+  pub fn total(&self) -> usize {
+    self.dict + self.nodes + self.edges
+  }
+}
+
+/// Accumulates a [`MemoryEstimate`] from the sizes of a chain's parts
+pub(crate) struct Estimator {
+  order: usize,
+  estimate: MemoryEstimate,
+}
+
+impl Estimator {
+  pub fn new(order: usize) -> Self {
+    Self {
+      order,
+      estimate: MemoryEstimate::default(),
+    }
+  }
+
+  /// `bytes` is the total length of the words
+  pub fn words(&mut self, count: usize, bytes: usize) {
+    // The interner stores each word after its length, which is a single byte for words shorter than 128 bytes
+    self.estimate.dict += bytes + count + table_size(count, size_of::<WordId>());
+  }
+
+  pub fn nodes(&mut self, count: usize) {
+    let key = (self.order * size_of::<Token>()).next_multiple_of(size_of::<usize>());
+    // Each entry is a key and an `EdgeId`
+    self.estimate.nodes += table_size(count, key + size_of::<usize>());
+    self.estimate.edges += count * size_of::<EdgeMap>();
+  }
+
+  /// Adds the edge map of a single node
+  pub fn edge_map(&mut self, edges: usize) {
+    self.estimate.edges += table_size(edges, size_of::<(Token, u64)>());
+  }
+
+  pub fn finish(self) -> MemoryEstimate {
+    self.estimate
+  }
+}
+
+/// Size of a hash table with `len` entries of `entry` bytes each
+fn table_size(len: usize, entry: usize) -> usize {
+  /// Control bytes past the last bucket, so that a group can be read from any bucket
+  const GROUP_WIDTH: usize = 16;
+
+  if len == 0 {
+    return 0;
+  }
+  let buckets = match len {
+    0..=3 => 4,
+    4..=7 => 8,
+    _ => (len * 8 / 7).next_power_of_two(),
+  };
+  buckets * (entry + 1) + GROUP_WIDTH
+}
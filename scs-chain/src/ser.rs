@@ -22,8 +22,28 @@
 //!
 //! ## EdgeMap
 //! 1. edges: List<(Token, u64)>
+//!
+//! # Delta Format
+//!
+//! A delta records the changes made to a chain since its checkpoint (a full chain file) or its previous delta.
+//! Deltas are saved next to the checkpoint as `<checkpoint>.<sequence>.delta`, see [`delta_path`].
+//!
+//! 1. Header: `delta:`, the chain's order as u8, then `;`
+//! 2. Base hash: u64, the [`checkpoint_hash`] of the checkpoint
+//! 3. Sequence: u64, starting at 1 for the first delta after the checkpoint
+//! 4. Dictionary length before the delta: u64
+//! 5. New words: List<String>
+//! 6. Increments: List<Increment>
+//!
+//! Tokens refer to the words of the checkpoint's dictionary, followed by the new words of each delta, in order.
+//!
+//! ## Increment
+//! 1. key: [Token; ORDER]
+//! 2. token: Token
+//! 3. weight: u64, added to the weight of the edge
 
 use std::io::Read;
+use std::path::{Path, PathBuf};
 
 use super::*;
 
@@ -66,6 +86,38 @@ impl<'a, const ORDER: usize> ChainSerializer<'a, ORDER> {
     Ok(())
   }
 
+  pub fn serialize_delta<W: Write>(
+    mut self,
+    buf: &mut W,
+    journal: &Journal<ORDER>,
+    base_hash: u64,
+    sequence: u64,
+  ) -> std::io::Result<()> {
+    buf.write_all(b"delta:")?;
+    buf.write_all(&(ORDER as u8).to_le_bytes())?;
+    buf.write_all(b";")?;
+    self.write_u64(buf, base_hash)?;
+    self.write_u64(buf, sequence)?;
+    self.write_u64(buf, journal.base_words as u64)?;
+
+    self.write_u64(buf, (self.chain.dict.len() - journal.base_words) as u64)?;
+    for (index, (word_id, word)) in (&self.chain.dict).into_iter().enumerate() {
+      self.word_map.insert(word_id, index);
+      if index >= journal.base_words {
+        self.write_string(buf, word)?;
+      }
+    }
+
+    self.write_u64(buf, journal.increments.len() as u64)?;
+    for ((key, token), weight) in &journal.increments {
+      self.write_key(buf, key)?;
+      self.write_token(buf, token)?;
+      self.write_u64(buf, *weight)?;
+    }
+
+    Ok(())
+  }
+
   fn write_header<W: Write>(&mut self, buf: &mut W, metadata: &str) -> std::io::Result<()> {
     buf.write_all(b"chain:")?;
     buf.write_all(&(ORDER as u8).to_le_bytes())?;
@@ -180,9 +232,112 @@ impl<const ORDER: usize> ChainDeserializer<ORDER> {
       dict: self.dict,
       nodes: self.nodes,
       edges: self.edges,
+      journal: None,
+      deltas: 0,
     })
   }
 
+  /// Reads a delta in full before applying it to `chain`, so that an invalid delta leaves it unchanged.
+  pub fn apply_delta<R: Read>(
+    mut self,
+    chain: &mut Chain<ORDER>,
+    reader: &mut R,
+    base_hash: u64,
+  ) -> anyhow::Result<()> {
+    let mut header = [0u8; 6];
+    reader.read_exact(&mut header)?;
+    if &header != b"delta:" {
+      anyhow::bail!("Invalid delta file: malformed header");
+    }
+    let order = Self::read_byte(reader)?;
+    if Self::read_byte(reader)? != b';' {
+      anyhow::bail!("Invalid delta file: malformed header");
+    }
+    if order as usize != ORDER {
+      anyhow::bail!(format!(
+        "Invalid chain order, deserializer expected {} but found {}",
+        ORDER, order
+      ));
+    }
+    if self.read_u64(reader)? != base_hash {
+      anyhow::bail!("The delta was saved against a different checkpoint");
+    }
+    let sequence = self.read_u64(reader)?;
+    if sequence != chain.deltas + 1 {
+      anyhow::bail!(format!(
+        "Expected delta {} but found delta {}",
+        chain.deltas + 1,
+        sequence
+      ));
+    }
+    if self.read_u64(reader)? as usize != chain.dict.len() {
+      anyhow::bail!("The delta doesn't match the dictionary of the chain");
+    }
+
+    let new_words_len = self.read_u64(reader)? as usize;
+    let mut new_words = Vec::with_capacity(new_words_len);
+    let mut seen = ahash::AHashSet::with_capacity(new_words_len);
+    for _ in 0..new_words_len {
+      let word = self.read_string(reader)?;
+      if chain.dict.get(&word).is_some() || !seen.insert(word.clone()) {
+        anyhow::bail!("Invalid delta file: duplicate word");
+      }
+      new_words.push(word);
+    }
+    let words = chain.dict.len() + new_words.len();
+
+    let increments_len = self.read_u64(reader)? as usize;
+    let mut increments = Vec::with_capacity(increments_len);
+    for _ in 0..increments_len {
+      let mut key = [None; ORDER];
+      for index in key.iter_mut() {
+        *index = Self::read_word_index(reader, words)?;
+      }
+      let token = Self::read_word_index(reader, words)?;
+      let weight = self.read_u64(reader)?;
+      increments.push((key, token, weight));
+    }
+
+    if reader.read(&mut [0u8])? != 0 {
+      anyhow::bail!("Invalid delta file: trailing data after the last increment");
+    }
+
+    // Tokens refer to words by their position in the dictionary
+    let mut word_ids = (&chain.dict)
+      .into_iter()
+      .map(|(word_id, _)| word_id)
+      .collect::<Vec<_>>();
+    for word in new_words {
+      word_ids.push(chain.dict.get_or_intern(word));
+    }
+    for (key, token, weight) in increments {
+      let edge_id = chain.add_node(key.map(|index| index.map(|i| word_ids[i])));
+      let map = &mut chain.edges[edge_id.0];
+      map.sum += weight;
+      *map.edges.entry(token.map(|i| word_ids[i])).or_insert(0) += weight;
+    }
+    chain.deltas = sequence;
+
+    Ok(())
+  }
+
+  /// Reads a token as the index of its word, checking that it's less than `words`
+  fn read_word_index<R: Read>(reader: &mut R, words: usize) -> anyhow::Result<Option<usize>> {
+    match Self::read_byte(reader)? {
+      0 => {
+        let mut buf = [0u8; 4];
+        reader.read_exact(&mut buf)?;
+        let index = u32::from_le_bytes(buf) as usize;
+        if index >= words {
+          anyhow::bail!("Invalid word id");
+        }
+        Ok(Some(index))
+      }
+      1 => Ok(None),
+      _ => anyhow::bail!("Invalid delta file: malformed token"),
+    }
+  }
+
   fn read_dict<R: Read>(&mut self, reader: &mut R) -> anyhow::Result<()> {
     let dict_len = self.read_u64(reader)? as usize;
     self.dict = Dict::with_capacity(dict_len);
@@ -293,7 +448,96 @@ impl<const ORDER: usize> ChainDeserializer<ORDER> {
   }
 }
 
-pub(crate) fn read_header<R: Read>(reader: &mut R) -> anyhow::Result<(u8, String)> {
+/// A summary of a serialized chain, produced by [`validate`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelSummary {
+  pub order: usize,
+  pub metadata: String,
+  /// Number of words in the dictionary
+  pub words: usize,
+  /// Number of distinct `ORDER`-grams
+  pub nodes: usize,
+  /// Number of distinct transitions between nodes and words
+  pub edges: usize,
+  /// Sum of all edge weights
+  pub total_weight: u64,
+  /// Memory the chain would use once loaded
+  pub memory_estimate: MemoryEstimate,
+}
+
+/// Walks a serialized chain and checks that it can be loaded, without building the chain in memory.
+/// The summary includes an estimate of the memory the chain would use once loaded.
+///
+/// This checks the header, the chain order, that every token refers to a word in the dictionary,
+/// and that there is no trailing data after the last node.
+pub fn validate<R: Read>(reader: &mut R) -> anyhow::Result<ModelSummary> {
+  let (order, metadata) = read_header(reader)?;
+  if !(1..=3).contains(&order) {
+    anyhow::bail!(format!("Unsupported chain order: {}", order));
+  }
+  let order = order as usize;
+
+  let mut de = ChainDeserializer::<0>::new();
+  let mut estimator = memory::Estimator::new(order);
+
+  let words = de.read_u64(reader)? as usize;
+  let mut word_bytes = 0;
+  for _ in 0..words {
+    let len = de.read_u16(reader)? as usize;
+    de.buf.resize(len, 0);
+    reader.read_exact(&mut de.buf)?;
+    std::str::from_utf8(&de.buf)?;
+    word_bytes += len;
+  }
+  estimator.words(words, word_bytes);
+
+  fn validate_token<R: Read>(de: &mut ChainDeserializer<0>, reader: &mut R, words: usize) -> anyhow::Result<()> {
+    match ChainDeserializer::<0>::read_byte(reader)? {
+      0 => {
+        if de.read_u32(reader)? as usize >= words {
+          anyhow::bail!("Invalid word id");
+        }
+      }
+      1 => (),
+      _ => anyhow::bail!("Invalid chain file: malformed token"),
+    }
+    Ok(())
+  }
+
+  let nodes = de.read_u64(reader)? as usize;
+  estimator.nodes(nodes);
+  let mut edges = 0;
+  let mut total_weight = 0u64;
+  for _ in 0..nodes {
+    for _ in 0..order {
+      validate_token(&mut de, reader, words)?;
+    }
+    let edge_len = de.read_u64(reader)? as usize;
+    for _ in 0..edge_len {
+      validate_token(&mut de, reader, words)?;
+      total_weight = total_weight.saturating_add(de.read_u64(reader)?);
+    }
+    edges += edge_len;
+    estimator.edge_map(edge_len);
+  }
+
+  if reader.read(&mut [0u8])? != 0 {
+    anyhow::bail!("Invalid chain file: trailing data after the last node");
+  }
+
+  Ok(ModelSummary {
+    order,
+    metadata,
+    words,
+    nodes,
+    edges,
+    total_weight,
+    memory_estimate: estimator.finish(),
+  })
+}
+
+/// Reads the order and metadata of a serialized chain, without reading the rest of the file
+pub fn read_header<R: Read>(reader: &mut R) -> anyhow::Result<(u8, String)> {
   let mut buf = [0u8; 6];
   reader.read_exact(&mut buf)?;
 
@@ -318,3 +562,51 @@ pub(crate) fn read_header<R: Read>(reader: &mut R) -> anyhow::Result<(u8, String
 
   Ok((order, metadata))
 }
+
+/// Identifies a checkpoint, so that deltas can't be applied to the wrong one.
+///
+/// This is the 64-bit FNV-1a hash of the checkpoint file.
+pub fn checkpoint_hash(bytes: &[u8]) -> u64 {
+  bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+    (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+  })
+}
+
+/// Returns the hash of the checkpoint the delta in `bytes` was saved against, or `None` if it isn't a delta
+pub fn delta_base_hash(bytes: &[u8]) -> Option<u64> {
+  // After `delta:`, the order, and `;`
+  let hash = bytes.strip_prefix(b"delta:")?.get(2..10)?;
+  Some(u64::from_le_bytes(hash.try_into().ok()?))
+}
+
+/// Path of the delta number `sequence` of `checkpoint`, e.g. `model.chain.0001.delta`
+pub fn delta_path(checkpoint: &Path, sequence: u64) -> PathBuf {
+  let mut name = checkpoint.file_name().unwrap_or_default().to_owned();
+  name.push(format!(".{:04}.delta", sequence));
+  checkpoint.with_file_name(name)
+}
+
+/// Returns the paths of the deltas saved next to `checkpoint`, in the order they must be applied in
+pub fn find_deltas(checkpoint: &Path) -> std::io::Result<Vec<PathBuf>> {
+  let prefix = format!("{}.", checkpoint.file_name().unwrap_or_default().to_string_lossy());
+  let dir = match checkpoint.parent() {
+    Some(dir) if !dir.as_os_str().is_empty() => dir,
+    _ => Path::new("."),
+  };
+
+  let mut deltas = Vec::new();
+  for entry in std::fs::read_dir(dir)? {
+    let entry = entry?;
+    let sequence = entry
+      .file_name()
+      .to_str()
+      .and_then(|name| name.strip_prefix(&prefix))
+      .and_then(|rest| rest.strip_suffix(".delta"))
+      .and_then(|sequence| sequence.parse::<u64>().ok());
+    if let Some(sequence) = sequence {
+      deltas.push((sequence, entry.path()));
+    }
+  }
+  deltas.sort();
+  Ok(deltas.into_iter().map(|(_, path)| path).collect())
+}
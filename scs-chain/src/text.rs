@@ -0,0 +1,72 @@
+//! Sanitization of chat messages.
+//!
+//! The same rules are applied to the messages a model is trained on and to the text it generates, so that characters
+//! which are invisible in chat can't end up in a model, and whatever is sent looks the same as what was trained on.
+
+use std::borrow::Cow;
+
+/// Zero-width and other invisible characters, e.g. the ones used to bypass Twitch's duplicate message detection
+pub fn is_invisible(c: char) -> bool {
+  matches!(
+    c,
+    '\u{200B}'..='\u{200F}' | '\u{2060}'..='\u{2064}' | '\u{FEFF}' | '\u{2800}' | '\u{E0000}'..='\u{E007F}'
+  )
+}
+
+fn is_stripped(c: char) -> bool {
+  is_invisible(c) || (c.is_control() && !c.is_whitespace())
+}
+
+/// Removes invisible and control characters, collapses runs of whitespace into a single space, and trims the result.
+/// Text which doesn't need any changes is returned as is.
+pub fn sanitize(text: &str) -> Cow<'_, str> {
+  let clean = !text.starts_with(char::is_whitespace)
+    && !text.ends_with(char::is_whitespace)
+    && !text.contains("  ")
+    && !text.chars().any(|c| is_stripped(c) || (c.is_whitespace() && c != ' '));
+  if clean {
+    return Cow::Borrowed(text);
+  }
+
+  let mut output = String::with_capacity(text.len());
+  let mut pending_space = false;
+  for c in text.chars().filter(|c| !is_stripped(*c)) {
+    if c.is_whitespace() {
+      pending_space = !output.is_empty();
+      continue;
+    }
+    if pending_space {
+      output.push(' ');
+      pending_space = false;
+    }
+    output.push(c);
+  }
+  Cow::Owned(output)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn clean_text_is_borrowed() {
+    assert!(matches!(sanitize("hello world"), Cow::Borrowed("hello world")));
+    assert!(matches!(sanitize(""), Cow::Borrowed("")));
+  }
+
+  #[test]
+  fn strips_invisible_and_control_characters() {
+    assert_eq!(sanitize("hello\u{E0000} world"), "hello world");
+    assert_eq!(sanitize("hello \u{2800}"), "hello");
+    assert_eq!(sanitize("he\u{200B}llo\u{7}"), "hello");
+    assert_eq!(sanitize("\u{E0000}"), "");
+  }
+
+  #[test]
+  fn normalizes_whitespace() {
+    assert_eq!(sanitize("  hello \t\n world  "), "hello world");
+    assert_eq!(sanitize("hello\tworld"), "hello world");
+    // whitespace left around a removed character is collapsed too
+    assert_eq!(sanitize("hello \u{200B} world"), "hello world");
+  }
+}
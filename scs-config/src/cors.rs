@@ -0,0 +1,85 @@
+//! CORS policy of the APIs, enabled by the `cors` feature
+
+use actix_cors::Cors;
+use actix_web::http::{header::HeaderName, Method};
+use serde::Deserialize;
+
+/// CORS policy of an API, set with `cors` in the config file of the manage API, and with options in the user API
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsSettings {
+  /// Origins which may call the API from a browser. `*` allows any origin, but without credentials.
+  /// Defaults to localhost in debug builds, and to no origins otherwise.
+  pub origins: Vec<String>,
+  pub methods: Vec<String>,
+  /// Request headers allowed in addition to `Authorization`, `Accept`, `Content-Type`, and the API's own headers
+  pub headers: Vec<String>,
+  /// How long browsers may cache the preflight response, in seconds
+  pub max_age: usize,
+}
+
+impl Default for CorsSettings {
+  fn default() -> Self {
+    Self {
+      origins: vec![],
+      methods: vec!["GET".into(), "POST".into()],
+      headers: vec![],
+      max_age: 3600,
+    }
+  }
+}
+
+/// Origins served by a local development server, on any port
+fn is_localhost(origin: &str) -> bool {
+  let Some(host) = origin
+    .strip_prefix("http://")
+    .or_else(|| origin.strip_prefix("https://"))
+  else {
+    return false;
+  };
+  let host = match host.rsplit_once(':') {
+    Some((host, port)) if !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()) => host,
+    _ => host,
+  };
+  matches!(host, "localhost" | "127.0.0.1" | "[::1]")
+}
+
+impl CorsSettings {
+  /// Fails if any method or header is invalid, so that a typo is reported on startup
+  pub fn validate(&self) -> anyhow::Result<()> {
+    for method in &self.methods {
+      Method::from_bytes(method.as_bytes()).map_err(|_| anyhow::anyhow!("Invalid CORS method `{method}`"))?;
+    }
+    for header in &self.headers {
+      HeaderName::try_from(header.as_str()).map_err(|_| anyhow::anyhow!("Invalid CORS header `{header}`"))?;
+    }
+    Ok(())
+  }
+
+  /// `own_headers` are headers of the API, which are both allowed in requests and exposed in responses
+  pub fn cors(&self, own_headers: &[HeaderName]) -> Cors {
+    use actix_web::http::header;
+
+    let mut cors = Cors::default()
+      .allowed_methods(self.methods.iter().map(String::as_str))
+      .allowed_headers(vec![header::AUTHORIZATION, header::ACCEPT, header::CONTENT_TYPE])
+      .allowed_headers(own_headers.iter().cloned())
+      .allowed_headers(self.headers.iter().map(String::as_str))
+      .max_age(self.max_age);
+    if !own_headers.is_empty() {
+      cors = cors.expose_headers(own_headers.iter().cloned());
+    }
+
+    if self.origins.iter().any(|origin| origin == "*") {
+      // Browsers reject credentialed responses to requests from any origin
+      return cors.allow_any_origin();
+    }
+    if self.origins.is_empty() && cfg!(debug_assertions) {
+      cors = cors.allowed_origin_fn(|origin, _| origin.to_str().map_or(false, is_localhost));
+    }
+    for origin in &self.origins {
+      cors = cors.allowed_origin(origin);
+    }
+    cors.supports_credentials()
+  }
+}
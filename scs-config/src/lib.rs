@@ -0,0 +1,173 @@
+//! Loading and validation of the JSON config files used by the services.
+//!
+//! Every config struct rejects unknown fields, so a typo such as `chanels` is reported
+//! with the path of the offending field instead of silently falling back to a default.
+
+use serde::{de::DeserializeOwned, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+#[cfg(feature = "cors")]
+pub mod cors;
+pub mod phrases;
+pub mod sinks;
+pub use phrases::PhraseBlocklist;
+pub use sinks::SinksConfig;
+
+/// Number of hex digits of the SHA-256 of a config kept in its hash
+const HASH_LENGTH: usize = 16;
+
+/// A config which couldn't be deserialized
+#[derive(Clone, Debug, Serialize)]
+pub struct Error {
+  /// JSON path of the field which failed to deserialize, e.g. `channels[1].name`. `.` is the document itself.
+  pub path: String,
+  pub message: String,
+  pub line: Option<usize>,
+  pub column: Option<usize>,
+}
+
+impl std::fmt::Display for Error {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}: {}", self.path, self.message)?;
+    if let (Some(line), Some(column)) = (self.line, self.column) {
+      write!(f, " at line {} column {}", line, column)?;
+    }
+    Ok(())
+  }
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+  /// An error which isn't tied to a specific field, such as a failed semantic check
+  fn other(error: &anyhow::Error) -> Self {
+    Self {
+      path: String::from("."),
+      message: format!("{error:#}"),
+      line: None,
+      column: None,
+    }
+  }
+}
+
+/// Deserializes `contents`, recording the path of the field on failure
+pub fn parse<T: DeserializeOwned>(contents: &str) -> Result<T, Error> {
+  let de = &mut serde_json::Deserializer::from_str(contents);
+  serde_path_to_error::deserialize(de).map_err(|e| {
+    let path = e.path().to_string();
+    let inner = e.into_inner();
+    // `serde_json` appends the position to the message, it's reported separately
+    let message = inner.to_string();
+    let suffix = format!(" at line {} column {}", inner.line(), inner.column());
+    Error {
+      path,
+      message: message.strip_suffix(&suffix).unwrap_or(&message).to_owned(),
+      line: Some(inner.line()),
+      column: Some(inner.column()),
+    }
+  })
+}
+
+/// Short SHA-256 of a config's contents, logged on startup to tell deployed configs apart
+pub fn hash(contents: &str) -> String {
+  let mut hash = hex::encode(Sha256::digest(contents.as_bytes()));
+  hash.truncate(HASH_LENGTH);
+  hash
+}
+
+/// Reads and deserializes the config at `path`, and logs its hash
+pub fn read<T: DeserializeOwned>(path: impl AsRef<Path>) -> anyhow::Result<T> {
+  let path = path.as_ref();
+  let contents = std::fs::read_to_string(path)
+    .map_err(|e| anyhow::anyhow!("Could not read config file {}: {}", path.display(), e))?;
+  let config = parse(&contents)?;
+  log::info!("Loaded config {} (hash {})", path.display(), hash(&contents));
+  Ok(config)
+}
+
+/// Outcome of validating a config file
+#[derive(Clone, Debug, Serialize)]
+pub struct Report {
+  pub file: String,
+  /// `None` if the file couldn't be read
+  pub hash: Option<String>,
+  pub valid: bool,
+  pub error: Option<Error>,
+}
+
+/// Validates the config at `path` with `load`, which should be the service's own loading function
+/// so that its semantic checks run as well. The report is printed to stdout as JSON.
+///
+/// Returns whether the config is valid.
+pub fn validate<T>(path: impl AsRef<Path>, load: impl FnOnce(&Path) -> anyhow::Result<T>) -> anyhow::Result<bool> {
+  let path = path.as_ref();
+  let hash = std::fs::read_to_string(path).ok().map(|contents| hash(&contents));
+  let error = load(path)
+    .err()
+    .map(|e| e.downcast_ref::<Error>().cloned().unwrap_or_else(|| Error::other(&e)));
+  let report = Report {
+    file: path.display().to_string(),
+    hash,
+    valid: error.is_none(),
+    error,
+  };
+  println!("{}", serde_json::to_string_pretty(&report)?);
+  Ok(report.valid)
+}
+
+/// Runs [`validate`] on every file in `paths` and exits with a non-zero status if any of them is invalid
+pub fn validate_all<T>(
+  paths: impl IntoIterator<Item = impl AsRef<Path>>,
+  load: impl Fn(&Path) -> anyhow::Result<T>,
+) -> anyhow::Result<()> {
+  let mut valid = true;
+  for path in paths {
+    valid &= validate(path, &load)?;
+  }
+  if !valid {
+    std::process::exit(1);
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[derive(Debug, serde::Deserialize)]
+  #[serde(deny_unknown_fields)]
+  struct Channel {
+    #[allow(dead_code)]
+    name: String,
+  }
+
+  #[derive(Debug, serde::Deserialize)]
+  #[serde(deny_unknown_fields)]
+  struct Config {
+    #[allow(dead_code)]
+    channels: Vec<Channel>,
+  }
+
+  #[test]
+  fn test_error_path() {
+    let error = parse::<Config>("{\"chanels\": []}").unwrap_err();
+    assert!(
+      error.message.starts_with("unknown field `chanels`"),
+      "{}",
+      error.message
+    );
+    assert_eq!(error.line, Some(1));
+
+    let error = parse::<Config>("{\"channels\": [{\"name\": \"a\"}, {\"name\": 1}]}").unwrap_err();
+    assert_eq!(error.path, "channels[1].name");
+    assert!(!error.message.contains(" at line "), "{}", error.message);
+  }
+
+  #[test]
+  fn test_hash() {
+    assert_eq!(hash("{}").len(), HASH_LENGTH);
+    assert_eq!(hash("{}"), hash("{}"));
+    assert_ne!(hash("{}"), hash("{ }"));
+  }
+}
@@ -0,0 +1,87 @@
+//! Blocklists of words and phrases which must never be generated.
+//!
+//! The trainer excludes the messages which contain them, and the services which send generated text check it again
+//! before it's shown, since a model can still put the words of a phrase together.
+
+use anyhow::Context;
+use std::path::Path;
+
+/// The entries of a phrase blocklist file, one per line. Empty lines and lines starting with `#` are ignored.
+///
+/// Entries are matched as sequences of whole words, ignoring case and extra whitespace.
+#[derive(Clone, Debug)]
+pub struct PhraseBlocklist {
+  entries: Vec<String>,
+  regex: regex::RegexSet,
+  hash: String,
+}
+
+impl PhraseBlocklist {
+  /// Reads and parses the blocklist at `path`
+  pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+    let path = path.as_ref();
+    let contents = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    Self::parse(&contents).with_context(|| format!("Invalid phrase blocklist {}", path.display()))
+  }
+
+  pub fn parse(contents: &str) -> anyhow::Result<Self> {
+    let entries = contents
+      .lines()
+      .map(str::trim)
+      .filter(|line| !line.is_empty() && !line.starts_with('#'))
+      .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+      .collect::<Vec<_>>();
+    let regex = regex::RegexSet::new(entries.iter().map(|entry| phrase_pattern(entry)))?;
+    Ok(Self {
+      entries,
+      regex,
+      hash: crate::hash(contents),
+    })
+  }
+
+  /// The entries, with their whitespace normalized
+  #[inline]
+  pub fn entries(&self) -> &[String] {
+    &self.entries
+  }
+
+  /// Short SHA-256 of the file, see [`crate::hash`]
+  #[inline]
+  pub fn hash(&self) -> &str {
+    &self.hash
+  }
+
+  /// Returns the indices of the entries which `text` contains
+  pub fn matches(&self, text: &str) -> Vec<usize> {
+    self.regex.matches(text).into_iter().collect()
+  }
+
+  /// Whether `text` contains any of the entries
+  #[inline]
+  pub fn is_match(&self, text: &str) -> bool {
+    self.regex.is_match(text)
+  }
+}
+
+/// Matches `phrase` as a sequence of whole words, ignoring case, since messages are split into words on whitespace
+fn phrase_pattern(phrase: &str) -> String {
+  let words = phrase.split_whitespace().map(regex::escape).collect::<Vec<_>>();
+  format!(r"(?i)(?:^|\s){}(?:\s|$)", words.join(r"\s+"))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_phrase_blocklist() {
+    let blocklist = PhraseBlocklist::parse("# comment\n\nbad   word\nclass\n:)\n").unwrap();
+    assert_eq!(blocklist.entries(), ["bad word", "class", ":)"]);
+    assert_eq!(blocklist.matches("this is a BAD  word"), vec![0]);
+    assert_eq!(blocklist.matches("bad words"), Vec::<usize>::new());
+    assert_eq!(blocklist.matches("classy :) class"), vec![1, 2]);
+    assert!(blocklist.is_match("class"));
+    assert!(!blocklist.is_match("nothing here"));
+    assert_eq!(blocklist.hash(), crate::hash("# comment\n\nbad   word\nclass\n:)\n"));
+  }
+}
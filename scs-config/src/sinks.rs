@@ -0,0 +1,240 @@
+//! Settings of the sinks which services write records to.
+//!
+//! The same `sinks` section is shared by every writer, so that a deployment describes where its data goes in one
+//! place. Each writer builds the sinks it supports, and rejects the others.
+
+use serde::Deserialize;
+use std::path::PathBuf;
+
+/// Where records are written. Every configured sink receives every record.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SinksConfig {
+  pub filesystem: Option<FilesystemSink>,
+  pub postgres: Option<PostgresSink>,
+  pub kafka: Option<KafkaSink>,
+}
+
+/// Log files, one per channel and rotation period
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FilesystemSink {
+  pub directory: PathBuf,
+  #[serde(default)]
+  pub rotation: Rotation,
+  #[serde(default)]
+  pub compression: Compression,
+}
+
+/// How often a new log file is started
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rotation {
+  /// `<channel>-YYYY-MM-DD.log`
+  #[default]
+  Daily,
+  /// `<channel>-YYYY-MM-DD-HH.log`
+  Hourly,
+}
+
+/// The name of a log file, see [`Rotation`]. Every service which reads log files parses their names with it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LogFileName<'a> {
+  pub channel: &'a str,
+  /// `YYYY-MM-DD`
+  pub date: &'a str,
+  /// Hour of the day of hourly logs
+  pub hour: Option<u32>,
+}
+
+impl<'a> LogFileName<'a> {
+  /// Parses `<channel>-YYYY-MM-DD.log` or `<channel>-YYYY-MM-DD-HH.log`. The date is only checked to have the right
+  /// shape, so it may still not exist.
+  pub fn parse(name: &'a str) -> Option<Self> {
+    let stem = name.strip_suffix(".log")?;
+    let (rest, hour) = match stem.rsplit_once('-') {
+      Some((rest, hour))
+        if hour.len() == 2 && hour.bytes().all(|b| b.is_ascii_digit()) && split_date(rest).is_some() =>
+      {
+        (rest, Some(hour.parse().ok()?))
+      }
+      _ => (stem, None),
+    };
+    let (channel, date) = split_date(rest)?;
+    if channel.is_empty() || !channel.chars().all(|c| c.is_alphanumeric() || c == '_') || hour.map_or(false, |h| h > 23)
+    {
+      return None;
+    }
+    Some(Self { channel, date, hour })
+  }
+}
+
+/// Splits `<channel>-YYYY-MM-DD` into the channel and the date
+fn split_date(name: &str) -> Option<(&str, &str)> {
+  let date = name.get(name.len().checked_sub(10)?..)?;
+  let is_date = date.bytes().enumerate().all(|(i, b)| match i {
+    4 | 7 => b == b'-',
+    _ => b.is_ascii_digit(),
+  });
+  let channel = name[..name.len() - 10].strip_suffix('-')?;
+  is_date.then_some((channel, date))
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+  #[default]
+  None,
+  Gzip,
+}
+
+/// The logs table of a database
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PostgresSink {
+  pub url: String,
+  #[serde(default)]
+  pub mode: PostgresMode,
+  #[serde(default)]
+  pub batch: Batch,
+}
+
+/// How messages are stored, see `db::logs::InsertMode`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PostgresMode {
+  #[default]
+  Plain,
+  /// Compressed with a dictionary trained per channel, requires the `compression` feature
+  Compressed,
+}
+
+/// A Kafka topic, with one message per record keyed by channel
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KafkaSink {
+  pub brokers: Vec<String>,
+  pub topic: String,
+  #[serde(default)]
+  pub batch: Batch,
+}
+
+/// Records are written once `size` of them are waiting, or `flush_interval_ms` after the first one
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Batch {
+  pub size: usize,
+  pub flush_interval_ms: u64,
+}
+
+impl Default for Batch {
+  fn default() -> Self {
+    Self {
+      size: 10_000,
+      flush_interval_ms: 1000,
+    }
+  }
+}
+
+impl Batch {
+  fn validate(&self, path: &str) -> anyhow::Result<()> {
+    if self.size == 0 || self.flush_interval_ms == 0 {
+      anyhow::bail!("{path}.batch.size and {path}.batch.flush_interval_ms must be positive");
+    }
+    Ok(())
+  }
+}
+
+impl SinksConfig {
+  /// Checks the settings of every configured sink. `path` is the prefix of the section in errors, e.g. `config.sinks`.
+  pub fn validate(&self, path: &str) -> anyhow::Result<()> {
+    if let Some(filesystem) = &self.filesystem {
+      if filesystem.directory.as_os_str().is_empty() {
+        anyhow::bail!("{path}.filesystem.directory must not be empty");
+      }
+    }
+    if let Some(postgres) = &self.postgres {
+      if postgres.url.is_empty() {
+        anyhow::bail!("{path}.postgres.url must not be empty");
+      }
+      postgres.batch.validate(&format!("{path}.postgres"))?;
+    }
+    if let Some(kafka) = &self.kafka {
+      if kafka.brokers.is_empty() || kafka.brokers.iter().any(String::is_empty) {
+        anyhow::bail!("{path}.kafka.brokers must list at least one broker");
+      }
+      if kafka.topic.is_empty() {
+        anyhow::bail!("{path}.kafka.topic must not be empty");
+      }
+      kafka.batch.validate(&format!("{path}.kafka"))?;
+    }
+    Ok(())
+  }
+
+  /// Names of the configured sinks, e.g. for errors about unsupported ones
+  pub fn configured(&self) -> Vec<&'static str> {
+    [
+      self.filesystem.as_ref().map(|_| "filesystem"),
+      self.postgres.as_ref().map(|_| "postgres"),
+      self.kafka.as_ref().map(|_| "kafka"),
+    ]
+    .into_iter()
+    .flatten()
+    .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn defaults() {
+    let sinks = crate::parse::<SinksConfig>(r#"{ "filesystem": { "directory": "logs" } }"#).unwrap();
+    let filesystem = sinks.filesystem.as_ref().unwrap();
+    assert_eq!(filesystem.rotation, Rotation::Daily);
+    assert_eq!(filesystem.compression, Compression::None);
+    assert_eq!(sinks.configured(), vec!["filesystem"]);
+    sinks.validate("config.sinks").unwrap();
+  }
+
+  #[test]
+  fn validation() {
+    let sinks = crate::parse::<SinksConfig>(r#"{ "kafka": { "brokers": [], "topic": "logs" } }"#).unwrap();
+    let error = sinks.validate("config.sinks").unwrap_err();
+    assert!(error.to_string().starts_with("config.sinks.kafka.brokers"));
+
+    let sinks =
+      crate::parse::<SinksConfig>(r#"{ "postgres": { "url": "postgres://", "batch": { "size": 0 } } }"#).unwrap();
+    assert!(sinks.validate("config.sinks").is_err());
+
+    let error =
+      crate::parse::<SinksConfig>(r#"{ "filesystem": { "directory": "logs", "rotation": "weekly" } }"#).unwrap_err();
+    assert_eq!(error.path, "filesystem.rotation");
+  }
+
+  #[test]
+  fn log_file_names() {
+    assert_eq!(
+      LogFileName::parse("forsen-2023-07-01.log"),
+      Some(LogFileName {
+        channel: "forsen",
+        date: "2023-07-01",
+        hour: None
+      })
+    );
+    assert_eq!(
+      LogFileName::parse("forsen_2-2023-07-01-13.log"),
+      Some(LogFileName {
+        channel: "forsen_2",
+        date: "2023-07-01",
+        hour: Some(13)
+      })
+    );
+    assert_eq!(LogFileName::parse("forsen-2023-07-01-24.log"), None);
+    assert_eq!(LogFileName::parse("forsen-2023-07-01.jsonl"), None);
+    assert_eq!(LogFileName::parse("forsen.log"), None);
+    assert_eq!(LogFileName::parse("2023-07-01.log"), None);
+    assert_eq!(LogFileName::parse("a-b-2023-07-01.log"), None);
+  }
+}
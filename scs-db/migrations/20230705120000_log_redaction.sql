@@ -0,0 +1,8 @@
+-- Redacted messages are kept for auditing purposes, but hidden from all regular reads
+ALTER TABLE twitch_logs
+  ADD COLUMN redacted_at TIMESTAMPTZ,
+  ADD COLUMN redacted_by INTEGER REFERENCES twitch_user(id);
+
+-- Admins are allowlisted users who may perform moderation actions, such as redactions
+ALTER TABLE allowlist
+  ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT false;
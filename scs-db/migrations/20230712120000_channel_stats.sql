@@ -0,0 +1,45 @@
+-- Per-channel statistics, maintained incrementally on insert so that they're cheap to read
+CREATE TABLE twitch_channel_stats (
+  channel INTEGER REFERENCES twitch_user(id) PRIMARY KEY,
+  message_count BIGINT NOT NULL DEFAULT 0,
+  first_message_at TIMESTAMPTZ,
+  last_message_at TIMESTAMPTZ
+);
+
+-- Distinct (channel, chatter) pairs, used to count the chatters of a channel
+CREATE TABLE twitch_channel_chatters (
+  channel INTEGER REFERENCES twitch_user(id),
+  chatter INTEGER REFERENCES twitch_user(id),
+  PRIMARY KEY (channel, chatter)
+);
+
+CREATE FUNCTION update_channel_stats() RETURNS TRIGGER AS $$
+BEGIN
+  INSERT INTO twitch_channel_stats (channel, message_count, first_message_at, last_message_at)
+    SELECT channel, COUNT(*), MIN(sent_at), MAX(sent_at) FROM new_logs GROUP BY channel
+  ON CONFLICT (channel) DO UPDATE
+    SET message_count = twitch_channel_stats.message_count + EXCLUDED.message_count,
+        -- LEAST and GREATEST ignore NULLs
+        first_message_at = LEAST(twitch_channel_stats.first_message_at, EXCLUDED.first_message_at),
+        last_message_at = GREATEST(twitch_channel_stats.last_message_at, EXCLUDED.last_message_at);
+
+  INSERT INTO twitch_channel_chatters (channel, chatter)
+    SELECT DISTINCT channel, chatter FROM new_logs
+  ON CONFLICT DO NOTHING;
+
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+-- Statement-level so that bulk inserts only update the stats once
+CREATE TRIGGER trg_twitch_logs_channel_stats
+  AFTER INSERT ON twitch_logs
+  REFERENCING NEW TABLE AS new_logs
+  FOR EACH STATEMENT EXECUTE FUNCTION update_channel_stats();
+
+-- Backfill the stats from the existing logs
+INSERT INTO twitch_channel_stats (channel, message_count, first_message_at, last_message_at)
+  SELECT channel, COUNT(*), MIN(sent_at), MAX(sent_at) FROM twitch_logs GROUP BY channel;
+
+INSERT INTO twitch_channel_chatters (channel, chatter)
+  SELECT DISTINCT channel, chatter FROM twitch_logs;
@@ -0,0 +1,26 @@
+-- Webhooks which are notified when a model is published or updated
+CREATE TABLE model_webhooks (
+  id SERIAL PRIMARY KEY,
+  url TEXT NOT NULL,
+  -- Used to sign the payloads, so that receivers can verify that they were sent by us
+  secret TEXT NOT NULL,
+  created_by INTEGER REFERENCES twitch_user(id) NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- One row per notification, after all of its delivery attempts
+CREATE TABLE model_webhook_deliveries (
+  id BIGSERIAL PRIMARY KEY,
+  webhook INTEGER REFERENCES model_webhooks(id) ON DELETE CASCADE NOT NULL,
+  event TEXT NOT NULL,
+  model TEXT NOT NULL,
+  attempts INTEGER NOT NULL,
+  -- HTTP status of the last attempt, if a response was received
+  status INTEGER,
+  -- Error of the last attempt, if it failed
+  error TEXT,
+  delivered BOOLEAN NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_model_webhook_deliveries_webhook ON model_webhook_deliveries (webhook, created_at);
@@ -0,0 +1,19 @@
+-- Text generated by the user API, so that it can be shared
+CREATE TABLE generations (
+  id BIGSERIAL PRIMARY KEY,
+  user_id INTEGER REFERENCES twitch_user(id) NOT NULL,
+  model TEXT NOT NULL,
+  seed TEXT NOT NULL,
+  text TEXT NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Public links to a generation, which don't reveal who created it
+CREATE TABLE generation_shares (
+  token TEXT PRIMARY KEY,
+  generation BIGINT REFERENCES generations(id) ON DELETE CASCADE NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX idx_generation_shares_expires_at ON generation_shares (expires_at);
@@ -0,0 +1,2 @@
+-- Used to page through the messages of a chatter across all channels
+CREATE INDEX idx_twitch_logs_chatter_sent_at_id ON twitch_logs (chatter, sent_at, id);
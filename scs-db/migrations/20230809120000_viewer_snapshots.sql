@@ -0,0 +1,13 @@
+-- Periodic snapshots of each channel's stream, recorded by the collector and imported by the ingester
+CREATE TABLE twitch_viewer_snapshots (
+  channel INTEGER REFERENCES twitch_user(id) NOT NULL,
+  taken_at TIMESTAMPTZ NOT NULL,
+  live BOOLEAN NOT NULL,
+  -- 0 when the channel is offline
+  viewer_count INTEGER NOT NULL,
+  title TEXT,
+  game TEXT,
+
+  -- Also makes re-ingesting the same snapshot a no-op
+  PRIMARY KEY (channel, taken_at)
+);
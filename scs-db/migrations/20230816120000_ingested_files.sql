@@ -0,0 +1,11 @@
+-- Log files which were ingested and archived, so that re-imports can be detected
+CREATE TABLE ingested_files (
+  -- Hex-encoded SHA-256 of the file's contents, which is also its name in the archive
+  hash TEXT PRIMARY KEY,
+  original_path TEXT NOT NULL,
+  channel INTEGER REFERENCES twitch_user(id) NOT NULL,
+  rows_inserted BIGINT NOT NULL,
+  ingested_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_ingested_files_channel ON ingested_files (channel, ingested_at);
@@ -0,0 +1,15 @@
+-- Zstandard dictionaries which messages are compressed with, trained per channel
+CREATE TABLE log_dictionaries (
+  id SERIAL PRIMARY KEY,
+  channel INTEGER REFERENCES twitch_user(id) NOT NULL,
+  dictionary BYTEA NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_log_dictionaries_channel ON log_dictionaries (channel, id);
+
+-- Compressed messages have an empty `message`, and their compressed text in `message_zstd`.
+-- Messages are only compressed when the ingest is built with the `compression` feature.
+ALTER TABLE twitch_logs
+  ADD COLUMN message_zstd BYTEA,
+  ADD COLUMN dictionary INTEGER REFERENCES log_dictionaries(id);
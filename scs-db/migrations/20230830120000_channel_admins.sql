@@ -0,0 +1,7 @@
+-- Users who may act on behalf of the bot in a channel, such as posting generated messages to its chat
+CREATE TABLE channel_admins (
+  user_id INTEGER REFERENCES twitch_user(id) NOT NULL,
+  channel INTEGER REFERENCES twitch_user(id) NOT NULL,
+  granted_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  PRIMARY KEY (user_id, channel)
+);
@@ -0,0 +1,5 @@
+-- Chatters who don't want their messages to be quoted by the chat bot
+CREATE TABLE quote_opt_outs (
+  user_id INTEGER REFERENCES twitch_user(id) PRIMARY KEY,
+  opted_out_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
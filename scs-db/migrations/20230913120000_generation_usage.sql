@@ -0,0 +1,2 @@
+-- Usage stats of each model are aggregated from its generations
+CREATE INDEX idx_generations_model ON generations (model, created_at);
@@ -0,0 +1,2 @@
+-- ISO 639-3 code of the language of the message, if the collector detected it
+ALTER TABLE twitch_logs ADD COLUMN language TEXT;
@@ -0,0 +1,14 @@
+-- Requests of API users to delete their account. Rows are kept once the deletion is completed or cancelled,
+-- as an audit trail.
+CREATE TABLE account_deletions (
+  id BIGSERIAL PRIMARY KEY,
+  user_id INTEGER REFERENCES twitch_user(id) NOT NULL,
+  requested_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  delete_after TIMESTAMPTZ NOT NULL,
+  cancelled_at TIMESTAMPTZ,
+  completed_at TIMESTAMPTZ
+);
+
+-- At most one pending deletion per user
+CREATE UNIQUE INDEX idx_account_deletions_pending ON account_deletions (user_id)
+  WHERE cancelled_at IS NULL AND completed_at IS NULL;
@@ -0,0 +1,3 @@
+-- Position of the message among the messages the collector received in its channel, see `sink::Sequences`
+ALTER TABLE twitch_logs ADD COLUMN seq BIGINT;
+CREATE INDEX twitch_logs_channel_seq_idx ON twitch_logs (channel, seq) WHERE seq IS NOT NULL;
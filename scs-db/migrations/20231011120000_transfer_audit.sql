@@ -0,0 +1,20 @@
+-- One row per batch of logs transferred by the ingester, recording whether the inserted rows match what was read
+CREATE TABLE transfer_audit (
+  id SERIAL PRIMARY KEY,
+  -- Path of the ingested file, or `<stdin>`
+  source TEXT NOT NULL,
+  started_at TIMESTAMPTZ NOT NULL,
+  duration_ms BIGINT NOT NULL,
+  expected_rows BIGINT NOT NULL,
+  inserted_rows BIGINT NOT NULL
+);
+
+-- Days of a transfer whose inserted row count differs from the expected one
+CREATE TABLE transfer_audit_mismatch (
+  audit INTEGER REFERENCES transfer_audit(id) ON DELETE CASCADE NOT NULL,
+  channel INTEGER REFERENCES twitch_user(id) NOT NULL,
+  day DATE NOT NULL,
+  expected BIGINT NOT NULL,
+  inserted BIGINT NOT NULL,
+  PRIMARY KEY (audit, channel, day)
+);
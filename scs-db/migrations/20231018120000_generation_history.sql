@@ -0,0 +1,5 @@
+-- Generations can be marked as favorites in the history
+ALTER TABLE generations ADD COLUMN favorite BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- The history of a user is paginated newest first
+CREATE INDEX idx_generations_user ON generations (user_id, created_at, id);
@@ -0,0 +1,4 @@
+-- Lines read from the source of a transfer, and the ones which weren't messages or were quarantined
+ALTER TABLE transfer_audit
+  ADD COLUMN source_lines BIGINT NOT NULL DEFAULT 0,
+  ADD COLUMN skipped_lines BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1,176 @@
+//! Data export and deletion of the accounts of API users.
+//!
+//! Deleting an account removes the user's API tokens, generations (along with their share links), and channel admin
+//! rights. Their chat logs, allowlist entry, and quote opt-out are kept, as they aren't part of the API account.
+
+use super::Result;
+use crate::generations::{Generation, Share};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+#[derive(Debug, Clone, sqlx::FromRow, Serialize, getset::Getters, getset::CopyGetters)]
+pub struct Deletion {
+  #[getset(get_copy = "pub")]
+  #[serde(skip)]
+  id: i64,
+  #[getset(get_copy = "pub")]
+  #[serde(skip)]
+  user_id: i32,
+  #[getset(get_copy = "pub")]
+  requested_at: DateTime<Utc>,
+  /// The account is deleted once this time has passed, unless the deletion is cancelled
+  #[getset(get_copy = "pub")]
+  delete_after: DateTime<Utc>,
+  #[getset(get_copy = "pub")]
+  cancelled_at: Option<DateTime<Utc>>,
+  #[getset(get_copy = "pub")]
+  completed_at: Option<DateTime<Utc>>,
+}
+
+/// Everything stored about an API user
+#[derive(Debug, Serialize)]
+pub struct Export {
+  pub user_id: i32,
+  pub username: String,
+  /// Number of API tokens which are still valid. The tokens themselves aren't exported.
+  pub active_tokens: i64,
+  pub generations: Vec<Generation>,
+  pub shares: Vec<Share>,
+  /// Channels the user is an admin of
+  pub admin_of: Vec<String>,
+  pub quote_opt_out_at: Option<DateTime<Utc>>,
+  /// Current and past deletion requests
+  pub deletions: Vec<Deletion>,
+}
+
+pub async fn export(executor: impl sqlx::PgExecutor<'_> + Copy, user_id: i32) -> Result<Export> {
+  let username = sqlx::query_scalar::<_, String>("SELECT username FROM twitch_user WHERE id = $1")
+    .bind(user_id)
+    .fetch_one(executor)
+    .await?;
+  let active_tokens = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM tokens WHERE user_id = $1")
+    .bind(user_id)
+    .fetch_one(executor)
+    .await?;
+  let generations = sqlx::query_as::<_, Generation>("SELECT * FROM generations WHERE user_id = $1 ORDER BY id")
+    .bind(user_id)
+    .fetch_all(executor)
+    .await?;
+  let shares = sqlx::query_as::<_, Share>(
+    "
+    SELECT generation_shares.token, generation_shares.generation, generation_shares.expires_at
+      FROM generation_shares
+      INNER JOIN generations ON generations.id = generation_shares.generation
+      WHERE generations.user_id = $1
+      ORDER BY generation_shares.created_at
+    ",
+  )
+  .bind(user_id)
+  .fetch_all(executor)
+  .await?;
+  let admin_of = sqlx::query_scalar::<_, String>(
+    "
+    SELECT twitch_user.username FROM channel_admins
+      INNER JOIN twitch_user ON twitch_user.id = channel_admins.channel
+      WHERE channel_admins.user_id = $1
+      ORDER BY twitch_user.username
+    ",
+  )
+  .bind(user_id)
+  .fetch_all(executor)
+  .await?;
+  let quote_opt_out_at =
+    sqlx::query_scalar::<_, DateTime<Utc>>("SELECT opted_out_at FROM quote_opt_outs WHERE user_id = $1")
+      .bind(user_id)
+      .fetch_optional(executor)
+      .await?;
+  let deletions =
+    sqlx::query_as::<_, Deletion>("SELECT * FROM account_deletions WHERE user_id = $1 ORDER BY requested_at")
+      .bind(user_id)
+      .fetch_all(executor)
+      .await?;
+
+  Ok(Export {
+    user_id,
+    username,
+    active_tokens,
+    generations,
+    shares,
+    admin_of,
+    quote_opt_out_at,
+    deletions,
+  })
+}
+
+/// Schedules the deletion of the account of `user_id` at `delete_after`.
+/// If a deletion is already pending, it's returned unchanged.
+pub async fn request_deletion(
+  executor: impl sqlx::PgExecutor<'_> + Copy,
+  user_id: i32,
+  delete_after: DateTime<Utc>,
+) -> Result<Deletion> {
+  sqlx::query(
+    "
+    INSERT INTO account_deletions (user_id, delete_after)
+      VALUES ($1, $2)
+    ON CONFLICT (user_id) WHERE cancelled_at IS NULL AND completed_at IS NULL DO NOTHING
+    ",
+  )
+  .bind(user_id)
+  .bind(delete_after)
+  .execute(executor)
+  .await?;
+  sqlx::query_as::<_, Deletion>(
+    "SELECT * FROM account_deletions WHERE user_id = $1 AND cancelled_at IS NULL AND completed_at IS NULL",
+  )
+  .bind(user_id)
+  .fetch_one(executor)
+  .await
+}
+
+/// Returns the pending deletion of the account of `user_id`, if there is one
+pub async fn pending_deletion(executor: impl sqlx::PgExecutor<'_>, user_id: i32) -> Result<Option<Deletion>> {
+  sqlx::query_as::<_, Deletion>(
+    "SELECT * FROM account_deletions WHERE user_id = $1 AND cancelled_at IS NULL AND completed_at IS NULL",
+  )
+  .bind(user_id)
+  .fetch_optional(executor)
+  .await
+}
+
+/// Cancels the pending deletion of the account of `user_id`. Returns `false` if there wasn't one.
+pub async fn cancel_deletion(executor: impl sqlx::PgExecutor<'_>, user_id: i32) -> Result<bool> {
+  sqlx::query(
+    "
+    UPDATE account_deletions SET cancelled_at = NOW()
+      WHERE user_id = $1 AND cancelled_at IS NULL AND completed_at IS NULL
+    ",
+  )
+  .bind(user_id)
+  .execute(executor)
+  .await
+  .map(|r| r.rows_affected() > 0)
+}
+
+/// Deletes the accounts whose grace period is over, and returns their user IDs
+pub async fn delete_due(executor: impl sqlx::PgExecutor<'_>) -> Result<Vec<i32>> {
+  // Share links are deleted along with their generation
+  sqlx::query_scalar::<_, i32>(
+    "
+    WITH due AS (
+      UPDATE account_deletions SET completed_at = NOW()
+        WHERE cancelled_at IS NULL AND completed_at IS NULL AND delete_after <= NOW()
+      RETURNING user_id
+    ), deleted_generations AS (
+      DELETE FROM generations WHERE user_id IN (SELECT user_id FROM due)
+    ), deleted_tokens AS (
+      DELETE FROM tokens WHERE user_id IN (SELECT user_id FROM due)
+    ), deleted_admins AS (
+      DELETE FROM channel_admins WHERE user_id IN (SELECT user_id FROM due)
+    )
+    SELECT user_id FROM due
+    ",
+  )
+  .fetch_all(executor)
+  .await
+}
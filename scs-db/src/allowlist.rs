@@ -25,3 +25,16 @@ pub async fn remove(executor: impl sqlx::PgExecutor<'_>, ids: &[i32]) -> Result<
   .await?;
   Ok(())
 }
+
+pub async fn is_admin(executor: impl sqlx::PgExecutor<'_>, id: i32) -> Result<bool> {
+  sqlx::query_scalar::<_, bool>(
+    "
+    SELECT is_admin FROM allowlist
+      WHERE id = $1
+    ",
+  )
+  .bind(id)
+  .fetch_optional(executor)
+  .await
+  .map(|v| v.unwrap_or(false))
+}
@@ -0,0 +1,51 @@
+//! Users with admin rights in specific channels. Unlike [`crate::allowlist::is_admin`], which applies to every channel.
+
+use super::Result;
+
+/// Makes `user_id` an admin of `channel`, returns `false` if they already were one
+pub async fn grant(executor: impl sqlx::PgExecutor<'_>, user_id: i32, channel: &str) -> Result<bool> {
+  sqlx::query(
+    "
+    INSERT INTO channel_admins (user_id, channel)
+      SELECT $1, id FROM twitch_user WHERE username = $2
+    ON CONFLICT DO NOTHING
+    ",
+  )
+  .bind(user_id)
+  .bind(channel)
+  .execute(executor)
+  .await
+  .map(|r| r.rows_affected() > 0)
+}
+
+/// Removes the admin rights of `user_id` in `channel`, returns `false` if they weren't an admin of it
+pub async fn revoke(executor: impl sqlx::PgExecutor<'_>, user_id: i32, channel: &str) -> Result<bool> {
+  sqlx::query(
+    "
+    DELETE FROM channel_admins
+      WHERE user_id = $1
+        AND channel = (SELECT id FROM twitch_user WHERE username = $2)
+    ",
+  )
+  .bind(user_id)
+  .bind(channel)
+  .execute(executor)
+  .await
+  .map(|r| r.rows_affected() > 0)
+}
+
+pub async fn is_channel_admin(executor: impl sqlx::PgExecutor<'_>, user_id: i32, channel: &str) -> Result<bool> {
+  sqlx::query_scalar::<_, bool>(
+    "
+    SELECT EXISTS (
+      SELECT 1 FROM channel_admins
+        WHERE user_id = $1
+          AND channel = (SELECT id FROM twitch_user WHERE username = $2)
+    )
+    ",
+  )
+  .bind(user_id)
+  .bind(channel)
+  .fetch_one(executor)
+  .await
+}
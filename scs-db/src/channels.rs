@@ -1,15 +1,88 @@
 use super::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
 
 pub async fn get_logged_channels(executor: impl sqlx::PgExecutor<'_>) -> Result<Vec<String>> {
   // TODO: be move careful with this one if we start logging more channels
 
-  sqlx::query_scalar::<_, String>(
-    "
+  crate::metrics::instrument(
+    "get_logged_channels",
+    sqlx::query_scalar::<_, String>(
+      "
       SELECT username FROM twitch_user
         WHERE is_logged_as_channel = true
       ",
+    )
+    .fetch_all(executor),
+  )
+  .await
+}
+
+#[derive(Debug, sqlx::FromRow, Serialize, getset::Getters, getset::CopyGetters)]
+pub struct ChannelInfo {
+  #[getset(get = "pub")]
+  name: String,
+  #[getset(get_copy = "pub")]
+  message_count: i64,
+  #[getset(get_copy = "pub")]
+  chatter_count: i64,
+  #[getset(get_copy = "pub")]
+  first_message_at: Option<DateTime<Utc>>,
+  #[getset(get_copy = "pub")]
+  last_message_at: Option<DateTime<Utc>>,
+}
+
+/// Same as [`get_logged_channels`], but also returns the statistics of each channel
+pub async fn get_logged_channels_with_stats(executor: impl sqlx::PgExecutor<'_>) -> Result<Vec<ChannelInfo>> {
+  crate::metrics::instrument(
+    "get_logged_channels_with_stats",
+    sqlx::query_as::<_, ChannelInfo>(
+      "
+    SELECT
+      tw.username name,
+      COALESCE(stats.message_count, 0) message_count,
+      (SELECT COUNT(*) FROM twitch_channel_chatters c WHERE c.channel = tw.id) chatter_count,
+      stats.first_message_at,
+      stats.last_message_at
+    FROM twitch_user tw
+    LEFT JOIN twitch_channel_stats stats ON stats.channel = tw.id
+    WHERE tw.is_logged_as_channel = true
+    ORDER BY tw.username
+    ",
+    )
+    .fetch_all(executor),
+  )
+  .await
+}
+
+/// Summary of the statistics returned by [`get_logged_channels_with_stats`], which changes whenever they do
+#[derive(Debug, Clone, PartialEq, Eq, Hash, sqlx::FromRow, getset::CopyGetters)]
+pub struct ChannelsVersion {
+  #[getset(get_copy = "pub")]
+  channel_count: i64,
+  #[getset(get_copy = "pub")]
+  message_count: i64,
+  #[getset(get_copy = "pub")]
+  last_message_at: Option<DateTime<Utc>>,
+}
+
+/// Cheaper than [`get_logged_channels_with_stats`], used to tell whether its result changed
+pub async fn get_logged_channels_version(executor: impl sqlx::PgExecutor<'_>) -> Result<ChannelsVersion> {
+  crate::metrics::instrument(
+    "get_logged_channels_version",
+    sqlx::query_as::<_, ChannelsVersion>(
+      "
+    SELECT
+      COUNT(*) channel_count,
+      COALESCE(SUM(stats.message_count), 0)::BIGINT message_count,
+      MAX(stats.last_message_at) last_message_at
+    FROM twitch_user tw
+    LEFT JOIN twitch_channel_stats stats ON stats.channel = tw.id
+    WHERE tw.is_logged_as_channel = true
+    ",
+    )
+    .fetch_one(executor),
   )
-  .fetch_all(executor)
   .await
 }
 
@@ -21,53 +94,11 @@ macro_rules! get_channel_id_sql {
 }
 
 pub async fn get_channel_id(executor: impl sqlx::PgExecutor<'_>, username: &str) -> Result<i32> {
-  sqlx::query_scalar::<_, i32>(&get_channel_id_sql!("1"))
-    .bind(username)
-    .fetch_one(executor)
-    .await
-}
-
-pub async fn get_or_create_channel(
-  executor: impl sqlx::PgExecutor<'_> + Copy,
-  username: &str,
-  is_logged_as_channel: bool,
-  cache: &mut ahash::AHashMap<String, i32>,
-) -> Result<i32> {
-  // Fast path: username is in the local cache
-  if let Some(id) = cache.get(username).copied() {
-    return Ok(id);
-  }
-
-  // Slow path: user is either in the database or doesn't exist
-  // This seems to be 30% faster than doing SELECT + INSERT
-  let id = sqlx::query_scalar::<_, i32>(
-    r#"
-    WITH
-      input_rows (username, is_logged_as_channel) AS (VALUES ($1, $2)),
-      inserted AS (
-        INSERT INTO twitch_user (username, is_logged_as_channel)
-          SELECT * FROM input_rows
-          ON CONFLICT (username) 
-            DO UPDATE 
-              SET is_logged_as_channel = EXCLUDED.is_logged_as_channel 
-              WHERE twitch_user.username = EXCLUDED.username 
-              AND twitch_user.is_logged_as_channel = FALSE
-          RETURNING id, is_logged_as_channel
-      )
-    SELECT id, is_logged_as_channel FROM inserted
-    UNION ALL
-    SELECT
-      c.id, c.is_logged_as_channel
-    FROM
-      input_rows
-      JOIN twitch_user c USING (username);
-    "#,
+  crate::metrics::instrument(
+    "get_channel_id",
+    sqlx::query_scalar::<_, i32>(&get_channel_id_sql!("1"))
+      .bind(username)
+      .fetch_one(executor),
   )
-  .bind(username)
-  .bind(is_logged_as_channel)
-  .fetch_one(executor)
-  .await?;
-
-  cache.insert(username.to_owned(), id);
-  Ok(id)
+  .await
 }
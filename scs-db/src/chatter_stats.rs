@@ -0,0 +1,163 @@
+//! Word statistics of a chatter's messages, to compare their real speech with a model's output
+
+use super::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+/// Messages with at least this many words are counted in the last length bucket
+pub const MAX_LENGTH_BUCKET: i32 = 32;
+
+#[derive(Debug, Clone, Serialize, getset::Getters, getset::CopyGetters)]
+pub struct TermCount {
+  #[getset(get = "pub")]
+  term: String,
+  #[getset(get_copy = "pub")]
+  count: i64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, getset::CopyGetters)]
+#[getset(get_copy = "pub")]
+pub struct LengthBucket {
+  /// Number of words, the last bucket also contains every longer message
+  words: i32,
+  messages: i64,
+}
+
+#[derive(Debug, Clone, Serialize, getset::Getters, getset::CopyGetters)]
+pub struct ChatterStats {
+  /// Messages sent by the chatter in the time range
+  #[getset(get_copy = "pub")]
+  messages: i64,
+  /// Messages the statistics were computed from
+  #[getset(get_copy = "pub")]
+  sampled_messages: i64,
+  /// Whether the statistics were computed from a random sample of the messages
+  #[getset(get_copy = "pub")]
+  sampled: bool,
+  #[getset(get = "pub")]
+  top_words: Vec<TermCount>,
+  #[getset(get = "pub")]
+  top_bigrams: Vec<TermCount>,
+  /// Number of messages per word count, ordered by word count
+  #[getset(get = "pub")]
+  lengths: Vec<LengthBucket>,
+}
+
+#[derive(sqlx::FromRow)]
+struct Row {
+  message: String,
+  #[cfg(feature = "compression")]
+  message_zstd: Option<Vec<u8>>,
+  #[cfg(feature = "compression")]
+  dictionary: Option<i32>,
+}
+
+#[cfg(feature = "compression")]
+impl crate::compression::CompressedMessage for Row {
+  fn compressed(&self) -> Option<(i32, &[u8])> {
+    self.dictionary.zip(self.message_zstd.as_deref())
+  }
+
+  fn set_message(&mut self, message: String) {
+    self.message = message;
+  }
+}
+
+/// Computes the most common words and bigrams of `chatter` in `channel`, and the distribution of their message lengths
+/// in words, from the messages sent in `[since, until)`. Redacted messages are excluded.
+///
+/// If there are more than `max_messages` messages, a random sample of `max_messages` of them is used.
+/// Words are split on whitespace and kept as they are, since emotes are case-sensitive.
+///
+/// The sample is counted after it's fetched, so that compressed messages can be decompressed first. Without compression,
+/// they're left out of the sample, and the statistics are marked as sampled.
+pub async fn fetch(
+  executor: impl sqlx::PgExecutor<'_> + Copy,
+  channel: &str,
+  chatter: &str,
+  since: DateTime<Utc>,
+  until: DateTime<Utc>,
+  max_messages: i64,
+  top: i64,
+) -> Result<ChatterStats> {
+  let messages = sqlx::query_scalar::<_, i64>(
+    r"
+    SELECT COUNT(*) FROM twitch_logs
+      WHERE channel = (SELECT id FROM twitch_user WHERE username = $1)
+        AND chatter = (SELECT id FROM twitch_user WHERE username = $2)
+        AND sent_at >= $3
+        AND sent_at < $4
+        AND redacted_at IS NULL
+    ",
+  )
+  .bind(channel)
+  .bind(chatter)
+  .bind(since)
+  .bind(until)
+  .fetch_one(executor)
+  .await?;
+
+  #[allow(unused_mut)]
+  let mut sample = sqlx::query_as::<_, Row>(
+    r"
+    SELECT message, message_zstd, dictionary FROM twitch_logs
+      WHERE channel = (SELECT id FROM twitch_user WHERE username = $1)
+        AND chatter = (SELECT id FROM twitch_user WHERE username = $2)
+        AND sent_at >= $3
+        AND sent_at < $4
+        AND redacted_at IS NULL
+        AND ($5 OR message_zstd IS NULL)
+      ORDER BY random()
+      LIMIT $6
+    ",
+  )
+  .bind(channel)
+  .bind(chatter)
+  .bind(since)
+  .bind(until)
+  .bind(cfg!(feature = "compression"))
+  .bind(max_messages)
+  .fetch_all(executor)
+  .await?;
+  #[cfg(feature = "compression")]
+  crate::compression::decompress(executor, &mut sample).await?;
+
+  let mut words = HashMap::<&str, i64>::new();
+  let mut bigrams = HashMap::<String, i64>::new();
+  let mut lengths = BTreeMap::<i32, i64>::new();
+  for row in &sample {
+    let split = row.message.split_whitespace().collect::<Vec<_>>();
+    for word in &split {
+      *words.entry(*word).or_default() += 1;
+    }
+    for pair in split.windows(2) {
+      *bigrams.entry(pair.join(" ")).or_default() += 1;
+    }
+    let length = i32::try_from(split.len()).unwrap_or(i32::MAX).min(MAX_LENGTH_BUCKET);
+    *lengths.entry(length).or_default() += 1;
+  }
+
+  let sampled_messages = sample.len() as i64;
+  Ok(ChatterStats {
+    messages,
+    sampled_messages,
+    sampled: messages > sampled_messages,
+    top_words: most_common(words.into_iter().map(|(word, count)| (word.to_owned(), count)), top),
+    top_bigrams: most_common(bigrams.into_iter(), top),
+    lengths: lengths
+      .into_iter()
+      .map(|(words, messages)| LengthBucket { words, messages })
+      .collect(),
+  })
+}
+
+/// The `top` terms with the highest counts, ordered by count and then by term
+fn most_common(counts: impl Iterator<Item = (String, i64)>, top: i64) -> Vec<TermCount> {
+  let mut terms = counts
+    .map(|(term, count)| TermCount { term, count })
+    .collect::<Vec<_>>();
+  terms.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.term.cmp(&b.term)));
+  terms.truncate(usize::try_from(top).unwrap_or(0));
+  terms
+}
@@ -0,0 +1,369 @@
+//! Compression of message text with Zstandard dictionaries trained per channel.
+//!
+//! Compressed messages are stored with an empty `message`, their compressed text in `message_zstd`,
+//! and the ID of their dictionary in `dictionary`. The fetch functions of [`crate::logs`] and
+//! [`crate::retention`] decompress them transparently. Searches, redactions by pattern, phrase counts and quotes
+//! can't match their text in SQL, so they decompress them and match them with [`matches_like`].
+
+use super::Result;
+use crate::{
+  logs::{DailyInserts, SOAEntry},
+  resolver::UserResolver,
+};
+use chrono::NaiveDate;
+use std::{
+  collections::HashMap,
+  io::Read,
+  sync::{Arc, Mutex, OnceLock},
+};
+use zstd::dict::{DecoderDictionary, EncoderDictionary};
+
+/// Compression level, higher is smaller but slower
+pub const LEVEL: i32 = 3;
+/// Maximum size of a trained dictionary, in bytes
+pub const DICTIONARY_SIZE: usize = 64 * 1024;
+/// Dictionaries trained on fewer messages than this don't compress well, so the messages are stored uncompressed
+pub const MIN_SAMPLES: usize = 1000;
+
+/// A row whose message may be compressed
+pub trait CompressedMessage {
+  /// ID of the dictionary and the compressed text, if the message is compressed
+  fn compressed(&self) -> Option<(i32, &[u8])>;
+  /// Replaces the message with its decompressed text
+  fn set_message(&mut self, message: String);
+}
+
+enum LikeToken {
+  /// `%`
+  Any,
+  /// `_`
+  One,
+  Char(char),
+}
+
+/// Whether `text` matches the `LIKE` pattern `pattern`, or the `ILIKE` pattern with `ignore_case`, where `%` matches any
+/// number of characters, `_` exactly one, and `\` escapes the next character
+pub fn matches_like(pattern: &str, text: &str, ignore_case: bool) -> bool {
+  let (pattern, text) = if ignore_case {
+    (pattern.to_lowercase(), text.to_lowercase())
+  } else {
+    (pattern.to_owned(), text.to_owned())
+  };
+  let mut tokens = Vec::with_capacity(pattern.len());
+  let mut chars = pattern.chars();
+  while let Some(c) = chars.next() {
+    tokens.push(match c {
+      '%' => LikeToken::Any,
+      '_' => LikeToken::One,
+      '\\' => LikeToken::Char(chars.next().unwrap_or('\\')),
+      c => LikeToken::Char(c),
+    });
+  }
+  let text = text.chars().collect::<Vec<_>>();
+
+  // Greedy matching, which backtracks to the last `%` when the rest of the pattern doesn't match
+  let (mut p, mut t) = (0, 0);
+  let mut backtrack = None;
+  while t < text.len() {
+    match tokens.get(p) {
+      Some(LikeToken::Any) => {
+        backtrack = Some((p, t));
+        p += 1;
+      }
+      Some(LikeToken::One) => {
+        p += 1;
+        t += 1;
+      }
+      Some(LikeToken::Char(c)) if *c == text[t] => {
+        p += 1;
+        t += 1;
+      }
+      _ => match backtrack {
+        Some((any, start)) => {
+          backtrack = Some((any, start + 1));
+          p = any + 1;
+          t = start + 1;
+        }
+        None => return false,
+      },
+    }
+  }
+  tokens[p..].iter().all(|token| matches!(token, LikeToken::Any))
+}
+
+fn decode_error(e: impl std::error::Error + Send + Sync + 'static) -> sqlx::Error {
+  sqlx::Error::Decode(Box::new(e))
+}
+
+/// Returns the most recent dictionary of `channel`
+pub async fn latest_dictionary(executor: impl sqlx::PgExecutor<'_>, channel: i32) -> Result<Option<(i32, Vec<u8>)>> {
+  sqlx::query_as::<_, (i32, Vec<u8>)>(
+    "SELECT id, dictionary FROM log_dictionaries WHERE channel = $1 ORDER BY id DESC LIMIT 1",
+  )
+  .bind(channel)
+  .fetch_optional(executor)
+  .await
+}
+
+/// Trains a dictionary on `samples` and stores it as the latest one of `channel`.
+///
+/// Returns `None` if there are fewer than [`MIN_SAMPLES`] samples, or if they're too small to train a dictionary on.
+pub async fn train(
+  executor: impl sqlx::PgExecutor<'_>,
+  channel: i32,
+  samples: &[&str],
+) -> Result<Option<(i32, Vec<u8>)>> {
+  if samples.len() < MIN_SAMPLES {
+    return Ok(None);
+  }
+  let dictionary = match zstd::dict::from_samples(samples, DICTIONARY_SIZE) {
+    Ok(dictionary) => dictionary,
+    Err(e) => {
+      log::warn!("Failed to train a dictionary on {} messages: {}", samples.len(), e);
+      return Ok(None);
+    }
+  };
+  let id =
+    sqlx::query_scalar::<_, i32>("INSERT INTO log_dictionaries (channel, dictionary) VALUES ($1, $2) RETURNING id")
+      .bind(channel)
+      .bind(&dictionary)
+      .fetch_one(executor)
+      .await?;
+  Ok(Some((id, dictionary)))
+}
+
+/// Compresses messages with the latest dictionary of their channel
+#[derive(Default)]
+pub struct Compressor {
+  /// `None` if the channel doesn't have a dictionary yet
+  dictionaries: HashMap<i32, Option<(i32, EncoderDictionary<'static>)>>,
+}
+
+impl Compressor {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Loads the dictionaries of the channels in `entry`. Channels without a dictionary get one
+  /// trained on their messages in `entry`, if there are enough of them.
+  async fn prepare(&mut self, executor: impl sqlx::PgExecutor<'_> + Copy, entry: &SOAEntry) -> Result<()> {
+    let mut missing = HashMap::<i32, Vec<&str>>::new();
+    for (channel, message) in entry.channel.iter().zip(entry.message.iter()) {
+      if !matches!(self.dictionaries.get(channel), Some(Some(_))) {
+        missing.entry(*channel).or_default().push(message.as_str());
+      }
+    }
+
+    for (channel, samples) in missing {
+      let dictionary = match latest_dictionary(executor, channel).await? {
+        Some(dictionary) => Some(dictionary),
+        None => {
+          let trained = train(executor, channel, &samples).await?;
+          if let Some((id, dictionary)) = &trained {
+            log::info!(
+              "Trained dictionary {} of {} bytes on {} messages of channel {}",
+              id,
+              dictionary.len(),
+              samples.len(),
+              channel
+            );
+          }
+          trained
+        }
+      };
+      self.dictionaries.insert(
+        channel,
+        dictionary.map(|(id, dictionary)| (id, EncoderDictionary::copy(&dictionary, LEVEL))),
+      );
+    }
+    Ok(())
+  }
+
+  /// Returns the ID of the dictionary and the compressed message, or `None` if `channel` has no dictionary
+  fn compress(&self, channel: i32, message: &str) -> Result<Option<(i32, Vec<u8>)>> {
+    let Some(Some((id, dictionary))) = self.dictionaries.get(&channel) else {
+      return Ok(None);
+    };
+    let mut compressor = zstd::bulk::Compressor::with_prepared_dictionary(dictionary).map_err(decode_error)?;
+    let compressed = compressor.compress(message.as_bytes()).map_err(decode_error)?;
+    Ok(Some((*id, compressed)))
+  }
+}
+
+/// Same as [`crate::logs::insert_soa_with_resolver`], but the messages are compressed with `compressor`
+///
+/// `entries` will be cleared
+pub async fn insert_soa_with_resolver(
+  executor: impl sqlx::PgExecutor<'_> + Copy,
+  resolver: &mut UserResolver,
+  compressor: &mut Compressor,
+  entry: &mut SOAEntry,
+) -> Result<DailyInserts> {
+  let prepared = prepare(executor, resolver, compressor, entry).await?;
+  insert_prepared(executor, prepared, entry).await
+}
+
+/// Chatter IDs and compressed messages of an entry, see [`prepare`]
+pub(crate) struct Prepared {
+  chatters: Vec<i32>,
+  compressed: Vec<Option<Vec<u8>>>,
+  dictionaries: Vec<Option<i32>>,
+}
+
+/// Resolves the chatters of `entry`, and compresses its messages, clearing the ones which were compressed
+pub(crate) async fn prepare(
+  executor: impl sqlx::PgExecutor<'_> + Copy,
+  resolver: &mut UserResolver,
+  compressor: &mut Compressor,
+  entry: &mut SOAEntry,
+) -> Result<Prepared> {
+  let chatters = resolver.resolve_many(executor, &entry.chatter).await?;
+  compressor.prepare(executor, entry).await?;
+
+  let mut compressed = Vec::with_capacity(entry.len());
+  let mut dictionaries = Vec::with_capacity(entry.len());
+  for (channel, message) in entry.channel.iter().zip(entry.message.iter_mut()) {
+    match compressor.compress(*channel, message)? {
+      Some((id, data)) => {
+        message.clear();
+        compressed.push(Some(data));
+        dictionaries.push(Some(id));
+      }
+      None => {
+        compressed.push(None);
+        dictionaries.push(None);
+      }
+    }
+  }
+  Ok(Prepared {
+    chatters,
+    compressed,
+    dictionaries,
+  })
+}
+
+/// Completes [`insert_soa_with_resolver`] once the entry is [`prepare`]d
+pub(crate) async fn insert_prepared(
+  executor: impl sqlx::PgExecutor<'_>,
+  prepared: Prepared,
+  entry: &mut SOAEntry,
+) -> Result<DailyInserts> {
+  let Prepared {
+    chatters,
+    compressed,
+    dictionaries,
+  } = prepared;
+  let rows = crate::metrics::instrument(
+    "insert_soa_compressed",
+    sqlx::query_as::<_, (i32, NaiveDate, i64, i64, i64)>(
+      "
+    WITH inserted AS (
+      INSERT INTO twitch_logs (channel, chatter, sent_at, message, message_zstd, dictionary, language, seq)
+        SELECT * FROM UNNEST($1, $2, $3, $4, $5, $6, $8, $9)
+      RETURNING channel, id, sent_at
+    ), notified AS (
+      SELECT inserted.channel, pg_notify($7, json_build_object(
+        'channel', tw.username,
+        'channel_id', inserted.channel,
+        'count', COUNT(*),
+        'first_id', MIN(inserted.id),
+        'last_id', MAX(inserted.id)
+      )::text)
+      FROM inserted
+      JOIN twitch_user tw ON tw.id = inserted.channel
+      GROUP BY inserted.channel, tw.username
+    )
+    -- Joined with `notified`, as a CTE which isn't referenced isn't evaluated
+    SELECT inserted.channel, (inserted.sent_at AT TIME ZONE 'UTC')::date AS day, COUNT(*), MIN(inserted.id),
+      MAX(inserted.id)
+    FROM inserted
+    JOIN notified ON notified.channel = inserted.channel
+    GROUP BY inserted.channel, day;
+    ",
+    )
+    .bind(&entry.channel)
+    .bind(&chatters)
+    .bind(&entry.sent_at)
+    .bind(&entry.message)
+    .bind(&compressed)
+    .bind(&dictionaries)
+    .bind(crate::notify::CHANNEL)
+    .bind(&entry.language)
+    .bind(&entry.seq)
+    .fetch_all(executor),
+  )
+  .await?;
+
+  entry.clear();
+
+  Ok(crate::logs::daily_inserts(rows))
+}
+
+type DecoderCache = Mutex<HashMap<i32, Arc<DecoderDictionary<'static>>>>;
+
+/// Dictionaries are never modified once they're stored, so they're cached for the lifetime of the process
+fn decoders() -> &'static DecoderCache {
+  static DECODERS: OnceLock<DecoderCache> = OnceLock::new();
+  DECODERS.get_or_init(Default::default)
+}
+
+/// Decompresses the messages of `rows` which are compressed, loading the dictionaries which aren't cached yet
+pub async fn decompress<T: CompressedMessage>(
+  executor: impl sqlx::PgExecutor<'_> + Copy,
+  rows: &mut [T],
+) -> Result<()> {
+  let mut missing = rows
+    .iter()
+    .filter_map(|row| row.compressed().map(|(id, _)| id))
+    .filter(|id| !decoders().lock().unwrap().contains_key(id))
+    .collect::<Vec<_>>();
+  missing.sort_unstable();
+  missing.dedup();
+  if !missing.is_empty() {
+    let loaded = sqlx::query_as::<_, (i32, Vec<u8>)>("SELECT id, dictionary FROM log_dictionaries WHERE id = ANY($1)")
+      .bind(&missing)
+      .fetch_all(executor)
+      .await?;
+    let mut decoders = decoders().lock().unwrap();
+    for (id, dictionary) in loaded {
+      decoders.insert(id, Arc::new(DecoderDictionary::copy(&dictionary)));
+    }
+  }
+
+  for row in rows {
+    let Some((id, data)) = row.compressed() else {
+      continue;
+    };
+    let dictionary = decoders()
+      .lock()
+      .unwrap()
+      .get(&id)
+      .cloned()
+      .ok_or_else(|| sqlx::Error::Decode(format!("Dictionary {id} does not exist").into()))?;
+    let mut message = String::new();
+    zstd::stream::read::Decoder::with_prepared_dictionary(data, &dictionary)
+      .and_then(|mut decoder| decoder.read_to_string(&mut message))
+      .map_err(decode_error)?;
+    row.set_message(message);
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn like_patterns() {
+    assert!(matches_like("%yo%", "oh yo chat", false));
+    assert!(!matches_like("%yo%", "oh YO chat", false));
+    assert!(matches_like("%yo%", "oh YO chat", true));
+    assert!(matches_like("y_", "yo", false));
+    assert!(!matches_like("y_", "y", false));
+    assert!(matches_like("%a%b%", "xxaxxbxx", false));
+    assert!(!matches_like("%a%b", "xxaxxbxx", false));
+    assert!(matches_like("%100\\%%", "it's 100% real", false));
+    assert!(!matches_like("%100\\%%", "it's 1000 real", false));
+    assert!(matches_like("%", "", false));
+  }
+}
@@ -0,0 +1,227 @@
+use super::Result;
+use crate::pagination::{Cursor, Keyed};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+#[derive(Debug, Clone, sqlx::FromRow, Serialize, getset::Getters, getset::CopyGetters)]
+pub struct Generation {
+  #[getset(get_copy = "pub")]
+  id: i64,
+  #[getset(get_copy = "pub")]
+  #[serde(skip)]
+  user_id: i32,
+  #[getset(get = "pub")]
+  model: String,
+  #[getset(get = "pub")]
+  seed: String,
+  #[getset(get = "pub")]
+  text: String,
+  #[getset(get_copy = "pub")]
+  created_at: DateTime<Utc>,
+  #[getset(get_copy = "pub")]
+  favorite: bool,
+}
+
+impl Keyed for Generation {
+  fn cursor(&self) -> Cursor {
+    Cursor::new(self.id, self.created_at)
+  }
+}
+
+/// Filters of a user's generation history
+#[derive(Debug, Clone, Default)]
+pub struct HistoryFilter {
+  /// Only generations created at or after this time
+  pub since: Option<DateTime<Utc>>,
+  /// Only generations created before this time
+  pub until: Option<DateTime<Utc>>,
+  /// Only generations of these models, or of every model if it's empty
+  pub models: Vec<String>,
+  pub favorites_only: bool,
+}
+
+/// How much a model was used to generate text
+#[derive(Debug, Clone, sqlx::FromRow, Serialize, getset::Getters, getset::CopyGetters)]
+pub struct ModelUsage {
+  #[getset(get = "pub")]
+  #[serde(skip)]
+  model: String,
+  #[getset(get_copy = "pub")]
+  generations: i64,
+  /// Number of distinct users who generated text with the model
+  #[getset(get_copy = "pub")]
+  users: i64,
+  #[getset(get_copy = "pub")]
+  last_generated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, sqlx::FromRow, Serialize, getset::Getters, getset::CopyGetters)]
+pub struct Share {
+  #[getset(get = "pub")]
+  token: String,
+  #[getset(get_copy = "pub")]
+  generation: i64,
+  #[getset(get_copy = "pub")]
+  expires_at: DateTime<Utc>,
+}
+
+pub async fn create(
+  executor: impl sqlx::PgExecutor<'_>,
+  user_id: i32,
+  model: &str,
+  seed: &str,
+  text: &str,
+) -> Result<Generation> {
+  sqlx::query_as::<_, Generation>(
+    "
+    INSERT INTO generations (user_id, model, seed, text)
+      VALUES ($1, $2, $3, $4)
+      RETURNING *
+    ",
+  )
+  .bind(user_id)
+  .bind(model)
+  .bind(seed)
+  .bind(text)
+  .fetch_one(executor)
+  .await
+}
+
+pub async fn get(executor: impl sqlx::PgExecutor<'_>, id: i64) -> Result<Option<Generation>> {
+  sqlx::query_as::<_, Generation>("SELECT * FROM generations WHERE id = $1")
+    .bind(id)
+    .fetch_optional(executor)
+    .await
+}
+
+/// Returns a page of the generations of `user_id` which match `filter`, newest first, starting after `cursor`
+pub async fn history(
+  executor: impl sqlx::PgExecutor<'_>,
+  user_id: i32,
+  filter: &HistoryFilter,
+  page_size: i32,
+  cursor: Option<Cursor>,
+) -> Result<Vec<Generation>> {
+  crate::metrics::instrument(
+    "generation_history",
+    sqlx::query_as::<_, Generation>(
+      "
+      SELECT * FROM generations
+        WHERE user_id = $1
+          AND ($2::TIMESTAMPTZ IS NULL OR created_at >= $2)
+          AND ($3::TIMESTAMPTZ IS NULL OR created_at < $3)
+          AND (CARDINALITY($4::TEXT[]) = 0 OR model = ANY($4))
+          AND (NOT $5 OR favorite)
+          AND ($6::TIMESTAMPTZ IS NULL OR (created_at, id) < ($6, $7))
+        ORDER BY created_at DESC, id DESC
+        LIMIT $8
+      ",
+    )
+    .bind(user_id)
+    .bind(filter.since)
+    .bind(filter.until)
+    .bind(&filter.models)
+    .bind(filter.favorites_only)
+    .bind(cursor.map(|cursor| cursor.timestamp))
+    .bind(cursor.map(|cursor| cursor.id))
+    .bind(page_size)
+    .fetch_all(executor),
+  )
+  .await
+}
+
+/// Marks a generation of `user_id` as a favorite or not. Returns `None` if it doesn't exist or belongs to another user.
+pub async fn set_favorite(
+  executor: impl sqlx::PgExecutor<'_>,
+  user_id: i32,
+  id: i64,
+  favorite: bool,
+) -> Result<Option<Generation>> {
+  sqlx::query_as::<_, Generation>("UPDATE generations SET favorite = $3 WHERE id = $1 AND user_id = $2 RETURNING *")
+    .bind(id)
+    .bind(user_id)
+    .bind(favorite)
+    .fetch_optional(executor)
+    .await
+}
+
+/// Deletes the generations of `user_id` among `ids`, along with their share links.
+/// Returns the IDs of the deleted generations, which excludes the ones that don't exist or belong to another user.
+pub async fn delete_many(executor: impl sqlx::PgExecutor<'_>, user_id: i32, ids: &[i64]) -> Result<Vec<i64>> {
+  crate::metrics::instrument(
+    "delete_generations",
+    sqlx::query_scalar::<_, i64>("DELETE FROM generations WHERE user_id = $1 AND id = ANY($2) RETURNING id")
+      .bind(user_id)
+      .bind(ids)
+      .fetch_all(executor),
+  )
+  .await
+}
+
+pub async fn create_share(
+  executor: impl sqlx::PgExecutor<'_>,
+  generation: i64,
+  token: &str,
+  expires_at: DateTime<Utc>,
+) -> Result<Share> {
+  sqlx::query_as::<_, Share>(
+    "
+    INSERT INTO generation_shares (token, generation, expires_at)
+      VALUES ($1, $2, $3)
+      RETURNING token, generation, expires_at
+    ",
+  )
+  .bind(token)
+  .bind(generation)
+  .bind(expires_at)
+  .fetch_one(executor)
+  .await
+}
+
+/// Returns the generation shared with `token`, or `None` if the token doesn't exist or has expired
+pub async fn get_shared(executor: impl sqlx::PgExecutor<'_>, token: &str) -> Result<Option<Generation>> {
+  sqlx::query_as::<_, Generation>(
+    "
+    SELECT generations.* FROM generation_shares
+      INNER JOIN generations ON generations.id = generation_shares.generation
+      WHERE generation_shares.token = $1 AND generation_shares.expires_at > NOW()
+    ",
+  )
+  .bind(token)
+  .fetch_optional(executor)
+  .await
+}
+
+/// Returns the usage of each model in `models` which was used at least once
+pub async fn usage_by_model(executor: impl sqlx::PgExecutor<'_>, models: &[String]) -> Result<Vec<ModelUsage>> {
+  sqlx::query_as::<_, ModelUsage>(
+    "
+    SELECT
+      model,
+      COUNT(*) AS generations,
+      COUNT(DISTINCT user_id) AS users,
+      MAX(created_at) AS last_generated_at
+    FROM generations
+    WHERE model = ANY($1)
+    GROUP BY model
+    ",
+  )
+  .bind(models)
+  .fetch_all(executor)
+  .await
+}
+
+/// ID of the latest generation, which changes whenever [`usage_by_model`] does, except for deleted generations
+pub async fn latest_id(executor: impl sqlx::PgExecutor<'_>) -> Result<Option<i64>> {
+  sqlx::query_scalar::<_, Option<i64>>("SELECT MAX(id) FROM generations")
+    .fetch_one(executor)
+    .await
+}
+
+/// Returns the number of deleted shares
+pub async fn delete_expired_shares(executor: impl sqlx::PgExecutor<'_>) -> Result<u64> {
+  sqlx::query("DELETE FROM generation_shares WHERE expires_at <= NOW()")
+    .execute(executor)
+    .await
+    .map(|r| r.rows_affected())
+}
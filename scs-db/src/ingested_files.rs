@@ -0,0 +1,76 @@
+//! Provenance of the log files imported by the ingester
+
+use super::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+#[derive(Debug, sqlx::FromRow, Serialize, getset::Getters, getset::CopyGetters)]
+pub struct IngestedFile {
+  #[getset(get = "pub")]
+  hash: String,
+  #[getset(get = "pub")]
+  original_path: String,
+  /// Username of the channel
+  #[getset(get = "pub")]
+  channel: String,
+  #[getset(get_copy = "pub")]
+  rows_inserted: i64,
+  #[getset(get_copy = "pub")]
+  ingested_at: DateTime<Utc>,
+}
+
+/// Records that the file with the contents hash `hash` was ingested into the channel with the ID `channel`.
+/// Returns `false` if a file with the same hash was already recorded, in which case it's kept.
+pub async fn record(
+  executor: impl sqlx::PgExecutor<'_>,
+  hash: &str,
+  original_path: &str,
+  channel: i32,
+  rows_inserted: i64,
+) -> Result<bool> {
+  let result = sqlx::query(
+    "
+    INSERT INTO ingested_files (hash, original_path, channel, rows_inserted)
+      VALUES ($1, $2, $3, $4)
+      ON CONFLICT (hash) DO NOTHING
+    ",
+  )
+  .bind(hash)
+  .bind(original_path)
+  .bind(channel)
+  .bind(rows_inserted)
+  .execute(executor)
+  .await?;
+  Ok(result.rows_affected() > 0)
+}
+
+/// Returns the file with the contents hash `hash`, if it was ingested
+pub async fn find(executor: impl sqlx::PgExecutor<'_>, hash: &str) -> Result<Option<IngestedFile>> {
+  sqlx::query_as::<_, IngestedFile>(
+    "
+    SELECT hash, original_path, tw.username channel, rows_inserted, ingested_at
+      FROM ingested_files f
+      JOIN twitch_user tw ON tw.id = f.channel
+      WHERE hash = $1
+    ",
+  )
+  .bind(hash)
+  .fetch_optional(executor)
+  .await
+}
+
+/// Returns the files ingested into `channel`, most recent first
+pub async fn fetch_by_channel(executor: impl sqlx::PgExecutor<'_>, channel: &str) -> Result<Vec<IngestedFile>> {
+  sqlx::query_as::<_, IngestedFile>(
+    "
+    SELECT hash, original_path, tw.username channel, rows_inserted, ingested_at
+      FROM ingested_files f
+      JOIN twitch_user tw ON tw.id = f.channel
+      WHERE tw.username = $1
+      ORDER BY ingested_at DESC
+    ",
+  )
+  .bind(channel)
+  .fetch_all(executor)
+  .await
+}
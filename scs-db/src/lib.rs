@@ -4,11 +4,33 @@ use sqlx::PgPool;
 
 pub use sqlx;
 
+pub mod accounts;
 pub mod allowlist;
+pub mod channel_admins;
 pub mod channels;
+pub mod chatter_stats;
+#[cfg(feature = "compression")]
+pub mod compression;
+pub mod generations;
+pub mod ingested_files;
 pub mod logs;
+pub mod metrics;
+pub mod notify;
+pub mod pagination;
+pub mod phrases;
+pub mod purge;
+pub mod query;
+pub mod quotes;
+pub mod resolver;
+pub mod retention;
+pub mod stats;
+#[cfg(feature = "test-harness")]
+pub mod testing;
 pub mod tokens;
+pub mod transfer_audits;
 pub mod users;
+pub mod viewers;
+pub mod webhooks;
 
 pub type Database = PgPool;
 
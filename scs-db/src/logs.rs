@@ -1,13 +1,21 @@
 use super::Result;
-use crate::users;
-use chrono::{DateTime, Utc};
+use crate::{
+  pagination::{Cursor, Keyed},
+  query::LogsQuery,
+  resolver::UserResolver,
+  users,
+};
+use chrono::{DateTime, NaiveDate, Utc};
 use serde::Serialize;
+use std::collections::BTreeMap;
 
 pub struct SOAEntry {
-  channel: Vec<i32>,
-  chatter: Vec<String>,
-  sent_at: Vec<DateTime<Utc>>,
-  message: Vec<String>,
+  pub(crate) channel: Vec<i32>,
+  pub(crate) chatter: Vec<String>,
+  pub(crate) sent_at: Vec<DateTime<Utc>>,
+  pub(crate) message: Vec<String>,
+  pub(crate) language: Vec<Option<String>>,
+  pub(crate) seq: Vec<Option<i64>>,
 }
 
 impl SOAEntry {
@@ -17,14 +25,52 @@ impl SOAEntry {
       chatter: Vec::with_capacity(capacity),
       sent_at: Vec::with_capacity(capacity),
       message: Vec::with_capacity(capacity),
+      language: Vec::with_capacity(capacity),
+      seq: Vec::with_capacity(capacity),
     }
   }
 
   pub fn add(&mut self, channel: i32, chatter: String, sent_at: DateTime<Utc>, message: String) {
+    self.add_with_language(channel, chatter, sent_at, message, None);
+  }
+
+  /// Same as [`SOAEntry::add`], with the ISO 639-3 code of the message's language, if it was detected
+  pub fn add_with_language(
+    &mut self,
+    channel: i32,
+    chatter: String,
+    sent_at: DateTime<Utc>,
+    message: String,
+    language: Option<String>,
+  ) {
+    self.add_with_sequence(channel, chatter, sent_at, message, language, None);
+  }
+
+  /// Same as [`SOAEntry::add_with_language`], with the sequence number the collector assigned to the message in its
+  /// channel, if it has one
+  pub fn add_with_sequence(
+    &mut self,
+    channel: i32,
+    chatter: String,
+    sent_at: DateTime<Utc>,
+    message: String,
+    language: Option<String>,
+    seq: Option<i64>,
+  ) {
     self.channel.push(channel);
     self.chatter.push(chatter);
     self.sent_at.push(sent_at);
     self.message.push(message);
+    self.language.push(language);
+    self.seq.push(seq);
+  }
+
+  pub fn len(&self) -> usize {
+    self.channel.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.channel.is_empty()
   }
 
   pub fn clear(&mut self) {
@@ -32,6 +78,8 @@ impl SOAEntry {
     self.chatter.clear();
     self.sent_at.clear();
     self.message.clear();
+    self.language.clear();
+    self.seq.clear();
   }
 }
 
@@ -45,6 +93,15 @@ pub struct Entry<U> {
   chatter: U,
   sent_at: DateTime<Utc>,
   message: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  redacted_at: Option<DateTime<Utc>>,
+  /// Compressed text of the message, see [`crate::compression`]
+  #[cfg(feature = "compression")]
+  #[serde(skip)]
+  message_zstd: Option<Vec<u8>>,
+  #[cfg(feature = "compression")]
+  #[serde(skip)]
+  dictionary: Option<i32>,
 }
 
 impl<U> Entry<U> {
@@ -55,6 +112,11 @@ impl<U> Entry<U> {
       chatter,
       sent_at,
       message,
+      redacted_at: None,
+      #[cfg(feature = "compression")]
+      message_zstd: None,
+      #[cfg(feature = "compression")]
+      dictionary: None,
     }
   }
 
@@ -87,152 +149,302 @@ impl<U> Entry<U> {
   pub fn message(&self) -> &str {
     &self.message
   }
+
+  #[inline]
+  pub fn redacted_at(&self) -> Option<&DateTime<Utc>> {
+    self.redacted_at.as_ref()
+  }
+
+  #[inline]
+  pub fn is_redacted(&self) -> bool {
+    self.redacted_at.is_some()
+  }
+}
+
+impl<U> Keyed for Entry<U> {
+  fn cursor(&self) -> Cursor {
+    Cursor::new(self.id, self.sent_at)
+  }
+}
+
+#[cfg(feature = "compression")]
+impl<U> crate::compression::CompressedMessage for Entry<U> {
+  fn compressed(&self) -> Option<(i32, &[u8])> {
+    self.dictionary.zip(self.message_zstd.as_deref())
+  }
+
+  fn set_message(&mut self, message: String) {
+    self.message = message;
+  }
 }
 
 /// Insert a single log entry
 pub async fn insert_one(executor: impl sqlx::PgExecutor<'_> + Copy, entry: &Entry<i32>) -> Result<()> {
-  let _ = sqlx::query(
-    "
+  let _ = crate::metrics::instrument(
+    "insert_one",
+    sqlx::query(
+      "
     INSERT INTO twitch_logs (channel, chatter, sent_at, message)
     VALUES ($1, $2, $3, $4)
     ",
+    )
+    .bind(entry.channel)
+    .bind(entry.chatter)
+    .bind(entry.sent_at)
+    .bind(&entry.message)
+    .execute(executor),
   )
-  .bind(entry.channel)
-  .bind(entry.chatter)
-  .bind(entry.sent_at)
-  .bind(&entry.message)
-  .execute(executor)
   .await?;
   Ok(())
 }
 
-/// Insert log entries in batch mode (efficient for large inserts)
+/// Number of rows per channel and UTC day
+pub type DailyCounts = BTreeMap<(i32, NaiveDate), i64>;
+
+/// Rows inserted into a channel on a UTC day, as returned by an insert
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InsertedDay {
+  pub count: i64,
+  /// Smallest ID of the inserted rows
+  pub first_id: i64,
+  /// Largest ID of the inserted rows
+  pub last_id: i64,
+}
+
+/// Rows inserted per channel and UTC day
+pub type DailyInserts = BTreeMap<(i32, NaiveDate), InsertedDay>;
+
+/// Collects the `(channel, day, count, first_id, last_id)` rows returned by an insert
+pub(crate) fn daily_inserts(rows: Vec<(i32, NaiveDate, i64, i64, i64)>) -> DailyInserts {
+  rows
+    .into_iter()
+    .map(|(channel, day, count, first_id, last_id)| {
+      let day_inserts = InsertedDay {
+        count,
+        first_id,
+        last_id,
+      };
+      ((channel, day), day_inserts)
+    })
+    .collect()
+}
+
+/// Insert log entries in batch mode (efficient for large inserts),
+/// and send a [`crate::notify::NewLogs`] notification for each channel.
+/// Returns the number of inserted rows and their IDs per channel and day.
 ///
 /// `entries` will be cleared
-pub async fn insert_soa(executor: impl sqlx::PgExecutor<'_> + Copy, entry: &mut SOAEntry) -> Result<()> {
+pub async fn insert_soa(executor: impl sqlx::PgExecutor<'_> + Copy, entry: &mut SOAEntry) -> Result<DailyInserts> {
   // Bulk insert the chatters
   users::create_bulk(executor, &entry.chatter).await?;
+  insert_joined(executor, entry).await
+}
 
-  // Then complete the insert into logs by joining chatters with twitch_user
-  sqlx::query(
-    "
+/// Completes [`insert_soa`] once the chatters exist, by joining them with twitch_user
+async fn insert_joined(executor: impl sqlx::PgExecutor<'_>, entry: &mut SOAEntry) -> Result<DailyInserts> {
+  let rows = crate::metrics::instrument(
+    "insert_soa",
+    sqlx::query_as::<_, (i32, NaiveDate, i64, i64, i64)>(
+      "
     WITH raw_logs AS (
       SELECT * 
-      FROM UNNEST($1, $2, $3, $4) 
-      soa_entry(channel, chatter, sent_at, message)
-    ) 
-    INSERT INTO twitch_logs (channel, chatter, sent_at, message)
-    SELECT * FROM (
-      SELECT rl.channel, tw.id chatter, rl.sent_at, rl.message
-      FROM raw_logs rl
-      JOIN twitch_user tw ON tw.username = rl.chatter
-    ) as joined;
+      FROM UNNEST($1, $2, $3, $4, $6, $7) 
+      soa_entry(channel, chatter, sent_at, message, language, seq)
+    ), inserted AS (
+      INSERT INTO twitch_logs (channel, chatter, sent_at, message, language, seq)
+      SELECT * FROM (
+        SELECT rl.channel, tw.id chatter, rl.sent_at, rl.message, rl.language, rl.seq
+        FROM raw_logs rl
+        JOIN twitch_user tw ON tw.username = rl.chatter
+      ) as joined
+      RETURNING channel, id, sent_at
+    ), notified AS (
+      SELECT inserted.channel, pg_notify($5, json_build_object(
+        'channel', tw.username,
+        'channel_id', inserted.channel,
+        'count', COUNT(*),
+        'first_id', MIN(inserted.id),
+        'last_id', MAX(inserted.id)
+      )::text)
+      FROM inserted
+      JOIN twitch_user tw ON tw.id = inserted.channel
+      GROUP BY inserted.channel, tw.username
+    )
+    -- Joined with `notified`, as a CTE which isn't referenced isn't evaluated
+    SELECT inserted.channel, (inserted.sent_at AT TIME ZONE 'UTC')::date AS day, COUNT(*), MIN(inserted.id),
+      MAX(inserted.id)
+    FROM inserted
+    JOIN notified ON notified.channel = inserted.channel
+    GROUP BY inserted.channel, day;
     ",
+    )
+    .bind(&entry.channel)
+    .bind(&entry.chatter)
+    .bind(&entry.sent_at)
+    .bind(&entry.message)
+    .bind(crate::notify::CHANNEL)
+    .bind(&entry.language)
+    .bind(&entry.seq)
+    .fetch_all(executor),
   )
-  .bind(&entry.channel)
-  .bind(&entry.chatter)
-  .bind(&entry.sent_at)
-  .bind(&entry.message)
-  .execute(executor)
   .await?;
 
   entry.clear();
 
-  Ok(())
+  Ok(daily_inserts(rows))
 }
 
-macro_rules! get_paged_query {
-  (
-    $query:ident,
-    usernames: $return_usernames:tt,
-    $channel:expr,
-    $chatter:expr,
-    $pattern:expr,
-    $limit:expr,
-    $cursor:expr,
-  ) => {{
-    macro_rules! inc {
-      ($n:ident) => {{
-        $n += 1;
-        $n - 1
-      }};
-    }
-    let chatter = $chatter;
-    let channel = $channel;
-    let pattern = $pattern;
-    let limit = $limit;
-    let cursor = $cursor;
-
-    let mut n = 1;
-    $query = if $return_usernames {
-      "SELECT logs.id, tw.username channel, tw2.username chatter, sent_at, message 
-       FROM twitch_logs logs\n"
-    } else {
-      "SELECT * FROM twitch_logs logs\n"
-    }
-    .to_owned();
+/// Same as [`insert_soa`], but chatters are resolved through `resolver`,
+/// so only the ones which aren't cached are looked up in the database.
+///
+/// `entries` will be cleared
+pub async fn insert_soa_with_resolver(
+  executor: impl sqlx::PgExecutor<'_> + Copy,
+  resolver: &mut UserResolver,
+  entry: &mut SOAEntry,
+) -> Result<DailyInserts> {
+  let chatters = resolver.resolve_many(executor, &entry.chatter).await?;
+  insert_resolved(executor, &chatters, entry).await
+}
 
-    if $return_usernames {
-      $query.push_str(
-        "JOIN twitch_user tw ON tw.id = logs.channel\n
-       JOIN twitch_user tw2 ON tw2.id = logs.chatter\n",
-      );
-    }
+/// Completes [`insert_soa_with_resolver`] once the chatters are resolved into `chatters`
+async fn insert_resolved(
+  executor: impl sqlx::PgExecutor<'_>,
+  chatters: &[i32],
+  entry: &mut SOAEntry,
+) -> Result<DailyInserts> {
+  let rows = crate::metrics::instrument(
+    "insert_soa_with_resolver",
+    sqlx::query_as::<_, (i32, NaiveDate, i64, i64, i64)>(
+      "
+    WITH inserted AS (
+      INSERT INTO twitch_logs (channel, chatter, sent_at, message, language, seq)
+        SELECT * FROM UNNEST($1, $2, $3, $4, $6, $7)
+      RETURNING channel, id, sent_at
+    ), notified AS (
+      SELECT inserted.channel, pg_notify($5, json_build_object(
+        'channel', tw.username,
+        'channel_id', inserted.channel,
+        'count', COUNT(*),
+        'first_id', MIN(inserted.id),
+        'last_id', MAX(inserted.id)
+      )::text)
+      FROM inserted
+      JOIN twitch_user tw ON tw.id = inserted.channel
+      GROUP BY inserted.channel, tw.username
+    )
+    -- Joined with `notified`, as a CTE which isn't referenced isn't evaluated
+    SELECT inserted.channel, (inserted.sent_at AT TIME ZONE 'UTC')::date AS day, COUNT(*), MIN(inserted.id),
+      MAX(inserted.id)
+    FROM inserted
+    JOIN notified ON notified.channel = inserted.channel
+    GROUP BY inserted.channel, day;
+    ",
+    )
+    .bind(&entry.channel)
+    .bind(chatters)
+    .bind(&entry.sent_at)
+    .bind(&entry.message)
+    .bind(crate::notify::CHANNEL)
+    .bind(&entry.language)
+    .bind(&entry.seq)
+    .fetch_all(executor),
+  )
+  .await?;
 
-    $query.push_str(&format!(
-      "WHERE logs.channel = ({})\n",
-      crate::get_channel_id_sql!(inc!(n))
-    ));
+  entry.clear();
 
-    if chatter.is_some() {
-      $query += &format!("AND logs.chatter = ({})\n", crate::get_channel_id_sql!(inc!(n)));
-    }
-    if pattern.is_some() {
-      $query += &format!("AND logs.message LIKE ${}\n", inc!(n));
-    }
+  Ok(daily_inserts(rows))
+}
 
-    $query += &format!("AND (sent_at, logs.id) < (${}, ${})\n", inc!(n), inc!(n));
+/// How [`insert_batch`] turns chatter names into user IDs
+pub enum InsertMode<'a> {
+  /// Chatters are created in bulk and joined with `twitch_user` by the insert, see [`insert_soa`]
+  Joined,
+  /// Chatters are resolved through a cache, see [`insert_soa_with_resolver`]
+  Resolved { resolver: &'a mut UserResolver },
+  /// Chatters are resolved through a cache, and messages are compressed,
+  /// see [`crate::compression::insert_soa_with_resolver`]
+  #[cfg(feature = "compression")]
+  Compressed {
+    resolver: &'a mut UserResolver,
+    compressor: &'a mut crate::compression::Compressor,
+  },
+}
 
-    $query += &format!("ORDER BY sent_at DESC, logs.id DESC LIMIT ${}", inc!(n));
+/// Result of [`insert_batch`]
+#[derive(Debug, Clone)]
+pub struct InsertStats {
+  /// Number of messages in the batch
+  pub rows: usize,
+  /// Rows which were actually inserted, per channel and day
+  pub inserted: DailyInserts,
+  pub duration: std::time::Duration,
+}
 
-    let mut query = sqlx::query_as::<_, Entry<_>>(&$query);
+/// Inserts the messages in `entry` using `mode`, and sends a [`crate::notify::NewLogs`] notification for each channel
+///
+/// `entry` will be cleared
+pub async fn insert_batch(
+  executor: impl sqlx::PgExecutor<'_> + Copy,
+  mode: InsertMode<'_>,
+  entry: &mut SOAEntry,
+) -> Result<InsertStats> {
+  insert_batch_with(executor, executor, mode, entry).await
+}
 
-    query = query.bind(channel);
-    if let Some(chatter) = chatter {
-      query = query.bind(chatter);
+/// Same as [`insert_batch`], but the rows are inserted with `executor`, e.g. a transaction which also records where
+/// they came from.
+///
+/// The chatters are created, and the compression dictionaries trained, with `lookups`, so that they're kept even if
+/// the transaction is rolled back, as they're cached by the resolver and the compressor.
+pub async fn insert_batch_with(
+  lookups: impl sqlx::PgExecutor<'_> + Copy,
+  executor: impl sqlx::PgExecutor<'_>,
+  mode: InsertMode<'_>,
+  entry: &mut SOAEntry,
+) -> Result<InsertStats> {
+  let start = std::time::Instant::now();
+  let rows = entry.len();
+  let inserted = match mode {
+    InsertMode::Joined => {
+      users::create_bulk(lookups, &entry.chatter).await?;
+      insert_joined(executor, entry).await?
     }
-    if let Some(pattern) = pattern {
-      query = query.bind(format!("%{pattern}%"));
+    InsertMode::Resolved { resolver } => {
+      let chatters = resolver.resolve_many(lookups, &entry.chatter).await?;
+      insert_resolved(executor, &chatters, entry).await?
     }
-
-    let (prev_id, prev_sent) = cursor.unwrap_or_else(|| (i64::MAX, chrono::offset::Utc::now()));
-    query = query.bind(prev_sent);
-    query = query.bind(prev_id);
-    query = query.bind(limit);
-
-    query
-  }};
+    #[cfg(feature = "compression")]
+    InsertMode::Compressed { resolver, compressor } => {
+      let compressed = crate::compression::prepare(lookups, resolver, compressor, entry).await?;
+      crate::compression::insert_prepared(executor, compressed, entry).await?
+    }
+  };
+  Ok(InsertStats {
+    rows,
+    inserted,
+    duration: start.elapsed(),
+  })
 }
 
+/// Same as [`fetch_logs_paged`], but resolves `channel` and `chatter` into usernames
 pub async fn fetch_logs_paged_with_usernames<S: Into<String>>(
   executor: impl sqlx::PgExecutor<'_> + Copy,
   channel: S,
   chatter: Option<S>,
   pattern: Option<S>,
   limit: i32,
-  cursor: Option<(i64, DateTime<Utc>)>,
+  cursor: Option<Cursor>,
+  include_redacted: bool,
 ) -> Result<Vec<Entry<String>>> {
-  let mut query;
-  let query = get_paged_query!(
-    query,
-    usernames: true,
-    channel.into(),
-    chatter.map(|v| v.into()),
-    pattern.map(|v| v.into()),
-    limit,
-    cursor,
-  );
-  query.fetch_all(executor).await
+  crate::metrics::instrument(
+    "fetch_logs_paged_with_usernames",
+    paged_query(channel, chatter, pattern, limit, cursor, include_redacted)
+      .with_usernames()
+      .fetch_all(executor),
+  )
+  .await
 }
 
 /// Retrieve logs into a `Vec`
@@ -242,23 +454,171 @@ pub async fn fetch_logs_paged_with_usernames<S: Into<String>>(
 /// * pattern - uses `LIKE` for matching, e.g. `%yo%`
 ///   * `%` multi-character wildcard
 ///   * `_` single-character wildcard
+/// * include_redacted - also return redacted messages (admin only)
 pub async fn fetch_logs_paged<S: Into<String>>(
-  executor: impl sqlx::PgExecutor<'_>,
+  executor: impl sqlx::PgExecutor<'_> + Copy,
   channel: S,
   chatter: Option<S>,
   pattern: Option<S>,
   limit: i32,
-  cursor: Option<(i64, DateTime<Utc>)>,
+  cursor: Option<Cursor>,
+  include_redacted: bool,
 ) -> Result<Vec<Entry<i32>>> {
-  let mut query;
-  let query = get_paged_query!(
-    query,
-    usernames: false,
-    channel.into(),
-    chatter.map(|v| v.into()),
-    pattern.map(|v| v.into()),
-    limit,
-    cursor,
+  crate::metrics::instrument(
+    "fetch_logs_paged",
+    paged_query(channel, chatter, pattern, limit, cursor, include_redacted).fetch_all(executor),
+  )
+  .await
+}
+
+fn paged_query<S: Into<String>>(
+  channel: S,
+  chatter: Option<S>,
+  pattern: Option<S>,
+  limit: i32,
+  cursor: Option<Cursor>,
+  include_redacted: bool,
+) -> LogsQuery {
+  let mut query = LogsQuery::new().channel(channel);
+  if let Some(chatter) = chatter {
+    query = query.chatter(chatter);
+  }
+  if let Some(pattern) = pattern {
+    query = query.pattern(pattern);
+  }
+  query.include_redacted(include_redacted).cursor(cursor).limit(limit)
+}
+
+/// Retrieve the logs of `chatter` in every channel into a `Vec`, newest first
+///
+/// * chatter - exact
+/// * since - only messages sent at or after this time
+/// * until - only messages sent before this time
+/// * include_redacted - also return redacted messages
+pub async fn fetch_chatter_logs_paged(
+  executor: impl sqlx::PgExecutor<'_> + Copy,
+  chatter: &str,
+  since: Option<DateTime<Utc>>,
+  until: Option<DateTime<Utc>>,
+  limit: i32,
+  cursor: Option<Cursor>,
+  include_redacted: bool,
+) -> Result<Vec<Entry<String>>> {
+  let mut query = LogsQuery::new().with_usernames().chatter(chatter);
+  if let Some(since) = since {
+    query = query.since(since);
+  }
+  if let Some(until) = until {
+    query = query.until(until);
+  }
+  crate::metrics::instrument(
+    "fetch_chatter_logs_paged",
+    query
+      .include_redacted(include_redacted)
+      .cursor(cursor)
+      .limit(limit)
+      .fetch_all(executor),
+  )
+  .await
+}
+
+/// Redact log entries by id
+///
+/// Redacted entries are kept in the database, but they're excluded from all fetches
+/// unless explicitly requested. Returns the number of newly redacted entries.
+pub async fn redact_by_id(executor: impl sqlx::PgExecutor<'_>, ids: &[i64], redacted_by: i32) -> Result<u64> {
+  crate::metrics::instrument(
+    "redact_by_id",
+    sqlx::query(
+      "
+    UPDATE twitch_logs
+      SET redacted_at = NOW(), redacted_by = $2
+      WHERE id IN (SELECT * FROM UNNEST($1))
+      AND redacted_at IS NULL
+    ",
+    )
+    .bind(ids)
+    .bind(redacted_by)
+    .execute(executor),
+  )
+  .await
+  .map(|r| r.rows_affected())
+}
+
+/// Redact all log entries in `channel` which match `pattern`
+///
+/// `pattern` is matched the same way as in [`fetch_logs_paged`], so that a redaction
+/// affects exactly the messages returned by a search with the same filters, compressed ones included.
+/// Returns the number of newly redacted entries.
+pub async fn redact_by_pattern<S: Into<String>>(
+  executor: impl sqlx::PgExecutor<'_> + Copy,
+  channel: S,
+  chatter: Option<S>,
+  pattern: S,
+  redacted_by: i32,
+) -> Result<u64> {
+  let channel = channel.into();
+  let chatter = chatter.map(Into::into);
+  let pattern = pattern.into();
+  let mut query = format!(
+    "
+    UPDATE twitch_logs logs
+      SET redacted_at = NOW(), redacted_by = $1
+      WHERE logs.channel = ({})
+      AND logs.message LIKE $3
+      AND logs.redacted_at IS NULL
+    ",
+    crate::get_channel_id_sql!(2)
   );
-  query.fetch_all(executor).await
+  if chatter.is_some() {
+    query += &format!("AND logs.chatter = ({})", crate::get_channel_id_sql!(4));
+  }
+
+  let mut query = sqlx::query(&query)
+    .bind(redacted_by)
+    .bind(&channel)
+    .bind(format!("%{pattern}%"));
+  if let Some(chatter) = &chatter {
+    query = query.bind(chatter);
+  }
+  let redacted = crate::metrics::instrument("redact_by_pattern", query.execute(executor))
+    .await
+    .map(|r| r.rows_affected())?;
+
+  #[cfg(feature = "compression")]
+  let redacted = redacted + redact_compressed_by_pattern(executor, channel, chatter, pattern, redacted_by).await?;
+  Ok(redacted)
+}
+
+/// Number of compressed messages matched at once by [`redact_compressed_by_pattern`]
+#[cfg(feature = "compression")]
+const REDACT_BATCH_SIZE: i32 = 1000;
+
+/// Redacts the compressed messages which match `pattern`, which the `LIKE` of [`redact_by_pattern`] can't see
+#[cfg(feature = "compression")]
+async fn redact_compressed_by_pattern(
+  executor: impl sqlx::PgExecutor<'_> + Copy,
+  channel: String,
+  chatter: Option<String>,
+  pattern: String,
+  redacted_by: i32,
+) -> Result<u64> {
+  use crate::compression::CompressedMessage;
+
+  // The uncompressed matches were already redacted, so the search only returns compressed ones
+  let mut query = paged_query(channel, chatter, Some(pattern), REDACT_BATCH_SIZE, None, false);
+  let mut redacted = 0;
+  loop {
+    let page = query.fetch_all::<i32>(executor).await?;
+    let ids = page
+      .iter()
+      .filter(|entry| entry.compressed().is_some())
+      .map(|entry| entry.id)
+      .collect::<Vec<_>>();
+    redacted += redact_by_id(executor, &ids, redacted_by).await?;
+    if page.len() < REDACT_BATCH_SIZE as usize {
+      return Ok(redacted);
+    }
+    query = query.cursor(page.last().map(Keyed::cursor));
+  }
 }
@@ -0,0 +1,160 @@
+//! Optional per-query instrumentation, rendered in the Prometheus text format.
+//!
+//! Queries are labeled by a logical name, usually the function running them (e.g. `fetch_logs_paged`).
+//! Nothing is recorded until [`enable`] is called, so services which don't serve the metrics don't pay for them.
+
+use std::{
+  collections::BTreeMap,
+  fmt::Write as _,
+  future::Future,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex,
+  },
+  time::{Duration, Instant},
+};
+
+use crate::Result;
+
+/// Upper bounds of the duration histogram buckets, in seconds
+const BUCKETS: [f64; 12] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static REGISTRY: Registry = Registry::new();
+
+#[derive(Debug, Default, Clone)]
+struct QueryStats {
+  count: u64,
+  errors: u64,
+  /// Number of queries in each of `BUCKETS`, not cumulative
+  buckets: [u64; BUCKETS.len()],
+  total: Duration,
+}
+
+/// Stats of every instrumented query
+pub struct Registry {
+  queries: Mutex<BTreeMap<&'static str, QueryStats>>,
+}
+
+impl Registry {
+  pub const fn new() -> Self {
+    Self {
+      queries: Mutex::new(BTreeMap::new()),
+    }
+  }
+
+  /// Records that `query` took `elapsed`, and whether it failed
+  pub fn record(&self, query: &'static str, elapsed: Duration, failed: bool) {
+    let mut queries = self.queries.lock().unwrap();
+    let stats = queries.entry(query).or_default();
+    stats.count += 1;
+    stats.errors += u64::from(failed);
+    stats.total += elapsed;
+    if let Some(bucket) = BUCKETS.iter().position(|&le| elapsed.as_secs_f64() <= le) {
+      stats.buckets[bucket] += 1;
+    }
+  }
+
+  /// Renders the stats in the Prometheus text format
+  pub fn render(&self) -> String {
+    let queries = self.queries.lock().unwrap().clone();
+    let mut output = String::new();
+    writeln!(output, "# TYPE scs_db_queries_total counter").unwrap();
+    for (query, stats) in &queries {
+      writeln!(output, "scs_db_queries_total{{query=\"{query}\"}} {}", stats.count).unwrap();
+    }
+    writeln!(output, "# TYPE scs_db_query_errors_total counter").unwrap();
+    for (query, stats) in &queries {
+      writeln!(
+        output,
+        "scs_db_query_errors_total{{query=\"{query}\"}} {}",
+        stats.errors
+      )
+      .unwrap();
+    }
+    writeln!(output, "# TYPE scs_db_query_duration_seconds histogram").unwrap();
+    for (query, stats) in &queries {
+      let mut cumulative = 0;
+      for (le, count) in BUCKETS.iter().zip(stats.buckets) {
+        cumulative += count;
+        writeln!(
+          output,
+          "scs_db_query_duration_seconds_bucket{{query=\"{query}\",le=\"{le}\"}} {cumulative}"
+        )
+        .unwrap();
+      }
+      writeln!(
+        output,
+        "scs_db_query_duration_seconds_bucket{{query=\"{query}\",le=\"+Inf\"}} {}",
+        stats.count
+      )
+      .unwrap();
+      writeln!(
+        output,
+        "scs_db_query_duration_seconds_sum{{query=\"{query}\"}} {}",
+        stats.total.as_secs_f64()
+      )
+      .unwrap();
+      writeln!(
+        output,
+        "scs_db_query_duration_seconds_count{{query=\"{query}\"}} {}",
+        stats.count
+      )
+      .unwrap();
+    }
+    output
+  }
+}
+
+impl Default for Registry {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+/// Starts recording the queries run by this process
+pub fn enable() {
+  ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Renders the stats of the queries run since [`enable`] was called
+pub fn render() -> String {
+  REGISTRY.render()
+}
+
+/// Runs `future`, recording its duration and whether it failed under `query` if the metrics are enabled
+pub async fn instrument<T>(query: &'static str, future: impl Future<Output = Result<T>>) -> Result<T> {
+  if !ENABLED.load(Ordering::Relaxed) {
+    return future.await;
+  }
+  let start = Instant::now();
+  let result = future.await;
+  REGISTRY.record(query, start.elapsed(), result.is_err());
+  result
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn renders_cumulative_buckets() {
+    let registry = Registry::new();
+    registry.record("fetch_logs_paged", Duration::from_millis(3), false);
+    registry.record("fetch_logs_paged", Duration::from_millis(30), true);
+    registry.record("fetch_logs_paged", Duration::from_secs(60), false);
+    registry.record("insert_soa", Duration::from_micros(10), false);
+
+    let rendered = registry.render();
+    let lines = rendered.lines().collect::<Vec<_>>();
+    assert!(lines.contains(&"scs_db_queries_total{query=\"fetch_logs_paged\"} 3"));
+    assert!(lines.contains(&"scs_db_query_errors_total{query=\"fetch_logs_paged\"} 1"));
+    assert!(lines.contains(&"scs_db_query_errors_total{query=\"insert_soa\"} 0"));
+    assert!(lines.contains(&"scs_db_query_duration_seconds_bucket{query=\"fetch_logs_paged\",le=\"0.001\"} 0"));
+    assert!(lines.contains(&"scs_db_query_duration_seconds_bucket{query=\"fetch_logs_paged\",le=\"0.005\"} 1"));
+    assert!(lines.contains(&"scs_db_query_duration_seconds_bucket{query=\"fetch_logs_paged\",le=\"0.05\"} 2"));
+    assert!(lines.contains(&"scs_db_query_duration_seconds_bucket{query=\"fetch_logs_paged\",le=\"10\"} 2"));
+    assert!(lines.contains(&"scs_db_query_duration_seconds_bucket{query=\"fetch_logs_paged\",le=\"+Inf\"} 3"));
+    assert!(lines.contains(&"scs_db_query_duration_seconds_count{query=\"insert_soa\"} 1"));
+  }
+}
@@ -0,0 +1,59 @@
+//! Notifications of newly inserted logs, sent on the `scs_new_logs` Postgres channel.
+//!
+//! Every batch insert into `twitch_logs` sends one notification per channel in the batch, when its transaction commits.
+//! Consumers which only need to know that new messages arrived can subscribe with [`NewLogsListener`] instead of
+//! polling the table.
+
+use super::{Database, Result};
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgListener;
+
+/// Name of the Postgres notification channel
+pub const CHANNEL: &str = "scs_new_logs";
+
+/// Payload of a notification, as JSON
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NewLogs {
+  /// Username of the channel
+  pub channel: String,
+  pub channel_id: i32,
+  /// Number of rows inserted
+  pub count: i64,
+  /// IDs of the first and last inserted rows. Other inserts may interleave, so not every ID in between belongs to
+  /// this batch.
+  pub first_id: i64,
+  pub last_id: i64,
+}
+
+/// A dedicated connection which listens for [`NewLogs`] notifications.
+///
+/// If the connection is lost, it's re-established on the next receive, and notifications sent in the meantime are missed.
+pub struct NewLogsListener {
+  listener: PgListener,
+}
+
+impl NewLogsListener {
+  pub async fn connect(pool: &Database) -> Result<Self> {
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener.listen(CHANNEL).await?;
+    Ok(Self { listener })
+  }
+
+  /// Waits for the next notification
+  pub async fn recv(&mut self) -> Result<NewLogs> {
+    let notification = self.listener.recv().await?;
+    parse(notification.payload())
+  }
+
+  pub fn into_stream(self) -> impl Stream<Item = Result<NewLogs>> + Unpin {
+    self
+      .listener
+      .into_stream()
+      .map(|notification| notification.and_then(|n| parse(n.payload())))
+  }
+}
+
+fn parse(payload: &str) -> Result<NewLogs> {
+  serde_json::from_str(payload).map_err(|e| sqlx::Error::Decode(Box::new(e)))
+}
@@ -0,0 +1,146 @@
+//! Keyset pagination.
+//!
+//! Rows are ordered by a timestamp, with their ID as a tie-breaker, and a [`Cursor`] holds the key of the last row
+//! of a page. The next page starts right after it, so rows inserted in the meantime don't shift the pages.
+//! Cursors are exchanged with clients as URL-safe base64 of `id,timestamp`, where the timestamp is RFC 3339.
+
+use base64::{engine::general_purpose, Engine as _};
+use chrono::{DateTime, Utc};
+
+/// Key of the last row of a page
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cursor {
+  pub id: i64,
+  pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+pub enum CursorError {
+  Base64(base64::DecodeError),
+  Utf8(std::string::FromUtf8Error),
+  /// The decoded cursor isn't `id,timestamp`
+  Format,
+  Id(std::num::ParseIntError),
+  Timestamp(chrono::ParseError),
+}
+
+impl std::fmt::Display for CursorError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      CursorError::Base64(e) => write!(f, "Invalid cursor: {}", e),
+      CursorError::Utf8(e) => write!(f, "Cursor is not a valid utf-8 string: {}", e),
+      CursorError::Format => write!(f, "Cursor string is not correctly formatted"),
+      CursorError::Id(e) => write!(f, "ID cursor is not a valid number: {}", e),
+      CursorError::Timestamp(e) => write!(f, "Timestamp cursor is not a valid RFC3339 date string: {}", e),
+    }
+  }
+}
+
+impl std::error::Error for CursorError {}
+
+impl Cursor {
+  pub fn new(id: i64, timestamp: DateTime<Utc>) -> Self {
+    Self { id, timestamp }
+  }
+
+  pub fn encode(&self) -> String {
+    general_purpose::URL_SAFE.encode(format!("{},{}", self.id, self.timestamp.to_rfc3339()))
+  }
+
+  pub fn decode(cursor: &str) -> Result<Self, CursorError> {
+    let bytes = general_purpose::URL_SAFE.decode(cursor).map_err(CursorError::Base64)?;
+    let decoded = String::from_utf8(bytes).map_err(CursorError::Utf8)?;
+    let (id, timestamp) = decoded.split_once(',').ok_or(CursorError::Format)?;
+    Ok(Self {
+      id: id.parse().map_err(CursorError::Id)?,
+      timestamp: DateTime::parse_from_rfc3339(timestamp)
+        .map_err(CursorError::Timestamp)?
+        .with_timezone(&Utc),
+    })
+  }
+
+  /// Decodes the cursor of a request, where a missing or empty cursor means the first page
+  pub fn parse(cursor: Option<&str>) -> Result<Option<Self>, CursorError> {
+    match cursor {
+      Some(cursor) if !cursor.is_empty() => Self::decode(cursor).map(Some),
+      _ => Ok(None),
+    }
+  }
+}
+
+/// A row which can be paginated over
+pub trait Keyed {
+  fn cursor(&self) -> Cursor;
+}
+
+/// Returns the encoded cursor of the page after `rows`, or `None` if it's empty
+pub fn next_cursor<T: Keyed>(rows: &[T]) -> Option<String> {
+  rows.last().map(|row| row.cursor().encode())
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Order {
+  #[default]
+  NewestFirst,
+  OldestFirst,
+}
+
+impl Order {
+  /// `ORDER BY` clause over the `timestamp` and `id` columns
+  pub fn order_by(self, timestamp: &str, id: &str) -> String {
+    let direction = match self {
+      Order::NewestFirst => "DESC",
+      Order::OldestFirst => "ASC",
+    };
+    format!("ORDER BY {timestamp} {direction}, {id} {direction}")
+  }
+
+  /// Condition which only matches the rows after the cursor in this order,
+  /// where the cursor's timestamp and ID are bound to `timestamp_param` and `id_param`
+  pub fn after(self, timestamp: &str, id: &str, timestamp_param: &str, id_param: &str) -> String {
+    let comparison = match self {
+      Order::NewestFirst => "<",
+      Order::OldestFirst => ">",
+    };
+    format!("({timestamp}, {id}) {comparison} ({timestamp_param}, {id_param})")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use chrono::TimeZone;
+
+  #[test]
+  fn round_trip() {
+    let cursor = Cursor::new(42, Utc.with_ymd_and_hms(2023, 1, 1, 12, 30, 0).unwrap());
+    let encoded = cursor.encode();
+    assert_eq!(
+      String::from_utf8(general_purpose::URL_SAFE.decode(&encoded).unwrap()).unwrap(),
+      "42,2023-01-01T12:30:00+00:00"
+    );
+    assert_eq!(Cursor::decode(&encoded).unwrap(), cursor);
+    assert_eq!(Cursor::parse(Some(&encoded)).unwrap(), Some(cursor));
+  }
+
+  #[test]
+  fn missing_or_empty_cursor_is_the_first_page() {
+    assert_eq!(Cursor::parse(None).unwrap(), None);
+    assert_eq!(Cursor::parse(Some("")).unwrap(), None);
+  }
+
+  #[test]
+  fn invalid_cursors() {
+    let encode = |s: &str| general_purpose::URL_SAFE.encode(s);
+    assert!(matches!(Cursor::decode("not base64!"), Err(CursorError::Base64(_))));
+    assert!(matches!(Cursor::decode(&encode("42")), Err(CursorError::Format)));
+    assert!(matches!(
+      Cursor::decode(&encode("x,2023-01-01T00:00:00Z")),
+      Err(CursorError::Id(_))
+    ));
+    assert!(matches!(
+      Cursor::decode(&encode("42,yesterday")),
+      Err(CursorError::Timestamp(_))
+    ));
+  }
+}
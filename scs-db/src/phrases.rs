@@ -0,0 +1,170 @@
+//! How often a phrase occurs in the logs, to check whether a model's output comes from its training data
+
+use super::Result;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+
+/// Shorter phrases can't be looked up in the trigram index on `message`, and would scan every message in the range
+pub const MIN_PHRASE_LENGTH: usize = 3;
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, getset::Getters, getset::CopyGetters)]
+pub struct PhraseCount {
+  #[getset(get = "pub")]
+  channel: String,
+  /// First day of the month, in UTC
+  #[getset(get_copy = "pub")]
+  month: NaiveDate,
+  /// Messages which contain the phrase as it is
+  #[getset(get_copy = "pub")]
+  exact: i64,
+  /// Messages which contain the phrase ignoring case, including the exact matches
+  #[getset(get_copy = "pub")]
+  case_insensitive: i64,
+}
+
+/// Counts the messages sent in `[since, until)` which contain `phrase`, per channel and month, ordered by both.
+/// Optionally only counts the messages of `channel` and `chatter`.
+///
+/// Both counts use the trigram index on `message`, as long as `phrase` has at least [`MIN_PHRASE_LENGTH`] characters.
+/// Redacted messages are excluded. Compressed messages can't be searched in SQL, so with compression, the ones in the
+/// range are decompressed and counted separately, which scans all of them.
+pub async fn count_by_month(
+  executor: impl sqlx::PgExecutor<'_> + Copy,
+  phrase: &str,
+  channel: Option<&str>,
+  chatter: Option<&str>,
+  since: DateTime<Utc>,
+  until: DateTime<Utc>,
+) -> Result<Vec<PhraseCount>> {
+  let counts = sqlx::query_as::<_, PhraseCount>(
+    r"
+    SELECT tw.username channel,
+        date_trunc('month', logs.sent_at AT TIME ZONE 'UTC')::DATE month,
+        COUNT(*) FILTER (WHERE logs.message LIKE '%' || $1 || '%') exact,
+        COUNT(*) case_insensitive
+      FROM twitch_logs logs
+      JOIN twitch_user tw ON tw.id = logs.channel
+      WHERE logs.message ILIKE '%' || $1 || '%'
+        AND logs.sent_at >= $2
+        AND logs.sent_at < $3
+        AND logs.redacted_at IS NULL
+        AND ($4::TEXT IS NULL OR logs.channel = (SELECT id FROM twitch_user WHERE username = $4))
+        AND ($5::TEXT IS NULL OR logs.chatter = (SELECT id FROM twitch_user WHERE username = $5))
+      GROUP BY tw.username, month
+      ORDER BY tw.username, month
+    ",
+  )
+  .bind(crate::query::escape_like(phrase))
+  .bind(since)
+  .bind(until)
+  .bind(channel)
+  .bind(chatter)
+  .fetch_all(executor)
+  .await?;
+
+  #[cfg(feature = "compression")]
+  let counts = compressed::count_by_month(executor, counts, phrase, channel, chatter, since, until).await?;
+  Ok(counts)
+}
+
+#[cfg(feature = "compression")]
+mod compressed {
+  use super::{PhraseCount, Result};
+  use crate::compression::{decompress, matches_like, CompressedMessage};
+  use chrono::{DateTime, NaiveDate, Utc};
+  use std::collections::BTreeMap;
+
+  /// Number of compressed messages decompressed at once
+  const BATCH_SIZE: i64 = 10_000;
+
+  #[derive(sqlx::FromRow)]
+  struct Row {
+    id: i64,
+    channel: String,
+    month: NaiveDate,
+    message: String,
+    message_zstd: Vec<u8>,
+    dictionary: i32,
+  }
+
+  impl CompressedMessage for Row {
+    fn compressed(&self) -> Option<(i32, &[u8])> {
+      Some((self.dictionary, &self.message_zstd))
+    }
+
+    fn set_message(&mut self, message: String) {
+      self.message = message;
+    }
+  }
+
+  /// Adds the compressed messages which contain `phrase` to `counts`, keeping them ordered by channel and month
+  pub(super) async fn count_by_month(
+    executor: impl sqlx::PgExecutor<'_> + Copy,
+    counts: Vec<PhraseCount>,
+    phrase: &str,
+    channel: Option<&str>,
+    chatter: Option<&str>,
+    since: DateTime<Utc>,
+    until: DateTime<Utc>,
+  ) -> Result<Vec<PhraseCount>> {
+    let like = format!("%{}%", crate::query::escape_like(phrase));
+    let mut merged = counts
+      .into_iter()
+      .map(|count| ((count.channel.clone(), count.month), count))
+      .collect::<BTreeMap<_, _>>();
+
+    let mut after = 0;
+    loop {
+      let mut rows = sqlx::query_as::<_, Row>(
+        r"
+        SELECT logs.id, tw.username channel,
+            date_trunc('month', logs.sent_at AT TIME ZONE 'UTC')::DATE month,
+            logs.message, logs.message_zstd, logs.dictionary
+          FROM twitch_logs logs
+          JOIN twitch_user tw ON tw.id = logs.channel
+          WHERE logs.message_zstd IS NOT NULL
+            AND logs.sent_at >= $1
+            AND logs.sent_at < $2
+            AND logs.redacted_at IS NULL
+            AND ($3::TEXT IS NULL OR logs.channel = (SELECT id FROM twitch_user WHERE username = $3))
+            AND ($4::TEXT IS NULL OR logs.chatter = (SELECT id FROM twitch_user WHERE username = $4))
+            AND logs.id > $5
+          ORDER BY logs.id
+          LIMIT $6
+        ",
+      )
+      .bind(since)
+      .bind(until)
+      .bind(channel)
+      .bind(chatter)
+      .bind(after)
+      .bind(BATCH_SIZE)
+      .fetch_all(executor)
+      .await?;
+      decompress(executor, &mut rows).await?;
+
+      for row in &rows {
+        if !matches_like(&like, &row.message, true) {
+          continue;
+        }
+        let count = merged
+          .entry((row.channel.clone(), row.month))
+          .or_insert_with(|| PhraseCount {
+            channel: row.channel.clone(),
+            month: row.month,
+            exact: 0,
+            case_insensitive: 0,
+          });
+        count.case_insensitive += 1;
+        if matches_like(&like, &row.message, false) {
+          count.exact += 1;
+        }
+      }
+
+      match rows.last() {
+        Some(last) if rows.len() as i64 == BATCH_SIZE => after = last.id,
+        _ => return Ok(merged.into_values().collect()),
+      }
+    }
+  }
+}
@@ -0,0 +1,130 @@
+//! Deletion of the history of a channel which is no longer logged.
+//!
+//! Logs are deleted in batches which are each committed on their own, walking the `(channel, sent_at, id)` index,
+//! so that purging a large channel neither holds a long transaction nor scans the rows it already deleted.
+
+use super::{Database, Result};
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use serde::Serialize;
+
+/// Progress of a purge, reported after every batch
+#[derive(Debug, Clone, Copy, Default, Serialize, getset::CopyGetters)]
+#[getset(get_copy = "pub")]
+pub struct PurgeProgress {
+  /// Number of logs deleted so far
+  deleted: i64,
+  /// Number of batches committed so far
+  batches: i64,
+  /// Send time of the last deleted log
+  last_sent_at: Option<DateTime<Utc>>,
+}
+
+#[derive(sqlx::FromRow)]
+struct Batch {
+  deleted: i64,
+  last_sent_at: Option<DateTime<Utc>>,
+  last_id: Option<i64>,
+}
+
+/// Deletes the logs of `channel` sent before the start of `before` in UTC, or all of them if it's `None`,
+/// in batches of up to `batch_size` logs. `progress` is called after every batch.
+///
+/// The message count of the channel is updated with every batch. Its first and last message times,
+/// and the chatters who no longer have any logs in the channel, are updated once every batch is deleted.
+pub async fn purge_channel(
+  db: &Database,
+  channel: i32,
+  before: Option<NaiveDate>,
+  batch_size: i64,
+  mut progress: impl FnMut(&PurgeProgress),
+) -> Result<PurgeProgress> {
+  let before = before.map(|date| Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap()));
+  let mut state = PurgeProgress::default();
+  let mut cursor: Option<(DateTime<Utc>, i64)> = None;
+  loop {
+    // The batch starts after the last deleted log, rather than at the start of the index, which still has to skip
+    // the deleted rows until they're vacuumed
+    let batch = crate::metrics::instrument(
+      "purge_channel_batch",
+      sqlx::query_as::<_, Batch>(
+        "
+        WITH batch AS (
+          SELECT id, sent_at FROM twitch_logs
+            WHERE channel = $1
+              AND sent_at < COALESCE($2, 'infinity'::TIMESTAMPTZ)
+              AND (sent_at, id) > (COALESCE($3, '-infinity'::TIMESTAMPTZ), COALESCE($4, 0))
+            ORDER BY sent_at, id
+            LIMIT $5
+        ), deleted_metadata AS (
+          DELETE FROM twitch_logs_metadata WHERE id IN (SELECT id FROM batch)
+        ), deleted AS (
+          DELETE FROM twitch_logs WHERE id IN (SELECT id FROM batch)
+            RETURNING id, sent_at
+        ), stats AS (
+          UPDATE twitch_channel_stats
+            SET message_count = message_count - (SELECT COUNT(*) FROM deleted)
+            WHERE channel = $1
+        )
+        SELECT
+          (SELECT COUNT(*) FROM deleted) deleted,
+          last.sent_at last_sent_at,
+          last.id last_id
+        FROM (SELECT 1) one
+        LEFT JOIN (SELECT sent_at, id FROM deleted ORDER BY sent_at DESC, id DESC LIMIT 1) last ON true
+        ",
+      )
+      .bind(channel)
+      .bind(before)
+      .bind(cursor.map(|(sent_at, _)| sent_at))
+      .bind(cursor.map(|(_, id)| id))
+      .bind(batch_size)
+      .fetch_one(db),
+    )
+    .await?;
+    if batch.deleted == 0 {
+      break;
+    }
+
+    state.deleted += batch.deleted;
+    state.batches += 1;
+    state.last_sent_at = batch.last_sent_at;
+    cursor = batch.last_sent_at.zip(batch.last_id);
+    progress(&state);
+  }
+
+  finish(db, channel).await?;
+  Ok(state)
+}
+
+/// Recomputes the first and last message times of `channel`, and forgets the chatters without logs in it
+async fn finish(db: &Database, channel: i32) -> Result<()> {
+  let mut tx = db.begin().await?;
+  crate::metrics::instrument(
+    "purge_channel_stats",
+    sqlx::query(
+      "
+      UPDATE twitch_channel_stats
+        SET first_message_at = (SELECT MIN(sent_at) FROM twitch_logs WHERE channel = $1),
+            last_message_at = (SELECT MAX(sent_at) FROM twitch_logs WHERE channel = $1)
+        WHERE channel = $1
+      ",
+    )
+    .bind(channel)
+    .execute(&mut *tx),
+  )
+  .await?;
+  crate::metrics::instrument(
+    "purge_channel_chatters",
+    sqlx::query(
+      "
+      DELETE FROM twitch_channel_chatters cc
+        WHERE cc.channel = $1
+          AND NOT EXISTS (SELECT 1 FROM twitch_logs WHERE channel = $1 AND chatter = cc.chatter)
+      ",
+    )
+    .bind(channel)
+    .execute(&mut *tx),
+  )
+  .await?;
+  tx.commit().await
+}
@@ -0,0 +1,385 @@
+//! Builder for queries over `twitch_logs`.
+//!
+//! Filters are collected first, and the SQL and its bind parameters are produced together by [`LogsQuery::render`],
+//! so that placeholders are always numbered in the same order as the values are bound.
+
+use super::Result;
+use crate::{logs::Entry, pagination::Cursor};
+use chrono::{DateTime, Utc};
+use sqlx::postgres::PgRow;
+
+/// A value bound to a query parameter
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Bind {
+  Text(String),
+  Int(i32),
+  BigInt(i64),
+  Timestamp(DateTime<Utc>),
+}
+
+pub use crate::pagination::Order;
+
+/// Escapes the `LIKE` wildcards in `pattern`, so that it's matched literally
+pub(crate) fn escape_like(pattern: &str) -> String {
+  let mut escaped = String::with_capacity(pattern.len());
+  for c in pattern.chars() {
+    if matches!(c, '%' | '_' | '\\') {
+      escaped.push('\\');
+    }
+    escaped.push(c);
+  }
+  escaped
+}
+
+#[derive(Clone, Debug)]
+enum Filter {
+  /// Username of the channel
+  Channel(String),
+  /// Username of the chatter
+  Chatter(String),
+  /// Substring of the message, which may contain `LIKE` wildcards
+  Pattern(String),
+  Since(DateTime<Utc>),
+  Until(DateTime<Utc>),
+}
+
+/// A query over `twitch_logs`, which returns [`Entry`] rows
+#[derive(Clone, Debug, Default)]
+pub struct LogsQuery {
+  usernames: bool,
+  filters: Vec<Filter>,
+  include_redacted: bool,
+  order: Order,
+  /// Last entry of the previous page
+  cursor: Option<Cursor>,
+  limit: Option<i32>,
+}
+
+impl LogsQuery {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Return the usernames of the channel and chatter instead of their IDs
+  pub fn with_usernames(mut self) -> Self {
+    self.usernames = true;
+    self
+  }
+
+  pub fn channel(mut self, channel: impl Into<String>) -> Self {
+    self.filters.push(Filter::Channel(channel.into()));
+    self
+  }
+
+  pub fn chatter(mut self, chatter: impl Into<String>) -> Self {
+    self.filters.push(Filter::Chatter(chatter.into()));
+    self
+  }
+
+  /// Only messages containing `pattern`, where `%` and `_` are wildcards
+  pub fn pattern(mut self, pattern: impl Into<String>) -> Self {
+    self.filters.push(Filter::Pattern(pattern.into()));
+    self
+  }
+
+  /// Only messages sent at or after `since`
+  pub fn since(mut self, since: DateTime<Utc>) -> Self {
+    self.filters.push(Filter::Since(since));
+    self
+  }
+
+  /// Only messages sent before `until`
+  pub fn until(mut self, until: DateTime<Utc>) -> Self {
+    self.filters.push(Filter::Until(until));
+    self
+  }
+
+  /// Also return redacted messages, which are excluded by default
+  pub fn include_redacted(mut self, include_redacted: bool) -> Self {
+    self.include_redacted = include_redacted;
+    self
+  }
+
+  pub fn order(mut self, order: Order) -> Self {
+    self.order = order;
+    self
+  }
+
+  /// Only messages after `cursor` in the query's order
+  pub fn cursor(mut self, cursor: Option<Cursor>) -> Self {
+    self.cursor = cursor;
+    self
+  }
+
+  pub fn limit(mut self, limit: i32) -> Self {
+    self.limit = Some(limit);
+    self
+  }
+
+  /// Produces the SQL, and the values of its parameters in order
+  pub fn render(&self) -> (String, Vec<Bind>) {
+    // Returns the number of the parameter
+    let mut binds = Vec::new();
+    let mut bind = |value: Bind| {
+      binds.push(value);
+      binds.len()
+    };
+
+    let mut sql = if self.usernames {
+      String::from(
+        "SELECT logs.id, tw.username channel, tw2.username chatter, sent_at, message, redacted_at, message_zstd, dictionary\n\
+         FROM twitch_logs logs\n\
+         JOIN twitch_user tw ON tw.id = logs.channel\n\
+         JOIN twitch_user tw2 ON tw2.id = logs.chatter\n",
+      )
+    } else {
+      String::from("SELECT * FROM twitch_logs logs\n")
+    };
+
+    let mut conditions = Vec::with_capacity(self.filters.len() + 1);
+    for filter in &self.filters {
+      conditions.push(match filter {
+        Filter::Channel(channel) => format!(
+          "logs.channel = ({})",
+          crate::get_channel_id_sql!(bind(Bind::Text(channel.clone())))
+        ),
+        Filter::Chatter(chatter) => format!(
+          "logs.chatter = ({})",
+          crate::get_channel_id_sql!(bind(Bind::Text(chatter.clone())))
+        ),
+        #[cfg(not(feature = "compression"))]
+        Filter::Pattern(pattern) => format!("logs.message LIKE ${}", bind(Bind::Text(format!("%{pattern}%")))),
+        // Postgres can't see the text of compressed messages, so they're matched in `fetch_all` once decompressed
+        #[cfg(feature = "compression")]
+        Filter::Pattern(pattern) => format!(
+          "(logs.message LIKE ${} OR logs.message_zstd IS NOT NULL)",
+          bind(Bind::Text(format!("%{pattern}%")))
+        ),
+        Filter::Since(since) => format!("sent_at >= ${}", bind(Bind::Timestamp(*since))),
+        Filter::Until(until) => format!("sent_at < ${}", bind(Bind::Timestamp(*until))),
+      });
+    }
+    if !self.include_redacted {
+      conditions.push(String::from("logs.redacted_at IS NULL"));
+    }
+
+    if let Some(cursor) = self.cursor {
+      let sent_at = format!("${}", bind(Bind::Timestamp(cursor.timestamp)));
+      let id = format!("${}", bind(Bind::BigInt(cursor.id)));
+      conditions.push(self.order.after("sent_at", "logs.id", &sent_at, &id));
+    }
+
+    if !conditions.is_empty() {
+      sql += "WHERE ";
+      sql += &conditions.join("\nAND ");
+      sql += "\n";
+    }
+    sql += &self.order.order_by("sent_at", "logs.id");
+    if let Some(limit) = self.limit {
+      sql += &format!(" LIMIT ${}", bind(Bind::Int(limit)));
+    }
+
+    (sql, binds)
+  }
+
+  /// Runs the query. `U` is `String` if the query was built [`with_usernames`](Self::with_usernames), `i32` otherwise.
+  #[cfg(not(feature = "compression"))]
+  pub async fn fetch_all<U>(&self, executor: impl sqlx::PgExecutor<'_> + Copy) -> Result<Vec<Entry<U>>>
+  where
+    U: Send + Unpin,
+    Entry<U>: for<'r> sqlx::FromRow<'r, PgRow>,
+  {
+    self.fetch_page(executor).await
+  }
+
+  /// Runs the query. `U` is `String` if the query was built [`with_usernames`](Self::with_usernames), `i32` otherwise.
+  ///
+  /// Compressed messages are decompressed, and matched against the pattern afterwards. Pages are fetched until
+  /// `limit` messages match or there are no more messages, so a page can take several round trips.
+  #[cfg(feature = "compression")]
+  pub async fn fetch_all<U>(&self, executor: impl sqlx::PgExecutor<'_> + Copy) -> Result<Vec<Entry<U>>>
+  where
+    U: Send + Unpin,
+    Entry<U>: for<'r> sqlx::FromRow<'r, PgRow>,
+  {
+    use crate::{compression::CompressedMessage, pagination::Keyed};
+
+    let pattern = self.filters.iter().find_map(|filter| match filter {
+      Filter::Pattern(pattern) => Some(format!("%{pattern}%")),
+      _ => None,
+    });
+    let Some(pattern) = pattern else {
+      let mut entries = self.fetch_page(executor).await?;
+      crate::compression::decompress(executor, &mut entries).await?;
+      return Ok(entries);
+    };
+
+    let mut query = self.clone();
+    let mut entries = Vec::new();
+    loop {
+      let mut page = query.fetch_page::<U>(executor).await?;
+      let exhausted = self.limit.map_or(true, |limit| page.len() < limit as usize);
+      if let Some(last) = page.last() {
+        query.cursor = Some(last.cursor());
+      }
+      crate::compression::decompress(executor, &mut page).await?;
+      // Uncompressed messages were already matched by Postgres
+      entries.extend(page.into_iter().filter(|entry| {
+        entry.compressed().is_none() || crate::compression::matches_like(&pattern, entry.message(), false)
+      }));
+      if exhausted || self.limit.map_or(false, |limit| entries.len() >= limit as usize) {
+        break;
+      }
+    }
+    if let Some(limit) = self.limit {
+      entries.truncate(limit as usize);
+    }
+    Ok(entries)
+  }
+
+  async fn fetch_page<U>(&self, executor: impl sqlx::PgExecutor<'_>) -> Result<Vec<Entry<U>>>
+  where
+    U: Send + Unpin,
+    Entry<U>: for<'r> sqlx::FromRow<'r, PgRow>,
+  {
+    let (sql, binds) = self.render();
+    let mut query = sqlx::query_as::<_, Entry<U>>(&sql);
+    for value in binds {
+      query = match value {
+        Bind::Text(v) => query.bind(v),
+        Bind::Int(v) => query.bind(v),
+        Bind::BigInt(v) => query.bind(v),
+        Bind::Timestamp(v) => query.bind(v),
+      };
+    }
+    query.fetch_all(executor).await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use chrono::TimeZone;
+
+  fn at(second: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, second).unwrap()
+  }
+
+  #[test]
+  fn channel_only() {
+    let (sql, binds) = LogsQuery::new().channel("forsen").render();
+    assert_eq!(
+      sql,
+      "SELECT * FROM twitch_logs logs\n\
+       WHERE logs.channel = (SELECT id FROM twitch_user WHERE username = $1)\n\
+       AND logs.redacted_at IS NULL\n\
+       ORDER BY sent_at DESC, logs.id DESC"
+    );
+    assert_eq!(binds, vec![Bind::Text("forsen".into())]);
+  }
+
+  #[test]
+  #[cfg(not(feature = "compression"))]
+  fn parameters_are_numbered_in_bind_order() {
+    let (sql, binds) = LogsQuery::new()
+      .with_usernames()
+      .channel("forsen")
+      .chatter("chatter")
+      .pattern("yo")
+      .cursor(Some(Cursor::new(10, at(5))))
+      .limit(128)
+      .render();
+    assert_eq!(
+      sql,
+      "SELECT logs.id, tw.username channel, tw2.username chatter, sent_at, message, redacted_at, message_zstd, dictionary\n\
+       FROM twitch_logs logs\n\
+       JOIN twitch_user tw ON tw.id = logs.channel\n\
+       JOIN twitch_user tw2 ON tw2.id = logs.chatter\n\
+       WHERE logs.channel = (SELECT id FROM twitch_user WHERE username = $1)\n\
+       AND logs.chatter = (SELECT id FROM twitch_user WHERE username = $2)\n\
+       AND logs.message LIKE $3\n\
+       AND logs.redacted_at IS NULL\n\
+       AND (sent_at, logs.id) < ($4, $5)\n\
+       ORDER BY sent_at DESC, logs.id DESC LIMIT $6"
+    );
+    assert_eq!(
+      binds,
+      vec![
+        Bind::Text("forsen".into()),
+        Bind::Text("chatter".into()),
+        Bind::Text("%yo%".into()),
+        Bind::Timestamp(at(5)),
+        Bind::BigInt(10),
+        Bind::Int(128),
+      ]
+    );
+  }
+
+  #[test]
+  #[cfg(feature = "compression")]
+  fn compressed_messages_are_matched_after_decompression() {
+    let (sql, binds) = LogsQuery::new().channel("forsen").pattern("yo").render();
+    assert_eq!(
+      sql,
+      "SELECT * FROM twitch_logs logs\n\
+       WHERE logs.channel = (SELECT id FROM twitch_user WHERE username = $1)\n\
+       AND (logs.message LIKE $2 OR logs.message_zstd IS NOT NULL)\n\
+       AND logs.redacted_at IS NULL\n\
+       ORDER BY sent_at DESC, logs.id DESC"
+    );
+    assert_eq!(binds, vec![Bind::Text("forsen".into()), Bind::Text("%yo%".into())]);
+  }
+
+  #[test]
+  fn skipped_filters_dont_shift_parameters() {
+    let (sql, binds) = LogsQuery::new()
+      .chatter("chatter")
+      .since(at(1))
+      .until(at(2))
+      .include_redacted(true)
+      .limit(10)
+      .render();
+    assert_eq!(
+      sql,
+      "SELECT * FROM twitch_logs logs\n\
+       WHERE logs.chatter = (SELECT id FROM twitch_user WHERE username = $1)\n\
+       AND sent_at >= $2\n\
+       AND sent_at < $3\n\
+       ORDER BY sent_at DESC, logs.id DESC LIMIT $4"
+    );
+    assert_eq!(
+      binds,
+      vec![
+        Bind::Text("chatter".into()),
+        Bind::Timestamp(at(1)),
+        Bind::Timestamp(at(2)),
+        Bind::Int(10),
+      ]
+    );
+  }
+
+  #[test]
+  fn cursor_follows_the_order() {
+    let (sql, _) = LogsQuery::new()
+      .include_redacted(true)
+      .order(Order::OldestFirst)
+      .cursor(Some(Cursor::new(1, at(0))))
+      .render();
+    assert_eq!(
+      sql,
+      "SELECT * FROM twitch_logs logs\n\
+       WHERE (sent_at, logs.id) > ($1, $2)\n\
+       ORDER BY sent_at ASC, logs.id ASC"
+    );
+  }
+
+  #[test]
+  fn no_filters() {
+    let (sql, binds) = LogsQuery::new().include_redacted(true).render();
+    assert_eq!(
+      sql,
+      "SELECT * FROM twitch_logs logs\nORDER BY sent_at DESC, logs.id DESC"
+    );
+    assert!(binds.is_empty());
+  }
+}
@@ -0,0 +1,100 @@
+//! Random messages of a chatter, for the chat bot's `quote` command. Chatters may opt out of being quoted.
+
+use super::Result;
+use crate::{logs::Entry, query::escape_like, users};
+
+/// Number of candidate quotes decompressed at once while looking for one which contains the pattern
+#[cfg(feature = "compression")]
+const DECOMPRESS_BATCH_SIZE: usize = 100;
+
+/// Returns a random message sent by `chatter` in `channel`, optionally only among the ones containing `pattern`,
+/// ignoring case. Redacted messages and chatters who opted out are never returned.
+///
+/// With compression, Postgres can't see the text of compressed messages, so all of them are candidates along with the
+/// messages which contain `pattern`, in a random order, and the first one which contains it once decompressed is returned.
+/// Without compression, compressed messages are never returned, since their `message` is empty.
+pub async fn random(
+  executor: impl sqlx::PgExecutor<'_> + Copy,
+  channel: &str,
+  chatter: &str,
+  pattern: Option<&str>,
+) -> Result<Option<Entry<String>>> {
+  let compressed_candidates = cfg!(feature = "compression") && pattern.is_some();
+  let entries = sqlx::query_as::<_, Entry<String>>(
+    r"
+    SELECT logs.id, tw.username channel, tw2.username chatter, sent_at, message, redacted_at, message_zstd, dictionary
+      FROM twitch_logs logs
+      JOIN twitch_user tw ON tw.id = logs.channel
+      JOIN twitch_user tw2 ON tw2.id = logs.chatter
+      WHERE logs.channel = (SELECT id FROM twitch_user WHERE username = $1)
+        AND logs.chatter = (SELECT id FROM twitch_user WHERE username = $2)
+        AND logs.redacted_at IS NULL
+        AND ($3::TEXT IS NULL OR logs.message ILIKE '%' || $3 || '%' OR ($4 AND logs.message_zstd IS NOT NULL))
+        AND ($6 OR logs.message_zstd IS NULL)
+        AND NOT EXISTS (SELECT 1 FROM quote_opt_outs o WHERE o.user_id = logs.chatter)
+      ORDER BY random()
+      LIMIT $5
+    ",
+  )
+  .bind(channel)
+  .bind(chatter)
+  .bind(pattern.map(escape_like))
+  .bind(compressed_candidates)
+  // `LIMIT NULL` returns every row
+  .bind((!compressed_candidates).then(|| 1i64))
+  .bind(cfg!(feature = "compression"))
+  .fetch_all(executor)
+  .await?;
+
+  #[cfg(not(feature = "compression"))]
+  return Ok(entries.into_iter().next());
+  #[cfg(feature = "compression")]
+  return first_match(executor, entries, pattern).await;
+}
+
+/// Returns the first of `entries` which contains `pattern` ignoring case, decompressing a few of them at a time
+#[cfg(feature = "compression")]
+async fn first_match(
+  executor: impl sqlx::PgExecutor<'_> + Copy,
+  mut entries: Vec<Entry<String>>,
+  pattern: Option<&str>,
+) -> Result<Option<Entry<String>>> {
+  let like = pattern.map(|pattern| format!("%{}%", escape_like(pattern)));
+  for start in (0..entries.len()).step_by(DECOMPRESS_BATCH_SIZE) {
+    let end = (start + DECOMPRESS_BATCH_SIZE).min(entries.len());
+    crate::compression::decompress(executor, &mut entries[start..end]).await?;
+    let found = (start..end).find(|&i| {
+      like.as_deref().map_or(true, |like| {
+        crate::compression::matches_like(like, entries[i].message(), true)
+      })
+    });
+    if let Some(i) = found {
+      return Ok(Some(entries.swap_remove(i)));
+    }
+  }
+  Ok(None)
+}
+
+/// Stops the messages of `username` from being quoted. Returns `false` if they had already opted out.
+pub async fn opt_out(executor: impl sqlx::PgExecutor<'_> + Copy, username: &str) -> Result<bool> {
+  let user = users::get_or_create(executor, username, None).await?;
+  sqlx::query("INSERT INTO quote_opt_outs (user_id) VALUES ($1) ON CONFLICT DO NOTHING")
+    .bind(user.id())
+    .execute(executor)
+    .await
+    .map(|r| r.rows_affected() > 0)
+}
+
+/// Allows the messages of `username` to be quoted again. Returns `false` if they hadn't opted out.
+pub async fn opt_in(executor: impl sqlx::PgExecutor<'_>, username: &str) -> Result<bool> {
+  sqlx::query(
+    "
+    DELETE FROM quote_opt_outs
+      WHERE user_id = (SELECT id FROM twitch_user WHERE username = $1)
+    ",
+  )
+  .bind(username)
+  .execute(executor)
+  .await
+  .map(|r| r.rows_affected() > 0)
+}
@@ -0,0 +1,153 @@
+//! Username to user ID resolution, with a bounded cache shared by all callers.
+
+use super::Result;
+use ahash::{AHashMap, AHashSet};
+use lru::LruCache;
+use std::num::NonZeroUsize;
+
+/// Cache hit/miss counters of a [`UserResolver`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ResolverStats {
+  pub hits: u64,
+  pub misses: u64,
+  /// Number of cached usernames
+  pub size: usize,
+}
+
+/// Resolves usernames into IDs from `twitch_user`, creating the users which don't exist yet.
+///
+/// Resolved IDs are kept in an LRU cache, so repeated lookups don't hit the database.
+pub struct UserResolver {
+  /// username -> (id, is_logged_as_channel)
+  cache: LruCache<String, (i32, bool)>,
+  hits: u64,
+  misses: u64,
+}
+
+impl UserResolver {
+  pub fn new(capacity: NonZeroUsize) -> Self {
+    Self {
+      cache: LruCache::new(capacity),
+      hits: 0,
+      misses: 0,
+    }
+  }
+
+  pub fn stats(&self) -> ResolverStats {
+    ResolverStats {
+      hits: self.hits,
+      misses: self.misses,
+      size: self.cache.len(),
+    }
+  }
+
+  /// Resolves the ID of a logged channel, and marks the user as a logged channel if they weren't one already.
+  pub async fn resolve_channel(&mut self, executor: impl sqlx::PgExecutor<'_>, username: &str) -> Result<i32> {
+    // Fast path: username is in the cache, and is already known to be a channel
+    if let Some((id, true)) = self.cache.get(username).copied() {
+      self.hits += 1;
+      return Ok(id);
+    }
+    self.misses += 1;
+
+    // Slow path: user is either in the database or doesn't exist
+    // This seems to be 30% faster than doing SELECT + INSERT
+    let id = sqlx::query_scalar::<_, i32>(
+      r#"
+      WITH
+        input_rows (username, is_logged_as_channel) AS (VALUES ($1, TRUE)),
+        inserted AS (
+          INSERT INTO twitch_user (username, is_logged_as_channel)
+            SELECT * FROM input_rows
+            ON CONFLICT (username) 
+              DO UPDATE 
+                SET is_logged_as_channel = EXCLUDED.is_logged_as_channel 
+                WHERE twitch_user.username = EXCLUDED.username 
+                AND twitch_user.is_logged_as_channel = FALSE
+            RETURNING id
+        )
+      SELECT id FROM inserted
+      UNION ALL
+      SELECT c.id FROM input_rows JOIN twitch_user c USING (username);
+      "#,
+    )
+    .bind(username)
+    .fetch_one(executor)
+    .await?;
+
+    self.cache.put(username.to_owned(), (id, true));
+    Ok(id)
+  }
+
+  /// Resolves the IDs of `usernames`, in the same order.
+  ///
+  /// All usernames which aren't cached are resolved in a single round trip.
+  pub async fn resolve_many(
+    &mut self,
+    executor: impl sqlx::PgExecutor<'_> + Copy,
+    usernames: &[String],
+  ) -> Result<Vec<i32>> {
+    // Cached IDs may be evicted while the missing ones are inserted, so they're collected up front
+    let mut resolved = AHashMap::<&str, i32>::with_capacity(usernames.len());
+    let mut missing = AHashSet::<&str>::new();
+    for username in usernames {
+      if let Some((id, _)) = self.cache.get(username) {
+        resolved.insert(username.as_str(), *id);
+      } else {
+        missing.insert(username.as_str());
+      }
+    }
+    self.misses += missing.len() as u64;
+    self.hits += (usernames.len() - missing.len()) as u64;
+
+    if !missing.is_empty() {
+      let names = missing.iter().copied().collect::<Vec<_>>();
+      let rows = sqlx::query_as::<_, (String, i32, bool)>(
+        "
+        WITH
+          input_rows (username) AS (SELECT DISTINCT * FROM UNNEST($1::VARCHAR[])),
+          inserted AS (
+            INSERT INTO twitch_user (username)
+              SELECT * FROM input_rows
+              ON CONFLICT (username) DO NOTHING
+              RETURNING username, id, is_logged_as_channel
+          )
+        SELECT * FROM inserted
+        UNION ALL
+        SELECT tw.username, tw.id, tw.is_logged_as_channel
+          FROM input_rows JOIN twitch_user tw USING (username)
+        ",
+      )
+      .bind(&names)
+      .fetch_all(executor)
+      .await?;
+
+      // Users inserted concurrently by another transaction are neither inserted nor visible above
+      let rows = if rows.len() < names.len() {
+        sqlx::query_as::<_, (String, i32, bool)>(
+          "
+          SELECT username, id, is_logged_as_channel FROM twitch_user
+            WHERE username = ANY($1::VARCHAR[])
+          ",
+        )
+        .bind(&names)
+        .fetch_all(executor)
+        .await?
+      } else {
+        rows
+      };
+
+      for (username, id, is_logged_as_channel) in rows {
+        if let Some(name) = missing.get(username.as_str()) {
+          resolved.insert(*name, id);
+        }
+        self.cache.put(username, (id, is_logged_as_channel));
+      }
+    }
+
+    usernames
+      .iter()
+      .map(|username| resolved.get(username.as_str()).copied().ok_or(sqlx::Error::RowNotFound))
+      .collect()
+  }
+}
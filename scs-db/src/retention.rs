@@ -0,0 +1,104 @@
+//! Removal of logs which are past their retention period
+
+use super::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// A deleted log, with enough information to archive it
+#[derive(Debug, sqlx::FromRow, Serialize, getset::Getters, getset::CopyGetters)]
+pub struct ExpiredLog {
+  #[getset(get_copy = "pub")]
+  id: i64,
+  #[getset(get = "pub")]
+  chatter: String,
+  #[getset(get_copy = "pub")]
+  sent_at: DateTime<Utc>,
+  #[getset(get = "pub")]
+  message: String,
+  /// Compressed text of the message, see [`crate::compression`]
+  #[cfg(feature = "compression")]
+  #[serde(skip)]
+  message_zstd: Option<Vec<u8>>,
+  #[cfg(feature = "compression")]
+  #[serde(skip)]
+  dictionary: Option<i32>,
+}
+
+#[cfg(feature = "compression")]
+impl crate::compression::CompressedMessage for ExpiredLog {
+  fn compressed(&self) -> Option<(i32, &[u8])> {
+    self.dictionary.zip(self.message_zstd.as_deref())
+  }
+
+  fn set_message(&mut self, message: String) {
+    self.message = message;
+  }
+}
+
+/// Number of logs in `channel` sent before `before`
+pub async fn count_expired(executor: impl sqlx::PgExecutor<'_>, channel: i32, before: DateTime<Utc>) -> Result<i64> {
+  sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM twitch_logs WHERE channel = $1 AND sent_at < $2")
+    .bind(channel)
+    .bind(before)
+    .fetch_one(executor)
+    .await
+}
+
+/// Deletes up to `limit` of the oldest logs in `channel` sent before `before`, and returns them.
+/// Compressed messages are returned as they're stored, see [`crate::compression::decompress`].
+///
+/// The statistics of the channel are updated in the same statement: its message count, its first and last message
+/// times, and the chatters who no longer have any logs in it, like [`crate::purge::purge_channel`] does.
+pub async fn delete_expired(
+  executor: impl sqlx::PgExecutor<'_>,
+  channel: i32,
+  before: DateTime<Utc>,
+  limit: i64,
+) -> Result<Vec<ExpiredLog>> {
+  // Every part of the statement sees the logs as they were before the deletion,
+  // so the deleted logs are excluded explicitly when updating the stats.
+  sqlx::query_as::<_, ExpiredLog>(
+    "
+    WITH batch AS (
+      SELECT id FROM twitch_logs
+        WHERE channel = $1 AND sent_at < $2
+        ORDER BY sent_at, id
+        LIMIT $3
+    ), deleted_metadata AS (
+      DELETE FROM twitch_logs_metadata WHERE id IN (SELECT id FROM batch)
+    ), deleted AS (
+      DELETE FROM twitch_logs WHERE id IN (SELECT id FROM batch)
+        RETURNING id, chatter, sent_at, message, message_zstd, dictionary
+    ), stats AS (
+      UPDATE twitch_channel_stats
+        SET message_count = message_count - (SELECT COUNT(*) FROM deleted),
+            first_message_at = (
+              SELECT MIN(sent_at) FROM twitch_logs
+                WHERE channel = $1 AND id NOT IN (SELECT id FROM deleted)
+            ),
+            last_message_at = (
+              SELECT MAX(sent_at) FROM twitch_logs
+                WHERE channel = $1 AND id NOT IN (SELECT id FROM deleted)
+            )
+        WHERE channel = $1
+    ), chatters AS (
+      DELETE FROM twitch_channel_chatters cc
+        WHERE cc.channel = $1
+          AND cc.chatter IN (SELECT chatter FROM deleted)
+          AND NOT EXISTS (
+            SELECT 1 FROM twitch_logs
+              WHERE channel = $1 AND chatter = cc.chatter AND id NOT IN (SELECT id FROM deleted)
+          )
+    )
+    SELECT deleted.id, tw.username chatter, deleted.sent_at, deleted.message, deleted.message_zstd, deleted.dictionary
+      FROM deleted
+      INNER JOIN twitch_user tw ON tw.id = deleted.chatter
+      ORDER BY deleted.sent_at, deleted.id
+    ",
+  )
+  .bind(channel)
+  .bind(before)
+  .bind(limit)
+  .fetch_all(executor)
+  .await
+}
@@ -0,0 +1,57 @@
+//! Counts shown on the admin dashboard
+
+use super::Result;
+use serde::Serialize;
+
+#[derive(Debug, sqlx::FromRow, Serialize, getset::Getters, getset::CopyGetters)]
+pub struct TableRows {
+  #[getset(get = "pub")]
+  table_name: String,
+  /// Estimated from the table statistics, so it may lag behind recent inserts and deletes
+  #[getset(get_copy = "pub")]
+  rows: i64,
+}
+
+#[derive(Debug, sqlx::FromRow, Serialize, getset::CopyGetters)]
+#[getset(get_copy = "pub")]
+pub struct UserCounts {
+  /// Every known Twitch user, including channels and chatters
+  users: i64,
+  /// Users allowed to use the API
+  allowlisted: i64,
+  admins: i64,
+  tokens: i64,
+  /// Users with at least one token
+  users_with_tokens: i64,
+}
+
+/// Returns the estimated number of rows of every table, ordered by name.
+///
+/// This reads the table statistics instead of counting rows, so it stays cheap on large tables.
+pub async fn estimated_row_counts(executor: impl sqlx::PgExecutor<'_>) -> Result<Vec<TableRows>> {
+  sqlx::query_as::<_, TableRows>(
+    r#"
+    SELECT relname::TEXT table_name, n_live_tup "rows"
+      FROM pg_stat_user_tables
+      WHERE relname NOT LIKE '\_sqlx%'
+      ORDER BY relname
+    "#,
+  )
+  .fetch_all(executor)
+  .await
+}
+
+pub async fn user_counts(executor: impl sqlx::PgExecutor<'_>) -> Result<UserCounts> {
+  sqlx::query_as::<_, UserCounts>(
+    "
+    SELECT
+      (SELECT COUNT(*) FROM twitch_user) users,
+      (SELECT COUNT(*) FROM allowlist) allowlisted,
+      (SELECT COUNT(*) FROM allowlist WHERE is_admin) admins,
+      (SELECT COUNT(*) FROM tokens) tokens,
+      (SELECT COUNT(DISTINCT user_id) FROM tokens) users_with_tokens
+    ",
+  )
+  .fetch_one(executor)
+  .await
+}
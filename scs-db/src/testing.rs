@@ -0,0 +1,63 @@
+//! A disposable database for integration tests.
+//!
+//! Requires a running docker daemon.
+
+use crate::{logs, resolver::UserResolver, Database};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use std::num::NonZeroUsize;
+use testcontainers::{clients::Cli, core::RunnableImage, images::postgres::Postgres, Container};
+
+/// The same version as the one used in `docker/docker-compose.yml`
+const POSTGRES_TAG: &str = "15";
+
+/// `second` seconds after the start of 2023, so that tests can order their messages
+pub fn at(second: u32) -> DateTime<Utc> {
+  Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap() + Duration::seconds(second.into())
+}
+
+/// A freshly migrated Postgres instance, which is removed when this is dropped
+pub struct TestDatabase {
+  pool: Database,
+  _container: Container<'static, Postgres>,
+}
+
+impl TestDatabase {
+  pub async fn new() -> crate::Result<Self> {
+    // Containers borrow the client, and each test owns its database for its entire duration,
+    // so the client is leaked to avoid threading a lifetime through every test.
+    let docker: &'static Cli = Box::leak(Box::new(Cli::default()));
+    let container = docker.run(RunnableImage::from(Postgres::default()).with_tag(POSTGRES_TAG));
+    let port = container.get_host_port_ipv4(5432);
+
+    let pool = crate::connect(format!("postgres://postgres@127.0.0.1:{port}/postgres")).await?;
+    sqlx::migrate!("./migrations").run(&pool).await?;
+
+    Ok(Self {
+      pool,
+      _container: container,
+    })
+  }
+
+  /// Same as [`TestDatabase::new`], with the logged channel `channel`, whose ID is returned as well
+  pub async fn with_channel(channel: &str) -> crate::Result<(Self, i32)> {
+    let db = Self::new().await?;
+    let channel = UserResolver::new(NonZeroUsize::new(10).unwrap())
+      .resolve_channel(db.pool(), channel)
+      .await?;
+    Ok((db, channel))
+  }
+
+  /// Inserts `messages` into the logs of `channel`, as their chatter, the second they were sent [`at`], and their text
+  pub async fn insert_messages(&self, channel: i32, messages: &[(&str, u32, &str)]) -> crate::Result<()> {
+    let mut soa = logs::SOAEntry::new(messages.len());
+    for (chatter, second, message) in messages {
+      soa.add(channel, chatter.to_string(), at(*second), message.to_string());
+    }
+    logs::insert_soa(self.pool(), &mut soa).await?;
+    Ok(())
+  }
+
+  pub fn pool(&self) -> &Database {
+    &self.pool
+  }
+}
@@ -50,8 +50,10 @@ pub async fn verify(
   user_id: i32,
   scs_user_api_token: &str,
 ) -> Result<bool> {
-  sqlx::query_scalar::<_, bool>(
-    "
+  crate::metrics::instrument(
+    "verify_token",
+    sqlx::query_scalar::<_, bool>(
+      "
       SELECT TRUE
       WHERE (
         SELECT TRUE FROM allowlist
@@ -63,10 +65,11 @@ pub async fn verify(
           AND scs_user_api_token = $2
       )
       ",
+    )
+    .bind(user_id)
+    .bind(scs_user_api_token)
+    .fetch_optional(executor),
   )
-  .bind(user_id)
-  .bind(scs_user_api_token)
-  .fetch_optional(executor)
   .await
   .map(|v| v.unwrap_or(false))
 }
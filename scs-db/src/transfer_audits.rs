@@ -0,0 +1,281 @@
+//! Verification of the log transfers done by the ingester.
+//!
+//! The lines of the source are counted as they're read, and its messages per channel and day before they're inserted,
+//! see [`Snapshot`]. [`verify`] then counts the rows in `twitch_logs` within the ID ranges returned by the inserts,
+//! compares them with the messages, and records the result as a [`TransferAudit`]. Rows inserted by other writers at
+//! the same time are only counted if their IDs fall within the transfer's ranges.
+
+use super::Result;
+use crate::logs::{DailyCounts, DailyInserts, SOAEntry};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Lines read, messages counted, and row IDs inserted by a transfer
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+  lines: i64,
+  skipped: i64,
+  counts: DailyCounts,
+  /// First and last ID of the rows inserted per channel and day
+  ids: BTreeMap<(i32, NaiveDate), (i64, i64)>,
+  started_at: DateTime<Utc>,
+  start: std::time::Instant,
+}
+
+impl Snapshot {
+  /// Starts a transfer. The lines of the source are counted with [`Snapshot::read`], the messages to transfer with
+  /// [`Snapshot::add`] before they're inserted, and the rows returned by the inserts with [`Snapshot::inserted`].
+  pub fn start() -> Self {
+    Self {
+      lines: 0,
+      skipped: 0,
+      counts: BTreeMap::new(),
+      ids: BTreeMap::new(),
+      started_at: Utc::now(),
+      start: std::time::Instant::now(),
+    }
+  }
+
+  /// Counts `lines` lines read from the source, of which `skipped` weren't messages, e.g. headers, empty lines, or
+  /// lines which were quarantined
+  pub fn read(&mut self, lines: i64, skipped: i64) {
+    self.lines += lines;
+    self.skipped += skipped;
+  }
+
+  /// Counts the messages in `entry`, which must be called before it's inserted, as that clears it
+  pub fn add(&mut self, entry: &SOAEntry) {
+    for (channel, sent_at) in entry.channel.iter().zip(&entry.sent_at) {
+      *self.counts.entry((*channel, sent_at.date_naive())).or_default() += 1;
+    }
+  }
+
+  /// Records the IDs of the rows which an insert returned, e.g. [`crate::logs::InsertStats::inserted`]
+  pub fn inserted(&mut self, inserted: &DailyInserts) {
+    for (key, day) in inserted {
+      let ids = self.ids.entry(*key).or_insert((day.first_id, day.last_id));
+      *ids = (ids.0.min(day.first_id), ids.1.max(day.last_id));
+    }
+  }
+
+  /// Number of messages counted so far
+  pub fn rows(&self) -> i64 {
+    self.counts.values().sum()
+  }
+}
+
+/// A day of a channel whose inserted row count differs from the expected one
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow, Serialize, getset::Getters, getset::CopyGetters)]
+pub struct Mismatch {
+  /// Username of the channel
+  #[getset(get = "pub")]
+  channel: String,
+  /// UTC date of the messages
+  #[getset(get_copy = "pub")]
+  day: NaiveDate,
+  #[getset(get_copy = "pub")]
+  expected: i64,
+  #[getset(get_copy = "pub")]
+  inserted: i64,
+}
+
+/// The verified result of a transfer
+#[derive(Debug, Clone, Serialize, getset::Getters, getset::CopyGetters)]
+pub struct TransferAudit {
+  #[getset(get_copy = "pub")]
+  id: i32,
+  /// Path of the ingested file, or `<stdin>`
+  #[getset(get = "pub")]
+  source: String,
+  #[getset(get_copy = "pub")]
+  started_at: DateTime<Utc>,
+  #[getset(get_copy = "pub")]
+  duration_ms: i64,
+  /// Number of lines read from the source
+  #[getset(get_copy = "pub")]
+  source_lines: i64,
+  /// Number of lines which weren't messages, or were quarantined
+  #[getset(get_copy = "pub")]
+  skipped_lines: i64,
+  /// Number of lines which should have been inserted, `source_lines - skipped_lines`
+  #[getset(get_copy = "pub")]
+  expected_rows: i64,
+  /// Number of rows found in the table
+  #[getset(get_copy = "pub")]
+  inserted_rows: i64,
+  #[getset(get = "pub")]
+  mismatches: Vec<Mismatch>,
+}
+
+impl TransferAudit {
+  /// Whether every line of the source which wasn't skipped is in the table, and every channel and day has the
+  /// expected number of rows
+  #[inline]
+  pub fn is_ok(&self) -> bool {
+    self.expected_rows == self.inserted_rows && self.mismatches.is_empty()
+  }
+}
+
+/// Counts the rows in `twitch_logs` within the ID ranges of the transfer, compares them with the lines read and the
+/// messages counted per channel and day, and records the result
+pub async fn verify(
+  executor: impl sqlx::PgExecutor<'_> + Copy,
+  source: &str,
+  snapshot: Snapshot,
+) -> Result<TransferAudit> {
+  let (mut channels, mut days, mut first_ids, mut last_ids) = (vec![], vec![], vec![], vec![]);
+  for ((channel, day), (first_id, last_id)) in &snapshot.ids {
+    channels.push(*channel);
+    days.push(*day);
+    first_ids.push(*first_id);
+    last_ids.push(*last_id);
+  }
+  let inserted = crate::metrics::instrument(
+    "count_transferred_logs",
+    sqlx::query_as::<_, (i32, NaiveDate, i64)>(
+      "
+      SELECT r.channel, r.day, COUNT(*)
+        FROM UNNEST($1::INTEGER[], $2::DATE[], $3::BIGINT[], $4::BIGINT[]) AS r(channel, day, first_id, last_id)
+        JOIN twitch_logs l ON l.channel = r.channel
+          AND l.id BETWEEN r.first_id AND r.last_id
+          AND (l.sent_at AT TIME ZONE 'UTC')::date = r.day
+        GROUP BY r.channel, r.day
+      ",
+    )
+    .bind(&channels)
+    .bind(&days)
+    .bind(&first_ids)
+    .bind(&last_ids)
+    .fetch_all(executor),
+  )
+  .await?
+  .into_iter()
+  .map(|(channel, day, count)| ((channel, day), count))
+  .collect::<DailyCounts>();
+
+  let days = snapshot.counts.keys().chain(inserted.keys()).collect::<BTreeSet<_>>();
+  let (mut mismatch_channels, mut mismatch_days, mut expected, mut actual) = (vec![], vec![], vec![], vec![]);
+  for key in days {
+    let (expected_count, inserted_count) = (
+      snapshot.counts.get(key).copied().unwrap_or(0),
+      inserted.get(key).copied().unwrap_or(0),
+    );
+    if expected_count != inserted_count {
+      mismatch_channels.push(key.0);
+      mismatch_days.push(key.1);
+      expected.push(expected_count);
+      actual.push(inserted_count);
+    }
+  }
+
+  let (id,): (i32,) = crate::metrics::instrument(
+    "insert_transfer_audit",
+    sqlx::query_as(
+      "
+      INSERT INTO transfer_audit (source, started_at, duration_ms, source_lines, skipped_lines, expected_rows,
+          inserted_rows)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id
+      ",
+    )
+    .bind(source)
+    .bind(snapshot.started_at)
+    .bind(snapshot.start.elapsed().as_millis() as i64)
+    .bind(snapshot.lines)
+    .bind(snapshot.skipped)
+    .bind(snapshot.lines - snapshot.skipped)
+    .bind(inserted.values().sum::<i64>())
+    .fetch_one(executor),
+  )
+  .await?;
+  if !mismatch_channels.is_empty() {
+    crate::metrics::instrument(
+      "insert_transfer_audit_mismatches",
+      sqlx::query(
+        "
+        INSERT INTO transfer_audit_mismatch (audit, channel, day, expected, inserted)
+          SELECT $1, * FROM UNNEST($2::INTEGER[], $3::DATE[], $4::BIGINT[], $5::BIGINT[])
+        ",
+      )
+      .bind(id)
+      .bind(&mismatch_channels)
+      .bind(&mismatch_days)
+      .bind(&expected)
+      .bind(&actual)
+      .execute(executor),
+    )
+    .await?;
+  }
+
+  let audit = fetch(executor, Some(id), 1).await?.pop();
+  Ok(audit.expect("The audit was just inserted"))
+}
+
+/// Returns the `limit` most recent audits, newest first
+pub async fn fetch_recent(executor: impl sqlx::PgExecutor<'_> + Copy, limit: i64) -> Result<Vec<TransferAudit>> {
+  fetch(executor, None, limit).await
+}
+
+/// Returns the audit with the ID `id`, or the `limit` most recent ones if it's `None`
+async fn fetch(executor: impl sqlx::PgExecutor<'_> + Copy, id: Option<i32>, limit: i64) -> Result<Vec<TransferAudit>> {
+  let mut audits = crate::metrics::instrument(
+    "fetch_transfer_audits",
+    sqlx::query_as::<_, (i32, String, DateTime<Utc>, i64, i64, i64, i64, i64)>(
+      "
+      SELECT id, source, started_at, duration_ms, source_lines, skipped_lines, expected_rows, inserted_rows
+        FROM transfer_audit
+        WHERE $1::INTEGER IS NULL OR id = $1
+        ORDER BY id DESC
+        LIMIT $2
+      ",
+    )
+    .bind(id)
+    .bind(limit)
+    .fetch_all(executor),
+  )
+  .await?
+  .into_iter()
+  .map(
+    |(id, source, started_at, duration_ms, source_lines, skipped_lines, expected_rows, inserted_rows)| TransferAudit {
+      id,
+      source,
+      started_at,
+      duration_ms,
+      source_lines,
+      skipped_lines,
+      expected_rows,
+      inserted_rows,
+      mismatches: vec![],
+    },
+  )
+  .collect::<Vec<_>>();
+
+  let ids = audits.iter().map(|audit| audit.id).collect::<Vec<_>>();
+  let mismatches = crate::metrics::instrument(
+    "fetch_transfer_audit_mismatches",
+    sqlx::query_as::<_, (i32, String, NaiveDate, i64, i64)>(
+      "
+      SELECT m.audit, tw.username, m.day, m.expected, m.inserted
+        FROM transfer_audit_mismatch m
+        JOIN twitch_user tw ON tw.id = m.channel
+        WHERE m.audit = ANY($1)
+        ORDER BY tw.username, m.day
+      ",
+    )
+    .bind(&ids)
+    .fetch_all(executor),
+  )
+  .await?;
+  for (audit, channel, day, expected, inserted) in mismatches {
+    if let Some(audit) = audits.iter_mut().find(|a| a.id == audit) {
+      audit.mismatches.push(Mismatch {
+        channel,
+        day,
+        expected,
+        inserted,
+      });
+    }
+  }
+  Ok(audits)
+}
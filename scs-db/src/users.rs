@@ -1,4 +1,10 @@
 use super::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Most IDs or usernames looked up by a single query of [`resolve_usernames`] and [`resolve_ids`]
+pub const RESOLVE_CHUNK_SIZE: usize = 10_000;
 
 #[derive(Debug, sqlx::FromRow, getset::Getters, getset::CopyGetters)]
 pub struct TwitchUser {
@@ -54,3 +60,101 @@ pub async fn create_bulk(executor: impl sqlx::PgExecutor<'_> + Copy, usernames:
   .await?;
   Ok(())
 }
+
+/// Returns the usernames of the users with `ids`. Unknown IDs are left out.
+///
+/// The IDs are looked up in chunks of [`RESOLVE_CHUNK_SIZE`], so any number of them can be resolved.
+pub async fn resolve_usernames(
+  executor: impl sqlx::PgExecutor<'_> + Copy,
+  ids: &[i32],
+) -> Result<HashMap<i32, String>> {
+  let mut usernames = HashMap::with_capacity(ids.len());
+  for chunk in ids.chunks(RESOLVE_CHUNK_SIZE) {
+    let rows = crate::metrics::instrument(
+      "resolve_usernames",
+      sqlx::query_as::<_, (i32, String)>("SELECT id, username FROM twitch_user WHERE id = ANY($1)")
+        .bind(chunk)
+        .fetch_all(executor),
+    )
+    .await?;
+    usernames.extend(rows);
+  }
+  Ok(usernames)
+}
+
+/// Returns the IDs of the users with `usernames`, keyed by the usernames as given. Usernames are stored in lowercase,
+/// so they're matched ignoring case. Unknown usernames are left out, and unlike [`crate::resolver::UserResolver`], no
+/// users are created.
+///
+/// The usernames are looked up in chunks of [`RESOLVE_CHUNK_SIZE`], so any number of them can be resolved.
+pub async fn resolve_ids(
+  executor: impl sqlx::PgExecutor<'_> + Copy,
+  usernames: &[String],
+) -> Result<HashMap<String, i32>> {
+  let mut ids = HashMap::with_capacity(usernames.len());
+  for chunk in usernames.chunks(RESOLVE_CHUNK_SIZE) {
+    let lowercase = chunk.iter().map(|username| username.to_lowercase()).collect::<Vec<_>>();
+    let rows = crate::metrics::instrument(
+      "resolve_ids",
+      sqlx::query_as::<_, (String, i32)>("SELECT username, id FROM twitch_user WHERE username = ANY($1)")
+        .bind(&lowercase)
+        .fetch_all(executor),
+    )
+    .await?
+    .into_iter()
+    .collect::<HashMap<_, _>>();
+    for (username, lowercase) in chunk.iter().zip(&lowercase) {
+      if let Some(id) = rows.get(lowercase) {
+        ids.insert(username.clone(), *id);
+      }
+    }
+  }
+  Ok(ids)
+}
+
+#[derive(Debug, sqlx::FromRow, Serialize, getset::Getters, getset::CopyGetters)]
+pub struct ChatterInfo {
+  #[getset(get_copy = "pub")]
+  id: i32,
+  #[getset(get = "pub")]
+  username: String,
+  #[getset(get_copy = "pub")]
+  is_logged_as_channel: bool,
+  /// When the chatter sent their first logged message, in any channel
+  #[getset(get_copy = "pub")]
+  first_seen_at: Option<DateTime<Utc>>,
+  #[getset(get_copy = "pub")]
+  last_seen_at: Option<DateTime<Utc>>,
+}
+
+/// Returns up to `limit` users ordered by ID, starting after the user with the ID `after`.
+///
+/// The first and last messages of each user are looked up with the `(chatter, sent_at, id)` index, so pages stay cheap
+/// regardless of the number of logs.
+pub async fn list_chatters(
+  executor: impl sqlx::PgExecutor<'_>,
+  after: Option<i32>,
+  limit: i64,
+) -> Result<Vec<ChatterInfo>> {
+  crate::metrics::instrument(
+    "list_chatters",
+    sqlx::query_as::<_, ChatterInfo>(
+      "
+    SELECT
+      tw.id,
+      tw.username,
+      tw.is_logged_as_channel,
+      (SELECT sent_at FROM twitch_logs WHERE chatter = tw.id ORDER BY sent_at ASC LIMIT 1) first_seen_at,
+      (SELECT sent_at FROM twitch_logs WHERE chatter = tw.id ORDER BY sent_at DESC LIMIT 1) last_seen_at
+    FROM twitch_user tw
+    WHERE $1::INTEGER IS NULL OR tw.id > $1
+    ORDER BY tw.id
+    LIMIT $2
+    ",
+    )
+    .bind(after)
+    .bind(limit)
+    .fetch_all(executor),
+  )
+  .await
+}
@@ -0,0 +1,77 @@
+//! Viewer count snapshots of logged channels
+
+use super::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// The state of a channel's stream at some point in time.
+///
+/// This is also the format of the snapshot files written by the collector, one JSON object per line.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+  pub channel: String,
+  pub taken_at: DateTime<Utc>,
+  pub live: bool,
+  pub viewer_count: i32,
+  pub title: Option<String>,
+  pub game: Option<String>,
+}
+
+/// A stored snapshot
+#[derive(Debug, sqlx::FromRow, Serialize, getset::Getters, getset::CopyGetters)]
+pub struct StoredSnapshot {
+  #[getset(get_copy = "pub")]
+  taken_at: DateTime<Utc>,
+  #[getset(get_copy = "pub")]
+  live: bool,
+  #[getset(get_copy = "pub")]
+  viewer_count: i32,
+  #[getset(get = "pub")]
+  title: Option<String>,
+  #[getset(get = "pub")]
+  game: Option<String>,
+}
+
+/// Inserts a snapshot of the channel with the ID `channel`. Returns `false` if a snapshot taken at the same time
+/// already exists, in which case it's kept.
+pub async fn insert(executor: impl sqlx::PgExecutor<'_>, channel: i32, snapshot: &Snapshot) -> Result<bool> {
+  let result = sqlx::query(
+    "
+    INSERT INTO twitch_viewer_snapshots (channel, taken_at, live, viewer_count, title, game)
+      VALUES ($1, $2, $3, $4, $5, $6)
+      ON CONFLICT DO NOTHING
+    ",
+  )
+  .bind(channel)
+  .bind(snapshot.taken_at)
+  .bind(snapshot.live)
+  .bind(snapshot.viewer_count)
+  .bind(&snapshot.title)
+  .bind(&snapshot.game)
+  .execute(executor)
+  .await?;
+  Ok(result.rows_affected() > 0)
+}
+
+/// Snapshots of `channel` taken in `[since, until)`, oldest first
+pub async fn fetch(
+  executor: impl sqlx::PgExecutor<'_>,
+  channel: &str,
+  since: DateTime<Utc>,
+  until: DateTime<Utc>,
+) -> Result<Vec<StoredSnapshot>> {
+  sqlx::query_as::<_, StoredSnapshot>(&format!(
+    "
+    SELECT taken_at, live, viewer_count, title, game
+      FROM twitch_viewer_snapshots
+      WHERE channel = ({}) AND taken_at >= $2 AND taken_at < $3
+      ORDER BY taken_at
+    ",
+    crate::get_channel_id_sql!(1)
+  ))
+  .bind(channel)
+  .bind(since)
+  .bind(until)
+  .fetch_all(executor)
+  .await
+}
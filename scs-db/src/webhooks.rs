@@ -0,0 +1,115 @@
+use super::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+#[derive(Debug, Clone, sqlx::FromRow, Serialize, getset::Getters, getset::CopyGetters)]
+pub struct Webhook {
+  #[getset(get_copy = "pub")]
+  id: i32,
+  #[getset(get = "pub")]
+  url: String,
+  #[getset(get = "pub")]
+  #[serde(skip)]
+  secret: String,
+  #[getset(get_copy = "pub")]
+  created_by: i32,
+  #[getset(get_copy = "pub")]
+  created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, sqlx::FromRow, Serialize, getset::Getters, getset::CopyGetters)]
+pub struct Delivery {
+  #[getset(get_copy = "pub")]
+  id: i64,
+  #[getset(get_copy = "pub")]
+  webhook: i32,
+  #[getset(get = "pub")]
+  event: String,
+  #[getset(get = "pub")]
+  model: String,
+  #[getset(get_copy = "pub")]
+  attempts: i32,
+  #[getset(get_copy = "pub")]
+  status: Option<i32>,
+  #[getset(get = "pub")]
+  error: Option<String>,
+  #[getset(get_copy = "pub")]
+  delivered: bool,
+  #[getset(get_copy = "pub")]
+  created_at: DateTime<Utc>,
+}
+
+pub async fn create(executor: impl sqlx::PgExecutor<'_>, url: &str, secret: &str, created_by: i32) -> Result<Webhook> {
+  sqlx::query_as::<_, Webhook>(
+    "
+    INSERT INTO model_webhooks (url, secret, created_by)
+      VALUES ($1, $2, $3)
+      RETURNING *
+    ",
+  )
+  .bind(url)
+  .bind(secret)
+  .bind(created_by)
+  .fetch_one(executor)
+  .await
+}
+
+pub async fn get_all(executor: impl sqlx::PgExecutor<'_>) -> Result<Vec<Webhook>> {
+  sqlx::query_as::<_, Webhook>("SELECT * FROM model_webhooks ORDER BY id")
+    .fetch_all(executor)
+    .await
+}
+
+/// Returns `false` if the webhook doesn't exist
+pub async fn delete(executor: impl sqlx::PgExecutor<'_>, id: i32) -> Result<bool> {
+  sqlx::query("DELETE FROM model_webhooks WHERE id = $1")
+    .bind(id)
+    .execute(executor)
+    .await
+    .map(|r| r.rows_affected() > 0)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn log_delivery(
+  executor: impl sqlx::PgExecutor<'_>,
+  webhook: i32,
+  event: &str,
+  model: &str,
+  attempts: i32,
+  status: Option<i32>,
+  error: Option<&str>,
+  delivered: bool,
+) -> Result<()> {
+  sqlx::query(
+    "
+    INSERT INTO model_webhook_deliveries (webhook, event, model, attempts, status, error, delivered)
+      VALUES ($1, $2, $3, $4, $5, $6, $7)
+    ",
+  )
+  .bind(webhook)
+  .bind(event)
+  .bind(model)
+  .bind(attempts)
+  .bind(status)
+  .bind(error)
+  .bind(delivered)
+  .execute(executor)
+  .await?;
+  Ok(())
+}
+
+/// Most recent deliveries of `webhook`
+pub async fn get_deliveries(executor: impl sqlx::PgExecutor<'_>, webhook: i32, limit: i32) -> Result<Vec<Delivery>> {
+  sqlx::query_as::<_, Delivery>(
+    "
+    SELECT * FROM model_webhook_deliveries
+      WHERE webhook = $1
+      ORDER BY created_at DESC, id DESC
+      LIMIT $2
+    ",
+  )
+  .bind(webhook)
+  .bind(limit)
+  .fetch_all(executor)
+  .await
+}
@@ -0,0 +1,62 @@
+#![cfg(feature = "test-harness")]
+
+use chrono::{Duration, Utc};
+use db::{accounts, channel_admins, generations, testing::TestDatabase};
+
+#[actix_web::test]
+async fn deletion_waits_for_the_grace_period() {
+  let db = TestDatabase::new().await.unwrap();
+  let user = db::users::get_or_create(db.pool(), "a", None).await.unwrap();
+  db::users::get_or_create(db.pool(), "forsen", None).await.unwrap();
+  db::tokens::create(db.pool(), user.id(), "scs", "access", "refresh")
+    .await
+    .unwrap();
+  let generation = generations::create(db.pool(), user.id(), "model", "seed", "text")
+    .await
+    .unwrap();
+  generations::create_share(db.pool(), generation.id(), "link", Utc::now() + Duration::hours(1))
+    .await
+    .unwrap();
+  channel_admins::grant(db.pool(), user.id(), "forsen").await.unwrap();
+
+  let export = accounts::export(db.pool(), user.id()).await.unwrap();
+  assert_eq!(export.username, "a");
+  assert_eq!(export.active_tokens, 1);
+  assert_eq!(export.generations.len(), 1);
+  assert_eq!(export.shares.len(), 1);
+  assert_eq!(export.admin_of, vec!["forsen"]);
+  assert!(export.deletions.is_empty());
+
+  // A pending deletion is returned unchanged when it's requested again
+  let later = accounts::request_deletion(db.pool(), user.id(), Utc::now() + Duration::days(7))
+    .await
+    .unwrap();
+  let again = accounts::request_deletion(db.pool(), user.id(), Utc::now())
+    .await
+    .unwrap();
+  assert_eq!(again.id(), later.id());
+  assert!(accounts::delete_due(db.pool()).await.unwrap().is_empty());
+
+  assert!(accounts::cancel_deletion(db.pool(), user.id()).await.unwrap());
+  assert!(!accounts::cancel_deletion(db.pool(), user.id()).await.unwrap());
+  assert!(accounts::pending_deletion(db.pool(), user.id())
+    .await
+    .unwrap()
+    .is_none());
+
+  accounts::request_deletion(db.pool(), user.id(), Utc::now() - Duration::seconds(1))
+    .await
+    .unwrap();
+  assert_eq!(accounts::delete_due(db.pool()).await.unwrap(), vec![user.id()]);
+
+  let export = accounts::export(db.pool(), user.id()).await.unwrap();
+  assert_eq!(export.active_tokens, 0);
+  assert!(export.generations.is_empty());
+  assert!(export.shares.is_empty());
+  assert!(export.admin_of.is_empty());
+  // Both requests are kept as an audit trail
+  assert_eq!(export.deletions.len(), 2);
+  assert!(export.deletions[0].cancelled_at().is_some());
+  assert!(export.deletions[1].completed_at().is_some());
+  assert!(!db::tokens::verify(db.pool(), user.id(), "scs").await.unwrap());
+}
@@ -0,0 +1,41 @@
+#![cfg(feature = "test-harness")]
+
+use db::{channel_admins, testing::TestDatabase};
+
+#[actix_web::test]
+async fn grant_and_revoke() {
+  let db = TestDatabase::new().await.unwrap();
+  let user = db::users::get_or_create(db.pool(), "a", None).await.unwrap();
+  db::users::get_or_create(db.pool(), "test_channel", None).await.unwrap();
+
+  assert!(!channel_admins::is_channel_admin(db.pool(), user.id(), "test_channel")
+    .await
+    .unwrap());
+  assert!(channel_admins::grant(db.pool(), user.id(), "test_channel")
+    .await
+    .unwrap());
+  assert!(!channel_admins::grant(db.pool(), user.id(), "test_channel")
+    .await
+    .unwrap());
+  assert!(channel_admins::is_channel_admin(db.pool(), user.id(), "test_channel")
+    .await
+    .unwrap());
+  assert!(!channel_admins::is_channel_admin(db.pool(), user.id(), "other_channel")
+    .await
+    .unwrap());
+
+  // Unknown channels can't be granted
+  assert!(!channel_admins::grant(db.pool(), user.id(), "other_channel")
+    .await
+    .unwrap());
+
+  assert!(channel_admins::revoke(db.pool(), user.id(), "test_channel")
+    .await
+    .unwrap());
+  assert!(!channel_admins::revoke(db.pool(), user.id(), "test_channel")
+    .await
+    .unwrap());
+  assert!(!channel_admins::is_channel_admin(db.pool(), user.id(), "test_channel")
+    .await
+    .unwrap());
+}
@@ -0,0 +1,28 @@
+#![cfg(feature = "test-harness")]
+
+use chrono::{TimeZone, Utc};
+use db::{channels, logs, resolver::UserResolver, testing::TestDatabase};
+use std::num::NonZeroUsize;
+
+#[actix_web::test]
+async fn channels_version_changes_with_new_messages() {
+  let db = TestDatabase::new().await.unwrap();
+  let mut resolver = UserResolver::new(NonZeroUsize::new(10).unwrap());
+  let channel = resolver.resolve_channel(db.pool(), "test_channel").await.unwrap();
+
+  let empty = channels::get_logged_channels_version(db.pool()).await.unwrap();
+  assert_eq!(empty.channel_count(), 1);
+  assert_eq!(empty.message_count(), 0);
+  assert_eq!(empty.last_message_at(), None);
+  assert_eq!(channels::get_logged_channels_version(db.pool()).await.unwrap(), empty);
+
+  let sent_at = Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap();
+  let mut soa = logs::SOAEntry::new(1);
+  soa.add(channel, "chatter".into(), sent_at, "message".into());
+  logs::insert_soa(db.pool(), &mut soa).await.unwrap();
+
+  let version = channels::get_logged_channels_version(db.pool()).await.unwrap();
+  assert_ne!(version, empty);
+  assert_eq!(version.message_count(), 1);
+  assert_eq!(version.last_message_at(), Some(sent_at));
+}
@@ -0,0 +1,56 @@
+#![cfg(feature = "test-harness")]
+
+use db::{
+  chatter_stats,
+  testing::{at, TestDatabase},
+};
+
+async fn setup(messages: &[(&str, u32, &str)]) -> TestDatabase {
+  let (db, channel) = TestDatabase::with_channel("test_channel").await.unwrap();
+  db.insert_messages(channel, messages).await.unwrap();
+  db
+}
+
+fn terms(counts: &[chatter_stats::TermCount]) -> Vec<(&str, i64)> {
+  counts.iter().map(|c| (c.term().as_str(), c.count())).collect()
+}
+
+#[actix_web::test]
+async fn counts_words_bigrams_and_lengths() {
+  let db = setup(&[
+    ("a", 0, "yo chat"),
+    ("a", 1, "yo chat yo"),
+    ("a", 2, "  Kappa  "),
+    ("b", 3, "yo yo yo yo"),
+    ("a", 10, "outside the range"),
+  ])
+  .await;
+
+  let stats = chatter_stats::fetch(db.pool(), "test_channel", "a", at(0), at(10), 100, 10)
+    .await
+    .unwrap();
+  assert_eq!(stats.messages(), 3);
+  assert_eq!(stats.sampled_messages(), 3);
+  assert!(!stats.sampled());
+  assert_eq!(terms(stats.top_words()), vec![("yo", 3), ("chat", 2), ("Kappa", 1)]);
+  assert_eq!(terms(stats.top_bigrams()), vec![("yo chat", 2), ("chat yo", 1)]);
+  let lengths = stats
+    .lengths()
+    .iter()
+    .map(|b| (b.words(), b.messages()))
+    .collect::<Vec<_>>();
+  assert_eq!(lengths, vec![(1, 1), (2, 1), (3, 1)]);
+}
+
+#[actix_web::test]
+async fn samples_large_ranges() {
+  let db = setup(&[("a", 0, "one"), ("a", 1, "two"), ("a", 2, "three"), ("a", 3, "four")]).await;
+
+  let stats = chatter_stats::fetch(db.pool(), "test_channel", "a", at(0), at(10), 2, 1)
+    .await
+    .unwrap();
+  assert_eq!(stats.messages(), 4);
+  assert_eq!(stats.sampled_messages(), 2);
+  assert!(stats.sampled());
+  assert_eq!(stats.top_words().len(), 1);
+}
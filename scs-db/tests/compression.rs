@@ -0,0 +1,136 @@
+#![cfg(all(feature = "test-harness", feature = "compression"))]
+
+use db::{
+  chatter_stats, compression, logs, phrases, quotes,
+  resolver::UserResolver,
+  retention,
+  testing::{at, TestDatabase},
+};
+use std::num::NonZeroUsize;
+
+fn message(i: usize) -> String {
+  format!(
+    "message number {} from the chatter Kappa {}",
+    i,
+    ["yo", "chat", "LUL", "pog"][i % 4]
+  )
+}
+
+#[actix_web::test]
+async fn compressed_messages_are_decompressed_on_fetch() {
+  let db = TestDatabase::new().await.unwrap();
+  let mut resolver = UserResolver::new(NonZeroUsize::new(10).unwrap());
+  let channel = resolver.resolve_channel(db.pool(), "test_channel").await.unwrap();
+  let mut compressor = compression::Compressor::new();
+
+  // Too few messages to train a dictionary on, so they're stored as they are
+  let mut soa = logs::SOAEntry::new(10);
+  soa.add(channel, "a".into(), at(0), "uncompressed".into());
+  compression::insert_soa_with_resolver(db.pool(), &mut resolver, &mut compressor, &mut soa)
+    .await
+    .unwrap();
+  assert!(compression::latest_dictionary(db.pool(), channel)
+    .await
+    .unwrap()
+    .is_none());
+
+  // Zstandard needs several times more data than the size of the dictionary
+  let count = compression::MIN_SAMPLES * 10;
+  let mut soa = logs::SOAEntry::new(count);
+  for i in 0..count {
+    soa.add(channel, "a".into(), at(i as u32 + 1), message(i));
+  }
+  compression::insert_soa_with_resolver(db.pool(), &mut resolver, &mut compressor, &mut soa)
+    .await
+    .unwrap();
+  assert!(compression::latest_dictionary(db.pool(), channel)
+    .await
+    .unwrap()
+    .is_some());
+
+  let stored =
+    sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM twitch_logs WHERE message = '' AND message_zstd IS NOT NULL")
+      .fetch_one(db.pool())
+      .await
+      .unwrap();
+  assert_eq!(stored, count as i64);
+
+  let entries = logs::fetch_logs_paged_with_usernames(db.pool(), "test_channel", None, None, 3, None, false)
+    .await
+    .unwrap();
+  let messages = entries.iter().map(|e| e.message()).collect::<Vec<_>>();
+  assert_eq!(
+    messages,
+    vec![message(count - 1), message(count - 2), message(count - 3)]
+  );
+
+  let mut expired = retention::delete_expired(db.pool(), channel, at(3), 10).await.unwrap();
+  compression::decompress(db.pool(), &mut expired).await.unwrap();
+  let messages = expired.iter().map(|e| e.message().as_str()).collect::<Vec<_>>();
+  assert_eq!(messages, vec!["uncompressed".to_string(), message(0), message(1)]);
+}
+
+#[actix_web::test]
+async fn compressed_messages_are_searched_and_redacted() {
+  let db = TestDatabase::new().await.unwrap();
+  let mut resolver = UserResolver::new(NonZeroUsize::new(10).unwrap());
+  let channel = resolver.resolve_channel(db.pool(), "test_channel").await.unwrap();
+  let mut compressor = compression::Compressor::new();
+  let count = compression::MIN_SAMPLES * 10;
+  let mut soa = logs::SOAEntry::new(count);
+  for i in 0..count {
+    soa.add(channel, "a".into(), at(i as u32), message(i));
+  }
+  compression::insert_soa_with_resolver(db.pool(), &mut resolver, &mut compressor, &mut soa)
+    .await
+    .unwrap();
+
+  // Only the messages ending with `pog` match, so the search has to skip pages of compressed ones
+  let entries = logs::fetch_logs_paged(db.pool(), "test_channel", None, Some("Kappa pog"), 5, None, false)
+    .await
+    .unwrap();
+  let messages = entries.iter().map(|e| e.message()).collect::<Vec<_>>();
+  assert_eq!(
+    messages,
+    (1..=5).map(|i| message(count - 4 * i + 3)).collect::<Vec<_>>()
+  );
+
+  let quote = quotes::random(db.pool(), "test_channel", "a", Some("KAPPA POG"))
+    .await
+    .unwrap()
+    .unwrap();
+  assert!(quote.message().ends_with("Kappa pog"));
+
+  let counts = phrases::count_by_month(db.pool(), "kappa pog", None, None, at(0), at(count as u32))
+    .await
+    .unwrap();
+  assert_eq!(counts.len(), 1);
+  assert_eq!(counts[0].exact(), 0);
+  assert_eq!(counts[0].case_insensitive(), count as i64 / 4);
+
+  let stats = chatter_stats::fetch(db.pool(), "test_channel", "a", at(0), at(count as u32), count as i64, 2)
+    .await
+    .unwrap();
+  assert_eq!(stats.sampled_messages(), count as i64);
+  let words = stats
+    .top_words()
+    .iter()
+    .map(|c| (c.term().as_str(), c.count()))
+    .collect::<Vec<_>>();
+  assert_eq!(words, vec![("Kappa", count as i64), ("chatter", count as i64)]);
+  let lengths = stats
+    .lengths()
+    .iter()
+    .map(|b| (b.words(), b.messages()))
+    .collect::<Vec<_>>();
+  assert_eq!(lengths, vec![(8, count as i64)]);
+
+  let redacted = logs::redact_by_pattern(db.pool(), "test_channel", None, "Kappa pog", channel)
+    .await
+    .unwrap();
+  assert_eq!(redacted, count as u64 / 4);
+  let entries = logs::fetch_logs_paged(db.pool(), "test_channel", None, Some("pog"), 5, None, false)
+    .await
+    .unwrap();
+  assert!(entries.is_empty());
+}
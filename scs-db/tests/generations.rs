@@ -0,0 +1,145 @@
+#![cfg(feature = "test-harness")]
+
+use chrono::{Duration, Utc};
+use db::{
+  generations::{self, HistoryFilter},
+  pagination::{next_cursor, Cursor},
+  testing::TestDatabase,
+};
+
+#[actix_web::test]
+async fn shares_expire() {
+  let db = TestDatabase::new().await.unwrap();
+  let user = db::users::get_or_create(db.pool(), "a", None).await.unwrap();
+  let generation = generations::create(db.pool(), user.id(), "model", "seed", "seed text")
+    .await
+    .unwrap();
+
+  generations::create_share(db.pool(), generation.id(), "live", Utc::now() + Duration::hours(1))
+    .await
+    .unwrap();
+  generations::create_share(db.pool(), generation.id(), "expired", Utc::now() - Duration::hours(1))
+    .await
+    .unwrap();
+
+  let shared = generations::get_shared(db.pool(), "live").await.unwrap().unwrap();
+  assert_eq!(shared.id(), generation.id());
+  assert_eq!(shared.text(), "seed text");
+  assert!(generations::get_shared(db.pool(), "expired").await.unwrap().is_none());
+  assert!(generations::get_shared(db.pool(), "unknown").await.unwrap().is_none());
+
+  assert_eq!(generations::delete_expired_shares(db.pool()).await.unwrap(), 1);
+  assert!(generations::get_shared(db.pool(), "live").await.unwrap().is_some());
+}
+
+#[actix_web::test]
+async fn usage_is_aggregated_by_model() {
+  let db = TestDatabase::new().await.unwrap();
+  let a = db::users::get_or_create(db.pool(), "a", None).await.unwrap();
+  let b = db::users::get_or_create(db.pool(), "b", None).await.unwrap();
+  assert_eq!(generations::latest_id(db.pool()).await.unwrap(), None);
+  generations::create(db.pool(), a.id(), "forsen", "", "1").await.unwrap();
+  generations::create(db.pool(), a.id(), "forsen", "", "2").await.unwrap();
+  let last = generations::create(db.pool(), b.id(), "forsen", "", "3").await.unwrap();
+  let latest = generations::create(db.pool(), b.id(), "xqc", "", "4").await.unwrap();
+  assert_eq!(generations::latest_id(db.pool()).await.unwrap(), Some(latest.id()));
+
+  let models = ["forsen".to_owned(), "xqc".to_owned(), "unused".to_owned()];
+  let mut usage = generations::usage_by_model(db.pool(), &models).await.unwrap();
+  usage.sort_by(|a, b| a.model().cmp(b.model()));
+  assert_eq!(usage.len(), 2);
+  assert_eq!(usage[0].model(), "forsen");
+  assert_eq!(usage[0].generations(), 3);
+  assert_eq!(usage[0].users(), 2);
+  assert_eq!(usage[0].last_generated_at(), last.created_at());
+  assert_eq!(usage[1].model(), "xqc");
+  assert_eq!(usage[1].generations(), 1);
+  assert_eq!(usage[1].users(), 1);
+
+  let usage = generations::usage_by_model(db.pool(), &models[1..]).await.unwrap();
+  assert_eq!(usage.len(), 1);
+  assert_eq!(usage[0].model(), "xqc");
+}
+
+#[actix_web::test]
+async fn history_is_filtered_and_paginated() {
+  let db = TestDatabase::new().await.unwrap();
+  let a = db::users::get_or_create(db.pool(), "a", None).await.unwrap();
+  let b = db::users::get_or_create(db.pool(), "b", None).await.unwrap();
+  let mut ids = Vec::new();
+  for (i, model) in ["forsen", "xqc", "forsen", "forsen"].iter().enumerate() {
+    ids.push(
+      generations::create(db.pool(), a.id(), model, "", &i.to_string())
+        .await
+        .unwrap()
+        .id(),
+    );
+  }
+  generations::create(db.pool(), b.id(), "forsen", "", "other user")
+    .await
+    .unwrap();
+  generations::set_favorite(db.pool(), a.id(), ids[0], true)
+    .await
+    .unwrap()
+    .unwrap();
+  // generations of other users can't be marked
+  assert!(generations::set_favorite(db.pool(), b.id(), ids[1], true)
+    .await
+    .unwrap()
+    .is_none());
+
+  let texts = |page: &[generations::Generation]| page.iter().map(|g| g.text().clone()).collect::<Vec<_>>();
+  let filter = HistoryFilter {
+    models: vec![String::from("forsen")],
+    ..HistoryFilter::default()
+  };
+  let first = generations::history(db.pool(), a.id(), &filter, 2, None).await.unwrap();
+  assert_eq!(texts(&first), vec!["3", "2"]);
+  let cursor = Cursor::parse(next_cursor(&first).as_deref()).unwrap();
+  let second = generations::history(db.pool(), a.id(), &filter, 2, cursor)
+    .await
+    .unwrap();
+  assert_eq!(texts(&second), vec!["0"]);
+
+  let favorites = HistoryFilter {
+    favorites_only: true,
+    ..HistoryFilter::default()
+  };
+  let page = generations::history(db.pool(), a.id(), &favorites, 10, None)
+    .await
+    .unwrap();
+  assert_eq!(texts(&page), vec!["0"]);
+  assert!(page[0].favorite());
+
+  let until = HistoryFilter {
+    until: Some(first[0].created_at()),
+    since: Some(page[0].created_at()),
+    ..HistoryFilter::default()
+  };
+  let page = generations::history(db.pool(), a.id(), &until, 10, None).await.unwrap();
+  assert_eq!(texts(&page), vec!["2", "1", "0"]);
+}
+
+#[actix_web::test]
+async fn bulk_delete_only_deletes_own_generations() {
+  let db = TestDatabase::new().await.unwrap();
+  let a = db::users::get_or_create(db.pool(), "a", None).await.unwrap();
+  let b = db::users::get_or_create(db.pool(), "b", None).await.unwrap();
+  let mine = generations::create(db.pool(), a.id(), "forsen", "", "mine")
+    .await
+    .unwrap();
+  let theirs = generations::create(db.pool(), b.id(), "forsen", "", "theirs")
+    .await
+    .unwrap();
+  generations::create_share(db.pool(), mine.id(), "link", Utc::now() + Duration::hours(1))
+    .await
+    .unwrap();
+
+  let deleted = generations::delete_many(db.pool(), a.id(), &[mine.id(), theirs.id(), -1])
+    .await
+    .unwrap();
+  assert_eq!(deleted, vec![mine.id()]);
+  assert!(generations::get(db.pool(), mine.id()).await.unwrap().is_none());
+  assert!(generations::get(db.pool(), theirs.id()).await.unwrap().is_some());
+  assert!(generations::get_shared(db.pool(), "link").await.unwrap().is_none());
+}
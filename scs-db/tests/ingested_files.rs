@@ -0,0 +1,36 @@
+#![cfg(feature = "test-harness")]
+
+use db::{ingested_files, testing::TestDatabase};
+
+#[actix_web::test]
+async fn files_are_recorded_once() {
+  let (db, channel) = TestDatabase::with_channel("test_channel").await.unwrap();
+
+  assert!(ingested_files::find(db.pool(), "abc").await.unwrap().is_none());
+  assert!(
+    ingested_files::record(db.pool(), "abc", "logs/test_channel-2023-01-01.log", channel, 10)
+      .await
+      .unwrap()
+  );
+  // The same contents under another name are a re-import
+  assert!(
+    !ingested_files::record(db.pool(), "abc", "old/test_channel-2023-01-01.log", channel, 10)
+      .await
+      .unwrap()
+  );
+  assert!(
+    ingested_files::record(db.pool(), "def", "logs/test_channel-2023-01-02.log", channel, 5)
+      .await
+      .unwrap()
+  );
+
+  let file = ingested_files::find(db.pool(), "abc").await.unwrap().unwrap();
+  assert_eq!(file.original_path(), "logs/test_channel-2023-01-01.log");
+  assert_eq!(file.channel(), "test_channel");
+  assert_eq!(file.rows_inserted(), 10);
+
+  let files = ingested_files::fetch_by_channel(db.pool(), "test_channel")
+    .await
+    .unwrap();
+  assert_eq!(files.len(), 2);
+}
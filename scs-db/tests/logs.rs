@@ -0,0 +1,314 @@
+#![cfg(feature = "test-harness")]
+
+use db::{
+  logs,
+  pagination::{Cursor, Keyed},
+  resolver::UserResolver,
+  testing::{at, TestDatabase},
+};
+use std::{collections::BTreeMap, num::NonZeroUsize};
+
+async fn fetch(
+  db: &TestDatabase,
+  chatter: Option<&str>,
+  pattern: Option<&str>,
+  limit: i32,
+  cursor: Option<Cursor>,
+) -> Vec<logs::ResolvedEntry> {
+  logs::fetch_logs_paged_with_usernames(db.pool(), "test_channel", chatter, pattern, limit, cursor, false)
+    .await
+    .unwrap()
+}
+
+#[actix_web::test]
+async fn insert_soa_creates_missing_chatters() {
+  let (db, channel) = TestDatabase::with_channel("test_channel").await.unwrap();
+  insert(
+    &db,
+    channel,
+    &[("a", 0, "first"), ("b", 1, "second"), ("a", 2, "third")],
+  )
+  .await;
+
+  let entries = fetch(&db, None, None, 10, None).await;
+  let entries = entries
+    .iter()
+    .map(|e| (e.channel().as_str(), e.chatter().as_str(), e.message()))
+    .collect::<Vec<_>>();
+  assert_eq!(
+    entries,
+    vec![
+      ("test_channel", "a", "third"),
+      ("test_channel", "b", "second"),
+      ("test_channel", "a", "first"),
+    ]
+  );
+}
+
+#[actix_web::test]
+async fn insert_soa_with_existing_chatters_and_empty_batches() {
+  let (db, channel) = TestDatabase::with_channel("test_channel").await.unwrap();
+  db.insert_messages(channel, &[("a", 0, "first")]).await.unwrap();
+  db.insert_messages(channel, &[]).await.unwrap();
+  db.insert_messages(channel, &[("a", 1, "second")]).await.unwrap();
+
+  let mut soa = logs::SOAEntry::new(1);
+  soa.add(channel, "a".into(), at(2), "third".into());
+  logs::insert_soa(db.pool(), &mut soa).await.unwrap();
+  // the entry is cleared, so inserting it again is a no-op
+  logs::insert_soa(db.pool(), &mut soa).await.unwrap();
+
+  assert_eq!(fetch(&db, None, None, 10, None).await.len(), 3);
+}
+
+#[actix_web::test]
+async fn insert_one_is_visible_to_fetch() {
+  let (db, channel) = TestDatabase::with_channel("test_channel").await.unwrap();
+  let chatter = db::users::get_or_create(db.pool(), "a", None).await.unwrap();
+  logs::insert_one(
+    db.pool(),
+    &logs::Entry::new(channel, chatter.id(), at(0), "hello".into()),
+  )
+  .await
+  .unwrap();
+
+  let entries = logs::fetch_logs_paged(db.pool(), "test_channel", None, None, 10, None, false)
+    .await
+    .unwrap();
+  assert_eq!(entries.len(), 1);
+  assert_eq!(*entries[0].channel(), channel);
+  assert_eq!(*entries[0].chatter(), chatter.id());
+  assert!(entries[0].is_valid());
+}
+
+/// Raw log files are transferred by the ingester in a transaction, which also records the file
+#[actix_web::test]
+async fn transfer_raw_logs() {
+  let (db, channel) = TestDatabase::with_channel("test_channel").await.unwrap();
+  let mut resolver = UserResolver::new(NonZeroUsize::new(10).unwrap());
+
+  let mut soa = logs::SOAEntry::new(1);
+  soa.add(channel, "a".into(), at(0), "rolled back".into());
+  let mut tx = db.pool().begin().await.unwrap();
+  let mode = logs::InsertMode::Resolved {
+    resolver: &mut resolver,
+  };
+  logs::insert_batch_with(db.pool(), &mut *tx, mode, &mut soa)
+    .await
+    .unwrap();
+  tx.rollback().await.unwrap();
+  assert!(fetch(&db, None, None, 10, None).await.is_empty());
+
+  // The chatter was created outside of the transaction, so the resolver's cached ID is still valid
+  soa.add(channel, "a".into(), at(1), "committed".into());
+  let mut tx = db.pool().begin().await.unwrap();
+  let mode = logs::InsertMode::Resolved {
+    resolver: &mut resolver,
+  };
+  logs::insert_batch_with(db.pool(), &mut *tx, mode, &mut soa)
+    .await
+    .unwrap();
+  tx.commit().await.unwrap();
+
+  let entries = fetch(&db, None, None, 10, None).await;
+  let entries = entries
+    .iter()
+    .map(|e| (e.chatter().as_str(), e.message()))
+    .collect::<Vec<_>>();
+  assert_eq!(entries, vec![("a", "committed")]);
+}
+
+#[actix_web::test]
+async fn pagination_visits_every_entry_once() {
+  let (db, channel) = TestDatabase::with_channel("test_channel").await.unwrap();
+  // entries with identical timestamps must still be ordered by their id
+  insert(
+    &db,
+    channel,
+    &[
+      ("a", 0, "0"),
+      ("a", 1, "1"),
+      ("b", 1, "2"),
+      ("a", 1, "3"),
+      ("b", 2, "4"),
+    ],
+  )
+  .await;
+
+  let mut seen = vec![];
+  let mut cursor = None;
+  loop {
+    let page = fetch(&db, None, None, 2, cursor).await;
+    assert!(page.len() <= 2);
+    match page.last() {
+      Some(last) => cursor = Some(last.cursor()),
+      None => break,
+    }
+    seen.extend(page.iter().map(|e| e.message().to_owned()));
+  }
+  assert_eq!(seen, vec!["4", "3", "2", "1", "0"]);
+}
+
+#[actix_web::test]
+async fn pagination_past_the_end_is_empty() {
+  let (db, channel) = TestDatabase::with_channel("test_channel").await.unwrap();
+  db.insert_messages(channel, &[("a", 0, "0"), ("a", 1, "1")])
+    .await
+    .unwrap();
+
+  let page = fetch(&db, None, None, 2, None).await;
+  assert_eq!(page.len(), 2);
+  let last = page.last().unwrap();
+  assert!(fetch(&db, None, None, 2, Some(last.cursor())).await.is_empty());
+  assert!(fetch(&db, None, None, 0, None).await.is_empty());
+}
+
+#[actix_web::test]
+async fn pagination_with_filters() {
+  let (db, channel) = TestDatabase::with_channel("test_channel").await.unwrap();
+  insert(
+    &db,
+    channel,
+    &[
+      ("a", 0, "yo"),
+      ("b", 1, "yo"),
+      ("a", 2, "nope"),
+      ("a", 3, "yoyo"),
+      ("a", 4, "y_o"),
+    ],
+  )
+  .await;
+
+  let page = fetch(&db, Some("a"), Some("yo"), 1, None).await;
+  assert_eq!(page[0].message(), "yoyo");
+  let last = page.last().unwrap();
+  let page = fetch(&db, Some("a"), Some("yo"), 10, Some(last.cursor())).await;
+  assert_eq!(page.iter().map(|e| e.message()).collect::<Vec<_>>(), vec!["yo"]);
+
+  // `_` is a single-character wildcard
+  let page = fetch(&db, None, Some("y_o"), 10, None).await;
+  assert_eq!(page.len(), 1);
+
+  assert!(fetch(&db, Some("nobody"), None, 10, None).await.is_empty());
+}
+
+#[actix_web::test]
+async fn chatter_logs_span_every_channel() {
+  let (db, channel) = TestDatabase::with_channel("test_channel").await.unwrap();
+  let other = UserResolver::new(NonZeroUsize::new(10).unwrap())
+    .resolve_channel(db.pool(), "other_channel")
+    .await
+    .unwrap();
+  db.insert_messages(channel, &[("a", 0, "0"), ("b", 1, "1"), ("a", 3, "3")])
+    .await
+    .unwrap();
+  db.insert_messages(other, &[("a", 2, "2"), ("a", 4, "4")])
+    .await
+    .unwrap();
+
+  let fetch_chatter =
+    |since, until, limit, cursor| logs::fetch_chatter_logs_paged(db.pool(), "a", since, until, limit, cursor, false);
+  let page = fetch_chatter(None, None, 3, None).await.unwrap();
+  assert_eq!(
+    page
+      .iter()
+      .map(|e| (e.channel().as_str(), e.message()))
+      .collect::<Vec<_>>(),
+    vec![("other_channel", "4"), ("test_channel", "3"), ("other_channel", "2")]
+  );
+  let last = page.last().unwrap();
+  let page = fetch_chatter(None, None, 3, Some(last.cursor())).await.unwrap();
+  assert_eq!(page.iter().map(|e| e.message()).collect::<Vec<_>>(), vec!["0"]);
+
+  let page = fetch_chatter(Some(at(2)), Some(at(4)), 10, None).await.unwrap();
+  assert_eq!(page.iter().map(|e| e.message()).collect::<Vec<_>>(), vec!["3", "2"]);
+}
+
+#[actix_web::test]
+async fn redacted_entries_are_skipped_by_cursor() {
+  let (db, channel) = TestDatabase::with_channel("test_channel").await.unwrap();
+  db.insert_messages(channel, &[("a", 0, "0"), ("a", 1, "1"), ("a", 2, "2")])
+    .await
+    .unwrap();
+  let admin = db::users::get_or_create(db.pool(), "admin", None).await.unwrap();
+
+  let entries = fetch(&db, None, None, 10, None).await;
+  logs::redact_by_id(db.pool(), &[entries[1].id()], admin.id())
+    .await
+    .unwrap();
+
+  let first = fetch(&db, None, None, 1, None).await;
+  let rest = fetch(&db, None, None, 10, Some(first[0].cursor())).await;
+  assert_eq!(rest.iter().map(|e| e.message()).collect::<Vec<_>>(), vec!["0"]);
+}
+
+#[actix_web::test]
+async fn insert_soa_stores_languages() {
+  let (db, channel) = TestDatabase::with_channel("test_channel").await.unwrap();
+  let mut soa = logs::SOAEntry::new(2);
+  soa.add_with_language(channel, "a".into(), at(0), "hello chat".into(), Some("eng".into()));
+  soa.add(channel, "a".into(), at(1), "LUL".into());
+  logs::insert_soa(db.pool(), &mut soa).await.unwrap();
+
+  let languages = sqlx::query_scalar::<_, Option<String>>("SELECT language FROM twitch_logs ORDER BY sent_at")
+    .fetch_all(db.pool())
+    .await
+    .unwrap();
+  assert_eq!(languages, vec![Some("eng".to_owned()), None]);
+}
+
+#[actix_web::test]
+async fn insert_soa_stores_sequences() {
+  let (db, channel) = TestDatabase::with_channel("test_channel").await.unwrap();
+  let mut resolver = UserResolver::new(NonZeroUsize::new(10).unwrap());
+  let mut soa = logs::SOAEntry::new(2);
+  soa.add_with_sequence(channel, "a".into(), at(0), "first".into(), None, Some(41));
+  soa.add(channel, "a".into(), at(1), "second".into());
+  logs::insert_soa_with_resolver(db.pool(), &mut resolver, &mut soa)
+    .await
+    .unwrap();
+
+  let seqs = sqlx::query_scalar::<_, Option<i64>>("SELECT seq FROM twitch_logs ORDER BY sent_at")
+    .fetch_all(db.pool())
+    .await
+    .unwrap();
+  assert_eq!(seqs, vec![Some(41), None]);
+}
+
+#[actix_web::test]
+async fn insert_batch_modes_insert_the_same_rows() {
+  let (db, channel) = TestDatabase::with_channel("test_channel").await.unwrap();
+  let mut resolver = UserResolver::new(NonZeroUsize::new(10).unwrap());
+
+  let mut soa = logs::SOAEntry::new(2);
+  soa.add(channel, "a".into(), at(0), "joined".into());
+  soa.add(channel, "b".into(), at(1), "joined".into());
+  let stats = logs::insert_batch(db.pool(), logs::InsertMode::Joined, &mut soa)
+    .await
+    .unwrap();
+  assert_eq!(stats.rows, 2);
+  let counts = stats.inserted.iter().map(|(key, day)| (*key, day.count));
+  assert_eq!(
+    counts.collect::<BTreeMap<_, _>>(),
+    BTreeMap::from([((channel, at(0).date_naive()), 2)])
+  );
+  assert!(soa.is_empty());
+
+  soa.add(channel, "a".into(), at(2), "resolved".into());
+  soa.add(channel, "c".into(), at(3), "resolved".into());
+  let mode = logs::InsertMode::Resolved {
+    resolver: &mut resolver,
+  };
+  let stats = logs::insert_batch(db.pool(), mode, &mut soa).await.unwrap();
+  assert_eq!(stats.rows, 2);
+
+  let entries = fetch(&db, None, None, 10, None).await;
+  let entries = entries
+    .iter()
+    .map(|e| (e.chatter().as_str(), e.message()))
+    .collect::<Vec<_>>();
+  assert_eq!(
+    entries,
+    vec![("c", "resolved"), ("a", "resolved"), ("b", "joined"), ("a", "joined")]
+  );
+}
@@ -0,0 +1,46 @@
+#![cfg(feature = "test-harness")]
+
+use chrono::{TimeZone, Utc};
+use db::{
+  logs::{self, SOAEntry},
+  notify::{NewLogs, NewLogsListener},
+  resolver::UserResolver,
+  testing::TestDatabase,
+};
+use std::num::NonZeroUsize;
+
+#[actix_web::test]
+async fn batch_inserts_notify_each_channel() {
+  let db = TestDatabase::new().await.unwrap();
+  let mut resolver = UserResolver::new(NonZeroUsize::new(10).unwrap());
+  let a = resolver.resolve_channel(db.pool(), "channel_a").await.unwrap();
+  let b = resolver.resolve_channel(db.pool(), "channel_b").await.unwrap();
+  let mut listener = NewLogsListener::connect(db.pool()).await.unwrap();
+
+  let sent_at = Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap();
+  let mut entry = SOAEntry::new(3);
+  entry.add(a, "chatter".into(), sent_at, "1".into());
+  entry.add(b, "chatter".into(), sent_at, "2".into());
+  entry.add(a, "chatter".into(), sent_at, "3".into());
+  logs::insert_soa_with_resolver(db.pool(), &mut resolver, &mut entry)
+    .await
+    .unwrap();
+
+  let mut received = vec![listener.recv().await.unwrap(), listener.recv().await.unwrap()];
+  received.sort_by_key(|n| n.channel_id);
+  let summary = |n: &NewLogs| (n.channel.clone(), n.count, n.first_id <= n.last_id);
+  assert_eq!(summary(&received[0]), ("channel_a".into(), 2, true));
+  assert_eq!(summary(&received[1]), ("channel_b".into(), 1, true));
+  assert_eq!(received[1].first_id, received[1].last_id);
+
+  // An empty batch doesn't notify anyone
+  logs::insert_soa_with_resolver(db.pool(), &mut resolver, &mut entry)
+    .await
+    .unwrap();
+  entry.add(b, "chatter".into(), sent_at, "4".into());
+  logs::insert_soa_with_resolver(db.pool(), &mut resolver, &mut entry)
+    .await
+    .unwrap();
+  let next = listener.recv().await.unwrap();
+  assert_eq!((next.channel.as_str(), next.count), ("channel_b", 1));
+}
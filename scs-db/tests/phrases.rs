@@ -0,0 +1,68 @@
+#![cfg(feature = "test-harness")]
+
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use db::{logs, phrases, resolver::UserResolver, testing::TestDatabase};
+use std::num::NonZeroUsize;
+
+fn at(month: u32, day: u32) -> DateTime<Utc> {
+  Utc.with_ymd_and_hms(2023, month, day, 12, 0, 0).unwrap()
+}
+
+async fn setup(messages: &[(&str, &str, DateTime<Utc>, &str)]) -> TestDatabase {
+  let db = TestDatabase::new().await.unwrap();
+  let mut resolver = UserResolver::new(NonZeroUsize::new(10).unwrap());
+  let mut soa = logs::SOAEntry::new(messages.len());
+  for (channel, chatter, sent_at, message) in messages {
+    let channel = resolver.resolve_channel(db.pool(), channel).await.unwrap();
+    soa.add(channel, chatter.to_string(), *sent_at, message.to_string());
+  }
+  logs::insert_soa(db.pool(), &mut soa).await.unwrap();
+  db
+}
+
+fn counts(counts: &[phrases::PhraseCount]) -> Vec<(&str, NaiveDate, i64, i64)> {
+  counts
+    .iter()
+    .map(|c| (c.channel().as_str(), c.month(), c.exact(), c.case_insensitive()))
+    .collect()
+}
+
+fn month(month: u32) -> NaiveDate {
+  NaiveDate::from_ymd_opt(2023, month, 1).unwrap()
+}
+
+#[actix_web::test]
+async fn counts_exact_and_case_insensitive_matches_by_month() {
+  let db = setup(&[
+    ("a", "x", at(1, 1), "forsen Pog"),
+    ("a", "x", at(1, 20), "FORSEN pog"),
+    ("a", "y", at(2, 1), "hi forsen"),
+    ("a", "y", at(2, 2), "unrelated"),
+    ("b", "x", at(1, 5), "Forsen"),
+    ("b", "x", at(6, 1), "forsen, outside the range"),
+  ])
+  .await;
+
+  let all = phrases::count_by_month(db.pool(), "forsen", None, None, at(1, 1), at(6, 1))
+    .await
+    .unwrap();
+  assert_eq!(
+    counts(&all),
+    vec![("a", month(1), 1, 2), ("a", month(2), 1, 1), ("b", month(1), 0, 1)]
+  );
+
+  let filtered = phrases::count_by_month(db.pool(), "forsen", Some("a"), Some("x"), at(1, 1), at(6, 1))
+    .await
+    .unwrap();
+  assert_eq!(counts(&filtered), vec![("a", month(1), 1, 2)]);
+}
+
+#[actix_web::test]
+async fn matches_wildcards_literally() {
+  let db = setup(&[("a", "x", at(1, 1), "100% real"), ("a", "x", at(1, 2), "1000 real")]).await;
+
+  let all = phrases::count_by_month(db.pool(), "100%", None, None, at(1, 1), at(2, 1))
+    .await
+    .unwrap();
+  assert_eq!(counts(&all), vec![("a", month(1), 1, 1)]);
+}
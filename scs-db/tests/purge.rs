@@ -0,0 +1,81 @@
+#![cfg(feature = "test-harness")]
+
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use db::{channels, logs, purge, resolver::UserResolver, retention, testing::TestDatabase};
+use std::num::NonZeroUsize;
+
+fn on(day: u32, second: u32) -> DateTime<Utc> {
+  Utc.with_ymd_and_hms(2023, 1, day, 0, 0, second).unwrap()
+}
+
+#[actix_web::test]
+async fn purge_channel_deletes_logs_before_a_date_in_batches() {
+  let db = TestDatabase::new().await.unwrap();
+  let mut resolver = UserResolver::new(NonZeroUsize::new(10).unwrap());
+  let channel = resolver.resolve_channel(db.pool(), "test_channel").await.unwrap();
+  let other = resolver.resolve_channel(db.pool(), "other_channel").await.unwrap();
+
+  let mut soa = logs::SOAEntry::new(7);
+  for second in 0..5 {
+    soa.add(
+      channel,
+      "old_chatter".into(),
+      on(1, second),
+      format!("message {second}"),
+    );
+  }
+  soa.add(channel, "new_chatter".into(), on(2, 0), "kept".into());
+  soa.add(other, "old_chatter".into(), on(1, 0), "other".into());
+  logs::insert_soa(db.pool(), &mut soa).await.unwrap();
+
+  let mut reports = vec![];
+  let before = NaiveDate::from_ymd_opt(2023, 1, 2);
+  let result = purge::purge_channel(db.pool(), channel, before, 2, |progress| {
+    reports.push(progress.deleted())
+  })
+  .await
+  .unwrap();
+  assert_eq!(result.deleted(), 5);
+  assert_eq!(result.batches(), 3);
+  assert_eq!(result.last_sent_at(), Some(on(1, 4)));
+  assert_eq!(reports, vec![2, 4, 5]);
+
+  let stats = channels::get_logged_channels_with_stats(db.pool()).await.unwrap();
+  let stats_of = |name: &str| stats.iter().find(|c| c.name() == name).unwrap();
+  assert_eq!(stats_of("test_channel").message_count(), 1);
+  assert_eq!(stats_of("test_channel").chatter_count(), 1);
+  assert_eq!(stats_of("test_channel").first_message_at(), Some(on(2, 0)));
+  assert_eq!(stats_of("test_channel").last_message_at(), Some(on(2, 0)));
+  // other channels are untouched
+  assert_eq!(stats_of("other_channel").message_count(), 1);
+  assert_eq!(stats_of("other_channel").chatter_count(), 1);
+}
+
+#[actix_web::test]
+async fn purge_channel_without_a_date_deletes_everything() {
+  let db = TestDatabase::new().await.unwrap();
+  let mut resolver = UserResolver::new(NonZeroUsize::new(10).unwrap());
+  let channel = resolver.resolve_channel(db.pool(), "test_channel").await.unwrap();
+
+  let mut soa = logs::SOAEntry::new(3);
+  for day in 1..=3 {
+    soa.add(channel, "chatter".into(), on(day, 0), format!("message {day}"));
+  }
+  logs::insert_soa(db.pool(), &mut soa).await.unwrap();
+
+  let result = purge::purge_channel(db.pool(), channel, None, 10, |_| ())
+    .await
+    .unwrap();
+  assert_eq!(result.deleted(), 3);
+  assert_eq!(result.batches(), 1);
+  assert_eq!(
+    retention::count_expired(db.pool(), channel, on(31, 0)).await.unwrap(),
+    0
+  );
+
+  let stats = channels::get_logged_channels_with_stats(db.pool()).await.unwrap();
+  assert_eq!(stats[0].message_count(), 0);
+  assert_eq!(stats[0].chatter_count(), 0);
+  assert_eq!(stats[0].first_message_at(), None);
+  assert_eq!(stats[0].last_message_at(), None);
+}
@@ -0,0 +1,78 @@
+#![cfg(feature = "test-harness")]
+
+use db::{
+  logs, quotes,
+  resolver::UserResolver,
+  testing::{at, TestDatabase},
+};
+use std::num::NonZeroUsize;
+
+async fn setup() -> TestDatabase {
+  let db = TestDatabase::new().await.unwrap();
+  let mut resolver = UserResolver::new(NonZeroUsize::new(10).unwrap());
+  let channel = resolver.resolve_channel(db.pool(), "test_channel").await.unwrap();
+  let other = resolver.resolve_channel(db.pool(), "other_channel").await.unwrap();
+  let mut soa = logs::SOAEntry::new(4);
+  soa.add(channel, "a".into(), at(0), "hello chat".into());
+  soa.add(channel, "a".into(), at(1), "100% Kappa".into());
+  soa.add(channel, "b".into(), at(2), "hello from b".into());
+  soa.add(other, "a".into(), at(3), "hello other channel".into());
+  logs::insert_soa(db.pool(), &mut soa).await.unwrap();
+  db
+}
+
+#[actix_web::test]
+async fn quotes_the_chatter_in_the_channel() {
+  let db = setup().await;
+
+  for _ in 0..10 {
+    let quote = quotes::random(db.pool(), "test_channel", "a", None)
+      .await
+      .unwrap()
+      .unwrap();
+    assert_eq!(quote.chatter(), "a");
+    assert_eq!(quote.channel(), "test_channel");
+  }
+  let quote = quotes::random(db.pool(), "test_channel", "a", Some("HELLO"))
+    .await
+    .unwrap()
+    .unwrap();
+  assert_eq!(quote.message(), "hello chat");
+  // Wildcards are matched literally
+  let quote = quotes::random(db.pool(), "test_channel", "a", Some("0%"))
+    .await
+    .unwrap()
+    .unwrap();
+  assert_eq!(quote.message(), "100% Kappa");
+  assert!(quotes::random(db.pool(), "test_channel", "a", Some("_"))
+    .await
+    .unwrap()
+    .is_none());
+  assert!(quotes::random(db.pool(), "test_channel", "c", None)
+    .await
+    .unwrap()
+    .is_none());
+}
+
+#[actix_web::test]
+async fn opted_out_chatters_are_not_quoted() {
+  let db = setup().await;
+
+  assert!(quotes::opt_out(db.pool(), "a").await.unwrap());
+  assert!(!quotes::opt_out(db.pool(), "a").await.unwrap());
+  assert!(quotes::random(db.pool(), "test_channel", "a", None)
+    .await
+    .unwrap()
+    .is_none());
+  assert!(quotes::random(db.pool(), "test_channel", "b", None)
+    .await
+    .unwrap()
+    .is_some());
+
+  assert!(quotes::opt_in(db.pool(), "a").await.unwrap());
+  assert!(!quotes::opt_in(db.pool(), "a").await.unwrap());
+  assert!(quotes::random(db.pool(), "test_channel", "a", None)
+    .await
+    .unwrap()
+    .is_some());
+}
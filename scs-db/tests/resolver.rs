@@ -0,0 +1,77 @@
+#![cfg(feature = "test-harness")]
+
+use db::{
+  resolver::{ResolverStats, UserResolver},
+  testing::TestDatabase,
+};
+use std::num::NonZeroUsize;
+
+fn names(names: &[&str]) -> Vec<String> {
+  names.iter().map(|v| v.to_string()).collect()
+}
+
+#[actix_web::test]
+async fn resolve_many_creates_missing_users_once() {
+  let db = TestDatabase::new().await.unwrap();
+  let existing = db::users::get_or_create(db.pool(), "existing", None).await.unwrap();
+
+  let mut resolver = UserResolver::new(NonZeroUsize::new(10).unwrap());
+  let ids = resolver
+    .resolve_many(db.pool(), &names(&["a", "existing", "a", "b"]))
+    .await
+    .unwrap();
+  assert_eq!(ids[1], existing.id());
+  assert_eq!(ids[0], ids[2]);
+  assert_ne!(ids[0], ids[3]);
+
+  // a fresh resolver has to go to the database, and must find the same users
+  let mut other = UserResolver::new(NonZeroUsize::new(10).unwrap());
+  assert_eq!(
+    other.resolve_many(db.pool(), &names(&["b", "a"])).await.unwrap(),
+    vec![ids[3], ids[0]]
+  );
+}
+
+#[actix_web::test]
+async fn resolve_many_uses_the_cache() {
+  let db = TestDatabase::new().await.unwrap();
+  let mut resolver = UserResolver::new(NonZeroUsize::new(2).unwrap());
+
+  resolver.resolve_many(db.pool(), &names(&["a", "b"])).await.unwrap();
+  resolver
+    .resolve_many(db.pool(), &names(&["a", "b", "a"]))
+    .await
+    .unwrap();
+  assert_eq!(
+    resolver.stats(),
+    ResolverStats {
+      hits: 3,
+      misses: 2,
+      size: 2
+    }
+  );
+
+  // evicts the least recently used username
+  let ids = resolver
+    .resolve_many(db.pool(), &names(&["c", "a", "b"]))
+    .await
+    .unwrap();
+  assert_eq!(ids.len(), 3);
+  assert_eq!(resolver.stats().size, 2);
+}
+
+#[actix_web::test]
+async fn resolve_channel_marks_chatters_as_channels() {
+  let db = TestDatabase::new().await.unwrap();
+  let mut resolver = UserResolver::new(NonZeroUsize::new(10).unwrap());
+
+  let ids = resolver.resolve_many(db.pool(), &names(&["a"])).await.unwrap();
+  let id = resolver.resolve_channel(db.pool(), "a").await.unwrap();
+  assert_eq!(ids[0], id);
+  assert_eq!(db::channels::get_logged_channels(db.pool()).await.unwrap(), vec!["a"]);
+
+  // cached as a channel now
+  let misses = resolver.stats().misses;
+  assert_eq!(resolver.resolve_channel(db.pool(), "a").await.unwrap(), id);
+  assert_eq!(resolver.stats().misses, misses);
+}
@@ -0,0 +1,75 @@
+#![cfg(feature = "test-harness")]
+
+use db::{
+  channels, logs,
+  resolver::UserResolver,
+  retention,
+  testing::{at, TestDatabase},
+};
+use std::num::NonZeroUsize;
+
+#[actix_web::test]
+async fn delete_expired_removes_the_oldest_logs_in_batches() {
+  let db = TestDatabase::new().await.unwrap();
+  let mut resolver = UserResolver::new(NonZeroUsize::new(10).unwrap());
+  let channel = resolver.resolve_channel(db.pool(), "test_channel").await.unwrap();
+  let other = resolver.resolve_channel(db.pool(), "other_channel").await.unwrap();
+
+  let mut soa = logs::SOAEntry::new(6);
+  for second in 0..5 {
+    soa.add(channel, "chatter".into(), at(second), format!("message {second}"));
+  }
+  soa.add(other, "chatter".into(), at(0), "other".into());
+  logs::insert_soa(db.pool(), &mut soa).await.unwrap();
+
+  assert_eq!(retention::count_expired(db.pool(), channel, at(3)).await.unwrap(), 3);
+
+  let batch = retention::delete_expired(db.pool(), channel, at(3), 2).await.unwrap();
+  assert_eq!(
+    batch.iter().map(|log| log.message().as_str()).collect::<Vec<_>>(),
+    vec!["message 0", "message 1"]
+  );
+  assert_eq!(batch[0].chatter(), "chatter");
+
+  let batch = retention::delete_expired(db.pool(), channel, at(3), 2).await.unwrap();
+  assert_eq!(batch.len(), 1);
+  assert!(retention::delete_expired(db.pool(), channel, at(3), 2)
+    .await
+    .unwrap()
+    .is_empty());
+
+  let stats = channels::get_logged_channels_with_stats(db.pool()).await.unwrap();
+  let stats_of = |name: &str| stats.iter().find(|c| c.name() == name).unwrap();
+  assert_eq!(stats_of("test_channel").message_count(), 2);
+  assert_eq!(stats_of("test_channel").first_message_at(), Some(at(3)));
+  // other channels are untouched
+  assert_eq!(stats_of("other_channel").message_count(), 1);
+  assert_eq!(retention::count_expired(db.pool(), other, at(3)).await.unwrap(), 1);
+}
+
+#[actix_web::test]
+async fn delete_expired_updates_the_chatters_and_message_times() {
+  let db = TestDatabase::new().await.unwrap();
+  let mut resolver = UserResolver::new(NonZeroUsize::new(10).unwrap());
+  let channel = resolver.resolve_channel(db.pool(), "test_channel").await.unwrap();
+
+  let mut soa = logs::SOAEntry::new(3);
+  soa.add(channel, "old_chatter".into(), at(0), "old".into());
+  soa.add(channel, "chatter".into(), at(1), "expired".into());
+  soa.add(channel, "chatter".into(), at(5), "kept".into());
+  logs::insert_soa(db.pool(), &mut soa).await.unwrap();
+
+  let stats = || async {
+    let stats = channels::get_logged_channels_with_stats(db.pool()).await.unwrap();
+    let stats = stats.into_iter().find(|c| c.name() == "test_channel").unwrap();
+    (stats.chatter_count(), stats.first_message_at(), stats.last_message_at())
+  };
+  assert_eq!(stats().await, (2, Some(at(0)), Some(at(5))));
+
+  retention::delete_expired(db.pool(), channel, at(3), 10).await.unwrap();
+  // `chatter` still has a message in the channel
+  assert_eq!(stats().await, (1, Some(at(5)), Some(at(5))));
+
+  retention::delete_expired(db.pool(), channel, at(10), 10).await.unwrap();
+  assert_eq!(stats().await, (0, None, None));
+}
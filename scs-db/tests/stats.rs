@@ -0,0 +1,27 @@
+#![cfg(feature = "test-harness")]
+
+use db::{allowlist, stats, testing::TestDatabase, tokens, users};
+
+#[actix_web::test]
+async fn counts_users_and_tokens() {
+  let db = TestDatabase::new().await.unwrap();
+  let a = users::get_or_create(db.pool(), "a", None).await.unwrap();
+  let b = users::get_or_create(db.pool(), "b", None).await.unwrap();
+  users::get_or_create(db.pool(), "c", None).await.unwrap();
+  allowlist::insert(db.pool(), &[a.id(), b.id()]).await.unwrap();
+  tokens::create(db.pool(), a.id(), "a1", "a1", "a1").await.unwrap();
+  tokens::create(db.pool(), a.id(), "a2", "a2", "a2").await.unwrap();
+
+  let counts = stats::user_counts(db.pool()).await.unwrap();
+  assert_eq!(counts.users(), 3);
+  assert_eq!(counts.allowlisted(), 2);
+  assert_eq!(counts.admins(), 0);
+  assert_eq!(counts.tokens(), 2);
+  assert_eq!(counts.users_with_tokens(), 1);
+
+  let tables = stats::estimated_row_counts(db.pool()).await.unwrap();
+  let names = tables.iter().map(|t| t.table_name().as_str()).collect::<Vec<_>>();
+  assert!(names.contains(&"twitch_logs"));
+  assert!(names.contains(&"tokens"));
+  assert!(!names.iter().any(|name| name.starts_with("_sqlx")));
+}
@@ -0,0 +1,109 @@
+#![cfg(feature = "test-harness")]
+
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use db::{logs, sqlx, testing::TestDatabase, transfer_audits};
+
+fn on(day: u32) -> DateTime<Utc> {
+  Utc.with_ymd_and_hms(2023, 1, day, 12, 0, 0).unwrap()
+}
+
+fn entry(channel: i32, days: &[u32]) -> logs::SOAEntry {
+  let mut soa = logs::SOAEntry::new(days.len());
+  for day in days {
+    soa.add(channel, "chatter".into(), on(*day), "message".into());
+  }
+  soa
+}
+
+#[actix_web::test]
+async fn transfers_are_verified_per_day() {
+  let (db, channel) = TestDatabase::with_channel("test_channel").await.unwrap();
+
+  let mut snapshot = transfer_audits::Snapshot::start();
+  // A header and the three messages
+  snapshot.read(4, 1);
+  let mut soa = entry(channel, &[1, 1, 2]);
+  snapshot.add(&soa);
+  snapshot.inserted(&logs::insert_soa(db.pool(), &mut soa).await.unwrap());
+  // Rows inserted by another writer during the transfer aren't counted
+  logs::insert_soa(db.pool(), &mut entry(channel, &[1])).await.unwrap();
+  let audit = transfer_audits::verify(db.pool(), "test_channel-2023-01-01.log", snapshot)
+    .await
+    .unwrap();
+  assert!(audit.is_ok());
+  assert_eq!(audit.source_lines(), 4);
+  assert_eq!(audit.skipped_lines(), 1);
+  assert_eq!(audit.expected_rows(), 3);
+  assert_eq!(audit.inserted_rows(), 3);
+
+  // Only part of the snapshot is inserted
+  let mut snapshot = transfer_audits::Snapshot::start();
+  snapshot.read(3, 0);
+  snapshot.add(&entry(channel, &[3, 3, 4]));
+  snapshot.inserted(&logs::insert_soa(db.pool(), &mut entry(channel, &[3])).await.unwrap());
+  let audit = transfer_audits::verify(db.pool(), "<stdin>", snapshot).await.unwrap();
+  assert!(!audit.is_ok());
+  assert_eq!(audit.expected_rows(), 3);
+  assert_eq!(audit.inserted_rows(), 1);
+  let mismatches = audit
+    .mismatches()
+    .iter()
+    .map(|m| (m.channel().as_str(), m.day(), m.expected(), m.inserted()))
+    .collect::<Vec<_>>();
+  assert_eq!(
+    mismatches,
+    vec![
+      ("test_channel", NaiveDate::from_ymd_opt(2023, 1, 3).unwrap(), 2, 1),
+      ("test_channel", NaiveDate::from_ymd_opt(2023, 1, 4).unwrap(), 1, 0),
+    ]
+  );
+
+  let recent = transfer_audits::fetch_recent(db.pool(), 10).await.unwrap();
+  assert_eq!(
+    recent.iter().map(|a| a.source().as_str()).collect::<Vec<_>>(),
+    vec!["<stdin>", "test_channel-2023-01-01.log"]
+  );
+  assert_eq!(recent[0].mismatches().len(), 2);
+  assert!(recent[1].is_ok());
+}
+
+#[actix_web::test]
+async fn rows_missing_from_the_table_are_mismatches() {
+  let (db, channel) = TestDatabase::with_channel("test_channel").await.unwrap();
+
+  let mut snapshot = transfer_audits::Snapshot::start();
+  snapshot.read(3, 0);
+  let mut soa = entry(channel, &[1, 1, 2]);
+  snapshot.add(&soa);
+  let inserted = logs::insert_soa(db.pool(), &mut soa).await.unwrap();
+  snapshot.inserted(&inserted);
+  // A row disappears before the transfer is verified
+  let first_day = inserted[&(channel, on(1).date_naive())];
+  sqlx::query("DELETE FROM twitch_logs WHERE id = $1")
+    .bind(first_day.first_id)
+    .execute(db.pool())
+    .await
+    .unwrap();
+  let audit = transfer_audits::verify(db.pool(), "<stdin>", snapshot).await.unwrap();
+  assert!(!audit.is_ok());
+  assert_eq!(audit.expected_rows(), 3);
+  assert_eq!(audit.inserted_rows(), 2);
+  let mismatches = audit
+    .mismatches()
+    .iter()
+    .map(|m| (m.day(), m.expected(), m.inserted()))
+    .collect::<Vec<_>>();
+  assert_eq!(mismatches, vec![(on(1).date_naive(), 2, 1)]);
+
+  // A line of the source is lost before it's parsed into a message
+  let mut snapshot = transfer_audits::Snapshot::start();
+  snapshot.read(2, 0);
+  let mut soa = entry(channel, &[3]);
+  snapshot.add(&soa);
+  snapshot.inserted(&logs::insert_soa(db.pool(), &mut soa).await.unwrap());
+  let audit = transfer_audits::verify(db.pool(), "<stdin>", snapshot).await.unwrap();
+  assert!(!audit.is_ok());
+  assert!(audit.mismatches().is_empty());
+  assert_eq!(audit.expected_rows(), 2);
+  assert_eq!(audit.inserted_rows(), 1);
+}
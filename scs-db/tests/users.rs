@@ -0,0 +1,65 @@
+#![cfg(feature = "test-harness")]
+
+use db::{
+  logs,
+  resolver::UserResolver,
+  testing::{at, TestDatabase},
+  users,
+};
+use std::num::NonZeroUsize;
+
+#[actix_web::test]
+async fn resolves_ids_and_usernames_in_bulk() {
+  let db = TestDatabase::new().await.unwrap();
+  let a = users::get_or_create(db.pool(), "a", None).await.unwrap();
+  let b = users::get_or_create(db.pool(), "b", None).await.unwrap();
+
+  let usernames = users::resolve_usernames(db.pool(), &[a.id(), b.id(), -1])
+    .await
+    .unwrap();
+  assert_eq!(usernames.len(), 2);
+  assert_eq!(usernames[&a.id()], "a");
+  assert_eq!(usernames[&b.id()], "b");
+
+  let ids = users::resolve_ids(db.pool(), &["b".into(), "unknown".into()])
+    .await
+    .unwrap();
+  assert_eq!(ids.len(), 1);
+  assert_eq!(ids["b"], b.id());
+  // usernames are matched ignoring case, and keyed as given
+  let ids = users::resolve_ids(db.pool(), &["B".into(), "a".into()]).await.unwrap();
+  assert_eq!(ids.len(), 2);
+  assert_eq!((ids["B"], ids["a"]), (b.id(), a.id()));
+  // unknown users aren't created
+  assert!(users::resolve_ids(db.pool(), &["unknown".into()])
+    .await
+    .unwrap()
+    .is_empty());
+}
+
+#[actix_web::test]
+async fn lists_chatters_by_id() {
+  let db = TestDatabase::new().await.unwrap();
+  let mut resolver = UserResolver::new(NonZeroUsize::new(10).unwrap());
+  let channel = resolver.resolve_channel(db.pool(), "channel").await.unwrap();
+  let silent = users::get_or_create(db.pool(), "silent", None).await.unwrap();
+
+  let mut soa = logs::SOAEntry::new(2);
+  soa.add(channel, "chatter".into(), at(2), "second".into());
+  soa.add(channel, "chatter".into(), at(1), "first".into());
+  logs::insert_soa(db.pool(), &mut soa).await.unwrap();
+
+  let first_page = users::list_chatters(db.pool(), None, 2).await.unwrap();
+  assert_eq!(first_page.len(), 2);
+  assert_eq!(first_page[0].username(), "channel");
+  assert!(first_page[0].is_logged_as_channel());
+  assert_eq!(first_page[1].username(), "silent");
+  assert_eq!(first_page[1].id(), silent.id());
+  assert_eq!(first_page[1].first_seen_at(), None);
+
+  let second_page = users::list_chatters(db.pool(), Some(silent.id()), 2).await.unwrap();
+  assert_eq!(second_page.len(), 1);
+  assert_eq!(second_page[0].username(), "chatter");
+  assert_eq!(second_page[0].first_seen_at(), Some(at(1)));
+  assert_eq!(second_page[0].last_seen_at(), Some(at(2)));
+}
@@ -0,0 +1,41 @@
+#![cfg(feature = "test-harness")]
+
+use chrono::{DateTime, TimeZone, Utc};
+use db::{testing::TestDatabase, viewers};
+
+fn at(minute: u32) -> DateTime<Utc> {
+  Utc.with_ymd_and_hms(2023, 1, 1, 0, minute, 0).unwrap()
+}
+
+fn snapshot(minute: u32, viewer_count: i32) -> viewers::Snapshot {
+  viewers::Snapshot {
+    channel: "test_channel".into(),
+    taken_at: at(minute),
+    live: viewer_count > 0,
+    viewer_count,
+    title: (viewer_count > 0).then(|| "title".into()),
+    game: (viewer_count > 0).then(|| "game".into()),
+  }
+}
+
+#[actix_web::test]
+async fn snapshots_are_inserted_once() {
+  let (db, channel) = TestDatabase::with_channel("test_channel").await.unwrap();
+
+  assert!(viewers::insert(db.pool(), channel, &snapshot(0, 0)).await.unwrap());
+  assert!(viewers::insert(db.pool(), channel, &snapshot(5, 42)).await.unwrap());
+  assert!(viewers::insert(db.pool(), channel, &snapshot(10, 50)).await.unwrap());
+  // Re-ingesting a snapshot keeps the original
+  assert!(!viewers::insert(db.pool(), channel, &snapshot(5, 1)).await.unwrap());
+
+  let snapshots = viewers::fetch(db.pool(), "test_channel", at(0), at(10)).await.unwrap();
+  assert_eq!(
+    snapshots
+      .iter()
+      .map(|s| (s.taken_at(), s.live(), s.viewer_count()))
+      .collect::<Vec<_>>(),
+    vec![(at(0), false, 0), (at(5), true, 42)]
+  );
+  assert_eq!(snapshots[0].title(), &None);
+  assert_eq!(snapshots[1].game().as_deref(), Some("game"));
+}
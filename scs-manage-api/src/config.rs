@@ -11,6 +11,12 @@ impl AccessToken {
   pub fn new_not_validated(token: String) -> Self {
     Self(token)
   }
+
+  /// Compares the token with `candidate` in constant time, so that it can't be guessed from how long it takes
+  pub fn matches(&self, candidate: &str) -> bool {
+    let (token, candidate) = (self.0.as_bytes(), candidate.as_bytes());
+    token.len() == candidate.len() && token.iter().zip(candidate).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+  }
 }
 impl std::borrow::Borrow<str> for AccessToken {
   fn borrow(&self) -> &str {
@@ -46,19 +52,68 @@ where
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(from = "RawConfig")]
 pub struct Config {
   pub project_source_folder: std::path::PathBuf,
   pub access_tokens: HashSet<AccessToken>,
-  #[serde(flatten)]
   pub compose: ComposeSettings,
+  pub cors: scs_config::cors::CorsSettings,
+  /// Enables the backup endpoints, see [`crate::backup`]
+  pub backups: Option<BackupSettings>,
+  /// Database to read the transfer audits from, defaults to the one of `backups`
+  database_url: Option<DatabaseUrl>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
-pub struct ComposeSettings {
+/// [`Config`] as it's written, with the [`ComposeSettings`] at the top level.
+///
+/// `#[serde(flatten)]` would let unknown fields through, since serde doesn't support it together with
+/// `deny_unknown_fields`, so the compose settings are listed here instead.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawConfig {
+  project_source_folder: std::path::PathBuf,
+  access_tokens: HashSet<AccessToken>,
   #[serde(alias = "compose_file")]
+  path: std::path::PathBuf,
+  #[serde(default = "ComposeSettings::default_profile")]
+  profile: String,
+  #[serde(default)]
+  cors: scs_config::cors::CorsSettings,
+  backups: Option<BackupSettings>,
+  database_url: Option<DatabaseUrl>,
+}
+
+impl From<RawConfig> for Config {
+  fn from(raw: RawConfig) -> Self {
+    Self {
+      project_source_folder: raw.project_source_folder,
+      access_tokens: raw.access_tokens,
+      compose: ComposeSettings {
+        path: raw.path,
+        profile: raw.profile,
+      },
+      cors: raw.cors,
+      backups: raw.backups,
+      database_url: raw.database_url,
+    }
+  }
+}
+
+#[derive(Clone, Deserialize)]
+struct DatabaseUrl(String);
+
+// The database URL usually contains a password
+impl std::fmt::Debug for DatabaseUrl {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.write_str("<redacted>")
+  }
+}
+
+/// Set at the top level of the config, see [`RawConfig`]
+#[derive(Debug, Clone)]
+pub struct ComposeSettings {
   pub path: std::path::PathBuf,
   /// The desired target profile from docker-compose.yml
-  #[serde(default = "ComposeSettings::default_profile")]
   pub profile: String,
 }
 
@@ -68,14 +123,68 @@ impl ComposeSettings {
   }
 }
 
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BackupSettings {
+  /// Where backups are written to, created if it doesn't exist
+  pub directory: std::path::PathBuf,
+  pub database_url: String,
+  /// Must be sent in the `X-Confirm-Restore` header to restore a backup
+  pub restore_token: AccessToken,
+  #[serde(default = "BackupSettings::default_pg_dump")]
+  pub pg_dump: std::path::PathBuf,
+  #[serde(default = "BackupSettings::default_pg_restore")]
+  pub pg_restore: std::path::PathBuf,
+}
+
+impl BackupSettings {
+  fn default_pg_dump() -> std::path::PathBuf {
+    "pg_dump".into()
+  }
+
+  fn default_pg_restore() -> std::path::PathBuf {
+    "pg_restore".into()
+  }
+}
+
+// The database URL usually contains a password
+impl std::fmt::Debug for BackupSettings {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("BackupSettings")
+      .field("directory", &self.directory)
+      .field("database_url", &"<redacted>")
+      .field("restore_token", &self.restore_token)
+      .field("pg_dump", &self.pg_dump)
+      .field("pg_restore", &self.pg_restore)
+      .finish()
+  }
+}
+
 impl Config {
+  /// URL of the database, if either `database_url` or `backups` is set
+  pub fn database_url(&self) -> Option<&str> {
+    self
+      .database_url
+      .as_ref()
+      .map(|url| url.0.as_str())
+      .or_else(|| self.backups.as_ref().map(|backups| backups.database_url.as_str()))
+  }
+
   pub fn load<P: AsRef<std::path::Path>>(path: P) -> anyhow::Result<Self> {
-    let mut config = serde_json::from_str::<Config>(
-      &std::fs::read_to_string(path.as_ref())
-        .map_err(|_| anyhow::anyhow!("Could not read the config file {}", path.as_ref().display()))?,
-    )?;
+    let mut config = scs_config::read::<Config>(path)?;
     config.compose.path = Self::process_path(&config.compose.path, "Compose file", false)?;
     config.project_source_folder = Self::process_path(&config.project_source_folder, "Source folder", true)?;
+    config.cors.validate()?;
+    if let Some(backups) = &mut config.backups {
+      if !backups.directory.exists() {
+        log::warn!(
+          "Backup directory '{}' does not exist, it will be created",
+          backups.directory.display()
+        );
+        std::fs::create_dir_all(&backups.directory)?;
+      }
+      backups.directory = Self::process_path(&backups.directory, "Backup directory", true)?;
+    }
     if config.access_tokens.is_empty() {
       log::error!("No access tokens were specified -- the API is going to be be inaccessible. Please provide at least one access token.");
       anyhow::bail!("No access tokens were specified");
@@ -105,3 +214,24 @@ impl Config {
     })
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_unknown_fields() {
+    let config = serde_json::from_str::<Config>(
+      r#"{ "project_source_folder": "..", "access_tokens": [], "compose_file": "docker-compose.yml" }"#,
+    )
+    .unwrap();
+    assert_eq!(config.compose.path, std::path::Path::new("docker-compose.yml"));
+    assert_eq!(config.compose.profile, "chatbot");
+
+    let error = serde_json::from_str::<Config>(
+      r#"{ "project_source_folder": "..", "access_tokens": [], "path": "docker-compose.yml", "profil": "chatbot" }"#,
+    )
+    .unwrap_err();
+    assert!(error.to_string().contains("unknown field `profil`"), "{error}");
+  }
+}
@@ -12,21 +12,29 @@ pub struct State {
   pub config_path: std::path::PathBuf,
   pub last_command: Option<Cow<'static, str>>,
   pub log_history: RwLock<Vec<schema::CommandLine>>,
+  /// Connected on first use, see [`crate::config::Config::database_url`]
+  pub database: Option<db::Database>,
   rx: Receiver<schema::CommandLine>,
   tx: Sender<schema::CommandLine>,
 }
 
 impl State {
-  pub fn new(config: crate::config::Config, config_path: std::path::PathBuf) -> Self {
+  pub fn new(config: crate::config::Config, config_path: std::path::PathBuf) -> anyhow::Result<Self> {
     let (tx, rx) = unbounded();
-    Self {
+    // The database may be started by this API, so it isn't connected to until it's needed
+    let database = config
+      .database_url()
+      .map(|url| db::sqlx::postgres::PgPoolOptions::new().connect_lazy(url))
+      .transpose()?;
+    Ok(Self {
       config,
       config_path,
       last_command: None,
       log_history: RwLock::new(Vec::new()),
+      database,
       rx,
       tx,
-    }
+    })
   }
 
   pub fn compose_command(&self, args: impl Fn(&mut tokio::process::Command)) -> tokio::process::Command {
@@ -1,13 +1,13 @@
 use std::env;
 
-use actix_cors::Cors;
-use actix_web::http::header;
 use actix_web::web;
 use actix_web::{middleware, web::Data, App, HttpServer};
 use actix_web_httpauth::middleware::HttpAuthentication;
 
+mod backup;
 mod config;
 pub mod ctx;
+mod plan;
 mod schema;
 mod streaming;
 mod v1;
@@ -19,6 +19,10 @@ async fn main() -> anyhow::Result<()> {
   }
   env_logger::init();
 
+  if env::args().nth(1).as_deref() == Some("validate") {
+    return scs_config::validate_all(env::args().skip(2), |path| config::Config::load(path));
+  }
+
   let config_path = env::args()
     .nth(1)
     .map(std::path::PathBuf::from)
@@ -36,20 +40,13 @@ async fn main() -> anyhow::Result<()> {
   log::info!("Changing the directory to {}", config.project_source_folder.display());
   std::env::set_current_dir(&config.project_source_folder)?;
 
-  let ctx = ctx::Context::new(ctx::State::new(config, config_path));
+  let cors = config.cors.clone();
+  let ctx = ctx::Context::new(ctx::State::new(config, config_path)?);
 
   let server = HttpServer::new(move || {
     App::new()
       .app_data(Data::new(ctx.clone()))
-      .wrap(
-        Cors::default()
-          .allow_any_origin()
-          .allowed_methods(vec!["POST", "GET"])
-          .allowed_headers(vec![header::AUTHORIZATION, header::ACCEPT])
-          .allowed_header(header::CONTENT_TYPE)
-          .supports_credentials()
-          .max_age(3600),
-      )
+      .wrap(cors.cors(&[]))
       .wrap(middleware::Compress::default())
       .wrap(middleware::Logger::default())
       .service(
@@ -58,12 +55,17 @@ async fn main() -> anyhow::Result<()> {
           .service(v1::run_compose_up)
           .service(v1::run_compose_down)
           .service(v1::deploy)
+          .service(v1::deploy_plan)
           .service(v1::restart)
           .service(v1::configs)
           .service(v1::is_running)
           .service(v1::last_command)
           .service(v1::services)
-          .service(v1::manage_service),
+          .service(v1::manage_service)
+          .service(v1::backup)
+          .service(v1::backups)
+          .service(v1::restore)
+          .service(v1::transfer_audits),
       )
   });
   server.bind("127.0.0.1:7191").unwrap().run().await?;
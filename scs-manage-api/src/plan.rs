@@ -0,0 +1,149 @@
+//! Dry run of `/v1/deploy`.
+//!
+//! The plan is built from read-only commands, except for `git fetch`, which only updates the remote-tracking branches:
+//! - the commits `git pull` would merge, with their stats, and the changes they make to the docker directory
+//! - per service, the config hash docker-compose stored on the running container against the hash of the current
+//!   compose file, and the image the container runs against the image currently tagged with the same name
+
+use std::collections::HashMap;
+
+use crate::{config::ComposeSettings, ctx, schema, v1::capture_output};
+
+/// The branch `git pull` merges from
+const UPSTREAM: &str = "@{upstream}";
+const FIELD_SEPARATOR: char = '\x1f';
+
+pub async fn plan(compose: &ComposeSettings) -> actix_web::Result<schema::DeployPlan> {
+  capture_output(ctx::command("git", |cmd| {
+    cmd.arg("fetch");
+  }))
+  .await?;
+
+  let range = format!("HEAD..{UPSTREAM}");
+  let commits = capture_output(ctx::command("git", |cmd| {
+    cmd.arg("log");
+    cmd.arg(format!(
+      "--format=%H{FIELD_SEPARATOR}%an{FIELD_SEPARATOR}%aI{FIELD_SEPARATOR}%s"
+    ));
+    cmd.arg(&range);
+  }))
+  .await?;
+  let stat = capture_output(ctx::command("git", |cmd| {
+    cmd.arg("log");
+    cmd.arg("--stat");
+    cmd.arg("--format=%h %s");
+    cmd.arg(&range);
+  }))
+  .await?;
+  let docker_dir = compose.path.parent().unwrap_or(&compose.path).to_owned();
+  let docker_diff = capture_output(ctx::command("git", |cmd| {
+    cmd.arg("diff");
+    cmd.arg(format!("HEAD...{UPSTREAM}"));
+    cmd.arg("--");
+    cmd.arg(&docker_dir);
+  }))
+  .await?;
+
+  Ok(schema::DeployPlan {
+    commits: commits.lines().filter_map(parse_commit).collect(),
+    stat,
+    docker_diff,
+    services: plan_services(compose).await?,
+  })
+}
+
+fn parse_commit(line: &str) -> Option<schema::PlannedCommit> {
+  let mut fields = line.splitn(4, FIELD_SEPARATOR);
+  Some(schema::PlannedCommit {
+    hash: fields.next()?.to_owned(),
+    author: fields.next()?.to_owned(),
+    date: fields.next()?.to_owned(),
+    summary: fields.next()?.to_owned(),
+  })
+}
+
+/// Config hash, image id, and image name of a running container
+struct Running {
+  config_hash: String,
+  image_id: String,
+  image: String,
+}
+
+async fn plan_services(compose: &ComposeSettings) -> actix_web::Result<Vec<schema::ServicePlan>> {
+  // Lines of `<service> <hash>`
+  let hashes = capture_output(ctx::compose_command(compose, |cmd| {
+    cmd.arg("config");
+    cmd.arg("--hash=*");
+  }))
+  .await?;
+
+  let mut running = HashMap::new();
+  let ids = capture_output(ctx::compose_command(compose, |cmd| {
+    cmd.arg("ps");
+    cmd.arg("-q");
+  }))
+  .await?;
+  let ids = ids.lines().filter(|id| !id.is_empty()).collect::<Vec<_>>();
+  if !ids.is_empty() {
+    let containers = capture_output(ctx::command("docker", |cmd| {
+      cmd.arg("inspect");
+      cmd.arg(
+        "--format={{index .Config.Labels \"com.docker.compose.service\"}} \
+         {{index .Config.Labels \"com.docker.compose.config-hash\"}} {{.Image}} {{.Config.Image}}",
+      );
+      cmd.args(&ids);
+    }))
+    .await?;
+    for line in containers.lines() {
+      if let [service, config_hash, image_id, image] = line.split(' ').collect::<Vec<_>>()[..] {
+        running.insert(
+          service.to_owned(),
+          Running {
+            config_hash: config_hash.to_owned(),
+            image_id: image_id.to_owned(),
+            image: image.to_owned(),
+          },
+        );
+      }
+    }
+  }
+
+  let mut services = Vec::new();
+  for line in hashes.lines() {
+    let Some((name, config_hash)) = line.split_once(' ') else {
+      continue;
+    };
+    let running = running.remove(name);
+    let image_id = match &running {
+      Some(running) => current_image_id(&running.image).await?,
+      None => None,
+    };
+    services.push(schema::ServicePlan {
+      name: name.to_owned(),
+      is_running: running.is_some(),
+      config_changed: running.as_ref().map_or(true, |r| r.config_hash != config_hash),
+      image_changed: match (&running, &image_id) {
+        (Some(running), Some(image_id)) => &running.image_id != image_id,
+        _ => false,
+      },
+      config_hash: config_hash.to_owned(),
+      running_config_hash: running.as_ref().map(|r| r.config_hash.clone()),
+      image: running.as_ref().map(|r| r.image.clone()),
+      running_image_id: running.map(|r| r.image_id),
+      image_id,
+    });
+  }
+  Ok(services)
+}
+
+/// Id of the image currently tagged as `image`, if there's one
+async fn current_image_id(image: &str) -> actix_web::Result<Option<String>> {
+  let ids = capture_output(ctx::command("docker", |cmd| {
+    cmd.arg("images");
+    cmd.arg("-q");
+    cmd.arg("--no-trunc");
+    cmd.arg(image);
+  }))
+  .await?;
+  Ok(ids.lines().next().map(str::to_owned))
+}
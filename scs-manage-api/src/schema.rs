@@ -48,6 +48,48 @@ pub struct Service {
   pub is_running: bool,
 }
 
+#[derive(serde::Serialize)]
+pub struct Backup {
+  pub name: String,
+  /// Size of the backup, in bytes
+  pub size: u64,
+  pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(serde::Serialize)]
+pub struct PlannedCommit {
+  pub hash: String,
+  pub author: String,
+  pub date: String,
+  pub summary: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct ServicePlan {
+  pub name: String,
+  pub is_running: bool,
+  /// Whether the service's configuration in the current compose file differs from the running container's
+  pub config_changed: bool,
+  pub config_hash: String,
+  pub running_config_hash: Option<String>,
+  /// Whether an image newer than the one the container runs was built
+  pub image_changed: bool,
+  pub image: Option<String>,
+  pub image_id: Option<String>,
+  pub running_image_id: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct DeployPlan {
+  /// The commits `git pull` would merge, newest first
+  pub commits: Vec<PlannedCommit>,
+  /// `git log --stat` of the commits
+  pub stat: String,
+  /// Changes the commits make to the docker directory
+  pub docker_diff: String,
+  pub services: Vec<ServicePlan>,
+}
+
 impl From<CommandResult> for CommandLine {
   fn from(result: CommandResult) -> Self {
     CommandLine::Result(result)
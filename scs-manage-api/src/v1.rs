@@ -1,11 +1,11 @@
 use std::process::Stdio;
 
-use actix_web::{dev::ServiceRequest, get, post, web, HttpResponse};
+use actix_web::{dev::ServiceRequest, get, post, web, HttpRequest, HttpResponse};
 use actix_web_httpauth::extractors::bearer::BearerAuth;
 use async_stream::try_stream;
 use futures::{Stream, StreamExt, TryStreamExt};
 
-use crate::{ctx, schema, streaming::StreamLock};
+use crate::{backup, config::BackupSettings, ctx, schema, streaming::StreamLock};
 
 use tokio::{
   io::{AsyncBufReadExt, BufReader},
@@ -338,6 +338,105 @@ pub async fn deploy(ctx: web::Data<ctx::Context>) -> actix_web::Result<HttpRespo
   Ok(HttpResponse::Ok().streaming(Box::pin(locked)))
 }
 
+#[get("/deploy/plan")]
+pub async fn deploy_plan(ctx: web::Data<ctx::Context>) -> actix_web::Result<web::Json<schema::DeployPlan>> {
+  // Don't fetch while a deploy may be pulling, and hold the lock while fetching, so that no deploy starts meanwhile
+  let Some(lock) = ctx.try_write() else {
+    return Err(actix_web::error::ErrorPreconditionFailed("a command is running"));
+  };
+  let lock = lock.downgrade();
+  let plan = crate::plan::plan(&lock.config.compose).await?;
+  drop(lock);
+  Ok(web::Json(plan))
+}
+
+async fn backup_settings(ctx: &web::Data<ctx::Context>) -> actix_web::Result<BackupSettings> {
+  ctx
+    .read()
+    .await
+    .config
+    .backups
+    .clone()
+    .ok_or_else(|| actix_web::error::ErrorNotFound("backups aren't configured"))
+}
+
+#[post("/backup")]
+pub async fn backup(ctx: web::Data<ctx::Context>) -> actix_web::Result<HttpResponse> {
+  let settings = backup_settings(&ctx).await?;
+  let name = backup::new_name();
+  let sink = ensure_unlocked!(ctx, format!("backup {name}"));
+  let partial = backup::partial_path(&settings, &name);
+  let lock = ctx.read_owned().await;
+
+  // pg_dump
+  let stream = execute_command(backup::dump_command(&settings, &partial), sink.clone())
+    // mv
+    .chain(execute_command(
+      ctx::command("mv", |cmd| {
+        cmd.arg(&partial);
+        cmd.arg(settings.directory.join(&name));
+      }),
+      sink,
+    ));
+  let stream = terminate_on_error!(stream);
+
+  let locked = StreamLock::chain(stream, lock);
+  Ok(
+    HttpResponse::Ok()
+      .insert_header(("X-Backup-Name", name))
+      .streaming(Box::pin(locked)),
+  )
+}
+
+#[get("/backups")]
+pub async fn backups(ctx: web::Data<ctx::Context>) -> actix_web::Result<web::Json<Vec<schema::Backup>>> {
+  let settings = backup_settings(&ctx).await?;
+  Ok(web::Json(backup::list(&settings).await?))
+}
+
+#[post("/restore/{name}")]
+pub async fn restore(
+  ctx: web::Data<ctx::Context>,
+  req: HttpRequest,
+  name: web::Path<String>,
+) -> actix_web::Result<HttpResponse> {
+  let name = name.into_inner();
+  let settings = backup_settings(&ctx).await?;
+  let confirmation = req.headers().get("X-Confirm-Restore").and_then(|v| v.to_str().ok());
+  if !confirmation.map_or(false, |confirmation| settings.restore_token.matches(confirmation)) {
+    return Err(actix_web::error::ErrorForbidden(
+      "restoring a backup requires the restore token in X-Confirm-Restore",
+    ));
+  }
+  let path = backup::find(&settings, &name).ok_or_else(|| actix_web::error::ErrorNotFound("backup not found"))?;
+
+  let sink = ensure_unlocked!(ctx, format!("restore {name}"));
+  Ok(stream_cmd!(ctx, backup::restore_command(&settings, &path), sink))
+}
+
+#[derive(serde::Deserialize)]
+pub struct AuditsQuery {
+  limit: Option<i64>,
+}
+
+#[get("/transfers/audits")]
+pub async fn transfer_audits(
+  ctx: web::Data<ctx::Context>,
+  query: web::Query<AuditsQuery>,
+) -> actix_web::Result<web::Json<Vec<db::transfer_audits::TransferAudit>>> {
+  let database = ctx
+    .read()
+    .await
+    .database
+    .clone()
+    .ok_or_else(|| actix_web::error::ErrorNotFound("the database isn't configured"))?;
+  let limit = query.limit.unwrap_or(50).clamp(1, 500);
+  let audits = db::transfer_audits::fetch_recent(&database, limit)
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+  Ok(web::Json(audits))
+}
+
 #[get("/configs")]
 pub async fn configs(ctx: web::Data<ctx::Context>) -> actix_web::Result<web::Json<schema::ConfigList>> {
   let lock = ctx.read().await;
@@ -0,0 +1,66 @@
+//! Jittered exponential backoff between connection attempts, so that a server which keeps dropping connections isn't
+//! reconnected to in a tight loop, and so that clients don't all reconnect at the same time after an outage.
+
+use std::time::Duration;
+
+use rand::Rng;
+
+#[derive(Debug, Clone)]
+pub struct Backoff {
+  initial: Duration,
+  max: Duration,
+  /// Number of delays handed out since the last reset
+  attempt: u32,
+}
+
+impl Backoff {
+  pub fn new(initial: Duration, max: Duration) -> Self {
+    Self {
+      initial,
+      max,
+      attempt: 0,
+    }
+  }
+
+  /// Delay before the next attempt, which doubles with every attempt up to `max`.
+  /// The delay is randomized between half of that and all of it.
+  pub fn next_delay(&mut self) -> Duration {
+    let delay = self
+      .initial
+      .saturating_mul(2u32.saturating_pow(self.attempt))
+      .min(self.max);
+    self.attempt = self.attempt.saturating_add(1);
+    delay / 2 + delay.mul_f64(rand::thread_rng().gen_range(0.0..=0.5))
+  }
+
+  /// Starts over from the initial delay, e.g. once a connection turned out to be stable
+  pub fn reset(&mut self) {
+    self.attempt = 0;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn delays_double_up_to_the_cap() {
+    let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(10));
+    for expected in [1, 2, 4, 8, 10, 10, 10] {
+      let expected = Duration::from_secs(expected);
+      let delay = backoff.next_delay();
+      assert!(delay >= expected / 2 && delay <= expected, "{delay:?} for {expected:?}");
+    }
+
+    backoff.reset();
+    assert!(backoff.next_delay() <= Duration::from_secs(1));
+  }
+
+  #[test]
+  fn delays_never_overflow() {
+    let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(60));
+    for _ in 0..100 {
+      assert!(backoff.next_delay() <= Duration::from_secs(60));
+    }
+  }
+}
@@ -0,0 +1,73 @@
+#[derive(Debug, Clone)]
+pub struct Regular {
+  pub login: String,
+  pub token: String,
+}
+
+/// Credentials which can be renewed using an OAuth refresh token.
+///
+/// `token` is the IRC password, i.e. the access token prefixed with `oauth:`.
+#[derive(Debug, Clone)]
+pub struct Refreshable {
+  pub login: String,
+  pub token: String,
+  pub refresh_token: String,
+  pub client_id: String,
+  pub client_secret: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct RefreshResponse {
+  access_token: String,
+  refresh_token: String,
+}
+
+impl Refreshable {
+  /// Exchanges the refresh token for a new access token.
+  ///
+  /// Twitch may rotate the refresh token, so both tokens are replaced.
+  pub async fn refresh(&mut self) -> anyhow::Result<()> {
+    let response = reqwest::Client::new()
+      .post("https://id.twitch.tv/oauth2/token")
+      .form(&[
+        ("client_id", &self.client_id[..]),
+        ("client_secret", &self.client_secret[..]),
+        ("grant_type", "refresh_token"),
+        ("refresh_token", &self.refresh_token[..]),
+      ])
+      .send()
+      .await?
+      .error_for_status()?
+      .json::<RefreshResponse>()
+      .await?;
+    self.token = format!("oauth:{}", response.access_token);
+    self.refresh_token = response.refresh_token;
+    Ok(())
+  }
+}
+
+#[derive(Debug, Clone)]
+pub enum Credentials {
+  Regular(Regular),
+  Refreshable(Refreshable),
+  Anonymous,
+}
+
+impl Credentials {
+  /// Returns the login and token as a tuple, in this order.
+  pub fn get(&self) -> (&str, &str) {
+    match self {
+      Credentials::Regular(r) => (&r.login[..], &r.token[..]),
+      Credentials::Refreshable(r) => (&r.login[..], &r.token[..]),
+      Credentials::Anonymous => ("justinfan83124", "just_a_lil_guy"),
+    }
+  }
+
+  /// Renews the token. Fails if the credentials are not [`Credentials::Refreshable`].
+  pub async fn refresh(&mut self) -> anyhow::Result<()> {
+    match self {
+      Credentials::Refreshable(r) => r.refresh().await,
+      _ => anyhow::bail!("Only refreshable credentials can be renewed, the token has to be replaced manually"),
+    }
+  }
+}
@@ -0,0 +1,309 @@
+//! Confirmation of sent messages.
+//!
+//! Twitch doesn't acknowledge a PRIVMSG directly. An accepted message is followed by a USERSTATE for its channel,
+//! and a rejected one by a NOTICE whose `msg-id` starts with `msg_`, e.g. `msg_ratelimit` or `msg_duplicate`.
+//! Replies arrive in the order the messages were sent, so each one settles the oldest pending message of its channel.
+//! Twitch also sends a USERSTATE when a channel is joined, which is ignored unless a message is pending. Messages
+//! which are still pending when their channel is left or the connection is replaced are settled as unconfirmed, so
+//! that the USERSTATE of the next join doesn't settle them.
+//! The USERSTATE which accepts a message has its ID in the `id` tag, which replies to it refer to.
+
+use std::{
+  collections::{HashMap, VecDeque},
+  time::{Duration, Instant},
+};
+
+/// How long a sent message may take to be confirmed, after which its outcome is unknown
+pub const CONFIRMATION_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SendOutcome {
+  Accepted,
+  /// Twitch rejected the message. `reason` is the `msg-id` of the NOTICE, and `notice` its text.
+  Dropped {
+    reason: String,
+    notice: String,
+  },
+  /// Neither a USERSTATE nor a NOTICE was received within [`CONFIRMATION_TIMEOUT`]
+  Unconfirmed,
+}
+
+impl SendOutcome {
+  /// Whether the message was dropped because the bot sent too many messages
+  pub fn is_rate_limited(&self) -> bool {
+    matches!(self, SendOutcome::Dropped { reason, .. } if reason == "msg_ratelimit")
+  }
+}
+
+/// A sent message whose outcome is known
+#[derive(Debug, Clone)]
+pub struct Delivery {
+  pub channel: String,
+  pub text: String,
+  pub sent_at: Instant,
+  pub outcome: SendOutcome,
+  /// ID of the message, if Twitch accepted it
+  pub id: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct Deliveries {
+  /// Sent messages which weren't settled yet, oldest first, by channel
+  pending: HashMap<String, VecDeque<(String, Instant)>>,
+  settled: Vec<Delivery>,
+}
+
+impl Deliveries {
+  /// Records that `text` was sent to `channel` at `now`
+  pub fn sent(&mut self, channel: &str, text: &str, now: Instant) {
+    self
+      .pending
+      .entry(channel.to_owned())
+      .or_default()
+      .push_back((text.to_owned(), now));
+  }
+
+  /// Settles the oldest pending message of a channel, if `line` is a reply to a sent message
+  pub fn receive(&mut self, line: &str) {
+    let Some((channel, outcome, id)) = parse_reply(line) else {
+      return;
+    };
+    let Some(pending) = self.pending.get_mut(channel) else {
+      return;
+    };
+    if let Some((text, sent_at)) = pending.pop_front() {
+      self.settled.push(Delivery {
+        channel: channel.to_owned(),
+        text,
+        sent_at,
+        outcome,
+        id: id.map(ToOwned::to_owned),
+      });
+    }
+    if pending.is_empty() {
+      self.pending.remove(channel);
+    }
+  }
+
+  /// Settles the pending messages of `channel` as unconfirmed, e.g. when it's left
+  pub fn abandon(&mut self, channel: &str) {
+    let Some(pending) = self.pending.remove(channel) else {
+      return;
+    };
+    for (text, sent_at) in pending {
+      self.settled.push(Delivery {
+        channel: channel.to_owned(),
+        text,
+        sent_at,
+        outcome: SendOutcome::Unconfirmed,
+        id: None,
+      });
+    }
+  }
+
+  /// Settles every pending message as unconfirmed, e.g. when the connection is replaced
+  pub fn abandon_all(&mut self) {
+    let channels = self.pending.keys().cloned().collect::<Vec<_>>();
+    for channel in channels {
+      self.abandon(&channel);
+    }
+  }
+
+  /// Returns the settled messages, including the ones which weren't confirmed in time by `now`, in the order they
+  /// were settled
+  pub fn drain(&mut self, now: Instant) -> Vec<Delivery> {
+    for (channel, pending) in self.pending.iter_mut() {
+      while let Some((_, sent_at)) = pending.front() {
+        if now.saturating_duration_since(*sent_at) < CONFIRMATION_TIMEOUT {
+          break;
+        }
+        let (text, sent_at) = pending.pop_front().unwrap();
+        self.settled.push(Delivery {
+          channel: channel.clone(),
+          text,
+          sent_at,
+          outcome: SendOutcome::Unconfirmed,
+          id: None,
+        });
+      }
+    }
+    self.pending.retain(|_, pending| !pending.is_empty());
+    std::mem::take(&mut self.settled)
+  }
+
+  /// Number of sent messages which weren't settled yet
+  pub fn pending(&self) -> usize {
+    self.pending.values().map(VecDeque::len).sum()
+  }
+}
+
+/// Returns the channel and the outcome of a sent message, along with its ID if it was accepted, if `line` is a
+/// USERSTATE, or a NOTICE about a message
+fn parse_reply(line: &str) -> Option<(&str, SendOutcome, Option<&str>)> {
+  let mut line = line.trim_end();
+  let mut msg_id = None;
+  let mut id = None;
+  if let Some(tags) = line.strip_prefix('@') {
+    let (tags, rest) = tags.split_once(' ')?;
+    msg_id = tags.split(';').find_map(|tag| tag.strip_prefix("msg-id="));
+    id = tags
+      .split(';')
+      .find_map(|tag| tag.strip_prefix("id="))
+      .filter(|id| !id.is_empty());
+    line = rest;
+  }
+  if line.starts_with(':') {
+    line = line.split_once(' ')?.1;
+  }
+  let (command, params) = line.split_once(' ')?;
+  let (channel, text) = match params.split_once(" :") {
+    Some((channel, text)) => (channel, text),
+    None => (params, ""),
+  };
+  let channel = channel.strip_prefix('#')?;
+  match command {
+    "USERSTATE" => Some((channel, SendOutcome::Accepted, id)),
+    "NOTICE" => match msg_id {
+      Some(reason) if reason.starts_with("msg_") => Some((
+        channel,
+        SendOutcome::Dropped {
+          reason: reason.to_owned(),
+          notice: text.to_owned(),
+        },
+        None,
+      )),
+      _ => None,
+    },
+    _ => None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn outcomes(deliveries: &[Delivery]) -> Vec<(&str, &str, &SendOutcome)> {
+    deliveries
+      .iter()
+      .map(|d| (d.channel.as_str(), d.text.as_str(), &d.outcome))
+      .collect()
+  }
+
+  #[test]
+  fn parses_replies() {
+    assert_eq!(
+      parse_reply("@badge-info=;badges=;color= :tmi.twitch.tv USERSTATE #forsen\r"),
+      Some(("forsen", SendOutcome::Accepted, None))
+    );
+    assert_eq!(
+      parse_reply("@badges=;id=885196de-cb67-427a-baa8-82f9b0fcd05f :tmi.twitch.tv USERSTATE #forsen"),
+      Some((
+        "forsen",
+        SendOutcome::Accepted,
+        Some("885196de-cb67-427a-baa8-82f9b0fcd05f")
+      ))
+    );
+    assert_eq!(
+      parse_reply("@msg-id=msg_ratelimit :tmi.twitch.tv NOTICE #forsen :Your message was not sent."),
+      Some((
+        "forsen",
+        SendOutcome::Dropped {
+          reason: "msg_ratelimit".into(),
+          notice: "Your message was not sent.".into()
+        },
+        None
+      ))
+    );
+    // Notices which aren't about a sent message
+    assert_eq!(
+      parse_reply("@msg-id=slow_on :tmi.twitch.tv NOTICE #forsen :This room is now in slow mode."),
+      None
+    );
+    assert_eq!(
+      parse_reply(":tmi.twitch.tv NOTICE * :Login authentication failed"),
+      None
+    );
+    assert_eq!(
+      parse_reply("@badges= :a!a@a.tmi.twitch.tv PRIVMSG #forsen :USERSTATE #forsen"),
+      None
+    );
+  }
+
+  #[test]
+  fn settles_the_oldest_message_of_each_channel() {
+    let start = Instant::now();
+    let mut deliveries = Deliveries::default();
+    // Ignored, nothing was sent yet
+    deliveries.receive(":tmi.twitch.tv USERSTATE #a");
+    deliveries.sent("a", "first", start);
+    deliveries.sent("a", "second", start);
+    deliveries.sent("b", "third", start);
+
+    deliveries.receive("@msg-id=msg_duplicate :tmi.twitch.tv NOTICE #a :Duplicate");
+    deliveries.receive(":tmi.twitch.tv USERSTATE #b");
+    deliveries.receive("@id=1 :tmi.twitch.tv USERSTATE #a");
+    let dropped = SendOutcome::Dropped {
+      reason: "msg_duplicate".into(),
+      notice: "Duplicate".into(),
+    };
+    let settled = deliveries.drain(start);
+    assert_eq!(
+      outcomes(&settled),
+      vec![
+        ("a", "first", &dropped),
+        ("b", "third", &SendOutcome::Accepted),
+        ("a", "second", &SendOutcome::Accepted),
+      ]
+    );
+    assert_eq!(deliveries.pending(), 0);
+    assert!(deliveries.drain(start).is_empty());
+    let ids = settled.iter().map(|d| d.id.as_deref()).collect::<Vec<_>>();
+    assert_eq!(ids, vec![None, None, Some("1")]);
+  }
+
+  #[test]
+  fn abandons_the_messages_of_left_channels() {
+    let start = Instant::now();
+    let mut deliveries = Deliveries::default();
+    deliveries.sent("a", "first", start);
+    deliveries.sent("b", "second", start);
+    deliveries.sent("c", "third", start);
+
+    deliveries.abandon("a");
+    // The USERSTATE sent when `a` is joined again
+    deliveries.receive(":tmi.twitch.tv USERSTATE #a");
+    assert_eq!(
+      outcomes(&deliveries.drain(start)),
+      vec![("a", "first", &SendOutcome::Unconfirmed)]
+    );
+    assert_eq!(deliveries.pending(), 2);
+
+    deliveries.abandon_all();
+    deliveries.receive(":tmi.twitch.tv USERSTATE #b");
+    let mut settled = outcomes(&deliveries.drain(start));
+    settled.sort_by_key(|(channel, _, _)| *channel);
+    assert_eq!(
+      settled,
+      vec![
+        ("b", "second", &SendOutcome::Unconfirmed),
+        ("c", "third", &SendOutcome::Unconfirmed)
+      ]
+    );
+    assert_eq!(deliveries.pending(), 0);
+  }
+
+  #[test]
+  fn times_out_unconfirmed_messages() {
+    let start = Instant::now();
+    let mut deliveries = Deliveries::default();
+    deliveries.sent("a", "first", start);
+    deliveries.sent("a", "second", start + CONFIRMATION_TIMEOUT / 2);
+
+    assert!(deliveries.drain(start + CONFIRMATION_TIMEOUT / 2).is_empty());
+    assert_eq!(
+      outcomes(&deliveries.drain(start + CONFIRMATION_TIMEOUT)),
+      vec![("a", "first", &SendOutcome::Unconfirmed)]
+    );
+    assert_eq!(deliveries.pending(), 1);
+  }
+}
@@ -0,0 +1,104 @@
+//! Stream events announced in chat with a USERNOTICE, e.g. raids and subscriptions.
+//!
+//! The event is described by the tags of the USERNOTICE: `msg-id` is its type, and the `msg-param-*` tags its
+//! details. Only the events the bot reacts to are parsed, others are ignored.
+
+/// An event of `channel`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamEvent<'a> {
+  pub channel: &'a str,
+  pub kind: EventKind<'a>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind<'a> {
+  /// The channel was raided by `from` with `viewers` viewers
+  Raid { from: &'a str, viewers: u32 },
+  /// `user` subscribed, or resubscribed for a total of `months` months
+  Sub { user: &'a str, months: u32 },
+  /// `gifter` gifted a subscription to `recipient`. Anonymous gifts are sent by `ananonymousgifter`.
+  GiftSub { gifter: &'a str, recipient: &'a str },
+}
+
+/// Returns the event of `line` if it's a USERNOTICE of a raid, a subscription, or a gifted subscription
+pub fn parse(line: &str) -> Option<StreamEvent<'_>> {
+  let (tags, rest) = line.trim_end().strip_prefix('@')?.split_once(' ')?;
+  let rest = match rest.strip_prefix(':') {
+    Some(prefixed) => prefixed.split_once(' ')?.1,
+    None => rest,
+  };
+  let channel = rest.strip_prefix("USERNOTICE #")?.split(' ').next()?;
+  let tag = |name: &str| {
+    tags
+      .split(';')
+      .find_map(|tag| tag.strip_prefix(name)?.strip_prefix('='))
+      .filter(|value| !value.is_empty())
+  };
+
+  let kind = match tag("msg-id")? {
+    "raid" => EventKind::Raid {
+      from: tag("msg-param-login")?,
+      viewers: tag("msg-param-viewerCount").and_then(|v| v.parse().ok()).unwrap_or(0),
+    },
+    "sub" | "resub" => EventKind::Sub {
+      user: tag("login")?,
+      months: tag("msg-param-cumulative-months")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(1),
+    },
+    "subgift" => EventKind::GiftSub {
+      gifter: tag("login")?,
+      recipient: tag("msg-param-recipient-user-name")?,
+    },
+    _ => return None,
+  };
+  Some(StreamEvent { channel, kind })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_events() {
+    assert_eq!(
+      parse(
+        "@badges=;login=xqc;msg-id=raid;msg-param-displayName=xQc;msg-param-login=xqc;msg-param-viewerCount=1500;\
+         system-msg=1500\\sraiders\\sfrom\\sxQc :tmi.twitch.tv USERNOTICE #forsen\r"
+      ),
+      Some(StreamEvent {
+        channel: "forsen",
+        kind: EventKind::Raid {
+          from: "xqc",
+          viewers: 1500
+        }
+      })
+    );
+    assert_eq!(
+      parse("@login=a;msg-id=resub;msg-param-cumulative-months=6 :tmi.twitch.tv USERNOTICE #forsen :hello"),
+      Some(StreamEvent {
+        channel: "forsen",
+        kind: EventKind::Sub { user: "a", months: 6 }
+      })
+    );
+    assert_eq!(
+      parse("@login=a;msg-id=subgift;msg-param-recipient-user-name=b :tmi.twitch.tv USERNOTICE #forsen"),
+      Some(StreamEvent {
+        channel: "forsen",
+        kind: EventKind::GiftSub {
+          gifter: "a",
+          recipient: "b"
+        }
+      })
+    );
+    // other events and commands are ignored
+    assert_eq!(
+      parse("@login=a;msg-id=announcement :tmi.twitch.tv USERNOTICE #forsen :hi"),
+      None
+    );
+    assert_eq!(
+      parse("@login=a;msg-id=raid;msg-param-login=b :a!a@a.tmi.twitch.tv PRIVMSG #forsen :USERNOTICE #x"),
+      None
+    );
+  }
+}
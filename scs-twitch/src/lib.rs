@@ -0,0 +1,440 @@
+use std::{
+  collections::VecDeque,
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+  },
+  time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use futures::{SinkExt, StreamExt};
+
+use tokio::net::TcpStream;
+use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+pub mod backoff;
+pub mod connect;
+pub mod credentials;
+pub mod delivery;
+pub mod events;
+pub mod membership;
+pub mod whisper;
+
+pub use backoff::Backoff;
+pub use connect::ConnectOptions;
+pub use credentials::Credentials;
+pub use delivery::{Deliveries, Delivery, SendOutcome};
+pub use membership::Membership;
+pub use whisper::{WhisperOutcome, Whispers};
+pub type WsError = tokio_tungstenite::tungstenite::Error;
+
+/// According to the docs, a user may attempt up to 20 JOINs per 10 seconds.
+/// See https://dev.twitch.tv/docs/irc/#rate-limits
+const CLOCK_SKEW: Duration = Duration::from_secs(3);
+const JOINS_PER_PERIOD: usize = 20;
+const PERIOD_DURATION: Duration = Duration::from_secs(10).saturating_add(CLOCK_SKEW);
+/// How often unacknowledged JOINs are checked for, see [`Membership`]
+const JOIN_RETRY_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// Bounds of the delay before each attempt to reconnect, see [`Backoff`]
+const RECONNECT_INITIAL_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(5 * 60);
+/// Number of failed attempts after which [`TwitchStream::reconnect`] gives up
+const RECONNECT_TRIES: u32 = 10;
+/// Connections which lasted at least this long reset the reconnection backoff
+const STABLE_CONNECTION: Duration = Duration::from_secs(60);
+
+/// Returns `true` if `notice` is the NOTICE sent by Twitch when the token is invalid or has expired.
+pub fn is_auth_failure(notice: &str) -> bool {
+  notice.contains("Login authentication failed") || notice.contains("Improperly formatted auth")
+}
+
+/// Returns the login of the user whose message `line` replies to, if it's a PRIVMSG sent as a reply.
+pub fn reply_parent_login(line: &str) -> Option<&str> {
+  let (tags, _) = line.strip_prefix('@')?.split_once(' ')?;
+  tags
+    .split(';')
+    .find_map(|tag| tag.strip_prefix("reply-parent-user-login="))
+    .filter(|login| !login.is_empty())
+}
+
+/// Returns the ID of the message `line` replies to, if it's a PRIVMSG sent as a reply.
+pub fn reply_parent_id(line: &str) -> Option<&str> {
+  let (tags, _) = line.strip_prefix('@')?.split_once(' ')?;
+  tags
+    .split(';')
+    .find_map(|tag| tag.strip_prefix("reply-parent-msg-id="))
+    .filter(|id| !id.is_empty())
+}
+
+/// Returns the ID of the user who sent `line`, from its `user-id` tag
+pub fn sender_id(line: &str) -> Option<&str> {
+  let (tags, _) = line.strip_prefix('@')?.split_once(' ')?;
+  tags
+    .split(';')
+    .find_map(|tag| tag.strip_prefix("user-id="))
+    .filter(|id| !id.is_empty())
+}
+
+/// Longest login Twitch allows
+pub const MAX_LOGIN_LENGTH: usize = 25;
+
+/// Returns `true` if `login` may be the login of a Twitch user: at most [`MAX_LOGIN_LENGTH`] lowercase ASCII letters,
+/// digits, and underscores. New logins need at least 4 characters, but some older ones are shorter.
+///
+/// Anything else, e.g. a space or a line break, must never be sent to Twitch as a channel, since it would change the
+/// meaning of the IRC command.
+pub fn is_valid_login(login: &str) -> bool {
+  (1..=MAX_LOGIN_LENGTH).contains(&login.len())
+    && login
+      .bytes()
+      .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_')
+}
+
+pub struct TwitchStream {
+  uri: String,
+  options: ConnectOptions,
+  ws: WebSocketStream<MaybeTlsStream<TcpStream>>,
+  /// Channels waiting to be joined, in batches of [`JOINS_PER_PERIOD`] every [`PERIOD_DURATION`]
+  join_queue: VecDeque<String>,
+  /// When the next batch may be joined, kept across reconnects so that they can't exceed the rate limit either
+  next_join_at: Instant,
+  smb: SameMessageBypass,
+  membership: Membership,
+  deliveries: Deliveries,
+  retry_timer: tokio::time::Interval,
+  connected_at: Instant,
+  /// Kept across reconnects, so that a connection which keeps dropping is retried less and less often
+  backoff: Backoff,
+  /// Number of attempts to reconnect, shared with the connections this one replaces and is replaced by
+  reconnect_attempts: Arc<AtomicU64>,
+}
+
+impl TwitchStream {
+  pub async fn new() -> Result<Self, WsError> {
+    Self::with_uri("wss://irc-ws.chat.twitch.tv:443").await
+  }
+
+  /// Connects to `uri`, using the proxy and CA file set in the environment, if any. See [`ConnectOptions::from_env`].
+  pub async fn with_uri(uri: impl Into<String>) -> Result<Self, WsError> {
+    Self::with_options(uri, ConnectOptions::from_env()).await
+  }
+
+  pub async fn with_options(uri: impl Into<String>, options: ConnectOptions) -> Result<Self, WsError> {
+    let uri = uri.into();
+    let ws = connect::connect(&uri, &options).await?;
+    Ok(Self {
+      ws,
+      uri,
+      options,
+      join_queue: VecDeque::new(),
+      next_join_at: Instant::now(),
+      smb: SameMessageBypass::default(),
+      membership: Membership::default(),
+      deliveries: Deliveries::default(),
+      retry_timer: tokio::time::interval(JOIN_RETRY_CHECK_INTERVAL),
+      connected_at: Instant::now(),
+      backoff: Backoff::new(RECONNECT_INITIAL_DELAY, RECONNECT_MAX_DELAY),
+      reconnect_attempts: Arc::default(),
+    })
+  }
+
+  /// Number of attempts to reconnect made so far, successful or not, which is updated while they're made.
+  /// It's the same counter for the connections which replace this one.
+  pub fn reconnect_counter(&self) -> Arc<AtomicU64> {
+    self.reconnect_attempts.clone()
+  }
+
+  pub async fn authenticate(&mut self, credentials: &Credentials) -> Result<(), WsError> {
+    let (login, token) = credentials.get();
+
+    log::info!("Authenticating as {}...", login);
+    self.send("CAP REQ :twitch.tv/commands twitch.tv/tags").await?;
+    self.send(format!("PASS {token}")).await?;
+    self.send(format!("NICK {login}")).await?;
+
+    Ok(())
+  }
+
+  /// The channels which were joined, and whether Twitch acknowledged them
+  pub fn membership(&self) -> &Membership {
+    &self.membership
+  }
+
+  /// Returns the channels quarantined since the previous call, see [`Membership`]
+  pub fn take_quarantined(&mut self) -> Vec<String> {
+    self.membership.take_quarantined()
+  }
+
+  /// Queues JOINs for `channels`. They're sent by [`TwitchStream::receive`], in batches of [`JOINS_PER_PERIOD`] every
+  /// [`PERIOD_DURATION`], along with the JOINs queued by other calls and the retries of unacknowledged ones.
+  pub fn schedule_joins(&mut self, channels: &[String]) {
+    self.membership.expect(channels);
+    for channel in channels {
+      if !self.join_queue.contains(channel) {
+        self.join_queue.push_back(channel.clone());
+      }
+    }
+    let batches = (self.join_queue.len() + JOINS_PER_PERIOD - 1) / JOINS_PER_PERIOD;
+    log::info!(
+      "[JOIN] {} channel(s) waiting to be joined in {} batch(es), to be completed in around {}s",
+      self.join_queue.len(),
+      batches,
+      self.next_join_at.saturating_duration_since(Instant::now()).as_secs()
+        + PERIOD_DURATION.as_secs() * batches.saturating_sub(1) as u64
+    );
+  }
+
+  /// Sends `content` to `channel`. Whether Twitch accepted it is reported by [`TwitchStream::take_deliveries`],
+  /// once its reply was received.
+  pub async fn respond(&mut self, channel: &str, content: &str) -> Result<(), WsError> {
+    let text = format!("PRIVMSG #{} :{}{}\r\n", channel, content, self.smb.get());
+    self.send(text).await?;
+    self.deliveries.sent(channel, content, Instant::now());
+    Ok(())
+  }
+
+  /// Returns the messages sent with [`TwitchStream::respond`] whose outcome became known since the previous call.
+  /// Messages which weren't confirmed in time are returned as [`SendOutcome::Unconfirmed`].
+  pub fn take_deliveries(&mut self) -> Vec<Delivery> {
+    self.deliveries.drain(Instant::now())
+  }
+
+  pub async fn receive(&mut self) -> Result<Option<Message>, WsError> {
+    let message = tokio::select! {
+      _ = tokio::time::sleep_until(self.next_join_at.into()), if !self.join_queue.is_empty() => {
+        let count = self.join_queue.len().min(JOINS_PER_PERIOD);
+        let batch = self.join_queue.drain(..count).collect::<Vec<_>>();
+        self.next_join_at = Instant::now() + PERIOD_DURATION;
+        self.join_batch(&batch).await?;
+        self.ws.next().await.transpose()
+      },
+      _ = self.retry_timer.tick() => {
+        let due = self.membership.due(Instant::now());
+        if !due.is_empty() {
+          self.schedule_joins(&due);
+        }
+        self.ws.next().await.transpose()
+      },
+      msg = self.ws.next() => msg.transpose(),
+    }?;
+
+    if let Some(Message::Text(batch)) = &message {
+      for channel in batch.lines().filter_map(membership::acknowledged_channel) {
+        self.membership.confirm(channel);
+      }
+      for channel in batch.lines().filter_map(membership::suspended_channel) {
+        self.membership.quarantine(channel, Instant::now());
+      }
+      for line in batch.lines() {
+        self.deliveries.receive(line);
+      }
+    }
+    Ok(message)
+  }
+
+  pub async fn pong(&mut self) -> Result<(), WsError> {
+    self.send("PONG").await
+  }
+
+  /// Replaces the connection with a new one to the same server, and joins `channels` again.
+  ///
+  /// Each attempt waits for a delay from the connection's [`Backoff`] first, and resolves the server's address again,
+  /// so that a dead address is dropped as soon as the DNS records are updated. The backoff keeps increasing across
+  /// reconnects, unless the connection lasted at least [`STABLE_CONNECTION`]. Fails after [`RECONNECT_TRIES`] failed
+  /// attempts.
+  pub async fn reconnect(&mut self, creds: &Credentials, channels: &[String]) -> std::result::Result<(), WsError> {
+    if self.connected_at.elapsed() >= STABLE_CONNECTION {
+      self.backoff.reset();
+    }
+
+    let mut tries = 1;
+    loop {
+      let delay = self.backoff.next_delay();
+      let attempt = self.reconnect_attempts.fetch_add(1, Ordering::Relaxed) + 1;
+      log::info!("> Reconnecting in {:.1}s (attempt {})", delay.as_secs_f64(), attempt);
+      tokio::time::sleep(delay).await;
+
+      let result = match Self::with_options(self.uri.clone(), self.options.clone()).await {
+        Ok(mut new_stream) => new_stream.authenticate(creds).await.map(|_| new_stream),
+        Err(e) => Err(e),
+      };
+      match result {
+        Ok(mut new_stream) => {
+          // Messages sent before reconnecting won't be confirmed, and the USERSTATE of each join mustn't settle them
+          self.deliveries.abandon_all();
+          new_stream.deliveries = std::mem::take(&mut self.deliveries);
+          new_stream.backoff = self.backoff.clone();
+          new_stream.reconnect_attempts = self.reconnect_attempts.clone();
+          new_stream.next_join_at = self.next_join_at;
+          let quarantined = self.membership.quarantined();
+          *self = new_stream;
+          self.schedule_joins(channels);
+          self.membership.restore_quarantine(&quarantined, Instant::now());
+          break Ok(());
+        }
+        Err(e) if tries < RECONNECT_TRIES => {
+          tries += 1;
+          log::info!("> Connection failed: {}", e);
+          continue;
+        }
+        Err(e) => {
+          log::warn!("Failed to reconnect: {}", e);
+          break Err(e);
+        }
+      }
+    }
+  }
+
+  /// Refreshes `creds` and reconnects using the new token.
+  ///
+  /// If the credentials can't be refreshed, this fails with an IO error, which suggests terminating.
+  pub async fn refresh_and_reconnect(&mut self, creds: &mut Credentials, channels: &[String]) -> Result<(), WsError> {
+    log::info!("> Refreshing credentials");
+    if let Err(e) = creds.refresh().await {
+      log::error!("Failed to refresh credentials: {}", e);
+      return Err(WsError::Io(std::io::Error::new(
+        std::io::ErrorKind::PermissionDenied,
+        e.to_string(),
+      )));
+    }
+    self.reconnect(creds, channels).await
+  }
+
+  /// Leaves `channels` immediately
+  pub async fn part(&mut self, channels: &[String]) -> Result<(), WsError> {
+    log::info!("Leaving channels: {}", channels.join(", "));
+    self.membership.part(channels);
+    self.join_queue.retain(|c| !channels.contains(c));
+    for channel in channels {
+      self.deliveries.abandon(channel);
+    }
+
+    self
+      .send(format!(
+        "PART {}",
+        channels.iter().map(|c| format!("#{c}")).collect::<Vec<_>>().join(",")
+      ))
+      .await
+  }
+
+  async fn join_batch(&mut self, channels: &[String]) -> Result<(), WsError> {
+    log::info!("Joining channels: {}", channels.join(", "));
+
+    self
+      .send(format!(
+        "JOIN {}",
+        channels.iter().map(|c| format!("#{c}")).collect::<Vec<_>>().join(",")
+      ))
+      .await?;
+    self.membership.sent(channels, Instant::now());
+    Ok(())
+  }
+
+  async fn send(&mut self, msg: impl Into<String>) -> Result<(), WsError> {
+    self.ws.send(Message::Text(msg.into())).await
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SameMessageBypass {
+  flag: u8,
+}
+impl SameMessageBypass {
+  /// Number of characters appended to every other message, which messages sent with [`TwitchStream::respond`] must
+  /// leave room for
+  pub const SUFFIX_CHARS: usize = 2;
+
+  pub fn get(&mut self) -> &'static str {
+    match self.flag {
+      0 => {
+        self.flag = 1;
+        ""
+      }
+      1 => {
+        self.flag = 0;
+        concat!(" ", "⠀")
+      }
+      _ => unreachable!(),
+    }
+  }
+}
+
+#[allow(clippy::derivable_impls)]
+impl Default for SameMessageBypass {
+  fn default() -> Self {
+    SameMessageBypass { flag: 0 }
+  }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum SuggestedAction {
+  KeepGoing,
+  Reconnect,
+  Terminate,
+}
+
+impl<'a> From<&'a WsError> for SuggestedAction {
+  fn from(e: &'a WsError) -> Self {
+    match e {
+      // We've received or sent a message that's too large. Extremely unlikely to happen.
+      tokio_tungstenite::tungstenite::Error::Capacity(_) => SuggestedAction::KeepGoing,
+      // The queue is unlimited by default, so this shouldn't happen.
+      tokio_tungstenite::tungstenite::Error::SendQueueFull(_) => SuggestedAction::KeepGoing,
+      // Can't really do anything about this, so just keep going.
+      tokio_tungstenite::tungstenite::Error::Utf8 => SuggestedAction::KeepGoing,
+      // This shouldn't happen, because the stream returns `None` once closed.
+      tokio_tungstenite::tungstenite::Error::ConnectionClosed
+      | tokio_tungstenite::tungstenite::Error::AlreadyClosed => SuggestedAction::Reconnect,
+      // Twitch isn't following the websocket protocol. Unlikely to happen.
+      tokio_tungstenite::tungstenite::Error::Protocol(_) => SuggestedAction::Reconnect,
+      // We've received an HTTP error while trying to upgrade the websocket connection. Unlikely to happen.
+      tokio_tungstenite::tungstenite::Error::Http(_) => SuggestedAction::Reconnect,
+      // This one covers a few errors, including badly formatted status codes and headers. Unlikely to happen.
+      tokio_tungstenite::tungstenite::Error::HttpFormat(_) => SuggestedAction::Reconnect,
+      // IO indicates a terminal error like DNS resolution failure or a broken socket.
+      tokio_tungstenite::tungstenite::Error::Io(_) => SuggestedAction::Terminate,
+      // URL error indicates that the URL we're trying to connect to is invalid. It's hardcoded to be valid, so this shouldn't happen.
+      tokio_tungstenite::tungstenite::Error::Url(_) => SuggestedAction::Terminate,
+      // TLS error, this includes protocol-level errors and other errors such as DNS errors.
+      tokio_tungstenite::tungstenite::Error::Tls(_) => SuggestedAction::Terminate,
+    }
+  }
+}
+
+impl std::fmt::Display for SuggestedAction {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    <Self as std::fmt::Debug>::fmt(self, f)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_same_message_bypass() {
+    let mut smb = SameMessageBypass::default();
+    assert_eq!(smb.get(), "");
+    assert_eq!(smb.get().chars().count(), SameMessageBypass::SUFFIX_CHARS);
+    assert_eq!(smb.get(), "");
+  }
+
+  #[test]
+  fn test_is_valid_login() {
+    for login in ["forsen", "a", "xqc_123", "_underscore", &"a".repeat(MAX_LOGIN_LENGTH)] {
+      assert!(is_valid_login(login), "{login}");
+    }
+    for login in [
+      "",
+      "Forsen",
+      "for sen",
+      "forsen\r\nPRIVMSG #xqc :hi",
+      "#forsen",
+      "försen",
+      &"a".repeat(MAX_LOGIN_LENGTH + 1),
+    ] {
+      assert!(!is_valid_login(login), "{login}");
+    }
+  }
+}
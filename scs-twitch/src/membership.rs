@@ -0,0 +1,314 @@
+//! Tracking of the channels a connection is expected to be in.
+//!
+//! Twitch doesn't report failed JOINs (e.g. suspended or misspelled channels), it only acknowledges successful ones
+//! with a JOIN of our own user, followed by a NAMES reply (`353`). Channels which weren't acknowledged in time are
+//! joined again, with an exponential backoff.
+//!
+//! Channels which still weren't acknowledged after `QUARANTINE_AFTER` attempts, or which Twitch reported as suspended,
+//! are quarantined: they're only probed with a single JOIN every `PROBE_INTERVAL`, until one is acknowledged.
+
+use std::{
+  collections::{HashMap, HashSet},
+  time::{Duration, Instant},
+};
+
+/// How long a JOIN may take to be acknowledged before it's retried
+const CONFIRMATION_TIMEOUT: Duration = Duration::from_secs(30);
+/// Upper bound of the delay between retries of the same channel
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30 * 60);
+/// Number of unacknowledged JOINs after which a channel is quarantined
+const QUARANTINE_AFTER: u32 = 6;
+/// Delay between the JOINs of a quarantined channel
+const PROBE_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+#[derive(Debug, Clone, Copy)]
+struct Pending {
+  /// Number of JOINs sent for the channel
+  attempts: u32,
+  /// When the channel is joined again, `None` until its JOIN is sent
+  retry_at: Option<Instant>,
+  /// Whether the channel is only probed every `PROBE_INTERVAL`
+  quarantined: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct Membership {
+  /// Channels which were joined and not parted, confirmed or not
+  expected: HashSet<String>,
+  /// Expected channels which weren't acknowledged yet
+  pending: HashMap<String, Pending>,
+  /// Channels quarantined since the last call to [`Membership::take_quarantined`]
+  newly_quarantined: Vec<String>,
+}
+
+impl Membership {
+  /// Records that `channels` are about to be joined
+  pub fn expect(&mut self, channels: &[String]) {
+    for channel in channels {
+      if self.expected.insert(channel.clone()) {
+        self.pending.insert(
+          channel.clone(),
+          Pending {
+            attempts: 0,
+            retry_at: None,
+            quarantined: false,
+          },
+        );
+      }
+    }
+  }
+
+  /// Records that `channels` were parted, so they're no longer expected
+  pub fn part(&mut self, channels: &[String]) {
+    for channel in channels {
+      self.expected.remove(channel);
+      self.pending.remove(channel);
+    }
+    self.newly_quarantined.retain(|c| !channels.contains(c));
+  }
+
+  /// Records that a JOIN for `channels` was sent at `now`
+  pub fn sent(&mut self, channels: &[String], now: Instant) {
+    for channel in channels {
+      if let Some(pending) = self.pending.get_mut(channel) {
+        pending.attempts += 1;
+        if !pending.quarantined && pending.attempts > QUARANTINE_AFTER {
+          log::warn!(
+            "[JOIN] #{} wasn't joined after {} attempts, quarantining it",
+            channel,
+            QUARANTINE_AFTER
+          );
+          pending.quarantined = true;
+          self.newly_quarantined.push(channel.clone());
+        }
+        let delay = if pending.quarantined {
+          PROBE_INTERVAL
+        } else {
+          CONFIRMATION_TIMEOUT
+            .saturating_mul(2u32.saturating_pow(pending.attempts - 1))
+            .min(MAX_RETRY_DELAY)
+        };
+        pending.retry_at = Some(now + delay);
+      }
+    }
+  }
+
+  /// Quarantines `channel` right away, e.g. because Twitch reported it as suspended
+  pub fn quarantine(&mut self, channel: &str, now: Instant) {
+    if let Some(pending) = self.pending.get_mut(channel) {
+      if !pending.quarantined {
+        log::warn!("[JOIN] #{} is suspended, quarantining it", channel);
+        pending.quarantined = true;
+        self.newly_quarantined.push(channel.to_owned());
+      }
+      pending.retry_at = Some(now + PROBE_INTERVAL);
+    }
+  }
+
+  /// Quarantines `channels` again after reconnecting, without reporting them as newly quarantined
+  pub fn restore_quarantine(&mut self, channels: &[String], now: Instant) {
+    for channel in channels {
+      if let Some(pending) = self.pending.get_mut(channel) {
+        pending.quarantined = true;
+        pending.retry_at = Some(now + PROBE_INTERVAL);
+      }
+    }
+  }
+
+  /// Returns the channels quarantined since the previous call
+  pub fn take_quarantined(&mut self) -> Vec<String> {
+    std::mem::take(&mut self.newly_quarantined)
+  }
+
+  /// Records that Twitch acknowledged the JOIN of `channel`
+  pub fn confirm(&mut self, channel: &str) {
+    match self.pending.remove(channel) {
+      Some(pending) if pending.quarantined => {
+        log::info!("[JOIN] Joined #{}, it's no longer quarantined", channel);
+        self.newly_quarantined.retain(|c| c != channel);
+      }
+      Some(_) => log::info!("[JOIN] Joined #{}", channel),
+      None => (),
+    }
+  }
+
+  /// Returns the channels whose JOIN wasn't acknowledged by `now`. They aren't returned again until they're re-sent.
+  pub fn due(&mut self, now: Instant) -> Vec<String> {
+    let mut due = Vec::new();
+    for (channel, pending) in self.pending.iter_mut() {
+      if pending.retry_at.map_or(false, |at| at <= now) {
+        if pending.quarantined {
+          log::info!("[JOIN] Probing quarantined #{}", channel);
+        } else {
+          log::warn!(
+            "[JOIN] #{} wasn't joined after {} attempt(s), retrying",
+            channel,
+            pending.attempts
+          );
+        }
+        pending.retry_at = None;
+        due.push(channel.clone());
+      }
+    }
+    due.sort();
+    due
+  }
+
+  /// Number of channels whose JOIN was acknowledged
+  pub fn joined(&self) -> usize {
+    self.expected.len() - self.pending.len()
+  }
+
+  /// Expected channels whose JOIN wasn't acknowledged yet and which aren't quarantined, sorted by name
+  pub fn unjoined(&self) -> Vec<String> {
+    self.pending_where(|pending| !pending.quarantined)
+  }
+
+  /// Quarantined channels, sorted by name
+  pub fn quarantined(&self) -> Vec<String> {
+    self.pending_where(|pending| pending.quarantined)
+  }
+
+  fn pending_where(&self, filter: impl Fn(&Pending) -> bool) -> Vec<String> {
+    let mut channels = self
+      .pending
+      .iter()
+      .filter(|(_, pending)| filter(pending))
+      .map(|(channel, _)| channel.clone())
+      .collect::<Vec<_>>();
+    channels.sort();
+    channels
+  }
+}
+
+/// Returns the channel acknowledged by `line`, if it's a JOIN or a NAMES reply
+pub fn acknowledged_channel(line: &str) -> Option<&str> {
+  let mut line = line.trim_end();
+  // Tags and the prefix are skipped
+  if line.starts_with('@') {
+    line = line.split_once(' ')?.1;
+  }
+  if line.starts_with(':') {
+    line = line.split_once(' ')?.1;
+  }
+  let mut params = line.split(' ');
+  let channel = match params.next()? {
+    "JOIN" => params.next()?,
+    // `353 <nick> = #<channel> :<names>`
+    "353" => params.nth(2)?,
+    _ => return None,
+  };
+  channel.strip_prefix('#')
+}
+
+/// Returns the channel of `line` if it's the NOTICE sent by Twitch when joining a suspended channel
+pub fn suspended_channel(line: &str) -> Option<&str> {
+  let (tags, rest) = line.trim_end().strip_prefix('@')?.split_once(' ')?;
+  if !tags.split(';').any(|tag| tag == "msg-id=msg_channel_suspended") {
+    return None;
+  }
+  let rest = match rest.strip_prefix(':') {
+    Some(prefixed) => prefixed.split_once(' ')?.1,
+    None => rest,
+  };
+  rest.strip_prefix("NOTICE #")?.split(' ').next()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn channels(names: &[&str]) -> Vec<String> {
+    names.iter().map(|s| s.to_string()).collect()
+  }
+
+  #[test]
+  fn parses_acknowledgements() {
+    assert_eq!(
+      acknowledged_channel(":bot!bot@bot.tmi.twitch.tv JOIN #forsen\r"),
+      Some("forsen")
+    );
+    assert_eq!(
+      acknowledged_channel(":bot.tmi.twitch.tv 353 bot = #forsen :bot"),
+      Some("forsen")
+    );
+    assert_eq!(
+      acknowledged_channel(":bot.tmi.twitch.tv 366 bot #forsen :End of /NAMES list"),
+      None
+    );
+    assert_eq!(
+      acknowledged_channel("@badges=;color= :a!a@a.tmi.twitch.tv PRIVMSG #forsen :JOIN #x"),
+      None
+    );
+    assert_eq!(
+      suspended_channel(
+        "@msg-id=msg_channel_suspended :tmi.twitch.tv NOTICE #forsen :This channel has been suspended.\r"
+      ),
+      Some("forsen")
+    );
+    assert_eq!(
+      suspended_channel("@msg-id=msg_banned :tmi.twitch.tv NOTICE #forsen :You are permanently banned"),
+      None
+    );
+  }
+
+  #[test]
+  fn retries_unacknowledged_joins_with_backoff() {
+    let start = Instant::now();
+    let mut membership = Membership::default();
+    membership.expect(&channels(&["a", "b", "c"]));
+    // Nothing is due until the JOINs are sent
+    assert!(membership.due(start + MAX_RETRY_DELAY).is_empty());
+
+    membership.sent(&channels(&["a", "b", "c"]), start);
+    membership.confirm("a");
+    assert_eq!(membership.joined(), 1);
+    assert_eq!(membership.unjoined(), channels(&["b", "c"]));
+    assert!(membership.due(start + CONFIRMATION_TIMEOUT / 2).is_empty());
+    assert_eq!(membership.due(start + CONFIRMATION_TIMEOUT), channels(&["b", "c"]));
+    // Not returned again until they're re-sent
+    assert!(membership.due(start + MAX_RETRY_DELAY).is_empty());
+
+    let retry = start + CONFIRMATION_TIMEOUT;
+    membership.sent(&channels(&["b", "c"]), retry);
+    membership.part(&channels(&["c"]));
+    assert_eq!(membership.unjoined(), channels(&["b"]));
+    // The second attempt waits twice as long
+    assert!(membership.due(retry + CONFIRMATION_TIMEOUT).is_empty());
+    assert_eq!(membership.due(retry + CONFIRMATION_TIMEOUT * 2), channels(&["b"]));
+  }
+
+  #[test]
+  fn quarantines_channels_which_are_never_joined() {
+    let start = Instant::now();
+    let mut membership = Membership::default();
+    membership.expect(&channels(&["a", "b", "c"]));
+
+    let mut now = start;
+    for _ in 0..QUARANTINE_AFTER {
+      membership.sent(&channels(&["a"]), now);
+      now += MAX_RETRY_DELAY;
+      assert_eq!(membership.due(now), channels(&["a"]));
+    }
+    assert!(membership.quarantined().is_empty());
+    membership.sent(&channels(&["a"]), now);
+    assert_eq!(membership.quarantined(), channels(&["a"]));
+    assert_eq!(membership.unjoined(), channels(&["b", "c"]));
+    assert_eq!(membership.take_quarantined(), channels(&["a"]));
+    assert!(membership.take_quarantined().is_empty());
+
+    // Suspended channels are quarantined without waiting for the retries
+    membership.sent(&channels(&["b", "c"]), now);
+    membership.quarantine("b", now);
+    assert_eq!(membership.quarantined(), channels(&["a", "b"]));
+    assert_eq!(membership.take_quarantined(), channels(&["b"]));
+
+    // Quarantined channels are only probed every `PROBE_INTERVAL`
+    assert_eq!(membership.due(now + MAX_RETRY_DELAY), channels(&["c"]));
+    assert_eq!(membership.due(now + PROBE_INTERVAL), channels(&["a", "b"]));
+    membership.sent(&channels(&["a", "b"]), now + PROBE_INTERVAL);
+    membership.confirm("a");
+    assert_eq!(membership.quarantined(), channels(&["b"]));
+    assert_eq!(membership.joined(), 1);
+  }
+}
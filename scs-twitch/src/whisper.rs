@@ -0,0 +1,239 @@
+//! Whispers, i.e. private messages to a single user.
+//!
+//! Twitch no longer accepts the `/w` command in chat, so whispers are sent with the Helix API, as the user the token
+//! belongs to. The token needs the `user:manage:whispers` scope, and the account a verified phone number.
+//! Whispers have their own rate limits, which are independent from the ones of chat messages:
+//! 3 per second and 100 per minute, to at most 40 different recipients per day.
+//! See https://dev.twitch.tv/docs/api/reference/#send-whisper
+
+use std::{
+  collections::{HashMap, VecDeque},
+  time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::Credentials;
+
+const VALIDATE_URL: &str = "https://id.twitch.tv/oauth2/validate";
+const WHISPERS_URL: &str = "https://api.twitch.tv/helix/whispers";
+const SCOPE: &str = "user:manage:whispers";
+/// Whispers are sent from the bot's message loop, so a slow request must not hold up the chat
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Limits on the whispers sent within each period, see the module docs
+const LIMITS: [(usize, Duration); 2] = [(3, Duration::from_secs(1)), (100, Duration::from_secs(60))];
+const RECIPIENTS_PER_DAY: usize = 40;
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Why a whisper wasn't sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WhisperLimit {
+  /// Too many whispers were sent recently
+  Rate,
+  /// Too many different users were whispered to in the last day
+  Recipients,
+}
+
+impl std::fmt::Display for WhisperLimit {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      WhisperLimit::Rate => write!(f, "too many whispers were sent recently"),
+      WhisperLimit::Recipients => write!(f, "{RECIPIENTS_PER_DAY} users were already whispered to today"),
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WhisperOutcome {
+  Sent,
+  /// The whisper was dropped before it was sent, or rejected by Twitch with `429 Too Many Requests`
+  Limited(WhisperLimit),
+}
+
+/// Tracks the whispers sent recently, to drop the ones which Twitch would reject
+#[derive(Debug, Default)]
+pub struct WhisperLimits {
+  /// Send times of the whispers sent in the longest period of [`LIMITS`], oldest first
+  sent: VecDeque<Instant>,
+  /// When each recipient was first whispered to, within the last day
+  recipients: HashMap<String, Instant>,
+}
+
+impl WhisperLimits {
+  /// Returns the limit which a whisper to `recipient` at `now` would exceed, if any
+  pub fn check(&mut self, recipient: &str, now: Instant) -> Option<WhisperLimit> {
+    self.expire(now);
+    let exceeded = LIMITS.iter().any(|(count, period)| {
+      self
+        .sent
+        .iter()
+        .filter(|at| now.saturating_duration_since(**at) < *period)
+        .count()
+        >= *count
+    });
+    if exceeded {
+      Some(WhisperLimit::Rate)
+    } else if !self.recipients.contains_key(recipient) && self.recipients.len() >= RECIPIENTS_PER_DAY {
+      Some(WhisperLimit::Recipients)
+    } else {
+      None
+    }
+  }
+
+  /// Records that a whisper was sent to `recipient` at `now`
+  pub fn sent(&mut self, recipient: &str, now: Instant) {
+    self.sent.push_back(now);
+    self.recipients.entry(recipient.to_owned()).or_insert(now);
+  }
+
+  fn expire(&mut self, now: Instant) {
+    let longest = LIMITS.iter().map(|(_, period)| *period).max().unwrap_or_default();
+    while let Some(at) = self.sent.front() {
+      if now.saturating_duration_since(*at) < longest {
+        break;
+      }
+      self.sent.pop_front();
+    }
+    self.recipients.retain(|_, at| now.saturating_duration_since(*at) < DAY);
+  }
+}
+
+#[derive(serde::Deserialize)]
+struct Validation {
+  client_id: String,
+  user_id: String,
+  #[serde(default)]
+  scopes: Vec<String>,
+}
+
+/// The user whispers are sent as, and the client their token was issued to
+#[derive(Debug, Clone)]
+struct Sender {
+  user_id: String,
+  client_id: String,
+}
+
+/// Sends whispers as the user of the bot's credentials, within Twitch's whisper limits
+pub struct Whispers {
+  client: reqwest::Client,
+  limits: WhisperLimits,
+  /// Looked up with the first whisper, it doesn't change when the token is renewed
+  sender: Option<Sender>,
+}
+
+impl Default for Whispers {
+  fn default() -> Self {
+    Self {
+      client: reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .expect("Failed to create the HTTP client"),
+      limits: WhisperLimits::default(),
+      sender: None,
+    }
+  }
+}
+
+impl Whispers {
+  /// Whispers `text` to the user with the ID `recipient_id`, which is the `user-id` tag of their messages.
+  /// Fails if the credentials are anonymous or lack the `user:manage:whispers` scope, or if Twitch rejects the whisper.
+  pub async fn send(&mut self, credentials: &Credentials, recipient_id: &str, text: &str) -> Result<WhisperOutcome> {
+    if let Some(limit) = self.limits.check(recipient_id, Instant::now()) {
+      return Ok(WhisperOutcome::Limited(limit));
+    }
+    let token = match credentials {
+      Credentials::Anonymous => bail!("Anonymous users can't whisper"),
+      _ => access_token(credentials),
+    };
+    let sender = match &self.sender {
+      Some(sender) => sender.clone(),
+      None => {
+        let sender = self.validate(token).await?;
+        self.sender.insert(sender).clone()
+      }
+    };
+
+    let response = self
+      .client
+      .post(WHISPERS_URL)
+      .query(&[("from_user_id", &sender.user_id[..]), ("to_user_id", recipient_id)])
+      .bearer_auth(token)
+      .header("Client-Id", &sender.client_id)
+      .json(&serde_json::json!({ "message": text }))
+      .send()
+      .await?;
+    if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+      return Ok(WhisperOutcome::Limited(WhisperLimit::Rate));
+    }
+    response.error_for_status()?;
+    self.limits.sent(recipient_id, Instant::now());
+    Ok(WhisperOutcome::Sent)
+  }
+
+  async fn validate(&self, token: &str) -> Result<Sender> {
+    let validation = self
+      .client
+      .get(VALIDATE_URL)
+      .header("Authorization", format!("OAuth {token}"))
+      .send()
+      .await?
+      .error_for_status()?
+      .json::<Validation>()
+      .await
+      .context("Failed to validate the token")?;
+    if !validation.scopes.iter().any(|scope| scope == SCOPE) {
+      bail!("The token lacks the `{SCOPE}` scope, which is required to whisper");
+    }
+    Ok(Sender {
+      user_id: validation.user_id,
+      client_id: validation.client_id,
+    })
+  }
+}
+
+/// The access token of `credentials`, without the `oauth:` prefix of IRC passwords
+fn access_token(credentials: &Credentials) -> &str {
+  let (_, token) = credentials.get();
+  token.strip_prefix("oauth:").unwrap_or(token)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_rate_limits() {
+    let mut limits = WhisperLimits::default();
+    let start = Instant::now();
+    for i in 0..3 {
+      assert_eq!(limits.check("a", start), None);
+      limits.sent("a", start + Duration::from_millis(i));
+    }
+    assert_eq!(limits.check("a", start), Some(WhisperLimit::Rate));
+    assert_eq!(limits.check("a", start + Duration::from_secs(1)), None);
+
+    for i in 0..97 {
+      limits.sent("a", start + Duration::from_secs(2 + i / 3));
+    }
+    assert_eq!(
+      limits.check("a", start + Duration::from_secs(59)),
+      Some(WhisperLimit::Rate)
+    );
+    assert_eq!(limits.check("a", start + Duration::from_secs(61)), None);
+  }
+
+  #[test]
+  fn test_recipient_limit() {
+    let mut limits = WhisperLimits::default();
+    let start = Instant::now();
+    for i in 0..RECIPIENTS_PER_DAY {
+      limits.sent(&i.to_string(), start + Duration::from_secs(i as u64));
+    }
+    let later = start + Duration::from_secs(3600);
+    assert_eq!(limits.check("new", later), Some(WhisperLimit::Recipients));
+    // users who were already whispered to today can still be
+    assert_eq!(limits.check("0", later), None);
+    assert_eq!(limits.check("new", start + DAY), None);
+  }
+}
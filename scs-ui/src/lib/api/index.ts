@@ -85,7 +85,14 @@ export namespace api {
     }
 
     export namespace logs {
-      export type ChannelsResponse = Response<string[]>;
+      export type Channel = {
+        name: string;
+        message_count: number;
+        chatter_count: number;
+        first_message_at?: string;
+        last_message_at?: string;
+      };
+      export type ChannelsResponse = Response<Channel[]>;
       export async function channels(): Promise<ChannelsResponse> {
         return await send("GET", BASE_URL + "/v1/logs/channels", null, access());
       }
@@ -141,6 +141,30 @@ impl FromRequest for AccessToken {
   }
 }
 
+/// An [`AccessToken`] which belongs to a user with admin rights.
+///
+/// Extracting this from a request fails with `403 Forbidden` if the user is not an admin.
+#[derive(Debug, Clone)]
+pub struct AdminToken(pub AccessToken);
+
+impl FromRequest for AdminToken {
+  type Error = crate::error::Error;
+  type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+  fn from_request(req: &actix_web::HttpRequest, payload: &mut actix_http::Payload) -> Self::Future {
+    let auth = AccessToken::from_request(req, payload);
+    let db = req.app_data::<web::Data<db::Database>>().unwrap().clone();
+    Box::pin(async move {
+      let auth = auth.await?;
+      if db::allowlist::is_admin(db.get_ref(), auth.user_id()).await.internal()? {
+        Ok(AdminToken(auth))
+      } else {
+        Err(StatusCode::FORBIDDEN.into())
+      }
+    })
+  }
+}
+
 impl serde::Serialize for AccessToken {
   fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
   where
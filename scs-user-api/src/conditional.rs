@@ -0,0 +1,56 @@
+//! Conditional requests for listings which are polled often, but rarely change.
+//!
+//! Each listing has a version which is cheap to compute, e.g. the modification times of the model files, from which
+//! its ETag is derived. If the client already has the current version, the listing isn't built again, and
+//! `304 Not Modified` is returned instead.
+//!
+//! There's no `Last-Modified` header, as a listing can change without any of its items being modified, e.g. when one
+//! of them is deleted, so `If-Modified-Since` can't tell whether the client's copy is current.
+
+use actix_web::{
+  http::header::{self, CacheDirective, EntityTag},
+  HttpMessage, HttpRequest, HttpResponse, HttpResponseBuilder,
+};
+use sha2::{Digest, Sha256};
+
+pub struct Validator {
+  etag: EntityTag,
+}
+
+impl Validator {
+  /// `version` must change whenever the response does. The tag is weak, because responses may be compressed.
+  pub fn new(version: &str) -> Self {
+    let digest = Sha256::digest(version.as_bytes());
+    Self {
+      etag: EntityTag::new_weak(hex::encode(&digest[..16])),
+    }
+  }
+
+  /// Whether the client's copy is current, according to `If-None-Match`
+  pub fn is_fresh(&self, req: &HttpRequest) -> bool {
+    match req.get_header::<header::IfNoneMatch>() {
+      Some(header::IfNoneMatch::Any) => true,
+      Some(header::IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(&self.etag)),
+      None => false,
+    }
+  }
+
+  pub fn not_modified(&self) -> HttpResponse {
+    self.with_headers(HttpResponse::NotModified()).finish()
+  }
+
+  /// `200 OK` with the validators, to which the body is added
+  pub fn ok(&self) -> HttpResponseBuilder {
+    self.with_headers(HttpResponse::Ok())
+  }
+
+  fn with_headers(&self, mut builder: HttpResponseBuilder) -> HttpResponseBuilder {
+    builder.insert_header(header::ETag(self.etag.clone()));
+    // Listings require a token, and must always be revalidated
+    builder.insert_header(header::CacheControl(vec![
+      CacheDirective::Private,
+      CacheDirective::NoCache,
+    ]));
+    builder
+  }
+}
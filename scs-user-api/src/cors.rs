@@ -0,0 +1,35 @@
+use scs_config::cors::CorsSettings;
+use structopt::StructOpt;
+
+#[derive(Clone, Debug, StructOpt)]
+pub struct CorsOptions {
+  /// Origins which may call the API from a browser, comma-separated. `*` allows any origin, but without credentials.
+  /// Defaults to localhost in debug builds, and to no origins otherwise.
+  #[structopt(long = "cors-origins", env = "SCS_USER_API_CORS_ORIGINS", use_delimiter = true)]
+  origins: Vec<String>,
+  #[structopt(
+    long = "cors-methods",
+    env = "SCS_USER_API_CORS_METHODS",
+    use_delimiter = true,
+    default_value = "GET,POST,DELETE"
+  )]
+  methods: Vec<String>,
+  /// Request headers allowed in addition to `Authorization`, `Accept`, `Content-Type`, and `X-Request-Id`
+  #[structopt(long = "cors-headers", env = "SCS_USER_API_CORS_HEADERS", use_delimiter = true)]
+  headers: Vec<String>,
+  /// How long browsers may cache the preflight response, in seconds
+  #[structopt(long = "cors-max-age", env = "SCS_USER_API_CORS_MAX_AGE", default_value = "3600")]
+  max_age: usize,
+}
+
+impl CorsOptions {
+  /// The policy is shared with the manage API, which reads it from its config file
+  pub fn settings(&self) -> CorsSettings {
+    CorsSettings {
+      origins: self.origins.clone(),
+      methods: self.methods.clone(),
+      headers: self.headers.clone(),
+      max_age: self.max_age,
+    }
+  }
+}
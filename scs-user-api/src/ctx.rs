@@ -1,7 +1,17 @@
-use crate::schema;
-use chrono::DateTime;
+use crate::{
+  model_options::{self, ModelOptions},
+  schema, webhooks,
+};
+use chain::TextGenerator;
+use chrono::{DateTime, Utc};
 use futures::TryStreamExt;
-use std::{ffi::OsStr, path::PathBuf, sync::Arc};
+use std::{
+  collections::HashMap,
+  ffi::OsStr,
+  path::{Path, PathBuf},
+  sync::Arc,
+  time::{Duration, SystemTime},
+};
 use tokio::sync::RwLock;
 
 #[inline]
@@ -9,53 +19,444 @@ fn bytes_to_megabytes(bytes: u64) -> f64 {
   (bytes as f64) / (1024.0 * 1024.0)
 }
 
+/// Directory of the model directory which deleted models are moved to, with a subdirectory per model
+const TRASH_DIR: &str = ".trash";
+/// File of a trashed model's directory which holds its [`schema::Tombstone`]
+const TOMBSTONE_FILE: &str = "tombstone.json";
+
+/// Result of [`State::restore_model`]
+pub enum RestoreOutcome {
+  Restored(schema::Tombstone),
+  NotTrashed,
+  /// Another model with the same name was created since
+  Exists,
+}
+
 pub struct State {
   models_dir: PathBuf,
+  /// Loaded models, along with the modification time of the file they were loaded from
+  models: HashMap<String, (SystemTime, Arc<dyn TextGenerator>)>,
+  /// Summaries of model files, along with the modification time of the file they were read from
+  summaries: HashMap<String, (SystemTime, chain::ser::ModelSummary)>,
+  /// Headers of model files, along with the modification time of the file they were read from
+  headers: HashMap<String, (DateTime<Utc>, schema::ModelMeta)>,
+  /// Modification time of each model as of the last call to `poll_model_changes`
+  known_models: Option<HashMap<String, DateTime<Utc>>>,
+  /// Temporary models trained by users, by ID
+  session_models: HashMap<String, SessionModel>,
+}
+
+/// Maximum number of session models per user, the oldest one is evicted to make room for a new one
+const MAX_SESSION_MODELS_PER_USER: usize = 3;
+/// How long a session model is kept after it's created
+const SESSION_MODEL_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// A model trained on a corpus uploaded by a user, only available to that user
+pub struct SessionModel {
+  pub user_id: i32,
+  pub created_at: DateTime<Utc>,
+  pub expires_at: DateTime<Utc>,
+  pub model: Arc<dyn TextGenerator>,
 }
 
 impl State {
   pub fn new(models_dir: PathBuf) -> Self {
-    Self { models_dir }
+    Self {
+      models_dir,
+      models: HashMap::new(),
+      summaries: HashMap::new(),
+      headers: HashMap::new(),
+      known_models: None,
+      session_models: HashMap::new(),
+    }
+  }
+
+  /// Stores `model` for `user_id`, and returns its ID and expiry date
+  pub fn add_session_model(&mut self, user_id: i32, model: Arc<dyn TextGenerator>) -> (String, DateTime<Utc>) {
+    use rand::{distributions::Alphanumeric, thread_rng, Rng};
+
+    self.evict_expired_session_models();
+    let mut owned = self
+      .session_models
+      .iter()
+      .filter(|(_, m)| m.user_id == user_id)
+      .map(|(id, m)| (m.created_at, id.clone()))
+      .collect::<Vec<_>>();
+    owned.sort();
+    for (_, id) in owned.iter().rev().skip(MAX_SESSION_MODELS_PER_USER - 1) {
+      self.session_models.remove(id);
+    }
+
+    let id: String = thread_rng()
+      .sample_iter(&Alphanumeric)
+      .take(16)
+      .map(char::from)
+      .collect();
+    let created_at = Utc::now();
+    let expires_at = created_at + chrono::Duration::from_std(SESSION_MODEL_TTL).unwrap();
+    self.session_models.insert(
+      id.clone(),
+      SessionModel {
+        user_id,
+        created_at,
+        expires_at,
+        model,
+      },
+    );
+    (id, expires_at)
+  }
+
+  /// Returns the session model with the ID `id` if it belongs to `user_id` and hasn't expired
+  pub fn get_session_model(&self, user_id: i32, id: &str) -> Option<Arc<dyn TextGenerator>> {
+    self
+      .session_models
+      .get(id)
+      .filter(|m| m.user_id == user_id && m.expires_at > Utc::now())
+      .map(|m| m.model.clone())
+  }
+
+  /// Returns `false` if the model doesn't exist, or doesn't belong to `user_id`
+  pub fn remove_session_model(&mut self, user_id: i32, id: &str) -> bool {
+    match self.session_models.get(id) {
+      Some(m) if m.user_id == user_id => self.session_models.remove(id).is_some(),
+      _ => false,
+    }
+  }
+
+  /// Returns the number of evicted models
+  pub fn evict_expired_session_models(&mut self) -> usize {
+    let now = Utc::now();
+    let before = self.session_models.len();
+    self.session_models.retain(|_, m| m.expires_at > now);
+    before - self.session_models.len()
+  }
+
+  /// Records the current `models`, and returns the ones which were created or updated since the previous call.
+  ///
+  /// The first call only records the models, and returns nothing.
+  pub fn record_model_changes(
+    &mut self,
+    models: Vec<schema::SimpleModelInfo>,
+  ) -> Vec<(webhooks::Event, schema::SimpleModelInfo)> {
+    let current = models
+      .iter()
+      .map(|m| (m.name.clone(), m.date_modified))
+      .collect::<HashMap<_, _>>();
+
+    let changes = match self.known_models.replace(current) {
+      None => vec![],
+      Some(known) => models
+        .into_iter()
+        .filter_map(|model| match known.get(&model.name) {
+          None => Some((webhooks::Event::Created, model)),
+          Some(modified) if *modified != model.date_modified => Some((webhooks::Event::Updated, model)),
+          Some(_) => None,
+        })
+        .collect(),
+    };
+    changes
+  }
+
+  /// Returns the model called `name`, or `None` if it doesn't exist.
+  ///
+  /// Models are cached, and reloaded once the file is modified, or a delta is saved next to it.
+  pub async fn get_model(&mut self, name: &str) -> anyhow::Result<Option<Arc<dyn TextGenerator>>> {
+    if !chain::is_valid_model_name(name) {
+      return Ok(None);
+    }
+
+    let path = self.models_dir.join(format!("{name}.chain"));
+    let modified = match async_fs::metadata(&path).await {
+      Ok(metadata) => metadata.modified()?,
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+      Err(e) => return Err(e.into()),
+    };
+    let modified = match latest_delta(&self.models_dir, name).await? {
+      Some(delta) => delta.max(modified),
+      None => modified,
+    };
+
+    if let Some((loaded_at, model)) = self.models.get(name) {
+      if *loaded_at == modified {
+        return Ok(Some(model.clone()));
+      }
+    }
+
+    log::info!("Loading model {}", path.display());
+    let model: Arc<dyn TextGenerator> =
+      Arc::from(tokio::task::spawn_blocking(move || chain::load_chain_of_any_supported_order(path)).await??);
+    log::info!(
+      "Loaded model {} (~{:.2} MB)",
+      name,
+      bytes_to_megabytes(model.memory_estimate().total() as u64)
+    );
+    self.models.insert(name.to_owned(), (modified, model.clone()));
+    Ok(Some(model))
+  }
+
+  /// Returns the path of the model called `name`, or `None` if it doesn't exist
+  async fn existing_model_path(&self, name: &str) -> anyhow::Result<Option<PathBuf>> {
+    if !chain::is_valid_model_name(name) {
+      return Ok(None);
+    }
+    let path = self.models_dir.join(format!("{name}.chain"));
+    match async_fs::metadata(&path).await {
+      Ok(_) => Ok(Some(path)),
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+      Err(e) => Err(e.into()),
+    }
   }
 
-  /// Returns a list of models
+  /// Returns the default generation options of the model called `name`, or `None` if it doesn't exist.
+  ///
+  /// The options are read on every call, so that edits apply to the next request.
+  pub async fn get_model_options(&self, name: &str) -> anyhow::Result<Option<ModelOptions>> {
+    use anyhow::Context;
+
+    let Some(path) = self.existing_model_path(name).await? else {
+      return Ok(None);
+    };
+    let path = model_options::path_of(&path);
+    match async_fs::read(&path).await {
+      Ok(bytes) => serde_json::from_slice(&bytes)
+        .map(Some)
+        .with_context(|| format!("Invalid model options in {}", path.display())),
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Some(ModelOptions::default())),
+      Err(e) => Err(e.into()),
+    }
+  }
+
+  /// Stores the default generation options of the model called `name`. Returns `false` if it doesn't exist.
+  pub async fn set_model_options(&self, name: &str, options: &ModelOptions) -> anyhow::Result<bool> {
+    let Some(path) = self.existing_model_path(name).await? else {
+      return Ok(false);
+    };
+    write_atomically(&model_options::path_of(&path), &serde_json::to_vec_pretty(options)?).await?;
+    Ok(true)
+  }
+
+  /// Removes the stored options of the model called `name`, so that it uses the defaults.
+  /// Returns `false` if it doesn't exist.
+  pub async fn reset_model_options(&self, name: &str) -> anyhow::Result<bool> {
+    let Some(path) = self.existing_model_path(name).await? else {
+      return Ok(false);
+    };
+    match async_fs::remove_file(model_options::path_of(&path)).await {
+      Ok(()) => Ok(true),
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(true),
+      Err(e) => Err(e.into()),
+    }
+  }
+
+  /// Moves the model called `name` to the trash, along with its options and deltas, replacing a trashed model of the
+  /// same name. Returns `None` if it doesn't exist.
+  pub async fn trash_model(&mut self, name: &str, deleted_by: i32) -> anyhow::Result<Option<schema::Tombstone>> {
+    let Some(path) = self.existing_model_path(name).await? else {
+      return Ok(None);
+    };
+    let trash = self.models_dir.join(TRASH_DIR).join(name);
+    match async_fs::remove_dir_all(&trash).await {
+      Ok(()) => log::info!("Replacing the trashed model {name}"),
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+      Err(e) => return Err(e.into()),
+    }
+    async_fs::create_dir_all(&trash).await?;
+
+    let tombstone = schema::Tombstone {
+      name: name.to_owned(),
+      deleted_by,
+      deleted_at: Utc::now(),
+    };
+    write_atomically(&trash.join(TOMBSTONE_FILE), &serde_json::to_vec_pretty(&tombstone)?).await?;
+    // The model is moved first, so that it disappears at once
+    let mut files = model_files(&self.models_dir, name).await?;
+    files.sort_by_key(|file| *file != path);
+    for file in files {
+      let file_name = file.file_name().map(ToOwned::to_owned).unwrap_or_default();
+      async_fs::rename(&file, trash.join(file_name)).await?;
+    }
+    self.forget(name);
+    Ok(Some(tombstone))
+  }
+
+  /// Moves the model called `name` back from the trash, unless another model of the same name was created since
+  pub async fn restore_model(&mut self, name: &str) -> anyhow::Result<RestoreOutcome> {
+    if !chain::is_valid_model_name(name) {
+      return Ok(RestoreOutcome::NotTrashed);
+    }
+    let trash = self.models_dir.join(TRASH_DIR).join(name);
+    let tombstone = match async_fs::read(trash.join(TOMBSTONE_FILE)).await {
+      Ok(bytes) => serde_json::from_slice::<schema::Tombstone>(&bytes)?,
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(RestoreOutcome::NotTrashed),
+      Err(e) => return Err(e.into()),
+    };
+    if self.existing_model_path(name).await?.is_some() {
+      return Ok(RestoreOutcome::Exists);
+    }
+
+    // The model is moved last, so that it only appears once its options and deltas are back
+    let model_path = trash.join(format!("{name}.chain"));
+    let mut files = model_files(&trash, name).await?;
+    files.sort_by_key(|file| *file == model_path);
+    for file in files {
+      let file_name = file.file_name().map(ToOwned::to_owned).unwrap_or_default();
+      async_fs::rename(&file, self.models_dir.join(file_name)).await?;
+    }
+    async_fs::remove_dir_all(&trash).await?;
+    self.forget(name);
+    Ok(RestoreOutcome::Restored(tombstone))
+  }
+
+  /// Permanently removes the models which were moved to the trash more than `retention` ago, and returns their names
+  pub async fn purge_trash(&self, retention: chrono::Duration) -> anyhow::Result<Vec<String>> {
+    let mut entries = match async_fs::read_dir(self.models_dir.join(TRASH_DIR)).await {
+      Ok(entries) => entries,
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+      Err(e) => return Err(e.into()),
+    };
+    let mut purged = Vec::new();
+    while let Some(entry) = entries.try_next().await? {
+      let tombstone = match async_fs::read(entry.path().join(TOMBSTONE_FILE)).await {
+        Ok(bytes) => serde_json::from_slice::<schema::Tombstone>(&bytes)?,
+        Err(e) => {
+          log::warn!("Skipping {} in the trash: {e}", entry.path().display());
+          continue;
+        }
+      };
+      if Utc::now() - tombstone.deleted_at >= retention {
+        async_fs::remove_dir_all(entry.path()).await?;
+        purged.push(tombstone.name);
+      }
+    }
+    Ok(purged)
+  }
+
+  /// Drops everything cached about the model called `name`
+  fn forget(&mut self, name: &str) {
+    self.models.remove(name);
+    self.summaries.remove(name);
+    self.headers.remove(name);
+  }
+
+  /// Returns a list of models. Their modification time is the latest of the model and its deltas.
   pub async fn get_models(&self) -> anyhow::Result<Vec<schema::SimpleModelInfo>> {
     // TODO: load the model to acquire `order` and `channels`
     // after loading, put it in a cache which:
     //   - evicts after some time
     //   - reloads if a new version is available
-    use anyhow::Context;
+    list_models(&self.models_dir).await
+  }
+}
 
-    let mut models = Vec::new();
+/// Returns the models in `models_dir`, along with the latest modification time of each one or its deltas
+async fn list_models(models_dir: &Path) -> anyhow::Result<Vec<schema::SimpleModelInfo>> {
+  use anyhow::Context;
 
-    let mut entries = async_fs::read_dir(&self.models_dir).await?;
-    while let Some(entry) = entries.try_next().await? {
-      let metadata = entry.metadata().await?;
-      let path = entry.path();
+  let mut models = Vec::new();
+  let mut deltas = HashMap::<String, DateTime<Utc>>::new();
 
-      if path.extension() != Some(OsStr::new("chain")) {
-        continue;
-      }
+  let mut entries = async_fs::read_dir(models_dir).await?;
+  while let Some(entry) = entries.try_next().await? {
+    let metadata = entry.metadata().await?;
+    let path = entry.path();
 
-      let name = path
-        .file_stem()
-        .map(|v| v.to_string_lossy())
-        .context("Invalid file stem")?
-        .to_string();
-      let date_created = DateTime::from(metadata.created()?);
-      let date_modified = DateTime::from(metadata.modified()?);
-      let size = bytes_to_megabytes(metadata.len());
-
-      models.push(schema::SimpleModelInfo {
-        name,
-        date_created,
-        date_modified,
-        size,
-      })
+    if let Some(name) = delta_model_name(&entry.file_name().to_string_lossy()) {
+      let modified = DateTime::from(metadata.modified()?);
+      let latest = deltas.entry(name.to_owned()).or_insert(modified);
+      *latest = modified.max(*latest);
+      continue;
+    }
+    if path.extension() != Some(OsStr::new("chain")) {
+      continue;
     }
 
-    Ok(models)
+    let name = path
+      .file_stem()
+      .map(|v| v.to_string_lossy())
+      .context("Invalid file stem")?
+      .to_string();
+    models.push(simple_model_info(name, &metadata)?);
   }
+
+  for model in &mut models {
+    if let Some(delta) = deltas.get(&model.name) {
+      model.date_modified = model.date_modified.max(*delta);
+    }
+  }
+  Ok(models)
+}
+
+/// Writes `contents` to a temporary file next to `path`, and renames it, so that readers never see a partial file
+async fn write_atomically(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+  let mut tmp = path.as_os_str().to_owned();
+  tmp.push(".tmp");
+  async_fs::write(&tmp, contents).await?;
+  async_fs::rename(&tmp, path).await
+}
+
+/// Returns the files of the model called `name` in `dir`: the model itself, its options, its deltas, and the statistics
+/// of its corpus
+async fn model_files(dir: &Path, name: &str) -> anyhow::Result<Vec<PathBuf>> {
+  let model = format!("{name}.chain");
+  let prefix = format!("{model}.");
+  let stats = format!("{name}.stats.json");
+  let mut files = Vec::new();
+  let mut entries = async_fs::read_dir(dir).await?;
+  while let Some(entry) = entries.try_next().await? {
+    let file_name = entry.file_name();
+    let file_name = file_name.to_string_lossy();
+    if file_name == model || file_name.starts_with(&prefix) || file_name == stats {
+      files.push(entry.path());
+    }
+  }
+  Ok(files)
+}
+
+/// Returns the name of the model whose delta is called `file_name`, e.g. `forsen` for `forsen.chain.0001.delta`
+fn delta_model_name(file_name: &str) -> Option<&str> {
+  let (name, sequence) = file_name.strip_suffix(".delta")?.rsplit_once(".chain.")?;
+  sequence.bytes().all(|b| b.is_ascii_digit()).then_some(name)
+}
+
+/// Returns when the latest delta of the model called `name` in `dir` was saved, if it has any
+async fn latest_delta(dir: &Path, name: &str) -> anyhow::Result<Option<SystemTime>> {
+  let mut latest = None;
+  for path in model_files(dir, name).await? {
+    let is_delta = path
+      .file_name()
+      .and_then(|file_name| delta_model_name(&file_name.to_string_lossy()).map(|model| model == name))
+      .unwrap_or(false);
+    if is_delta {
+      let modified = async_fs::metadata(&path).await?.modified()?;
+      latest = latest.max(Some(modified));
+    }
+  }
+  Ok(latest)
+}
+
+/// Parses the channels out of metadata written by `train`, like `{ channels: forsen,xqc; order: 2 }`
+fn channels_of(metadata: &str) -> Vec<String> {
+  metadata
+    .trim_start_matches('{')
+    .trim_end_matches('}')
+    .split(';')
+    .filter_map(|field| field.trim().strip_prefix("channels:"))
+    .flat_map(|channels| channels.split(','))
+    .map(str::trim)
+    .filter(|channel| !channel.is_empty())
+    .map(str::to_owned)
+    .collect()
+}
+
+fn simple_model_info(name: String, metadata: &std::fs::Metadata) -> std::io::Result<schema::SimpleModelInfo> {
+  Ok(schema::SimpleModelInfo {
+    name,
+    date_created: DateTime::from(metadata.created()?),
+    date_modified: DateTime::from(metadata.modified()?),
+    size: bytes_to_megabytes(metadata.len()),
+    meta: None,
+    usage: None,
+  })
 }
 
 #[derive(Clone)]
@@ -73,4 +474,292 @@ impl Context {
   pub async fn write(&self) -> tokio::sync::RwLockWriteGuard<'_, State> {
     self.0.write().await
   }
+
+  /// Returns information about the model called `name`, or `None` if it doesn't exist.
+  ///
+  /// The file is scanned without loading the model, and the result is cached until the file is modified. It's scanned
+  /// without holding the lock, so that other requests aren't blocked meanwhile.
+  pub async fn get_model_info(&self, name: &str) -> anyhow::Result<Option<schema::ModelInfo>> {
+    if !chain::is_valid_model_name(name) {
+      return Ok(None);
+    }
+
+    let path = self.read().await.models_dir.join(format!("{name}.chain"));
+    let metadata = match async_fs::metadata(&path).await {
+      Ok(metadata) => metadata,
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+      Err(e) => return Err(e.into()),
+    };
+    let modified = metadata.modified()?;
+
+    let cached = match self.read().await.summaries.get(name) {
+      Some((scanned_at, summary)) if *scanned_at == modified => Some(summary.clone()),
+      _ => None,
+    };
+    let summary = match cached {
+      Some(summary) => summary,
+      None => {
+        let summary = tokio::task::spawn_blocking(move || {
+          chain::ser::validate(&mut std::io::BufReader::new(std::fs::File::open(path)?))
+        })
+        .await??;
+        self
+          .write()
+          .await
+          .summaries
+          .insert(name.to_owned(), (modified, summary.clone()));
+        summary
+      }
+    };
+
+    Ok(Some(schema::ModelInfo {
+      file: simple_model_info(name.to_owned(), &metadata)?,
+      order: summary.order,
+      metadata: summary.metadata,
+      words: summary.words,
+      nodes: summary.nodes,
+      edges: summary.edges,
+      memory_estimate: summary.memory_estimate.into(),
+      loaded: self.read().await.models.contains_key(name),
+    }))
+  }
+
+  /// Returns the models which were created or updated since the previous call, see [`State::record_model_changes`].
+  ///
+  /// The model directory is scanned without holding the lock, which is only taken to record the result.
+  pub async fn poll_model_changes(&self) -> anyhow::Result<Vec<(webhooks::Event, schema::SimpleModelInfo)>> {
+    let models_dir = self.read().await.models_dir.clone();
+    let models = list_models(&models_dir).await?;
+    Ok(self.write().await.record_model_changes(models))
+  }
+
+  /// Returns the header metadata of every model in `models`, by name.
+  ///
+  /// Headers are cached, and only read again for the models which were modified since. They're read without holding
+  /// the lock, so that other requests aren't blocked meanwhile. Models whose header can't be read are logged and left
+  /// out, so that a single corrupt file doesn't fail the whole list.
+  pub async fn get_models_meta(
+    &self,
+    models: &[&schema::SimpleModelInfo],
+  ) -> anyhow::Result<HashMap<String, schema::ModelMeta>> {
+    let (mut metas, models_dir) = {
+      let state = self.read().await;
+      let metas = models
+        .iter()
+        .filter_map(|model| match state.headers.get(&model.name) {
+          Some((read_at, meta)) if *read_at == model.date_modified => Some((model.name.clone(), meta.clone())),
+          _ => None,
+        })
+        .collect::<HashMap<_, _>>();
+      (metas, state.models_dir.clone())
+    };
+
+    let mut read = Vec::new();
+    for model in models.iter().filter(|model| !metas.contains_key(&model.name)) {
+      let path = models_dir.join(format!("{}.chain", model.name));
+      let header = tokio::task::spawn_blocking(move || {
+        chain::ser::read_header(&mut std::io::BufReader::new(std::fs::File::open(path)?))
+      })
+      .await?;
+      let (order, metadata) = match header {
+        Ok(header) => header,
+        Err(e) => {
+          log::warn!("Failed to read the header of {}: {e}", model.name);
+          continue;
+        }
+      };
+      let meta = schema::ModelMeta {
+        order: order as usize,
+        channels: channels_of(&metadata),
+        metadata,
+      };
+      read.push((model, meta));
+    }
+
+    if !read.is_empty() {
+      let mut state = self.write().await;
+      for (model, meta) in read {
+        state
+          .headers
+          .insert(model.name.clone(), (model.date_modified, meta.clone()));
+        metas.insert(model.name.clone(), meta);
+      }
+    }
+    Ok(metas)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn models_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("scs-user-api-{test}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    for file in [
+      "forsen.chain",
+      "forsen.chain.options.json",
+      "forsen.chain.0001.delta",
+      "forsen.stats.json",
+      "xqc.chain",
+    ] {
+      std::fs::write(dir.join(file), file).unwrap();
+    }
+    dir
+  }
+
+  fn files(dir: &Path) -> Vec<String> {
+    let mut files = std::fs::read_dir(dir)
+      .unwrap()
+      .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+      .collect::<Vec<_>>();
+    files.sort();
+    files
+  }
+
+  #[test]
+  fn test_delta_model_name() {
+    assert_eq!(delta_model_name("forsen.chain.0001.delta"), Some("forsen"));
+    assert_eq!(delta_model_name("forsen.chain.options.json"), None);
+    assert_eq!(delta_model_name("forsen.chain.tmp.delta"), None);
+    assert_eq!(delta_model_name("forsen.chain"), None);
+  }
+
+  #[tokio::test]
+  async fn test_deltas_update_the_modification_time() {
+    let dir = models_dir("deltas");
+    let mut state = State::new(dir.clone());
+    let modified = |models: &[schema::SimpleModelInfo], name: &str| {
+      models.iter().find(|model| model.name == name).unwrap().date_modified
+    };
+    let model = std::fs::metadata(dir.join("forsen.chain")).unwrap().modified().unwrap();
+
+    let later = model + Duration::from_secs(60);
+    std::fs::File::options()
+      .write(true)
+      .open(dir.join("forsen.chain.0001.delta"))
+      .unwrap()
+      .set_modified(later)
+      .unwrap();
+    let models = state.get_models().await.unwrap();
+    assert_eq!(modified(&models, "forsen"), DateTime::<Utc>::from(later));
+    assert_ne!(modified(&models, "xqc"), DateTime::<Utc>::from(later));
+    assert_eq!(latest_delta(&dir, "forsen").await.unwrap(), Some(later));
+    assert_eq!(latest_delta(&dir, "xqc").await.unwrap(), None);
+
+    // The first poll only records the models
+    state.record_model_changes(models);
+    let later = later + Duration::from_secs(60);
+    std::fs::write(dir.join("forsen.chain.0002.delta"), "delta").unwrap();
+    std::fs::File::options()
+      .write(true)
+      .open(dir.join("forsen.chain.0002.delta"))
+      .unwrap()
+      .set_modified(later)
+      .unwrap();
+    let models = state.get_models().await.unwrap();
+    let changes = state.record_model_changes(models);
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].1.name, "forsen");
+  }
+
+  #[tokio::test]
+  async fn test_corrupt_headers_are_left_out() {
+    let dir = models_dir("headers");
+    // `forsen.chain` doesn't have a valid header
+    std::fs::write(dir.join("xqc.chain"), b"chain:\x02;").unwrap();
+    let ctx = Context::new(State::new(dir.clone()));
+
+    let models = ctx.read().await.get_models().await.unwrap();
+    let metas = ctx.get_models_meta(&models.iter().collect::<Vec<_>>()).await.unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(metas.keys().collect::<Vec<_>>(), vec!["xqc"]);
+    assert_eq!(metas["xqc"].order, 2);
+  }
+
+  #[tokio::test]
+  async fn test_trash_and_restore() {
+    let dir = models_dir("trash");
+    let mut state = State::new(dir.clone());
+
+    let tombstone = state.trash_model("forsen", 1).await.unwrap().unwrap();
+    assert_eq!((tombstone.name.as_str(), tombstone.deleted_by), ("forsen", 1));
+    assert_eq!(files(&dir), vec![TRASH_DIR, "xqc.chain"]);
+    assert_eq!(
+      files(&dir.join(TRASH_DIR).join("forsen")),
+      vec![
+        "forsen.chain",
+        "forsen.chain.0001.delta",
+        "forsen.chain.options.json",
+        "forsen.stats.json",
+        TOMBSTONE_FILE
+      ]
+    );
+    assert!(state.trash_model("forsen", 1).await.unwrap().is_none());
+    assert!(state.trash_model("../xqc", 1).await.unwrap().is_none());
+
+    assert!(matches!(
+      state.restore_model("forsen").await.unwrap(),
+      RestoreOutcome::Restored(tombstone) if tombstone.deleted_by == 1
+    ));
+    assert_eq!(
+      files(&dir),
+      vec![
+        TRASH_DIR,
+        "forsen.chain",
+        "forsen.chain.0001.delta",
+        "forsen.chain.options.json",
+        "forsen.stats.json",
+        "xqc.chain"
+      ]
+    );
+    assert!(matches!(
+      state.restore_model("forsen").await.unwrap(),
+      RestoreOutcome::NotTrashed
+    ));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[tokio::test]
+  async fn test_restore_conflict() {
+    let dir = models_dir("restore-conflict");
+    let mut state = State::new(dir.clone());
+
+    state.trash_model("forsen", 1).await.unwrap().unwrap();
+    std::fs::write(dir.join("forsen.chain"), "new").unwrap();
+    assert!(matches!(
+      state.restore_model("forsen").await.unwrap(),
+      RestoreOutcome::Exists
+    ));
+    assert_eq!(std::fs::read_to_string(dir.join("forsen.chain")).unwrap(), "new");
+    assert!(dir.join(TRASH_DIR).join("forsen").join("forsen.chain").exists());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[tokio::test]
+  async fn test_purge_trash() {
+    let dir = models_dir("purge");
+    let mut state = State::new(dir.clone());
+    assert!(state.purge_trash(chrono::Duration::zero()).await.unwrap().is_empty());
+
+    state.trash_model("forsen", 1).await.unwrap().unwrap();
+    state.trash_model("xqc", 1).await.unwrap().unwrap();
+    assert!(state.purge_trash(chrono::Duration::days(1)).await.unwrap().is_empty());
+    assert_eq!(files(&dir.join(TRASH_DIR)), vec!["forsen", "xqc"]);
+
+    let mut purged = state.purge_trash(chrono::Duration::zero()).await.unwrap();
+    purged.sort();
+    assert_eq!(purged, vec!["forsen", "xqc"]);
+    assert!(files(&dir.join(TRASH_DIR)).is_empty());
+    assert!(matches!(
+      state.restore_model("forsen").await.unwrap(),
+      RestoreOutcome::NotTrashed
+    ));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
 }
@@ -1,38 +1,91 @@
-use actix_cors::Cors;
-use actix_web::{self, get, http::header, middleware, web::Data, App, HttpResponse, HttpServer};
+use actix_web::{self, get, middleware, web::Data, App, HttpResponse, HttpServer};
 use db::ConnString;
-use std::{env, path::PathBuf};
+use std::{env, path::PathBuf, time::Duration};
 use structopt::StructOpt;
 
 mod auth;
+mod conditional;
+mod cors;
 mod ctx;
 mod error;
 mod ex;
+mod model_options;
+mod moderation;
+mod poster;
+mod rate_limit;
+mod request_id;
+mod sample_cache;
 mod schema;
+mod timezone;
+mod tls;
 mod v1;
+mod webhooks;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "scs-user-api", about = "SCS User API")]
 struct Options {
+  /// Address the API is served on
   #[structopt(long, env = "SCS_USER_API_HOST", default_value = "127.0.0.1")]
   host: String,
+  /// Port the API is served on. Named so that it isn't mistaken for the database's `--db-port`
+  #[structopt(long, env = "SCS_USER_API_HTTP_PORT", default_value = "8080")]
+  http_port: u16,
   #[structopt(long, env = "SCS_USER_API_CLIENT_SECRET")]
   secret: String,
   #[structopt(long, env = "SCS_USER_API_MODEL_DIR", parse(from_os_str))]
   model_dir: Option<PathBuf>,
+  /// How often the model directory is checked for new models, in seconds
+  #[structopt(long, env = "SCS_USER_API_MODEL_POLL_INTERVAL", default_value = "60")]
+  model_poll_interval: u64,
+  /// Maximum number of requests per minute to shared generations from a single address
+  #[structopt(long, env = "SCS_USER_API_SHARE_RATE_LIMIT", default_value = "30")]
+  share_rate_limit: u32,
+  /// Addresses of the reverse proxies whose `Forwarded` and `X-Forwarded-For` headers are trusted, to rate limit by
+  /// the address of the client instead of the proxy's
+  #[structopt(long = "trusted-proxy", env = "SCS_USER_API_TRUSTED_PROXIES", use_delimiter = true)]
+  trusted_proxies: Vec<std::net::IpAddr>,
+  /// Maximum number of recently generated texts which are kept in memory, zero disables the cache
+  #[structopt(long, env = "SCS_USER_API_SAMPLE_CACHE_SIZE", default_value = "256")]
+  sample_cache_size: usize,
+  /// How long generated texts are kept in memory, in seconds
+  #[structopt(long, env = "SCS_USER_API_SAMPLE_CACHE_TTL", default_value = "10")]
+  sample_cache_ttl: u64,
+  /// Status endpoint of the collector, which the admin summary reports the liveness of
+  #[structopt(long, env = "SCS_USER_API_COLLECTOR_STATUS_URL")]
+  collector_status_url: Option<reqwest::Url>,
+  /// How long deleted models are kept in the trash before they're purged, in days
+  #[structopt(long, env = "SCS_USER_API_MODEL_TRASH_RETENTION_DAYS", default_value = "30")]
+  model_trash_retention_days: i64,
+  /// How long users can cancel the deletion of their account, in days
+  #[structopt(long, env = "SCS_USER_API_ACCOUNT_DELETION_GRACE_DAYS", default_value = "7")]
+  account_deletion_grace_days: i64,
+  /// Whether the database query metrics are recorded and served on `/metrics`
+  #[structopt(long, env = "SCS_USER_API_METRICS", parse(try_from_str), default_value = "false")]
+  metrics: bool,
+  #[structopt(flatten)]
+  cors: cors::CorsOptions,
+  #[structopt(flatten)]
+  poster: poster::PosterOptions,
+  #[structopt(flatten)]
+  moderation: moderation::ModerationOptions,
+  #[structopt(flatten)]
+  tls: tls::TlsOptions,
+  #[structopt(flatten)]
+  db: DbOptions,
 }
 
-#[derive(StructOpt)]
+/// Database connection, its flags are prefixed with `db-` so that they don't clash with the API's own `--host`
+#[derive(Debug, StructOpt)]
 struct DbOptions {
-  #[structopt(long, env = "SCS_DB_HOST", default_value = "localhost")]
+  #[structopt(long = "db-host", env = "SCS_DB_HOST", default_value = "localhost")]
   host: String,
-  #[structopt(long, env = "SCS_DB_PORT", default_value = "5432")]
+  #[structopt(long = "db-port", env = "SCS_DB_PORT", default_value = "5432")]
   port: u16,
-  #[structopt(long, env = "SCS_DB_NAME", default_value = "scs")]
+  #[structopt(long = "db-name", env = "SCS_DB_NAME", default_value = "scs")]
   name: String,
-  #[structopt(long, env = "SCS_DB_USER", default_value = "scs")]
+  #[structopt(long = "db-user", env = "SCS_DB_USER", default_value = "scs")]
   user: String,
-  #[structopt(long, env = "SCS_DB_PASSWORD")]
+  #[structopt(long = "db-password", env = "SCS_DB_PASSWORD", hide_env_values = true)]
   password: Option<String>,
 }
 
@@ -53,15 +106,29 @@ async fn health_check() -> HttpResponse {
   HttpResponse::Ok().finish()
 }
 
+#[get("/metrics")]
+async fn metrics() -> HttpResponse {
+  HttpResponse::Ok()
+    .content_type("text/plain; version=0.0.4")
+    .body(db::metrics::render())
+}
+
 #[actix_web::main]
 async fn main() -> anyhow::Result<()> {
   if std::env::var("RUST_LOG").is_err() {
     env::set_var("RUST_LOG", "info,actix_web=debug"); // actix_web=debug enables error logging
   }
-  env_logger::init();
+  // `log` records are forwarded to `tracing`, so they're emitted inside the span of the request they belong to.
+  // Closed spans are logged along with their duration.
+  tracing_subscriber::fmt()
+    .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+    .with_span_events(tracing_subscriber::fmt::format::FmtSpan::CLOSE)
+    .init();
 
   let options = Options::from_args_safe()?;
-  let db_options = DbOptions::from_args_safe()?;
+  let cors = options.cors.settings();
+  cors.validate()?;
+  let tls = options.tls.server_config()?;
 
   let client_secret = auth::ClientSecret(options.secret);
   let model_dir = options.model_dir.unwrap_or_else(|| {
@@ -71,32 +138,149 @@ async fn main() -> anyhow::Result<()> {
   });
 
   let ctx = ctx::Context::new(ctx::State::new(model_dir));
-  let db = db::connect(db_options).await?;
+  let serve_metrics = options.metrics;
+  if serve_metrics {
+    db::metrics::enable();
+  }
+  let db = db::connect(options.db).await?;
 
   let req_client = reqwest::Client::new();
 
+  // Session models are also checked for expiry when they're used, this only frees their memory
+  let session_ctx = ctx.clone();
+  tokio::spawn(async move {
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+    loop {
+      interval.tick().await;
+      let evicted = session_ctx.write().await.evict_expired_session_models();
+      if evicted > 0 {
+        log::info!("[session-models] evicted {} expired model(s)", evicted);
+      }
+    }
+  });
+
+  let trash_ctx = ctx.clone();
+  let trash_retention = chrono::Duration::days(options.model_trash_retention_days.max(0));
+  tokio::spawn(async move {
+    let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+    loop {
+      interval.tick().await;
+      match trash_ctx.read().await.purge_trash(trash_retention).await {
+        Ok(purged) => {
+          for name in purged {
+            log::info!("[models] purged {} from the trash", name);
+          }
+        }
+        Err(e) => log::error!("[models] failed to purge the trash: {}", e),
+      }
+    }
+  });
+
+  let share_limiter = Data::new(rate_limit::RateLimiter::new(
+    options.share_rate_limit,
+    Duration::from_secs(60),
+  ));
+  let trusted_proxies = Data::new(rate_limit::TrustedProxies(options.trusted_proxies.clone()));
+  let post_limiter = Data::new(v1::channels::PostLimiter(rate_limit::RateLimiter::new(
+    options.poster.post_rate_limit,
+    Duration::from_secs(60),
+  )));
+  let share_db = db.clone();
+  let share_limiter_ref = share_limiter.clone();
+  let post_limiter_ref = post_limiter.clone();
+  tokio::spawn(async move {
+    let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+    loop {
+      interval.tick().await;
+      share_limiter_ref.evict_expired();
+      post_limiter_ref.0.evict_expired();
+      match db::generations::delete_expired_shares(&share_db).await {
+        Ok(deleted) if deleted > 0 => log::info!("[shares] deleted {} expired share link(s)", deleted),
+        Ok(_) => (),
+        Err(e) => log::error!("[shares] failed to delete expired share links: {}", e),
+      }
+      match db::accounts::delete_due(&share_db).await {
+        Ok(deleted) => {
+          for user_id in deleted {
+            log::info!("[account] deleted the account of user {}", user_id);
+          }
+        }
+        Err(e) => log::error!("[account] failed to delete accounts: {}", e),
+      }
+    }
+  });
+
+  let sample_cache = Data::new(sample_cache::SampleCache::new(
+    options.sample_cache_size,
+    options.sample_cache_ttl,
+  ));
+  let poster = poster::Poster::new(&options.poster).map(Data::new);
+  if poster.is_none() {
+    log::info!("[post] bot account isn't configured, posting to Twitch is disabled");
+  }
+  let moderator = moderation::Moderator::new(&options.moderation, req_client.clone())?.map(Data::new);
+  if moderator.is_none() {
+    log::info!("[moderation] neither patterns nor an API are configured, generations aren't moderated");
+  }
+  let deletion_grace_period = Data::new(v1::account::DeletionGracePeriod(chrono::Duration::days(
+    options.account_deletion_grace_days.max(0),
+  )));
+  let summary_sources = Data::new(v1::admin::SummarySources {
+    collector_status_url: options.collector_status_url.clone(),
+  });
+
+  tokio::spawn(webhooks::watch(
+    ctx.clone(),
+    db.clone(),
+    req_client.clone(),
+    Duration::from_secs(options.model_poll_interval.max(1)),
+  ));
+
   let server = HttpServer::new(move || {
-    App::new()
+    let app = match &poster {
+      Some(poster) => App::new().app_data(poster.clone()),
+      None => App::new(),
+    };
+    let app = match &moderator {
+      Some(moderator) => app.app_data(moderator.clone()),
+      None => app,
+    };
+    app
       .app_data(Data::new(client_secret.clone()))
       .app_data(Data::new(ctx.clone()))
       .app_data(Data::new(db.clone()))
       .app_data(Data::new(req_client.clone()))
-      .wrap(
-        Cors::default()
-          .allow_any_origin()
-          .allowed_methods(vec!["POST", "GET"])
-          .allowed_headers(vec![header::AUTHORIZATION, header::ACCEPT])
-          .allowed_header(header::CONTENT_TYPE)
-          .supports_credentials()
-          .max_age(3600),
-      )
+      .app_data(share_limiter.clone())
+      .app_data(trusted_proxies.clone())
+      .app_data(sample_cache.clone())
+      .app_data(summary_sources.clone())
+      .app_data(post_limiter.clone())
+      .app_data(deletion_grace_period.clone())
+      .wrap(cors.cors(&[request_id::HEADER]))
       .wrap(middleware::Compress::default())
       .wrap(middleware::Logger::default())
+      .wrap_fn(request_id::instrument)
       .service(health_check)
+      .configure(|cfg| {
+        if serve_metrics {
+          cfg.service(metrics);
+        }
+      })
       .service(auth::create_token)
       .service(v1::routes())
   });
-  server.bind(format!("{}:8080", options.host)).unwrap().run().await?;
+  let address = (options.host.as_str(), options.http_port);
+  let server = match tls {
+    Some(tls) => {
+      log::info!("Serving HTTPS on {}:{}", address.0, address.1);
+      server.bind_rustls(address, tls)?
+    }
+    None => {
+      log::info!("Serving HTTP on {}:{}", address.0, address.1);
+      server.bind(address)?
+    }
+  };
+  server.run().await?;
 
   Ok(())
 }
@@ -0,0 +1,122 @@
+//! Default generation options of a model, stored next to it as `<name>.chain.options.json`.
+//!
+//! Models without a sidecar use [`ModelOptions::default`]. Requests may override `max_samples` and `max_length`,
+//! up to [`MAX_SAMPLES_LIMIT`] and [`chain::limits::TWITCH_MAX_CHARS`].
+
+use serde::{Deserialize, Serialize};
+
+/// Most times a single request may resample its output
+pub const MAX_SAMPLES_LIMIT: usize = 64;
+/// Most samples a single request may generate, counting both the resamples of output which only repeats the seed, and
+/// of output which contains a banned token
+pub const MAX_GENERATIONS: usize = 256;
+const DEFAULT_MAX_SAMPLES: usize = 16;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModelOptions {
+  /// How many times output which only repeats the seed, or contains a banned token, is generated again
+  pub max_samples: usize,
+  /// Maximum length of the output, in characters
+  pub max_length: usize,
+  /// Words which are never part of the output, compared case-insensitively
+  pub banned_tokens: Vec<String>,
+}
+
+impl Default for ModelOptions {
+  fn default() -> Self {
+    Self {
+      max_samples: DEFAULT_MAX_SAMPLES,
+      max_length: chain::limits::TWITCH_MAX_CHARS,
+      banned_tokens: vec![],
+    }
+  }
+}
+
+impl ModelOptions {
+  pub fn validate(&self) -> Result<(), String> {
+    if self.max_samples > MAX_SAMPLES_LIMIT {
+      return Err(format!("max_samples must be at most {MAX_SAMPLES_LIMIT}"));
+    }
+    if self.max_length == 0 || self.max_length > chain::limits::TWITCH_MAX_CHARS {
+      return Err(format!(
+        "max_length must be between 1 and {}",
+        chain::limits::TWITCH_MAX_CHARS
+      ));
+    }
+    if self
+      .banned_tokens
+      .iter()
+      .any(|token| token.split_whitespace().count() != 1)
+    {
+      return Err("banned_tokens must be single words".into());
+    }
+    Ok(())
+  }
+
+  /// Replaces the defaults with the values requested by a client, which must be within the limits
+  pub fn with_overrides(mut self, max_samples: Option<usize>, max_length: Option<usize>) -> Result<Self, String> {
+    self.max_samples = max_samples.unwrap_or(self.max_samples);
+    self.max_length = max_length.unwrap_or(self.max_length);
+    self.validate()?;
+    Ok(self)
+  }
+
+  /// How many times output is generated before its banned tokens are removed, so that the request stays within
+  /// [`MAX_GENERATIONS`]. Each attempt may itself sample up to `max_samples` times, twice when it falls back to
+  /// another seed.
+  pub fn attempts(&self) -> usize {
+    let per_attempt = 2 * self.max_samples.max(1);
+    (MAX_GENERATIONS / per_attempt).clamp(1, self.max_samples + 1)
+  }
+
+  pub fn generation_options(&self) -> chain::GenerationOptions {
+    chain::GenerationOptions::default().with_max_chars(self.max_length)
+  }
+
+  fn is_banned(&self, word: &str) -> bool {
+    self
+      .banned_tokens
+      .iter()
+      .any(|token| token.to_lowercase() == word.to_lowercase())
+  }
+
+  pub fn contains_banned_token(&self, text: &str) -> bool {
+    !self.banned_tokens.is_empty() && text.split_whitespace().any(|word| self.is_banned(word))
+  }
+
+  /// Removes the banned tokens from `text`, for output which still contained some after resampling
+  pub fn remove_banned_tokens(&self, text: &str) -> String {
+    text
+      .split_whitespace()
+      .filter(|word| !self.is_banned(word))
+      .collect::<Vec<_>>()
+      .join(" ")
+  }
+}
+
+/// Path of the options of the model at `model_path`
+pub fn path_of(model_path: &std::path::Path) -> std::path::PathBuf {
+  let mut path = model_path.as_os_str().to_owned();
+  path.push(".options.json");
+  path.into()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn attempts_stay_within_the_generation_limit() {
+    for max_samples in 0..=MAX_SAMPLES_LIMIT {
+      let options = ModelOptions {
+        max_samples,
+        ..Default::default()
+      };
+      assert!(options.attempts() >= 1);
+      assert!(options.attempts() <= max_samples + 1);
+      assert!(options.attempts() * 2 * max_samples <= MAX_GENERATIONS);
+    }
+    assert_eq!(ModelOptions::default().attempts(), 8);
+  }
+}
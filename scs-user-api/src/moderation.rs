@@ -0,0 +1,316 @@
+//! Moderation of generated text, with a local list of blocked patterns, the trainer's phrase blocklist, and/or an
+//! external moderation API.
+//!
+//! Every generation is annotated with a [`Verdict`], and unsafe text is refused if `--moderation-refuse-unsafe` is set.
+//! Text which contains a blocklisted phrase is always refused.
+
+use anyhow::{anyhow, bail, Context, Result};
+use regex::{RegexSet, RegexSetBuilder};
+use serde::{Deserialize, Serialize};
+use std::{
+  path::{Path, PathBuf},
+  time::Duration,
+};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct ModerationOptions {
+  /// File with one regular expression per line, matched ignoring case. Text which matches any of them is unsafe.
+  /// Empty lines and lines starting with `#` are ignored.
+  #[structopt(long, env = "SCS_USER_API_MODERATION_PATTERNS", parse(from_os_str))]
+  pub moderation_patterns: Option<PathBuf>,
+  /// The trainer's `phrase_blocklist`. Text which contains any of its phrases is unsafe, and always refused.
+  #[structopt(long, env = "SCS_USER_API_MODERATION_PHRASE_BLOCKLIST", parse(from_os_str))]
+  pub moderation_phrase_blocklist: Option<PathBuf>,
+  /// Moderation API which generated text is sent to, see the README for its request and response
+  #[structopt(long, env = "SCS_USER_API_MODERATION_URL")]
+  pub moderation_url: Option<reqwest::Url>,
+  /// Bearer token sent to the moderation API
+  #[structopt(long, env = "SCS_USER_API_MODERATION_TOKEN", hide_env_values = true)]
+  pub moderation_token: Option<String>,
+  /// How long to wait for the moderation API, in milliseconds
+  #[structopt(long, env = "SCS_USER_API_MODERATION_TIMEOUT_MS", default_value = "2000")]
+  pub moderation_timeout_ms: u64,
+  /// Verdict when the moderation API fails or times out: `open` treats the text as safe, `closed` as unsafe
+  #[structopt(long, env = "SCS_USER_API_MODERATION_FAILURE_MODE", default_value = "open")]
+  pub moderation_failure_mode: FailureMode,
+  /// Refuse to return unsafe text, instead of only annotating it
+  #[structopt(
+    long,
+    env = "SCS_USER_API_MODERATION_REFUSE_UNSAFE",
+    parse(try_from_str),
+    default_value = "false"
+  )]
+  pub moderation_refuse_unsafe: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FailureMode {
+  Open,
+  Closed,
+}
+
+impl std::str::FromStr for FailureMode {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    match s {
+      "open" => Ok(FailureMode::Open),
+      "closed" => Ok(FailureMode::Closed),
+      other => bail!("Unknown moderation failure mode `{other}`, expected `open` or `closed`"),
+    }
+  }
+}
+
+/// Whether generated text is safe to show
+#[derive(Clone, Debug, Serialize)]
+pub struct Verdict {
+  pub safe: bool,
+  /// Why the text is unsafe, or why it couldn't be checked
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub reason: Option<String>,
+}
+
+impl Verdict {
+  fn safe() -> Self {
+    Self {
+      safe: true,
+      reason: None,
+    }
+  }
+
+  fn unsafe_because(reason: impl Into<String>) -> Self {
+    Self {
+      safe: false,
+      reason: Some(reason.into()),
+    }
+  }
+}
+
+#[derive(Serialize)]
+struct ApiRequest<'a> {
+  text: &'a str,
+}
+
+#[derive(Deserialize)]
+struct ApiResponse {
+  flagged: bool,
+  reason: Option<String>,
+}
+
+struct Api {
+  url: reqwest::Url,
+  token: Option<String>,
+  timeout: Duration,
+  failure_mode: FailureMode,
+}
+
+pub struct Moderator {
+  patterns: Option<RegexSet>,
+  phrase_blocklist: Option<scs_config::PhraseBlocklist>,
+  api: Option<Api>,
+  refuse_unsafe: bool,
+  client: reqwest::Client,
+}
+
+impl Moderator {
+  /// Returns `None` if neither the patterns, the phrase blocklist nor the API are configured
+  pub fn new(options: &ModerationOptions, client: reqwest::Client) -> Result<Option<Self>> {
+    let patterns = match &options.moderation_patterns {
+      Some(path) => Some(read_patterns(path)?),
+      None => None,
+    };
+    let phrase_blocklist = match &options.moderation_phrase_blocklist {
+      Some(path) => Some(scs_config::PhraseBlocklist::load(path)?),
+      None => None,
+    };
+    let api = options.moderation_url.clone().map(|url| Api {
+      url,
+      token: options.moderation_token.clone(),
+      timeout: Duration::from_millis(options.moderation_timeout_ms),
+      failure_mode: options.moderation_failure_mode,
+    });
+    if patterns.is_none() && phrase_blocklist.is_none() && api.is_none() {
+      return Ok(None);
+    }
+    Ok(Some(Self {
+      patterns,
+      phrase_blocklist,
+      api,
+      refuse_unsafe: options.moderation_refuse_unsafe,
+      client,
+    }))
+  }
+
+  /// Whether `text` contains a phrase of the phrase blocklist, which is refused even if unsafe text isn't
+  pub fn is_blocklisted(&self, text: &str) -> bool {
+    self
+      .phrase_blocklist
+      .as_ref()
+      .map_or(false, |blocklist| blocklist.is_match(text))
+  }
+
+  /// Checks `text` against the phrase blocklist and the patterns, and then with the API if neither matched
+  pub async fn check(&self, text: &str) -> Verdict {
+    if self.is_blocklisted(text) {
+      return Verdict::unsafe_because("contains a blocklisted phrase");
+    }
+    if self.patterns.as_ref().map_or(false, |patterns| patterns.is_match(text)) {
+      return Verdict::unsafe_because("matched a blocked pattern");
+    }
+    let Some(api) = &self.api else {
+      return Verdict::safe();
+    };
+    match self.check_with_api(api, text).await {
+      Ok(response) if response.flagged => Verdict::unsafe_because(
+        response
+          .reason
+          .unwrap_or_else(|| String::from("flagged by the moderation API")),
+      ),
+      Ok(_) => Verdict::safe(),
+      Err(e) => {
+        log::warn!("[moderation] failed to check a generation: {:#}", e);
+        match api.failure_mode {
+          FailureMode::Open => Verdict {
+            safe: true,
+            reason: Some(String::from("moderation API unavailable")),
+          },
+          FailureMode::Closed => Verdict::unsafe_because("moderation API unavailable"),
+        }
+      }
+    }
+  }
+
+  /// Checks `text` like [`Moderator::check`], and returns the verdict as an error if the text must be refused, because
+  /// it's unsafe and unsafe text is refused, or it contains a blocklisted phrase
+  pub async fn review(&self, text: &str) -> std::result::Result<Verdict, Verdict> {
+    let verdict = self.check(text).await;
+    if !verdict.safe && (self.refuse_unsafe || self.is_blocklisted(text)) {
+      log::info!(
+        "[moderation] refused a generation: {}",
+        verdict.reason.as_deref().unwrap_or("unsafe")
+      );
+      return Err(verdict);
+    }
+    Ok(verdict)
+  }
+
+  async fn check_with_api(&self, api: &Api, text: &str) -> Result<ApiResponse> {
+    let mut request = self
+      .client
+      .post(api.url.clone())
+      .timeout(api.timeout)
+      .json(&ApiRequest { text });
+    if let Some(token) = &api.token {
+      request = request.bearer_auth(token);
+    }
+    let response = request.send().await?.error_for_status()?;
+    response
+      .json::<ApiResponse>()
+      .await
+      .context("Invalid response from the moderation API")
+  }
+}
+
+fn read_patterns(path: &Path) -> Result<RegexSet> {
+  let contents = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+  let patterns = contents
+    .lines()
+    .map(str::trim)
+    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+    .collect::<Vec<_>>();
+  RegexSetBuilder::new(&patterns)
+    .case_insensitive(true)
+    .build()
+    .map_err(|e| anyhow!("Invalid moderation pattern in {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn options(test: &str, patterns: Option<&str>) -> ModerationOptions {
+    let moderation_patterns = patterns.map(|patterns| {
+      let path = std::env::temp_dir().join(format!("scs-user-api-moderation-{test}-{}", std::process::id()));
+      std::fs::write(&path, patterns).unwrap();
+      path
+    });
+    ModerationOptions {
+      moderation_patterns,
+      moderation_phrase_blocklist: None,
+      moderation_url: None,
+      moderation_token: None,
+      moderation_timeout_ms: 500,
+      moderation_failure_mode: FailureMode::Open,
+      moderation_refuse_unsafe: false,
+    }
+  }
+
+  /// An API which refuses connections, so that every check fails
+  fn unreachable_api() -> reqwest::Url {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    drop(listener);
+    format!("http://127.0.0.1:{port}/moderate").parse().unwrap()
+  }
+
+  #[tokio::test]
+  async fn test_patterns() {
+    let options = options("patterns", Some("# comment\n\nbad\\s+word\n"));
+    let moderator = Moderator::new(&options, reqwest::Client::new()).unwrap().unwrap();
+    let verdict = moderator.check("a BAD  word").await;
+    assert!(!verdict.safe);
+    assert_eq!(verdict.reason.as_deref(), Some("matched a blocked pattern"));
+    assert!(moderator.check("a good word").await.safe);
+    // Unsafe text is only refused if it's configured to
+    assert!(moderator.review("a bad word").await.is_ok());
+
+    let options = ModerationOptions {
+      moderation_refuse_unsafe: true,
+      ..options
+    };
+    let moderator = Moderator::new(&options, reqwest::Client::new()).unwrap().unwrap();
+    assert!(moderator.review("a bad word").await.is_err());
+    assert!(moderator.review("a good word").await.is_ok());
+
+    // Blocklisted phrases are always refused
+    let blocklist = std::env::temp_dir().join(format!("scs-user-api-moderation-blocklist-{}", std::process::id()));
+    std::fs::write(&blocklist, "never say this\n").unwrap();
+    let options = ModerationOptions {
+      moderation_phrase_blocklist: Some(blocklist),
+      moderation_refuse_unsafe: false,
+      ..options
+    };
+    let moderator = Moderator::new(&options, reqwest::Client::new()).unwrap().unwrap();
+    let verdict = moderator.review("i'd NEVER say  this").await.unwrap_err();
+    assert_eq!(verdict.reason.as_deref(), Some("contains a blocklisted phrase"));
+    assert!(moderator.review("never say that").await.is_ok());
+
+    assert!(Moderator::new(&self::options("none", None), reqwest::Client::new())
+      .unwrap()
+      .is_none());
+  }
+
+  #[tokio::test]
+  async fn test_api_failures() {
+    let options = ModerationOptions {
+      moderation_url: Some(unreachable_api()),
+      ..options("fail-open", None)
+    };
+    let moderator = Moderator::new(&options, reqwest::Client::new()).unwrap().unwrap();
+    let verdict = moderator.check("anything").await;
+    assert!(verdict.safe);
+    assert_eq!(verdict.reason.as_deref(), Some("moderation API unavailable"));
+
+    let options = ModerationOptions {
+      moderation_failure_mode: FailureMode::Closed,
+      moderation_refuse_unsafe: true,
+      ..options
+    };
+    let moderator = Moderator::new(&options, reqwest::Client::new()).unwrap().unwrap();
+    let verdict = moderator.check("anything").await;
+    assert!(!verdict.safe);
+    assert_eq!(verdict.reason.as_deref(), Some("moderation API unavailable"));
+    assert!(moderator.review("anything").await.is_err());
+  }
+}
@@ -0,0 +1,107 @@
+use anyhow::{anyhow, bail, Result};
+use std::time::Duration;
+use structopt::StructOpt;
+use twitch_api::{credentials, ConnectOptions, Credentials, SendOutcome, TwitchStream};
+
+/// How long to wait for Twitch to accept the login
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, StructOpt)]
+pub struct PosterOptions {
+  /// Login of the account which generated messages are posted as
+  #[structopt(long, env = "SCS_USER_API_BOT_LOGIN")]
+  pub bot_login: Option<String>,
+  /// OAuth token of the bot account, with or without the `oauth:` prefix
+  #[structopt(long, env = "SCS_USER_API_BOT_TOKEN", hide_env_values = true)]
+  pub bot_token: Option<String>,
+  #[structopt(
+    long,
+    env = "SCS_USER_API_IRC_URI",
+    default_value = "wss://irc-ws.chat.twitch.tv:443"
+  )]
+  pub irc_uri: String,
+  /// Maximum number of generated messages posted to a single channel per minute
+  #[structopt(long, env = "SCS_USER_API_POST_RATE_LIMIT", default_value = "2")]
+  pub post_rate_limit: u32,
+}
+
+/// Posts messages to Twitch chat as the bot account.
+///
+/// Posting is rare, so every message is sent over a new connection instead of keeping one open.
+pub struct Poster {
+  uri: String,
+  credentials: Credentials,
+  connect: ConnectOptions,
+}
+
+impl Poster {
+  /// Returns `None` if the bot account isn't configured
+  pub fn new(options: &PosterOptions) -> Option<Self> {
+    let (login, token) = (options.bot_login.clone()?, options.bot_token.clone()?);
+    let token = if token.starts_with("oauth:") {
+      token
+    } else {
+      format!("oauth:{token}")
+    };
+    Some(Self {
+      uri: options.irc_uri.clone(),
+      credentials: Credentials::Regular(credentials::Regular { login, token }),
+      connect: ConnectOptions::from_env(),
+    })
+  }
+
+  pub async fn post(&self, channel: &str, text: &str) -> Result<()> {
+    let mut stream = TwitchStream::with_options(self.uri.clone(), self.connect.clone()).await?;
+    stream.authenticate(&self.credentials).await?;
+    tokio::time::timeout(LOGIN_TIMEOUT, wait_for_login(&mut stream))
+      .await
+      .map_err(|_| anyhow!("Timed out waiting for Twitch to accept the login"))??;
+    stream.respond(channel, text).await?;
+    match tokio::time::timeout(
+      twitch_api::delivery::CONFIRMATION_TIMEOUT,
+      wait_for_delivery(&mut stream),
+    )
+    .await
+    {
+      Ok(result) => result,
+      Err(_) => {
+        log::warn!("[post] Twitch didn't confirm the message to {}", channel);
+        Ok(())
+      }
+    }
+  }
+}
+
+/// Waits for Twitch to accept the message which was just sent, or fails if it's dropped
+async fn wait_for_delivery(stream: &mut TwitchStream) -> Result<()> {
+  while stream.receive().await?.is_some() {
+    if let Some(delivery) = stream.take_deliveries().pop() {
+      return match delivery.outcome {
+        SendOutcome::Dropped { reason, notice } => bail!("Twitch dropped the message ({}): {}", reason, notice),
+        SendOutcome::Accepted | SendOutcome::Unconfirmed => Ok(()),
+      };
+    }
+  }
+  bail!("Connection closed before the message was confirmed")
+}
+
+/// Waits for the welcome message (`001`), or fails if the login is rejected
+async fn wait_for_login(stream: &mut TwitchStream) -> Result<()> {
+  while let Some(message) = stream.receive().await? {
+    let Ok(batch) = message.to_text() else {
+      continue;
+    };
+    for line in batch.lines() {
+      // Skip the tags and the prefix
+      let command = line
+        .split(' ')
+        .find(|part| !part.starts_with('@') && !part.starts_with(':'));
+      match command {
+        Some("001") => return Ok(()),
+        Some("NOTICE") if twitch_api::is_auth_failure(line) => bail!("Twitch rejected the login: {}", line),
+        _ => (),
+      }
+    }
+  }
+  bail!("Connection closed before the login was accepted")
+}
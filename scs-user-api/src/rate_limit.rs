@@ -0,0 +1,126 @@
+use actix_web::HttpRequest;
+use std::{
+  collections::HashMap,
+  net::IpAddr,
+  sync::Mutex,
+  time::{Duration, Instant},
+};
+
+/// Fixed-window rate limiter, keyed by client address
+pub struct RateLimiter {
+  limit: u32,
+  window: Duration,
+  clients: Mutex<Clients>,
+}
+
+struct Clients {
+  /// Start of the current window of each client, and the number of requests made in it
+  windows: HashMap<String, (Instant, u32)>,
+  /// When the clients whose window has ended were last forgotten
+  evicted_at: Instant,
+}
+
+impl Clients {
+  fn evict_expired(&mut self, window: Duration) -> usize {
+    let before = self.windows.len();
+    self
+      .windows
+      .retain(|_, (window_start, _)| window_start.elapsed() < window);
+    self.evicted_at = Instant::now();
+    before - self.windows.len()
+  }
+}
+
+impl RateLimiter {
+  pub fn new(limit: u32, window: Duration) -> Self {
+    Self {
+      limit,
+      window,
+      clients: Mutex::new(Clients {
+        windows: HashMap::new(),
+        evicted_at: Instant::now(),
+      }),
+    }
+  }
+
+  /// Records a request from `client`, and returns `false` if it's over the limit.
+  ///
+  /// Clients whose window has ended are forgotten once per window, so that the limiter doesn't grow with the number of
+  /// addresses seen between two calls to [`RateLimiter::evict_expired`].
+  pub fn check(&self, client: &str) -> bool {
+    let mut clients = self.clients.lock().unwrap();
+    if clients.evicted_at.elapsed() >= self.window {
+      clients.evict_expired(self.window);
+    }
+    let now = Instant::now();
+    let (window_start, requests) = clients.windows.entry(client.to_owned()).or_insert((now, 0));
+    if now.duration_since(*window_start) >= self.window {
+      *window_start = now;
+      *requests = 0;
+    }
+    *requests += 1;
+    *requests <= self.limit
+  }
+
+  /// Forgets clients whose window has ended, returns the number of evicted clients
+  pub fn evict_expired(&self) -> usize {
+    self.clients.lock().unwrap().evict_expired(self.window)
+  }
+}
+
+/// Reverse proxies whose forwarded client address is trusted
+pub struct TrustedProxies(pub Vec<IpAddr>);
+
+impl TrustedProxies {
+  /// Returns the address of the client which sent `req`.
+  ///
+  /// That's the address of the connection, unless it comes from a trusted proxy, in which case it's the address in the
+  /// `Forwarded` or `X-Forwarded-For` header. Otherwise clients could pick a different address for every request.
+  pub fn client(&self, req: &HttpRequest) -> String {
+    match req.peer_addr() {
+      Some(peer) if self.0.contains(&peer.ip()) => req
+        .connection_info()
+        .realip_remote_addr()
+        .map_or_else(|| peer.ip().to_string(), ToOwned::to_owned),
+      Some(peer) => peer.ip().to_string(),
+      None => "unknown".to_owned(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use actix_web::test::TestRequest;
+
+  #[test]
+  fn test_limit() {
+    let limiter = RateLimiter::new(2, Duration::from_secs(60));
+    assert!(limiter.check("a"));
+    assert!(limiter.check("a"));
+    assert!(!limiter.check("a"));
+    assert!(limiter.check("b"));
+    assert_eq!(limiter.evict_expired(), 0);
+  }
+
+  #[test]
+  fn test_expired_clients_are_evicted() {
+    let limiter = RateLimiter::new(1, Duration::ZERO);
+    assert!(limiter.check("a"));
+    assert!(limiter.check("b"));
+    assert_eq!(limiter.clients.lock().unwrap().windows.len(), 1);
+  }
+
+  #[test]
+  fn test_forwarded_address_is_only_trusted_from_proxies() {
+    let req = TestRequest::default()
+      .peer_addr("10.0.0.1:1234".parse().unwrap())
+      .insert_header(("X-Forwarded-For", "1.2.3.4"))
+      .to_http_request();
+    assert_eq!(TrustedProxies(vec![]).client(&req), "10.0.0.1");
+    assert_eq!(
+      TrustedProxies(vec!["10.0.0.1".parse().unwrap()]).client(&req),
+      "1.2.3.4"
+    );
+  }
+}
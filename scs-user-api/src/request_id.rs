@@ -0,0 +1,64 @@
+//! Correlation IDs for requests.
+//!
+//! Every request is handled inside a `request` span which carries its ID, so all logs emitted while
+//! handling it can be correlated. The ID is taken from the `X-Request-Id` header if the client sent a valid one,
+//! otherwise it's generated, and it's always returned in the `X-Request-Id` response header.
+
+use actix_web::{
+  dev::{Service, ServiceRequest, ServiceResponse},
+  http::header::{HeaderName, HeaderValue},
+  HttpMessage,
+};
+use std::{future::Future, time::Instant};
+use tracing::Instrument;
+
+pub const HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// ID of the current request, available as a request extension
+#[derive(Clone, Debug)]
+pub struct RequestId(pub String);
+
+fn is_valid(id: &str) -> bool {
+  !id.is_empty() && id.len() <= 64 && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn generate() -> String {
+  format!("{:016x}", rand::random::<u64>())
+}
+
+/// Middleware function, used with `App::wrap_fn`
+pub fn instrument<S, B>(
+  req: ServiceRequest,
+  srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>
+where
+  S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+  let id = req
+    .headers()
+    .get(&HEADER)
+    .and_then(|v| v.to_str().ok())
+    .filter(|id| is_valid(id))
+    .map(String::from)
+    .unwrap_or_else(generate);
+  req.extensions_mut().insert(RequestId(id.clone()));
+
+  let span = tracing::info_span!("request", request_id = %id, method = %req.method(), path = %req.path());
+  let start = Instant::now();
+  let response = span.in_scope(|| srv.call(req));
+
+  async move {
+    let mut response = response.await?;
+    tracing::info!(
+      status = response.status().as_u16(),
+      elapsed = ?start.elapsed(),
+      "finished request"
+    );
+    // `id` is either generated or a valid header value
+    response
+      .headers_mut()
+      .insert(HEADER, HeaderValue::from_str(&id).unwrap());
+    Ok(response)
+  }
+  .instrument(span)
+}
@@ -0,0 +1,122 @@
+use cached::{Cached, TimedSizedCache};
+use serde::Serialize;
+use std::sync::Mutex;
+
+/// Everything which determines the output of a generation request
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Key {
+  pub model: String,
+  pub token: String,
+  pub continuation: bool,
+  pub channel: Option<String>,
+  /// Chosen by the client, so that identical requests with a different seed are generated again.
+  /// Requests without a seed aren't cached.
+  pub seed: u64,
+  /// The model's options with the request's overrides, so that editing them isn't hidden by text cached before
+  pub options: crate::model_options::ModelOptions,
+  pub strategy: crate::v1::models::Strategy,
+  pub beam_width: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Stats {
+  pub size: usize,
+  pub capacity: usize,
+  pub ttl: u64,
+  pub hits: u64,
+  pub misses: u64,
+}
+
+/// LRU cache of recently generated text, whose entries expire after a fixed lifespan
+pub struct SampleCache {
+  capacity: usize,
+  ttl: u64,
+  /// `None` if the cache is disabled
+  cache: Option<Mutex<TimedSizedCache<Key, String>>>,
+}
+
+impl SampleCache {
+  /// A `capacity` or `ttl` (in seconds) of zero disables the cache
+  pub fn new(capacity: usize, ttl: u64) -> Self {
+    let cache = (capacity > 0 && ttl > 0).then(|| Mutex::new(TimedSizedCache::with_size_and_lifespan(capacity, ttl)));
+    Self { capacity, ttl, cache }
+  }
+
+  pub fn get(&self, key: &Key) -> Option<String> {
+    self.cache.as_ref()?.lock().unwrap().cache_get(key).cloned()
+  }
+
+  pub fn insert(&self, key: Key, text: String) {
+    if let Some(cache) = &self.cache {
+      cache.lock().unwrap().cache_set(key, text);
+    }
+  }
+
+  /// Drops the text generated by `model`, e.g. once it's deleted, so that it can't be returned for a model which doesn't
+  /// exist anymore, or for another model of the same name
+  pub fn evict_model(&self, model: &str) {
+    if let Some(cache) = &self.cache {
+      let mut cache = cache.lock().unwrap();
+      let keys = cache
+        .key_order()
+        .filter(|key| key.model == model)
+        .cloned()
+        .collect::<Vec<_>>();
+      for key in keys {
+        cache.cache_remove(&key);
+      }
+    }
+  }
+
+  pub fn stats(&self) -> Stats {
+    let (size, hits, misses) = match &self.cache {
+      Some(cache) => {
+        let cache = cache.lock().unwrap();
+        (
+          cache.cache_size(),
+          cache.cache_hits().unwrap_or(0),
+          cache.cache_misses().unwrap_or(0),
+        )
+      }
+      None => (0, 0, 0),
+    };
+    Stats {
+      size,
+      capacity: self.capacity,
+      ttl: self.ttl,
+      hits,
+      misses,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn key(model: &str, seed: u64) -> Key {
+    Key {
+      model: model.into(),
+      token: "forsen".into(),
+      continuation: false,
+      channel: None,
+      seed,
+      options: Default::default(),
+      strategy: Default::default(),
+      beam_width: None,
+    }
+  }
+
+  #[test]
+  fn evicts_the_entries_of_a_model() {
+    let cache = SampleCache::new(16, 60);
+    cache.insert(key("forsen", 1), "a".into());
+    cache.insert(key("forsen", 2), "b".into());
+    cache.insert(key("xqc", 1), "c".into());
+
+    cache.evict_model("forsen");
+    assert_eq!(cache.get(&key("forsen", 1)), None);
+    assert_eq!(cache.get(&key("forsen", 2)), None);
+    assert_eq!(cache.get(&key("xqc", 1)), Some("c".into()));
+  }
+}
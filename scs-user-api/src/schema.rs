@@ -1,14 +1,120 @@
 use chain::TextGenerator;
 use chrono::{DateTime, Utc};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 /// Information that can be gathered just by reading the filesystem
-#[derive(Serialize)]
+#[derive(Clone, Serialize)]
 pub struct SimpleModelInfo {
   pub name: String,
   pub date_created: DateTime<Utc>,
   pub date_modified: DateTime<Utc>,
   pub size: f64,
+  /// Only included in the model list with `include=meta`
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub meta: Option<ModelMeta>,
+  /// Only included in the model list with `include=usage`
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub usage: Option<ModelUsage>,
+}
+
+/// Information read from the header of the model file
+#[derive(Clone, Serialize)]
+pub struct ModelMeta {
+  pub order: usize,
+  pub metadata: String,
+  /// Channels the model was trained on, as described by its metadata
+  pub channels: Vec<String>,
+}
+
+/// How much a model was used to generate text
+#[derive(Clone, Default, Serialize)]
+pub struct ModelUsage {
+  pub generations: i64,
+  /// Number of distinct users who generated text with the model
+  pub users: i64,
+  pub last_generated_at: Option<DateTime<Utc>>,
+}
+
+/// Information read from the model file, without loading the model
+#[derive(Clone, Serialize)]
+pub struct ModelInfo {
+  #[serde(flatten)]
+  pub file: SimpleModelInfo,
+  pub order: usize,
+  pub metadata: String,
+  pub words: usize,
+  pub nodes: usize,
+  pub edges: usize,
+  pub memory_estimate: MemoryEstimate,
+  /// Whether the model is currently loaded
+  pub loaded: bool,
+}
+
+/// Estimated memory used by a loaded model, in bytes
+#[derive(Clone, Copy, Serialize)]
+pub struct MemoryEstimate {
+  pub dict: usize,
+  pub nodes: usize,
+  pub edges: usize,
+  pub total: usize,
+}
+
+impl From<chain::MemoryEstimate> for MemoryEstimate {
+  fn from(estimate: chain::MemoryEstimate) -> Self {
+    Self {
+      dict: estimate.dict,
+      nodes: estimate.nodes,
+      edges: estimate.edges,
+      total: estimate.total(),
+    }
+  }
+}
+
+/// A model along with its timestamped checkpoints, e.g. `channel.chain` and `channel-2023-07-01.chain`
+#[derive(Serialize)]
+pub struct ModelFamily {
+  pub name: String,
+  /// The model without a timestamp, if there is one
+  pub latest: Option<SimpleModelInfo>,
+  pub checkpoints: Vec<SimpleModelInfo>,
+}
+
+#[derive(Serialize)]
+pub struct GeneratedText {
+  pub text: String,
+  /// ID of the stored generation, which can be shared. Generations of session models aren't stored.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub generation_id: Option<i64>,
+  /// Only included if moderation is configured
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub safety: Option<crate::moderation::Verdict>,
+}
+
+/// Text generated by one of the models compared by `/v1/models/compare`, along with its stats
+#[derive(Serialize)]
+pub struct ModelComparison {
+  pub model: String,
+  /// Empty if the text was refused by moderation
+  pub text: String,
+  /// Not included if the text was refused, as it isn't stored
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub generation_id: Option<i64>,
+  /// Whether the text was refused by moderation, in which case `safety` says why
+  pub refused: bool,
+  pub order: usize,
+  /// Size of the model file in megabytes
+  pub size: f64,
+  /// Channels the model was trained on, as described by its metadata
+  pub channels: Vec<String>,
+  /// Only included if moderation is configured
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub safety: Option<crate::moderation::Verdict>,
+}
+
+#[derive(Serialize)]
+pub struct TextScore {
+  /// Average log-probability of the transitions between the words of the text, see `chain::Chain::score_text`
+  pub score: f64,
 }
 
 /// Information that
@@ -31,6 +137,17 @@ impl Model {
       date_created: self.date_created,
       date_modified: self.date_modified,
       size: self.size,
+      meta: None,
+      usage: None,
     }
   }
 }
+
+/// Record of a deleted model, kept in the trash along with its files
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Tombstone {
+  pub name: String,
+  /// User ID of the admin who deleted the model
+  pub deleted_by: i32,
+  pub deleted_at: DateTime<Utc>,
+}
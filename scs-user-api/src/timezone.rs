@@ -0,0 +1,116 @@
+//! Timezones requested by clients with the `tz` query parameter, so that timestamps can be shown in local time
+//! without converting them in the browser.
+
+use chrono::{DateTime, Utc};
+use chrono_tz::{Tz, TZ_VARIANTS};
+
+/// Most close matches listed when a timezone isn't recognized
+const MAX_SUGGESTIONS: usize = 5;
+
+/// Parses an IANA timezone name such as `Europe/Berlin`. Case and spaces instead of underscores are tolerated.
+/// Unknown names fail with a list of the closest known ones.
+pub fn parse(name: &str) -> Result<Tz, crate::error::Error> {
+  let normalized = name.trim().replace(' ', "_");
+  if let Some(tz) = TZ_VARIANTS
+    .iter()
+    .find(|tz| tz.name().eq_ignore_ascii_case(&normalized))
+  {
+    return Ok(*tz);
+  }
+
+  let suggestions = suggestions(&normalized);
+  Err(
+    if suggestions.is_empty() {
+      format!("Unknown timezone `{name}`, expected an IANA name such as `Europe/Berlin`")
+    } else {
+      format!("Unknown timezone `{name}`, did you mean {}?", suggestions.join(", "))
+    }
+    .into(),
+  )
+}
+
+/// `time` in `tz`, as an RFC 3339 timestamp with its offset
+pub fn format(time: &DateTime<Utc>, tz: Tz) -> String {
+  time.with_timezone(&tz).to_rfc3339()
+}
+
+/// Known timezones whose name, or its last part (e.g. `Berlin`), is close to `name`, closest first
+fn suggestions(name: &str) -> Vec<&'static str> {
+  let name = name.to_lowercase();
+  let max_distance = (name.chars().count() / 4).max(2);
+  let mut matches = TZ_VARIANTS
+    .iter()
+    .map(|tz| tz.name())
+    .filter_map(|candidate| {
+      let candidate_lower = candidate.to_lowercase();
+      let city = candidate_lower.rsplit('/').next().unwrap_or(&candidate_lower);
+      let distance = levenshtein(&name, &candidate_lower).min(levenshtein(&name, city));
+      (distance <= max_distance).then_some((distance, candidate))
+    })
+    .collect::<Vec<_>>();
+  matches.sort();
+  matches
+    .into_iter()
+    .take(MAX_SUGGESTIONS)
+    .map(|(_, name)| name)
+    .collect()
+}
+
+/// Number of single character insertions, deletions, or substitutions needed to turn `a` into `b`
+fn levenshtein(a: &str, b: &str) -> usize {
+  let b = b.chars().collect::<Vec<_>>();
+  let mut previous = (0..=b.len()).collect::<Vec<_>>();
+  for (i, a) in a.chars().enumerate() {
+    let mut current = vec![i + 1; b.len() + 1];
+    for (j, b) in b.iter().enumerate() {
+      let substitution = previous[j] + usize::from(a != *b);
+      current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+    }
+    previous = current;
+  }
+  previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse() {
+    assert_eq!(parse("Europe/Berlin").unwrap(), Tz::Europe__Berlin);
+    assert_eq!(parse("europe/BERLIN").unwrap(), Tz::Europe__Berlin);
+    assert_eq!(parse(" America/New York ").unwrap(), Tz::America__New_York);
+    assert_eq!(parse("UTC").unwrap(), Tz::UTC);
+
+    let error = parse("Europe/Berln").unwrap_err().to_string();
+    assert!(
+      error.starts_with("Unknown timezone `Europe/Berln`, did you mean Europe/Berlin"),
+      "{error}"
+    );
+    let error = parse("zzzzzzzzzzzzzzzz").unwrap_err().to_string();
+    assert_eq!(
+      error,
+      "Unknown timezone `zzzzzzzzzzzzzzzz`, expected an IANA name such as `Europe/Berlin`"
+    );
+  }
+
+  #[test]
+  fn test_suggestions() {
+    // The city alone is close enough
+    assert_eq!(suggestions("berln").first(), Some(&"Europe/Berlin"));
+    assert_eq!(suggestions("America/New_Yrok").first(), Some(&"America/New_York"));
+    assert!(suggestions("a").len() <= MAX_SUGGESTIONS);
+    assert!(suggestions("zzzzzzzzzzzzzzzz").is_empty());
+  }
+
+  #[test]
+  fn test_levenshtein() {
+    assert_eq!(levenshtein("kitten", "sitting"), 3);
+    assert_eq!(levenshtein("flaw", "lawn"), 2);
+    assert_eq!(levenshtein("berlin", "berlin"), 0);
+    assert_eq!(levenshtein("", "abc"), 3);
+    assert_eq!(levenshtein("abc", ""), 3);
+    // Characters rather than bytes
+    assert_eq!(levenshtein("zürich", "zurich"), 1);
+  }
+}
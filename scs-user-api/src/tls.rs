@@ -0,0 +1,54 @@
+use anyhow::{bail, Context, Result};
+use std::{fs::File, io::BufReader, path::PathBuf};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct TlsOptions {
+  /// PEM file with the certificate chain, which enables HTTPS together with `--tls-key`
+  #[structopt(long, env = "SCS_USER_API_TLS_CERT", parse(from_os_str))]
+  pub tls_cert: Option<PathBuf>,
+  /// PEM file with the private key of the certificate (PKCS#8, RSA, or EC)
+  #[structopt(long, env = "SCS_USER_API_TLS_KEY", parse(from_os_str))]
+  pub tls_key: Option<PathBuf>,
+}
+
+impl TlsOptions {
+  /// Returns `None` if TLS isn't configured, in which case the API is served over plain HTTP
+  pub fn server_config(&self) -> Result<Option<rustls::ServerConfig>> {
+    let (cert, key) = match (&self.tls_cert, &self.tls_key) {
+      (Some(cert), Some(key)) => (cert, key),
+      (None, None) => return Ok(None),
+      _ => bail!("--tls-cert and --tls-key must be set together"),
+    };
+
+    let certs = rustls_pemfile::certs(&mut BufReader::new(
+      File::open(cert).with_context(|| format!("Failed to open {}", cert.display()))?,
+    ))
+    .with_context(|| format!("Failed to read the certificates in {}", cert.display()))?;
+    if certs.is_empty() {
+      bail!("{} doesn't contain any certificates", cert.display());
+    }
+
+    let mut reader = BufReader::new(File::open(key).with_context(|| format!("Failed to open {}", key.display()))?);
+    let key = std::iter::from_fn(|| rustls_pemfile::read_one(&mut reader).transpose())
+      .find_map(|item| match item {
+        Ok(
+          rustls_pemfile::Item::PKCS8Key(key) | rustls_pemfile::Item::RSAKey(key) | rustls_pemfile::Item::ECKey(key),
+        ) => Some(Ok(key)),
+        Ok(_) => None,
+        Err(e) => Some(Err(e)),
+      })
+      .transpose()
+      .with_context(|| format!("Failed to read the private key in {}", key.display()))?
+      .with_context(|| format!("{} doesn't contain a private key", key.display()))?;
+
+    let config = rustls::ServerConfig::builder()
+      .with_safe_defaults()
+      .with_no_client_auth()
+      .with_single_cert(
+        certs.into_iter().map(rustls::Certificate).collect(),
+        rustls::PrivateKey(key),
+      )?;
+    Ok(Some(config))
+  }
+}
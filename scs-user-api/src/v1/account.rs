@@ -0,0 +1,66 @@
+use crate::{auth, error::FailWith};
+use actix_http::StatusCode;
+use actix_web::{delete, get, http::header, post, web, HttpResponse, Responder, Result};
+use chrono::Utc;
+use db::Database;
+
+/// How long a deletion request can be cancelled before the account is deleted
+#[derive(Clone, Copy)]
+pub struct DeletionGracePeriod(pub chrono::Duration);
+
+/// Returns everything stored about the caller as a JSON file
+#[get("/me/export")]
+pub async fn export_account(token: auth::AccessToken, db: web::Data<Database>) -> Result<impl Responder> {
+  let export = db::accounts::export(db.get_ref(), token.user_id()).await.internal()?;
+  log::info!("[account] user {} exported their data", token.user_id());
+  Ok(
+    HttpResponse::Ok()
+      .insert_header((
+        header::CONTENT_DISPOSITION,
+        format!("attachment; filename=\"scs-{}.json\"", export.username),
+      ))
+      .json(export),
+  )
+}
+
+/// Schedules the deletion of the caller's account once the grace period is over
+#[post("/me/deletion")]
+pub async fn request_account_deletion(
+  token: auth::AccessToken,
+  db: web::Data<Database>,
+  grace_period: web::Data<DeletionGracePeriod>,
+) -> Result<impl Responder> {
+  let deletion = db::accounts::request_deletion(db.get_ref(), token.user_id(), Utc::now() + grace_period.0)
+    .await
+    .internal()?;
+  log::info!(
+    "[account] user {} requested the deletion of their account, scheduled for {}",
+    token.user_id(),
+    deletion.delete_after()
+  );
+  Ok(web::Json(deletion))
+}
+
+#[get("/me/deletion")]
+pub async fn get_account_deletion(token: auth::AccessToken, db: web::Data<Database>) -> Result<impl Responder> {
+  let deletion = db::accounts::pending_deletion(db.get_ref(), token.user_id())
+    .await
+    .internal()?
+    .with((StatusCode::NOT_FOUND, "No deletion is pending"))?;
+  Ok(web::Json(deletion))
+}
+
+#[delete("/me/deletion")]
+pub async fn cancel_account_deletion(token: auth::AccessToken, db: web::Data<Database>) -> Result<impl Responder> {
+  if !db::accounts::cancel_deletion(db.get_ref(), token.user_id())
+    .await
+    .internal()?
+  {
+    return Err(crate::error::Error::from((StatusCode::NOT_FOUND, "No deletion is pending")).into());
+  }
+  log::info!(
+    "[account] user {} cancelled the deletion of their account",
+    token.user_id()
+  );
+  Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,289 @@
+use crate::auth;
+use crate::ctx::Context;
+use crate::error::FailWith;
+use crate::model_options::ModelOptions;
+use crate::schema;
+use actix_web::{delete, get, http::StatusCode, post, put, web, Responder, Result};
+use db::{self, Database};
+use serde::{Deserialize, Serialize};
+use std::{fmt::Display, future::Future, time::Duration};
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum RedactRequest {
+  /// Redact specific messages
+  ById { ids: Vec<i64> },
+  /// Redact every message in `channel` matching `pattern`, optionally only those sent by `chatter`
+  ByPattern {
+    channel: String,
+    chatter: Option<String>,
+    pattern: String,
+  },
+}
+
+#[derive(Debug, Serialize)]
+pub struct RedactResponse {
+  pub redacted: u64,
+}
+
+#[post("/admin/redact")]
+pub async fn redact_logs(
+  admin: auth::AdminToken,
+  db: web::Data<Database>,
+  body: web::Json<RedactRequest>,
+) -> Result<impl Responder> {
+  let redacted_by = admin.0.user_id();
+  let redacted = match body.into_inner() {
+    RedactRequest::ById { ids } => db::logs::redact_by_id(db.get_ref(), &ids, redacted_by)
+      .await
+      .internal()?,
+    RedactRequest::ByPattern {
+      channel,
+      chatter,
+      pattern,
+    } => {
+      // An empty pattern would match the entire channel
+      if pattern.is_empty() {
+        return Err(crate::error::Error::from("Pattern must not be empty").into());
+      }
+      db::logs::redact_by_pattern(db.get_ref(), channel, chatter, pattern, redacted_by)
+        .await
+        .internal()?
+    }
+  };
+  log::info!("[redact] user {} redacted {} message(s)", redacted_by, redacted);
+  Ok(web::Json(RedactResponse { redacted }))
+}
+
+#[get("/admin/sample-cache")]
+pub async fn get_sample_cache_stats(
+  _: auth::AdminToken,
+  cache: web::Data<crate::sample_cache::SampleCache>,
+) -> Result<impl Responder> {
+  Ok(web::Json(cache.stats()))
+}
+
+/// How long each source of the summary may take before it's reported as timed out
+const SUMMARY_SOURCE_TIMEOUT: Duration = Duration::from_secs(3);
+/// Number of most recently modified models in the summary
+const SUMMARY_LATEST_MODELS: usize = 5;
+
+/// Services queried by the summary, other than the database and the model directory
+pub struct SummarySources {
+  pub collector_status_url: Option<reqwest::Url>,
+}
+
+/// The result of a single source, so that one failing source doesn't fail the whole summary
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Section<T> {
+  Ok(T),
+  Error(String),
+}
+
+async fn section<T, E: Display>(name: &str, source: impl Future<Output = std::result::Result<T, E>>) -> Section<T> {
+  match tokio::time::timeout(SUMMARY_SOURCE_TIMEOUT, source).await {
+    Ok(Ok(value)) => Section::Ok(value),
+    Ok(Err(e)) => {
+      log::warn!("[summary] failed to get {}: {}", name, e);
+      Section::Error(e.to_string())
+    }
+    Err(_) => {
+      log::warn!("[summary] timed out getting {}", name);
+      Section::Error(format!("Timed out after {:?}", SUMMARY_SOURCE_TIMEOUT))
+    }
+  }
+}
+
+#[derive(Serialize)]
+pub struct CollectorStatus {
+  /// HTTP status of the collector's response
+  status: u16,
+  /// The response body, if it's JSON
+  body: Option<serde_json::Value>,
+}
+
+#[derive(Serialize)]
+pub struct Summary {
+  tables: Section<Vec<db::stats::TableRows>>,
+  users: Section<db::stats::UserCounts>,
+  latest_models: Section<Vec<schema::SimpleModelInfo>>,
+  /// `None` if the collector's status URL isn't configured
+  collector: Option<Section<CollectorStatus>>,
+}
+
+async fn latest_models(ctx: &Context) -> anyhow::Result<Vec<schema::SimpleModelInfo>> {
+  let mut models = ctx.read().await.get_models().await?;
+  models.sort_by(|a, b| b.date_modified.cmp(&a.date_modified));
+  models.truncate(SUMMARY_LATEST_MODELS);
+  Ok(models)
+}
+
+async fn collector_status(client: &reqwest::Client, url: reqwest::Url) -> reqwest::Result<CollectorStatus> {
+  let response = client.get(url).send().await?;
+  let status = response.status().as_u16();
+  Ok(CollectorStatus {
+    status,
+    body: response.json().await.ok(),
+  })
+}
+
+#[get("/admin/summary")]
+pub async fn get_summary(
+  _: auth::AdminToken,
+  db: web::Data<Database>,
+  ctx: web::Data<Context>,
+  client: web::Data<reqwest::Client>,
+  sources: web::Data<SummarySources>,
+) -> Result<impl Responder> {
+  let collector = async {
+    match &sources.collector_status_url {
+      Some(url) => Some(section("collector status", collector_status(&client, url.clone())).await),
+      None => None,
+    }
+  };
+  let (tables, users, latest_models, collector) = futures::join!(
+    section("row counts", db::stats::estimated_row_counts(db.get_ref())),
+    section("user counts", db::stats::user_counts(db.get_ref())),
+    section("latest models", latest_models(&ctx)),
+    collector,
+  );
+  Ok(web::Json(Summary {
+    tables,
+    users,
+    latest_models,
+    collector,
+  }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateWebhookRequest {
+  url: String,
+}
+
+/// The secret is only ever returned when the webhook is created
+#[derive(Debug, Serialize)]
+pub struct CreateWebhookResponse {
+  #[serde(flatten)]
+  webhook: db::webhooks::Webhook,
+  secret: String,
+}
+
+#[post("/admin/webhooks")]
+pub async fn create_webhook(
+  admin: auth::AdminToken,
+  db: web::Data<Database>,
+  body: web::Json<CreateWebhookRequest>,
+) -> Result<impl Responder> {
+  let url = reqwest::Url::parse(&body.url).with("Invalid URL")?;
+  if !matches!(url.scheme(), "http" | "https") {
+    return Err(crate::error::Error::from("URL must use http or https").into());
+  }
+
+  let secret = crate::webhooks::generate_secret();
+  let webhook = db::webhooks::create(db.get_ref(), url.as_str(), &secret, admin.0.user_id())
+    .await
+    .internal()?;
+  log::info!(
+    "[webhooks] user {} registered webhook {}",
+    admin.0.user_id(),
+    webhook.id()
+  );
+  Ok(web::Json(CreateWebhookResponse { webhook, secret }))
+}
+
+#[get("/admin/webhooks")]
+pub async fn get_webhooks(_admin: auth::AdminToken, db: web::Data<Database>) -> Result<impl Responder> {
+  Ok(web::Json(db::webhooks::get_all(db.get_ref()).await.internal()?))
+}
+
+#[delete("/admin/webhooks/{id}")]
+pub async fn delete_webhook(
+  admin: auth::AdminToken,
+  db: web::Data<Database>,
+  id: web::Path<i32>,
+) -> Result<impl Responder> {
+  let id = id.into_inner();
+  if !db::webhooks::delete(db.get_ref(), id).await.internal()? {
+    return Err(crate::error::Error::from(StatusCode::NOT_FOUND).into());
+  }
+  log::info!("[webhooks] user {} deleted webhook {}", admin.0.user_id(), id);
+  Ok(web::Json(()))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeliveriesQuery {
+  limit: Option<i32>,
+}
+
+#[get("/admin/webhooks/{id}/deliveries")]
+pub async fn get_webhook_deliveries(
+  _admin: auth::AdminToken,
+  db: web::Data<Database>,
+  id: web::Path<i32>,
+  query: web::Query<DeliveriesQuery>,
+) -> Result<impl Responder> {
+  let limit = query.limit.unwrap_or(50).clamp(1, 500);
+  Ok(web::Json(
+    db::webhooks::get_deliveries(db.get_ref(), id.into_inner(), limit)
+      .await
+      .internal()?,
+  ))
+}
+
+#[get("/admin/models/{name}/options")]
+pub async fn get_model_options(
+  _admin: auth::AdminToken,
+  ctx: web::Data<Context>,
+  name: web::Path<String>,
+) -> Result<impl Responder> {
+  let options = ctx
+    .read()
+    .await
+    .get_model_options(&name)
+    .await
+    .internal()?
+    .with((StatusCode::NOT_FOUND, "Model not found"))?;
+  Ok(web::Json(options))
+}
+
+/// Replaces the default generation options of a model
+#[put("/admin/models/{name}/options")]
+pub async fn set_model_options(
+  admin: auth::AdminToken,
+  ctx: web::Data<Context>,
+  name: web::Path<String>,
+  body: web::Json<ModelOptions>,
+) -> Result<impl Responder> {
+  let options = body.into_inner();
+  options.validate().map_err(crate::error::Error::from)?;
+  ctx
+    .read()
+    .await
+    .set_model_options(&name, &options)
+    .await
+    .internal()?
+    .then_some(())
+    .with((StatusCode::NOT_FOUND, "Model not found"))?;
+  log::info!("[models] user {} updated the options of {}", admin.0.user_id(), name);
+  Ok(web::Json(options))
+}
+
+/// Removes the stored options of a model, so that it uses the defaults
+#[delete("/admin/models/{name}/options")]
+pub async fn reset_model_options(
+  admin: auth::AdminToken,
+  ctx: web::Data<Context>,
+  name: web::Path<String>,
+) -> Result<impl Responder> {
+  ctx
+    .read()
+    .await
+    .reset_model_options(&name)
+    .await
+    .internal()?
+    .then_some(())
+    .with((StatusCode::NOT_FOUND, "Model not found"))?;
+  log::info!("[models] user {} reset the options of {}", admin.0.user_id(), name);
+  Ok(web::Json(ModelOptions::default()))
+}
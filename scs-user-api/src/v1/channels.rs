@@ -0,0 +1,173 @@
+use crate::{
+  auth, ctx::Context, error::FailWith, moderation::Moderator, poster::Poster, rate_limit::RateLimiter, schema,
+};
+use actix_http::StatusCode;
+use actix_web::{delete, post, put, web, HttpResponse, Responder, Result};
+use db::Database;
+use serde::Deserialize;
+
+/// Limits the number of messages posted to each channel, keyed by channel name
+pub struct PostLimiter(pub RateLimiter);
+
+#[derive(Debug, Deserialize)]
+pub struct PostMessageRequest {
+  pub model: String,
+  /// Seed of the generated text, a random word is picked if it's empty
+  #[serde(default)]
+  pub seed: String,
+  /// Treat `seed` as the beginning of a message, and complete it
+  #[serde(default)]
+  pub continuation: bool,
+}
+
+/// Generates text from a model and posts it to the chat of `channel` as the bot account.
+/// Only admins, and admins of the channel, may post to it. `channel` must be a Twitch login, since it's sent to Twitch
+/// as part of an IRC command.
+#[post("/channels/{channel}/messages")]
+pub async fn post_generated_message(
+  user: auth::AccessToken,
+  ctx: web::Data<Context>,
+  db: web::Data<Database>,
+  poster: Option<web::Data<Poster>>,
+  limiter: web::Data<PostLimiter>,
+  moderator: Option<web::Data<Moderator>>,
+  channel: web::Path<String>,
+  body: web::Json<PostMessageRequest>,
+) -> Result<impl Responder> {
+  let channel = channel.into_inner().to_lowercase();
+  if !twitch_api::is_valid_login(&channel) {
+    return Err(crate::error::Error::from("Invalid channel name").into());
+  }
+  let allowed = db::allowlist::is_admin(db.get_ref(), user.user_id()).await.internal()?
+    || db::channel_admins::is_channel_admin(db.get_ref(), user.user_id(), &channel)
+      .await
+      .internal()?;
+  if !allowed {
+    return Err(crate::error::Error::from(StatusCode::FORBIDDEN).into());
+  }
+  let poster = poster.with((StatusCode::SERVICE_UNAVAILABLE, "Posting to Twitch is not configured"))?;
+  if !limiter.0.check(&channel) {
+    return Err(crate::error::Error::from(StatusCode::TOO_MANY_REQUESTS).into());
+  }
+
+  let PostMessageRequest {
+    model: name,
+    seed,
+    continuation,
+  } = body.into_inner();
+  let model = ctx
+    .write()
+    .await
+    .get_model(&name)
+    .await
+    .internal()?
+    .with((StatusCode::NOT_FOUND, "Model not found"))?;
+  let options = ctx
+    .read()
+    .await
+    .get_model_options(&name)
+    .await
+    .internal()?
+    .with((StatusCode::NOT_FOUND, "Model not found"))?;
+  let text = super::models::sample(model, &name, seed.clone(), continuation, None, None, options).await?;
+  if text.trim().is_empty() {
+    return Err(crate::error::Error::from("The model generated an empty message, try another seed").into());
+  }
+  // Unsafe text is never posted, even if it would only be annotated otherwise
+  let safety = super::models::moderate(moderator.as_deref(), &text).await?;
+  if let Some(verdict) = safety.as_ref().filter(|verdict| !verdict.safe) {
+    log::info!(
+      "[post] refused to post to {}: {}",
+      channel,
+      verdict.reason.as_deref().unwrap_or("unsafe")
+    );
+    return Err(
+      crate::error::Error::from((
+        StatusCode::UNPROCESSABLE_ENTITY,
+        "The generated text was refused by moderation, try again or use another seed",
+      ))
+      .into(),
+    );
+  }
+
+  poster
+    .post(&channel, &text)
+    .await
+    .map_err(|e| {
+      log::error!("[post] failed to post to {}: {:#}", channel, e);
+      e
+    })
+    .with((StatusCode::BAD_GATEWAY, "Failed to post the message to Twitch"))?;
+  log::info!(
+    "[post] user {} posted to {} with model {}",
+    user.user_id(),
+    channel,
+    name
+  );
+
+  let generation = db::generations::create(db.get_ref(), user.user_id(), &name, &seed, &text)
+    .await
+    .internal()?;
+  Ok(web::Json(schema::GeneratedText {
+    text,
+    generation_id: Some(generation.id()),
+    safety,
+  }))
+}
+
+/// Makes `login` an admin of `channel`, which must be a known channel
+#[put("/admin/channels/{channel}/admins/{login}")]
+pub async fn grant_channel_admin(
+  admin: auth::AdminToken,
+  db: web::Data<Database>,
+  path: web::Path<(String, String)>,
+) -> Result<impl Responder> {
+  let (channel, login) = path.into_inner();
+  let (channel, login) = (channel.to_lowercase(), login.to_lowercase());
+  if !twitch_api::is_valid_login(&login) {
+    return Err(crate::error::Error::from("Invalid login").into());
+  }
+  db::users::resolve_ids(db.get_ref(), std::slice::from_ref(&channel))
+    .await
+    .internal()?
+    .contains_key(&channel)
+    .then_some(())
+    .with((StatusCode::NOT_FOUND, "Channel not found"))?;
+  let user = db::users::get_or_create(db.get_ref(), &login, None).await.internal()?;
+  let granted = db::channel_admins::grant(db.get_ref(), user.id(), &channel)
+    .await
+    .internal()?;
+  if granted {
+    log::info!(
+      "[channel-admins] user {} made {} an admin of {}",
+      admin.0.user_id(),
+      login,
+      channel
+    );
+  }
+  Ok(HttpResponse::NoContent().finish())
+}
+
+#[delete("/admin/channels/{channel}/admins/{login}")]
+pub async fn revoke_channel_admin(
+  admin: auth::AdminToken,
+  db: web::Data<Database>,
+  path: web::Path<(String, String)>,
+) -> Result<impl Responder> {
+  let (channel, login) = path.into_inner();
+  let user = db::users::get_or_create(db.get_ref(), &login.to_lowercase(), None)
+    .await
+    .internal()?;
+  db::channel_admins::revoke(db.get_ref(), user.id(), &channel.to_lowercase())
+    .await
+    .internal()?
+    .then_some(())
+    .with((StatusCode::NOT_FOUND, "User is not an admin of the channel"))?;
+  log::info!(
+    "[channel-admins] user {} revoked {} as an admin of {}",
+    admin.0.user_id(),
+    login,
+    channel
+  );
+  Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,91 @@
+use crate::{auth, error::FailWith};
+use actix_web::{get, post, web, Responder, Result};
+use db::Database;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tracing::Instrument;
+
+pub const MAX_PAGE_SIZE: i64 = 10_000;
+pub const DEFAULT_PAGE_SIZE: i64 = 1000;
+/// Most IDs and usernames which may be resolved in one request, in total
+pub const MAX_RESOLVED: usize = 100_000;
+
+#[derive(Debug, Deserialize)]
+pub struct ChattersQuery {
+  /// ID of the last chatter of the previous page
+  pub after: Option<i32>,
+  pub page_size: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChattersResponse {
+  pub chatters: Vec<db::users::ChatterInfo>,
+  /// Value of `after` for the next page, `None` if this was the last one
+  pub after: Option<i32>,
+}
+
+/// Lists every known user (chatters and channels) for export tooling. Admin only.
+#[get("/chatters")]
+pub async fn get_chatters(
+  _: auth::AdminToken,
+  db: web::Data<Database>,
+  query: web::Query<ChattersQuery>,
+) -> Result<impl Responder> {
+  let page_size = query.page_size.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+  let chatters = db::users::list_chatters(db.get_ref(), query.after, page_size)
+    .instrument(tracing::info_span!("db", query = "list_chatters"))
+    .await
+    .internal()?;
+  let after = if chatters.len() as i64 == page_size {
+    chatters.last().map(|c| c.id())
+  } else {
+    None
+  };
+  Ok(web::Json(ChattersResponse { chatters, after }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResolveRequest {
+  #[serde(default)]
+  pub ids: Vec<i32>,
+  #[serde(default)]
+  pub usernames: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ResolveResponse {
+  /// ID -> username, without the unknown IDs
+  pub usernames: HashMap<i32, String>,
+  /// Username -> ID, without the unknown usernames
+  pub ids: HashMap<String, i32>,
+}
+
+/// Maps user IDs to usernames and back in bulk. Admin only.
+#[post("/chatters/resolve")]
+pub async fn resolve_chatters(
+  _: auth::AdminToken,
+  db: web::Data<Database>,
+  body: web::Json<ResolveRequest>,
+) -> Result<impl Responder> {
+  let ResolveRequest { ids, usernames } = body.into_inner();
+  if ids.len() + usernames.len() > MAX_RESOLVED {
+    return Err(
+      crate::error::Error::from(format!(
+        "At most {MAX_RESOLVED} IDs and usernames may be resolved at once"
+      ))
+      .into(),
+    );
+  }
+  let usernames_by_id = db::users::resolve_usernames(db.get_ref(), &ids)
+    .instrument(tracing::info_span!("db", query = "resolve_usernames"))
+    .await
+    .internal()?;
+  let ids_by_username = db::users::resolve_ids(db.get_ref(), &usernames)
+    .instrument(tracing::info_span!("db", query = "resolve_ids"))
+    .await
+    .internal()?;
+  Ok(web::Json(ResolveResponse {
+    usernames: usernames_by_id,
+    ids: ids_by_username,
+  }))
+}
@@ -0,0 +1,147 @@
+use super::logs::{parse_cursor, DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE};
+use crate::{auth, error::FailWith};
+use actix_http::StatusCode;
+use actix_web::{delete, get, post, put, web, Responder, Result};
+use chrono::{DateTime, Utc};
+use db::{generations::HistoryFilter, pagination::next_cursor, Database};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct HistoryQuery {
+  /// Only return generations created at or after this time
+  pub since: Option<DateTime<Utc>>,
+  /// Only return generations created before this time
+  pub until: Option<DateTime<Utc>>,
+  /// Comma-separated list of models to return the generations of
+  pub models: Option<String>,
+  #[serde(default)]
+  pub favorites_only: bool,
+  pub cursor: Option<String>,
+  pub page_size: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HistoryResponse {
+  pub generations: Vec<db::generations::Generation>,
+  pub cursor: Option<String>,
+}
+
+/// The caller's generations, newest first
+#[get("/generations")]
+pub async fn get_history(
+  token: auth::AccessToken,
+  db: web::Data<Database>,
+  query: web::Query<HistoryQuery>,
+) -> Result<impl Responder> {
+  let HistoryQuery {
+    since,
+    until,
+    models,
+    favorites_only,
+    cursor,
+    page_size,
+  } = query.0;
+  if let (Some(since), Some(until)) = (since, until) {
+    if since >= until {
+      return Err(crate::error::Error::from("`since` must be before `until`").into());
+    }
+  }
+  let cursor = parse_cursor(cursor)?;
+  let filter = HistoryFilter {
+    since,
+    until,
+    models: models
+      .iter()
+      .flat_map(|models| models.split(','))
+      .map(str::trim)
+      .filter(|model| !model.is_empty())
+      .map(String::from)
+      .collect(),
+    favorites_only,
+  };
+
+  let generations = db::generations::history(
+    db.get_ref(),
+    token.user_id(),
+    &filter,
+    page_size.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE) as i32,
+    cursor,
+  )
+  .await
+  .internal()?;
+  Ok(web::Json(HistoryResponse {
+    cursor: next_cursor(&generations),
+    generations,
+  }))
+}
+
+/// Marks one of the caller's generations as a favorite
+#[put("/generations/{id}/favorite")]
+pub async fn add_favorite(
+  token: auth::AccessToken,
+  db: web::Data<Database>,
+  id: web::Path<i64>,
+) -> Result<impl Responder> {
+  set_favorite(&token, db.get_ref(), *id, true).await
+}
+
+/// Removes one of the caller's generations from their favorites
+#[delete("/generations/{id}/favorite")]
+pub async fn remove_favorite(
+  token: auth::AccessToken,
+  db: web::Data<Database>,
+  id: web::Path<i64>,
+) -> Result<impl Responder> {
+  set_favorite(&token, db.get_ref(), *id, false).await
+}
+
+async fn set_favorite(
+  token: &auth::AccessToken,
+  db: &Database,
+  id: i64,
+  favorite: bool,
+) -> Result<web::Json<db::generations::Generation>> {
+  // Like with share links, generations of other users are reported as missing
+  let generation = db::generations::set_favorite(db, token.user_id(), id, favorite)
+    .await
+    .internal()?
+    .with((StatusCode::NOT_FOUND, "Generation not found"))?;
+  Ok(web::Json(generation))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteGenerationsBody {
+  pub ids: Vec<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeleteGenerationsResponse {
+  pub deleted: Vec<i64>,
+}
+
+/// Deletes the selected generations of the caller, along with their share links
+#[post("/generations/delete")]
+pub async fn delete_generations(
+  token: auth::AccessToken,
+  db: web::Data<Database>,
+  body: web::Json<DeleteGenerationsBody>,
+) -> Result<impl Responder> {
+  if body.ids.is_empty() || body.ids.len() > MAX_PAGE_SIZE as usize {
+    return Err(
+      crate::error::Error::from(format!(
+        "Between 1 and {MAX_PAGE_SIZE} generations can be deleted at once"
+      ))
+      .into(),
+    );
+  }
+
+  let deleted = db::generations::delete_many(db.get_ref(), token.user_id(), &body.ids)
+    .await
+    .internal()?;
+  log::info!(
+    "[generations] user {} deleted {} generations",
+    token.user_id(),
+    deleted.len()
+  );
+  Ok(web::Json(DeleteGenerationsResponse { deleted }))
+}
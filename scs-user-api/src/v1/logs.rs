@@ -1,17 +1,46 @@
 use crate::auth;
+use crate::conditional::Validator;
 use crate::error::FailWith;
-use actix_web::{get, web, Responder, Result};
-use base64::{engine::general_purpose, Engine as _};
-use db::{self, Database};
+use actix_http::StatusCode;
+use actix_web::{get, web, HttpRequest, Responder, Result};
+use db::{
+  self,
+  pagination::{next_cursor, Cursor},
+  Database,
+};
 use serde::{Deserialize, Serialize};
+use tracing::Instrument;
 
 pub const MAX_PAGE_SIZE: u32 = 1024;
 pub const DEFAULT_PAGE_SIZE: u32 = 128;
+/// Longest time range chatter statistics may be computed over
+pub const MAX_STATS_RANGE_DAYS: i64 = 365;
+pub const DEFAULT_STATS_RANGE_DAYS: i64 = 30;
+/// Statistics are computed from a random sample of this many messages if the chatter sent more
+pub const MAX_STATS_MESSAGES: i64 = 10_000;
+pub const MAX_STATS_TOP: u32 = 100;
+pub const DEFAULT_STATS_TOP: u32 = 20;
 
 #[get("/logs/channels")]
-pub async fn get_channel_list(_: auth::AccessToken, db: web::Data<Database>) -> Result<impl Responder> {
-  let channels = db::channels::get_logged_channels(db.get_ref()).await.internal()?;
-  Ok(web::Json(channels))
+pub async fn get_channel_list(
+  _: auth::AccessToken,
+  req: HttpRequest,
+  db: web::Data<Database>,
+) -> Result<impl Responder> {
+  let version = db::channels::get_logged_channels_version(db.get_ref())
+    .instrument(tracing::info_span!("db", query = "get_logged_channels_version"))
+    .await
+    .internal()?;
+  let validator = Validator::new(&format!("{version:?}"));
+  if validator.is_fresh(&req) {
+    return Ok(validator.not_modified());
+  }
+
+  let channels = db::channels::get_logged_channels_with_stats(db.get_ref())
+    .instrument(tracing::info_span!("db", query = "get_logged_channels_with_stats"))
+    .await
+    .internal()?;
+  Ok(validator.ok().json(channels))
 }
 
 #[derive(Debug, Deserialize)]
@@ -20,17 +49,52 @@ pub struct ChannelLogsQuery {
   pub pattern: Option<String>,
   pub cursor: Option<String>,
   pub page_size: Option<u32>,
+  /// Also return redacted messages. Only available to admins.
+  #[serde(default)]
+  pub include_redacted: bool,
+  /// IANA timezone to also return the timestamps in, e.g. `Europe/Berlin`
+  pub tz: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct ChannelsResponse<T> {
-  pub messages: Vec<db::logs::Entry<T>>,
+  pub messages: Vec<LocalizedEntry<T>>,
   pub cursor: Option<String>,
+  /// Timezone of `sent_at_local`, if one was requested
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub timezone: Option<&'static str>,
+}
+
+/// A message with its timestamp in the requested timezone, in addition to `sent_at` in UTC
+#[derive(Debug, Serialize)]
+pub struct LocalizedEntry<T> {
+  #[serde(flatten)]
+  pub entry: db::logs::Entry<T>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub sent_at_local: Option<String>,
+}
+
+impl<T> ChannelsResponse<T> {
+  fn new(messages: Vec<db::logs::Entry<T>>, tz: Option<chrono_tz::Tz>) -> Self {
+    let cursor = next_cursor(&messages);
+    let messages = messages
+      .into_iter()
+      .map(|entry| LocalizedEntry {
+        sent_at_local: tz.map(|tz| crate::timezone::format(entry.sent_at(), tz)),
+        entry,
+      })
+      .collect();
+    Self {
+      messages,
+      cursor,
+      timezone: tz.map(|tz| tz.name()),
+    }
+  }
 }
 
 #[get("/logs/{channel}")]
 pub async fn get_channel_logs(
-  _: auth::AccessToken,
+  token: auth::AccessToken,
   db: web::Data<Database>,
   channel: web::Path<String>,
   query: web::Query<ChannelLogsQuery>,
@@ -40,7 +104,18 @@ pub async fn get_channel_logs(
     pattern,
     cursor,
     page_size,
+    include_redacted,
+    tz,
   } = query.0;
+  let tz = tz.as_deref().map(crate::timezone::parse).transpose()?;
+
+  if include_redacted
+    && !db::allowlist::is_admin(db.get_ref(), token.user_id())
+      .await
+      .internal()?
+  {
+    return Err(crate::error::Error::from(StatusCode::FORBIDDEN).into());
+  }
 
   let cursor = parse_cursor(cursor)?;
 
@@ -51,43 +126,168 @@ pub async fn get_channel_logs(
     pattern,
     page_size.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE) as i32,
     cursor,
+    include_redacted,
   )
+  .instrument(tracing::info_span!("db", query = "fetch_logs_paged_with_usernames"))
   .await
   .internal()?;
-  let cursor = generate_cursor(&messages);
-  Ok(web::Json(ChannelsResponse { messages, cursor }))
+  Ok(web::Json(ChannelsResponse::new(messages, tz)))
 }
 
-fn parse_cursor(cursor: Option<String>) -> Result<Option<(i64, chrono::DateTime<chrono::Utc>)>> {
-  Ok(if let Some(c) = cursor {
-    if c.is_empty() {
-      return Ok(None);
-    }
-    let bytes = general_purpose::URL_SAFE
-      .decode(c)
-      .map_err(|e| actix_web::error::ErrorBadRequest(format!("Invalid cursor: {}", e)))?;
-    let decoded = String::from_utf8(bytes)
-      .map_err(|e| actix_web::error::ErrorBadRequest(format!("Cursor is not a valid utf-8 string: {}", e)))?;
-    let (id, sent_at) = decoded
-      .split_once(',')
-      .ok_or_else(|| actix_web::error::ErrorBadRequest("Cursor string is not correctly formatted"))?;
-    let id = id
-      .parse::<i64>()
-      .map_err(|e| actix_web::error::ErrorBadRequest(format!("Channel ID cursor is not a valid number: {}", e)))?;
-    let sent_at = chrono::DateTime::<chrono::FixedOffset>::parse_from_rfc3339(sent_at)
-      .map_err(|e| {
-        actix_web::error::ErrorBadRequest(format!("sent_at cursor is not a valid RFC3339 date string: {}", e))
-      })?
-      .with_timezone(&chrono::Utc);
-    Some((id, sent_at))
-  } else {
-    None
-  })
+#[derive(Debug, Deserialize)]
+pub struct ChatterLogsQuery {
+  /// Only return messages sent at or after this time
+  pub since: Option<chrono::DateTime<chrono::Utc>>,
+  /// Only return messages sent before this time
+  pub until: Option<chrono::DateTime<chrono::Utc>>,
+  pub cursor: Option<String>,
+  pub page_size: Option<u32>,
+  #[serde(default)]
+  pub include_redacted: bool,
+  /// IANA timezone to also return the timestamps in
+  pub tz: Option<String>,
+}
+
+/// Messages sent by a chatter in every logged channel. This is sensitive, so it's only available to admins.
+#[get("/chatters/{login}/logs")]
+pub async fn get_chatter_logs(
+  admin: auth::AdminToken,
+  db: web::Data<Database>,
+  login: web::Path<String>,
+  query: web::Query<ChatterLogsQuery>,
+) -> Result<impl Responder> {
+  let ChatterLogsQuery {
+    since,
+    until,
+    cursor,
+    page_size,
+    include_redacted,
+    tz,
+  } = query.0;
+  let tz = tz.as_deref().map(crate::timezone::parse).transpose()?;
+
+  let cursor = parse_cursor(cursor)?;
+  log::info!(
+    "[chatter-logs] user {} fetched the logs of {}",
+    admin.0.user_id(),
+    login
+  );
+
+  let messages = db::logs::fetch_chatter_logs_paged(
+    db.get_ref(),
+    &login,
+    since,
+    until,
+    page_size.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE) as i32,
+    cursor,
+    include_redacted,
+  )
+  .instrument(tracing::info_span!("db", query = "fetch_chatter_logs_paged"))
+  .await
+  .internal()?;
+  Ok(web::Json(ChannelsResponse::new(messages, tz)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChatterStatsQuery {
+  /// Defaults to 30 days before `until`
+  pub since: Option<chrono::DateTime<chrono::Utc>>,
+  /// Defaults to now
+  pub until: Option<chrono::DateTime<chrono::Utc>>,
+  /// Number of words and bigrams to return
+  pub top: Option<u32>,
+}
+
+/// Most common words and bigrams of a chatter in a channel, and the distribution of their message lengths
+#[get("/logs/{channel}/chatters/{chatter}/stats")]
+pub async fn get_chatter_stats(
+  _: auth::AccessToken,
+  db: web::Data<Database>,
+  path: web::Path<(String, String)>,
+  query: web::Query<ChatterStatsQuery>,
+) -> Result<impl Responder> {
+  let (channel, chatter) = path.into_inner();
+  let until = query.until.unwrap_or_else(chrono::Utc::now);
+  let since = query
+    .since
+    .unwrap_or_else(|| until - chrono::Duration::days(DEFAULT_STATS_RANGE_DAYS));
+  if since >= until {
+    return Err(crate::error::Error::from("`since` must be before `until`").into());
+  }
+  if until - since > chrono::Duration::days(MAX_STATS_RANGE_DAYS) {
+    return Err(crate::error::Error::from(format!("The time range may be at most {MAX_STATS_RANGE_DAYS} days")).into());
+  }
+
+  let stats = db::chatter_stats::fetch(
+    db.get_ref(),
+    &channel,
+    &chatter,
+    since,
+    until,
+    MAX_STATS_MESSAGES,
+    query.top.unwrap_or(DEFAULT_STATS_TOP).min(MAX_STATS_TOP) as i64,
+  )
+  .instrument(tracing::info_span!("db", query = "chatter_stats"))
+  .await
+  .internal()?;
+  Ok(web::Json(stats))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PhraseCountsQuery {
+  pub phrase: String,
+  /// Only count the messages of this channel
+  pub channel: Option<String>,
+  /// Only count the messages of this chatter
+  pub chatter: Option<String>,
+  /// Defaults to 365 days before `until`
+  pub since: Option<chrono::DateTime<chrono::Utc>>,
+  /// Defaults to now
+  pub until: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Number of messages containing a phrase per channel and month, e.g. to check where a model's output comes from
+#[get("/phrase-counts")]
+pub async fn get_phrase_counts(
+  _: auth::AccessToken,
+  db: web::Data<Database>,
+  query: web::Query<PhraseCountsQuery>,
+) -> Result<impl Responder> {
+  let query = query.into_inner();
+  if query.phrase.chars().count() < db::phrases::MIN_PHRASE_LENGTH {
+    return Err(
+      crate::error::Error::from(format!(
+        "The phrase must be at least {} characters long",
+        db::phrases::MIN_PHRASE_LENGTH
+      ))
+      .into(),
+    );
+  }
+  let until = query.until.unwrap_or_else(chrono::Utc::now);
+  let since = query
+    .since
+    .unwrap_or_else(|| until - chrono::Duration::days(MAX_STATS_RANGE_DAYS));
+  if since >= until {
+    return Err(crate::error::Error::from("`since` must be before `until`").into());
+  }
+  if until - since > chrono::Duration::days(MAX_STATS_RANGE_DAYS) {
+    return Err(crate::error::Error::from(format!("The time range may be at most {MAX_STATS_RANGE_DAYS} days")).into());
+  }
+
+  let counts = db::phrases::count_by_month(
+    db.get_ref(),
+    &query.phrase,
+    query.channel.as_deref(),
+    query.chatter.as_deref(),
+    since,
+    until,
+  )
+  .instrument(tracing::info_span!("db", query = "phrase_counts"))
+  .await
+  .internal()?;
+  Ok(web::Json(counts))
 }
 
-fn generate_cursor<T>(messages: &[db::logs::Entry<T>]) -> Option<String> {
-  messages.last().map(|msg| {
-    let cursor = format!("{},{}", msg.id(), msg.sent_at().to_rfc3339());
-    general_purpose::URL_SAFE.encode(cursor)
-  })
+pub(crate) fn parse_cursor(cursor: Option<String>) -> Result<Option<Cursor>> {
+  Cursor::parse(cursor.as_deref()).map_err(|e| crate::error::Error::from(e.to_string()).into())
 }
@@ -1,14 +1,56 @@
 use actix_web::{web, Scope};
 
+pub mod account;
+pub mod admin;
+pub mod channels;
+pub mod chatters;
+pub mod generations;
 pub mod logs;
 pub mod models;
+pub mod sessions;
+pub mod shares;
 
 pub fn routes() -> Scope {
   web::scope("/v1")
     .service(logs::get_channel_list)
     .service(logs::get_channel_logs)
+    .service(logs::get_chatter_logs)
+    .service(logs::get_chatter_stats)
+    .service(logs::get_phrase_counts)
     .service(models::get_models_list)
+    .service(models::compare_models)
     .service(models::get_model)
+    .service(models::delete_model)
+    .service(models::restore_model)
+    .service(models::score_text)
     .service(models::get_model_edges)
     .service(models::get_model_generated_text)
+    .service(sessions::create_session_model)
+    .service(sessions::get_session_model_generated_text)
+    .service(sessions::delete_session_model)
+    .service(generations::get_history)
+    .service(generations::add_favorite)
+    .service(generations::remove_favorite)
+    .service(generations::delete_generations)
+    .service(shares::create_share)
+    .service(shares::get_shared_generation)
+    .service(admin::redact_logs)
+    .service(admin::get_sample_cache_stats)
+    .service(admin::get_summary)
+    .service(admin::create_webhook)
+    .service(admin::get_webhooks)
+    .service(admin::delete_webhook)
+    .service(admin::get_webhook_deliveries)
+    .service(admin::get_model_options)
+    .service(admin::set_model_options)
+    .service(admin::reset_model_options)
+    .service(channels::post_generated_message)
+    .service(channels::grant_channel_admin)
+    .service(channels::revoke_channel_admin)
+    .service(chatters::get_chatters)
+    .service(chatters::resolve_chatters)
+    .service(account::export_account)
+    .service(account::request_account_deletion)
+    .service(account::get_account_deletion)
+    .service(account::cancel_account_deletion)
 }
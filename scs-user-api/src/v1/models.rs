@@ -1,11 +1,258 @@
-use crate::{auth, ctx::Context, error::FailWith};
-use actix_web::{get, web, HttpResponse, Responder, Result};
-use serde::Deserialize;
+use crate::{
+  auth,
+  conditional::Validator,
+  ctx::{Context, RestoreOutcome},
+  error::FailWith,
+  model_options::ModelOptions,
+  moderation::{Moderator, Verdict},
+  sample_cache, schema,
+};
+use actix_http::StatusCode;
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse, Responder, Result};
+use rand::{rngs::StdRng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use tracing::Instrument;
+
+pub const MAX_PAGE_SIZE: usize = 1024;
+pub const DEFAULT_PAGE_SIZE: usize = 128;
+/// Most models which may be compared in one request
+pub const MAX_COMPARED_MODELS: usize = 8;
+/// Most words added by the `greedy` and `beam` strategies, before the text is cut to `max_length`
+pub const MAX_COMPLETION_TOKENS: usize = 64;
+pub const DEFAULT_BEAM_WIDTH: usize = 3;
+pub const MAX_BEAM_WIDTH: usize = 16;
+
+/// How the next word is picked
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Strategy {
+  /// At random, weighted by how often each word followed the previous ones
+  #[default]
+  Sample,
+  /// Always the most likely word, see [`chain::Chain::generate_greedy`]
+  Greedy,
+  /// The best completion found by a beam search, which is approximate, see [`chain::Chain::generate_beam`]
+  Beam,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModelSort {
+  #[default]
+  Name,
+  DateModified,
+  Size,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ModelsQuery {
+  /// Case-insensitive substring of the model name
+  pub name: Option<String>,
+  #[serde(default)]
+  pub sort: ModelSort,
+  #[serde(default)]
+  pub desc: bool,
+  /// Group timestamped checkpoints under their base model
+  #[serde(default)]
+  pub group: bool,
+  pub limit: Option<usize>,
+  #[serde(default)]
+  pub offset: usize,
+  /// Comma-separated list of extra information to include with each model: `meta` and `usage`
+  pub include: Option<String>,
+}
+
+/// Extra information to include with each model in the list
+#[derive(Clone, Copy, Debug, Default)]
+struct Include {
+  meta: bool,
+  usage: bool,
+}
+
+impl std::str::FromStr for Include {
+  type Err = String;
+
+  fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+    let mut include = Include::default();
+    for part in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+      match part {
+        "meta" => include.meta = true,
+        "usage" => include.usage = true,
+        other => return Err(format!("Unknown include `{other}`, expected `meta` or `usage`")),
+      }
+    }
+    Ok(include)
+  }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModelsResponse<T> {
+  /// Number of models (or families) which matched the filter, before pagination
+  pub total: usize,
+  pub models: Vec<T>,
+}
+
+/// Splits a checkpoint name like `channel-2023-07-01` into `channel` and its date
+fn split_checkpoint(name: &str) -> (&str, Option<&str>) {
+  name
+    .len()
+    .checked_sub("-YYYY-MM-DD".len())
+    .filter(|&i| name.is_char_boundary(i) && name[i..].starts_with('-'))
+    .filter(|&i| chrono::NaiveDate::parse_from_str(&name[i + 1..], "%Y-%m-%d").is_ok())
+    .map_or((name, None), |i| (&name[..i], Some(&name[i + 1..])))
+}
+
+fn compare(a: &schema::SimpleModelInfo, b: &schema::SimpleModelInfo, sort: ModelSort) -> std::cmp::Ordering {
+  match sort {
+    ModelSort::Name => a.name.cmp(&b.name),
+    ModelSort::DateModified => a.date_modified.cmp(&b.date_modified),
+    ModelSort::Size => a.size.total_cmp(&b.size),
+  }
+}
+
+fn group(models: Vec<schema::SimpleModelInfo>) -> Vec<schema::ModelFamily> {
+  let mut families = std::collections::BTreeMap::<String, schema::ModelFamily>::new();
+  for model in models {
+    let (base, date) = split_checkpoint(&model.name);
+    let family = families.entry(base.to_owned()).or_insert_with(|| schema::ModelFamily {
+      name: base.to_owned(),
+      latest: None,
+      checkpoints: vec![],
+    });
+    match date {
+      Some(_) => family.checkpoints.push(model),
+      None => family.latest = Some(model),
+    }
+  }
+  families.into_values().collect()
+}
+
+fn members(family: &schema::ModelFamily) -> impl Iterator<Item = &schema::SimpleModelInfo> {
+  family.latest.iter().chain(family.checkpoints.iter())
+}
+
+/// Families are sorted by their name, most recently modified model, or total size
+fn compare_families(a: &schema::ModelFamily, b: &schema::ModelFamily, sort: ModelSort) -> std::cmp::Ordering {
+  match sort {
+    ModelSort::Name => a.name.cmp(&b.name),
+    ModelSort::DateModified => {
+      let latest = |f| members(f).map(|m| m.date_modified).max();
+      latest(a).cmp(&latest(b))
+    }
+    ModelSort::Size => {
+      let total = |f| members(f).map(|m| m.size).sum::<f64>();
+      total(a).total_cmp(&total(b))
+    }
+  }
+}
+
+/// Adds the information requested by `include` to `models`, the header metadata is read from a cache
+async fn include_extra(
+  ctx: &Context,
+  db: &db::Database,
+  include: Include,
+  mut models: Vec<&mut schema::SimpleModelInfo>,
+) -> Result<()> {
+  if include.meta {
+    let mut metas = {
+      let refs = models.iter().map(|m| &**m).collect::<Vec<_>>();
+      ctx.get_models_meta(&refs).await.internal()?
+    };
+    for model in &mut models {
+      model.meta = metas.remove(&model.name);
+    }
+  }
+  if include.usage {
+    let names = models.iter().map(|m| m.name.clone()).collect::<Vec<_>>();
+    let mut usage = db::generations::usage_by_model(db, &names)
+      .await
+      .internal()?
+      .into_iter()
+      .map(|u| {
+        let usage = schema::ModelUsage {
+          generations: u.generations(),
+          users: u.users(),
+          last_generated_at: Some(u.last_generated_at()),
+        };
+        (u.model().clone(), usage)
+      })
+      .collect::<std::collections::HashMap<_, _>>();
+    for model in &mut models {
+      model.usage = Some(usage.remove(&model.name).unwrap_or_default());
+    }
+  }
+  Ok(())
+}
+
+fn paginate<T>(mut items: Vec<T>, query: &ModelsQuery) -> ModelsResponse<T> {
+  let total = items.len();
+  let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE);
+  let models = items.drain(query.offset.min(total)..).take(limit).collect();
+  ModelsResponse { total, models }
+}
 
 #[get("/models")]
-pub async fn get_models_list(_: auth::AccessToken, ctx: web::Data<Context>) -> Result<impl Responder> {
-  let channels = ctx.write().await.get_models().await.internal()?;
-  Ok(web::Json(channels))
+pub async fn get_models_list(
+  _: auth::AccessToken,
+  req: HttpRequest,
+  ctx: web::Data<Context>,
+  db: web::Data<db::Database>,
+  query: web::Query<ModelsQuery>,
+) -> Result<impl Responder> {
+  let include = match &query.include {
+    Some(include) => include.parse::<Include>().map_err(crate::error::Error::from)?,
+    None => Include::default(),
+  };
+  let mut models = ctx.write().await.get_models().await.internal()?;
+
+  let validator = models_validator(&req, &db, include, &models).await?;
+  if validator.is_fresh(&req) {
+    return Ok(validator.not_modified());
+  }
+
+  if let Some(name) = &query.name {
+    let name = name.to_lowercase();
+    models.retain(|model| model.name.to_lowercase().contains(&name));
+  }
+
+  let sort = query.sort;
+  let order = |ordering: std::cmp::Ordering| if query.desc { ordering.reverse() } else { ordering };
+  models.sort_by(|a, b| order(compare(a, b, sort)));
+
+  Ok(if query.group {
+    let mut families = group(models);
+    families.sort_by(|a, b| order(compare_families(a, b, sort)));
+    let mut page = paginate(families, &query);
+    let members = page
+      .models
+      .iter_mut()
+      .flat_map(|f| f.latest.iter_mut().chain(f.checkpoints.iter_mut()))
+      .collect();
+    include_extra(&ctx, &db, include, members).await?;
+    validator.ok().json(page)
+  } else {
+    let mut page = paginate(models, &query);
+    include_extra(&ctx, &db, include, page.models.iter_mut().collect()).await?;
+    validator.ok().json(page)
+  })
+}
+
+/// The model list changes with the query and the model files, and with new generations if their usage is included.
+/// Headers are read from the model files, so they're covered by the modification times.
+async fn models_validator(
+  req: &HttpRequest,
+  db: &db::Database,
+  include: Include,
+  models: &[schema::SimpleModelInfo],
+) -> Result<Validator> {
+  let mut files = models.iter().map(|m| (&m.name, m.date_modified)).collect::<Vec<_>>();
+  files.sort();
+  let mut version = format!("{}|{files:?}", req.query_string());
+  if include.usage {
+    let latest = db::generations::latest_id(db).await.internal()?;
+    version.push_str(&format!("|{latest:?}"));
+  }
+  Ok(Validator::new(&version))
 }
 
 #[get("/models/{name}")]
@@ -14,8 +261,56 @@ pub async fn get_model(
   ctx: web::Data<Context>,
   name: web::Path<String>,
 ) -> Result<impl Responder> {
-  log::info!("name {:?}", name);
-  Ok(HttpResponse::Ok().finish())
+  let info = ctx
+    .get_model_info(&name)
+    .await
+    .internal()?
+    .with((StatusCode::NOT_FOUND, "Model not found"))?;
+  Ok(web::Json(info))
+}
+
+/// Moves a model to the trash, from which it can be restored until it's purged. Admin only.
+#[delete("/models/{name}")]
+pub async fn delete_model(
+  admin: auth::AdminToken,
+  ctx: web::Data<Context>,
+  cache: web::Data<sample_cache::SampleCache>,
+  name: web::Path<String>,
+) -> Result<impl Responder> {
+  let tombstone = ctx
+    .write()
+    .await
+    .trash_model(&name, admin.0.user_id())
+    .await
+    .internal()?
+    .with((StatusCode::NOT_FOUND, "Model not found"))?;
+  cache.evict_model(&name);
+  log::info!("[models] user {} moved {} to the trash", admin.0.user_id(), name);
+  Ok(web::Json(tombstone))
+}
+
+/// Moves a model back from the trash. Admin only.
+#[post("/models/{name}/restore")]
+pub async fn restore_model(
+  admin: auth::AdminToken,
+  ctx: web::Data<Context>,
+  cache: web::Data<sample_cache::SampleCache>,
+  name: web::Path<String>,
+) -> Result<impl Responder> {
+  match ctx.write().await.restore_model(&name).await.internal()? {
+    RestoreOutcome::Restored(tombstone) => {
+      // A generation which was running when the model was trashed may have cached its text after it was evicted
+      cache.evict_model(&name);
+      log::info!("[models] user {} restored {} from the trash", admin.0.user_id(), name);
+      Ok(web::Json(tombstone))
+    }
+    RestoreOutcome::NotTrashed => {
+      Err(crate::error::Error::from((StatusCode::NOT_FOUND, "Model is not in the trash")).into())
+    }
+    RestoreOutcome::Exists => {
+      Err(crate::error::Error::from((StatusCode::CONFLICT, "A model with the same name exists")).into())
+    }
+  }
 }
 
 #[get("/models/{name}/{token}")]
@@ -29,20 +324,371 @@ pub async fn get_model_edges(
   Ok(HttpResponse::Ok().finish())
 }
 
+#[derive(Debug, Deserialize)]
+pub struct ScoreTextRequest {
+  pub text: String,
+  /// Score the text as a message of this channel, for models trained with channel tags
+  pub channel: Option<String>,
+}
+
+/// Scores how likely the model is to generate a message, e.g. to tell whether a screenshot of the bot is genuine
+#[post("/models/{name}/score")]
+pub async fn score_text(
+  _: auth::AccessToken,
+  ctx: web::Data<Context>,
+  name: web::Path<String>,
+  body: web::Json<ScoreTextRequest>,
+) -> Result<impl Responder> {
+  if body.text.chars().count() > chain::limits::TWITCH_MAX_CHARS {
+    return Err(
+      crate::error::Error::from(format!(
+        "text must be at most {} characters",
+        chain::limits::TWITCH_MAX_CHARS
+      ))
+      .into(),
+    );
+  }
+  let model = ctx
+    .write()
+    .await
+    .get_model(&name)
+    .instrument(tracing::info_span!("load_model", model = %name))
+    .await
+    .internal()?
+    .with((StatusCode::NOT_FOUND, "Model not found"))?;
+
+  let text = match &body.channel {
+    Some(channel) => format!("{} {}", chain::channel_tag(channel), body.text),
+    None => body.text.clone(),
+  };
+  Ok(web::Json(schema::TextScore {
+    score: model.score_text(&text),
+  }))
+}
+
 #[derive(Debug, Deserialize)]
 pub struct ModelGenerateTextQuery {
-  pub query: String,
-  pub page: usize,
+  /// Treat `token` as the beginning of a message, and complete it
+  #[serde(default)]
+  pub continuation: bool,
+  /// Generate text in the style of this channel, for models trained with channel tags
+  pub channel: Option<String>,
+  /// Seeds the random number generator of the `sample` strategy, so that identical requests generate the same text.
+  /// They may also return recently generated text instead of generating it again.
+  pub seed: Option<u64>,
+  /// Overrides the model's default `max_samples`
+  pub max_samples: Option<usize>,
+  /// Overrides the model's default `max_length`
+  pub max_length: Option<usize>,
+  #[serde(default)]
+  pub strategy: Strategy,
+  /// Number of completions kept at each step of the `beam` strategy
+  pub beam_width: Option<usize>,
 }
 
 #[get("/models/{name}/{token}/generate")]
 pub async fn get_model_generated_text(
-  _: auth::AccessToken,
+  user: auth::AccessToken,
   ctx: web::Data<Context>,
+  db: web::Data<db::Database>,
+  cache: web::Data<sample_cache::SampleCache>,
+  moderator: Option<web::Data<Moderator>>,
   path: web::Path<(String, String)>,
   query: web::Query<ModelGenerateTextQuery>,
 ) -> Result<impl Responder> {
   let (name, token) = path.into_inner();
-  log::info!("name {:?}, token {:?}", name, token);
-  Ok(HttpResponse::Ok().finish())
+  let text = generate_cached(&ctx, &cache, &name, &token, &query).await?;
+  let safety = moderate(moderator.as_deref(), &text).await?;
+
+  let generation = db::generations::create(db.get_ref(), user.user_id(), &name, &token, &text)
+    .await
+    .internal()?;
+  Ok(web::Json(schema::GeneratedText {
+    text,
+    generation_id: Some(generation.id()),
+    safety,
+  }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CompareModelsRequest {
+  /// Names of the models to generate text with, in the order of the results
+  pub models: Vec<String>,
+  /// The word(s) to seed every model with
+  pub token: String,
+  #[serde(flatten)]
+  pub options: ModelGenerateTextQuery,
+}
+
+/// Generates text with several models from the same seed, e.g. to pick which checkpoint to promote
+#[post("/models/compare")]
+pub async fn compare_models(
+  user: auth::AccessToken,
+  ctx: web::Data<Context>,
+  db: web::Data<db::Database>,
+  cache: web::Data<sample_cache::SampleCache>,
+  moderator: Option<web::Data<Moderator>>,
+  body: web::Json<CompareModelsRequest>,
+) -> Result<impl Responder> {
+  if body.models.is_empty() || body.models.len() > MAX_COMPARED_MODELS {
+    return Err(
+      crate::error::Error::from(format!("models must contain between 1 and {MAX_COMPARED_MODELS} names")).into(),
+    );
+  }
+
+  // The stats are read from the file headers, without loading the models
+  let files = ctx.read().await.get_models().await.internal()?;
+  let mut files = body
+    .models
+    .iter()
+    .map(|name| {
+      files
+        .iter()
+        .find(|file| &file.name == name)
+        .cloned()
+        .with((StatusCode::NOT_FOUND, format!("Model not found: {name}")))
+    })
+    .collect::<Result<Vec<_>, _>>()?;
+  let metas = {
+    let refs = files.iter().collect::<Vec<_>>();
+    ctx.get_models_meta(&refs).await.internal()?
+  };
+
+  let mut results = Vec::with_capacity(files.len());
+  for file in files.drain(..) {
+    let text = generate_cached(&ctx, &cache, &file.name, &body.token, &body.options).await?;
+    // A refused text is left out of the results rather than failing the request, as the other models' text is stored
+    let review = match moderator.as_deref() {
+      Some(moderator) => moderator
+        .review(&text)
+        .instrument(tracing::info_span!("moderate"))
+        .await
+        .map(Some),
+      None => Ok(None),
+    };
+    let (text, generation_id, safety, refused) = match review {
+      Ok(safety) => {
+        let generation = db::generations::create(db.get_ref(), user.user_id(), &file.name, &body.token, &text)
+          .await
+          .internal()?;
+        (text, Some(generation.id()), safety, false)
+      }
+      Err(verdict) => (String::new(), None, Some(verdict), true),
+    };
+    let meta = metas.get(&file.name).cloned().internal()?;
+    results.push(schema::ModelComparison {
+      model: file.name,
+      text,
+      generation_id,
+      refused,
+      order: meta.order,
+      size: file.size,
+      channels: meta.channels,
+      safety,
+    });
+  }
+  Ok(web::Json(results))
+}
+
+/// Checks generated text with `moderator`, if moderation is configured.
+/// Unsafe text is refused with a 422 if the moderator is configured to, or if it contains a blocklisted phrase, so that
+/// it's neither returned nor stored.
+pub async fn moderate(moderator: Option<&Moderator>, text: &str) -> Result<Option<Verdict>> {
+  let Some(moderator) = moderator else {
+    return Ok(None);
+  };
+  match moderator.review(text).instrument(tracing::info_span!("moderate")).await {
+    Ok(verdict) => Ok(Some(verdict)),
+    Err(_) => Err(
+      crate::error::Error::from((
+        StatusCode::UNPROCESSABLE_ENTITY,
+        "The generated text was refused by moderation, try again or use another seed",
+      ))
+      .into(),
+    ),
+  }
+}
+
+/// Same as [`generate`], but requests with a seed may return text recently generated for an identical request.
+/// Requests without one are always generated again, as they're expected to return different text every time.
+async fn generate_cached(
+  ctx: &Context,
+  cache: &sample_cache::SampleCache,
+  name: &str,
+  token: &str,
+  query: &ModelGenerateTextQuery,
+) -> Result<String> {
+  let options = model_options(ctx, name, query).await?;
+  let Some(seed) = query.seed else {
+    return generate(ctx, name, token, query, options).await;
+  };
+  let key = sample_cache::Key {
+    model: name.into(),
+    token: token.into(),
+    continuation: query.continuation,
+    channel: query.channel.clone(),
+    seed,
+    options: options.clone(),
+    strategy: query.strategy,
+    beam_width: query.beam_width,
+  };
+  if let Some(text) = cache.get(&key) {
+    return Ok(text);
+  }
+  let text = generate(ctx, name, token, query, options).await?;
+  cache.insert(key, text.clone());
+  Ok(text)
+}
+
+/// Returns the options of the model called `name`, with the overrides of `query`
+async fn model_options(ctx: &Context, name: &str, query: &ModelGenerateTextQuery) -> Result<ModelOptions> {
+  let options = ctx
+    .read()
+    .await
+    .get_model_options(name)
+    .await
+    .internal()?
+    .with((StatusCode::NOT_FOUND, "Model not found"))?
+    .with_overrides(query.max_samples, query.max_length)
+    .map_err(crate::error::Error::from)?;
+  Ok(options)
+}
+
+async fn generate(
+  ctx: &Context,
+  name: &str,
+  token: &str,
+  query: &ModelGenerateTextQuery,
+  options: ModelOptions,
+) -> Result<String> {
+  let model = ctx
+    .write()
+    .await
+    .get_model(name)
+    .instrument(tracing::info_span!("load_model", model = %name))
+    .await
+    .internal()?
+    .with((StatusCode::NOT_FOUND, "Model not found"))?;
+
+  match query.strategy {
+    Strategy::Sample => {
+      sample(
+        model,
+        name,
+        token.to_owned(),
+        query.continuation,
+        query.channel.clone(),
+        query.seed,
+        options,
+      )
+      .await
+    }
+    strategy => {
+      let width = query.beam_width.unwrap_or(DEFAULT_BEAM_WIDTH);
+      if strategy == Strategy::Beam && !(1..=MAX_BEAM_WIDTH).contains(&width) {
+        return Err(crate::error::Error::from(format!("beam_width must be between 1 and {MAX_BEAM_WIDTH}")).into());
+      }
+      complete(
+        model,
+        name,
+        token.to_owned(),
+        query.channel.clone(),
+        strategy,
+        width,
+        options,
+      )
+      .await
+    }
+  }
+}
+
+/// Completes `token` with the `greedy` or `beam` strategy, in the style of `channel` if it's set and the model knows
+/// how the channel's messages continue `token`.
+///
+/// The completion is deterministic, so banned tokens are removed instead of generating it again. The text always
+/// starts with `token`, and is cut to `options.max_length`.
+async fn complete(
+  model: std::sync::Arc<dyn chain::TextGenerator>,
+  name: &str,
+  token: String,
+  channel: Option<String>,
+  strategy: Strategy,
+  width: usize,
+  options: ModelOptions,
+) -> Result<String> {
+  let span = tracing::info_span!("complete", model = %name, ?strategy);
+  let text = web::block(move || {
+    let _span = span.entered();
+    let complete = |seed: &str| match strategy {
+      Strategy::Beam => model.generate_text_beam(seed, MAX_COMPLETION_TOKENS, width),
+      _ => model.generate_text_greedy(seed, MAX_COMPLETION_TOKENS),
+    };
+    let tagged = channel
+      .map(|channel| complete(&format!("{} {token}", chain::channel_tag(&channel))))
+      .filter(|text| !text.is_empty());
+    let text = match tagged {
+      Some(text) => chain::strip_channel_tag(&text),
+      None => complete(&token),
+    };
+    let text = options.remove_banned_tokens(&text);
+    options.generation_options().apply(&text).to_owned()
+  })
+  .await
+  .internal()?;
+  Ok(text)
+}
+
+/// Generates text from `model`, seeded with `token`, and conditioned on `channel` if it's set. With `rng_seed`, the same
+/// request generates the same text, as long as the model isn't reloaded.
+///
+/// Output which contains a banned token is generated again, up to [`ModelOptions::attempts`] times in total, after
+/// which the tokens are removed. The text is then cut to `options.max_length`.
+pub async fn sample(
+  model: std::sync::Arc<dyn chain::TextGenerator>,
+  name: &str,
+  token: String,
+  continuation: bool,
+  channel: Option<String>,
+  rng_seed: Option<u64>,
+  options: ModelOptions,
+) -> Result<String> {
+  // `web::block` runs on another thread, so the span has to be passed explicitly
+  let span = tracing::info_span!("sample", model = %name, continuation);
+  let text = web::block(move || {
+    let _span = span.entered();
+    let max_samples = options.max_samples;
+    let mut rng = rng_seed.map_or_else(StdRng::from_entropy, StdRng::seed_from_u64);
+    // `None` if the model doesn't know the channel
+    let mut generate = || {
+      let rng = &mut rng;
+      if continuation {
+        return Some(chain::sample_continuation_with_rng(&*model, rng, &token, max_samples));
+      }
+      let words = token.split_whitespace().collect::<Vec<_>>();
+      if let Some(channel) = &channel {
+        return chain::sample_tagged_with_rng(&*model, rng, channel, &words, max_samples);
+      }
+      Some(match words.len() {
+        0 => chain::sample_with_rng(&*model, rng, "", max_samples),
+        1 => chain::sample_with_rng(&*model, rng, words[0], max_samples),
+        _ => chain::sample_seq_with_rng(&*model, rng, &words, max_samples),
+      })
+    };
+
+    let mut text = generate()?;
+    for _ in 1..options.attempts() {
+      if !options.contains_banned_token(&text) {
+        break;
+      }
+      text = generate()?;
+    }
+    if options.contains_banned_token(&text) {
+      text = options.remove_banned_tokens(&text);
+    }
+    Some(options.generation_options().apply(&text).to_owned())
+  })
+  .await
+  .internal()?
+  .with((StatusCode::NOT_FOUND, "The model doesn't know the channel"))?;
+  Ok(text)
 }
@@ -0,0 +1,136 @@
+use crate::{auth, ctx::Context, error::FailWith, moderation::Moderator, schema};
+use actix_http::StatusCode;
+use actix_web::{delete, get, post, web, Responder, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{
+  sync::Arc,
+  time::{Duration, Instant},
+};
+
+/// Training is cut short after this long, the chain is kept as is
+const MAX_TRAINING_TIME: Duration = Duration::from_secs(5);
+/// Limit on the number of distinct words in a session model
+const MAX_WORDS: usize = 50_000;
+
+#[derive(Debug, Serialize)]
+pub struct SessionModelResponse {
+  pub session_model_id: String,
+  pub expires_at: DateTime<Utc>,
+  /// Number of lines the model was trained on
+  pub lines: usize,
+  /// Whether training was stopped early because of the time or size limits
+  pub truncated: bool,
+}
+
+struct Trained {
+  chain: chain::Chain<2>,
+  lines: usize,
+  truncated: bool,
+}
+
+fn train(corpus: &str) -> Trained {
+  let started = Instant::now();
+  let mut chain = chain::Chain::<2>::new();
+  let mut lines = 0;
+  for line in corpus.lines().map(str::trim).filter(|line| !line.is_empty()) {
+    if started.elapsed() > MAX_TRAINING_TIME || chain.word_count() > MAX_WORDS {
+      return Trained {
+        chain,
+        lines,
+        truncated: true,
+      };
+    }
+    chain.feed_str(line);
+    lines += 1;
+  }
+  Trained {
+    chain,
+    lines,
+    truncated: false,
+  }
+}
+
+/// Trains a temporary model on the text in the body, one message per line.
+///
+/// The body is limited to 256 KiB by the default payload config.
+#[post("/session-models")]
+pub async fn create_session_model(
+  token: auth::AccessToken,
+  ctx: web::Data<Context>,
+  corpus: String,
+) -> Result<impl Responder> {
+  if corpus.trim().is_empty() {
+    return Err(crate::error::Error::from("The corpus must not be empty").into());
+  }
+
+  let span = tracing::info_span!("train_session_model", bytes = corpus.len());
+  let trained = web::block(move || {
+    let _span = span.entered();
+    train(&corpus)
+  })
+  .await
+  .internal()?;
+  if trained.lines == 0 {
+    return Err(crate::error::Error::from("The corpus must not be empty").into());
+  }
+
+  let (session_model_id, expires_at) = ctx
+    .write()
+    .await
+    .add_session_model(token.user_id(), Arc::new(trained.chain));
+  log::info!(
+    "[session-models] user {} trained a model on {} line(s)",
+    token.user_id(),
+    trained.lines
+  );
+  Ok(web::Json(SessionModelResponse {
+    session_model_id,
+    expires_at,
+    lines: trained.lines,
+    truncated: trained.truncated,
+  }))
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct SessionModelGenerateTextQuery {
+  /// Treat `token` as the beginning of a message, and complete it
+  #[serde(default)]
+  pub continuation: bool,
+}
+
+#[get("/session-models/{session_model_id}/{token}/generate")]
+pub async fn get_session_model_generated_text(
+  token: auth::AccessToken,
+  ctx: web::Data<Context>,
+  path: web::Path<(String, String)>,
+  query: web::Query<SessionModelGenerateTextQuery>,
+  moderator: Option<web::Data<Moderator>>,
+) -> Result<impl Responder> {
+  let (id, seed) = path.into_inner();
+  let model = ctx
+    .read()
+    .await
+    .get_session_model(token.user_id(), &id)
+    .with((StatusCode::NOT_FOUND, "Session model not found"))?;
+
+  let text = super::models::sample(model, &id, seed, query.continuation, None, None, Default::default()).await?;
+  let safety = super::models::moderate(moderator.as_deref(), &text).await?;
+  Ok(web::Json(schema::GeneratedText {
+    text,
+    generation_id: None,
+    safety,
+  }))
+}
+
+#[delete("/session-models/{session_model_id}")]
+pub async fn delete_session_model(
+  token: auth::AccessToken,
+  ctx: web::Data<Context>,
+  id: web::Path<String>,
+) -> Result<impl Responder> {
+  if !ctx.write().await.remove_session_model(token.user_id(), &id) {
+    return Err(crate::error::Error::from((StatusCode::NOT_FOUND, "Session model not found")).into());
+  }
+  Ok(web::Json(()))
+}
@@ -0,0 +1,95 @@
+use crate::{
+  auth,
+  error::FailWith,
+  rate_limit::{RateLimiter, TrustedProxies},
+};
+use actix_http::StatusCode;
+use actix_web::{get, post, web, HttpRequest, Responder, Result};
+use chrono::{DateTime, Utc};
+use db::Database;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+
+/// Default lifetime of a share link, in seconds
+const DEFAULT_SHARE_TTL: i64 = 24 * 60 * 60;
+/// Bounds of the lifetime of a share link, in seconds
+const MIN_SHARE_TTL: i64 = 60;
+const MAX_SHARE_TTL: i64 = 7 * 24 * 60 * 60;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateShareQuery {
+  /// Lifetime of the link, in seconds
+  pub ttl: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreateShareResponse {
+  pub share_token: String,
+  pub expires_at: DateTime<Utc>,
+}
+
+/// Creates a public link to a generation of the caller
+#[post("/generations/{id}/share")]
+pub async fn create_share(
+  token: auth::AccessToken,
+  db: web::Data<Database>,
+  id: web::Path<i64>,
+  query: web::Query<CreateShareQuery>,
+) -> Result<impl Responder> {
+  let ttl = query.ttl.unwrap_or(DEFAULT_SHARE_TTL);
+  if !(MIN_SHARE_TTL..=MAX_SHARE_TTL).contains(&ttl) {
+    return Err(crate::error::Error::from(format!("ttl must be between {MIN_SHARE_TTL} and {MAX_SHARE_TTL}")).into());
+  }
+
+  // Generations of other users are reported as missing, so that their IDs can't be probed
+  let generation = db::generations::get(db.get_ref(), *id)
+    .await
+    .internal()?
+    .filter(|generation| generation.user_id() == token.user_id())
+    .with((StatusCode::NOT_FOUND, "Generation not found"))?;
+
+  let share_token: String = thread_rng()
+    .sample_iter(&Alphanumeric)
+    .take(24)
+    .map(char::from)
+    .collect();
+  let share = db::generations::create_share(
+    db.get_ref(),
+    generation.id(),
+    &share_token,
+    Utc::now() + chrono::Duration::seconds(ttl),
+  )
+  .await
+  .internal()?;
+  log::info!(
+    "[shares] user {} shared generation {} until {}",
+    token.user_id(),
+    generation.id(),
+    share.expires_at()
+  );
+
+  Ok(web::Json(CreateShareResponse {
+    share_token,
+    expires_at: share.expires_at(),
+  }))
+}
+
+/// Returns a shared generation. This endpoint doesn't require authentication, so it's rate limited by client address.
+#[get("/shared/{share_token}")]
+pub async fn get_shared_generation(
+  req: HttpRequest,
+  db: web::Data<Database>,
+  limiter: web::Data<RateLimiter>,
+  proxies: web::Data<TrustedProxies>,
+  share_token: web::Path<String>,
+) -> Result<impl Responder> {
+  if !limiter.check(&proxies.client(&req)) {
+    return Err(crate::error::Error::from(StatusCode::TOO_MANY_REQUESTS).into());
+  }
+
+  let generation = db::generations::get_shared(db.get_ref(), &share_token)
+    .await
+    .internal()?
+    .with((StatusCode::NOT_FOUND, "Share link not found or expired"))?;
+  Ok(web::Json(generation))
+}
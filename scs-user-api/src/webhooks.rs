@@ -0,0 +1,166 @@
+//! Notifies registered webhooks when models are published or updated.
+//!
+//! Payloads are signed with the webhook's secret: the `X-SCS-Signature` header contains
+//! `sha256=<hex encoded HMAC-SHA256 of the body>`.
+
+use crate::{ctx, schema::SimpleModelInfo};
+use db::Database;
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::time::Duration;
+
+pub const SIGNATURE_HEADER: &str = "x-scs-signature";
+
+/// Number of attempts made to deliver each event
+const MAX_ATTEMPTS: i32 = 3;
+/// Delay before the first retry, doubled after each attempt
+const INITIAL_BACKOFF: Duration = Duration::from_secs(2);
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Event {
+  #[serde(rename = "model.created")]
+  Created,
+  #[serde(rename = "model.updated")]
+  Updated,
+}
+
+impl Event {
+  pub fn as_str(self) -> &'static str {
+    match self {
+      Event::Created => "model.created",
+      Event::Updated => "model.updated",
+    }
+  }
+}
+
+#[derive(Serialize)]
+struct Payload<'a> {
+  event: Event,
+  model: &'a SimpleModelInfo,
+}
+
+/// Generates a secret for a new webhook
+pub fn generate_secret() -> String {
+  use rand::{distributions::Alphanumeric, thread_rng, Rng};
+  thread_rng()
+    .sample_iter(&Alphanumeric)
+    .take(40)
+    .map(char::from)
+    .collect()
+}
+
+fn sign(secret: &str, body: &[u8]) -> String {
+  let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+  mac.update(body);
+  format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Periodically checks the model directory for changes, and notifies every webhook of each one.
+pub async fn watch(ctx: ctx::Context, db: Database, client: reqwest::Client, interval: Duration) {
+  let mut interval = tokio::time::interval(interval);
+  interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+  loop {
+    interval.tick().await;
+
+    let changes = match ctx.poll_model_changes().await {
+      Ok(changes) => changes,
+      Err(e) => {
+        log::error!("[webhooks] Failed to poll models: {:?}", e);
+        continue;
+      }
+    };
+    if changes.is_empty() {
+      continue;
+    }
+
+    let webhooks = match db::webhooks::get_all(&db).await {
+      Ok(webhooks) => webhooks,
+      Err(e) => {
+        log::error!("[webhooks] Failed to fetch webhooks: {:?}", e);
+        continue;
+      }
+    };
+    for (event, model) in changes {
+      log::info!("[webhooks] {} {}", event.as_str(), model.name);
+      let body = match serde_json::to_vec(&Payload { event, model: &model }) {
+        Ok(body) => body,
+        Err(e) => {
+          log::error!("[webhooks] Failed to serialize payload: {:?}", e);
+          continue;
+        }
+      };
+      for webhook in &webhooks {
+        tokio::spawn(deliver(
+          db.clone(),
+          client.clone(),
+          webhook.clone(),
+          event,
+          model.name.clone(),
+          body.clone(),
+        ));
+      }
+    }
+  }
+}
+
+/// Sends `body` to `webhook`, retrying with exponential backoff, and logs the outcome.
+async fn deliver(
+  db: Database,
+  client: reqwest::Client,
+  webhook: db::webhooks::Webhook,
+  event: Event,
+  model: String,
+  body: Vec<u8>,
+) {
+  let signature = sign(webhook.secret(), &body);
+  let mut backoff = INITIAL_BACKOFF;
+  let mut attempts = 0;
+  let (status, error) = loop {
+    attempts += 1;
+    let (status, error) = match client
+      .post(webhook.url())
+      .timeout(TIMEOUT)
+      .header(reqwest::header::CONTENT_TYPE, "application/json")
+      .header(SIGNATURE_HEADER, &signature)
+      .body(body.clone())
+      .send()
+      .await
+    {
+      Ok(res) if res.status().is_success() => break (Some(res.status().as_u16() as i32), None),
+      Ok(res) => (Some(res.status().as_u16() as i32), None),
+      Err(e) => (e.status().map(|s| s.as_u16() as i32), Some(e.to_string())),
+    };
+    if attempts >= MAX_ATTEMPTS {
+      break (status, error.or_else(|| Some("Unsuccessful status code".into())));
+    }
+    tokio::time::sleep(backoff).await;
+    backoff *= 2;
+  };
+
+  let delivered = error.is_none();
+  if !delivered {
+    log::warn!(
+      "[webhooks] Failed to deliver {} to webhook {} after {} attempt(s): {:?}",
+      event.as_str(),
+      webhook.id(),
+      attempts,
+      error
+    );
+  }
+  if let Err(e) = db::webhooks::log_delivery(
+    &db,
+    webhook.id(),
+    event.as_str(),
+    &model,
+    attempts,
+    status,
+    error.as_deref(),
+    delivered,
+  )
+  .await
+  {
+    log::error!("[webhooks] Failed to log delivery: {:?}", e);
+  }
+}
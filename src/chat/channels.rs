@@ -0,0 +1,37 @@
+//! The list of channels saved to `channels_path` when the owner joins or parts a channel, which replaces the
+//! configured `channels` on startup.
+
+use anyhow::Result;
+use std::path::Path;
+
+/// Longest Twitch login
+const MAX_NAME_LENGTH: usize = 25;
+
+/// Returns the saved channels, or `None` if they were never saved
+pub fn load(path: &Path) -> Result<Option<Vec<String>>> {
+  match std::fs::read_to_string(path) {
+    Ok(contents) => Ok(Some(serde_json::from_str(&contents).map_err(|e| {
+      anyhow::anyhow!("Could not parse the channel list {}: {}", path.display(), e)
+    })?)),
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+    Err(e) => Err(anyhow::anyhow!(
+      "Could not read the channel list {}: {}",
+      path.display(),
+      e
+    )),
+  }
+}
+
+/// Replaces the saved channels, so that a crash while saving leaves the previous list intact
+pub fn save(path: &Path, channels: &[String]) -> Result<()> {
+  let mut temp = path.as_os_str().to_owned();
+  temp.push(".tmp");
+  std::fs::write(&temp, serde_json::to_vec_pretty(channels)?)?;
+  std::fs::rename(&temp, path)?;
+  Ok(())
+}
+
+/// Whether `name` may be the login of a Twitch channel
+pub fn is_valid_name(name: &str) -> bool {
+  (1..=MAX_NAME_LENGTH).contains(&name.len()) && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_')
+}
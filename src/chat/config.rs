@@ -1,14 +1,27 @@
+use crate::{templates::Templates, triggers::TriggersConfig};
 use anyhow::Result;
 use serde::Deserialize;
-use std::{fs, time::Duration};
+use std::time::Duration;
 
 #[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
   pub login: String,
   pub token: String,
+  #[serde(default)]
+  pub refresh: Option<RefreshConfig>,
   #[serde(default = "std::path::PathBuf::new")]
   pub model_path: std::path::PathBuf,
+  /// A model which generates responses to the same messages as the live model, but whose responses are only logged
+  #[serde(default)]
+  pub shadow_model_path: Option<std::path::PathBuf>,
   pub channels: Vec<String>,
+  /// Login of the user who may make the bot join and part channels
+  #[serde(default)]
+  pub owner: Option<String>,
+  /// File the channel list is saved to when the owner joins or parts a channel. Once saved, it replaces `channels`.
+  #[serde(default)]
+  pub channels_path: Option<std::path::PathBuf>,
   #[serde(default = "default_reply_probability")]
   pub reply_probability: f64,
   #[serde(with = "humantime_serde")]
@@ -18,9 +31,244 @@ pub struct Config {
   pub reply_after_messages: usize,
   #[serde(default = "std::collections::HashSet::new")]
   pub reply_blocklist: std::collections::HashSet<String>,
+  /// The trainer's `phrase_blocklist`. Responses which contain any of its phrases are dropped.
+  #[serde(default)]
+  pub phrase_blocklist: Option<std::path::PathBuf>,
+  /// Internal parsed version of `phrase_blocklist`
+  #[serde(skip)]
+  pub phrase_blocklist_entries: Option<scs_config::PhraseBlocklist>,
+  /// Condition responses on the channel they're sent in, for models trained with `channel_tags`
+  #[serde(default)]
+  pub channel_tags: bool,
   #[serde(with = "humantime_serde")]
   #[serde(default = "default_user_cooldown")]
   pub user_cooldown: Duration,
+  /// Tell users who mention the bot during their cooldown how long they have to wait, once per cooldown
+  #[serde(default)]
+  pub cooldown_feedback: bool,
+  #[serde(with = "humantime_serde")]
+  #[serde(default = "default_slow_generation_threshold")]
+  pub slow_generation_threshold: Duration,
+  /// Longest time a response may take to generate before `timeout_fallback` is used instead
+  #[serde(with = "humantime_serde")]
+  #[serde(default = "default_generation_timeout")]
+  pub generation_timeout: Duration,
+  #[serde(default)]
+  pub timeout_fallback: TimeoutFallback,
+  #[serde(default)]
+  pub reply_queue: ReplyQueueConfig,
+  /// Longest time a response may be delayed to respect slow mode before it's dropped
+  #[serde(with = "humantime_serde")]
+  #[serde(default = "default_max_slow_mode_delay")]
+  pub max_slow_mode_delay: Duration,
+  /// How long the bot stays silent after Twitch drops one of its messages for exceeding the rate limit
+  #[serde(with = "humantime_serde")]
+  #[serde(default = "default_rate_limit_backoff")]
+  pub rate_limit_backoff: Duration,
+  #[serde(with = "humantime_serde")]
+  #[serde(default = "default_metrics_log_interval")]
+  pub metrics_log_interval: Duration,
+  #[serde(default)]
+  pub metrics_address: Option<std::net::SocketAddr>,
+  #[serde(default)]
+  pub templates: Templates,
+  /// Websocket URI of the IRC server
+  #[serde(default = "default_server")]
+  pub server: String,
+  /// Proxy and CA certificates used to connect to `server`
+  #[serde(default)]
+  pub connection: twitch_api::ConnectOptions,
+  /// Postgres connection string of the logs database, which enables the `quote` command
+  #[serde(default)]
+  pub database_url: Option<String>,
+  #[serde(default)]
+  pub quotes: QuoteConfig,
+  #[serde(default)]
+  pub regenerate: RegenerateConfig,
+  #[serde(default)]
+  pub events: EventsConfig,
+  #[serde(default)]
+  pub repetition: RepetitionConfig,
+  /// What makes the bot reply to a message, defaults to its `@mention`
+  #[serde(default)]
+  pub triggers: TriggersConfig,
+  /// Where the responses to commands and notices are sent
+  #[serde(default)]
+  pub reply_targets: ReplyTargets,
+}
+
+/// What to respond with when generating a response times out
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum TimeoutFallback {
+  /// Generate an unseeded response, which is also subject to the timeout
+  #[default]
+  Generate,
+  /// Respond with a fixed text
+  Canned(String),
+  /// Don't respond
+  Silent,
+}
+
+/// Where a message is sent
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplyTarget {
+  /// The channel the user wrote in
+  #[default]
+  Channel,
+  /// A whisper to the user, which the other chatters don't see
+  Whisper,
+}
+
+/// Where each type of response is sent
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReplyTargets {
+  /// Telling a user how long their cooldown lasts, see `cooldown_feedback`
+  pub cooldown: ReplyTarget,
+  /// Confirming `quote-optout` and `quote-optin`
+  pub quote_opt_out: ReplyTarget,
+  /// The `version` command
+  pub version: ReplyTarget,
+  /// The `model` command
+  pub model_info: ReplyTarget,
+  /// The `?` command
+  pub phrase_info: ReplyTarget,
+  /// The `status` command
+  pub status: ReplyTarget,
+}
+
+/// Settings of the `quote` command
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuoteConfig {
+  /// How long a user has to wait between quotes, moderators and the streamer excepted
+  #[serde(with = "humantime_serde")]
+  pub user_cooldown: Duration,
+  /// How long a channel has to wait between quotes
+  #[serde(with = "humantime_serde")]
+  pub channel_cooldown: Duration,
+}
+
+impl Default for QuoteConfig {
+  fn default() -> Self {
+    Self {
+      user_cooldown: Duration::from_secs(60),
+      channel_cooldown: Duration::from_secs(10),
+    }
+  }
+}
+
+/// Settings of regenerating the bot's last response, by replying to it with one of `keywords`
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RegenerateConfig {
+  /// Replies which ask for a new response, ignoring case. Empty disables regenerating.
+  pub keywords: Vec<String>,
+  /// Most times the same seed may be regenerated
+  pub max_retries: u32,
+}
+
+impl Default for RegenerateConfig {
+  fn default() -> Self {
+    Self {
+      keywords: vec![String::from("again")],
+      max_retries: 3,
+    }
+  }
+}
+
+/// Avoiding responses which were recently sent to the same channel
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RepetitionConfig {
+  /// Number of recent responses remembered per channel. Zero disables it.
+  pub window: usize,
+  /// Most times a response is sampled, after which a repeated one is sent anyway
+  pub max_attempts: u32,
+}
+
+impl Default for RepetitionConfig {
+  fn default() -> Self {
+    Self {
+      window: 20,
+      max_attempts: 3,
+    }
+  }
+}
+
+/// Reactions to stream events, which are off unless `enabled`
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EventsConfig {
+  /// `{user}` is the raiding channel, and `{response}` the number of viewers
+  pub raid: EventConfig,
+  /// `{user}` is the subscriber, and `{response}` the number of months they subscribed for
+  pub sub: EventConfig,
+  /// `{user}` is the gifter, and `{response}` the recipient
+  pub gift_sub: EventConfig,
+}
+
+/// Reaction to a type of stream event: the bot sends a continuation of `seed` by the model
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EventConfig {
+  pub enabled: bool,
+  /// Template of the beginning of the message. Defaults to a greeting fitting the event.
+  pub seed: Option<String>,
+  /// Shortest time between two reactions to this type of event in a channel
+  #[serde(with = "humantime_serde")]
+  pub cooldown: Duration,
+}
+
+impl Default for EventConfig {
+  fn default() -> Self {
+    Self {
+      enabled: false,
+      seed: None,
+      cooldown: Duration::from_secs(60),
+    }
+  }
+}
+
+/// Limits on the replies to mentions, which are queued per channel while the bot is busy
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReplyQueueConfig {
+  /// Most messages sent to a channel per `window`, including command responses and random replies
+  pub max_messages: usize,
+  #[serde(with = "humantime_serde")]
+  pub window: Duration,
+  /// Most mentions waiting for a reply per channel. The oldest ones are dropped first.
+  pub max_pending: usize,
+  /// Mentions which waited longer than this for a reply are dropped
+  #[serde(with = "humantime_serde")]
+  pub max_wait: Duration,
+}
+
+impl Default for ReplyQueueConfig {
+  fn default() -> Self {
+    Self {
+      max_messages: 10,
+      window: Duration::from_secs(30),
+      max_pending: 20,
+      max_wait: Duration::from_secs(30),
+    }
+  }
+}
+
+/// OAuth app credentials used to renew `token` once it expires
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RefreshConfig {
+  pub refresh_token: String,
+  pub client_id: String,
+  pub client_secret: String,
+}
+
+fn default_server() -> String {
+  String::from("wss://irc-ws.chat.twitch.tv:443")
 }
 
 const fn default_reply_probability() -> f64 {
@@ -39,28 +287,90 @@ const fn default_user_cooldown() -> Duration {
   Duration::from_secs(60)
 }
 
+const fn default_slow_generation_threshold() -> Duration {
+  Duration::from_millis(250)
+}
+
+const fn default_generation_timeout() -> Duration {
+  Duration::from_secs(2)
+}
+
+const fn default_max_slow_mode_delay() -> Duration {
+  Duration::from_secs(5)
+}
+
+const fn default_rate_limit_backoff() -> Duration {
+  Duration::from_secs(30)
+}
+
+const fn default_metrics_log_interval() -> Duration {
+  Duration::from_secs(300)
+}
+
 impl Config {
   pub fn load<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
-    let mut config = serde_json::from_str::<Config>(
-      &fs::read_to_string(path.as_ref()).map_err(|_| anyhow::anyhow!("Could not read 'chat.json' config file"))?,
-    )?;
+    let mut config = scs_config::read::<Config>(path)?;
+    if let Some(channels_path) = &config.channels_path {
+      if let Some(channels) = crate::channels::load(channels_path)? {
+        log::info!(
+          "Using the channel list saved to {} instead of config.channels",
+          channels_path.display()
+        );
+        config.channels = channels;
+      }
+    }
     if config.channels.is_empty() {
       anyhow::bail!("config.channels is empty, exiting.");
     }
+    config.templates.validate()?;
+    for (name, event) in [
+      ("raid", &config.events.raid),
+      ("sub", &config.events.sub),
+      ("gift_sub", &config.events.gift_sub),
+    ] {
+      if let Some(seed) = &event.seed {
+        crate::templates::validate(&format!("events.{name}.seed"), seed)?;
+      }
+    }
+    if config.reply_queue.max_messages == 0 || config.reply_queue.max_pending == 0 {
+      anyhow::bail!("config.reply_queue.max_messages and config.reply_queue.max_pending must be positive");
+    }
+    config.connection = config.connection.or_env();
+    config.owner = config.owner.map(|owner| owner.to_ascii_lowercase());
+    config.triggers.prepare(&config.login.to_ascii_lowercase())?;
+    for keyword in &mut config.regenerate.keywords {
+      *keyword = keyword.to_ascii_lowercase();
+    }
     config.reply_blocklist = config
       .reply_blocklist
       .into_iter()
       .map(|s| s.to_ascii_lowercase())
       .collect();
+    if let Some(path) = &config.phrase_blocklist {
+      config.phrase_blocklist_entries = Some(scs_config::PhraseBlocklist::load(path)?);
+    }
     Ok(config)
   }
 }
 
 impl<'a> From<&'a Config> for twitch_api::Credentials {
   fn from(config: &'a Config) -> Self {
-    twitch_api::Credentials::Regular(twitch_api::credentials::Regular {
-      login: config.login.clone(),
-      token: config.token.clone(),
-    })
+    match &config.refresh {
+      Some(refresh) => twitch_api::Credentials::Refreshable(twitch_api::credentials::Refreshable {
+        login: config.login.clone(),
+        token: if config.token.starts_with("oauth:") {
+          config.token.clone()
+        } else {
+          format!("oauth:{}", config.token)
+        },
+        refresh_token: refresh.refresh_token.clone(),
+        client_id: refresh.client_id.clone(),
+        client_secret: refresh.client_secret.clone(),
+      }),
+      None => twitch_api::Credentials::Regular(twitch_api::credentials::Regular {
+        login: config.login.clone(),
+        token: config.token.clone(),
+      }),
+    }
   }
 }
@@ -1,28 +1,53 @@
+mod channels;
 mod config;
+mod metrics;
+mod queue;
+mod recent;
+mod room;
+mod templates;
+mod triggers;
 
 use anyhow::Result;
-use config::Config;
+use config::{Config, ReplyTarget, TimeoutFallback};
+use metrics::{Metrics, Stage};
+use queue::{Mention, ReplyQueue};
 use rand::Rng;
+use recent::RecentOutputs;
+use room::RoomState;
 use std::{
-  collections::HashMap,
+  collections::{HashMap, VecDeque},
   env,
   ops::Sub,
   path::PathBuf,
+  sync::Arc,
   time::{Duration, Instant},
 };
+use templates::Vars;
 use tokio_tungstenite::tungstenite::Message;
 use twitch::Command;
-use twitch_api::SuggestedAction;
+use twitch_api::{SendOutcome, SuggestedAction, WhisperOutcome};
 
 // Set to 0 to disable sampling.
 const MAX_SAMPLES: usize = 4;
 const MAX_SAMPLES_FOR_SEQ_INPUT: usize = 16;
+/// How often the reply queue is checked for a mention to reply to
+const QUEUE_INTERVAL: Duration = Duration::from_millis(250);
+/// Number of responses in each channel whose seed is kept, so that replying to one of them regenerates it
+const REGENERABLE_RESPONSES: usize = 10;
 
 struct ChannelReplyTracker {
   reply_timer: std::time::Instant,
   message_count: usize,
 }
 impl ChannelReplyTracker {
+  /// A tracker which allows replying to the next message
+  fn new(config: &Config) -> Self {
+    Self {
+      reply_timer: std::time::Instant::now().sub(config.reply_timeout),
+      message_count: config.reply_after_messages,
+    }
+  }
+
   fn count_message(&mut self) {
     self.message_count += 1;
   }
@@ -54,8 +79,15 @@ async fn stop_signal() -> std::io::Result<()> {
   }
 }
 
+/// Cooldown of a single user
+struct UserCooldown {
+  started: Instant,
+  /// Whether the user was told they're on cooldown since it started
+  notified: bool,
+}
+
 struct Cooldowns {
-  last_sent: HashMap<String, HashMap<String, Instant>>,
+  last_sent: HashMap<String, HashMap<String, UserCooldown>>,
   last_eviction: Instant,
   cd: Duration,
 }
@@ -72,15 +104,23 @@ impl Cooldowns {
     }
   }
 
+  pub fn add_channel(&mut self, channel: &str) {
+    self.last_sent.entry(channel.to_owned()).or_default();
+  }
+
+  pub fn remove_channel(&mut self, channel: &str) {
+    self.last_sent.remove(channel);
+  }
+
   pub fn has_cd(&mut self, channel: &str, user: &str) -> bool {
     // regularly evict users
     if self.last_eviction.elapsed() > self.cd {
       for (_, ch) in self.last_sent.iter_mut() {
         ch.retain(|k, v| {
-          if cfg!(debug_assertions) && v.elapsed() >= self.cd {
+          if cfg!(debug_assertions) && v.started.elapsed() >= self.cd {
             log::info!("{} cooldown expired", k);
           }
-          v.elapsed() < self.cd
+          v.started.elapsed() < self.cd
         });
       }
     }
@@ -90,58 +130,874 @@ impl Cooldowns {
       .last_sent
       .get(channel)
       .and_then(|v| v.get(user))
-      .map(|v| v.elapsed() > self.cd)
+      .map(|v| v.started.elapsed() > self.cd)
       .unwrap_or(true)
   }
 
+  /// Returns how much longer `user` is on cooldown, the first time it's called during a cooldown.
+  /// Returns `None` if the user isn't on cooldown, or was already told about it.
+  pub fn take_feedback(&mut self, channel: &str, user: &str) -> Option<Duration> {
+    let cooldown = self.last_sent.get_mut(channel)?.get_mut(user)?;
+    let remaining = self.cd.checked_sub(cooldown.started.elapsed())?;
+    if cooldown.notified {
+      return None;
+    }
+    cooldown.notified = true;
+    Some(remaining)
+  }
+
   pub fn set_cd(&mut self, channel: &str, user: &str) {
     if cfg!(debug_assertions) {
       log::info!("Replied to {}", user);
     }
     if let Some(ch) = self.last_sent.get_mut(channel) {
-      ch.insert(user.to_string(), Instant::now());
+      ch.insert(
+        user.to_string(),
+        UserCooldown {
+          started: Instant::now(),
+          notified: false,
+        },
+      );
     }
   }
 }
 
+/// Seed of a response, kept so that moderators can ask for a new one
+#[derive(Clone)]
+struct LastSeed {
+  /// User the response was addressed to
+  user: String,
+  words: Vec<String>,
+  /// How many times the seed has been regenerated
+  retries: u32,
+  /// The response as it was sent, which identifies its delivery
+  text: String,
+  /// ID of the response, once Twitch accepted it
+  id: Option<String>,
+}
+
 struct State {
-  model: Box<dyn chain::TextGenerator>,
+  model: Arc<dyn chain::TextGenerator>,
+  shadow_model: Option<Arc<dyn chain::TextGenerator>>,
   credentials: twitch_api::Credentials,
+  /// Sends the responses whose `config.reply_targets` is `whisper`
+  whispers: twitch_api::Whispers,
   cooldowns: Cooldowns,
   reply_times: HashMap<String, ChannelReplyTracker>,
-  prefix: String,
+  rooms: HashMap<String, RoomState>,
+  /// Response waiting for slow mode in each channel, with when it may be sent, see `send_delayed`
+  delayed: HashMap<String, (Instant, String)>,
+  replies: ReplyQueue,
   command_prefix: String,
+  metrics: Metrics,
+  model_name: String,
+  /// Logs database, if `config.database_url` is set
+  db: Option<db::Database>,
+  quote_cooldowns: Cooldowns,
+  /// When a quote was last sent to each channel
+  last_quote: HashMap<String, Instant>,
+  /// Seeds of the latest responses sent to each channel, oldest first, which may be regenerated
+  last_seeds: HashMap<String, VecDeque<LastSeed>>,
+  /// Responses recently sent to each channel, which are re-sampled if generated again
+  recent: RecentOutputs,
+  /// When the bot last reacted to each type of stream event in each channel, see `config.events`
+  last_events: HashMap<(String, &'static str), Instant>,
+  /// Set when Twitch drops a message for exceeding the rate limit, the bot doesn't respond until then
+  rate_limited_until: Option<Instant>,
   config: Config,
 }
 
+fn model_name(path: &std::path::Path) -> String {
+  // Safe to unwrap the filename here since the model has been successfully loaded.
+  path.file_name().unwrap().to_string_lossy().into_owned()
+}
+
+impl State {
+  /// Swaps the live and shadow models, so that the previous live model can be promoted back
+  fn promote_shadow(&mut self) -> bool {
+    match (&mut self.shadow_model, &mut self.config.shadow_model_path) {
+      (Some(shadow_model), Some(shadow_model_path)) => {
+        std::mem::swap(&mut self.model, shadow_model);
+        std::mem::swap(&mut self.config.model_path, shadow_model_path);
+        self.model_name = model_name(&self.config.model_path);
+        log::info!("Promoted the shadow model {}", self.model_name);
+        true
+      }
+      _ => false,
+    }
+  }
+
+  /// Keeps the seed of a response sent to `channel`, forgetting the oldest one beyond `REGENERABLE_RESPONSES`
+  fn record_seed(&mut self, channel: &str, seed: LastSeed) {
+    let seeds = self.last_seeds.entry(channel.to_owned()).or_default();
+    seeds.push_back(seed);
+    if seeds.len() > REGENERABLE_RESPONSES {
+      seeds.pop_front();
+    }
+  }
+
+  fn is_owner(&self, login: &str) -> bool {
+    self.config.owner.as_deref() == Some(&login.to_ascii_lowercase())
+  }
+
+  fn add_channel(&mut self, channel: &str) {
+    self.config.channels.push(channel.to_owned());
+    self.cooldowns.add_channel(channel);
+    self.quote_cooldowns.add_channel(channel);
+    self.replies.add_channel(channel);
+    self
+      .reply_times
+      .insert(channel.to_owned(), ChannelReplyTracker::new(&self.config));
+  }
+
+  fn remove_channel(&mut self, channel: &str) {
+    self.config.channels.retain(|c| c != channel);
+    self.cooldowns.remove_channel(channel);
+    self.quote_cooldowns.remove_channel(channel);
+    self.replies.remove_channel(channel);
+    self.reply_times.remove(channel);
+    self.rooms.remove(channel);
+    self.delayed.remove(channel);
+    self.last_quote.remove(channel);
+    self.last_seeds.remove(channel);
+    self.recent.remove_channel(channel);
+    self.last_events.retain(|(c, _), _| c != channel);
+  }
+
+  /// Saves the channel list to `config.channels_path`, if it's set
+  fn save_channels(&self) {
+    if let Some(path) = &self.config.channels_path {
+      match channels::save(path, &self.config.channels) {
+        Ok(()) => log::info!("Saved the channel list to {}", path.display()),
+        Err(e) => log::error!("Failed to save the channel list to {}: {e}", path.display()),
+      }
+    }
+  }
+
+  fn vars<'a>(&'a self, channel: &'a str, user: &'a str) -> Vars<'a> {
+    Vars {
+      user,
+      channel,
+      model_name: &self.model_name,
+      ..Default::default()
+    }
+  }
+}
+
+/// Samples a response seeded with `words`. If `channel` is set, the model is conditioned on its channel tag, unless the
+/// model doesn't know it.
+fn sample(model: &dyn chain::TextGenerator, channel: Option<&str>, words: &[&str]) -> String {
+  if let Some(channel) = channel {
+    match chain::sample_tagged(model, channel, words, MAX_SAMPLES_FOR_SEQ_INPUT) {
+      Some(response) => return response,
+      None => log::warn!("[{channel}] The model doesn't know the channel's tag, sampling without it"),
+    }
+  }
+  match words.len() {
+    0 => chain::sample(model, "", MAX_SAMPLES),
+    1 => chain::sample(model, words[0], MAX_SAMPLES),
+    _ => chain::sample_seq(model, words, MAX_SAMPLES_FOR_SEQ_INPUT),
+  }
+}
+
+/// Samples a response on a blocking thread, and returns `None` if it takes longer than `timeout`.
+///
+/// Sampling can't be interrupted, so it keeps running in the background after a timeout, and its result is discarded.
+async fn sample_with_timeout(
+  model: &Arc<dyn chain::TextGenerator>,
+  channel: Option<&str>,
+  words: &[&str],
+  timeout: Duration,
+) -> Option<String> {
+  let model = model.clone();
+  let channel = channel.map(String::from);
+  let words = words.iter().map(|w| w.to_string()).collect::<Vec<_>>();
+  let task = tokio::task::spawn_blocking(move || {
+    let words = words.iter().map(String::as_str).collect::<Vec<_>>();
+    sample(&*model, channel.as_deref(), &words)
+  });
+  match tokio::time::timeout(timeout, task).await {
+    Ok(Ok(response)) => Some(response),
+    Ok(Err(e)) => {
+      log::error!("Generation failed: {e}");
+      Some(String::new())
+    }
+    Err(_) => None,
+  }
+}
+
+/// Generates a response with the live model, or falls back to `config.timeout_fallback` if it times out.
+///
+/// A response which is one of the `recent` responses in `channel` is re-sampled, up to `config.repetition.max_attempts`
+/// times in total, after which the last one is used anyway. All the attempts share `config.generation_timeout`, and
+/// the last response is used once it's over. The fallback is never re-sampled. A response which contains a phrase of
+/// `config.phrase_blocklist` is dropped, and an empty one returned instead.
+///
+/// If there's a shadow model, it generates a response to the same input, which is logged along with the live one.
+async fn generate(
+  model: &Arc<dyn chain::TextGenerator>,
+  shadow_model: Option<&Arc<dyn chain::TextGenerator>>,
+  metrics: &Metrics,
+  recent: &RecentOutputs,
+  config: &Config,
+  channel: &str,
+  words: &[&str],
+) -> String {
+  let tag = config.channel_tags.then_some(channel);
+  let start = Instant::now();
+  let deadline = start + config.generation_timeout;
+  let mut fell_back = false;
+  let mut response = match sample_with_timeout(model, tag, words, config.generation_timeout).await {
+    Some(response) => response,
+    None => {
+      metrics.record_timeout(channel);
+      log::warn!(
+        "[{channel}] Generation timed out after {:?} with seed `{}`",
+        config.generation_timeout,
+        words.join(" ")
+      );
+      fell_back = true;
+      match &config.timeout_fallback {
+        TimeoutFallback::Generate => sample_with_timeout(model, None, &[], config.generation_timeout)
+          .await
+          .unwrap_or_default(),
+        TimeoutFallback::Canned(text) => text.clone(),
+        TimeoutFallback::Silent => String::new(),
+      }
+    }
+  };
+  let mut attempts = 1;
+  while !fell_back
+    && !response.is_empty()
+    && attempts < config.repetition.max_attempts
+    && recent.contains(channel, &response)
+  {
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    if remaining.is_zero() {
+      log::info!("[{channel}] Not re-sampling `{response}`, out of time");
+      break;
+    }
+    log::info!("[{channel}] Re-sampling `{response}`, it was sent recently");
+    match sample_with_timeout(model, tag, words, remaining).await {
+      Some(candidate) => response = candidate,
+      None => break,
+    }
+    attempts += 1;
+  }
+  if let Some(blocklist) = &config.phrase_blocklist_entries {
+    if blocklist.is_match(&response) {
+      log::warn!("[{channel}] Dropped `{response}`, it contains a blocklisted phrase");
+      response.clear();
+    }
+  }
+  let elapsed = start.elapsed();
+  metrics.record(Stage::Generation, elapsed);
+  if elapsed > config.slow_generation_threshold {
+    log::warn!(
+      "[{channel}] Slow generation: took {elapsed:?} with seed `{}`",
+      words.join(" ")
+    );
+  }
+
+  if let Some(shadow_model) = shadow_model {
+    let start = Instant::now();
+    let shadow_response = sample_with_timeout(shadow_model, tag, words, config.generation_timeout)
+      .await
+      .unwrap_or_else(|| String::from("<timed out>"));
+    log::info!(
+      "[{channel}] [=SHADOW=] seed `{}`\n  live ({elapsed:?}): {response}\n  shadow ({:?}): {shadow_response}",
+      words.join(" "),
+      start.elapsed()
+    );
+  }
+
+  response
+}
+
+/// Sends `text` to `channel` if the bot is allowed to speak there, or schedules it once slow mode allows it, see
+/// [`send_delayed`]. Messages beyond `config.reply_queue.max_messages` per window are dropped, and text beyond Twitch's
+/// length limit is cut. The text is sanitized like the messages models are trained on, see `chain::text`.
+///
+/// Returns the text which was sent or delayed, or `None` if the message was dropped.
+async fn respond(
+  conn: &mut twitch_api::TwitchStream,
+  state: &mut State,
+  channel: &str,
+  text: &str,
+) -> std::result::Result<Option<String>, twitch_api::WsError> {
+  if let Some(until) = state.rate_limited_until {
+    if until > Instant::now() {
+      log::info!(
+        "[{channel}] Not responding, rate limited for another {:?}",
+        until - Instant::now()
+      );
+      return Ok(None);
+    }
+  }
+  if !state.replies.can_send(channel, Instant::now()) {
+    log::info!(
+      "[{channel}] Not responding, sent {} message(s) in the last {:?}",
+      state.config.reply_queue.max_messages,
+      state.config.reply_queue.window
+    );
+    return Ok(None);
+  }
+  let room = state.rooms.entry(channel.to_string()).or_default();
+  if !room.can_speak() {
+    log::info!("[{channel}] Not responding, chat is restricted ({room})");
+    return Ok(None);
+  }
+  let wait = room.wait_time();
+  if wait > state.config.max_slow_mode_delay {
+    log::info!("[{channel}] Not responding, slow mode requires waiting {wait:?}");
+    return Ok(None);
+  }
+  if !wait.is_zero() && state.delayed.contains_key(channel) {
+    log::info!("[{channel}] Not responding, another response is already waiting for slow mode");
+    return Ok(None);
+  }
+
+  let text = chain::text::sanitize(text);
+  if text.is_empty() {
+    log::info!("[{channel}] Not responding, the response is empty once sanitized");
+    return Ok(None);
+  }
+  // Leaves room for the suffix which lets the same message be sent twice in a row
+  let limited = chain::GenerationOptions::twitch()
+    .with_max_chars(chain::limits::TWITCH_MAX_CHARS - twitch_api::SameMessageBypass::SUFFIX_CHARS)
+    .apply(&text);
+  if limited.len() < text.len() {
+    log::info!(
+      "[{channel}] Truncated a response from {} to {} characters",
+      text.chars().count(),
+      limited.chars().count()
+    );
+  }
+
+  if !wait.is_zero() {
+    log::info!("[{channel}] Delaying a response by {wait:?} for slow mode");
+    state
+      .delayed
+      .insert(channel.to_owned(), (Instant::now() + wait, limited.to_owned()));
+    return Ok(Some(limited.to_owned()));
+  }
+  send_response(conn, state, channel, limited).await?;
+  Ok(Some(limited.to_owned()))
+}
+
+/// Sends the responses delayed by slow mode whose time has come, unless the bot may no longer speak in their channel.
+///
+/// They're sent from the main loop rather than waited for, so that messages keep being received meanwhile.
+async fn send_delayed(
+  conn: &mut twitch_api::TwitchStream,
+  state: &mut State,
+) -> std::result::Result<(), twitch_api::WsError> {
+  let now = Instant::now();
+  let due = state
+    .delayed
+    .iter()
+    .filter(|(_, (send_at, _))| *send_at <= now)
+    .map(|(channel, _)| channel.clone())
+    .collect::<Vec<_>>();
+  for channel in due {
+    let Some((_, text)) = state.delayed.remove(&channel) else {
+      continue;
+    };
+    let room = state.rooms.entry(channel.clone()).or_default();
+    if !room.can_speak() {
+      log::info!("[{channel}] Dropped a delayed response, chat is restricted ({room})");
+      continue;
+    }
+    send_response(conn, state, &channel, &text).await?;
+  }
+  Ok(())
+}
+
+/// Sends `text` to `channel`, and records it against slow mode and the reply queue's limits once it's sent
+async fn send_response(
+  conn: &mut twitch_api::TwitchStream,
+  state: &mut State,
+  channel: &str,
+  text: &str,
+) -> std::result::Result<(), twitch_api::WsError> {
+  let start = Instant::now();
+  conn.respond(channel, text).await?;
+  state.metrics.record(Stage::Respond, start.elapsed());
+  state.rooms.entry(channel.to_owned()).or_default().after_send();
+  state.replies.after_send(channel, Instant::now());
+  Ok(())
+}
+
+/// Sends `text` to `user` in `channel`, or whispers it to them if `target` is [`ReplyTarget::Whisper`].
+/// Whispers aren't limited like the messages sent to the channel, but have their own limits, see [`twitch_api::whisper`].
+/// Whispers which can't be sent are dropped rather than sent to the channel.
+///
+/// Returns `false` if the message was dropped.
+async fn reply(
+  conn: &mut twitch_api::TwitchStream,
+  state: &mut State,
+  channel: &str,
+  user: &MessageUser<'_>,
+  target: ReplyTarget,
+  text: &str,
+) -> std::result::Result<bool, twitch_api::WsError> {
+  if target == ReplyTarget::Channel {
+    return Ok(respond(conn, state, channel, text).await?.is_some());
+  }
+  let Some(recipient_id) = user.id else {
+    log::warn!("[{channel}] Not whispering to {}, their user ID is unknown", user.login);
+    return Ok(false);
+  };
+  let text = chain::text::sanitize(text);
+  if text.is_empty() {
+    log::info!(
+      "[{channel}] Not whispering to {}, the message is empty once sanitized",
+      user.login
+    );
+    return Ok(false);
+  }
+  let text = chain::GenerationOptions::twitch().apply(&text);
+
+  match state.whispers.send(&state.credentials, recipient_id, text).await {
+    Ok(WhisperOutcome::Sent) => Ok(true),
+    Ok(WhisperOutcome::Limited(limit)) => {
+      log::warn!("[{channel}] Not whispering to {}, {limit}", user.login);
+      state.metrics.record_dropped(channel, "whisper_limit");
+      Ok(false)
+    }
+    Err(e) => {
+      log::error!("[{channel}] Failed to whisper to {}: {e:#}", user.login);
+      state.metrics.record_dropped(channel, "whisper_failed");
+      Ok(false)
+    }
+  }
+}
+
+/// Responds to `$<login> quote <user> [words...]` with a random message of `user` in `channel`,
+/// which contains `words` if there are any
+async fn quote(
+  conn: &mut twitch_api::TwitchStream,
+  state: &mut State,
+  channel: &str,
+  user: &MessageUser<'_>,
+  args: &[&str],
+) -> std::result::Result<(), twitch_api::WsError> {
+  let (Some(db), Some(target)) = (state.db.clone(), args.first()) else {
+    return Ok(());
+  };
+  if !(user.is_mod() || user.is_streamer()) && state.quote_cooldowns.has_cd(channel, user.login) {
+    return Ok(());
+  }
+  if let Some(last) = state.last_quote.get(channel) {
+    if last.elapsed() < state.config.quotes.channel_cooldown {
+      return Ok(());
+    }
+  }
+
+  let target = target.trim_start_matches('@').to_ascii_lowercase();
+  let pattern = (args.len() > 1).then(|| args[1..].join(" "));
+  let quote = match db::quotes::random(&db, channel, &target, pattern.as_deref()).await {
+    Ok(Some(quote)) => quote,
+    Ok(None) => return Ok(()),
+    Err(e) => {
+      log::error!("[{channel}] Failed to fetch a quote of {target}: {e}");
+      return Ok(());
+    }
+  };
+  let date = quote.sent_at().format("%F").to_string();
+  let message = templates::render(
+    &state.config.templates.quote,
+    &Vars {
+      response: quote.message(),
+      quote_user: &target,
+      quote_date: &date,
+      ..state.vars(channel, user.login)
+    },
+  );
+  if respond(conn, state, channel, &message).await?.is_some() {
+    state.quote_cooldowns.set_cd(channel, user.login);
+    state.last_quote.insert(channel.to_string(), Instant::now());
+  }
+  Ok(())
+}
+
+/// Opts the sender of `$<login> quote-optout` out of being quoted, or back in with `quote-optin`
+async fn set_quote_opt_out(
+  conn: &mut twitch_api::TwitchStream,
+  state: &mut State,
+  channel: &str,
+  user: &MessageUser<'_>,
+  opt_out: bool,
+) -> std::result::Result<(), twitch_api::WsError> {
+  let Some(db) = state.db.clone() else {
+    return Ok(());
+  };
+  let login = user.login.to_ascii_lowercase();
+  let result = if opt_out {
+    db::quotes::opt_out(&db, &login).await
+  } else {
+    db::quotes::opt_in(&db, &login).await
+  };
+  if let Err(e) = result {
+    log::error!("[{channel}] Failed to update the quote opt-out of {login}: {e}");
+    return Ok(());
+  }
+  log::info!(
+    "[{channel}] {login} opted {} quotes",
+    if opt_out { "out of" } else { "into" }
+  );
+  let template = if opt_out {
+    &state.config.templates.quote_opt_out
+  } else {
+    &state.config.templates.quote_opt_in
+  };
+  let message = templates::render(template, &state.vars(channel, user.login));
+  let target = state.config.reply_targets.quote_opt_out;
+  reply(conn, state, channel, user, target, &message).await?;
+  Ok(())
+}
+
+/// Joins `target` when the owner sends `$<login> join <target>` in `channel`, and saves the channel list
+async fn join_channel(
+  conn: &mut twitch_api::TwitchStream,
+  state: &mut State,
+  channel: &str,
+  user: &MessageUser<'_>,
+  target: &str,
+) -> std::result::Result<(), twitch_api::WsError> {
+  let target = target.trim_start_matches('#').to_ascii_lowercase();
+  if !channels::is_valid_name(&target) || state.config.channels.contains(&target) {
+    return Ok(());
+  }
+  log::info!("[{channel}] {} added #{target}", user.login);
+  state.add_channel(&target);
+  // JOINs are queued, so that they respect Twitch's rate limit
+  conn.schedule_joins(std::slice::from_ref(&target));
+  state.save_channels();
+
+  let message = templates::render(
+    &state.config.templates.join,
+    &Vars {
+      response: &target,
+      ..state.vars(channel, user.login)
+    },
+  );
+  respond(conn, state, channel, &message).await?;
+  Ok(())
+}
+
+/// Parts `target` when the owner sends `$<login> part [target]` in `channel`, which defaults to `channel` itself,
+/// and saves the channel list. The last channel is never parted, since the bot couldn't be told to join another one.
+async fn part_channel(
+  conn: &mut twitch_api::TwitchStream,
+  state: &mut State,
+  channel: &str,
+  user: &MessageUser<'_>,
+  target: &str,
+) -> std::result::Result<(), twitch_api::WsError> {
+  let target = target.trim_start_matches('#').to_ascii_lowercase();
+  if !state.config.channels.contains(&target) {
+    return Ok(());
+  }
+  if state.config.channels.len() == 1 {
+    log::info!("[{channel}] Not parting #{target}, it's the only channel");
+    return Ok(());
+  }
+  log::info!("[{channel}] {} removed #{target}", user.login);
+
+  // Responding first, in case `channel` is the one being parted
+  let message = templates::render(
+    &state.config.templates.part,
+    &Vars {
+      response: &target,
+      ..state.vars(channel, user.login)
+    },
+  );
+  respond(conn, state, channel, &message).await?;
+
+  state.remove_channel(&target);
+  conn.part(std::slice::from_ref(&target)).await?;
+  state.save_channels();
+  Ok(())
+}
+
+/// Replies to the next mention in the queue, if there's one which may be sent
+async fn reply_to_next_mention(
+  conn: &mut twitch_api::TwitchStream,
+  state: &mut State,
+) -> std::result::Result<(), twitch_api::WsError> {
+  let Some((channel, mention)) = state.replies.next(Instant::now()) else {
+    return Ok(());
+  };
+  state.metrics.record(Stage::Queue, mention.received.elapsed());
+
+  let words = mention.words.iter().map(String::as_str).collect::<Vec<_>>();
+  let response = generate(
+    &state.model,
+    state.shadow_model.as_ref(),
+    &state.metrics,
+    &state.recent,
+    &state.config,
+    &channel,
+    &words,
+  )
+  .await;
+  if !response.is_empty() {
+    let message = templates::render(
+      &state.config.templates.mention_reply,
+      &Vars {
+        response: &response,
+        ..state.vars(&channel, &mention.user)
+      },
+    );
+    if let Some(text) = respond(conn, state, &channel, &message).await? {
+      state.cooldowns.set_cd(&channel, &mention.user);
+      state.recent.record(&channel, &response);
+      state.record_seed(
+        &channel,
+        LastSeed {
+          user: mention.user,
+          words: mention.words,
+          retries: 0,
+          text,
+          id: None,
+        },
+      );
+    }
+  }
+  Ok(())
+}
+
+/// Responds again to the seed of the response `parent_id`, when a moderator or the streamer replies to it with one of
+/// `config.regenerate.keywords`. The new response is sampled with a different RNG, and may itself be regenerated.
+async fn regenerate(
+  conn: &mut twitch_api::TwitchStream,
+  state: &mut State,
+  channel: &str,
+  user: &MessageUser<'_>,
+  parent_id: &str,
+) -> std::result::Result<(), twitch_api::WsError> {
+  if state.cooldowns.has_cd(channel, user.login) {
+    return Ok(());
+  }
+  let last = state
+    .last_seeds
+    .get(channel)
+    .and_then(|seeds| seeds.iter().find(|seed| seed.id.as_deref() == Some(parent_id)));
+  let Some(last) = last.cloned() else {
+    log::info!("[{channel}] Not regenerating {parent_id}, its seed isn't known");
+    return Ok(());
+  };
+  if last.retries >= state.config.regenerate.max_retries {
+    log::info!(
+      "[{channel}] Not regenerating {:?}, it was already regenerated {} times",
+      last.words,
+      last.retries
+    );
+    return Ok(());
+  }
+
+  let words = last.words.iter().map(String::as_str).collect::<Vec<_>>();
+  let response = generate(
+    &state.model,
+    state.shadow_model.as_ref(),
+    &state.metrics,
+    &state.recent,
+    &state.config,
+    channel,
+    &words,
+  )
+  .await;
+  if response.is_empty() {
+    return Ok(());
+  }
+  let message = templates::render(
+    &state.config.templates.mention_reply,
+    &Vars {
+      response: &response,
+      ..state.vars(channel, &last.user)
+    },
+  );
+  if let Some(text) = respond(conn, state, channel, &message).await? {
+    state.cooldowns.set_cd(channel, user.login);
+    state.recent.record(channel, &response);
+    state.record_seed(
+      channel,
+      LastSeed {
+        retries: last.retries + 1,
+        text,
+        id: None,
+        ..last
+      },
+    );
+  }
+  Ok(())
+}
+
+/// Reacts to a raid or a subscription with a continuation of the event's seed by the model, at most once per
+/// `cooldown` for each type of event and channel.
+async fn react_to_event(
+  conn: &mut twitch_api::TwitchStream,
+  state: &mut State,
+  event: twitch_api::events::StreamEvent<'_>,
+) -> std::result::Result<(), twitch_api::WsError> {
+  use twitch_api::events::EventKind;
+
+  let channel = event.channel;
+  let (name, config, default_seed, user, response) = match event.kind {
+    EventKind::Raid { from, viewers } => (
+      "raid",
+      &state.config.events.raid,
+      "welcome raiders from {user}",
+      from,
+      viewers.to_string(),
+    ),
+    EventKind::Sub { user, months } => (
+      "sub",
+      &state.config.events.sub,
+      "{user} thanks for the sub",
+      user,
+      months.to_string(),
+    ),
+    EventKind::GiftSub { gifter, recipient } => (
+      "gift_sub",
+      &state.config.events.gift_sub,
+      "{user} thanks for gifting a sub to {response}",
+      gifter,
+      recipient.to_owned(),
+    ),
+  };
+  if !config.enabled {
+    return Ok(());
+  }
+  // Like their messages, the events of blocklisted users are ignored, including the subs gifted to them
+  let recipient = match event.kind {
+    EventKind::GiftSub { recipient, .. } => Some(recipient),
+    _ => None,
+  };
+  if std::iter::once(user)
+    .chain(recipient)
+    .any(|login| state.config.reply_blocklist.contains(&login.to_ascii_lowercase()))
+  {
+    log::info!("[{channel}] Not reacting to a {name} of {user}, the user is blocklisted");
+    return Ok(());
+  }
+  let key = (channel.to_owned(), name);
+  if state
+    .last_events
+    .get(&key)
+    .map_or(false, |at| at.elapsed() < config.cooldown)
+  {
+    log::info!("[{channel}] Not reacting to a {name} of {user}, on cooldown");
+    return Ok(());
+  }
+
+  let seed = templates::render(
+    config.seed.as_deref().unwrap_or(default_seed),
+    &Vars {
+      response: &response,
+      ..state.vars(channel, user)
+    },
+  );
+  let model = state.model.clone();
+  let task = {
+    let seed = seed.clone();
+    tokio::task::spawn_blocking(move || chain::sample_continuation(&*model, &seed, MAX_SAMPLES_FOR_SEQ_INPUT))
+  };
+  let text = match tokio::time::timeout(state.config.generation_timeout, task).await {
+    Ok(Ok(text)) => text,
+    Ok(Err(e)) => {
+      log::error!("Generation failed: {e}");
+      return Ok(());
+    }
+    Err(_) => {
+      state.metrics.record_timeout(channel);
+      log::warn!("[{channel}] Generation timed out while reacting to a {name} with seed `{seed}`");
+      return Ok(());
+    }
+  };
+  if text.is_empty() {
+    log::info!("[{channel}] Not reacting to a {name} of {user}, the model couldn't continue `{seed}`");
+    return Ok(());
+  }
+
+  log::info!("[{channel}] Reacting to a {name} of {user}");
+  if respond(conn, state, channel, &text).await?.is_some() {
+    state.last_events.insert(key, Instant::now());
+  }
+  Ok(())
+}
+
+/// Returns `true` if `text` is a reply asking for the bot's response to be regenerated.
+///
+/// Twitch prefixes replies with `@<parent login>`, which is ignored.
+fn is_regenerate_request(config: &Config, text: &str) -> bool {
+  let text = text.to_ascii_lowercase();
+  let mention = format!("@{}", config.login.to_ascii_lowercase());
+  let text = text.strip_prefix(&mention).unwrap_or(&text).trim();
+  config.regenerate.keywords.iter().any(|keyword| keyword == text)
+}
+
 async fn run(config: Config) -> Result<()> {
+  let db = match &config.database_url {
+    Some(url) => {
+      log::info!("Connecting to the logs database");
+      Some(db::connect(url.as_str()).await?)
+    }
+    None => None,
+  };
+
   log::info!("Loading model");
 
   let mut state = State {
-    model: chain::load_chain_of_any_supported_order(&config.model_path)?,
+    model: chain::load_chain_of_any_supported_order(&config.model_path)?.into(),
+    shadow_model: match &config.shadow_model_path {
+      Some(path) => {
+        log::info!("Loading shadow model");
+        Some(chain::load_chain_of_any_supported_order(path)?.into())
+      }
+      None => None,
+    },
     cooldowns: Cooldowns::new(&config.channels, config.user_cooldown),
     credentials: twitch_api::Credentials::from(&config),
+    whispers: twitch_api::Whispers::default(),
     reply_times: HashMap::new(),
-    prefix: format!("@{}", config.login.to_ascii_lowercase()),
+    rooms: HashMap::new(),
+    delayed: HashMap::new(),
+    replies: ReplyQueue::new(&config.channels, config.reply_queue.clone()),
     command_prefix: format!("${}", config.login.to_ascii_lowercase()),
+    metrics: Metrics::default(),
+    model_name: model_name(&config.model_path),
+    db,
+    quote_cooldowns: Cooldowns::new(&config.channels, config.quotes.user_cooldown),
+    last_quote: HashMap::new(),
+    last_seeds: HashMap::new(),
+    recent: RecentOutputs::new(config.repetition.window),
+    last_events: HashMap::new(),
+    rate_limited_until: None,
     config,
   };
 
+  if let Some(address) = state.config.metrics_address {
+    metrics::spawn_server(address, state.metrics.clone());
+  }
+  let mut metrics_timer = tokio::time::interval(state.config.metrics_log_interval);
+  let mut queue_timer = tokio::time::interval(QUEUE_INTERVAL);
+  queue_timer.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
   'stop: loop {
     log::info!("Connecting to Twitch");
-    let mut conn = twitch_api::TwitchStream::new().await?;
+    let mut conn =
+      twitch_api::TwitchStream::with_options(state.config.server.clone(), state.config.connection.clone()).await?;
 
     let mut reply_times = std::collections::HashMap::with_capacity(state.config.channels.len());
     for channel in &state.config.channels {
-      reply_times.insert(
-        channel.to_string(),
-        ChannelReplyTracker {
-          reply_timer: std::time::Instant::now().sub(state.config.reply_timeout),
-          message_count: state.config.reply_after_messages,
-        },
-      );
+      reply_times.insert(channel.to_string(), ChannelReplyTracker::new(&state.config));
     }
     state.reply_times = reply_times;
+    // Twitch sends the full room state of every channel when it's joined
+    state.rooms.clear();
+    state.delayed.clear();
     conn.authenticate(&state.credentials).await?;
     conn.schedule_joins(&state.config.channels);
 
@@ -153,6 +1009,14 @@ async fn run(config: Config) -> Result<()> {
           log::info!("Process terminated");
           break 'stop Ok(());
         },
+        _ = metrics_timer.tick() => {
+          state.metrics.log_summary();
+          Ok(())
+        },
+        _ = queue_timer.tick() => match send_delayed(&mut conn, &mut state).await {
+          Ok(()) => reply_to_next_mention(&mut conn, &mut state).await,
+          Err(e) => Err(e),
+        },
         result = conn.receive() => match result {
           Ok(Some(message)) => if let Message::Text(batch) = message {
             handle_messages(&mut conn, &mut state, batch).await
@@ -164,6 +1028,8 @@ async fn run(config: Config) -> Result<()> {
         }
       };
 
+      handle_deliveries(&mut conn, &mut state);
+
       if let Err(e) = error {
         log::error!("Error receiving or processing messages: {:?}", e);
         let action = SuggestedAction::from(&e);
@@ -177,35 +1043,115 @@ async fn run(config: Config) -> Result<()> {
   }
 }
 
+/// Logs the messages which Twitch dropped or didn't confirm, and stops responding for a while if it was rate limited
+fn handle_deliveries(conn: &mut twitch_api::TwitchStream, state: &mut State) {
+  for delivery in conn.take_deliveries() {
+    let channel = &delivery.channel;
+    match &delivery.outcome {
+      SendOutcome::Accepted => {
+        // Replies refer to the response by its ID, which is only known now
+        let Some(id) = &delivery.id else {
+          continue;
+        };
+        let seed = state.last_seeds.get_mut(channel).and_then(|seeds| {
+          seeds
+            .iter_mut()
+            .find(|seed| seed.id.is_none() && seed.text == delivery.text)
+        });
+        if let Some(seed) = seed {
+          seed.id = Some(id.clone());
+        }
+      }
+      SendOutcome::Dropped { reason, notice } => {
+        log::warn!("[{channel}] Twitch dropped `{}` ({reason}): {notice}", delivery.text);
+        state.metrics.record_dropped(channel, reason);
+        if delivery.outcome.is_rate_limited() {
+          log::warn!(
+            "Rate limited by Twitch, not responding for {:?}",
+            state.config.rate_limit_backoff
+          );
+          state.rate_limited_until = Some(Instant::now() + state.config.rate_limit_backoff);
+        }
+      }
+      SendOutcome::Unconfirmed => {
+        log::warn!(
+          "[{channel}] Twitch didn't confirm `{}` within {:?}",
+          delivery.text,
+          twitch_api::delivery::CONFIRMATION_TIMEOUT
+        );
+        state.metrics.record_dropped(channel, "unconfirmed");
+      }
+    }
+  }
+}
+
 async fn handle_messages(
   conn: &mut twitch_api::TwitchStream,
   state: &mut State,
   batch: String,
 ) -> std::result::Result<(), twitch_api::WsError> {
-  for twitch_msg in batch.lines().map(twitch::Message::parse).filter_map(Result::ok) {
+  for line in batch.lines() {
+    let Ok(twitch_msg) = twitch::Message::parse(line) else {
+      continue;
+    };
     match twitch_msg.command() {
       Command::Ping => conn.pong().await?,
       Command::Reconnect => conn.reconnect(&state.credentials, &state.config.channels).await?,
       Command::Notice => {
         let text = twitch_msg.text().unwrap_or("???").trim();
         log::info!("Notice: {}", text);
+        if twitch_api::is_auth_failure(text) {
+          conn
+            .refresh_and_reconnect(&mut state.credentials, &state.config.channels)
+            .await?;
+        }
+        if let (Some(channel), Some("msg_followersonly" | "msg_followersonly_zero" | "msg_followersonly_followed")) =
+          (twitch_msg.channel(), twitch_msg.tag(twitch::Tag::MsgId))
+        {
+          let channel = channel.strip_prefix('#').unwrap_or(channel);
+          state.rooms.entry(channel.to_string()).or_default().not_following = true;
+        }
+      }
+      Command::RoomState => {
+        if let Some(channel) = twitch_msg.channel() {
+          let channel = channel.strip_prefix('#').unwrap_or(channel);
+          let room = state.rooms.entry(channel.to_string()).or_default();
+          room.update(&twitch_msg);
+          log::info!("[{channel}] Room state: {room}");
+        }
+      }
+      Command::UserState => {
+        if let Some(channel) = twitch_msg.channel() {
+          let channel = channel.strip_prefix('#').unwrap_or(channel);
+          let badges = twitch_msg.tag(twitch::Tag::Badges).unwrap_or("");
+          state.rooms.entry(channel.to_string()).or_default().update_user(badges);
+        }
       }
       Command::Privmsg => {
         let channel = twitch_msg.channel().unwrap_or("???");
         let login = twitch_msg.prefix().and_then(|v| v.nick).unwrap_or("???");
         let text = twitch_msg.text().unwrap_or("???").trim();
         let badges = twitch_msg.tag(twitch::Tag::Badges).unwrap_or("");
+        let id = twitch_api::sender_id(line);
+        let reply_to = twitch_api::reply_parent_login(line);
+        let reply_parent_id = twitch_api::reply_parent_id(line);
 
         handle_message(
           conn,
           state,
           channel.strip_prefix('#').unwrap_or(channel),
-          MessageUser { login, badges },
+          MessageUser { login, badges, id },
           text,
+          reply_to,
+          reply_parent_id,
         )
         .await?;
       }
-      _ => (),
+      _ => {
+        if let Some(event) = twitch_api::events::parse(line) {
+          react_to_event(conn, state, event).await?;
+        }
+      }
     }
   }
   Ok(())
@@ -214,6 +1160,8 @@ async fn handle_messages(
 struct MessageUser<'a> {
   login: &'a str,
   badges: &'a str,
+  /// From the `user-id` tag, which is needed to whisper to the user
+  id: Option<&'a str>,
 }
 
 impl<'a> MessageUser<'a> {
@@ -234,43 +1182,79 @@ async fn handle_message(
   channel: &str,
   user: MessageUser<'_>,
   text: &str,
+  reply_to: Option<&str>,
+  reply_parent_id: Option<&str>,
 ) -> std::result::Result<(), twitch_api::WsError> {
   log::info!("[{channel}] {}: {text}", user.login);
 
-  // format: `@LOGIN <seed> <...rest>`
-  // `rest` is ignored
+  if let Some(parent_id) = reply_parent_id {
+    if (user.is_mod() || user.is_streamer())
+      && reply_to.map_or(false, |parent| parent.eq_ignore_ascii_case(&state.config.login))
+      && is_regenerate_request(&state.config, text)
+    {
+      return regenerate(conn, state, channel, &user, parent_id).await;
+    }
+  }
+
+  // The words of a message other than its trigger, e.g. `@LOGIN <seed>`, seed the reply
+  let seed = state.config.triggers.seed(channel, text);
+
+  // Users on cooldown are told how long they have to wait, once per cooldown
+  if state.config.cooldown_feedback
+    && seed.is_some()
+    && !(user.is_mod() || user.is_streamer())
+    && !state.config.reply_blocklist.contains(&user.login.to_ascii_lowercase())
+    && state.cooldowns.has_cd(channel, user.login)
+  {
+    if let Some(remaining) = state.cooldowns.take_feedback(channel, user.login) {
+      // Rounded up, so that it never says `0s`
+      let seconds = (remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0)).to_string();
+      let message = templates::render(
+        &state.config.templates.cooldown,
+        &Vars {
+          response: &seconds,
+          ..state.vars(channel, user.login)
+        },
+      );
+      let target = state.config.reply_targets.cooldown;
+      reply(conn, state, channel, &user, target, &message).await?;
+    }
+    return Ok(());
+  }
 
-  if text.to_ascii_lowercase().starts_with(&state.prefix)
-    && (user.is_mod() || user.is_streamer() || !state.cooldowns.has_cd(channel, user.login))
+  if let Some(words) =
+    seed.filter(|_| user.is_mod() || user.is_streamer() || !state.cooldowns.has_cd(channel, user.login))
   {
     if state.config.reply_blocklist.contains(&user.login.to_ascii_lowercase()) {
       return Ok(());
     }
 
-    let words = text.split_whitespace().skip(1).collect::<Vec<_>>();
-    let response = match words.len() {
-      0 => chain::sample(&state.model, "", MAX_SAMPLES),
-      1 => chain::sample(&state.model, words[0], MAX_SAMPLES),
-      _ => chain::sample_seq(&state.model, &words, MAX_SAMPLES_FOR_SEQ_INPUT),
-    };
-    if !response.is_empty() {
-      conn.respond(channel, &response).await?;
-      state.cooldowns.set_cd(channel, user.login);
-    }
+    // Replies are sent from the queue, which keeps only the latest mention of each user
+    state.replies.push(
+      channel,
+      Mention {
+        user: user.login.to_string(),
+        words,
+        received: Instant::now(),
+      },
+    );
+    log::debug!(
+      "[{channel}] {} mention(s) waiting for a reply",
+      state.replies.pending(channel)
+    );
 
     return Ok(());
   }
 
   if text.to_ascii_lowercase().starts_with(&state.command_prefix) {
+    let vars = state.vars(channel, user.login);
     match text.split_whitespace().nth(1) {
       Some("version") => {
-        conn
-          .respond(channel, &format!("SCS v{}", env!("CARGO_PKG_VERSION")))
-          .await?;
+        let message = templates::render(&state.config.templates.version, &vars);
+        let target = state.config.reply_targets.version;
+        reply(conn, state, channel, &user, target, &message).await?;
       }
       Some("model") => {
-        // Save to unwrap the filename here since the model has been successfully loaded.
-        let model_name = state.config.model_path.file_name().unwrap();
         let model_snapshot = state
           .config
           .model_path
@@ -284,29 +1268,69 @@ async fn handle_message(
           })
           .unwrap_or_else(|_| String::from("unknown"));
         let model_metadata = state.model.model_meta_data();
-        conn
-          .respond(
-            channel,
-            &format!(
-              "{} (version: {}; metadata: {})",
-              model_name.to_string_lossy(),
-              model_snapshot,
-              if model_metadata.is_empty() {
-                "none"
-              } else {
-                model_metadata
-              }
-            ),
-          )
-          .await?;
+        let message = templates::render(
+          &state.config.templates.model_info,
+          &Vars {
+            model_version: &model_snapshot,
+            model_metadata: if model_metadata.is_empty() {
+              "none"
+            } else {
+              model_metadata
+            },
+            ..vars
+          },
+        );
+        let target = state.config.reply_targets.model_info;
+        reply(conn, state, channel, &user, target, &message).await?;
       }
       Some("?") => {
         let words = text.split_whitespace().skip(2).collect::<Vec<_>>();
         if !words.is_empty() {
-          let word_metadata = state.model.phrase_meta_data(&words);
-          conn.respond(channel, &word_metadata.replace('\n', " ")).await?;
+          let word_metadata = state.model.phrase_meta_data(&words).replace('\n', " ");
+          let message = templates::render(
+            &state.config.templates.phrase_info,
+            &Vars {
+              response: &word_metadata,
+              ..vars
+            },
+          );
+          let target = state.config.reply_targets.phrase_info;
+          reply(conn, state, channel, &user, target, &message).await?;
+        }
+      }
+      Some("status") => {
+        let room = state.rooms.get(channel).cloned().unwrap_or_default().to_string();
+        let message = templates::render(
+          &state.config.templates.status,
+          &Vars {
+            response: &room,
+            ..vars
+          },
+        );
+        let target = state.config.reply_targets.status;
+        reply(conn, state, channel, &user, target, &message).await?;
+      }
+      Some("quote") => {
+        let args = text.split_whitespace().skip(2).collect::<Vec<_>>();
+        quote(conn, state, channel, &user, &args).await?;
+      }
+      Some("quote-optout") => set_quote_opt_out(conn, state, channel, &user, true).await?,
+      Some("quote-optin") => set_quote_opt_out(conn, state, channel, &user, false).await?,
+      Some("promote-shadow") if user.is_mod() || user.is_streamer() => {
+        if state.promote_shadow() {
+          let message = templates::render(&state.config.templates.promote_shadow, &state.vars(channel, user.login));
+          respond(conn, state, channel, &message).await?;
+        }
+      }
+      Some("join") if state.is_owner(user.login) => {
+        if let Some(target) = text.split_whitespace().nth(2) {
+          join_channel(conn, state, channel, &user, target).await?;
         }
       }
+      Some("part") if state.is_owner(user.login) => {
+        let target = text.split_whitespace().nth(2).unwrap_or(channel).to_owned();
+        part_channel(conn, state, channel, &user, &target).await?;
+      }
       Some(_) | None => (),
     }
     return Ok(());
@@ -331,14 +1355,42 @@ async fn handle_message(
     }
 
     let words = text.split_whitespace().collect::<Vec<_>>();
-    let response = match words.len() {
-      1 => chain::sample(&state.model, words[0], MAX_SAMPLES),
-      _ => chain::sample_seq(&state.model, &words, MAX_SAMPLES_FOR_SEQ_INPUT),
-    };
+    let response = generate(
+      &state.model,
+      state.shadow_model.as_ref(),
+      &state.metrics,
+      &state.recent,
+      &state.config,
+      channel,
+      &words,
+    )
+    .await;
 
     if !response.is_empty() && response != text.trim() && !text.starts_with(&response) {
       tracker.after_reply();
-      conn.respond(channel, &format!("@{} {response}", user.login)).await?;
+      let message = templates::render(
+        &state.config.templates.random_reply,
+        &Vars {
+          response: &response,
+          user: user.login,
+          channel,
+          model_name: &state.model_name,
+          ..Default::default()
+        },
+      );
+      if let Some(text) = respond(conn, state, channel, &message).await? {
+        state.recent.record(channel, &response);
+        state.record_seed(
+          channel,
+          LastSeed {
+            user: user.login.to_string(),
+            words: words.iter().map(|w| w.to_string()).collect(),
+            retries: 0,
+            text,
+            id: None,
+          },
+        );
+      }
     }
   }
 
@@ -354,6 +1406,10 @@ async fn main() -> Result<()> {
   }
   env_logger::try_init()?;
 
+  if env::args().nth(1).as_deref() == Some("validate") {
+    return scs_config::validate_all(env::args().skip(2), |path| Config::load(path));
+  }
+
   let mut config = Config::load(
     env::args()
       .nth(1)
@@ -0,0 +1,248 @@
+use std::{
+  collections::{BTreeMap, VecDeque},
+  fmt::Write as _,
+  net::SocketAddr,
+  sync::{Arc, Mutex},
+  time::Duration,
+};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// Number of most recent samples used to compute percentiles
+const WINDOW_SIZE: usize = 1024;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Stage {
+  /// Waiting in the reply queue
+  Queue,
+  /// Sampling the model
+  Generation,
+  /// Sending the reply to Twitch
+  Respond,
+}
+
+impl Stage {
+  fn name(&self) -> &'static str {
+    match self {
+      Stage::Queue => "queue",
+      Stage::Generation => "generation",
+      Stage::Respond => "respond",
+    }
+  }
+}
+
+#[derive(Default)]
+struct Timings {
+  window: VecDeque<Duration>,
+  count: u64,
+  total: Duration,
+}
+
+impl Timings {
+  fn record(&mut self, elapsed: Duration) {
+    if self.window.len() == WINDOW_SIZE {
+      self.window.pop_front();
+    }
+    self.window.push_back(elapsed);
+    self.count += 1;
+    self.total += elapsed;
+  }
+
+  fn summary(&self) -> Option<Summary> {
+    if self.window.is_empty() {
+      return None;
+    }
+    let mut sorted = self.window.iter().copied().collect::<Vec<_>>();
+    sorted.sort_unstable();
+    let percentile = |p: f64| sorted[((sorted.len() - 1) as f64 * p).round() as usize];
+    Some(Summary {
+      count: self.count,
+      total: self.total,
+      p50: percentile(0.5),
+      p90: percentile(0.9),
+      p99: percentile(0.99),
+      max: sorted[sorted.len() - 1],
+    })
+  }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Summary {
+  pub count: u64,
+  pub total: Duration,
+  pub p50: Duration,
+  pub p90: Duration,
+  pub p99: Duration,
+  pub max: Duration,
+}
+
+#[derive(Default)]
+struct Inner {
+  queue: Timings,
+  generation: Timings,
+  respond: Timings,
+  /// Number of generations which timed out, per channel
+  timeouts: BTreeMap<String, u64>,
+  /// Number of sent messages which Twitch dropped or didn't confirm, per channel and reason
+  dropped: BTreeMap<(String, String), u64>,
+}
+
+impl Inner {
+  fn timings(&mut self, stage: Stage) -> &mut Timings {
+    match stage {
+      Stage::Queue => &mut self.queue,
+      Stage::Generation => &mut self.generation,
+      Stage::Respond => &mut self.respond,
+    }
+  }
+}
+
+/// Latency measurements of the reply path, shared with the metrics endpoint
+#[derive(Clone, Default)]
+pub struct Metrics(Arc<Mutex<Inner>>);
+
+impl Metrics {
+  pub fn record(&self, stage: Stage, elapsed: Duration) {
+    self.0.lock().unwrap().timings(stage).record(elapsed);
+  }
+
+  pub fn summary(&self, stage: Stage) -> Option<Summary> {
+    self.0.lock().unwrap().timings(stage).summary()
+  }
+
+  pub fn record_timeout(&self, channel: &str) {
+    *self.0.lock().unwrap().timeouts.entry(channel.to_owned()).or_default() += 1;
+  }
+
+  fn timeouts(&self) -> BTreeMap<String, u64> {
+    self.0.lock().unwrap().timeouts.clone()
+  }
+
+  /// Records a message which was dropped because of `reason`, the `msg-id` of Twitch's NOTICE or `unconfirmed`
+  pub fn record_dropped(&self, channel: &str, reason: &str) {
+    *self
+      .0
+      .lock()
+      .unwrap()
+      .dropped
+      .entry((channel.to_owned(), reason.to_owned()))
+      .or_default() += 1;
+  }
+
+  fn dropped(&self) -> BTreeMap<(String, String), u64> {
+    self.0.lock().unwrap().dropped.clone()
+  }
+
+  pub fn log_summary(&self) {
+    for stage in [Stage::Queue, Stage::Generation, Stage::Respond] {
+      if let Some(s) = self.summary(stage) {
+        log::info!(
+          "[metrics] {}: count={} p50={:?} p90={:?} p99={:?} max={:?}",
+          stage.name(),
+          s.count,
+          s.p50,
+          s.p90,
+          s.p99,
+          s.max
+        );
+      }
+    }
+    let timeouts = self.timeouts();
+    if !timeouts.is_empty() {
+      let counts = timeouts
+        .iter()
+        .map(|(channel, count)| format!("{channel}={count}"))
+        .collect::<Vec<_>>();
+      log::info!("[metrics] generation timeouts: {}", counts.join(" "));
+    }
+    let dropped = self.dropped();
+    if !dropped.is_empty() {
+      let counts = dropped
+        .iter()
+        .map(|((channel, reason), count)| format!("{channel}/{reason}={count}"))
+        .collect::<Vec<_>>();
+      log::info!("[metrics] dropped messages: {}", counts.join(" "));
+    }
+  }
+
+  /// Renders the metrics in the Prometheus text format
+  pub fn render(&self) -> String {
+    let mut output = String::new();
+    writeln!(output, "# TYPE scs_chat_latency_seconds summary").unwrap();
+    for stage in [Stage::Queue, Stage::Generation, Stage::Respond] {
+      if let Some(s) = self.summary(stage) {
+        let name = stage.name();
+        for (quantile, value) in [("0.5", s.p50), ("0.9", s.p90), ("0.99", s.p99), ("1", s.max)] {
+          writeln!(
+            output,
+            "scs_chat_latency_seconds{{stage=\"{name}\",quantile=\"{quantile}\"}} {}",
+            value.as_secs_f64()
+          )
+          .unwrap();
+        }
+        writeln!(
+          output,
+          "scs_chat_latency_seconds_sum{{stage=\"{name}\"}} {}",
+          s.total.as_secs_f64()
+        )
+        .unwrap();
+        writeln!(output, "scs_chat_latency_seconds_count{{stage=\"{name}\"}} {}", s.count).unwrap();
+      }
+    }
+    writeln!(output, "# TYPE scs_chat_generation_timeouts_total counter").unwrap();
+    for (channel, count) in self.timeouts() {
+      writeln!(
+        output,
+        "scs_chat_generation_timeouts_total{{channel=\"{channel}\"}} {count}"
+      )
+      .unwrap();
+    }
+    writeln!(output, "# TYPE scs_chat_dropped_messages_total counter").unwrap();
+    for ((channel, reason), count) in self.dropped() {
+      writeln!(
+        output,
+        "scs_chat_dropped_messages_total{{channel=\"{channel}\",reason=\"{reason}\"}} {count}"
+      )
+      .unwrap();
+    }
+    output
+  }
+}
+
+/// Serves the metrics over plain HTTP. Every request receives the same response, regardless of its path.
+pub fn spawn_server(address: SocketAddr, metrics: Metrics) -> tokio::task::JoinHandle<()> {
+  tokio::spawn(async move {
+    let listener = match tokio::net::TcpListener::bind(address).await {
+      Ok(listener) => listener,
+      Err(e) => {
+        log::error!("[metrics] Failed to bind to {address}: {e}");
+        return;
+      }
+    };
+    log::info!("[metrics] Listening on {address}");
+
+    loop {
+      let (mut socket, _) = match listener.accept().await {
+        Ok(conn) => conn,
+        Err(e) => {
+          log::warn!("[metrics] Failed to accept a connection: {e}");
+          continue;
+        }
+      };
+      let body = metrics.render();
+      tokio::spawn(async move {
+        // The request itself is irrelevant, but it has to be read before responding
+        let mut buf = [0u8; 1024];
+        let _ = socket.read(&mut buf).await;
+        let response = format!(
+          "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+          body.len(),
+          body
+        );
+        if let Err(e) = socket.write_all(response.as_bytes()).await {
+          log::warn!("[metrics] Failed to respond: {e}");
+        }
+      });
+    }
+  })
+}
@@ -0,0 +1,274 @@
+use crate::config::ReplyQueueConfig;
+use std::{collections::VecDeque, time::Instant};
+
+/// A mention of the bot waiting for a reply
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mention {
+  pub user: String,
+  /// Words after the mention, used to seed the reply
+  pub words: Vec<String>,
+  pub received: Instant,
+}
+
+struct ChannelQueue {
+  name: String,
+  pending: VecDeque<Mention>,
+  /// When the messages sent within the last `window` were sent
+  sent: VecDeque<Instant>,
+}
+
+impl ChannelQueue {
+  fn expire(&mut self, config: &ReplyQueueConfig, now: Instant) {
+    while let Some(sent) = self.sent.front() {
+      if now.saturating_duration_since(*sent) < config.window {
+        break;
+      }
+      self.sent.pop_front();
+    }
+    let before = self.pending.len();
+    self
+      .pending
+      .retain(|mention| now.saturating_duration_since(mention.received) <= config.max_wait);
+    if self.pending.len() < before {
+      log::info!(
+        "[{}] Dropped {} mention(s) which waited longer than {:?}",
+        self.name,
+        before - self.pending.len(),
+        config.max_wait
+      );
+    }
+  }
+
+  fn is_limited(&self, config: &ReplyQueueConfig) -> bool {
+    self.sent.len() >= config.max_messages
+  }
+}
+
+/// Mentions waiting for a reply, queued per channel.
+///
+/// Only the most recent mention of each user is kept, the number of messages sent to each channel
+/// is capped per `window`, and channels take turns so that a burst in one doesn't starve the others.
+pub struct ReplyQueue {
+  channels: Vec<ChannelQueue>,
+  /// Index of the channel which was served last
+  cursor: usize,
+  config: ReplyQueueConfig,
+}
+
+impl ReplyQueue {
+  pub fn new(channels: &[String], config: ReplyQueueConfig) -> Self {
+    Self {
+      channels: channels
+        .iter()
+        .map(|name| ChannelQueue {
+          name: name.clone(),
+          pending: VecDeque::new(),
+          sent: VecDeque::new(),
+        })
+        .collect(),
+      cursor: 0,
+      config,
+    }
+  }
+
+  /// Starts queueing mentions in `channel`
+  pub fn add_channel(&mut self, channel: &str) {
+    if self.channel_mut(channel).is_none() {
+      self.channels.push(ChannelQueue {
+        name: channel.to_owned(),
+        pending: VecDeque::new(),
+        sent: VecDeque::new(),
+      });
+    }
+  }
+
+  /// Drops the queue of `channel`, along with its pending mentions
+  pub fn remove_channel(&mut self, channel: &str) {
+    self.channels.retain(|queue| queue.name != channel);
+    self.cursor = 0;
+  }
+
+  fn channel_mut(&mut self, channel: &str) -> Option<&mut ChannelQueue> {
+    self.channels.iter_mut().find(|queue| queue.name == channel)
+  }
+
+  /// Queues `mention`, replacing the pending mention of the same user if there is one.
+  /// If the channel's queue is full, its oldest mention is dropped.
+  pub fn push(&mut self, channel: &str, mention: Mention) {
+    let max_pending = self.config.max_pending;
+    let Some(queue) = self.channel_mut(channel) else {
+      return;
+    };
+    if let Some(pending) = queue.pending.iter_mut().find(|pending| pending.user == mention.user) {
+      // The user keeps their place in the queue
+      *pending = mention;
+      return;
+    }
+    queue.pending.push_back(mention);
+    if queue.pending.len() > max_pending {
+      if let Some(dropped) = queue.pending.pop_front() {
+        log::info!(
+          "[{channel}] Reply queue is full, dropped the mention of {}",
+          dropped.user
+        );
+      }
+    }
+  }
+
+  /// Takes the next mention to reply to, from the first channel after the last served one
+  /// which has pending mentions and hasn't reached its message cap
+  pub fn next(&mut self, now: Instant) -> Option<(String, Mention)> {
+    let count = self.channels.len();
+    for offset in 1..=count {
+      let index = (self.cursor + offset) % count;
+      let queue = &mut self.channels[index];
+      queue.expire(&self.config, now);
+      if queue.is_limited(&self.config) {
+        continue;
+      }
+      if let Some(mention) = queue.pending.pop_front() {
+        self.cursor = index;
+        return Some((queue.name.clone(), mention));
+      }
+    }
+    None
+  }
+
+  /// Whether another message may be sent to `channel` without exceeding its cap
+  pub fn can_send(&mut self, channel: &str, now: Instant) -> bool {
+    let config = &self.config;
+    match self.channels.iter_mut().find(|queue| queue.name == channel) {
+      Some(queue) => {
+        queue.expire(config, now);
+        !queue.is_limited(config)
+      }
+      None => true,
+    }
+  }
+
+  /// Records a message sent to `channel`, which counts towards its cap
+  pub fn after_send(&mut self, channel: &str, now: Instant) {
+    if let Some(queue) = self.channel_mut(channel) {
+      queue.sent.push_back(now);
+    }
+  }
+
+  /// Number of mentions waiting for a reply in `channel`
+  pub fn pending(&self, channel: &str) -> usize {
+    self
+      .channels
+      .iter()
+      .find(|queue| queue.name == channel)
+      .map_or(0, |queue| queue.pending.len())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::time::Duration;
+
+  fn config() -> ReplyQueueConfig {
+    ReplyQueueConfig {
+      max_messages: 2,
+      window: Duration::from_secs(30),
+      max_pending: 3,
+      max_wait: Duration::from_secs(60),
+    }
+  }
+
+  fn mention(user: &str, text: &str, received: Instant) -> Mention {
+    Mention {
+      user: user.into(),
+      words: text.split_whitespace().map(String::from).collect(),
+      received,
+    }
+  }
+
+  fn channels() -> Vec<String> {
+    vec!["a".into(), "b".into()]
+  }
+
+  #[test]
+  fn test_coalesce_per_user() {
+    let now = Instant::now();
+    let mut queue = ReplyQueue::new(&channels(), config());
+    queue.push("a", mention("foo", "first", now));
+    queue.push("a", mention("bar", "hello", now));
+    queue.push("a", mention("foo", "second", now));
+    assert_eq!(queue.pending("a"), 2);
+
+    let (channel, next) = queue.next(now).unwrap();
+    assert_eq!(channel, "a");
+    assert_eq!(next.user, "foo");
+    assert_eq!(next.words, vec!["second"]);
+  }
+
+  #[test]
+  fn test_drop_oldest_when_full() {
+    let now = Instant::now();
+    let mut queue = ReplyQueue::new(&channels(), config());
+    for user in ["1", "2", "3", "4"] {
+      queue.push("a", mention(user, "hi", now));
+    }
+    assert_eq!(queue.pending("a"), 3);
+    assert_eq!(queue.next(now).unwrap().1.user, "2");
+  }
+
+  #[test]
+  fn test_message_cap() {
+    let now = Instant::now();
+    let mut queue = ReplyQueue::new(&channels(), config());
+    for user in ["1", "2", "3"] {
+      queue.push("a", mention(user, "hi", now));
+    }
+    for _ in 0..2 {
+      let (channel, _) = queue.next(now).unwrap();
+      queue.after_send(&channel, now);
+    }
+    assert!(!queue.can_send("a", now));
+    assert_eq!(queue.next(now), None);
+
+    let later = now + Duration::from_secs(30);
+    assert!(queue.can_send("a", later));
+    assert_eq!(queue.next(later).unwrap().1.user, "3");
+  }
+
+  #[test]
+  fn test_channels_take_turns() {
+    let now = Instant::now();
+    let mut queue = ReplyQueue::new(&channels(), config());
+    for user in ["1", "2", "3"] {
+      queue.push("a", mention(user, "hi", now));
+    }
+    queue.push("b", mention("4", "hi", now));
+
+    let order = std::iter::from_fn(|| queue.next(now).map(|(channel, _)| channel)).collect::<Vec<_>>();
+    assert_eq!(order, vec!["b", "a", "a", "a"]);
+  }
+
+  #[test]
+  fn test_expire_stale_mentions() {
+    let now = Instant::now();
+    let mut queue = ReplyQueue::new(&channels(), config());
+    queue.push("a", mention("foo", "hi", now));
+    assert_eq!(queue.next(now + Duration::from_secs(61)), None);
+    assert_eq!(queue.pending("a"), 0);
+  }
+
+  #[test]
+  fn test_add_and_remove_channels() {
+    let now = Instant::now();
+    let mut queue = ReplyQueue::new(&channels(), config());
+    queue.push("c", mention("foo", "hi", now));
+    assert_eq!(queue.pending("c"), 0);
+
+    queue.add_channel("c");
+    queue.push("c", mention("foo", "hi", now));
+    queue.push("a", mention("bar", "hi", now));
+    queue.remove_channel("a");
+    assert_eq!(queue.pending("a"), 0);
+    assert_eq!(queue.next(now).unwrap().0, "c");
+    assert_eq!(queue.next(now), None);
+  }
+}
@@ -0,0 +1,87 @@
+use std::{
+  collections::{hash_map::DefaultHasher, HashMap, VecDeque},
+  hash::{Hash, Hasher},
+};
+
+/// Hashes of the responses recently sent to each channel, so that the bot doesn't repeat itself.
+///
+/// Each channel keeps the `window` most recently used responses, sending a response again makes it the most recent.
+pub struct RecentOutputs {
+  channels: HashMap<String, VecDeque<u64>>,
+  window: usize,
+}
+
+impl RecentOutputs {
+  pub fn new(window: usize) -> Self {
+    Self {
+      channels: HashMap::new(),
+      window,
+    }
+  }
+
+  /// Whether `text` is one of the recent responses in `channel`, ignoring case, whitespace, and invisible characters
+  pub fn contains(&self, channel: &str, text: &str) -> bool {
+    let hash = fingerprint(text);
+    self
+      .channels
+      .get(channel)
+      .map_or(false, |recent| recent.contains(&hash))
+  }
+
+  /// Records `text` as the most recent response in `channel`, forgetting the least recent one if the window is full
+  pub fn record(&mut self, channel: &str, text: &str) {
+    if self.window == 0 {
+      return;
+    }
+    let hash = fingerprint(text);
+    let recent = self.channels.entry(channel.to_owned()).or_default();
+    recent.retain(|h| *h != hash);
+    recent.push_back(hash);
+    while recent.len() > self.window {
+      recent.pop_front();
+    }
+  }
+
+  pub fn remove_channel(&mut self, channel: &str) {
+    self.channels.remove(channel);
+  }
+}
+
+fn fingerprint(text: &str) -> u64 {
+  let mut hasher = DefaultHasher::new();
+  chain::text::sanitize(text).to_lowercase().hash(&mut hasher);
+  hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_normalized_match() {
+    let mut recent = RecentOutputs::new(2);
+    recent.record("a", "Hello  there\u{200b}");
+    assert!(recent.contains("a", "hello there"));
+    assert!(!recent.contains("a", "hello"));
+    assert!(!recent.contains("b", "hello there"));
+  }
+
+  #[test]
+  fn test_evict_least_recent() {
+    let mut recent = RecentOutputs::new(2);
+    recent.record("a", "one");
+    recent.record("a", "two");
+    recent.record("a", "one");
+    recent.record("a", "three");
+    assert!(recent.contains("a", "one"));
+    assert!(!recent.contains("a", "two"));
+    assert!(recent.contains("a", "three"));
+  }
+
+  #[test]
+  fn test_disabled() {
+    let mut recent = RecentOutputs::new(0);
+    recent.record("a", "one");
+    assert!(!recent.contains("a", "one"));
+  }
+}
@@ -0,0 +1,99 @@
+use std::{
+  fmt,
+  time::{Duration, Instant},
+};
+
+/// Chat restrictions of a channel, as reported by its ROOMSTATE messages
+#[derive(Clone, Debug, Default)]
+pub struct RoomState {
+  /// Minimum interval between messages from the same user, zero if slow mode is off
+  pub slow: Duration,
+  /// How long users must have followed the channel to chat, `None` if followers-only mode is off
+  pub followers_only: Option<Duration>,
+  pub emote_only: bool,
+  pub subs_only: bool,
+  /// Whether the bot is a moderator or VIP in the channel, which exempts it from every restriction
+  pub privileged: bool,
+  /// Set when Twitch rejected a message because the bot doesn't follow the channel
+  pub not_following: bool,
+  last_sent: Option<Instant>,
+}
+
+impl RoomState {
+  /// Applies a ROOMSTATE message. Twitch only sends the tags which changed after the initial one.
+  pub fn update(&mut self, message: &twitch::Message) {
+    if let Some(slow) = message.tag(twitch::Tag::Slow).and_then(|v| v.parse().ok()) {
+      self.slow = Duration::from_secs(slow);
+    }
+    if let Some(minutes) = message
+      .tag(twitch::Tag::FollowersOnly)
+      .and_then(|v| v.parse::<i64>().ok())
+    {
+      // `-1` means followers-only mode is off
+      self.followers_only = u64::try_from(minutes).ok().map(|m| Duration::from_secs(m * 60));
+      self.not_following = false;
+    }
+    if let Some(emote_only) = message.tag(twitch::Tag::EmoteOnly) {
+      self.emote_only = emote_only == "1";
+    }
+    if let Some(subs_only) = message.tag(twitch::Tag::SubsOnly) {
+      self.subs_only = subs_only == "1";
+    }
+  }
+
+  /// Applies the bot's own badges from a USERSTATE message, formatted as `name/version,name/version`
+  pub fn update_user(&mut self, badges: &str) {
+    self.privileged = badges
+      .split(',')
+      .filter_map(|badge| badge.split('/').next())
+      .any(|name| matches!(name, "moderator" | "vip" | "broadcaster"));
+  }
+
+  /// Whether the bot is allowed to send messages in the channel
+  pub fn can_speak(&self) -> bool {
+    self.privileged || !(self.emote_only || self.subs_only || self.not_following)
+  }
+
+  /// How long the bot has to wait before it may send another message
+  pub fn wait_time(&self) -> Duration {
+    match self.last_sent {
+      Some(last_sent) if !self.privileged => self.slow.saturating_sub(last_sent.elapsed()),
+      _ => Duration::ZERO,
+    }
+  }
+
+  pub fn after_send(&mut self) {
+    self.last_sent = Some(Instant::now());
+  }
+}
+
+impl fmt::Display for RoomState {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let on_off = |on: bool| if on { "on" } else { "off" };
+    if self.slow.is_zero() {
+      write!(f, "slow: off")?;
+    } else {
+      write!(
+        f,
+        "slow: {}",
+        humantime_serde::re::humantime::format_duration(self.slow)
+      )?;
+    }
+    match self.followers_only {
+      Some(duration) if duration.is_zero() => write!(f, ", followers-only: on")?,
+      Some(duration) => write!(
+        f,
+        ", followers-only: {}",
+        humantime_serde::re::humantime::format_duration(duration)
+      )?,
+      None => write!(f, ", followers-only: off")?,
+    }
+    write!(
+      f,
+      ", emote-only: {}, subs-only: {}, privileged: {}",
+      on_off(self.emote_only),
+      on_off(self.subs_only),
+      if self.privileged { "yes" } else { "no" }
+    )
+  }
+}
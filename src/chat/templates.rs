@@ -0,0 +1,174 @@
+use anyhow::Result;
+use serde::Deserialize;
+
+/// Placeholders which may be used in any template.
+///
+/// Placeholders which don't apply to a message type are replaced with an empty string.
+pub const PLACEHOLDERS: &[&str] = &[
+  "user",
+  "channel",
+  "response",
+  "model_name",
+  "model_version",
+  "model_metadata",
+  "version",
+  "quote_user",
+  "quote_date",
+];
+
+/// Formats of all messages sent by the bot.
+///
+/// Placeholders are written as `{name}`, and `{{`/`}}` are used for literal braces.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Templates {
+  /// Reply to a message which mentions the bot
+  pub mention_reply: String,
+  /// Reply to a random message in chat
+  pub random_reply: String,
+  /// Response to the `version` command
+  pub version: String,
+  /// Response to the `model` command
+  pub model_info: String,
+  /// Response to the `?` command
+  pub phrase_info: String,
+  /// Response to the `promote-shadow` command
+  pub promote_shadow: String,
+  /// Response to the `status` command
+  pub status: String,
+  /// Response to the `quote` command
+  pub quote: String,
+  /// Response to the `quote-optout` command
+  pub quote_opt_out: String,
+  /// Response to the `quote-optin` command
+  pub quote_opt_in: String,
+  /// Response to the `join` command, `{response}` is the joined channel
+  pub join: String,
+  /// Response to the `part` command, `{response}` is the parted channel
+  pub part: String,
+  /// Response to a mention during the user's cooldown, `{response}` is the number of seconds left
+  pub cooldown: String,
+}
+
+impl Default for Templates {
+  fn default() -> Self {
+    Self {
+      mention_reply: "{response}".into(),
+      random_reply: "@{user} {response}".into(),
+      version: "SCS v{version}".into(),
+      model_info: "{model_name} (version: {model_version}; metadata: {model_metadata})".into(),
+      phrase_info: "{response}".into(),
+      promote_shadow: "Now using {model_name}".into(),
+      status: "{channel}: {response}".into(),
+      quote: "\"{response}\" - {quote_user}, {quote_date}".into(),
+      quote_opt_out: "@{user} Your messages won't be quoted anymore".into(),
+      quote_opt_in: "@{user} Your messages may be quoted again".into(),
+      join: "Joining #{response}".into(),
+      part: "Leaving #{response}".into(),
+      cooldown: "@{user} on cooldown ({response}s left)".into(),
+    }
+  }
+}
+
+impl Templates {
+  pub fn validate(&self) -> Result<()> {
+    for (name, template) in [
+      ("mention_reply", &self.mention_reply),
+      ("random_reply", &self.random_reply),
+      ("version", &self.version),
+      ("model_info", &self.model_info),
+      ("phrase_info", &self.phrase_info),
+      ("promote_shadow", &self.promote_shadow),
+      ("status", &self.status),
+      ("quote", &self.quote),
+      ("quote_opt_out", &self.quote_opt_out),
+      ("quote_opt_in", &self.quote_opt_in),
+      ("join", &self.join),
+      ("part", &self.part),
+      ("cooldown", &self.cooldown),
+    ] {
+      validate(name, template)?;
+    }
+    Ok(())
+  }
+}
+
+/// Checks that `template` only uses known placeholders, and that its braces are balanced
+pub fn validate(name: &str, template: &str) -> Result<()> {
+  parse(template, |_| Ok(())).map_err(|e| anyhow::anyhow!("Invalid template `{name}`: {e}"))
+}
+
+/// Values substituted into a template
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Vars<'a> {
+  pub user: &'a str,
+  pub channel: &'a str,
+  pub response: &'a str,
+  pub model_name: &'a str,
+  pub model_version: &'a str,
+  pub model_metadata: &'a str,
+  pub quote_user: &'a str,
+  pub quote_date: &'a str,
+}
+
+impl<'a> Vars<'a> {
+  fn get(&self, placeholder: &str) -> &'a str {
+    match placeholder {
+      "user" => self.user,
+      "channel" => self.channel,
+      "response" => self.response,
+      "model_name" => self.model_name,
+      "model_version" => self.model_version,
+      "model_metadata" => self.model_metadata,
+      "version" => env!("CARGO_PKG_VERSION"),
+      "quote_user" => self.quote_user,
+      "quote_date" => self.quote_date,
+      _ => "",
+    }
+  }
+}
+
+/// Fills in the placeholders in `template`. It must have been validated with [`Templates::validate`].
+pub fn render(template: &str, vars: &Vars<'_>) -> String {
+  let mut output = String::with_capacity(template.len() + vars.response.len());
+  // Validated templates can't fail
+  let _ = parse(template, |segment| {
+    match segment {
+      Segment::Text(text) => output.push_str(text),
+      Segment::Placeholder(name) => output.push_str(vars.get(name)),
+    }
+    Ok(())
+  });
+  output
+}
+
+enum Segment<'a> {
+  Text(&'a str),
+  Placeholder(&'a str),
+}
+
+fn parse<'a>(template: &'a str, mut visit: impl FnMut(Segment<'a>) -> Result<()>) -> Result<()> {
+  let mut rest = template;
+  while let Some(i) = rest.find(|c| c == '{' || c == '}') {
+    visit(Segment::Text(&rest[..i]))?;
+    let escaped = &rest[i..i + 1];
+    if rest[i + 1..].starts_with(escaped) {
+      visit(Segment::Text(escaped))?;
+      rest = &rest[i + 2..];
+      continue;
+    }
+    if escaped == "}" {
+      anyhow::bail!("unmatched `}}`, use `}}}}` for a literal brace");
+    }
+    let end = rest[i..]
+      .find('}')
+      .ok_or_else(|| anyhow::anyhow!("unclosed placeholder, use `{{{{` for a literal brace"))?;
+    let name = &rest[i + 1..i + end];
+    if !PLACEHOLDERS.contains(&name) {
+      anyhow::bail!("unknown placeholder `{{{name}}}`, expected one of {PLACEHOLDERS:?}");
+    }
+    visit(Segment::Placeholder(name))?;
+    rest = &rest[i + end + 1..];
+  }
+  visit(Segment::Text(rest))
+}
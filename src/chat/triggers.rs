@@ -0,0 +1,204 @@
+//! Triggers which make the bot reply to a message, such as its `@mention`, a nickname, or an emote.
+//!
+//! Triggers are configured per channel and compiled when the config is loaded. The words of the message other than
+//! the trigger seed the reply.
+
+use anyhow::Result;
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TriggerKind {
+  /// The message starts with the pattern, ignoring case
+  Prefix,
+  /// One of the words of the message is the pattern, ignoring case
+  Keyword,
+  /// The pattern is a regular expression which matches somewhere in the message
+  Regex,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawTrigger {
+  kind: TriggerKind,
+  pattern: String,
+  #[serde(default)]
+  priority: i32,
+}
+
+#[derive(Clone, Debug)]
+enum Matcher {
+  /// Lowercase prefix, and the number of words it spans
+  Prefix(String, usize),
+  /// Lowercase keyword
+  Keyword(String),
+  Regex(Regex),
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "RawTrigger")]
+pub struct Trigger {
+  matcher: Matcher,
+  /// Triggers with a higher priority are tried first
+  priority: i32,
+}
+
+impl Trigger {
+  pub fn new(kind: TriggerKind, pattern: &str, priority: i32) -> Result<Self> {
+    if pattern.trim().is_empty() {
+      anyhow::bail!("The pattern of a trigger can't be empty");
+    }
+    let matcher = match kind {
+      TriggerKind::Prefix => Matcher::Prefix(pattern.to_lowercase(), pattern.split_whitespace().count()),
+      TriggerKind::Keyword => {
+        if pattern.split_whitespace().count() != 1 {
+          anyhow::bail!("The keyword trigger `{pattern}` must be a single word");
+        }
+        Matcher::Keyword(pattern.to_lowercase())
+      }
+      TriggerKind::Regex => {
+        Matcher::Regex(Regex::new(pattern).map_err(|e| anyhow::anyhow!("Invalid trigger regex `{pattern}`: {e}"))?)
+      }
+    };
+    Ok(Self { matcher, priority })
+  }
+
+  /// Returns the words of `text` which seed the reply, if `text` contains the trigger
+  pub fn seed(&self, text: &str) -> Option<Vec<String>> {
+    match &self.matcher {
+      Matcher::Prefix(prefix, words) => text
+        .to_lowercase()
+        .starts_with(prefix.as_str())
+        .then(|| text.split_whitespace().skip(*words).map(String::from).collect()),
+      Matcher::Keyword(keyword) => {
+        let words = text.split_whitespace().collect::<Vec<_>>();
+        let position = words.iter().position(|word| word.to_lowercase() == *keyword)?;
+        Some(
+          words
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != position)
+            .map(|(_, word)| word.to_string())
+            .collect(),
+        )
+      }
+      Matcher::Regex(regex) => {
+        let found = regex.find(text)?;
+        Some(
+          text[..found.start()]
+            .split_whitespace()
+            .chain(text[found.end()..].split_whitespace())
+            .map(String::from)
+            .collect(),
+        )
+      }
+    }
+  }
+}
+
+impl TryFrom<RawTrigger> for Trigger {
+  type Error = anyhow::Error;
+
+  fn try_from(raw: RawTrigger) -> Result<Self> {
+    Self::new(raw.kind, &raw.pattern, raw.priority)
+  }
+}
+
+/// Triggers of the bot's replies
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TriggersConfig {
+  /// Triggers of the channels which aren't in `channels`. Defaults to the `@<login>` prefix.
+  pub default: Vec<Trigger>,
+  /// Triggers per channel, which replace `default`
+  pub channels: HashMap<String, Vec<Trigger>>,
+}
+
+impl TriggersConfig {
+  /// Fills in the default trigger, and orders the triggers by priority
+  pub fn prepare(&mut self, login: &str) -> Result<()> {
+    if self.default.is_empty() {
+      self
+        .default
+        .push(Trigger::new(TriggerKind::Prefix, &format!("@{login}"), 0)?);
+    }
+    self.channels = std::mem::take(&mut self.channels)
+      .into_iter()
+      .map(|(channel, triggers)| (channel.to_ascii_lowercase(), triggers))
+      .collect();
+    for triggers in std::iter::once(&mut self.default).chain(self.channels.values_mut()) {
+      // The sort is stable, so triggers with the same priority are tried in the configured order
+      triggers.sort_by_key(|trigger| std::cmp::Reverse(trigger.priority));
+    }
+    Ok(())
+  }
+
+  /// Returns the words which seed the reply to `text` in `channel`, if it contains one of the channel's triggers.
+  /// When several triggers match, the one with the highest priority is used.
+  pub fn seed(&self, channel: &str, text: &str) -> Option<Vec<String>> {
+    self
+      .channels
+      .get(channel)
+      .unwrap_or(&self.default)
+      .iter()
+      .find_map(|trigger| trigger.seed(text))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn config() -> TriggersConfig {
+    let mut config: TriggersConfig = serde_json::from_str(
+      r#"{
+        "channels": {
+          "Forsen": [
+            { "kind": "keyword", "pattern": "scsL" },
+            { "kind": "prefix", "pattern": "hey bot", "priority": 1 },
+            { "kind": "regex", "pattern": "^!s(ay)?\\b" }
+          ]
+        }
+      }"#,
+    )
+    .unwrap();
+    config.prepare("my_bot").unwrap();
+    config
+  }
+
+  fn seed(config: &TriggersConfig, channel: &str, text: &str) -> Option<String> {
+    config.seed(channel, text).map(|words| words.join(" "))
+  }
+
+  #[test]
+  fn test_default_mention() {
+    let config = config();
+    assert_eq!(
+      seed(&config, "other", "@My_Bot hello there").as_deref(),
+      Some("hello there")
+    );
+    assert_eq!(seed(&config, "other", "@my_bot, hi").as_deref(), Some("hi"));
+    assert_eq!(seed(&config, "other", "hello @my_bot"), None);
+    // channels with their own triggers don't use the default one
+    assert_eq!(seed(&config, "forsen", "@my_bot hello"), None);
+  }
+
+  #[test]
+  fn test_channel_triggers() {
+    let config = config();
+    assert_eq!(seed(&config, "forsen", "what SCSL is").as_deref(), Some("what is"));
+    assert_eq!(seed(&config, "forsen", "!say hello").as_deref(), Some("hello"));
+    assert_eq!(seed(&config, "forsen", "!sing hello"), None);
+    // the prefix has the highest priority, so the keyword is part of the seed
+    assert_eq!(seed(&config, "forsen", "Hey bot scsL hi").as_deref(), Some("scsL hi"));
+  }
+
+  #[test]
+  fn test_invalid_triggers() {
+    assert!(Trigger::new(TriggerKind::Keyword, "two words", 0).is_err());
+    assert!(Trigger::new(TriggerKind::Prefix, " ", 0).is_err());
+    assert!(Trigger::new(TriggerKind::Regex, "(", 0).is_err());
+  }
+}
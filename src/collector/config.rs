@@ -1,15 +1,75 @@
 use anyhow::Result;
 use serde::Deserialize;
-use std::fs;
-use std::path::{Path, PathBuf};
+use std::{
+  collections::{BTreeMap, HashSet},
+  path::{Component, Path, PathBuf},
+};
+
+use crate::{middleware, summary, viewers};
 
 const DEFAULT_OUTPUT_DIRECTORY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "\\logs");
-const DEFAULT_BUF_SIZE: usize = 1024; // 1 KiB
+pub const DEFAULT_BUF_SIZE: usize = 1024; // 1 KiB
 
 fn default_output_directory() -> std::path::PathBuf {
   std::path::PathBuf::from(DEFAULT_OUTPUT_DIRECTORY)
 }
 
+fn default_server() -> String {
+  String::from("wss://irc-ws.chat.twitch.tv:443")
+}
+
+/// Bounds and adjustment interval of automatically sized buffers
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AutoBuffer {
+  /// Minimum buffer size, in bytes
+  pub min: usize,
+  /// Maximum buffer size, in bytes
+  pub max: usize,
+  /// How often buffers are resized, in seconds.
+  /// Each buffer is sized to hold about as much data as its channel receives during this interval.
+  pub interval: u64,
+}
+
+impl Default for AutoBuffer {
+  fn default() -> Self {
+    Self {
+      min: DEFAULT_BUF_SIZE,
+      max: 1024 * 1024, // 1 MiB
+      interval: 60,
+    }
+  }
+}
+
+/// What happens once a sink keeps failing and more than `max_pending` records are waiting to be written
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Backpressure {
+  pub max_pending: usize,
+  pub policy: BackpressurePolicy,
+  /// How long the `block` policy retries the writes before moving on to the next batch, in milliseconds
+  pub max_wait_ms: u64,
+}
+
+impl Default for Backpressure {
+  fn default() -> Self {
+    Self {
+      max_pending: 100_000,
+      policy: BackpressurePolicy::Block,
+      max_wait_ms: 1000,
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackpressurePolicy {
+  /// Retry the writes, which holds up receiving messages for up to `max_wait_ms`. The records are kept afterwards.
+  Block,
+  /// Append the oldest records over the limit to `spill/YYYY-MM-DD.spill` in the output directory
+  Spill,
+}
+
 // We only want `Buffered`, but the user should be able to write
 // just the name, without having to specify the buffer size.
 // We also don't want this distinction when using the channel list,
@@ -17,87 +77,274 @@ fn default_output_directory() -> std::path::PathBuf {
 // the deserialization into two steps.
 
 #[derive(Deserialize)]
-#[serde(untagged)]
+#[serde(untagged, deny_unknown_fields)]
 pub enum TempChannel {
   NameOnly(String),
   Buffered { name: String, buffer: usize },
 }
 
 #[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 struct TempConfig {
+  #[serde(default)]
   channels: Vec<TempChannel>,
   #[serde(default = "default_output_directory")]
   output_directory: PathBuf,
   credentials: Option<TwitchLogin>,
+  #[serde(default = "Vec::new")]
+  middleware: Vec<middleware::Builtin>,
+  #[serde(default = "default_server")]
+  server: String,
+  summary_webhook: Option<summary::Webhook>,
+  auto_buffer: Option<AutoBuffer>,
+  #[serde(default)]
+  connection: twitch_api::ConnectOptions,
+  viewer_snapshots: Option<viewers::ViewerSnapshots>,
+  status_address: Option<std::net::SocketAddr>,
+  #[serde(default)]
+  backpressure: Backpressure,
+  #[serde(default)]
+  coalesce_ms: u64,
+  #[serde(default)]
+  auto_remove_quarantined: bool,
+  #[serde(default)]
+  sinks: scs_config::SinksConfig,
+  #[serde(default)]
+  tenants: BTreeMap<String, TempTenant>,
+}
+
+/// Channels collected separately from the other tenants, with their own connection, sinks and output directory.
+/// Optional fields default to the top-level ones.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TempTenant {
+  channels: Vec<TempChannel>,
+  /// Relative to the top-level `output_directory`, defaults to the tenant's name
+  output_subdirectory: Option<PathBuf>,
+  credentials: Option<TwitchLogin>,
+  middleware: Option<Vec<middleware::Builtin>>,
+  summary_webhook: Option<summary::Webhook>,
+  auto_buffer: Option<AutoBuffer>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct TwitchLogin {
   pub login: String,
   pub token: String,
 }
 
+#[derive(Clone, Copy, Debug)]
+pub enum Buffer {
+  Fixed(usize),
+  Auto(AutoBuffer),
+}
+
 #[derive(Clone, Debug)]
 pub struct Channel {
   pub name: String,
-  pub buffer: usize,
+  pub buffer: Buffer,
 }
 
-impl From<TempChannel> for Channel {
-  fn from(c: TempChannel) -> Self {
+impl Channel {
+  /// Channels without an explicit buffer size are automatically sized if `auto_buffer` is set
+  fn new(c: TempChannel, auto_buffer: Option<AutoBuffer>) -> Self {
     match c {
       TempChannel::NameOnly(name) => Self {
         name,
-        buffer: DEFAULT_BUF_SIZE,
+        buffer: auto_buffer.map_or(Buffer::Fixed(DEFAULT_BUF_SIZE), Buffer::Auto),
+      },
+      TempChannel::Buffered { name, buffer } => Self {
+        name,
+        buffer: Buffer::Fixed(buffer),
       },
-      TempChannel::Buffered { name, buffer } => Self { name, buffer },
     }
   }
 }
 
 #[derive(Clone, Debug)]
 pub struct Config {
+  /// Name of the tenant this config belongs to, or `None` if the config file doesn't have tenants
+  pub tenant: Option<String>,
   pub channels: Vec<Channel>,
   pub output_directory: PathBuf,
   pub credentials: Option<TwitchLogin>,
+  pub middleware: Vec<middleware::Builtin>,
+  /// Websocket URI of the IRC server
+  pub server: String,
+  /// Where to post the daily summary, in addition to logging it
+  pub summary_webhook: Option<summary::Webhook>,
+  /// Proxy and CA certificates used to connect to `server`
+  pub connection: twitch_api::ConnectOptions,
+  /// Where to get the viewer counts of the channels from, if they should be recorded
+  pub viewer_snapshots: Option<viewers::ViewerSnapshots>,
+  /// Address to serve the collector's status on, see [`crate::status`]
+  pub status_address: Option<std::net::SocketAddr>,
+  pub backpressure: Backpressure,
+  /// Shortest interval between two writes to the sinks, in milliseconds. `0` writes every batch as it's received.
+  pub coalesce_ms: u64,
+  /// Whether quarantined channels are left until the next reload, instead of being probed, see [`twitch_api::Membership`]
+  pub auto_remove_quarantined: bool,
+  /// How often the file sinks start a new log file
+  pub rotation: scs_config::sinks::Rotation,
 }
 
-impl From<TempConfig> for Config {
-  fn from(c: TempConfig) -> Self {
+/// Checks that `sinks` only configures a filesystem sink, which is the only one the collector can write to, and
+/// returns its directory and rotation if it's set
+fn filesystem_sink(sinks: &scs_config::SinksConfig) -> Result<Option<(PathBuf, scs_config::sinks::Rotation)>> {
+  sinks.validate("config.sinks")?;
+  if let Some(name) = sinks.configured().into_iter().find(|name| *name != "filesystem") {
+    anyhow::bail!("config.sinks.{name} isn't supported, the collector only writes to the filesystem");
+  }
+  let Some(filesystem) = &sinks.filesystem else {
+    return Ok(None);
+  };
+  if filesystem.compression != scs_config::sinks::Compression::None {
+    anyhow::bail!("config.sinks.filesystem.compression isn't supported by the collector");
+  }
+  Ok(Some((filesystem.directory.clone(), filesystem.rotation)))
+}
+
+impl TempConfig {
+  /// Splits the config into one config per tenant, or a single one if there are no tenants
+  fn into_configs(self) -> Result<Vec<Config>> {
     let TempConfig {
       channels,
       output_directory,
       credentials,
-    } = c;
-    Self {
-      channels: channels.into_iter().map(Channel::from).collect(),
-      output_directory,
-      credentials,
+      middleware,
+      server,
+      summary_webhook,
+      auto_buffer,
+      connection,
+      viewer_snapshots,
+      status_address,
+      backpressure,
+      coalesce_ms,
+      auto_remove_quarantined,
+      sinks,
+      tenants,
+    } = self;
+    let connection = connection.or_env();
+    // `sinks.filesystem.directory` replaces `output_directory`
+    let (output_directory, rotation) = match filesystem_sink(&sinks)? {
+      Some(filesystem) => filesystem,
+      None => (output_directory, Default::default()),
+    };
+
+    if tenants.is_empty() {
+      return Ok(vec![Config {
+        tenant: None,
+        channels: channels.into_iter().map(|c| Channel::new(c, auto_buffer)).collect(),
+        output_directory,
+        credentials,
+        middleware,
+        server,
+        summary_webhook,
+        connection,
+        viewer_snapshots,
+        status_address,
+        backpressure,
+        coalesce_ms,
+        auto_remove_quarantined,
+        rotation,
+      }]);
     }
+
+    if !channels.is_empty() {
+      anyhow::bail!("config.channels must be empty if config.tenants is set, each tenant lists its own channels");
+    }
+    tenants
+      .into_iter()
+      .map(|(name, tenant)| {
+        let subdirectory = tenant.output_subdirectory.unwrap_or_else(|| PathBuf::from(&name));
+        // Keeps each tenant's logs inside the shared output directory
+        if !subdirectory.components().all(|c| matches!(c, Component::Normal(_))) {
+          anyhow::bail!("config.tenants.{name}.output_subdirectory must be a relative path without `..`");
+        }
+        let auto_buffer = tenant.auto_buffer.or(auto_buffer);
+        Ok(Config {
+          channels: tenant
+            .channels
+            .into_iter()
+            .map(|c| Channel::new(c, auto_buffer))
+            .collect(),
+          output_directory: output_directory.join(subdirectory),
+          credentials: tenant.credentials.or_else(|| credentials.clone()),
+          middleware: tenant.middleware.unwrap_or_else(|| middleware.clone()),
+          server: server.clone(),
+          summary_webhook: tenant.summary_webhook.or_else(|| summary_webhook.clone()),
+          connection: connection.clone(),
+          viewer_snapshots: viewer_snapshots.clone(),
+          status_address,
+          backpressure: backpressure.clone(),
+          coalesce_ms,
+          auto_remove_quarantined,
+          rotation,
+          tenant: Some(name),
+        })
+      })
+      .collect()
   }
 }
 
 impl Config {
-  pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
-    let content = fs::read_to_string(path)?;
-    let config = Config::from(serde_json::from_str::<TempConfig>(&content)?);
+  /// Loads the config at `path`, with one entry per tenant, or a single one if the file doesn't have tenants
+  pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<Self>> {
+    let configs = scs_config::read::<TempConfig>(path)?.into_configs()?;
+
+    let mut directories = HashSet::new();
+    for config in &configs {
+      config.validate()?;
+      if !directories.insert(&config.output_directory) {
+        anyhow::bail!(
+          "{} is the output directory of more than one tenant",
+          config.output_directory.display()
+        );
+      }
+    }
+
+    Ok(configs)
+  }
+
+  /// Prefix of the config's fields in errors, e.g. `config.tenants.<name>`
+  fn path(&self) -> String {
+    match &self.tenant {
+      Some(tenant) => format!("config.tenants.{tenant}"),
+      None => String::from("config"),
+    }
+  }
 
-    if config.channels.is_empty() {
-      log::error!("config.channels is empty, exiting.");
+  fn validate(&self) -> Result<()> {
+    let path = self.path();
+    if self.channels.is_empty() {
+      log::error!("{path}.channels is empty, exiting.");
       anyhow::bail!("No channels specified");
     }
 
-    if !config.output_directory.exists() {
-      log::warn!("config.output_directory does not exist, it will be created.");
-      std::fs::create_dir_all(&config.output_directory)?;
+    if !self.output_directory.exists() {
+      log::warn!("{path}.output_directory does not exist, it will be created.");
+      std::fs::create_dir_all(&self.output_directory)?;
+    }
+
+    if !self.output_directory.is_dir() {
+      log::error!("{path}.output_directory is not a directory.");
+      anyhow::bail!(format!("{} is not a directory", self.output_directory.display()));
+    }
+
+    if self.backpressure.max_pending == 0 {
+      anyhow::bail!("{path}.backpressure.max_pending must be positive");
     }
 
-    if !config.output_directory.is_dir() {
-      log::error!("config.output_directory is not a directory.");
-      anyhow::bail!(format!("{} is not a directory", config.output_directory.display()));
+    for channel in &self.channels {
+      if let Buffer::Auto(auto) = channel.buffer {
+        if auto.min == 0 || auto.min > auto.max || auto.interval == 0 {
+          anyhow::bail!("{path}.auto_buffer must have 0 < min <= max, and a non-zero interval");
+        }
+      }
     }
 
-    Ok(config)
+    Ok(())
   }
 }
 
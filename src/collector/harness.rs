@@ -0,0 +1,388 @@
+//! End-to-end tests for the collector.
+//!
+//! A fake IRC server sends a known sequence of messages to the collector,
+//! and the tests assert that every one of them reaches the sinks exactly once.
+
+use std::{
+  collections::HashMap,
+  io::{self, Write},
+  path::PathBuf,
+  sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+  },
+};
+
+use futures::{SinkExt, StreamExt};
+use tokio::{net::TcpListener, sync::oneshot};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::{
+  config::{Backpressure, BackpressurePolicy, Buffer, Channel, Config},
+  middleware::Middleware,
+  sink::{RawLogRecord, SinkManager},
+  summary::SummarySink,
+};
+
+/// In-memory sink which fails the next `failures` writes
+#[derive(Clone, Default)]
+struct MemorySink {
+  data: Arc<Mutex<Vec<u8>>>,
+  failures: Arc<AtomicUsize>,
+  /// Number of successful writes
+  writes: Arc<AtomicUsize>,
+}
+
+impl MemorySink {
+  fn failing(failures: usize) -> Self {
+    Self {
+      failures: Arc::new(AtomicUsize::new(failures)),
+      ..Default::default()
+    }
+  }
+
+  /// Written lines, without their sequence numbers
+  fn lines(&self) -> Vec<String> {
+    self
+      .raw_lines()
+      .iter()
+      .map(|line| match line.split_once(',') {
+        Some((chatter, text)) => format!("{},{text}", chatter.split('#').next().unwrap()),
+        None => line.clone(),
+      })
+      .collect()
+  }
+
+  fn sequences(&self) -> Vec<u64> {
+    self
+      .raw_lines()
+      .iter()
+      .filter_map(|line| line.split_once(',')?.0.split_once('#')?.1.parse().ok())
+      .collect()
+  }
+
+  fn raw_lines(&self) -> Vec<String> {
+    String::from_utf8(self.data.lock().unwrap().clone())
+      .unwrap()
+      .lines()
+      .map(String::from)
+      .collect()
+  }
+}
+
+impl Write for MemorySink {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    if self
+      .failures
+      .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+      .is_ok()
+    {
+      return Err(io::Error::new(io::ErrorKind::Other, "injected failure"));
+    }
+    self.data.lock().unwrap().extend_from_slice(buf);
+    self.writes.fetch_add(1, Ordering::SeqCst);
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+fn privmsg(channel: &str, chatter: &str, text: &str) -> String {
+  format!(":{chatter}!{chatter}@{chatter}.tmi.twitch.tv PRIVMSG #{channel} :{text}\r\n")
+}
+
+/// Accepts a single connection, waits for the collector to join, and sends each of `batches` as one websocket message.
+///
+/// `done` is notified once the collector has processed every batch.
+async fn serve(listener: TcpListener, batches: Vec<String>, done: oneshot::Sender<()>) {
+  let (stream, _) = listener.accept().await.unwrap();
+  let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+
+  while let Some(Ok(message)) = ws.next().await {
+    if matches!(message, Message::Text(ref text) if text.starts_with("JOIN")) {
+      break;
+    }
+  }
+
+  for batch in batches {
+    ws.send(Message::Text(batch)).await.unwrap();
+  }
+
+  // Batches are processed in order, so receiving the PONG means that every batch before the PING was handled.
+  ws.send(Message::Text("PING :tmi.twitch.tv\r\n".into())).await.unwrap();
+  while let Some(Ok(message)) = ws.next().await {
+    if matches!(message, Message::Text(ref text) if text.starts_with("PONG")) {
+      break;
+    }
+  }
+  done.send(()).unwrap();
+
+  // Keep the connection open until the collector disconnects
+  while let Some(Ok(_)) = ws.next().await {}
+}
+
+/// Runs the collector against a fake server which sends `batches`, and returns the result of the run
+async fn collect(sinks: &HashMap<&str, MemorySink>, batches: Vec<String>) -> anyhow::Result<()> {
+  collect_with(sinks, batches, &Backpressure::default(), None).await
+}
+
+/// Like [`collect`], spilling records to `spill_directory` if the policy is [`BackpressurePolicy::Spill`]
+async fn collect_with(
+  sinks: &HashMap<&str, MemorySink>,
+  batches: Vec<String>,
+  backpressure: &Backpressure,
+  spill_directory: Option<PathBuf>,
+) -> anyhow::Result<()> {
+  let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+  let address = listener.local_addr().unwrap();
+  let (done_tx, done_rx) = oneshot::channel();
+  let server = tokio::spawn(serve(listener, batches, done_tx));
+
+  let config = Config {
+    tenant: None,
+    channels: sinks
+      .keys()
+      .map(|name| Channel {
+        name: name.to_string(),
+        buffer: Buffer::Fixed(1024),
+      })
+      .collect(),
+    output_directory: std::env::temp_dir(),
+    credentials: None,
+    middleware: vec![],
+    server: format!("ws://{address}"),
+    summary_webhook: None,
+    connection: Default::default(),
+    viewer_snapshots: None,
+    status_address: None,
+    backpressure: backpressure.clone(),
+    coalesce_ms: 0,
+    auto_remove_quarantined: false,
+    rotation: Default::default(),
+  };
+  let mut manager = SinkManager::with_sinks(
+    sinks
+      .iter()
+      .map(|(name, sink)| (name.to_string(), Box::new(sink.clone()) as Box<dyn Write + Send>))
+      .collect(),
+    Middleware::default(),
+    SummarySink::new(None),
+  )
+  .with_backpressure(backpressure.clone(), spill_directory);
+
+  // Keep the sender alive, so that the collector keeps waiting for reloads
+  let (_reload_tx, reload_rx) = tokio::sync::mpsc::unbounded_channel();
+  let result = crate::run(
+    config,
+    &mut manager,
+    &Default::default(),
+    async {
+      let _ = done_rx.await;
+    },
+    reload_rx,
+  )
+  .await;
+  drop(manager);
+  server.await.unwrap();
+  result
+}
+
+fn numbered(channel: &str, range: std::ops::Range<usize>) -> String {
+  range
+    .map(|i| privmsg(channel, "chatter", &format!("message {i}")))
+    .collect()
+}
+
+fn expected(range: std::ops::Range<usize>) -> Vec<String> {
+  range.map(|i| format!("chatter,message {i}")).collect()
+}
+
+#[tokio::test]
+async fn delivers_every_message() {
+  let sinks = HashMap::from([("a", MemorySink::default()), ("b", MemorySink::default())]);
+  let batches = vec![
+    numbered("a", 0..10),
+    numbered("b", 0..5) + &numbered("a", 10..20),
+    privmsg("a", "other", "hello, world"),
+    numbered("b", 5..10),
+  ];
+
+  collect(&sinks, batches).await.unwrap();
+
+  let mut a = expected(0..20);
+  a.push("other,hello, world".into());
+  assert_eq!(sinks["a"].lines(), a);
+  assert_eq!(sinks["b"].lines(), expected(0..10));
+  // every message is numbered, consecutively within its channel
+  for (channel, count) in [("a", 21), ("b", 10)] {
+    let sequences = sinks[channel].sequences();
+    assert_eq!(sequences.len(), count);
+    assert!(sequences.windows(2).all(|pair| pair[1] == pair[0] + 1));
+  }
+}
+
+#[tokio::test]
+async fn retries_after_sink_failures() {
+  let sinks = HashMap::from([("a", MemorySink::failing(3)), ("b", MemorySink::default())]);
+  let batches = vec![
+    numbered("a", 0..2),
+    numbered("b", 0..2),
+    numbered("a", 2..4),
+    numbered("b", 2..4),
+  ];
+
+  collect(&sinks, batches).await.unwrap();
+
+  assert_eq!(sinks["a"].lines(), expected(0..4));
+  assert_eq!(sinks["b"].lines(), expected(0..4));
+}
+
+#[tokio::test]
+async fn reports_persistent_sink_failures_on_shutdown() {
+  let sinks = HashMap::from([("a", MemorySink::failing(usize::MAX))]);
+
+  assert!(collect(&sinks, vec![numbered("a", 0..2)]).await.is_err());
+  assert!(sinks["a"].lines().is_empty());
+}
+
+#[tokio::test]
+async fn failing_sinks_dont_hold_back_other_channels() {
+  for coalesce in [None, Some(std::time::Duration::ZERO)] {
+    let sinks = HashMap::from([("a", MemorySink::failing(usize::MAX)), ("b", MemorySink::default())]);
+    let mut manager = SinkManager::with_sinks(
+      sinks
+        .iter()
+        .map(|(name, sink)| (name.to_string(), Box::new(sink.clone()) as Box<dyn Write + Send>))
+        .collect(),
+      Middleware::default(),
+      SummarySink::new(None),
+    )
+    .with_coalescing(coalesce);
+    let record = |channel: &str, i: usize| RawLogRecord {
+      channel: channel.into(),
+      chatter: "chatter".into(),
+      text: format!("message {i}"),
+      language: None,
+      seq: None,
+    };
+
+    manager.write_batch(vec![record("a", 0), record("b", 0)]).await;
+    manager.write_batch(vec![record("a", 1), record("b", 1)]).await;
+    assert_eq!(sinks["b"].lines(), expected(0..2));
+    assert_eq!(manager.stats().pending, 2);
+
+    sinks["a"].failures.store(0, Ordering::SeqCst);
+    manager.flush().unwrap();
+    assert_eq!(sinks["a"].lines(), expected(0..2));
+    assert_eq!(manager.stats().pending, 0);
+  }
+}
+
+#[tokio::test]
+async fn spills_records_over_the_limit() {
+  let dir = std::env::temp_dir().join(format!("scs-collector-spill-{}", std::process::id()));
+  std::fs::create_dir_all(&dir).unwrap();
+  let sinks = HashMap::from([("a", MemorySink::failing(usize::MAX))]);
+  let backpressure = Backpressure {
+    max_pending: 3,
+    policy: BackpressurePolicy::Spill,
+    ..Default::default()
+  };
+
+  let result = collect_with(
+    &sinks,
+    vec![numbered("a", 0..5), numbered("a", 5..7)],
+    &backpressure,
+    Some(dir.clone()),
+  )
+  .await;
+  let files = std::fs::read_dir(&dir)
+    .unwrap()
+    .map(|entry| entry.unwrap().path())
+    .collect::<Vec<_>>();
+  let spilled = files
+    .iter()
+    .map(|path| std::fs::read_to_string(path).unwrap())
+    .collect::<String>();
+  std::fs::remove_dir_all(&dir).unwrap();
+
+  // The 3 most recent records are still pending when the collector stops
+  assert!(result.is_err());
+  // spill files aren't picked up as channel logs
+  assert!(files
+    .iter()
+    .all(|path| path.extension() == Some(std::ffi::OsStr::new("spill"))));
+  assert_eq!(
+    spilled.lines().collect::<Vec<_>>(),
+    (0..4).map(|i| format!("a,chatter,message {i}")).collect::<Vec<_>>()
+  );
+}
+
+#[tokio::test]
+async fn coalesces_batches_per_channel() {
+  let sinks = HashMap::from([("a", MemorySink::default()), ("b", MemorySink::default())]);
+  let mut manager = SinkManager::with_sinks(
+    sinks
+      .iter()
+      .map(|(name, sink)| (name.to_string(), Box::new(sink.clone()) as Box<dyn Write + Send>))
+      .collect(),
+    Middleware::default(),
+    SummarySink::new(None),
+  )
+  .with_coalescing(Some(std::time::Duration::from_secs(3600)));
+  let record = |channel: &str, i: usize| RawLogRecord {
+    channel: channel.into(),
+    chatter: "chatter".into(),
+    text: format!("message {i}"),
+    language: None,
+    seq: None,
+  };
+
+  // The first batch is written right away, the next ones wait for the interval or a flush
+  manager.write_batch(vec![record("a", 0)]).await;
+  assert_eq!(sinks["a"].lines(), expected(0..1));
+  manager.write_batch(vec![record("a", 1), record("b", 0)]).await;
+  manager.write_batch(vec![record("b", 1), record("a", 2)]).await;
+  assert_eq!(sinks["a"].lines(), expected(0..1));
+  assert!(sinks["b"].lines().is_empty());
+
+  manager.flush().unwrap();
+  assert_eq!(sinks["a"].lines(), expected(0..3));
+  assert_eq!(sinks["b"].lines(), expected(0..2));
+  assert_eq!(sinks["a"].writes.load(Ordering::SeqCst), 2);
+  assert_eq!(sinks["b"].writes.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn writes_held_batches_once_the_interval_has_passed() {
+  let sink = MemorySink::default();
+  let interval = std::time::Duration::from_millis(200);
+  let mut manager = SinkManager::with_sinks(
+    HashMap::from([("a".to_string(), Box::new(sink.clone()) as Box<dyn Write + Send>)]),
+    Middleware::default(),
+    SummarySink::new(None),
+  )
+  .with_coalescing(Some(interval));
+  let record = |i: usize| RawLogRecord {
+    channel: "a".into(),
+    chatter: "chatter".into(),
+    text: format!("message {i}"),
+    language: None,
+    seq: None,
+  };
+
+  manager.write_batch(vec![record(0)]).await;
+  assert_eq!(manager.held_until(), None);
+  manager.write_batch(vec![record(1)]).await;
+  let held_until = manager.held_until().unwrap();
+
+  manager.write_held().await;
+  assert_eq!(sink.lines(), expected(0..1));
+
+  tokio::time::sleep_until(held_until.into()).await;
+  manager.write_held().await;
+  assert_eq!(sink.lines(), expected(0..2));
+  assert_eq!(manager.held_until(), None);
+}
@@ -1,8 +1,10 @@
-use std::collections::HashMap;
-use std::env;
-use std::io::Write;
+use std::{
+  collections::{HashMap, HashSet},
+  env,
+  future::Future,
+};
 
-use anyhow::Result;
+use anyhow::{Context as _, Result};
 use tokio_tungstenite::tungstenite::Message;
 use twitch::Command;
 
@@ -10,61 +12,75 @@ use config::Config;
 use twitch_api::SuggestedAction;
 
 pub mod config;
+#[cfg(test)]
+mod harness;
+pub mod middleware;
+mod selftest;
+#[cfg(target_family = "windows")]
+mod service;
+mod signal;
+mod simulate;
 pub mod sink;
+pub mod status;
+pub mod summary;
+pub mod viewers;
 
-use sink::DailyLogSink;
+use signal::{reload_signal, stop_signal};
+use sink::{RawLogRecord, Sequences, SinkManager};
+use status::Status;
 // TODO: handle TMI restarts + disconnections with retry
 
-#[cfg(target_family = "windows")]
-use tokio::signal::ctrl_c as stop_signal;
-
-#[cfg(target_family = "unix")]
-async fn stop_signal() -> std::io::Result<()> {
-  let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?; // SIGTERM for docker-compose down
-  let mut sigint = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::interrupt())?; // SIGINT for ctrl-c
-
-  let sigterm = sigterm.recv();
-  let sigint = sigint.recv();
-
-  tokio::select! {
-    _ = sigterm => Ok(()),
-    _ = sigint => Ok(()),
-  }
-}
+/// Runs the collector until `stop` resolves, then flushes `sinks`.
+///
+/// Configs received from `reloads` are applied without reconnecting, see [`reload`].
+async fn run(
+  mut config: Config,
+  sinks: &mut SinkManager,
+  status: &Status,
+  stop: impl Future<Output = ()>,
+  mut reloads: tokio::sync::mpsc::UnboundedReceiver<Config>,
+) -> Result<()> {
+  tokio::pin!(stop);
+  // Kept across reconnects, so that missed messages show up as gaps
+  let mut sequences = Sequences::new();
+  log::info!("Connecting to Twitch");
+  let mut conn = twitch_api::TwitchStream::with_options(config.server.clone(), config.connection.clone()).await?;
+  let creds = twitch_api::Credentials::from(&config);
+  let mut channel_names = config.channels.iter().map(|c| c.name.clone()).collect::<Vec<_>>();
+  conn.authenticate(&creds).await?;
+  conn.schedule_joins(&channel_names);
 
-async fn run(config: Config) -> Result<()> {
   'stop: loop {
-    log::info!("Connecting to Twitch");
-    let mut conn = twitch_api::TwitchStream::new().await?;
-    let creds = twitch_api::Credentials::from(&config);
-    let channel_names = config.channels.iter().map(|c| c.name.clone()).collect::<Vec<_>>();
-
-    // one sink per channel
-    let mut sinks = HashMap::<String, sink::DailyLogSink>::with_capacity(config.channels.len());
-    for channel in config.channels.iter() {
-      log::info!("Initializing sink for {}", channel.name);
-      sinks.insert(
-        channel.name.clone(),
-        sink::DailyLogSink::new(config.output_directory.clone(), channel.name.clone(), channel.buffer)?,
-      );
-    }
-
-    conn.authenticate(&creds).await?;
-    conn.schedule_joins(&channel_names);
+    status.connected(&conn);
 
     log::info!("Entering main loop.");
     loop {
+      // Messages held back by coalescing are written once the interval has passed, even if no message follows
+      let held_until = sinks.held_until();
+      let write_held = tokio::time::sleep_until(held_until.unwrap_or_else(std::time::Instant::now).into());
       let error = tokio::select! {
-          _ = stop_signal() => {
+          _ = &mut stop => {
             log::info!("Process terminated");
-            for sink in sinks.values_mut() {
-              sink.flush()?;
-            }
             break 'stop;
           },
+          _ = write_held, if held_until.is_some() => {
+            sinks.write_held().await;
+            status.sinks(sinks.stats());
+            Ok(())
+          },
+          Some(new_config) = reloads.recv() => {
+            reload(&mut conn, &mut config, &mut channel_names, sinks, new_config).await
+          },
           result = conn.receive() => match result {
             Ok(Some(message)) => if let Message::Text(batch) = message {
-              handle_messages(&mut conn, &creds, &channel_names, &mut sinks, batch).await
+              let mut result = handle_messages(&mut conn, &creds, &channel_names, sinks, &mut sequences, batch).await;
+              if config.auto_remove_quarantined && result.is_ok() {
+                result = remove_quarantined(&mut conn, &mut channel_names).await;
+              }
+              status.received(&conn);
+              status.sinks(sinks.stats());
+              status.scrubbed(sinks.scrub_stats());
+              result
             } else {
               Ok(())
             },
@@ -84,19 +100,204 @@ async fn run(config: Config) -> Result<()> {
       }
     }
 
-    for sink in sinks.values_mut() {
-      sink.flush()?;
+    status.disconnected();
+    sinks.flush()?;
+
+    // Waits for a backoff which keeps increasing while the connection keeps dropping, see `TwitchStream::reconnect`
+    tokio::select! {
+      _ = &mut stop => {
+        log::info!("Process terminated");
+        break 'stop;
+      },
+      result = conn.reconnect(&creds, &channel_names) => result?,
     }
   }
 
+  status.disconnected();
+  sinks.flush()?;
   Ok(())
 }
 
+/// Applies `new_config` to the running collector: joins and leaves channels, and replaces the sinks and middleware.
+///
+/// The connection settings and the summary webhook are kept, since they can't be changed without reconnecting.
+async fn reload(
+  conn: &mut twitch_api::TwitchStream,
+  config: &mut Config,
+  channel_names: &mut Vec<String>,
+  sinks: &mut SinkManager,
+  new_config: Config,
+) -> std::result::Result<(), twitch_api::WsError> {
+  let new_config = Config {
+    credentials: config.credentials.clone(),
+    server: config.server.clone(),
+    connection: config.connection.clone(),
+    summary_webhook: config.summary_webhook.clone(),
+    viewer_snapshots: config.viewer_snapshots.clone(),
+    status_address: config.status_address,
+    ..new_config
+  };
+  if let Err(e) = sinks.reconfigure(&new_config, middleware::Middleware::from_config(&new_config.middleware)) {
+    log::error!("Failed to reload the config, keeping the previous one: {}", e);
+    return Ok(());
+  }
+
+  let new_names = new_config.channels.iter().map(|c| c.name.clone()).collect::<Vec<_>>();
+  let (old, new) = (
+    channel_names.iter().collect::<HashSet<_>>(),
+    new_names.iter().collect::<HashSet<_>>(),
+  );
+  let removed = old.difference(&new).map(|c| c.to_string()).collect::<Vec<_>>();
+  let added = new.difference(&old).map(|c| c.to_string()).collect::<Vec<_>>();
+  log::info!(
+    "Reloaded the config: {} channel(s) added, {} removed",
+    added.len(),
+    removed.len()
+  );
+
+  *channel_names = new_names;
+  *config = new_config;
+  if !removed.is_empty() {
+    conn.part(&removed).await?;
+  }
+  if !added.is_empty() {
+    conn.schedule_joins(&added);
+  }
+  Ok(())
+}
+
+/// Leaves the channels which were quarantined since the last call, until they're added back by a reload
+async fn remove_quarantined(
+  conn: &mut twitch_api::TwitchStream,
+  channel_names: &mut Vec<String>,
+) -> std::result::Result<(), twitch_api::WsError> {
+  let quarantined = conn.take_quarantined();
+  if quarantined.is_empty() {
+    return Ok(());
+  }
+  log::warn!(
+    "Removing quarantined channel(s) until the config is reloaded: {}",
+    quarantined.join(", ")
+  );
+  channel_names.retain(|c| !quarantined.contains(c));
+  conn.part(&quarantined).await
+}
+
+fn channel_names(config: &Config) -> Vec<String> {
+  config.channels.iter().map(|c| c.name.clone()).collect()
+}
+
+fn tenant_name(tenant: &Option<String>) -> &str {
+  tenant.as_deref().unwrap_or("<default>")
+}
+
+/// Runs a collector for each tenant, with one file sink per channel, until the process is asked to stop.
+/// Once any of them stops, e.g. because its credentials were rejected, the others are stopped as well.
+///
+/// The config is reloaded from `config_path` on SIGHUP. Tenants are only added or removed on restart.
+async fn start(configs: Vec<Config>, config_path: Option<String>) -> Result<()> {
+  let (stop_tx, stop_rx) = tokio::sync::watch::channel(false);
+  tokio::spawn({
+    let stop_tx = stop_tx.clone();
+    async move {
+      let _ = stop_signal().await;
+      stop_tx.send_replace(true);
+    }
+  });
+
+  let statuses = configs
+    .iter()
+    .map(|config| (config.tenant.clone(), Status::default()))
+    .collect::<Vec<_>>();
+  if let Some(address) = configs[0].status_address {
+    status::spawn_server(address, statuses.clone());
+  }
+
+  let mut reloads = HashMap::with_capacity(configs.len());
+  let mut tenants = Vec::with_capacity(configs.len());
+  for (config, (_, status)) in configs.into_iter().zip(statuses) {
+    let sinks = SinkManager::new(&config, middleware::Middleware::from_config(&config.middleware))?;
+
+    let (channels_tx, channels_rx) = tokio::sync::watch::channel(channel_names(&config));
+    if let Some(viewer_snapshots) = config.viewer_snapshots.clone() {
+      tokio::spawn(viewers::poll(
+        viewer_snapshots,
+        config.output_directory.clone(),
+        channels_rx,
+      ));
+    }
+
+    let (reload_tx, reload_rx) = tokio::sync::mpsc::unbounded_channel();
+    reloads.insert(config.tenant.clone(), (channels_tx, reload_tx));
+    tenants.push((config, sinks, status, reload_rx));
+  }
+
+  tokio::spawn(async move {
+    while reload_signal().await.is_ok() {
+      log::info!("Reloading the config");
+      let mut configs = match load_config(config_path.clone()) {
+        Ok(configs) => configs
+          .into_iter()
+          .map(|config| (config.tenant.clone(), config))
+          .collect::<HashMap<_, _>>(),
+        Err(e) => {
+          log::error!("Failed to load the config, keeping the previous one: {}", e);
+          continue;
+        }
+      };
+      for (tenant, (channels_tx, reload_tx)) in &reloads {
+        match configs.remove(tenant) {
+          Some(config) => {
+            channels_tx.send_replace(channel_names(&config));
+            let _ = reload_tx.send(config);
+          }
+          None => log::warn!(
+            "Tenant {} was removed from the config, it keeps running until a restart",
+            tenant_name(tenant)
+          ),
+        }
+      }
+      for tenant in configs.keys() {
+        log::warn!(
+          "Tenant {} was added to the config, it's started after a restart",
+          tenant_name(tenant)
+        );
+      }
+    }
+  });
+
+  let results = futures::future::join_all(tenants.into_iter().map(|(config, mut sinks, status, reload_rx)| {
+    let mut stop_rx = stop_rx.clone();
+    let stop_tx = stop_tx.clone();
+    async move {
+      let tenant = config.tenant.clone();
+      let result = run(
+        config,
+        &mut sinks,
+        &status,
+        async move {
+          let _ = stop_rx.wait_for(|stop| *stop).await;
+        },
+        reload_rx,
+      )
+      .await;
+      if let Err(e) = &result {
+        log::error!("Tenant {} stopped with an error: {:?}", tenant_name(&tenant), e);
+      }
+      stop_tx.send_replace(true);
+      result
+    }
+  }))
+  .await;
+  results.into_iter().collect()
+}
+
 async fn handle_messages(
   conn: &mut twitch_api::TwitchStream,
   creds: &twitch_api::Credentials,
   channels: &[String],
-  sinks: &mut HashMap<String, DailyLogSink>,
+  sinks: &mut SinkManager,
+  sequences: &mut Sequences,
   batch: String,
 ) -> std::result::Result<(), twitch_api::WsError> {
   let all_messages = batch
@@ -106,6 +307,7 @@ async fn handle_messages(
     .collect::<Vec<_>>();
 
   // Process all the text messages first
+  let mut records = Vec::new();
   for twitch_msg in all_messages
     .iter()
     .filter(|msg| matches!(msg.command(), Command::Privmsg))
@@ -116,12 +318,18 @@ async fn handle_messages(
 
     if let (Some(channel), Some(login), Some(text)) = (channel, login, text) {
       log::info!("[{channel}] {login}: {text}");
-      let mut sink = sinks.get_mut(channel).unwrap();
-      write!(&mut sink, "{login},{text}\n")?;
+      records.push(RawLogRecord {
+        channel: channel.to_owned(),
+        chatter: login.to_owned(),
+        text: text.to_owned(),
+        language: None,
+        seq: Some(sequences.next(channel)),
+      });
     } else {
       log::warn!("Invalid message: {twitch_msg:?}");
     }
   }
+  sinks.write_batch(records).await;
 
   for twitch_msg in all_messages
     .into_iter()
@@ -139,19 +347,62 @@ async fn handle_messages(
 
 static CARGO_MANIFEST_DIR: &str = env!("CARGO_MANIFEST_DIR");
 
-#[tokio::main]
-async fn main() -> Result<()> {
+fn config_path(path: Option<String>) -> std::path::PathBuf {
+  path.map(std::path::PathBuf::from).unwrap_or_else(|| {
+    std::path::PathBuf::from(CARGO_MANIFEST_DIR)
+      .join("config")
+      .join("collector.json")
+  })
+}
+
+fn load_config(path: Option<String>) -> Result<Vec<Config>> {
+  let configs = self::Config::load(config_path(path))?;
+  for config in &configs {
+    log::info!("{config:?}");
+  }
+  Ok(configs)
+}
+
+fn main() -> Result<()> {
   if env::var("RUST_LOG").is_err() {
     env::set_var("RUST_LOG", "INFO");
   }
   env_logger::try_init()?;
 
-  let config = self::Config::load(env::args().nth(1).map(std::path::PathBuf::from).unwrap_or_else(|| {
-    std::path::PathBuf::from(CARGO_MANIFEST_DIR)
-      .join("config")
-      .join("collector.json")
-  }))?;
-  log::info!("{config:?}");
+  #[cfg(target_family = "windows")]
+  if env::args().nth(1).as_deref() == Some("--service") {
+    return service::start();
+  }
+
+  if env::args().nth(1).as_deref() == Some("validate") {
+    return scs_config::validate_all(env::args().skip(2), |path| self::Config::load(path));
+  }
+
+  // usage: collector --self-test [config]
+  if env::args().nth(1).as_deref() == Some("--self-test") {
+    let passed = tokio::runtime::Runtime::new()?.block_on(selftest::run(&config_path(env::args().nth(2))))?;
+    std::process::exit(if passed { 0 } else { 1 });
+  }
+
+  // usage: collector --simulate <dir> [--speed 10x] [config]
+  if env::args().nth(1).as_deref() == Some("--simulate") {
+    let mut args = env::args().skip(2);
+    let dir = args.next().context("Missing the directory of the logs to replay")?;
+    let mut speed = simulate::Speed::default();
+    let mut path = None;
+    while let Some(arg) = args.next() {
+      match arg.as_str() {
+        "--speed" => speed = args.next().context("Missing the value of --speed")?.parse()?,
+        _ => path = Some(arg),
+      }
+    }
+    let config = load_config(path)?
+      .into_iter()
+      .next()
+      .context("The config doesn't have any tenants")?;
+    return tokio::runtime::Runtime::new()?.block_on(simulate::run(dir.into(), speed, config));
+  }
 
-  run(config).await
+  let configs = load_config(env::args().nth(1))?;
+  tokio::runtime::Runtime::new()?.block_on(start(configs, env::args().nth(1)))
 }
@@ -0,0 +1,289 @@
+//! Transformations applied to every batch of records before it is written to the sinks.
+
+use std::sync::{
+  atomic::{AtomicU64, Ordering},
+  Arc,
+};
+
+use futures::future::BoxFuture;
+use serde::Deserialize;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::sink::RawLogRecord;
+
+pub type SyncStage = Box<dyn Fn(Vec<RawLogRecord>) -> Vec<RawLogRecord> + Send + Sync>;
+pub type AsyncStage = Box<dyn Fn(Vec<RawLogRecord>) -> BoxFuture<'static, Vec<RawLogRecord>> + Send + Sync>;
+
+pub enum Stage {
+  Sync(SyncStage),
+  Async(AsyncStage),
+}
+
+/// Built-in stages which can be enabled in the config
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Builtin {
+  /// Converts chatter names to lowercase
+  LowercaseChatters,
+  /// Removes zero-width and other invisible characters (e.g. the ones used to bypass duplicate message detection)
+  StripInvisible,
+  /// Normalizes messages to NFC and removes invisible characters, counting what it changed. It always runs before
+  /// the other stages, so that they and the sinks only see normalized text.
+  Normalize(NormalizeConfig),
+  /// Drops records with an empty message
+  DropEmpty,
+  /// Tags records with the language of their message, if it can be detected reliably.
+  /// Detection runs on a blocking thread, as it's relatively expensive.
+  DetectLanguage,
+}
+
+/// Options of the `normalize` stage
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NormalizeConfig {
+  /// Codepoints which are removed in addition to the invisible ones, e.g. `U+00AD`
+  pub strip: Vec<Codepoint>,
+}
+
+/// A single codepoint, written either as `U+XXXX` or as the character itself
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Codepoint(pub char);
+
+impl TryFrom<String> for Codepoint {
+  type Error = String;
+
+  fn try_from(value: String) -> Result<Self, Self::Error> {
+    let mut chars = value.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+      return Ok(Codepoint(c));
+    }
+    value
+      .strip_prefix("U+")
+      .or_else(|| value.strip_prefix("u+"))
+      .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+      .and_then(char::from_u32)
+      .map(Codepoint)
+      .ok_or_else(|| format!("`{value}` is not a codepoint, expected a single character or `U+XXXX`"))
+  }
+}
+
+/// Whether `c` is removed by the `normalize` stage regardless of its config: the characters removed by
+/// `strip_invisible`, and the bidirectional controls which can reorder the text around them
+fn is_scrubbed(c: char) -> bool {
+  chain::text::is_invisible(c) || matches!(c, '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}')
+}
+
+/// Counts of the changes made by the `normalize` stage
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ScrubStats {
+  /// Messages which weren't in NFC
+  pub normalized: u64,
+  /// Characters removed from messages
+  pub scrubbed: u64,
+}
+
+impl std::ops::Add for ScrubStats {
+  type Output = Self;
+
+  fn add(self, other: Self) -> Self {
+    Self {
+      normalized: self.normalized + other.normalized,
+      scrubbed: self.scrubbed + other.scrubbed,
+    }
+  }
+}
+
+#[derive(Debug, Default)]
+struct ScrubCounters {
+  normalized: AtomicU64,
+  scrubbed: AtomicU64,
+}
+
+fn normalize(records: &mut [RawLogRecord], strip: &[char], counters: &ScrubCounters) {
+  for record in records.iter_mut() {
+    if !unicode_normalization::is_nfc(&record.text) {
+      record.text = record.text.nfc().collect();
+      counters.normalized.fetch_add(1, Ordering::Relaxed);
+    }
+    let is_removed = |c: char| is_scrubbed(c) || strip.contains(&c);
+    let removed = record.text.chars().filter(|c| is_removed(*c)).count();
+    if removed > 0 {
+      record.text = record.text.chars().filter(|c| !is_removed(*c)).collect();
+      record.text = record.text.trim().to_owned();
+      counters.scrubbed.fetch_add(removed as u64, Ordering::Relaxed);
+    }
+  }
+}
+
+impl Builtin {
+  fn stage(&self, counters: &Arc<ScrubCounters>) -> Stage {
+    match self {
+      Builtin::LowercaseChatters => Stage::Sync(Box::new(|mut records| {
+        for record in records.iter_mut() {
+          record.chatter.make_ascii_lowercase();
+        }
+        records
+      })),
+      Builtin::StripInvisible => Stage::Sync(Box::new(|mut records| {
+        for record in records.iter_mut() {
+          if record.text.chars().any(chain::text::is_invisible) {
+            record.text = record.text.chars().filter(|c| !chain::text::is_invisible(*c)).collect();
+          }
+          record.text = record.text.trim().to_owned();
+        }
+        records
+      })),
+      Builtin::Normalize(config) => {
+        let strip = config.strip.iter().map(|c| c.0).collect::<Vec<_>>();
+        let counters = counters.clone();
+        Stage::Sync(Box::new(move |mut records| {
+          normalize(&mut records, &strip, &counters);
+          records
+        }))
+      }
+      Builtin::DropEmpty => Stage::Sync(Box::new(|mut records| {
+        records.retain(|record| !record.text.trim().is_empty());
+        records
+      })),
+      Builtin::DetectLanguage => Stage::Async(Box::new(|records| {
+        Box::pin(async move {
+          tokio::task::spawn_blocking(move || detect_languages(records))
+            .await
+            .expect("language detection panicked")
+        })
+      })),
+    }
+  }
+}
+
+fn detect_languages(mut records: Vec<RawLogRecord>) -> Vec<RawLogRecord> {
+  for record in records.iter_mut() {
+    record.language = whatlang::detect(&record.text)
+      .filter(|info| info.is_reliable())
+      .map(|info| info.lang().code());
+  }
+  records
+}
+
+/// A chain of stages, which are applied in order
+#[derive(Default)]
+pub struct Middleware {
+  stages: Vec<Stage>,
+  counters: Arc<ScrubCounters>,
+}
+
+impl Middleware {
+  pub fn from_config(builtins: &[Builtin]) -> Self {
+    let counters = Arc::<ScrubCounters>::default();
+    // Normalization runs first, so that no other stage sees text which is about to change
+    let (normalize, others): (Vec<_>, Vec<_>) = builtins.iter().partition(|b| matches!(b, Builtin::Normalize(_)));
+    Self {
+      stages: normalize
+        .into_iter()
+        .chain(others)
+        .map(|b| b.stage(&counters))
+        .collect(),
+      counters,
+    }
+  }
+
+  /// Changes made by the `normalize` stage since the middleware was created
+  pub fn scrub_stats(&self) -> ScrubStats {
+    ScrubStats {
+      normalized: self.counters.normalized.load(Ordering::Relaxed),
+      scrubbed: self.counters.scrubbed.load(Ordering::Relaxed),
+    }
+  }
+
+  pub fn with(mut self, stage: impl Fn(Vec<RawLogRecord>) -> Vec<RawLogRecord> + Send + Sync + 'static) -> Self {
+    self.stages.push(Stage::Sync(Box::new(stage)));
+    self
+  }
+
+  pub fn with_async(
+    mut self,
+    stage: impl Fn(Vec<RawLogRecord>) -> BoxFuture<'static, Vec<RawLogRecord>> + Send + Sync + 'static,
+  ) -> Self {
+    self.stages.push(Stage::Async(Box::new(stage)));
+    self
+  }
+
+  pub async fn apply(&self, mut records: Vec<RawLogRecord>) -> Vec<RawLogRecord> {
+    for stage in &self.stages {
+      if records.is_empty() {
+        break;
+      }
+      records = match stage {
+        Stage::Sync(f) => f(records),
+        Stage::Async(f) => f(records).await,
+      };
+    }
+    records
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn record(text: &str) -> RawLogRecord {
+    RawLogRecord {
+      channel: "forsen".into(),
+      chatter: "chatter".into(),
+      text: text.into(),
+      language: None,
+      seq: None,
+    }
+  }
+
+  #[tokio::test]
+  async fn test_detect_language() {
+    let middleware = Middleware::from_config(&[Builtin::DetectLanguage]);
+    let records = middleware
+      .apply(vec![
+        record("Der schnelle braune Fuchs springt über den faulen Hund und läuft weiter in den Wald"),
+        record("The quick brown fox jumps over the lazy dog and keeps running into the forest"),
+        record("LUL"),
+      ])
+      .await;
+
+    let languages = records.iter().map(|r| r.language).collect::<Vec<_>>();
+    assert_eq!(languages, vec![Some("deu"), Some("eng"), None]);
+  }
+
+  #[tokio::test]
+  async fn test_normalize() {
+    let builtins: Vec<Builtin> =
+      serde_json::from_str(r#"["drop_empty", { "normalize": { "strip": ["U+00AD", "~"] } }]"#).unwrap();
+    let middleware = Middleware::from_config(&builtins);
+    let records = middleware
+      .apply(vec![
+        // `e` followed by a combining acute accent
+        record("cafe\u{301}"),
+        record("\u{200b}hel\u{ad}lo\u{202e} ~"),
+        // only invisible characters, dropped because normalization runs first
+        record("\u{2066}\u{2069}"),
+        record("already clean"),
+      ])
+      .await;
+
+    let texts = records.iter().map(|r| r.text.as_str()).collect::<Vec<_>>();
+    assert_eq!(texts, vec!["caf\u{e9}", "hello", "already clean"]);
+    assert_eq!(
+      middleware.scrub_stats(),
+      ScrubStats {
+        normalized: 1,
+        scrubbed: 6
+      }
+    );
+  }
+
+  #[test]
+  fn test_codepoint() {
+    assert_eq!(Codepoint::try_from(String::from("U+202E")), Ok(Codepoint('\u{202e}')));
+    assert_eq!(Codepoint::try_from(String::from("~")), Ok(Codepoint('~')));
+    assert!(Codepoint::try_from(String::from("U+D800")).is_err());
+    assert!(Codepoint::try_from(String::from("ab")).is_err());
+  }
+}
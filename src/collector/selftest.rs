@@ -0,0 +1,216 @@
+//! Checks that the collector can run with a config, without collecting any messages. Used by deploy pipelines:
+//! the report is printed as JSON, and the process exits with a non-zero status if any check failed.
+
+use std::{
+  collections::HashMap,
+  io::Write,
+  path::{Path, PathBuf},
+  time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context as _, Result};
+use serde::Serialize;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::{
+  config::{Buffer, Config},
+  middleware::Middleware,
+  sink::{DailyLogSink, RawLogRecord, SinkManager},
+  summary::SummarySink,
+};
+
+/// How long to wait for Twitch to acknowledge the JOIN
+const JOIN_TIMEOUT: Duration = Duration::from_secs(15);
+const SANDBOX_CHANNEL: &str = "self-test";
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum CheckStatus {
+  Passed,
+  Failed,
+  Skipped,
+}
+
+#[derive(Debug, Serialize)]
+struct Check {
+  name: &'static str,
+  /// Tenant whose config was checked, if the config has tenants
+  #[serde(skip_serializing_if = "Option::is_none")]
+  tenant: Option<String>,
+  status: CheckStatus,
+  /// What was checked, or why the check failed or was skipped
+  detail: String,
+  duration_ms: u128,
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+  config: String,
+  ok: bool,
+  checks: Vec<Check>,
+  /// Tenant of the checks being run
+  #[serde(skip)]
+  tenant: Option<String>,
+}
+
+impl Report {
+  async fn run<F: std::future::Future<Output = Result<String>>>(&mut self, name: &'static str, check: F) -> bool {
+    let start = Instant::now();
+    let result = check.await;
+    let passed = result.is_ok();
+    self.checks.push(Check {
+      name,
+      tenant: self.tenant.clone(),
+      status: if passed {
+        CheckStatus::Passed
+      } else {
+        CheckStatus::Failed
+      },
+      detail: result.unwrap_or_else(|e| format!("{e:#}")),
+      duration_ms: start.elapsed().as_millis(),
+    });
+    self.ok &= passed;
+    passed
+  }
+
+  fn skip(&mut self, name: &'static str, reason: impl Into<String>) {
+    self.checks.push(Check {
+      name,
+      tenant: self.tenant.clone(),
+      status: CheckStatus::Skipped,
+      detail: reason.into(),
+      duration_ms: 0,
+    });
+  }
+}
+
+/// Runs every check against the config at `path`, prints the report, and returns whether all checks passed
+pub async fn run(path: &Path) -> Result<bool> {
+  let mut report = Report {
+    config: path.display().to_string(),
+    ok: true,
+    checks: Vec::new(),
+    tenant: None,
+  };
+
+  let mut configs = None;
+  report
+    .run("config", async {
+      let loaded = Config::load(path)?;
+      let channels = loaded.iter().map(|config| config.channels.len()).sum::<usize>();
+      let detail = match loaded[0].tenant {
+        Some(_) => format!("{} tenant(s), {} channel(s)", loaded.len(), channels),
+        None => format!("{} channel(s)", channels),
+      };
+      configs = Some(loaded);
+      Ok(detail)
+    })
+    .await;
+
+  match configs {
+    Some(configs) => {
+      for config in configs {
+        report.tenant = config.tenant.clone();
+        report.run("output_directory", check_output_directory(&config)).await;
+        report.run("file_sink", check_file_sink(&config)).await;
+        report.skip(
+          "postgres",
+          "The collector only writes to files, logs are ingested separately",
+        );
+        report.run("twitch", check_twitch(&config)).await;
+      }
+    }
+    None => {
+      for name in ["output_directory", "file_sink", "postgres", "twitch"] {
+        report.skip(name, "The config is invalid");
+      }
+    }
+  }
+
+  println!("{}", serde_json::to_string_pretty(&report)?);
+  Ok(report.ok)
+}
+
+/// Directory the sinks are tested in, which is removed afterwards
+fn sandbox(config: &Config) -> PathBuf {
+  config
+    .output_directory
+    .join(format!(".self-test-{}", std::process::id()))
+}
+
+async fn check_output_directory(config: &Config) -> Result<String> {
+  let path = config
+    .output_directory
+    .join(format!(".self-test-{}.tmp", std::process::id()));
+  std::fs::File::create(&path)
+    .and_then(|mut file| file.write_all(b"self-test\n"))
+    .with_context(|| format!("{} is not writable", config.output_directory.display()))?;
+  std::fs::remove_file(&path)?;
+  Ok(format!("{} is writable", config.output_directory.display()))
+}
+
+/// Writes a record through a [`SinkManager`] with a file sink in the sandbox, and reads it back
+async fn check_file_sink(config: &Config) -> Result<String> {
+  let dir = sandbox(config);
+  let result = async {
+    let sink = DailyLogSink::new(
+      dir.clone(),
+      SANDBOX_CHANNEL.into(),
+      Buffer::Fixed(1024),
+      config.rotation,
+    )?;
+    let mut sinks = SinkManager::with_sinks(
+      HashMap::from([(SANDBOX_CHANNEL.to_owned(), Box::new(sink) as Box<dyn Write + Send>)]),
+      Middleware::from_config(&[]),
+      SummarySink::new(None),
+    );
+    sinks
+      .write_batch(vec![RawLogRecord {
+        channel: SANDBOX_CHANNEL.into(),
+        chatter: "self_test".into(),
+        text: "self-test record".into(),
+        language: None,
+        seq: None,
+      }])
+      .await;
+    sinks.flush()?;
+
+    let written = std::fs::read_dir(dir.join(SANDBOX_CHANNEL))?
+      .filter_map(|entry| entry.ok())
+      .map(|entry| std::fs::read_to_string(entry.path()))
+      .collect::<std::io::Result<String>>()?;
+    if written != "self_test,self-test record\n" {
+      bail!("The test record wasn't written as expected, got {written:?}");
+    }
+    Ok(format!("Wrote a test record to {}", dir.display()))
+  }
+  .await;
+  let _ = std::fs::remove_dir_all(&dir);
+  result
+}
+
+/// Connects to the IRC server anonymously, and joins the first channel of the config
+async fn check_twitch(config: &Config) -> Result<String> {
+  let channel = config.channels[0].name.clone();
+  let mut conn = twitch_api::TwitchStream::with_options(config.server.clone(), config.connection.clone())
+    .await
+    .with_context(|| format!("Failed to connect to {}", config.server))?;
+  conn.authenticate(&twitch_api::Credentials::Anonymous).await?;
+  conn.schedule_joins(&[channel.clone()]);
+
+  let joined = tokio::time::timeout(JOIN_TIMEOUT, async {
+    while conn.membership().joined() == 0 {
+      match conn.receive().await? {
+        Some(Message::Text(batch)) if batch.lines().any(|line| line.starts_with("PING")) => conn.pong().await?,
+        Some(_) => (),
+        None => bail!("The connection was closed"),
+      }
+    }
+    Ok(())
+  })
+  .await;
+  match joined {
+    Ok(result) => result.map(|_| format!("Joined #{} on {}", channel, config.server)),
+    Err(_) => bail!("#{} wasn't joined within {:?}", channel, JOIN_TIMEOUT),
+  }
+}
@@ -0,0 +1,75 @@
+//! Windows service wrapper for the collector.
+//!
+//! Register the service with:
+//! `sc.exe create scs-collector binPath= "<path to collector.exe> --service <path to collector.json>"`
+
+use std::{ffi::OsString, time::Duration};
+
+use anyhow::Result;
+use windows_service::{
+  define_windows_service,
+  service::{ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus, ServiceType},
+  service_control_handler::{self, ServiceControlHandlerResult},
+  service_dispatcher,
+};
+
+const SERVICE_NAME: &str = "scs-collector";
+
+define_windows_service!(ffi_service_main, service_main);
+
+/// Hands control over to the service control manager. Blocks until the service is stopped.
+pub fn start() -> Result<()> {
+  service_dispatcher::start(SERVICE_NAME, ffi_service_main)?;
+  Ok(())
+}
+
+fn service_main(_: Vec<OsString>) {
+  if let Err(e) = run_service() {
+    log::error!("Service failed: {:?}", e);
+  }
+}
+
+fn run_service() -> Result<()> {
+  let status_handle = service_control_handler::register(SERVICE_NAME, |control| match control {
+    ServiceControl::Stop | ServiceControl::Shutdown => {
+      // `notify_one` stores a permit, so the stop request isn't lost if nobody is waiting for it yet
+      crate::signal::SERVICE_STOP.notify_one();
+      ServiceControlHandlerResult::NoError
+    }
+    ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+    _ => ServiceControlHandlerResult::NotImplemented,
+  })?;
+
+  let set_state = |current_state, controls_accepted, exit_code| {
+    status_handle.set_service_status(ServiceStatus {
+      service_type: ServiceType::OWN_PROCESS,
+      current_state,
+      controls_accepted,
+      exit_code,
+      checkpoint: 0,
+      wait_hint: Duration::default(),
+      process_id: None,
+    })
+  };
+
+  set_state(
+    ServiceState::Running,
+    ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+    ServiceExitCode::Win32(0),
+  )?;
+
+  // The first argument is `--service`, the config path follows it
+  let result = crate::load_config(std::env::args().nth(2))
+    .and_then(|config| tokio::runtime::Runtime::new()?.block_on(crate::start(config, std::env::args().nth(2))));
+
+  set_state(
+    ServiceState::Stopped,
+    ServiceControlAccept::empty(),
+    match result {
+      Ok(_) => ServiceExitCode::Win32(0),
+      Err(_) => ServiceExitCode::ServiceSpecific(1),
+    },
+  )?;
+
+  result
+}
@@ -0,0 +1,52 @@
+#[cfg(target_family = "windows")]
+lazy_static::lazy_static! {
+  /// Notified by the service control handler when the collector runs as a Windows service and is asked to stop.
+  pub static ref SERVICE_STOP: tokio::sync::Notify = tokio::sync::Notify::new();
+}
+
+#[cfg(target_family = "windows")]
+pub async fn stop_signal() -> std::io::Result<()> {
+  use tokio::signal::windows;
+
+  let mut ctrl_c = windows::ctrl_c()?; // ctrl-c
+  let mut ctrl_break = windows::ctrl_break()?; // ctrl-break
+  let mut ctrl_close = windows::ctrl_close()?; // the console window is closed
+  let mut ctrl_logoff = windows::ctrl_logoff()?; // the user is logging off
+  let mut ctrl_shutdown = windows::ctrl_shutdown()?; // the system is shutting down
+
+  tokio::select! {
+    _ = ctrl_c.recv() => Ok(()),
+    _ = ctrl_break.recv() => Ok(()),
+    _ = ctrl_close.recv() => Ok(()),
+    _ = ctrl_logoff.recv() => Ok(()),
+    _ = ctrl_shutdown.recv() => Ok(()),
+    _ = SERVICE_STOP.notified() => Ok(()),
+  }
+}
+
+/// Resolves when the collector is asked to reload its config. Only SIGHUP is supported, so this never resolves on Windows.
+#[cfg(target_family = "windows")]
+pub async fn reload_signal() -> std::io::Result<()> {
+  std::future::pending().await
+}
+
+#[cfg(target_family = "unix")]
+pub async fn reload_signal() -> std::io::Result<()> {
+  let mut sighup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+  sighup.recv().await;
+  Ok(())
+}
+
+#[cfg(target_family = "unix")]
+pub async fn stop_signal() -> std::io::Result<()> {
+  let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?; // SIGTERM for docker-compose down
+  let mut sigint = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::interrupt())?; // SIGINT for ctrl-c
+
+  let sigterm = sigterm.recv();
+  let sigint = sigint.recv();
+
+  tokio::select! {
+    _ = sigterm => Ok(()),
+    _ = sigint => Ok(()),
+  }
+}
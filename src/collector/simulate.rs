@@ -0,0 +1,326 @@
+//! Replays archived logs through the collector instead of collecting from Twitch, to load-test the sinks and reproduce
+//! bugs with realistic traffic.
+//!
+//! The messages are sent by a fake IRC server at the times they were logged, sped up by `speed`, so they go through
+//! the same connection, [`crate::handle_messages`], middleware, and [`SinkManager`] as live messages.
+
+use std::{
+  path::{Path, PathBuf},
+  time::Instant,
+};
+
+use anyhow::{bail, Context as _, Result};
+use chrono::{Duration as ChronoDuration, NaiveDate, NaiveDateTime, NaiveTime};
+use futures::{SinkExt, StreamExt};
+use lazy_static::lazy_static;
+use regex::Regex;
+use scs_config::sinks::LogFileName;
+use tokio::{net::TcpListener, sync::oneshot};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::{
+  config::{Buffer, Channel, Config, DEFAULT_BUF_SIZE},
+  middleware::Middleware,
+  sink::SinkManager,
+};
+
+lazy_static! {
+  static ref CHATTERINO_LINE: Regex = Regex::new(r"^\[(\d{2}:\d{2}:\d{2})\]  (\w+): (.*)").unwrap();
+  static ref COLLECTOR_LINE: Regex = Regex::new(r"^(\w+)(?:@[a-z]{3})?(?:#\d+)?,(.*)").unwrap();
+}
+
+/// How much faster than real time the logs are replayed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Speed(f64);
+
+impl std::str::FromStr for Speed {
+  type Err = anyhow::Error;
+
+  /// Parses `10x` or `10`
+  fn from_str(s: &str) -> Result<Self> {
+    let factor = s
+      .strip_suffix('x')
+      .unwrap_or(s)
+      .parse::<f64>()
+      .with_context(|| format!("Invalid speed `{s}`, expected e.g. `10x`"))?;
+    if !factor.is_finite() || factor <= 0.0 {
+      bail!("Invalid speed `{s}`, it must be greater than zero");
+    }
+    Ok(Speed(factor))
+  }
+}
+
+impl Default for Speed {
+  fn default() -> Self {
+    Speed(1.0)
+  }
+}
+
+/// A logged message, and when it was sent
+#[derive(Clone, Debug, PartialEq)]
+struct Replayed {
+  time: NaiveDateTime,
+  channel: String,
+  chatter: String,
+  text: String,
+}
+
+/// Channel and period covered by a log file, from its name
+fn parse_file_name(name: &str) -> Option<(String, NaiveDateTime, ChronoDuration)> {
+  let name = LogFileName::parse(name)?;
+  let date = NaiveDate::parse_from_str(name.date, "%Y-%m-%d").ok()?;
+  let (start, period) = match name.hour {
+    Some(hour) => (date.and_hms_opt(hour, 0, 0)?, ChronoDuration::hours(1)),
+    None => (date.and_hms_opt(0, 0, 0)?, ChronoDuration::days(1)),
+  };
+  Some((name.channel.to_owned(), start, period))
+}
+
+/// Reads the messages of a log file, in either the Chatterino or the collector's format.
+///
+/// Collector logs don't have timestamps, so their messages are spread evenly over the period of the file.
+fn read_file(path: &Path, channel: &str, start: NaiveDateTime, period: ChronoDuration) -> Result<Vec<Replayed>> {
+  let contents = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+  let mut timed = Vec::new();
+  let mut untimed = Vec::new();
+  for line in contents.lines().map(|line| line.trim_end_matches('\r')) {
+    if let Some(captures) = CHATTERINO_LINE.captures(line) {
+      let Ok(time) = NaiveTime::parse_from_str(&captures[1], "%H:%M:%S") else {
+        continue;
+      };
+      timed.push(Replayed {
+        time: start.date().and_time(time),
+        channel: channel.to_owned(),
+        chatter: captures[2].to_owned(),
+        text: captures[3].to_owned(),
+      });
+    } else if let Some(captures) = COLLECTOR_LINE.captures(line) {
+      untimed.push((captures[1].to_owned(), captures[2].to_owned()));
+    }
+  }
+
+  let count = untimed.len() as i32;
+  timed.extend(untimed.into_iter().enumerate().map(|(i, (chatter, text))| Replayed {
+    time: start + period * i as i32 / count,
+    channel: channel.to_owned(),
+    chatter,
+    text,
+  }));
+  Ok(timed)
+}
+
+/// Reads every log file in `dir` and its subdirectories, ordered by time. Files with other names are skipped.
+fn read_logs(dir: &Path) -> Result<Vec<Replayed>> {
+  let mut messages = Vec::new();
+  for entry in walkdir::WalkDir::new(dir) {
+    let entry = entry?;
+    if !entry.file_type().is_file() {
+      continue;
+    }
+    let Some((channel, start, period)) = entry.file_name().to_str().and_then(parse_file_name) else {
+      log::debug!("Skipping {}", entry.path().display());
+      continue;
+    };
+    messages.extend(read_file(entry.path(), &channel, start, period)?);
+  }
+  // Stable, so messages logged in the same second keep their order
+  messages.sort_by_key(|message| message.time);
+  Ok(messages)
+}
+
+/// Accepts the collector's connection, and sends `messages` at their time since the first one, divided by `speed`.
+/// Messages which are due at the same time are sent in one batch.
+///
+/// `done` is notified once the collector has processed every message.
+async fn serve(listener: TcpListener, messages: Vec<Replayed>, speed: Speed, done: oneshot::Sender<()>) -> Result<()> {
+  let (stream, _) = listener.accept().await?;
+  let mut ws = tokio_tungstenite::accept_async(stream).await?;
+
+  while let Some(message) = ws.next().await {
+    if matches!(message?, Message::Text(ref text) if text.starts_with("JOIN")) {
+      break;
+    }
+  }
+
+  let started = Instant::now();
+  let Some(first) = messages.first().map(|message| message.time) else {
+    return Ok(());
+  };
+  let due = |message: &Replayed| {
+    let offset = (message.time - first).to_std().unwrap_or_default();
+    started + offset.div_f64(speed.0)
+  };
+  let mut messages = messages.iter().peekable();
+  while let Some(message) = messages.next() {
+    tokio::time::sleep_until(due(message).into()).await;
+    let mut batch = privmsg(message);
+    while let Some(next) = messages.next_if(|next| due(next) <= Instant::now()) {
+      batch.push_str(&privmsg(next));
+    }
+    ws.send(Message::Text(batch)).await?;
+  }
+
+  // Batches are processed in order, so receiving the PONG means that every message was handled
+  ws.send(Message::Text("PING :tmi.twitch.tv\r\n".into())).await?;
+  while let Some(message) = ws.next().await {
+    if matches!(message?, Message::Text(ref text) if text.starts_with("PONG")) {
+      break;
+    }
+  }
+  let _ = done.send(());
+
+  // Keep the connection open until the collector disconnects
+  while let Some(Ok(_)) = ws.next().await {}
+  Ok(())
+}
+
+fn privmsg(message: &Replayed) -> String {
+  let Replayed {
+    channel, chatter, text, ..
+  } = message;
+  format!(":{chatter}!{chatter}@{chatter}.tmi.twitch.tv PRIVMSG #{channel} :{text}\r\n")
+}
+
+/// Replays the logs in `dir` through the collector with the sinks and middleware of `config`, and logs how long it took.
+///
+/// Every channel found in the logs is collected, with its buffer from `config` if it's listed there.
+pub async fn run(dir: PathBuf, speed: Speed, mut config: Config) -> Result<()> {
+  let dir = dir
+    .canonicalize()
+    .with_context(|| format!("Failed to open {}", dir.display()))?;
+  if config.output_directory.canonicalize().ok().as_ref() == Some(&dir) {
+    bail!("The output directory must not be the replayed directory, as the replayed logs would be appended to");
+  }
+  let messages = read_logs(&dir)?;
+  if messages.is_empty() {
+    bail!("No log files found in {}", dir.display());
+  }
+  let duration = messages.last().unwrap().time - messages[0].time;
+  log::info!(
+    "Replaying {} messages logged over {:.1} hours at {}x",
+    messages.len(),
+    duration.num_seconds() as f64 / 3600.0,
+    speed.0
+  );
+
+  let mut channels = messages
+    .iter()
+    .map(|message| message.channel.clone())
+    .collect::<Vec<_>>();
+  channels.sort();
+  channels.dedup();
+  config.channels = channels
+    .into_iter()
+    .map(|name| {
+      let buffer = config
+        .channels
+        .iter()
+        .find(|channel| channel.name == name)
+        .map_or(Buffer::Fixed(DEFAULT_BUF_SIZE), |channel| channel.buffer);
+      Channel { name, buffer }
+    })
+    .collect();
+
+  let listener = TcpListener::bind("127.0.0.1:0").await?;
+  config.server = format!("ws://{}", listener.local_addr()?);
+  config.credentials = None;
+  config.connection = Default::default();
+
+  let count = messages.len();
+  let (done_tx, done_rx) = oneshot::channel();
+  let server = tokio::spawn(serve(listener, messages, speed, done_tx));
+
+  let mut sinks = SinkManager::new(&config, Middleware::from_config(&config.middleware))?;
+  let started = Instant::now();
+  // Keep the sender alive, so that the collector doesn't stop waiting for reloads
+  let (_reload_tx, reload_rx) = tokio::sync::mpsc::unbounded_channel();
+  crate::run(
+    config,
+    &mut sinks,
+    &Default::default(),
+    async {
+      let _ = done_rx.await;
+    },
+    reload_rx,
+  )
+  .await?;
+  let elapsed = started.elapsed();
+  server.await??;
+
+  log::info!(
+    "Replayed {count} messages in {:.1?} ({:.0} messages/s), {:?}",
+    elapsed,
+    count as f64 / elapsed.as_secs_f64(),
+    sinks.stats()
+  );
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_speed() {
+    assert_eq!("10x".parse::<Speed>().unwrap(), Speed(10.0));
+    assert_eq!("0.5".parse::<Speed>().unwrap(), Speed(0.5));
+    assert!("0x".parse::<Speed>().is_err());
+    assert!("fast".parse::<Speed>().is_err());
+  }
+
+  #[test]
+  fn parses_file_names() {
+    let date = NaiveDate::from_ymd_opt(2023, 4, 5).unwrap();
+    assert_eq!(
+      parse_file_name("forsen-2023-04-05.log"),
+      Some((
+        "forsen".into(),
+        date.and_hms_opt(0, 0, 0).unwrap(),
+        ChronoDuration::days(1)
+      ))
+    );
+    assert_eq!(
+      parse_file_name("forsen-2023-04-05-13.log"),
+      Some((
+        "forsen".into(),
+        date.and_hms_opt(13, 0, 0).unwrap(),
+        ChronoDuration::hours(1)
+      ))
+    );
+    assert_eq!(parse_file_name("viewers-2023-04-05.jsonl"), None);
+    assert_eq!(parse_file_name("forsen.log"), None);
+  }
+
+  #[test]
+  fn reads_both_formats() {
+    let dir = std::env::temp_dir().join(format!("scs-simulate-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("forsen-2023-04-05-12.log");
+    std::fs::write(
+      &path,
+      "# Start logging at 2023-04-05 12:00:00 UTC\r\n[12:30:00]  a: hello\r\nb#1,first\nc@eng#2,second\n",
+    )
+    .unwrap();
+
+    let start = NaiveDate::from_ymd_opt(2023, 4, 5)
+      .unwrap()
+      .and_hms_opt(12, 0, 0)
+      .unwrap();
+    let mut messages = read_file(&path, "forsen", start, ChronoDuration::hours(1)).unwrap();
+    messages.sort_by_key(|message| message.time);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let summary = messages
+      .iter()
+      .map(|m| (m.time.time().to_string(), m.chatter.as_str(), m.text.as_str()))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      summary,
+      vec![
+        ("12:00:00".to_string(), "b", "first"),
+        ("12:30:00".to_string(), "a", "hello"),
+        ("12:30:00".to_string(), "c", "second"),
+      ]
+    );
+  }
+}
@@ -1,52 +1,634 @@
 use chrono::{DateTime, Utc};
+use scs_config::sinks::Rotation;
 use std::{
+  collections::{HashMap, VecDeque},
   fs::{self, File},
   io::{self, BufWriter, Write},
   path::{Path, PathBuf},
+  time::{Duration, Instant},
 };
 
-/// File sink which writes to a new file for each day
+use crate::{
+  config::{AutoBuffer, Backpressure, BackpressurePolicy, Buffer, Config},
+  middleware::{Middleware, ScrubStats},
+  summary::SummarySink,
+};
+
+/// A single chat message, as received from Twitch
+#[derive(Clone, Debug)]
+pub struct RawLogRecord {
+  pub channel: String,
+  pub chatter: String,
+  pub text: String,
+  /// ISO 639-3 code of the language of `text`, set by the `detect_language` middleware
+  pub language: Option<&'static str>,
+  /// Position of the record among the messages received in its channel, see [`Sequences`]
+  pub seq: Option<u64>,
+}
+
+impl RawLogRecord {
+  /// The record as a line of a log file, `chatter,text`. A detected language and the sequence number are appended to
+  /// the chatter, e.g. `chatter@eng#123,text`, as chatter names can't contain `@` or `#`.
+  pub fn log_line(&self) -> String {
+    let mut line = self.chatter.clone();
+    if let Some(language) = self.language {
+      line.push('@');
+      line.push_str(language);
+    }
+    if let Some(seq) = self.seq {
+      line.push('#');
+      line.push_str(&seq.to_string());
+    }
+    line.push(',');
+    line.push_str(&self.text);
+    line.push('\n');
+    line
+  }
+}
+
+/// Assigns sequence numbers to the messages of each channel as they're received, which increase by one with every
+/// message, so that consumers can detect gaps and order the messages of a channel the same way in every store.
+///
+/// Sequences start at the time they were created, in microseconds since the Unix epoch, so that they keep increasing
+/// across restarts. A restart shows up as a gap, since the messages sent in the meantime were missed.
+pub struct Sequences {
+  start: u64,
+  next: HashMap<String, u64>,
+}
+
+impl Sequences {
+  pub fn new() -> Self {
+    Self {
+      start: Utc::now().timestamp_micros() as u64,
+      next: HashMap::new(),
+    }
+  }
+
+  /// Returns the sequence number of the next message of `channel`
+  pub fn next(&mut self, channel: &str) -> u64 {
+    let next = match self.next.get_mut(channel) {
+      Some(next) => next,
+      None => self.next.entry(channel.to_owned()).or_insert(self.start),
+    };
+    *next += 1;
+    *next - 1
+  }
+}
+
+/// How often the `block` policy retries the writes
+const BLOCK_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Effects of the backpressure policy since the collector started
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BackpressureStats {
+  /// Records waiting to be written
+  pub pending: usize,
+  /// Time spent blocked by the `block` policy
+  pub blocked: Duration,
+  /// Records written to a spill file by the `spill` policy
+  pub spilled: u64,
+}
+
+/// Records waiting to be written, queued per channel so that a failing sink doesn't hold back the other channels.
+///
+/// Records are numbered in the order they were received, so that the oldest ones across channels can be spilled.
+#[derive(Default)]
+struct PendingRecords {
+  channels: HashMap<String, VecDeque<(u64, RawLogRecord)>>,
+  len: usize,
+  next: u64,
+}
+
+impl PendingRecords {
+  fn push(&mut self, record: RawLogRecord) {
+    let queue = self.channels.entry(record.channel.clone()).or_default();
+    queue.push_back((self.next, record));
+    self.next += 1;
+    self.len += 1;
+  }
+
+  fn len(&self) -> usize {
+    self.len
+  }
+
+  fn is_empty(&self) -> bool {
+    self.len == 0
+  }
+
+  /// The oldest `count` records across channels, in the order they were received
+  fn oldest(&self, count: usize) -> Vec<&(u64, RawLogRecord)> {
+    let mut records = self.channels.values().flatten().collect::<Vec<_>>();
+    records.sort_unstable_by_key(|(i, _)| *i);
+    records.truncate(count);
+    records
+  }
+
+  /// Drops the records received before the record numbered `end`
+  fn remove_before(&mut self, end: u64) {
+    for queue in self.channels.values_mut() {
+      while queue.front().map_or(false, |(i, _)| *i < end) {
+        queue.pop_front();
+        self.len -= 1;
+      }
+    }
+    self.channels.retain(|_, queue| !queue.is_empty());
+  }
+
+  /// Writes the queue of each channel with `write`, which removes the records it wrote from the queue.
+  /// Returns the first error, after the queues of the other channels are written as well.
+  fn write_each(
+    &mut self,
+    mut write: impl FnMut(&str, &mut VecDeque<(u64, RawLogRecord)>) -> io::Result<()>,
+  ) -> io::Result<()> {
+    let mut result = Ok(());
+    for (channel, queue) in self.channels.iter_mut() {
+      let before = queue.len();
+      let written = write(channel, queue);
+      self.len -= before - queue.len();
+      if result.is_ok() {
+        result = written;
+      }
+    }
+    self.channels.retain(|_, queue| !queue.is_empty());
+    result
+  }
+}
+
+/// Owns one sink per channel, and runs every batch of records through the middleware before writing it.
+///
+/// Records which fail to be written are kept, and retried before the next batch or when flushing,
+/// so that a temporarily failing sink doesn't lose messages. Each channel has its own queue, so the other channels
+/// keep being written while a sink fails. Once more than `backpressure.max_pending` records
+/// are waiting, the backpressure policy either blocks until the writes succeed, or spills the oldest records.
+///
+/// With coalescing, batches received less than `coalesce` after the previous write are held back until the interval
+/// has passed, see [`SinkManager::held_until`], and the records of each channel are then written to its sink together.
+pub struct SinkManager {
+  sinks: HashMap<String, Box<dyn Write + Send>>,
+  middleware: Middleware,
+  pending: PendingRecords,
+  summary: SummarySink,
+  backpressure: Backpressure,
+  /// Where spill files are written, records are kept in memory instead if it's `None`
+  spill_directory: Option<PathBuf>,
+  stats: BackpressureStats,
+  /// Changes made by the `normalize` middleware which was replaced by a reload
+  previous_scrubs: ScrubStats,
+  coalesce: Option<Duration>,
+  last_write: Option<Instant>,
+  /// Bytes already written of the records whose write failed partway, by record number
+  partial: HashMap<u64, usize>,
+}
+
+impl SinkManager {
+  pub fn new(config: &Config, middleware: Middleware) -> io::Result<Self> {
+    Ok(
+      Self::with_sinks(
+        file_sinks(config)?,
+        middleware,
+        SummarySink::new(config.summary_webhook.clone()).with_tenant(config.tenant.clone()),
+      )
+      .with_backpressure(config.backpressure.clone(), Some(spill_directory(config)))
+      .with_coalescing(coalesce_interval(config)),
+    )
+  }
+
+  pub fn with_sinks(
+    sinks: HashMap<String, Box<dyn Write + Send>>,
+    middleware: Middleware,
+    summary: SummarySink,
+  ) -> Self {
+    Self {
+      sinks,
+      middleware,
+      pending: PendingRecords::default(),
+      summary,
+      backpressure: Backpressure::default(),
+      spill_directory: None,
+      stats: BackpressureStats::default(),
+      previous_scrubs: ScrubStats::default(),
+      coalesce: None,
+      last_write: None,
+      partial: HashMap::new(),
+    }
+  }
+
+  /// Writes at most once per `interval`, or every batch if it's `None`
+  pub fn with_coalescing(mut self, interval: Option<Duration>) -> Self {
+    self.coalesce = interval;
+    self
+  }
+
+  pub fn with_backpressure(mut self, backpressure: Backpressure, spill_directory: Option<PathBuf>) -> Self {
+    self.backpressure = backpressure;
+    self.spill_directory = spill_directory;
+    self
+  }
+
+  pub fn stats(&self) -> BackpressureStats {
+    BackpressureStats {
+      pending: self.pending.len(),
+      ..self.stats
+    }
+  }
+
+  /// Changes made by the `normalize` middleware since the collector started
+  pub fn scrub_stats(&self) -> ScrubStats {
+    self.previous_scrubs + self.middleware.scrub_stats()
+  }
+
+  /// Writes `records`, applying the backpressure policy if too many records are waiting afterwards
+  pub async fn write_batch(&mut self, records: Vec<RawLogRecord>) {
+    self.summary.rotate();
+    for record in self.middleware.apply(records).await {
+      self.pending.push(record);
+    }
+    if self.is_coalescing() {
+      return;
+    }
+    self.write_and_relieve_pressure().await;
+  }
+
+  /// When the records held back by coalescing are due to be written, or `None` if no records are held back
+  pub fn held_until(&self) -> Option<Instant> {
+    match (self.coalesce, self.last_write) {
+      (Some(interval), Some(last_write)) if !self.pending.is_empty() => Some(last_write + interval),
+      _ => None,
+    }
+  }
+
+  /// Writes the records held back by coalescing if they're due, so that they don't wait for the next batch
+  pub async fn write_held(&mut self) {
+    if self.held_until().map_or(false, |due| due <= Instant::now()) {
+      self.write_and_relieve_pressure().await;
+    }
+  }
+
+  async fn write_and_relieve_pressure(&mut self) {
+    if let Err(e) = self.write_all_pending() {
+      log::error!(
+        "Failed to write to sink, {} records will be retried: {}",
+        self.pending.len(),
+        e
+      );
+      self.relieve_pressure().await;
+    }
+  }
+
+  async fn relieve_pressure(&mut self) {
+    let max_pending = self.backpressure.max_pending;
+    if self.pending.len() <= max_pending {
+      return;
+    }
+    match self.backpressure.policy {
+      BackpressurePolicy::Block => {
+        let start = Instant::now();
+        let max_wait = Duration::from_millis(self.backpressure.max_wait_ms);
+        while self.pending.len() > max_pending && start.elapsed() < max_wait {
+          tokio::time::sleep(BLOCK_RETRY_INTERVAL).await;
+          // Failures were already logged
+          let _ = self.write_all_pending();
+        }
+        self.stats.blocked += start.elapsed();
+        if self.pending.len() > max_pending {
+          log::warn!(
+            "Blocked for {:?}, {} records are still waiting to be written",
+            start.elapsed(),
+            self.pending.len()
+          );
+        }
+      }
+      BackpressurePolicy::Spill => {
+        let count = self.pending.len() - max_pending;
+        match self.spill(count) {
+          Ok(path) => {
+            self.stats.spilled += count as u64;
+            log::warn!("Spilled {count} records to {}", path.display());
+          }
+          Err(e) => log::error!("Failed to spill {count} records, they're kept in memory: {e}"),
+        }
+      }
+    }
+  }
+
+  /// Appends the oldest `count` pending records across channels to today's spill file as `channel,` followed by their
+  /// log line, and drops them. Spill files have their own extension, so that the ingester and the trainer don't
+  /// mistake them for the log of a channel.
+  ///
+  /// A spilled record whose write failed partway leaves the part of its line already written in its sink.
+  fn spill(&mut self, count: usize) -> io::Result<PathBuf> {
+    let Some(dir) = &self.spill_directory else {
+      return Err(io::Error::new(io::ErrorKind::Other, "no spill directory"));
+    };
+    fs::create_dir_all(dir)?;
+    let path = dir.join(format!("{}.spill", Utc::now().format("%F")));
+    let mut file = BufWriter::new(fs::OpenOptions::new().create(true).append(true).open(&path)?);
+    let oldest = self.pending.oldest(count);
+    for (_, record) in &oldest {
+      write!(file, "{},{}", record.channel, record.log_line())?;
+    }
+    file.flush()?;
+    if let Some(end) = oldest.last().map(|(i, _)| i + 1) {
+      self.pending.remove_before(end);
+      self.partial.retain(|i, _| *i >= end);
+    }
+    Ok(path)
+  }
+
+  /// Replaces the sinks and middleware with the ones for `config`.
+  ///
+  /// Pending records are written to the previous sinks first, so that removed channels don't lose any messages.
+  /// The previous sinks are kept if the new ones can't be created.
+  pub fn reconfigure(&mut self, config: &Config, middleware: Middleware) -> io::Result<()> {
+    let sinks = file_sinks(config)?;
+    self.flush()?;
+    self.sinks = sinks;
+    self.previous_scrubs = self.scrub_stats();
+    self.middleware = middleware;
+    self.backpressure = config.backpressure.clone();
+    self.spill_directory = Some(spill_directory(config));
+    self.coalesce = coalesce_interval(config);
+    Ok(())
+  }
+
+  /// Writes all pending records and flushes the sinks. Fails if any records couldn't be written.
+  pub fn flush(&mut self) -> io::Result<()> {
+    self.write_all_pending()?;
+    for sink in self.sinks.values_mut() {
+      sink.flush()?;
+    }
+    Ok(())
+  }
+
+  /// Whether new records are held back, because the previous write was less than `coalesce` ago.
+  /// They're never held back once more than `backpressure.max_pending` records are waiting.
+  fn is_coalescing(&self) -> bool {
+    match (self.coalesce, self.last_write) {
+      (Some(interval), Some(last_write)) => {
+        last_write.elapsed() < interval && self.pending.len() <= self.backpressure.max_pending
+      }
+      _ => false,
+    }
+  }
+
+  /// Writes pending records, coalesced per channel if coalescing is enabled
+  fn write_all_pending(&mut self) -> io::Result<()> {
+    if self.coalesce.is_none() {
+      return self.write_pending();
+    }
+    self.last_write = Some(Instant::now());
+    self.write_coalesced()
+  }
+
+  /// Writes the pending records of each channel together, in order. The records of a channel whose write fails are
+  /// kept, except for the ones which were written in full before the failure.
+  fn write_coalesced(&mut self) -> io::Result<()> {
+    let Self {
+      sinks,
+      pending,
+      summary,
+      partial,
+      ..
+    } = self;
+    pending.write_each(|channel, queue| {
+      let Some(sink) = sinks.get_mut(channel) else {
+        log::warn!("No sink for channel {}", channel);
+        queue.clear();
+        return Ok(());
+      };
+      let count = queue.len();
+      write_front(sink, queue, count, partial, summary).map_err(|e| {
+        summary.record_error(channel);
+        e
+      })
+    })
+  }
+
+  /// Writes the pending records of each channel in order, stopping at the first failure of each channel
+  fn write_pending(&mut self) -> io::Result<()> {
+    let Self {
+      sinks,
+      pending,
+      summary,
+      partial,
+      ..
+    } = self;
+    pending.write_each(|channel, queue| {
+      let Some(sink) = sinks.get_mut(channel) else {
+        log::warn!("No sink for channel {}", channel);
+        queue.clear();
+        return Ok(());
+      };
+      while !queue.is_empty() {
+        if let Err(e) = write_front(sink, queue, 1, partial, summary) {
+          summary.record_error(channel);
+          return Err(e);
+        }
+      }
+      Ok(())
+    })
+  }
+}
+
+/// Writes the first `count` records of `queue` to `sink` in one buffer, and removes the ones which were written in full.
+///
+/// If the write fails partway through a record, the record is kept along with the number of its bytes which were
+/// written in `partial`, so that the retry only writes the rest of its line instead of writing it twice.
+fn write_front(
+  sink: &mut Box<dyn Write + Send>,
+  queue: &mut VecDeque<(u64, RawLogRecord)>,
+  count: usize,
+  partial: &mut HashMap<u64, usize>,
+  summary: &mut SummarySink,
+) -> io::Result<()> {
+  let lines = queue
+    .iter()
+    .take(count)
+    .map(|(_, record)| record.log_line())
+    .collect::<Vec<_>>();
+  let buf = lines.concat();
+  let skip = queue.front().and_then(|(i, _)| partial.remove(i)).unwrap_or(0);
+  let mut written = skip;
+  let mut result = Ok(());
+  while written < buf.len() {
+    match sink.write(&buf.as_bytes()[written..]) {
+      Ok(0) => {
+        result = Err(io::Error::from(io::ErrorKind::WriteZero));
+        break;
+      }
+      Ok(n) => written += n,
+      Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+      Err(e) => {
+        result = Err(e);
+        break;
+      }
+    }
+  }
+
+  for line in &lines {
+    if written < line.len() {
+      break;
+    }
+    written -= line.len();
+    if let Some((_, record)) = queue.pop_front() {
+      summary.record(&record);
+    }
+  }
+  if written > 0 {
+    if let Some((i, _)) = queue.front() {
+      partial.insert(*i, written);
+    }
+  }
+  result
+}
+
+/// Spill files are written to the `spill` directory in the output directory
+fn spill_directory(config: &Config) -> PathBuf {
+  config.output_directory.join("spill")
+}
+
+fn coalesce_interval(config: &Config) -> Option<Duration> {
+  (config.coalesce_ms > 0).then(|| Duration::from_millis(config.coalesce_ms))
+}
+
+/// Creates a [`DailyLogSink`] for each channel in `config`, rotated every `config.rotation`
+fn file_sinks(config: &Config) -> io::Result<HashMap<String, Box<dyn Write + Send>>> {
+  let mut sinks = HashMap::with_capacity(config.channels.len());
+  for channel in config.channels.iter() {
+    log::info!("Initializing sink for {}", channel.name);
+    sinks.insert(
+      channel.name.clone(),
+      Box::new(DailyLogSink::new(
+        config.output_directory.clone(),
+        channel.name.clone(),
+        channel.buffer,
+        config.rotation,
+      )?) as Box<dyn Write + Send>,
+    );
+  }
+  Ok(sinks)
+}
+
+/// File sink which writes to a new file for each day, or each hour with [`Rotation::Hourly`]
 pub struct DailyLogSink {
   log_file_prefix: String,
   log_dir: PathBuf,
-  date: DateTime<Utc>,
+  rotation: Rotation,
+  /// Date (and hour) in the name of the current file
+  period: String,
   file: BufWriter<std::fs::File>,
+  sizer: Option<BufferSizer>,
+}
+
+/// Tracks the traffic of a channel to pick its buffer size
+struct BufferSizer {
+  bounds: AutoBuffer,
+  window_start: Instant,
+  bytes: usize,
 }
 
-fn open_log_file(dir: &Path, prefix: &str) -> io::Result<File> {
-  let date = Utc::now().format("%F");
+impl BufferSizer {
+  fn new(bounds: AutoBuffer) -> Self {
+    Self {
+      bounds,
+      window_start: Instant::now(),
+      bytes: 0,
+    }
+  }
+
+  /// Records a write of `len` bytes, and returns the new buffer size once per interval
+  fn record(&mut self, len: usize) -> Option<usize> {
+    self.bytes += len;
+    let elapsed = self.window_start.elapsed();
+    let interval = Duration::from_secs(self.bounds.interval);
+    if elapsed < interval {
+      return None;
+    }
+
+    // Scaled to the interval, so that a write after a long pause shrinks the buffer
+    let per_interval = (self.bytes as f64 * interval.as_secs_f64() / elapsed.as_secs_f64()) as usize;
+    self.window_start = Instant::now();
+    self.bytes = 0;
+    // Rounded to a power of two, so that small changes in traffic don't cause a resize
+    Some(
+      per_interval
+        .checked_next_power_of_two()
+        .unwrap_or(usize::MAX)
+        .clamp(self.bounds.min, self.bounds.max),
+    )
+  }
+}
+
+/// Date (and hour) of `time` in the file names of `rotation`
+fn period_of(time: DateTime<Utc>, rotation: Rotation) -> String {
+  match rotation {
+    Rotation::Daily => time.format("%F").to_string(),
+    Rotation::Hourly => time.format("%F-%H").to_string(),
+  }
+}
+
+fn open_log_file(dir: &Path, prefix: &str, period: &str) -> io::Result<File> {
   fs::OpenOptions::new()
     .create(true)
     .append(true)
-    .open(dir.join(format!("{prefix}-{date}.log")))
+    .open(dir.join(format!("{prefix}-{period}.log")))
 }
 
 impl DailyLogSink {
-  pub fn new(mut log_dir: PathBuf, log_file_prefix: String, buf_size: usize) -> io::Result<Self> {
+  pub fn new(mut log_dir: PathBuf, log_file_prefix: String, buffer: Buffer, rotation: Rotation) -> io::Result<Self> {
     log_dir = log_dir.join(&log_file_prefix);
     if !log_dir.exists() {
       fs::create_dir_all(&log_dir)?;
     }
-    let date = Utc::now();
-    let file = open_log_file(&log_dir, &log_file_prefix).map(|file| BufWriter::with_capacity(buf_size, file))?;
+    let period = period_of(Utc::now(), rotation);
+    let (buf_size, sizer) = match buffer {
+      Buffer::Fixed(size) => (size, None),
+      Buffer::Auto(bounds) => (bounds.min, Some(BufferSizer::new(bounds))),
+    };
+    let file =
+      open_log_file(&log_dir, &log_file_prefix, &period).map(|file| BufWriter::with_capacity(buf_size, file))?;
 
     Ok(DailyLogSink {
       log_file_prefix,
       log_dir,
-      date,
+      rotation,
+      period,
       file,
+      sizer,
     })
   }
+
+  /// Flushes the buffer and replaces it with one of `size` bytes
+  fn resize_buffer(&mut self, size: usize) -> io::Result<()> {
+    let previous = self.file.capacity();
+    if size == previous {
+      return Ok(());
+    }
+    self.file.flush()?;
+    // `BufWriter` can't be resized, so the file is moved into a new one through a duplicated handle.
+    // The old buffer is empty, so dropping it doesn't write anything.
+    self.file = BufWriter::with_capacity(size, self.file.get_ref().try_clone()?);
+    log::info!(
+      "Resized buffer of {} from {} to {} bytes",
+      self.log_file_prefix,
+      previous,
+      size
+    );
+    Ok(())
+  }
 }
 
 impl Write for DailyLogSink {
   fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-    // rotate file every day
-    let today = Utc::now();
-    if today.signed_duration_since(self.date).num_days() > 0 {
-      self.date = today;
+    // rotate file every day (or hour)
+    let period = period_of(Utc::now(), self.rotation);
+    if period != self.period {
       self.file.flush()?;
-      *self.file.get_mut() = open_log_file(&self.log_dir, &self.log_file_prefix)?;
+      *self.file.get_mut() = open_log_file(&self.log_dir, &self.log_file_prefix, &period)?;
+      self.period = period;
+    }
+    if let Some(size) = self.sizer.as_mut().and_then(|sizer| sizer.record(buf.len())) {
+      self.resize_buffer(size)?;
     }
     // then actually write
     self.file.write(buf)
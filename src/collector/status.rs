@@ -0,0 +1,152 @@
+//! Liveness of the collector, served as JSON over plain HTTP.
+
+use std::{
+  collections::BTreeMap,
+  net::SocketAddr,
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+  },
+};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+#[derive(Debug, Default, Clone, Serialize)]
+struct Snapshot {
+  /// Whether the collector is connected to Twitch
+  connected: bool,
+  connected_since: Option<DateTime<Utc>>,
+  /// Number of attempts to reconnect to Twitch since the collector started, successful or not
+  reconnect_attempts: u64,
+  /// Counts the reconnection attempts while they're made, see [`twitch_api::TwitchStream::reconnect_counter`]
+  #[serde(skip)]
+  reconnect_counter: Option<Arc<AtomicU64>>,
+  /// When the last batch of messages was received
+  last_message_at: Option<DateTime<Utc>>,
+  /// Number of channels the collector should be in
+  channels: usize,
+  /// Number of channels whose JOIN was acknowledged
+  joined: usize,
+  /// Channels whose JOIN wasn't acknowledged, e.g. because they're suspended or misspelled.
+  /// They're joined again with an exponential backoff.
+  unjoined: Vec<String>,
+  /// Channels which were never joined or were reported as suspended, and are only probed every few hours
+  quarantined: Vec<String>,
+  /// Records waiting to be written because a sink is failing
+  pending_records: usize,
+  /// Total time the `block` backpressure policy held up receiving messages
+  blocked_ms: u64,
+  /// Records written to spill files by the `spill` backpressure policy
+  spilled_records: u64,
+  /// Messages which the `normalize` middleware converted to NFC
+  normalized_records: u64,
+  /// Characters removed by the `normalize` middleware
+  scrubbed_chars: u64,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Status(Arc<Mutex<Snapshot>>);
+
+impl Status {
+  /// Records that `conn` was connected, or reconnected
+  pub fn connected(&self, conn: &twitch_api::TwitchStream) {
+    let mut status = self.0.lock().unwrap();
+    status.connected = true;
+    status.connected_since = Some(Utc::now());
+    status.reconnect_counter = Some(conn.reconnect_counter());
+  }
+
+  pub fn disconnected(&self) {
+    let mut status = self.0.lock().unwrap();
+    status.connected = false;
+    status.connected_since = None;
+  }
+
+  /// Records that a batch of messages was received on `conn`
+  pub fn received(&self, conn: &twitch_api::TwitchStream) {
+    let membership = conn.membership();
+    let mut status = self.0.lock().unwrap();
+    status.last_message_at = Some(Utc::now());
+    status.unjoined = membership.unjoined();
+    status.quarantined = membership.quarantined();
+    status.joined = membership.joined();
+    status.channels = status.joined + status.unjoined.len() + status.quarantined.len();
+  }
+
+  /// Records the backpressure stats of the tenant's sinks
+  pub fn sinks(&self, stats: crate::sink::BackpressureStats) {
+    let mut status = self.0.lock().unwrap();
+    status.pending_records = stats.pending;
+    status.blocked_ms = stats.blocked.as_millis() as u64;
+    status.spilled_records = stats.spilled;
+  }
+
+  /// Records the changes made by the tenant's `normalize` middleware
+  pub fn scrubbed(&self, stats: crate::middleware::ScrubStats) {
+    let mut status = self.0.lock().unwrap();
+    status.normalized_records = stats.normalized;
+    status.scrubbed_chars = stats.scrubbed;
+  }
+
+  fn snapshot(&self) -> Snapshot {
+    let mut snapshot = self.0.lock().unwrap().clone();
+    if let Some(counter) = &snapshot.reconnect_counter {
+      snapshot.reconnect_attempts = counter.load(Ordering::Relaxed);
+    }
+    snapshot
+  }
+}
+
+/// Renders the status of a config without tenants as is, and the status of each tenant under `tenants` otherwise
+fn render(statuses: &[(Option<String>, Status)]) -> String {
+  match statuses {
+    [(None, status)] => serde_json::to_string(&status.snapshot()).unwrap(),
+    _ => {
+      let tenants = statuses
+        .iter()
+        .map(|(tenant, status)| (tenant.clone().unwrap_or_default(), status.snapshot()))
+        .collect::<BTreeMap<_, _>>();
+      serde_json::json!({ "tenants": tenants }).to_string()
+    }
+  }
+}
+
+/// Serves the status over plain HTTP. Every request receives the same response, regardless of its path.
+pub fn spawn_server(address: SocketAddr, statuses: Vec<(Option<String>, Status)>) -> tokio::task::JoinHandle<()> {
+  tokio::spawn(async move {
+    let listener = match tokio::net::TcpListener::bind(address).await {
+      Ok(listener) => listener,
+      Err(e) => {
+        log::error!("[status] Failed to bind to {address}: {e}");
+        return;
+      }
+    };
+    log::info!("[status] Listening on {address}");
+
+    loop {
+      let (mut socket, _) = match listener.accept().await {
+        Ok(conn) => conn,
+        Err(e) => {
+          log::warn!("[status] Failed to accept a connection: {e}");
+          continue;
+        }
+      };
+      let body = render(&statuses);
+      tokio::spawn(async move {
+        // The request itself is irrelevant, but it has to be read before responding
+        let mut buf = [0u8; 1024];
+        let _ = socket.read(&mut buf).await;
+        let response = format!(
+          "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+          body.len(),
+          body
+        );
+        if let Err(e) = socket.write_all(response.as_bytes()).await {
+          log::warn!("[status] Failed to respond: {e}");
+        }
+      });
+    }
+  })
+}
@@ -0,0 +1,150 @@
+//! Daily digest of the collected messages.
+
+use std::collections::{BTreeMap, HashSet};
+
+use chrono::{NaiveDate, Utc};
+use serde::Deserialize;
+
+use crate::sink::RawLogRecord;
+
+/// Discord rejects messages longer than 2000 characters
+const DISCORD_MAX_LENGTH: usize = 2000;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookFormat {
+  #[default]
+  Discord,
+  Slack,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Webhook {
+  pub url: String,
+  #[serde(default)]
+  pub format: WebhookFormat,
+}
+
+impl Webhook {
+  async fn post(&self, client: &reqwest::Client, text: &str) -> reqwest::Result<()> {
+    let body = match self.format {
+      WebhookFormat::Discord => {
+        let mut text = format!("```\n{text}\n```");
+        if text.len() > DISCORD_MAX_LENGTH {
+          let mut end = DISCORD_MAX_LENGTH - "…\n```".len();
+          while !text.is_char_boundary(end) {
+            end -= 1;
+          }
+          text.truncate(end);
+          text.push_str("…\n```");
+        }
+        serde_json::json!({ "content": text })
+      }
+      WebhookFormat::Slack => serde_json::json!({ "text": format!("```{text}```") }),
+    };
+    client
+      .post(&self.url)
+      .json(&body)
+      .send()
+      .await?
+      .error_for_status()
+      .map(|_| ())
+  }
+}
+
+#[derive(Default)]
+struct ChannelSummary {
+  messages: usize,
+  chatters: HashSet<String>,
+  errors: usize,
+}
+
+/// Aggregates per-channel statistics in memory, and reports them once a day.
+///
+/// The report is logged, and posted to the webhook if one is configured.
+pub struct SummarySink {
+  date: NaiveDate,
+  channels: BTreeMap<String, ChannelSummary>,
+  webhook: Option<Webhook>,
+  client: reqwest::Client,
+  /// Included in the report, so that the reports of tenants which share a webhook can be told apart
+  tenant: Option<String>,
+}
+
+impl SummarySink {
+  pub fn new(webhook: Option<Webhook>) -> Self {
+    Self {
+      date: Utc::now().date_naive(),
+      channels: BTreeMap::new(),
+      webhook,
+      client: reqwest::Client::new(),
+      tenant: None,
+    }
+  }
+
+  pub fn with_tenant(mut self, tenant: Option<String>) -> Self {
+    self.tenant = tenant;
+    self
+  }
+
+  /// Counts a record which was successfully written
+  pub fn record(&mut self, record: &RawLogRecord) {
+    let summary = self.channels.entry(record.channel.clone()).or_default();
+    summary.messages += 1;
+    if !summary.chatters.contains(&record.chatter) {
+      summary.chatters.insert(record.chatter.clone());
+    }
+  }
+
+  /// Counts a failed write
+  pub fn record_error(&mut self, channel: &str) {
+    self.channels.entry(channel.to_owned()).or_default().errors += 1;
+  }
+
+  /// Reports and resets the statistics if the day has changed since the last report
+  pub fn rotate(&mut self) {
+    let today = Utc::now().date_naive();
+    if today == self.date {
+      return;
+    }
+
+    let report = self.report();
+    self.date = today;
+    self.channels.clear();
+
+    log::info!("{report}");
+    if let Some(webhook) = self.webhook.clone() {
+      let client = self.client.clone();
+      tokio::spawn(async move {
+        if let Err(e) = webhook.post(&client, &report).await {
+          log::error!("Failed to post the daily summary: {e}");
+        }
+      });
+    }
+  }
+
+  fn report(&self) -> String {
+    use std::fmt::Write;
+
+    let mut report = match &self.tenant {
+      Some(tenant) => format!("Summary of {} for {}", tenant, self.date.format("%F")),
+      None => format!("Summary for {}", self.date.format("%F")),
+    };
+    let (mut messages, mut errors) = (0, 0);
+    for (channel, summary) in &self.channels {
+      messages += summary.messages;
+      errors += summary.errors;
+      write!(
+        report,
+        "\n#{channel}: {} messages, {} chatters, {} errors",
+        summary.messages,
+        summary.chatters.len(),
+        summary.errors
+      )
+      .unwrap();
+    }
+    write!(report, "\nTotal: {messages} messages, {errors} errors").unwrap();
+    report
+  }
+}
@@ -0,0 +1,170 @@
+//! Periodic snapshots of the viewer count of each channel, taken from the Helix streams API.
+//!
+//! Snapshots are appended to a `viewers-YYYY-MM-DD.jsonl` file in the output directory, which the ingester imports.
+
+use std::{
+  fs::OpenOptions,
+  io::Write,
+  path::PathBuf,
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use chrono::Utc;
+use db::viewers::Snapshot;
+use serde::Deserialize;
+use tokio::sync::watch;
+
+const STREAMS_URL: &str = "https://api.twitch.tv/helix/streams";
+/// Maximum number of `user_login` parameters in a single request
+const MAX_LOGINS_PER_REQUEST: usize = 100;
+
+fn default_interval() -> u64 {
+  300
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ViewerSnapshots {
+  /// Client ID of the application which `token` belongs to
+  pub client_id: String,
+  /// App or user access token, without the `oauth:` prefix
+  pub token: String,
+  /// How often snapshots are taken, in seconds
+  #[serde(default = "default_interval")]
+  pub interval: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamsResponse {
+  data: Vec<Stream>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Stream {
+  user_login: String,
+  viewer_count: i32,
+  title: String,
+  game_name: String,
+}
+
+/// Takes a snapshot of every channel in `channels` each interval, until the channel list is dropped
+pub async fn poll(config: ViewerSnapshots, output_directory: PathBuf, mut channels: watch::Receiver<Vec<String>>) {
+  let client = reqwest::Client::new();
+  let mut interval = tokio::time::interval(Duration::from_secs(config.interval.max(1)));
+  interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+  loop {
+    tokio::select! {
+      _ = interval.tick() => (),
+      changed = channels.changed() => match changed {
+        // The new channels are picked up on the next tick
+        Ok(()) => continue,
+        Err(_) => break,
+      },
+    }
+
+    let logins = channels.borrow().clone();
+    match snapshot(&client, &config, &logins).await {
+      Ok(snapshots) => {
+        if let Err(e) = write(&output_directory, &snapshots) {
+          log::error!("[viewers] failed to write {} snapshot(s): {}", snapshots.len(), e);
+        }
+      }
+      Err(e) => log::error!("[viewers] failed to fetch streams: {}", e),
+    }
+  }
+}
+
+/// Fetches the streams of `logins`. Channels which aren't live get an offline snapshot.
+async fn snapshot(
+  client: &reqwest::Client,
+  config: &ViewerSnapshots,
+  logins: &[String],
+) -> anyhow::Result<Vec<Snapshot>> {
+  let taken_at = Utc::now();
+  let mut snapshots = Vec::with_capacity(logins.len());
+  for chunk in logins.chunks(MAX_LOGINS_PER_REQUEST) {
+    let streams = fetch_streams(client, config, chunk).await?;
+    for login in chunk {
+      let stream = streams.iter().find(|s| s.user_login.eq_ignore_ascii_case(login));
+      snapshots.push(Snapshot {
+        channel: login.clone(),
+        taken_at,
+        live: stream.is_some(),
+        viewer_count: stream.map_or(0, |s| s.viewer_count),
+        title: stream.map(|s| s.title.clone()),
+        game: stream.map(|s| s.game_name.clone()),
+      });
+    }
+  }
+  Ok(snapshots)
+}
+
+/// Requests the live streams of `logins`, respecting the rate limit.
+///
+/// Helix reports the remaining requests and when the bucket refills in the `Ratelimit-*` headers.
+/// When the bucket is empty, or a request is rejected with 429, this waits until the reset time before continuing.
+async fn fetch_streams(
+  client: &reqwest::Client,
+  config: &ViewerSnapshots,
+  logins: &[String],
+) -> anyhow::Result<Vec<Stream>> {
+  let query = logins
+    .iter()
+    .map(|login| ("user_login", login.as_str()))
+    .collect::<Vec<_>>();
+  loop {
+    let response = client
+      .get(STREAMS_URL)
+      .header("Client-Id", &config.client_id)
+      .bearer_auth(&config.token)
+      .query(&query)
+      .query(&[("first", MAX_LOGINS_PER_REQUEST)])
+      .send()
+      .await?;
+
+    let header = |name: &str| {
+      response
+        .headers()
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok())
+    };
+    let remaining = header("Ratelimit-Remaining");
+    let reset_wait = header("Ratelimit-Reset").map(|reset| {
+      let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+      Duration::from_secs(reset.saturating_sub(now).max(1))
+    });
+
+    if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+      let wait = reset_wait.unwrap_or(Duration::from_secs(60));
+      log::warn!("[viewers] rate limited, retrying in {:?}", wait);
+      tokio::time::sleep(wait).await;
+      continue;
+    }
+
+    let streams = response.error_for_status()?.json::<StreamsResponse>().await?.data;
+    if let (Some(0), Some(wait)) = (remaining, reset_wait) {
+      log::warn!("[viewers] rate limit exhausted, waiting {:?}", wait);
+      tokio::time::sleep(wait).await;
+    }
+    return Ok(streams);
+  }
+}
+
+fn write(output_directory: &std::path::Path, snapshots: &[Snapshot]) -> anyhow::Result<()> {
+  let path = output_directory.join(format!("viewers-{}.jsonl", Utc::now().format("%Y-%m-%d")));
+  let mut buffer = Vec::new();
+  for snapshot in snapshots {
+    serde_json::to_writer(&mut buffer, snapshot)?;
+    buffer.push(b'\n');
+  }
+  OpenOptions::new()
+    .create(true)
+    .append(true)
+    .open(path)?
+    .write_all(&buffer)?;
+  Ok(())
+}
@@ -9,6 +9,14 @@ fn main() -> Result<()> {
     .map(PathBuf::from)
     .unwrap_or_else(|_| PathBuf::from(CARGO_MANIFEST_DIR).join("models").join("model.chain"));
 
+  if std::env::args().skip(1).any(|arg| arg == "--validate") {
+    println!("Validating model {}...", model_dir.display());
+    let mut reader = std::io::BufReader::new(std::fs::File::open(&model_dir)?);
+    let summary = chain::ser::validate(&mut reader)?;
+    println!("{summary:#?}");
+    return Ok(());
+  }
+
   println!("Loading model from {}...", model_dir.display());
   let chain = chain::load_chain_of_any_supported_order(model_dir)?;
   let mut rl = rustyline::DefaultEditor::new().unwrap();
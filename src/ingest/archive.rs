@@ -0,0 +1,76 @@
+//! Content-addressed archive of ingested log files.
+//!
+//! Each file is stored as `<archive>/ab/cd/abcd...`, named after the hex SHA-256 of its contents, and recorded in the
+//! `ingested_files` table along with its original path. A file whose hash was already recorded is a re-import.
+
+use sha2::{Digest, Sha256};
+use std::{
+  fs, io,
+  path::{Path, PathBuf},
+  str::FromStr,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+  Copy,
+  Move,
+}
+
+impl FromStr for Mode {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "copy" => Ok(Self::Copy),
+      "move" => Ok(Self::Move),
+      _ => anyhow::bail!("Invalid archive mode `{s}`, expected `copy` or `move`"),
+    }
+  }
+}
+
+pub struct Archive {
+  dir: PathBuf,
+  mode: Mode,
+}
+
+impl Archive {
+  pub fn new(dir: PathBuf, mode: Mode) -> Self {
+    Self { dir, mode }
+  }
+
+  /// Hex-encoded SHA-256 of `contents`
+  pub fn hash(contents: &[u8]) -> String {
+    hex::encode(Sha256::digest(contents))
+  }
+
+  /// Path of the file with the hash `hash` in the archive
+  pub fn path_of(&self, hash: &str) -> PathBuf {
+    self.dir.join(&hash[..2]).join(&hash[2..4]).join(hash)
+  }
+
+  /// Copies `source` into the archive unless it's already there, and returns its path in the archive.
+  ///
+  /// The source is kept even when files are moved, so that it isn't lost if the file fails to be recorded afterwards.
+  /// It's removed by [`Archive::release`] once it is.
+  pub fn store(&self, source: &Path, hash: &str) -> io::Result<PathBuf> {
+    let path = self.path_of(hash);
+    if !path.exists() {
+      fs::create_dir_all(path.parent().unwrap())?;
+      // Copied under a temporary name first, so that an interrupted copy doesn't leave a partial file behind
+      let tmp = path.with_extension("tmp");
+      fs::copy(source, &tmp)?;
+      fs::rename(&tmp, &path)?;
+    }
+    Ok(path)
+  }
+
+  /// Removes `source` if files are moved into the archive, and it was stored with the hash `hash`.
+  /// Returns whether it was removed.
+  pub fn release(&self, source: &Path, hash: &str) -> io::Result<bool> {
+    if self.mode != Mode::Move || !self.path_of(hash).exists() {
+      return Ok(false);
+    }
+    fs::remove_file(source)?;
+    Ok(true)
+  }
+}
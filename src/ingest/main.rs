@@ -1,45 +1,356 @@
 use anyhow::Result;
-use regex::Regex;
+use archive::Archive;
+use parse::{Format, Line, Parser};
+use quarantine::Quarantine;
+use scs_config::sinks::LogFileName;
 use std::{
   env, fs,
+  io::BufRead,
   path::{Path, PathBuf},
 };
 use structopt::StructOpt;
+use timezone::{Timezones, Zone};
+use validate::Limits;
 use walkdir::{DirEntry, WalkDir};
 
+mod archive;
+mod parse;
+mod quarantine;
+mod timezone;
+mod validate;
+
 #[derive(Debug, StructOpt)]
 #[structopt(name = "ingest", about = "Ingest Chatterino logs into a pgsql database")]
 struct Options {
   #[structopt(short, long, env = "INGEST_DB_URI")]
   uri: String,
-  #[structopt(short, long, env = "INGEST_LOGS_DIR", parse(from_os_str))]
-  logs: PathBuf,
+  #[structopt(short, long, env = "INGEST_LOGS_DIR", parse(from_os_str), required_unless = "stdin")]
+  logs: Option<PathBuf>,
+  /// Read a single log from stdin instead of a directory, `--channel` and `--date` must be set
+  #[structopt(long, conflicts_with = "logs")]
+  stdin: bool,
+  /// Channel of the log read from stdin
+  #[structopt(long)]
+  channel: Option<String>,
+  /// Date of the log read from stdin (YYYY-MM-DD)
+  #[structopt(long)]
+  date: Option<String>,
+  /// Format of the logs: `auto` detects the format of each line, `chatterino`, or `scs` (or `scs-v2`) for collector
+  /// logs
+  #[structopt(long, default_value = "auto")]
+  format: Format,
+  /// File to write lines which couldn't be parsed to
+  #[structopt(
+    short,
+    long,
+    env = "INGEST_QUARANTINE",
+    parse(from_os_str),
+    default_value = "quarantine.log"
+  )]
+  quarantine: PathBuf,
+  /// Directory to archive ingested log files in, named after the SHA-256 of their contents.
+  /// Files which were already archived are skipped.
+  #[structopt(long, env = "INGEST_ARCHIVE_DIR", parse(from_os_str))]
+  archive: Option<PathBuf>,
+  /// Whether log files are copied or moved into the archive: `copy` or `move`
+  #[structopt(long, default_value = "copy", requires = "archive")]
+  archive_mode: archive::Mode,
+  /// JSON file overriding the timezone of Chatterino logs per channel or per directory
+  #[structopt(long, env = "INGEST_TIMEZONES", parse(from_os_str))]
+  timezones: Option<PathBuf>,
+  /// Messages longer than this many bytes are quarantined instead of being inserted
+  #[structopt(long, env = "INGEST_MAX_MESSAGE_BYTES", default_value = "4096")]
+  max_message_bytes: usize,
+  /// Messages sent more than this many hours in the future are quarantined instead of being inserted
+  #[structopt(long, env = "INGEST_MAX_FUTURE_HOURS", default_value = "24")]
+  max_future_hours: i64,
+  /// Store messages compressed with a dictionary trained per channel
+  #[cfg(feature = "compression")]
+  #[structopt(long)]
+  compress: bool,
 }
 
-fn parse_known_tz_offset(tz: &str) -> Result<&'static str> {
-  Ok(match tz {
-    "EDT" => "-0400",
-    "EST" => "-0500",
-    "UTC" => "+0000",
-    _ => anyhow::bail!("Encountered unknown timezone: {}", tz),
-  })
+/// Inserts parsed messages, compressing them if `--compress` is set
+struct Inserter {
+  #[cfg(feature = "compression")]
+  compressor: Option<db::compression::Compressor>,
+}
+
+impl Inserter {
+  #[allow(unused_variables)]
+  fn new(opts: &Options) -> Self {
+    Self {
+      #[cfg(feature = "compression")]
+      compressor: opts.compress.then(db::compression::Compressor::new),
+    }
+  }
+
+  /// Inserts the messages in `soa_entry` with `executor`, which is cleared, counting them and recording the IDs of the
+  /// inserted rows in `snapshot`. Chatters and dictionaries are created with `db`, see [`db::logs::insert_batch_with`].
+  async fn insert(
+    &mut self,
+    db: &db::Database,
+    executor: impl db::sqlx::PgExecutor<'_>,
+    resolver: &mut db::resolver::UserResolver,
+    snapshot: &mut db::transfer_audits::Snapshot,
+    soa_entry: &mut db::logs::SOAEntry,
+  ) -> Result<()> {
+    snapshot.add(soa_entry);
+    #[cfg(feature = "compression")]
+    let mode = match &mut self.compressor {
+      Some(compressor) => db::logs::InsertMode::Compressed { resolver, compressor },
+      None => db::logs::InsertMode::Resolved { resolver },
+    };
+    #[cfg(not(feature = "compression"))]
+    let mode = db::logs::InsertMode::Resolved { resolver };
+    let stats = db::logs::insert_batch_with(db, executor, mode, soa_entry).await?;
+    snapshot.inserted(&stats.inserted);
+    log::debug!("Inserted {} messages in {:?}", stats.rows, stats.duration);
+    Ok(())
+  }
+}
+
+/// Compares the rows inserted during `snapshot` with the lines and messages it counted, and records the result
+async fn verify_transfer(db: &db::Database, source: &str, snapshot: db::transfer_audits::Snapshot) -> Result<()> {
+  let audit = db::transfer_audits::verify(db, source, snapshot).await?;
+  if audit.expected_rows() != audit.inserted_rows() {
+    log::warn!(
+      "{} ({} lines read, {} skipped, so {} rows were expected, {} were inserted)",
+      source,
+      audit.source_lines(),
+      audit.skipped_lines(),
+      audit.expected_rows(),
+      audit.inserted_rows()
+    );
+  }
+  for mismatch in audit.mismatches() {
+    log::warn!(
+      "{} {} {} (expected {} rows, {} were inserted)",
+      mismatch.channel(),
+      mismatch.day(),
+      source,
+      mismatch.expected(),
+      mismatch.inserted()
+    );
+  }
+  Ok(())
 }
 
 fn walk_logs(dir: impl AsRef<Path>) -> impl Iterator<Item = (String, String, DirEntry)> {
+  WalkDir::new(dir).into_iter().filter_map(|e| e.ok()).filter_map(|e| {
+    let name = LogFileName::parse(e.file_name().to_str()?)?;
+    Some((name.channel.to_owned(), name.date.to_owned(), e))
+  })
+}
+
+/// Viewer snapshot files written by the collector, named `viewers-YYYY-MM-DD.jsonl`
+fn walk_viewer_snapshots(dir: impl AsRef<Path>) -> impl Iterator<Item = DirEntry> {
   WalkDir::new(dir)
     .into_iter()
     .filter_map(|e| e.ok())
-    .filter(|e| e.path().extension() == Some(std::ffi::OsStr::new("log")))
-    .filter_map(|e| {
+    .filter(|e| e.path().extension() == Some(std::ffi::OsStr::new("jsonl")))
+    .filter(|e| {
       e.path()
         .file_stem()
         .and_then(|v| v.to_str())
-        .and_then(|v| v.split_once('-'))
-        .map(|(channel, date)| (channel.to_owned(), date.to_owned()))
-        .map(|(channel, date)| (channel, date, e))
+        .map_or(false, |v| v.starts_with("viewers-"))
     })
 }
 
+/// Inserts the snapshots in `path`. Snapshots which were already ingested are skipped.
+async fn ingest_viewer_snapshots(
+  db: &db::Database,
+  path: &Path,
+  quarantine: &mut Quarantine,
+  resolver: &mut db::resolver::UserResolver,
+) -> Result<()> {
+  let instant = std::time::Instant::now();
+  let content = fs::read_to_string(path)?;
+  let mut inserted = 0;
+  for (line_no, line) in content.split('\n').enumerate() {
+    if line.trim().is_empty() {
+      continue;
+    }
+    let snapshot = match serde_json::from_str::<db::viewers::Snapshot>(line) {
+      Ok(snapshot) => snapshot,
+      Err(e) => {
+        quarantine.add(path, line_no + 1, &e.to_string(), line)?;
+        continue;
+      }
+    };
+    let channel_id = resolver.resolve_channel(db, &snapshot.channel).await?;
+    if db::viewers::insert(db, channel_id, &snapshot).await? {
+      inserted += 1;
+    }
+  }
+  log::info!(
+    "{} ({} viewer snapshots inserted in {:.4}s)",
+    path.display(),
+    inserted,
+    instant.elapsed().as_secs_f64()
+  );
+  Ok(())
+}
+
+/// Parses the lines of the log of one channel and day
+struct LogReader<'p> {
+  parser: &'p Parser,
+  limits: &'p Limits,
+  format: Format,
+  channel_id: i32,
+  date: String,
+  /// Timezone of Chatterino messages, set by the last header
+  zone: Zone,
+  /// Replaces the timezone of the headers, if set
+  zone_override: Option<Zone>,
+  /// Number of Chatterino messages whose timestamp was changed by `zone_override`
+  adjusted: usize,
+  /// Number of lines which weren't messages, i.e. headers and empty lines
+  skipped: usize,
+}
+
+impl<'p> LogReader<'p> {
+  fn new(
+    parser: &'p Parser,
+    limits: &'p Limits,
+    format: Format,
+    channel_id: i32,
+    date: String,
+    zone_override: Option<Zone>,
+  ) -> Self {
+    Self {
+      parser,
+      limits,
+      format,
+      channel_id,
+      date,
+      zone: Zone::UTC,
+      zone_override,
+      adjusted: 0,
+      skipped: 0,
+    }
+  }
+
+  /// Parses `line` and adds the message to `soa_entry`, if it's a message. Messages which exceed the limits are
+  /// rejected with an error, like lines which can't be parsed.
+  fn read_line(&mut self, line: &str, soa_entry: &mut db::logs::SOAEntry) -> Result<()> {
+    let date = &self.date;
+    // format options: https://docs.rs/chrono/latest/chrono/format/strftime/index.html
+    let (sent_at, chatter, message, language, seq) = match self.parser.parse_log_line_as(line, self.format)? {
+      Line::Header { tz } => {
+        match Zone::parse(tz) {
+          Ok(zone) => self.zone = zone,
+          // The header's timezone doesn't matter if it's overridden
+          Err(e) if self.zone_override.is_none() => return Err(e),
+          Err(_) => (),
+        }
+        self.skipped += 1;
+        return Ok(());
+      }
+      Line::Chatterino { time, chatter, message } => {
+        let time = chrono::NaiveDateTime::parse_from_str(&format!("{date} {time}"), "%F %T")?;
+        let sent_at = self.zone_override.unwrap_or(self.zone).to_utc(&time)?;
+        if self.zone_override.is_some() && self.zone.to_utc(&time).ok() != Some(sent_at) {
+          self.adjusted += 1;
+        }
+        (sent_at, chatter, message, None, None)
+      }
+      // Collector logs don't have timestamps, so they're all placed at the start of the day
+      Line::Scs {
+        chatter,
+        message,
+        language,
+        seq,
+      } => (
+        chrono::DateTime::parse_from_str(&format!("{date} 00:00:00 +0000"), "%F %T %z")?.with_timezone(&chrono::Utc),
+        chatter,
+        message,
+        language,
+        seq,
+      ),
+      Line::Empty => {
+        self.skipped += 1;
+        return Ok(());
+      }
+    };
+    self.limits.check(chatter, message, sent_at)?;
+    soa_entry.add_with_sequence(
+      self.channel_id,
+      chatter.to_owned(),
+      sent_at,
+      message.to_owned(),
+      language.map(str::to_owned),
+      seq,
+    );
+    Ok(())
+  }
+}
+
+/// Number of lines read from stdin between inserts
+const STDIN_BATCH_SIZE: usize = 1_000_000;
+
+/// Ingests a single log of `channel` from stdin
+async fn ingest_stdin(
+  db: &db::Database,
+  opts: &Options,
+  parser: &Parser,
+  timezones: &Timezones,
+  quarantine: &mut Quarantine,
+  resolver: &mut db::resolver::UserResolver,
+  inserter: &mut Inserter,
+  soa_entry: &mut db::logs::SOAEntry,
+) -> Result<()> {
+  let (Some(channel), Some(date)) = (&opts.channel, &opts.date) else {
+    anyhow::bail!("--channel and --date are required with --stdin");
+  };
+  if chrono::NaiveDate::parse_from_str(date, "%F").is_err() {
+    anyhow::bail!("--date must be formatted as YYYY-MM-DD");
+  }
+
+  let channel_id = resolver.resolve_channel(db, channel).await?;
+  let limits = Limits::new(opts.max_message_bytes, opts.max_future_hours);
+  let mut reader = LogReader::new(
+    parser,
+    &limits,
+    opts.format,
+    channel_id,
+    date.clone(),
+    timezones.find(channel, None),
+  );
+  let source = Path::new("<stdin>");
+  let instant = std::time::Instant::now();
+  let mut snapshot = db::transfer_audits::Snapshot::start();
+  let (mut lines, mut quarantined) = (0, 0);
+  for (line_no, line) in std::io::stdin().lock().split(b'\n').enumerate() {
+    let line = line?;
+    let line = String::from_utf8_lossy(&line);
+    if let Err(e) = reader.read_line(&line, soa_entry) {
+      quarantine.add(source, line_no + 1, &e.to_string(), &line)?;
+      quarantined += 1;
+    }
+    lines += 1;
+    if soa_entry.len() >= STDIN_BATCH_SIZE {
+      inserter.insert(db, db, resolver, &mut snapshot, soa_entry).await?;
+      log::info!("{} {} <stdin> ({} lines read)", channel, date, lines);
+    }
+  }
+  inserter.insert(db, db, resolver, &mut snapshot, soa_entry).await?;
+  snapshot.read(lines as i64, (reader.skipped + quarantined) as i64);
+  verify_transfer(db, "<stdin>", snapshot).await?;
+
+  log::info!(
+    "{} {} <stdin> ({} lines inserted in {:.4}s)",
+    channel,
+    date,
+    lines,
+    instant.elapsed().as_secs_f64()
+  );
+  if reader.adjusted > 0 {
+    log::info!("{} {} <stdin> ({} timestamps adjusted)", channel, date, reader.adjusted);
+  }
+  Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
   if env::var("RUST_LOG").is_err() {
@@ -50,40 +361,91 @@ async fn main() -> Result<()> {
   let opts = Options::from_args_safe()?;
 
   log::info!("Connecting to {}", opts.uri);
-  let db = db::connect(opts.uri).await?;
+  let db = db::connect(opts.uri.as_str()).await?;
 
-  log::info!("Reading logs from {}", opts.logs.display());
-  let tz_re = Regex::new(r"# Start logging at \d{4}-\d{2}-\d{2} \d{2}:\d{2}:\d{2} (\w+)")?;
-  let msg_re = Regex::new(r"\[(\d{2}:\d{2}:\d{2})\]  (\w+): (.*)")?;
+  let parser = Parser::new()?;
+  let limits = Limits::new(opts.max_message_bytes, opts.max_future_hours);
+  let timezones = match &opts.timezones {
+    Some(path) => Timezones::load(path)?,
+    None => Timezones::default(),
+  };
+  // Files whose timestamps were changed by a timezone override, and how many
+  let mut adjusted = Vec::new();
+  let mut quarantine = Quarantine::new(opts.quarantine.clone());
 
   // NOTE: The channel name queries are going to slow this done somewhat, but it shouldn't be too bad.
   // If this turns out to be a problem, we can run this on a thread pool with each log line spawned as a task.
-  let mut cache = ahash::AHashMap::with_capacity(10); // set this to 1 million if the cache is used as the main username resolution strategy
+  let mut resolver = db::resolver::UserResolver::new(std::num::NonZeroUsize::new(1_000_000).unwrap());
   let mut soa_entry = db::logs::SOAEntry::new(2_000_000); // 56 bytes each * 2,000,000 = 100MB
-  for (channel, date, entry) in walk_logs(opts.logs) {
-    let channel_id = db::channels::get_or_create_channel(&db, &channel, true, &mut cache).await?;
+  let archive = opts.archive.clone().map(|dir| Archive::new(dir, opts.archive_mode));
+  let mut inserter = Inserter::new(&opts);
+
+  if opts.stdin {
+    log::info!("Reading a log from stdin");
+    ingest_stdin(
+      &db,
+      &opts,
+      &parser,
+      &timezones,
+      &mut quarantine,
+      &mut resolver,
+      &mut inserter,
+      &mut soa_entry,
+    )
+    .await?;
+  }
+
+  if let Some(logs) = &opts.logs {
+    log::info!("Reading logs from {}", logs.display());
+  }
+  for (channel, date, entry) in opts.logs.iter().flat_map(walk_logs) {
+    let channel_id = resolver.resolve_channel(&db, &channel).await?;
 
     let instant = std::time::Instant::now();
-    log::info!("{} {} {} (collect started)", channel, date, entry.path().display());
-    let mut file_tz_offset = "+0000";
     let content = fs::read_to_string(entry.path())?;
-    for line in content.split('\n') {
-      if let Some(timezone) = tz_re.captures(line).and_then(|v| v.get(1).map(|v| v.as_str())) {
-        file_tz_offset = parse_known_tz_offset(timezone)?;
-      } else if let Some((time, chatter, message)) = msg_re
-        .captures(line)
-        .and_then(|v| Some((v.get(1)?.as_str(), v.get(2)?.as_str(), v.get(3)?.as_str())))
-      {
-        let chatter = chatter.to_owned();
-        // format options: https://docs.rs/chrono/latest/chrono/format/strftime/index.html
-        let sent_at = chrono::DateTime::parse_from_str(&format!("{date} {time} {file_tz_offset}"), "%F %T %z")?
-          .with_timezone(&chrono::Utc);
-        let message = message.to_string();
-
-        soa_entry.add(channel_id, chatter, sent_at, message);
+    let hash = archive.as_ref().map(|_| Archive::hash(content.as_bytes()));
+    if let Some(hash) = &hash {
+      if let Some(file) = db::ingested_files::find(&db, hash).await? {
+        // A previous run may have recorded the file and failed to remove it
+        if let Some(archive) = &archive {
+          if archive.release(entry.path(), hash)? {
+            log::info!(
+              "{} {} {} (removed, it was archived already)",
+              channel,
+              date,
+              entry.path().display()
+            );
+            continue;
+          }
+        }
+        log::warn!(
+          "{} {} {} (skipped, same contents as {} ingested at {})",
+          channel,
+          date,
+          entry.path().display(),
+          file.original_path(),
+          file.ingested_at()
+        );
+        continue;
       }
     }
 
+    log::info!("{} {} {} (collect started)", channel, date, entry.path().display());
+    let relative_path = opts
+      .logs
+      .as_deref()
+      .and_then(|logs| entry.path().strip_prefix(logs).ok());
+    let zone_override = timezones.find(&channel, relative_path);
+    let mut reader = LogReader::new(&parser, &limits, opts.format, channel_id, date.clone(), zone_override);
+    let (mut lines, mut quarantined) = (0, 0);
+    for (line_no, line) in content.split('\n').enumerate() {
+      if let Err(e) = reader.read_line(line, &mut soa_entry) {
+        quarantine.add(entry.path(), line_no + 1, &e.to_string(), line)?;
+        quarantined += 1;
+      }
+      lines += 1;
+    }
+
     log::info!(
       "{} {} {} (collect finished in {:.4}s)",
       channel,
@@ -91,8 +453,42 @@ async fn main() -> Result<()> {
       entry.path().display(),
       instant.elapsed().as_secs_f64()
     );
+    if reader.adjusted > 0 {
+      adjusted.push((entry.path().to_owned(), reader.adjusted));
+    }
 
-    db::logs::insert_soa(&db, &mut soa_entry).await?;
+    let rows = soa_entry.len();
+    let mut snapshot = db::transfer_audits::Snapshot::start();
+    snapshot.read(lines, reader.skipped as i64 + quarantined);
+    let original_path = entry.path().to_string_lossy();
+    // The file is archived before it's recorded, so that a recorded file is always in the archive. With `move`, the
+    // source is only removed once the file was recorded.
+    let archived = match (&archive, &hash) {
+      (Some(archive), Some(hash)) => Some(archive.store(entry.path(), hash)?),
+      _ => None,
+    };
+    // The rows and the file's record are committed together, so that a file is never recorded without its rows, or
+    // inserted again after a crash in between
+    let mut tx = db.begin().await?;
+    inserter
+      .insert(&db, &mut *tx, &mut resolver, &mut snapshot, &mut soa_entry)
+      .await?;
+    if let Some(hash) = &hash {
+      db::ingested_files::record(&mut *tx, hash, &original_path, channel_id, rows as i64).await?;
+    }
+    tx.commit().await?;
+    verify_transfer(&db, &original_path, snapshot).await?;
+
+    if let (Some(archive), Some(hash), Some(archived)) = (&archive, &hash, &archived) {
+      archive.release(entry.path(), hash)?;
+      log::info!(
+        "{} {} {} (archived as {})",
+        channel,
+        date,
+        original_path,
+        archived.display()
+      );
+    }
 
     log::info!(
       "{} {} {} (file inserted in {:.4}s)\n",
@@ -102,5 +498,26 @@ async fn main() -> Result<()> {
       instant.elapsed().as_secs_f64()
     );
   }
+
+  for entry in opts.logs.iter().flat_map(walk_viewer_snapshots) {
+    ingest_viewer_snapshots(&db, entry.path(), &mut quarantine, &mut resolver).await?;
+  }
+
+  quarantine.finish()?;
+
+  if !adjusted.is_empty() {
+    log::info!("Timestamps adjusted by timezone overrides:");
+    for (path, count) in &adjusted {
+      log::info!("  {}: {} lines", path.display(), count);
+    }
+  }
+
+  let stats = resolver.stats();
+  log::info!(
+    "Resolved usernames: {} cache hits, {} cache misses, {} cached",
+    stats.hits,
+    stats.misses,
+    stats.size
+  );
   Ok(())
 }
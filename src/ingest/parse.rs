@@ -0,0 +1,137 @@
+//! Log line parsing.
+//!
+//! The format is detected per line, so that files which mix formats (e.g. a Chatterino log which was
+//! continued by the collector) are ingested completely.
+
+use anyhow::Result;
+use regex::Regex;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Line<'a> {
+  /// Chatterino header, e.g. `# Start logging at 2021-12-11 23:15:18 EST`.
+  /// Sets the timezone of the Chatterino messages which follow it, see [`crate::timezone::Zone::parse`].
+  Header {
+    tz: &'a str,
+  },
+  /// Chatterino message, e.g. `[23:15:18]  chatter: message`
+  Chatterino {
+    time: &'a str,
+    chatter: &'a str,
+    message: &'a str,
+  },
+  /// Collector message, e.g. `chatter,message`, `chatter@eng,message` if its language was detected, and
+  /// `chatter@eng#123,message` if it has a sequence number. These don't have a timestamp.
+  Scs {
+    chatter: &'a str,
+    message: &'a str,
+    language: Option<&'a str>,
+    seq: Option<i64>,
+  },
+  Empty,
+}
+
+/// Format of the lines of a log
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+  /// Detect the format of each line
+  Auto,
+  Chatterino,
+  /// Written by the collector, with or without the language and sequence number annotations added to its format
+  /// later on. Also accepted as `scs-v2`, the name of the annotated format.
+  Scs,
+}
+
+impl std::str::FromStr for Format {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    Ok(match s {
+      "auto" => Format::Auto,
+      "chatterino" => Format::Chatterino,
+      "scs" | "scs-v2" => Format::Scs,
+      _ => anyhow::bail!(
+        "Unknown format `{}`, expected one of `auto`, `chatterino`, `scs`, or `scs-v2`",
+        s
+      ),
+    })
+  }
+}
+
+pub struct Parser {
+  tz_re: Regex,
+  chatterino_re: Regex,
+  scs_re: Regex,
+}
+
+impl Parser {
+  pub fn new() -> Result<Self> {
+    Ok(Self {
+      tz_re: Regex::new(r"^# Start logging at \d{4}-\d{2}-\d{2} \d{2}:\d{2}:\d{2} (\S+)")?,
+      chatterino_re: Regex::new(r"^\[(\d{2}:\d{2}:\d{2})\]  (\w+): (.*)")?,
+      scs_re: Regex::new(r"^(\w+)(?:@([a-z]{3}))?(?:#(\d+))?,(.*)")?,
+    })
+  }
+
+  /// Detects the format of `line` and parses it. Fails if the line doesn't match any known format.
+  pub fn parse_log_line<'a>(&self, line: &'a str) -> Result<Line<'a>> {
+    let line = line.trim_end_matches('\r');
+    if line.trim().is_empty() {
+      return Ok(Line::Empty);
+    }
+    if let Some(captures) = self.tz_re.captures(line) {
+      return Ok(Line::Header {
+        tz: captures.get(1).unwrap().as_str(),
+      });
+    }
+    if let Some(captures) = self.chatterino_re.captures(line) {
+      return Ok(Line::Chatterino {
+        time: captures.get(1).unwrap().as_str(),
+        chatter: captures.get(2).unwrap().as_str(),
+        message: captures.get(3).unwrap().as_str(),
+      });
+    }
+    if let Some(captures) = self.scs_re.captures(line) {
+      return Ok(Line::Scs {
+        chatter: captures.get(1).unwrap().as_str(),
+        language: captures.get(2).map(|m| m.as_str()),
+        seq: captures.get(3).map(|m| m.as_str().parse()).transpose()?,
+        message: captures.get(4).unwrap().as_str(),
+      });
+    }
+    anyhow::bail!("Unknown line format")
+  }
+
+  /// Parses `line`, which must be in `format`
+  pub fn parse_log_line_as<'a>(&self, line: &'a str, format: Format) -> Result<Line<'a>> {
+    let parsed = self.parse_log_line(line)?;
+    match (format, &parsed) {
+      (Format::Auto, _)
+      | (_, Line::Empty)
+      | (Format::Chatterino, Line::Header { .. } | Line::Chatterino { .. })
+      | (Format::Scs, Line::Scs { .. }) => Ok(parsed),
+      (format, _) => anyhow::bail!("Line isn't in the {:?} format", format),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_format() {
+    assert_eq!("auto".parse::<Format>().unwrap(), Format::Auto);
+    assert_eq!("chatterino".parse::<Format>().unwrap(), Format::Chatterino);
+    assert_eq!("scs".parse::<Format>().unwrap(), Format::Scs);
+    assert_eq!("scs-v2".parse::<Format>().unwrap(), Format::Scs);
+    assert!("scs-v3".parse::<Format>().is_err());
+
+    let parser = Parser::new().unwrap();
+    for line in ["chatter,hello", "chatter@eng#12,hello"] {
+      assert!(parser.parse_log_line_as(line, Format::Scs).is_ok(), "{line}");
+    }
+    assert!(parser
+      .parse_log_line_as("[23:15:18]  chatter: hello", Format::Scs)
+      .is_err());
+  }
+}
@@ -0,0 +1,60 @@
+//! Lines which couldn't be parsed, or whose message was rejected (see [`crate::validate`]), are written to a quarantine
+//! file instead of being dropped.
+
+use std::{
+  collections::BTreeMap,
+  fs::File,
+  io::{self, BufWriter, Write},
+  path::{Path, PathBuf},
+};
+
+pub struct Quarantine {
+  path: PathBuf,
+  /// Created once the first line is quarantined
+  file: Option<BufWriter<File>>,
+  /// Number of quarantined lines per log file
+  counts: BTreeMap<PathBuf, usize>,
+}
+
+impl Quarantine {
+  pub fn new(path: PathBuf) -> Self {
+    Self {
+      path,
+      file: None,
+      counts: BTreeMap::new(),
+    }
+  }
+
+  /// Quarantines line number `line_no` (starting at 1) of `source`
+  pub fn add(&mut self, source: &Path, line_no: usize, reason: &str, line: &str) -> io::Result<()> {
+    let file = match &mut self.file {
+      Some(file) => file,
+      None => self.file.insert(BufWriter::new(File::create(&self.path)?)),
+    };
+    writeln!(file, "{}:{line_no}: {reason}\t{line}", source.display())?;
+    *self.counts.entry(source.to_owned()).or_default() += 1;
+    Ok(())
+  }
+
+  /// Appends a summary of the quarantined lines per file to the quarantine file, and logs it
+  pub fn finish(self) -> io::Result<()> {
+    let Some(mut file) = self.file else {
+      log::info!("No lines were quarantined");
+      return Ok(());
+    };
+
+    let total = self.counts.values().sum::<usize>();
+    writeln!(file, "# Summary: {total} lines from {} files", self.counts.len())?;
+    for (source, count) in &self.counts {
+      writeln!(file, "# {}: {count} lines", source.display())?;
+    }
+    file.flush()?;
+
+    log::warn!(
+      "{total} lines from {} files could not be parsed or were rejected, see {}",
+      self.counts.len(),
+      self.path.display()
+    );
+    Ok(())
+  }
+}
@@ -0,0 +1,183 @@
+//! Timezones of Chatterino timestamps.
+//!
+//! Chatterino headers only carry an abbreviation of the local timezone, which is often ambiguous or unknown, so the
+//! timezone can be overridden per channel or per directory.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, FixedOffset, NaiveDateTime, TimeZone, Utc};
+use serde::Deserialize;
+use std::{
+  collections::HashMap,
+  path::{Path, PathBuf},
+};
+
+/// Timezone which Chatterino timestamps are in
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Zone {
+  Fixed(FixedOffset),
+  /// IANA timezone, whose offset depends on daylight saving time
+  Named(chrono_tz::Tz),
+}
+
+impl Zone {
+  pub const UTC: Zone = Zone::Named(chrono_tz::UTC);
+
+  /// Parses one of the abbreviations written by Chatterino (`EDT`, `EST` and `UTC`), or an IANA name such as
+  /// `Europe/Berlin`
+  pub fn parse(tz: &str) -> Result<Self> {
+    let hours = match tz {
+      "EDT" => -4,
+      "EST" => -5,
+      "UTC" => 0,
+      _ => {
+        return tz
+          .parse::<chrono_tz::Tz>()
+          .map(Zone::Named)
+          .map_err(|_| anyhow::anyhow!("Encountered unknown timezone: {}", tz))
+      }
+    };
+    Ok(Zone::Fixed(FixedOffset::east_opt(hours * 3600).unwrap()))
+  }
+
+  /// Converts a local `time` in this timezone to UTC. Times which are skipped by a daylight saving transition fail,
+  /// and ambiguous ones resolve to the earlier time.
+  pub fn to_utc(&self, time: &NaiveDateTime) -> Result<DateTime<Utc>> {
+    let utc = match self {
+      Zone::Fixed(offset) => offset
+        .from_local_datetime(time)
+        .earliest()
+        .map(|t| t.with_timezone(&Utc)),
+      Zone::Named(tz) => tz.from_local_datetime(time).earliest().map(|t| t.with_timezone(&Utc)),
+    };
+    utc.with_context(|| format!("{time} doesn't exist in {self:?}"))
+  }
+}
+
+impl TryFrom<String> for Zone {
+  type Error = anyhow::Error;
+
+  fn try_from(tz: String) -> Result<Self> {
+    Zone::parse(&tz)
+  }
+}
+
+/// Timezone overrides, which replace the timezone of the headers of Chatterino logs
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Timezones {
+  /// Timezone of the logs of each channel
+  channels: HashMap<String, Zone>,
+  /// Timezone of the logs in each directory, relative to the logs directory
+  directories: HashMap<PathBuf, Zone>,
+}
+
+impl Timezones {
+  pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+    scs_config::read::<Timezones>(path)
+  }
+
+  /// Returns the timezone override of the log of `channel` at `path`, if there's one. Channel overrides take
+  /// precedence, then the override of the innermost directory containing `path`.
+  pub fn find(&self, channel: &str, path: Option<&Path>) -> Option<Zone> {
+    if let Some(zone) = self.channels.get(channel) {
+      return Some(*zone);
+    }
+    let path = path?;
+    self
+      .directories
+      .iter()
+      .filter(|(directory, _)| path.starts_with(directory))
+      .max_by_key(|(directory, _)| directory.components().count())
+      .map(|(_, zone)| *zone)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use chrono::NaiveDate;
+
+  fn time(y: i32, m: u32, d: u32, h: u32, min: u32) -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(y, m, d)
+      .unwrap()
+      .and_hms_opt(h, min, 0)
+      .unwrap()
+  }
+
+  fn utc(y: i32, m: u32, d: u32, h: u32, min: u32) -> DateTime<Utc> {
+    Utc.from_utc_datetime(&time(y, m, d, h, min))
+  }
+
+  #[test]
+  fn test_offsets() {
+    let noon = time(2023, 7, 1, 12, 0);
+    assert_eq!(
+      Zone::parse("EDT").unwrap().to_utc(&noon).unwrap(),
+      utc(2023, 7, 1, 16, 0)
+    );
+    assert_eq!(
+      Zone::parse("EST").unwrap().to_utc(&noon).unwrap(),
+      utc(2023, 7, 1, 17, 0)
+    );
+    assert_eq!(
+      Zone::parse("UTC").unwrap().to_utc(&noon).unwrap(),
+      utc(2023, 7, 1, 12, 0)
+    );
+    assert_eq!(Zone::UTC.to_utc(&noon).unwrap(), utc(2023, 7, 1, 12, 0));
+    // Fixed offsets ignore daylight saving time
+    let winter = time(2023, 1, 1, 12, 0);
+    assert_eq!(
+      Zone::parse("EDT").unwrap().to_utc(&winter).unwrap(),
+      utc(2023, 1, 1, 16, 0)
+    );
+  }
+
+  #[test]
+  fn test_dst_transitions() {
+    let berlin = Zone::parse("Europe/Berlin").unwrap();
+    assert_eq!(berlin, Zone::Named(chrono_tz::Europe::Berlin));
+    assert_eq!(berlin.to_utc(&time(2023, 1, 1, 12, 0)).unwrap(), utc(2023, 1, 1, 11, 0));
+    assert_eq!(berlin.to_utc(&time(2023, 7, 1, 12, 0)).unwrap(), utc(2023, 7, 1, 10, 0));
+    // Clocks skip from 02:00 to 03:00
+    assert!(berlin.to_utc(&time(2023, 3, 26, 2, 30)).is_err());
+    assert_eq!(berlin.to_utc(&time(2023, 3, 26, 3, 0)).unwrap(), utc(2023, 3, 26, 1, 0));
+    // Clocks go back from 03:00 to 02:00, the earlier 02:30 is in summer time
+    assert_eq!(
+      berlin.to_utc(&time(2023, 10, 29, 2, 30)).unwrap(),
+      utc(2023, 10, 29, 0, 30)
+    );
+  }
+
+  #[test]
+  fn test_invalid_zones() {
+    for tz in ["", "CEST", "Europe/Atlantis", "+02:00"] {
+      assert!(Zone::parse(tz).is_err(), "{tz}");
+    }
+    assert!(serde_json::from_str::<Zone>(r#""Mars/Olympus_Mons""#).is_err());
+    assert!(serde_json::from_str::<Timezones>(r#"{"channels": {"forsen": "Nowhere"}}"#).is_err());
+  }
+
+  #[test]
+  fn test_find() {
+    let timezones = serde_json::from_str::<Timezones>(
+      r#"{
+        "channels": {"forsen": "UTC"},
+        "directories": {"eu": "Europe/Berlin", "eu/uk": "Europe/London"}
+      }"#,
+    )
+    .unwrap();
+    let london = Zone::Named(chrono_tz::Europe::London);
+    assert_eq!(
+      timezones.find("forsen", Some(Path::new("eu/uk/forsen.log"))),
+      Some(Zone::UTC)
+    );
+    assert_eq!(timezones.find("xqc", Some(Path::new("eu/uk/xqc.log"))), Some(london));
+    assert_eq!(
+      timezones.find("xqc", Some(Path::new("eu/xqc.log"))),
+      Some(Zone::Named(chrono_tz::Europe::Berlin))
+    );
+    assert_eq!(timezones.find("xqc", Some(Path::new("us/xqc.log"))), None);
+    assert_eq!(timezones.find("xqc", None), None);
+  }
+}
@@ -0,0 +1,91 @@
+//! Limits checked for every message before it's buffered, so that a single malformed record (e.g. a huge message
+//! produced by a parsing bug) is quarantined with the reason, instead of failing the insert of its whole batch.
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+
+pub struct Limits {
+  max_message_bytes: usize,
+  /// Messages sent before this are rejected, Twitch chat didn't exist yet
+  earliest: DateTime<Utc>,
+  /// How far in the future messages may be, to allow for clock skew and timezone mistakes
+  max_future: Duration,
+}
+
+impl Limits {
+  pub fn new(max_message_bytes: usize, max_future_hours: i64) -> Self {
+    Self {
+      max_message_bytes,
+      earliest: Utc.with_ymd_and_hms(2011, 6, 6, 0, 0, 0).unwrap(),
+      max_future: Duration::hours(max_future_hours),
+    }
+  }
+
+  /// Fails with the reason if the message can't be inserted as is.
+  ///
+  /// Chatterino logs have display names, which may only differ from the login by case, see
+  /// [`twitch_api::is_valid_login`].
+  pub fn check(&self, chatter: &str, message: &str, sent_at: DateTime<Utc>) -> Result<()> {
+    if !twitch_api::is_valid_login(&chatter.to_ascii_lowercase()) {
+      bail!("Rejected: `{chatter}` isn't a valid chatter name");
+    }
+    if message.len() > self.max_message_bytes {
+      bail!(
+        "Rejected: the message is {} bytes long, the limit is {}",
+        message.len(),
+        self.max_message_bytes
+      );
+    }
+    // Postgres can't store NUL characters in text columns
+    if message.contains('\0') {
+      bail!("Rejected: the message contains a NUL character");
+    }
+    if sent_at < self.earliest {
+      bail!("Rejected: the message was sent at {sent_at}, before {}", self.earliest);
+    }
+    if sent_at > Utc::now() + self.max_future {
+      bail!("Rejected: the message was sent at {sent_at}, which is in the future");
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_chatter_names() {
+    let limits = Limits::new(500, 24);
+    let now = Utc::now();
+    for chatter in ["forsen", "Forsen", "a", "x_0", "abcdefghijklmnopqrstuvwxy"] {
+      assert!(limits.check(chatter, "hello", now).is_ok(), "{chatter}");
+    }
+    for chatter in [
+      "",
+      "abcdefghijklmnopqrstuvwxyz",
+      "a b",
+      "a-b",
+      "ünicode",
+      "日本語",
+      "a\r",
+    ] {
+      let error = limits.check(chatter, "hello", now).unwrap_err().to_string();
+      assert!(error.contains("isn't a valid chatter name"), "{chatter}: {error}");
+    }
+  }
+
+  #[test]
+  fn test_messages() {
+    let limits = Limits::new(5, 24);
+    let now = Utc::now();
+    assert!(limits.check("forsen", "hello", now).is_ok());
+    assert!(limits.check("forsen", "hello!", now).is_err());
+    assert!(limits.check("forsen", "a\0b", now).is_err());
+    assert!(limits
+      .check("forsen", "hi", Utc.with_ymd_and_hms(2011, 6, 5, 0, 0, 0).unwrap())
+      .is_err());
+    assert!(limits.check("forsen", "hi", now + Duration::hours(23)).is_ok());
+    assert!(limits.check("forsen", "hi", now + Duration::hours(25)).is_err());
+  }
+}
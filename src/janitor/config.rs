@@ -0,0 +1,74 @@
+use anyhow::Result;
+use serde::Deserialize;
+use std::{collections::HashMap, fs, path::PathBuf, time::Duration};
+
+fn default_batch_size() -> i64 {
+  10_000
+}
+
+const fn default_batch_delay() -> Duration {
+  Duration::from_millis(100)
+}
+
+const fn default_interval() -> Duration {
+  Duration::from_secs(24 * 60 * 60)
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+  /// Logs older than this are removed. Logs are kept forever if this is `null`.
+  #[serde(with = "humantime_serde")]
+  #[serde(default)]
+  pub max_age: Option<Duration>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+  /// Policy of the channels which don't have their own
+  #[serde(with = "humantime_serde")]
+  #[serde(default)]
+  pub default_max_age: Option<Duration>,
+  /// Per-channel policies
+  #[serde(default)]
+  pub channels: HashMap<String, Policy>,
+  /// Number of logs removed per transaction
+  #[serde(default = "default_batch_size")]
+  pub batch_size: i64,
+  /// Pause between batches, to limit the load on the database
+  #[serde(with = "humantime_serde")]
+  #[serde(default = "default_batch_delay")]
+  pub batch_delay: Duration,
+  /// How often the job runs
+  #[serde(with = "humantime_serde")]
+  #[serde(default = "default_interval")]
+  pub interval: Duration,
+  /// Directory to write removed logs to before they're deleted. Logs are only deleted if this isn't set.
+  #[serde(default)]
+  pub archive_directory: Option<PathBuf>,
+}
+
+impl Config {
+  pub fn load<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+    let config = scs_config::read::<Config>(path)?;
+    if config.batch_size <= 0 {
+      anyhow::bail!("config.batch_size must be positive");
+    }
+    if let Some(dir) = &config.archive_directory {
+      if !dir.exists() {
+        log::warn!("config.archive_directory does not exist, it will be created.");
+        fs::create_dir_all(dir)?;
+      }
+    }
+    Ok(config)
+  }
+
+  /// Maximum age of the logs of `channel`, or `None` if they're kept forever
+  pub fn max_age(&self, channel: &str) -> Option<Duration> {
+    match self.channels.get(channel) {
+      Some(policy) => policy.max_age,
+      None => self.default_max_age,
+    }
+  }
+}
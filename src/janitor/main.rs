@@ -0,0 +1,147 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use config::Config;
+use std::{
+  env,
+  fs::File,
+  io::{BufWriter, Write},
+  path::PathBuf,
+};
+use structopt::StructOpt;
+
+mod config;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "janitor", about = "Remove logs which are past their retention period")]
+struct Options {
+  #[structopt(short, long, env = "JANITOR_DB_URI")]
+  uri: String,
+  #[structopt(short, long, env = "JANITOR_CONFIG", parse(from_os_str))]
+  config: PathBuf,
+  /// Only report how many logs would be removed, then exit
+  #[structopt(long)]
+  dry_run: bool,
+  /// Run once and exit, instead of running every `interval`
+  #[structopt(long)]
+  once: bool,
+}
+
+/// Appends removed logs to `<archive_directory>/<channel>.jsonl`
+fn open_archive(config: &Config, channel: &str) -> Result<Option<BufWriter<File>>> {
+  Ok(match &config.archive_directory {
+    Some(dir) => Some(BufWriter::new(
+      std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(format!("{channel}.jsonl")))?,
+    )),
+    None => None,
+  })
+}
+
+/// Removes the logs of `channel` sent before `before` in batches, and returns how many were removed
+async fn remove_expired(
+  db: &db::Database,
+  config: &Config,
+  channel: &str,
+  channel_id: i32,
+  before: DateTime<Utc>,
+) -> Result<usize> {
+  let mut archive = open_archive(config, channel)?;
+  let mut removed = 0;
+  loop {
+    let mut tx = db.begin().await?;
+    #[allow(unused_mut)]
+    let mut batch = db::retention::delete_expired(&mut *tx, channel_id, before, config.batch_size).await?;
+    if batch.is_empty() {
+      break;
+    }
+    #[cfg(feature = "compression")]
+    if archive.is_some() {
+      db::compression::decompress(db, &mut batch).await?;
+    }
+
+    // The logs are only deleted once they're safely archived
+    if let Some(archive) = &mut archive {
+      for log in &batch {
+        serde_json::to_writer(&mut *archive, log)?;
+        archive.write_all(b"\n")?;
+      }
+      archive.flush()?;
+      archive.get_ref().sync_data()?;
+    }
+    tx.commit().await?;
+
+    removed += batch.len();
+    log::info!("{}: removed {} log(s) so far", channel, removed);
+    tokio::time::sleep(config.batch_delay).await;
+  }
+  Ok(removed)
+}
+
+async fn run(db: &db::Database, config: &Config, dry_run: bool) -> Result<()> {
+  let action = if config.archive_directory.is_some() {
+    "archived"
+  } else {
+    "deleted"
+  };
+
+  for channel in db::channels::get_logged_channels(db).await? {
+    let max_age = match config.max_age(&channel) {
+      Some(max_age) => max_age,
+      None => continue,
+    };
+    let before = Utc::now() - chrono::Duration::from_std(max_age)?;
+    let channel_id = db::channels::get_channel_id(db, &channel).await?;
+
+    if dry_run {
+      let count = db::retention::count_expired(db, channel_id, before).await?;
+      log::info!(
+        "[dry-run] {}: {} log(s) sent before {} would be {}",
+        channel,
+        count,
+        before,
+        action
+      );
+      continue;
+    }
+
+    let removed = remove_expired(db, config, &channel, channel_id, before).await?;
+    log::info!("{}: {} {} log(s) sent before {}", channel, action, removed, before);
+  }
+  Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+  if env::var("RUST_LOG").is_err() {
+    env::set_var("RUST_LOG", "INFO,sqlx=WARN");
+  }
+  env_logger::init();
+
+  if env::args().nth(1).as_deref() == Some("validate") {
+    return scs_config::validate_all(env::args().skip(2), |path| Config::load(path));
+  }
+
+  let opts = Options::from_args_safe()?;
+  let config = Config::load(&opts.config)?;
+  log::info!("Loaded config {:?}", config);
+
+  log::info!("Connecting to {}", opts.uri);
+  let db = db::connect(opts.uri).await?;
+
+  let mut interval = tokio::time::interval(config.interval);
+  loop {
+    interval.tick().await;
+    if let Err(e) = run(&db, &config, opts.dry_run).await {
+      log::error!("Failed to remove expired logs: {:?}", e);
+      if opts.once {
+        return Err(e);
+      }
+    }
+    if opts.once || opts.dry_run {
+      break;
+    }
+  }
+  Ok(())
+}
@@ -0,0 +1,309 @@
+//! Periodic snapshots of a training run, so that a crashed run can be resumed with `--resume-from`.
+//!
+//! A checkpoint directory contains the in-progress models and a `manifest.json`, which lists the models that were
+//! already saved, and the log files the in-progress models were trained on. Only the files listed in the manifest
+//! are ever removed, so pointing `checkpoint_directory` at a directory with other files doesn't lose them.
+
+use std::{
+  collections::{HashMap, HashSet},
+  fs,
+  path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{model::Model, Target};
+
+const MANIFEST: &str = "manifest.json";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Manifest {
+  /// Names of the models which were trained and saved in full
+  completed: HashSet<String>,
+  /// The model which was being trained, if any
+  in_progress: Option<InProgress>,
+  /// Names of the model files saved in the checkpoint directory
+  #[serde(default)]
+  models: HashSet<String>,
+  /// Bigram fingerprints of the completed models, so that a resumed run can include them in the similarity report
+  #[serde(default)]
+  fingerprints: HashMap<String, Vec<(String, String, f64)>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct InProgress {
+  name: String,
+  /// Log files the checkpointed models were trained on
+  files: HashSet<String>,
+}
+
+pub struct Checkpoints {
+  dir: PathBuf,
+  /// Number of files between checkpoints, `None` if checkpoints are only used to resume
+  interval: Option<usize>,
+  /// Whether this run writes checkpoints or resumes one, `dir` is left alone otherwise
+  active: bool,
+  manifest: Manifest,
+  /// Files processed since the last checkpoint
+  pending: Vec<String>,
+}
+
+impl Checkpoints {
+  /// Starts a new run, removing any previous checkpoint in `dir`
+  pub fn new(dir: PathBuf, interval: Option<usize>) -> anyhow::Result<Self> {
+    if dir.join(MANIFEST).exists() {
+      match read_manifest(&dir) {
+        Ok(previous) => {
+          log::warn!(
+            "Removing the previous checkpoint in {}, use --resume-from to resume it",
+            dir.display()
+          );
+          remove(&dir, &previous)?;
+        }
+        Err(e) => log::warn!("Keeping {}, it isn't a checkpoint: {}", dir.join(MANIFEST).display(), e),
+      }
+    }
+    Ok(Self {
+      dir,
+      interval,
+      active: interval.is_some(),
+      manifest: Manifest::default(),
+      pending: vec![],
+    })
+  }
+
+  /// Continues the run checkpointed in `dir`
+  pub fn resume(dir: PathBuf, interval: Option<usize>) -> anyhow::Result<Self> {
+    let manifest = read_manifest(&dir)?;
+    log::info!(
+      "Resuming from {}: {} model(s) completed{}",
+      dir.display(),
+      manifest.completed.len(),
+      manifest
+        .in_progress
+        .as_ref()
+        .map(|p| format!(", {} trained on {} file(s)", p.name, p.files.len()))
+        .unwrap_or_default()
+    );
+    Ok(Self {
+      dir,
+      interval,
+      active: true,
+      manifest,
+      pending: vec![],
+    })
+  }
+
+  /// Whether the models called `name` were saved before the run was interrupted
+  pub fn is_completed(&self, name: &str) -> bool {
+    self.manifest.completed.contains(name)
+  }
+
+  /// The fingerprint of the completed models called `name`, if one was recorded by [`Checkpoints::complete`]
+  pub fn fingerprint(&self, name: &str) -> Option<chain::Fingerprint> {
+    self.manifest.fingerprints.get(name).map(|bigrams| {
+      bigrams
+        .iter()
+        .map(|(first, second, value)| ((first.clone(), second.clone()), *value))
+        .collect()
+    })
+  }
+
+  /// Replaces the models of `targets` with their checkpointed versions, if `name` was being trained.
+  ///
+  /// Afterwards, [`Checkpoints::is_processed`] returns `true` for the files the restored models were trained on.
+  pub fn restore(&mut self, name: &str, targets: &mut [Target]) -> anyhow::Result<()> {
+    match &self.manifest.in_progress {
+      Some(in_progress) if in_progress.name == name => {
+        log::info!("=> Restoring {} from the checkpoint", name);
+        for target in targets.iter_mut() {
+          target.model = Model::load(&self.model_path(&target.name), target.model.order())?;
+        }
+      }
+      _ => {
+        self.manifest.in_progress = Some(InProgress {
+          name: name.to_owned(),
+          files: HashSet::new(),
+        })
+      }
+    }
+    Ok(())
+  }
+
+  /// Whether the in-progress models were already trained on `file`
+  pub fn is_processed(&self, file: &str) -> bool {
+    self
+      .manifest
+      .in_progress
+      .as_ref()
+      .map_or(false, |p| p.files.contains(file))
+  }
+
+  /// Records that `targets` were trained on `file`, and saves a checkpoint every `interval` files
+  pub fn after_file(&mut self, file: &str, targets: &[Target]) -> anyhow::Result<()> {
+    let Some(interval) = self.interval else {
+      return Ok(());
+    };
+    self.pending.push(file.to_owned());
+    if self.pending.len() >= interval {
+      self.save(targets)?;
+    }
+    Ok(())
+  }
+
+  /// Whether [`Checkpoints::after_file`] saves a checkpoint after the next file
+  pub fn is_due_after_next_file(&self) -> bool {
+    self
+      .interval
+      .map_or(false, |interval| self.pending.len() + 1 >= interval)
+  }
+
+  /// Marks the in-progress models as saved along with their `fingerprint`, and removes their checkpoint
+  pub fn complete(&mut self, targets: &[Target], fingerprint: Option<&chain::Fingerprint>) -> anyhow::Result<()> {
+    let Some(in_progress) = self.manifest.in_progress.take() else {
+      return Ok(());
+    };
+    self.pending.clear();
+    if let Some(fingerprint) = fingerprint {
+      let bigrams = fingerprint
+        .iter()
+        .map(|((first, second), value)| (first.clone(), second.clone(), value))
+        .collect();
+      self.manifest.fingerprints.insert(in_progress.name.clone(), bigrams);
+    }
+    self.manifest.completed.insert(in_progress.name);
+    if self.active {
+      fs::create_dir_all(&self.dir)?;
+      for target in targets {
+        let file = model_file(&target.name);
+        let path = self.dir.join(&file);
+        if path.exists() {
+          fs::remove_file(path)?;
+        }
+        self.manifest.models.remove(&file);
+      }
+      self.write_manifest()?;
+    }
+    Ok(())
+  }
+
+  /// Removes the checkpoint once the run has finished
+  pub fn finish(self) -> anyhow::Result<()> {
+    if self.active {
+      remove(&self.dir, &self.manifest)?;
+    }
+    Ok(())
+  }
+
+  fn save(&mut self, targets: &[Target]) -> anyhow::Result<()> {
+    fs::create_dir_all(&self.dir)?;
+    // The models are written before the manifest, so the manifest never lists files the saved models weren't trained on
+    for target in targets {
+      let file = model_file(&target.name);
+      let path = self.dir.join(&file);
+      let tmp = path.with_extension("chain.tmp");
+      target.model.save(&tmp)?;
+      fs::rename(&tmp, &path)?;
+      self.manifest.models.insert(file);
+    }
+    if let Some(in_progress) = &mut self.manifest.in_progress {
+      in_progress.files.extend(self.pending.drain(..));
+      log::info!(
+        "=> Checkpointed {} after {} file(s)",
+        in_progress.name,
+        in_progress.files.len()
+      );
+    }
+    self.write_manifest()
+  }
+
+  fn write_manifest(&self) -> anyhow::Result<()> {
+    let path = self.dir.join(MANIFEST);
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string(&self.manifest)?)?;
+    fs::rename(&tmp, &path)?;
+    Ok(())
+  }
+
+  fn model_path(&self, name: &str) -> PathBuf {
+    self.dir.join(model_file(name))
+  }
+}
+
+fn model_file(name: &str) -> String {
+  format!("{name}.chain")
+}
+
+fn read_manifest(dir: &Path) -> anyhow::Result<Manifest> {
+  let contents = fs::read_to_string(dir.join(MANIFEST))
+    .map_err(|e| anyhow::anyhow!("Could not read the checkpoint manifest in {}: {}", dir.display(), e))?;
+  Ok(serde_json::from_str(&contents)?)
+}
+
+/// Removes the model files listed in `manifest` and the manifest itself, and then `dir` if nothing else is left in it
+fn remove(dir: &Path, manifest: &Manifest) -> anyhow::Result<()> {
+  for file in &manifest.models {
+    let path = dir.join(file);
+    if path.exists() {
+      fs::remove_file(path)?;
+    }
+  }
+  let path = dir.join(MANIFEST);
+  if path.exists() {
+    fs::remove_file(path)?;
+  }
+  // Fails if the directory isn't empty, which is fine
+  let _ = fs::remove_dir(dir);
+  Ok(())
+}
+
+/// Default location of the checkpoints of a run writing models to `output_directory`
+pub fn default_directory(output_directory: &Path) -> PathBuf {
+  output_directory.join("checkpoint")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_only_checkpoint_files_are_removed() {
+    let dir = std::env::temp_dir().join(format!("scs-train-checkpoint-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("forsen.chain"), "").unwrap();
+    fs::write(dir.join("forsen-2023-07-01.log"), "").unwrap();
+    fs::write(
+      dir.join(MANIFEST),
+      r#"{ "completed": [], "in_progress": null, "models": ["forsen.chain"] }"#,
+    )
+    .unwrap();
+
+    let checkpoints = Checkpoints::new(dir.clone(), Some(10)).unwrap();
+    let left = fs::read_dir(&dir)
+      .unwrap()
+      .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+      .collect::<Vec<_>>();
+    checkpoints.finish().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(left, vec!["forsen-2023-07-01.log"]);
+  }
+
+  #[test]
+  fn test_fingerprints_are_restored() {
+    let mut checkpoints = Checkpoints::new(PathBuf::from("unused"), None).unwrap();
+    let fingerprint =
+      chain::Fingerprint::from_counts(vec![(("a".into(), "b".into()), 3), (("b".into(), "c".into()), 4)]);
+    checkpoints.restore("forsen", &mut []).unwrap();
+    checkpoints.complete(&[], Some(&fingerprint)).unwrap();
+
+    let manifest = serde_json::to_string(&checkpoints.manifest).unwrap();
+    let resumed = Checkpoints {
+      manifest: serde_json::from_str(&manifest).unwrap(),
+      ..checkpoints
+    };
+    let restored = resumed.fingerprint("forsen").unwrap();
+    assert_eq!(restored.get("b", "c"), Some(0.8));
+    assert!((restored.similarity(&fingerprint) - 1.0).abs() < 1e-9);
+    assert!(resumed.fingerprint("xqc").is_none());
+  }
+}
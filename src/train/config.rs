@@ -3,12 +3,14 @@ use std::{
   path::PathBuf,
 };
 
-use chrono::{DateTime, NaiveDateTime, Utc};
-use serde::Deserialize;
+use chrono::{DateTime, NaiveDate, Utc};
+use scs_config::sinks::LogFileName;
+use serde::{Deserialize, Serialize};
 
 const CARGO_MANIFEST_DIR: &str = env!("CARGO_MANIFEST_DIR");
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct TrainingConfig {
   /// Internal time filter. Only set if `model_to_fine_tune` with a timestamped name is provided.
   #[serde(skip)]
@@ -30,6 +32,43 @@ pub struct TrainingConfig {
   /// If true, prefixes each sentence with the name of its author.
   #[serde(default = "default_authored_mode")]
   pub authored_mode: bool,
+  /// If true, prefixes each sentence with a tag of the channel it was sent in, so that generations can be
+  /// conditioned on a channel. See `chain::sample_tagged`.
+  #[serde(default)]
+  pub channel_tags: bool,
+  /// Chatters whose messages are excluded from training, e.g. bots.
+  #[serde(default = "HashSet::<_>::default")]
+  pub chatter_blocklist: HashSet<String>,
+  /// Regular expressions matched against chatter names. Matching chatters are excluded from training.
+  #[serde(default = "Vec::new")]
+  pub chatter_blocklist_patterns: Vec<String>,
+  /// Internal compiled version of `chatter_blocklist_patterns`.
+  #[serde(skip)]
+  pub chatter_blocklist_regex: Option<regex::RegexSet>,
+  /// An optional file of words and phrases which must never be generated, one per line.
+  /// Messages containing any of them are excluded from training. Empty lines and lines starting with `#` are ignored.
+  pub phrase_blocklist: Option<PathBuf>,
+  /// Internal parsed version of `phrase_blocklist`, whose hash is stored in the metadata of the models.
+  #[serde(skip)]
+  pub phrase_blocklist_entries: Option<scs_config::PhraseBlocklist>,
+  /// An optional path to write the pairwise similarities of the per-channel models to.
+  pub similarity_report: Option<PathBuf>,
+  /// The number of bigrams used to compare models in the similarity report.
+  #[serde(default = "default_fingerprint_size")]
+  pub fingerprint_size: usize,
+  /// If true, existing models in `output_directory` are trained further, and only the changes are saved, as deltas.
+  #[serde(default)]
+  pub incremental: bool,
+  /// Orders of the models to train. Models of every order are trained in a single pass over the logs.
+  #[serde(default = "default_orders")]
+  pub orders: Vec<usize>,
+  /// If set, the in-progress models are checkpointed after every `checkpoint_interval` log files.
+  pub checkpoint_interval: Option<usize>,
+  /// Where checkpoints are written to. Defaults to `checkpoint` in `output_directory`.
+  pub checkpoint_directory: Option<PathBuf>,
+  /// Number of threads each model is fed on. Messages are fed in batches when it's greater than 1.
+  #[serde(default = "default_threads")]
+  pub threads: usize,
 }
 
 impl Default for TrainingConfig {
@@ -42,6 +81,19 @@ impl Default for TrainingConfig {
       save_timestamped_checkpoint: default_save_timestamped_checkpoint(),
       model_to_fine_tune: None,
       authored_mode: false,
+      channel_tags: false,
+      chatter_blocklist: HashSet::new(),
+      chatter_blocklist_patterns: Vec::new(),
+      chatter_blocklist_regex: None,
+      phrase_blocklist: None,
+      phrase_blocklist_entries: None,
+      similarity_report: None,
+      fingerprint_size: default_fingerprint_size(),
+      incremental: false,
+      orders: default_orders(),
+      checkpoint_interval: None,
+      checkpoint_directory: None,
+      threads: default_threads(),
     }
   }
 }
@@ -66,6 +118,18 @@ fn default_authored_mode() -> bool {
   false
 }
 
+fn default_fingerprint_size() -> usize {
+  1000
+}
+
+fn default_orders() -> Vec<usize> {
+  vec![2]
+}
+
+fn default_threads() -> usize {
+  1
+}
+
 impl TrainingConfig {
   pub fn filter(&self, channel: &str, filename: &str) -> bool {
     filename.ends_with(".log")
@@ -79,25 +143,109 @@ impl TrainingConfig {
       }
   }
 
+  /// Returns `true` if the messages of `chatter` should be excluded from training.
+  pub fn is_blocked(&self, chatter: &str) -> bool {
+    self.chatter_blocklist.contains(chatter)
+      || self
+        .chatter_blocklist_regex
+        .as_ref()
+        .map_or(false, |set| set.is_match(chatter))
+  }
+
+  /// Returns the indices of the entries of `phrase_blocklist` which `message` contains.
+  pub fn blocked_phrases(&self, message: &str) -> Vec<usize> {
+    self
+      .phrase_blocklist_entries
+      .as_ref()
+      .map_or_else(Vec::new, |blocklist| blocklist.matches(message))
+  }
+
+  /// Hash of `phrase_blocklist`, if there is one
+  pub fn phrase_blocklist_hash(&self) -> Option<&str> {
+    self.phrase_blocklist_entries.as_ref().map(|blocklist| blocklist.hash())
+  }
+
+  /// Whether the log `filename` starts at or after `time_filter`. Both daily and hourly logs are compared by the start
+  /// of the period they cover.
   pub fn is_after_date(&self, filename: &str) -> bool {
     self.time_filter.map_or(true, |min_date| {
-      NaiveDateTime::parse_from_str(&filename[filename.len() - 14..], "%Y-%m-%d")
+      LogFileName::parse(filename)
+        .and_then(|name| {
+          NaiveDate::parse_from_str(name.date, "%Y-%m-%d")
+            .ok()?
+            .and_hms_opt(name.hour.unwrap_or(0), 0, 0)
+        })
         .map(|file_date| file_date >= min_date.naive_utc())
-        .map_err(|_| {
+        .unwrap_or_else(|| {
           log::error!("Log filename not timestamped, skipping: {}", filename);
+          false
         })
-        .unwrap_or(false)
     })
   }
 
   pub fn load<P: AsRef<std::path::Path>>(path: P) -> anyhow::Result<Self> {
-    let content = std::fs::read_to_string(path)?;
-    let mut config = serde_json::from_str::<Self>(&content)?;
+    let mut config = scs_config::read::<Self>(path)?;
 
     if config.channels.is_empty() {
       log::info!("config.channel is empty, the model will be trained on all logs.")
     }
 
+    config.chatter_blocklist = config
+      .chatter_blocklist
+      .into_iter()
+      .map(|s| s.to_ascii_lowercase())
+      .collect();
+    if !config.chatter_blocklist_patterns.is_empty() {
+      config.chatter_blocklist_regex = Some(regex::RegexSet::new(&config.chatter_blocklist_patterns).map_err(|e| {
+        log::error!("config.chatter_blocklist_patterns contains an invalid pattern: {}", e);
+        anyhow::anyhow!("config.chatter_blocklist_patterns is invalid.")
+      })?);
+    }
+
+    if let Some(path) = &config.phrase_blocklist {
+      let blocklist = scs_config::PhraseBlocklist::load(path).map_err(|e| {
+        log::error!("config.phrase_blocklist is invalid: {:#}", e);
+        anyhow::anyhow!("config.phrase_blocklist is invalid.")
+      })?;
+      log::info!(
+        "Loaded {} blocklisted phrase(s) from {}",
+        blocklist.entries().len(),
+        path.display()
+      );
+      config.phrase_blocklist_entries = Some(blocklist);
+    }
+
+    config.orders.sort_unstable();
+    config.orders.dedup();
+    if config.orders.is_empty() {
+      anyhow::bail!("config.orders is empty")
+    }
+    if let Some(order) = config
+      .orders
+      .iter()
+      .find(|order| !crate::model::SUPPORTED_ORDERS.contains(order))
+    {
+      log::error!(
+        "config.orders contains {}, supported orders are {:?}",
+        order,
+        crate::model::SUPPORTED_ORDERS
+      );
+      anyhow::bail!("config.orders is invalid.")
+    }
+
+    if config.checkpoint_interval == Some(0) {
+      anyhow::bail!("config.checkpoint_interval must be greater than 0")
+    }
+    if config.checkpoint_interval.is_some() && config.incremental {
+      // Restoring a checkpoint would lose the changes tracked for the delta
+      log::error!("config.checkpoint_interval can't be used with config.incremental");
+      anyhow::bail!("config.checkpoint_interval is invalid.")
+    }
+
+    if config.threads == 0 {
+      anyhow::bail!("config.threads must be greater than 0")
+    }
+
     if !config.input_directory.exists() {
       log::error!("config.input_directory doesn't exist.");
       anyhow::bail!("Input directory doesn't exist")
@@ -122,6 +270,15 @@ impl TrainingConfig {
         log::error!("config.model_to_fine_tune is a directory, expected a model file.");
         anyhow::bail!("config.model_to_fine_tune is invalid.")
       }
+      let order = chain::order_of(model_path)?;
+      if config.orders != [order] {
+        log::error!(
+          "config.model_to_fine_tune is of order {}, so config.orders must be [{}]",
+          order,
+          order
+        );
+        anyhow::bail!("config.model_to_fine_tune is invalid.")
+      }
 
       // Attempt to extract the date the model was trained on from the filename.
       if let Some(date_str) = regex::Regex::new(r"\d{4}-\d{2}-\d{2}")
@@ -130,8 +287,9 @@ impl TrainingConfig {
         .and_then(|captures| captures.get(0))
         .map(|m| m.as_str())
       {
-        config.time_filter = NaiveDateTime::parse_from_str(date_str, "%Y-%m-%d")
+        config.time_filter = NaiveDate::parse_from_str(date_str, "%Y-%m-%d")
           .ok()
+          .and_then(|d| d.and_hms_opt(0, 0, 0))
           .map(|d| DateTime::from_utc(d, Utc));
       }
     }
@@ -141,8 +299,125 @@ impl TrainingConfig {
     Ok(config)
   }
 
+  /// Name of the model called `name` of the given `order`.
+  /// The order is only added to the name when models of several orders are trained.
+  pub fn model_name(&self, name: &str, order: usize) -> String {
+    if self.orders.len() > 1 {
+      format!("{}-order{}", name, order)
+    } else {
+      name.to_owned()
+    }
+  }
+
+  pub fn checkpoint_directory(&self) -> PathBuf {
+    self
+      .checkpoint_directory
+      .clone()
+      .unwrap_or_else(|| crate::checkpoint::default_directory(&self.output_directory))
+  }
+
+  /// Metadata stored in the models, describing what they were trained on, and the hash of their manifest if it's known
+  pub fn model_metadata(&self, channels: &str, order: usize, manifest: Option<&str>) -> String {
+    let mut metadata = format!("{{ channels: {}; order: {}", channels, order);
+    if let Some(hash) = self.phrase_blocklist_hash() {
+      metadata.push_str(&format!("; phrase_blocklist: {}", hash));
+    }
+    if let Some(hash) = manifest {
+      metadata.push_str(&format!("; manifest: {}", hash));
+    }
+    metadata.push_str(" }");
+    metadata
+  }
+
+  /// Returns the channel of the log `name`, or `None` if it isn't a log file
   #[inline]
-  pub fn extract_channel_name<'a>(&self, name: &'a str) -> &'a str {
-    name.get(..name.len().wrapping_sub(15)).unwrap_or(name)
+  pub fn extract_channel_name<'a>(&self, name: &'a str) -> Option<&'a str> {
+    LogFileName::parse(name).map(|name| name.channel)
+  }
+}
+
+/// Returns the hash of the phrase blocklist stored in the `metadata` of a model by [`TrainingConfig::model_metadata`]
+pub fn phrase_blocklist_of(metadata: &str) -> Option<&str> {
+  let (_, rest) = metadata.split_once("phrase_blocklist: ")?;
+  rest.split(|c: char| c == ';' || c.is_whitespace()).next()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn config(blocklist: &str) -> TrainingConfig {
+    TrainingConfig {
+      phrase_blocklist_entries: Some(scs_config::PhraseBlocklist::parse(blocklist).unwrap()),
+      ..TrainingConfig::default()
+    }
+  }
+
+  #[test]
+  fn test_blocked_phrases() {
+    let config = config("# comment\n\nbad   word\nclass\n:)\n");
+    assert_eq!(config.blocked_phrases("this is a BAD  word"), vec![0]);
+    assert_eq!(config.blocked_phrases("bad words"), Vec::<usize>::new());
+    assert_eq!(config.blocked_phrases("classy :) class"), vec![1, 2]);
+    assert_eq!(config.blocked_phrases("nothing here"), Vec::<usize>::new());
+  }
+
+  #[test]
+  fn test_phrase_blocklist_metadata() {
+    let config = config("bad word");
+    let hash = config.phrase_blocklist_hash().unwrap();
+    assert_eq!(
+      phrase_blocklist_of(&config.model_metadata("forsen", 2, None)),
+      Some(hash)
+    );
+    assert_eq!(
+      phrase_blocklist_of(&config.model_metadata("forsen", 2, Some("abc"))),
+      Some(hash)
+    );
+    let config = TrainingConfig::default();
+    assert_eq!(
+      phrase_blocklist_of(&config.model_metadata("forsen", 2, Some("abc"))),
+      None
+    );
+  }
+
+  #[test]
+  fn test_model_names() {
+    let config = TrainingConfig {
+      orders: vec![2, 3],
+      ..TrainingConfig::default()
+    };
+    assert_eq!(config.model_name("forsen", 3), "forsen-order3");
+    // Served by the user API, which only accepts valid names
+    assert!(chain::is_valid_model_name(&config.model_name("forsen", 3)));
+
+    let config = TrainingConfig {
+      orders: vec![2],
+      ..TrainingConfig::default()
+    };
+    assert_eq!(config.model_name("forsen", 2), "forsen");
+  }
+
+  #[test]
+  fn test_log_file_names() {
+    let config = TrainingConfig {
+      time_filter: Some(DateTime::from_utc(
+        NaiveDate::from_ymd_opt(2023, 7, 1)
+          .unwrap()
+          .and_hms_opt(12, 0, 0)
+          .unwrap(),
+        Utc,
+      )),
+      ..TrainingConfig::default()
+    };
+    assert_eq!(config.extract_channel_name("forsen-2023-07-01.log"), Some("forsen"));
+    assert_eq!(config.extract_channel_name("forsen-2023-07-01-13.log"), Some("forsen"));
+    assert_eq!(config.extract_channel_name("forsen.bin"), None);
+
+    assert!(config.is_after_date("forsen-2023-07-02.log"));
+    assert!(!config.is_after_date("forsen-2023-07-01.log"));
+    assert!(config.is_after_date("forsen-2023-07-01-13.log"));
+    assert!(!config.is_after_date("forsen-2023-07-01-11.log"));
+    assert!(!config.is_after_date("forsen.log"));
   }
 }
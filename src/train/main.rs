@@ -1,21 +1,34 @@
 #![feature(iter_intersperse)]
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::env;
 use std::fs;
 
 use anyhow::Result;
+use checkpoint::Checkpoints;
 use chrono::Utc;
 use config::TrainingConfig;
+use model::Model;
 use walkdir::WalkDir;
 
 #[cfg(not(feature = "no-progress"))]
 use indicatif::ProgressBar;
 
+mod checkpoint;
 mod config;
+mod manifest;
+mod model;
+mod stats;
 
+/// Number of messages fed to the models at once when training on several threads
+const PARALLEL_BATCH_SIZE: usize = 500_000;
+
+/// Splits a collector log line into the chatter and the message, dropping the language tag and the sequence number
+/// of the chatter
 fn split_line(line: &str) -> Option<(&str, &str)> {
   if !line.trim().is_empty() {
-    line.split_once(',')
+    let (chatter, message) = line.split_once(',')?;
+    Some((chatter.split(['@', '#']).next().unwrap_or(chatter), message))
   } else {
     None
   }
@@ -40,11 +53,12 @@ impl LogStore {
     self.channels.contains_key(channel)
   }
 
+  /// Returns the logs of `channel` and its data sources, along with the channel and file name of each log
   pub fn filter<'this>(
     &'this self,
     channel: &'this str,
     config: &'this config::TrainingConfig,
-  ) -> impl Iterator<Item = &'this str> {
+  ) -> impl Iterator<Item = (&'this str, &'this str, &'this str)> {
     config
       .channels
       .get(channel)
@@ -52,16 +66,21 @@ impl LogStore {
       .iter()
       .map(AsRef::as_ref)
       .chain(std::iter::once(channel))
-      .filter_map(move |target_channel| self.channels.get(target_channel))
-      .flat_map(|logs| logs.iter().map(|(_, contents)| contents.as_ref()))
+      .filter_map(move |target_channel| self.channels.get_key_value(target_channel))
+      .flat_map(|(channel, logs)| {
+        logs
+          .iter()
+          .map(move |(filename, contents)| (channel.as_str(), filename.as_str(), contents.as_str()))
+      })
   }
 
   #[inline]
-  pub fn all(&self) -> impl Iterator<Item = &'_ str> {
-    self
-      .channels
-      .values()
-      .flat_map(|logs| logs.iter().map(|(_, contents)| contents.as_ref()))
+  pub fn all(&self) -> impl Iterator<Item = (&'_ str, &'_ str, &'_ str)> {
+    self.channels.iter().flat_map(|(channel, logs)| {
+      logs
+        .iter()
+        .map(move |(filename, contents)| (channel.as_str(), filename.as_str(), contents.as_str()))
+    })
   }
 }
 
@@ -87,11 +106,9 @@ fn collect_logs(store: &mut LogStore, config: &TrainingConfig) {
     .into_iter()
     .filter_map(|e| e.ok())
     .filter_map(|entry| {
-      entry
-        .file_name()
-        .to_str()
-        .map(|name| (name, config.extract_channel_name(name)))
-        .map(|(name, channel)| (channel.to_owned(), name.to_owned(), entry.clone()))
+      let name = entry.file_name().to_str()?;
+      let channel = config.extract_channel_name(name)?;
+      Some((channel.to_owned(), name.to_owned(), entry.clone()))
     })
     .filter_map(|(channel, file_name, entry)| {
       if (all_channels.is_empty() || all_channels.contains(&channel)) && config.is_after_date(&file_name) {
@@ -112,7 +129,62 @@ fn collect_logs(store: &mut LogStore, config: &TrainingConfig) {
   bar.finish();
 }
 
-fn train<'a>(chain: &mut chain::Chain<2>, authored_mode: bool, logs: impl Iterator<Item = &'a str>) {
+#[derive(Debug, Default)]
+struct TrainingReport {
+  /// Messages fed to the chain
+  messages: usize,
+  /// Messages skipped because their chatter is blocklisted
+  excluded: usize,
+  /// Messages skipped because they contain a blocklisted phrase
+  excluded_phrases: usize,
+  /// Number of messages containing each entry of the phrase blocklist
+  phrase_counts: Vec<usize>,
+  /// Statistics of the messages fed to the chain
+  stats: stats::CorpusStats,
+}
+
+/// A model being trained, along with the name it's saved under
+struct Target {
+  model: Model,
+  name: String,
+  /// Source channels of the model, as written in its metadata
+  channels: String,
+  /// Hash of the checkpoint the model was loaded from in incremental mode
+  base_hash: Option<u64>,
+}
+
+/// Feeds `batch` to every model in `targets` on `threads` threads, and empties it
+fn feed_batch(targets: &mut [Target], batch: &mut Vec<String>, threads: usize) {
+  for target in targets.iter_mut() {
+    target.model.feed_parallel(batch, threads);
+  }
+  batch.clear();
+}
+
+/// Feeds the messages in `logs` to every model in `targets`, so that the logs are only read once.
+///
+/// With more than one thread, messages are buffered and fed in batches, which are fed before each checkpoint.
+///
+/// `logs` yields the contents of each log along with its channel and file name.
+/// Logs which the checkpointed models were already trained on are skipped, but still recorded in `run`'s manifest.
+fn train<'a>(
+  targets: &mut [Target],
+  config: &TrainingConfig,
+  logs: impl Iterator<Item = (&'a str, &'a str, &'a str)>,
+  checkpoints: &mut Checkpoints,
+  run: &mut manifest::Run,
+) -> Result<TrainingReport> {
+  let mut report = TrainingReport {
+    phrase_counts: vec![
+      0;
+      config
+        .phrase_blocklist_entries
+        .as_ref()
+        .map_or(0, |b| b.entries().len())
+    ],
+    ..TrainingReport::default()
+  };
+
   #[cfg(not(feature = "no-progress"))]
   let bar = ProgressBar::new_spinner().with_style(
     indicatif::ProgressStyle::default_spinner()
@@ -120,32 +192,232 @@ fn train<'a>(chain: &mut chain::Chain<2>, authored_mode: bool, logs: impl Iterat
       .unwrap(),
   );
 
-  for log in logs {
+  let mut batch = Vec::new();
+  for (channel, filename, log) in logs {
     #[cfg(not(feature = "no-progress"))]
     bar.inc(1);
+    run.input(channel, filename, log);
+    if checkpoints.is_processed(filename) {
+      report.stats.skip_file();
+      continue;
+    }
+    report.stats.file(channel, filename);
     for (user, message) in log.split('\n').filter_map(split_line) {
-      if authored_mode {
-        chain.feed_str(&format!("{}: {}", user, message.trim()));
+      // Generated text is sanitized the same way before it's sent, see `chain::text`
+      let message = chain::text::sanitize(message);
+      if message.is_empty() {
+        continue;
+      }
+      if config.is_blocked(user) {
+        report.excluded += 1;
+        continue;
+      }
+      let blocked = config.blocked_phrases(&message);
+      if !blocked.is_empty() {
+        for entry in blocked {
+          report.phrase_counts[entry] += 1;
+        }
+        report.excluded_phrases += 1;
+        continue;
+      }
+      report.messages += 1;
+      report.stats.message(channel, user, &message);
+      let message = match (config.channel_tags, config.authored_mode) {
+        (true, true) => Cow::Owned(format!("{} {}: {}", chain::channel_tag(channel), user, message)),
+        (true, false) => Cow::Owned(format!("{} {}", chain::channel_tag(channel), message)),
+        (false, true) => Cow::Owned(format!("{}: {}", user, message)),
+        (false, false) => message,
+      };
+      if config.threads > 1 {
+        batch.push(message.into_owned());
       } else {
-        chain.feed_str(message.trim());
+        for target in targets.iter_mut() {
+          target.model.feed_str(&message);
+        }
       }
     }
+    if batch.len() >= PARALLEL_BATCH_SIZE || (!batch.is_empty() && checkpoints.is_due_after_next_file()) {
+      feed_batch(targets, &mut batch, config.threads);
+    }
+    checkpoints.after_file(filename, targets)?;
+  }
+  if !batch.is_empty() {
+    feed_batch(targets, &mut batch, config.threads);
   }
 
   #[cfg(not(feature = "no-progress"))]
   bar.finish();
+
+  log::info!(
+    "Trained on {} messages, excluded {} messages from blocklisted chatters",
+    report.messages,
+    report.excluded
+  );
+  if let Some(blocklist) = &config.phrase_blocklist_entries {
+    log::info!(
+      "Excluded {} messages containing blocklisted phrases",
+      report.excluded_phrases
+    );
+    for (entry, count) in blocklist.entries().iter().zip(&report.phrase_counts) {
+      log::info!("  {:?}: {} message(s)", entry, count);
+    }
+  }
+  Ok(report)
 }
 
-fn save_model<const ORDER: usize>(
-  chain: &chain::Chain<ORDER>,
+/// Saves the model, and its manifest next to it
+fn save_model(
+  chain: &Model,
   name: &str,
   output_path: &std::path::Path,
   save_timestamped_checkpoint: bool,
+  manifest: &str,
 ) -> anyhow::Result<()> {
+  let mut paths = vec![output_path.join(format!("{}.chain", name))];
   if save_timestamped_checkpoint {
-    chain.save(output_path.join(format!("{}-{}.chain", name, Utc::now().format("%F"))))?;
+    paths.push(output_path.join(format!("{}-{}.chain", name, Utc::now().format("%F"))));
+  }
+  for path in paths {
+    chain.save(&path)?;
+    fs::write(manifest::path(&path), manifest)?;
+  }
+  Ok(())
+}
+
+/// In incremental mode, loads the existing model called `name` with its deltas, along with the hash of its checkpoint.
+fn load_incremental(config: &TrainingConfig, name: &str, order: usize) -> anyhow::Result<Option<(Model, u64)>> {
+  let path = config.output_directory.join(format!("{}.chain", name));
+  if !config.incremental || !path.exists() {
+    return Ok(None);
+  }
+  log::info!("=> Loading {} for incremental training...", path.display());
+  Ok(Some(Model::load_with_deltas(&path, order)?))
+}
+
+/// Creates the models called `name` for every configured order, continuing from the existing ones in incremental mode.
+///
+/// New models are copies of the matching model in `base_models`, described by `channels` in their metadata.
+fn prepare_targets(
+  config: &TrainingConfig,
+  base_models: &[Model],
+  name: &str,
+  channels: &str,
+) -> anyhow::Result<Vec<Target>> {
+  let mut targets = Vec::with_capacity(base_models.len());
+  for base_model in base_models {
+    let name = config.model_name(name, base_model.order());
+    let (model, base_hash) = match load_incremental(config, &name, base_model.order())? {
+      Some((model, base_hash)) => {
+        // The metadata of the checkpoint is kept, so the blocklist it names must filter the deltas as well
+        let trained_with = config::phrase_blocklist_of(model.metadata());
+        match (trained_with, config.phrase_blocklist_hash()) {
+          (Some(trained_with), current) if Some(trained_with) != current => anyhow::bail!(
+            "{} was trained with the phrase blocklist {}, it must be retrained without `incremental` to change it",
+            name,
+            trained_with
+          ),
+          (None, Some(current)) => log::warn!(
+            "{} was trained without a phrase blocklist, its metadata won't include {}",
+            name,
+            current
+          ),
+          _ => (),
+        }
+        (model, Some(base_hash))
+      }
+      None => (
+        base_model
+          .clone()
+          .with_metadata(config.model_metadata(channels, base_model.order(), None)),
+        None,
+      ),
+    };
+    targets.push(Target {
+      model,
+      name,
+      channels: channels.to_owned(),
+      base_hash,
+    });
+  }
+  Ok(targets)
+}
+
+/// Saves the model as a delta against the checkpoint with the hash `base_hash` if there is one, or in full otherwise,
+/// along with the manifest of `run`. The hash of the manifest is stored in the metadata of full models.
+fn save(target: &mut Target, config: &TrainingConfig, run: &manifest::Run) -> anyhow::Result<()> {
+  log::info!("=> Saving {}.chain...", target.name);
+  let order = target.model.order();
+  let (manifest, hash) = manifest::render(config, run, &target.name, order, target.base_hash)?;
+  match target.base_hash {
+    Some(base_hash) => {
+      let path = target.model.save_delta(
+        &config.output_directory.join(format!("{}.chain", target.name)),
+        base_hash,
+      )?;
+      fs::write(manifest::path(&path), manifest)?;
+      log::info!("=> Saved the changes to {} (manifest {})", path.display(), hash);
+      Ok(())
+    }
+    None => {
+      target
+        .model
+        .set_metadata(config.model_metadata(&target.channels, order, Some(&hash)));
+      save_model(
+        &target.model,
+        &target.name,
+        &config.output_directory,
+        config.save_timestamped_checkpoint,
+        &manifest,
+      )?;
+      log::info!("=> Saved {}.chain (manifest {})", target.name, hash);
+      Ok(())
+    }
+  }
+}
+
+/// Merges the deltas of the model at `path` into it, and removes them.
+///
+/// The deltas are only removed once the compacted model has replaced the previous one. If that's interrupted, the
+/// deltas left behind were saved against the previous model, so they're skipped when loading, and removed by the next
+/// compaction.
+fn compact(path: &std::path::Path) -> anyhow::Result<()> {
+  let deltas = chain::ser::find_deltas(path)?;
+  if deltas.is_empty() {
+    log::info!("{} has no deltas", path.display());
+    return Ok(());
+  }
+
+  log::info!("Compacting {} delta(s) into {}...", deltas.len(), path.display());
+  let (chain, _) = Model::load_with_deltas(path, chain::order_of(path)?)?;
+  // Write to a temporary file first, so that a failure doesn't leave a partially written model behind
+  let tmp = path.with_extension("chain.tmp");
+  chain.save(&tmp)?;
+  fs::File::open(&tmp)?.sync_all()?;
+  fs::rename(&tmp, path)?;
+  for delta in deltas {
+    fs::remove_file(delta)?;
   }
-  chain.save(output_path.join(format!("{}.chain", name)))?;
+  Ok(())
+}
+
+#[derive(serde::Serialize)]
+struct SimilarityReport<'a> {
+  channels: Vec<&'a str>,
+  /// `similarity[i][j]` is the similarity of the models of `channels[i]` and `channels[j]`
+  similarity: Vec<Vec<f64>>,
+}
+
+fn save_similarity_report(fingerprints: &[(&str, chain::Fingerprint)], path: &std::path::Path) -> anyhow::Result<()> {
+  let report = SimilarityReport {
+    channels: fingerprints.iter().map(|(channel, _)| *channel).collect(),
+    similarity: chain::fingerprint::similarity_matrix(
+      &fingerprints
+        .iter()
+        .map(|(_, fingerprint)| fingerprint.clone())
+        .collect::<Vec<_>>(),
+    ),
+  };
+  fs::write(path, serde_json::to_string_pretty(&report)?)?;
   Ok(())
 }
 
@@ -155,62 +427,134 @@ fn main() -> Result<()> {
   }
   env_logger::init();
 
-  let config = if let Some(path) = env::args().nth(1) {
+  if env::args().nth(1).as_deref() == Some("compact") {
+    for path in env::args().skip(2) {
+      compact(std::path::Path::new(&path))?;
+    }
+    return Ok(());
+  }
+
+  if env::args().nth(1).as_deref() == Some("validate") {
+    return scs_config::validate_all(env::args().skip(2), |path| config::TrainingConfig::load(path));
+  }
+
+  // usage: train [config] [--resume-from <checkpoint directory>]
+  let mut args = env::args().skip(1).collect::<Vec<_>>();
+  let resume_from = match args.iter().position(|arg| arg == "--resume-from") {
+    Some(i) if i + 1 < args.len() => Some(std::path::PathBuf::from(args.remove(i + 1))),
+    Some(_) => anyhow::bail!("--resume-from requires a checkpoint directory"),
+    None => None,
+  };
+  args.retain(|arg| arg != "--resume-from");
+
+  let config = if let Some(path) = args.first() {
     config::TrainingConfig::load(&std::path::PathBuf::from(path))?
   } else {
     config::TrainingConfig::default()
   };
   log::info!("Loaded config {:?}", config);
 
+  let mut checkpoints = match resume_from {
+    Some(dir) => Checkpoints::resume(dir, config.checkpoint_interval)?,
+    None => Checkpoints::new(config.checkpoint_directory(), config.checkpoint_interval)?,
+  };
+
+  let fine_tuned_from = match &config.model_to_fine_tune {
+    Some(path) => Some(manifest::hash_file(path)?),
+    None => None,
+  };
   let mut store = LogStore::default();
 
   log::info!("Collecting logs...");
   collect_logs(&mut store, &config);
 
-  let mut base_chain = if let Some(path) = &config.model_to_fine_tune {
-    log::info!("Loading a previous model for fine-tuning...");
-    chain::Chain::<2>::load(path)?
-  } else {
-    chain::of_order!(2)
-  };
+  let base_models = config
+    .orders
+    .iter()
+    .map(|&order| match &config.model_to_fine_tune {
+      Some(path) => {
+        log::info!("Loading a previous model for fine-tuning...");
+        Model::load(path, order)
+      }
+      None => Model::new(order),
+    })
+    .collect::<Result<Vec<_>>>()?;
 
   if config.channels.is_empty() {
+    let mut run = manifest::Run::start(fine_tuned_from.as_deref());
+    let mut targets = prepare_targets(&config, &base_models, "model", "all")?;
+    checkpoints.restore("model", &mut targets)?;
+
     log::info!("Training a model on all data...");
-    train(&mut base_chain, config.authored_mode, store.all());
+    let report = train(&mut targets, &config, store.all(), &mut checkpoints, &mut run)?;
 
     log::info!("Saving the model...");
-    save_model(
-      &base_chain,
-      "model",
-      &config.output_directory,
-      config.save_timestamped_checkpoint,
-    )?;
-    return Ok(());
+    for target in &mut targets {
+      save(target, &config, &run)?;
+      report
+        .stats
+        .save(&config.output_directory, &target.name, target.base_hash.is_some())?;
+    }
+    checkpoints.complete(&targets, None)?;
+    return checkpoints.finish();
   }
 
   log::info!("Training per-channel models");
+  let mut fingerprints = Vec::new();
   for channel in config.channels.keys() {
+    if checkpoints.is_completed(channel) {
+      log::info!("=> Skipping {}, it was saved before the run was interrupted", channel);
+      if config.similarity_report.is_some() {
+        match checkpoints.fingerprint(channel) {
+          Some(fingerprint) => fingerprints.push((channel.as_str(), fingerprint)),
+          None => log::warn!(
+            "=> The checkpoint has no fingerprint of {}, it's left out of the similarity report",
+            channel
+          ),
+        }
+      }
+      continue;
+    }
     log::info!("=> Training for {}", channel);
 
-    let mut chain = base_chain.clone().with_metadata(format!(
-      "{{ channels: {}; order: {} }}",
-      std::iter::once(channel)
-        .chain(config.channels[channel].iter())
-        .map(|s| s.as_ref())
-        .intersperse(",")
-        .collect::<String>(),
-      base_chain.order()
-    ));
-    train(&mut chain, config.authored_mode, store.filter(channel, &config));
-    log::info!("=> Saving {}.chain...", channel);
-    save_model(
-      &chain,
-      channel,
-      &config.output_directory,
-      config.save_timestamped_checkpoint,
+    let channels = std::iter::once(channel)
+      .chain(config.channels[channel].iter())
+      .map(|s| s.as_ref())
+      .intersperse(",")
+      .collect::<String>();
+    let mut run = manifest::Run::start(fine_tuned_from.as_deref());
+    let mut targets = prepare_targets(&config, &base_models, channel, &channels)?;
+    checkpoints.restore(channel, &mut targets)?;
+    let report = train(
+      &mut targets,
+      &config,
+      store.filter(channel, &config),
+      &mut checkpoints,
+      &mut run,
     )?;
+    for target in &mut targets {
+      save(target, &config, &run)?;
+      report
+        .stats
+        .save(&config.output_directory, &target.name, target.base_hash.is_some())?;
+    }
+    // Fingerprints don't depend on the order, so any of the models will do
+    let fingerprint = config
+      .similarity_report
+      .is_some()
+      .then(|| targets[0].model.bigram_fingerprint(config.fingerprint_size));
+    checkpoints.complete(&targets, fingerprint.as_ref())?;
+    if let Some(fingerprint) = fingerprint {
+      fingerprints.push((channel.as_str(), fingerprint));
+    }
+  }
+
+  if let Some(path) = &config.similarity_report {
+    log::info!("Saving the similarity report to {}...", path.display());
+    save_similarity_report(&fingerprints, path)?;
   }
 
+  checkpoints.finish()?;
   log::info!("Done");
 
   Ok(())
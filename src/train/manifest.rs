@@ -0,0 +1,189 @@
+//! Manifests of how each model was trained, so that a model in production can be traced back to its exact inputs.
+//!
+//! A manifest records the config, the hash of every log file the model was trained on, the version of the trainer,
+//! how long training took, and the machine it ran on. It's saved next to the model as `<name>.chain.manifest.json`, so
+//! that it's moved and deleted along with the model's other files, and its hash is stored in the model's metadata.
+//! Deltas don't have metadata, so the manifest of a delta is saved next to it, e.g. `forsen.chain.0001.manifest.json`,
+//! and the metadata keeps the hash of the full model's manifest.
+//!
+//! Log files are hashed as the trainer reads them, so a manifest only lists the logs its model was trained on, and
+//! the manifest of a delta only the logs of the run which saved it.
+
+use std::{
+  collections::BTreeMap,
+  ffi::OsStr,
+  path::{Path, PathBuf},
+  time::Instant,
+};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::config::TrainingConfig;
+
+/// A log file a model was trained on
+#[derive(Debug, Clone, Serialize)]
+pub struct InputFile {
+  channel: String,
+  file: String,
+  bytes: usize,
+  sha256: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Machine {
+  hostname: Option<String>,
+  os: &'static str,
+  arch: &'static str,
+  cpus: usize,
+}
+
+impl Machine {
+  fn current() -> Self {
+    Self {
+      hostname: std::env::var("HOSTNAME")
+        .or_else(|_| std::env::var("COMPUTERNAME"))
+        .ok(),
+      os: std::env::consts::OS,
+      arch: std::env::consts::ARCH,
+      cpus: std::thread::available_parallelism().map_or(1, |n| n.get()),
+    }
+  }
+}
+
+/// The training of the models of a channel, or of the model of all channels
+pub struct Run {
+  started_at: DateTime<Utc>,
+  started: Instant,
+  /// Log files read so far, by channel and file name
+  inputs: BTreeMap<(String, String), InputFile>,
+  /// SHA-256 of `config.model_to_fine_tune`
+  fine_tuned_from: Option<String>,
+}
+
+impl Run {
+  pub fn start(fine_tuned_from: Option<&str>) -> Self {
+    Self {
+      started_at: Utc::now(),
+      started: Instant::now(),
+      inputs: BTreeMap::new(),
+      fine_tuned_from: fine_tuned_from.map(String::from),
+    }
+  }
+
+  /// Records that the models were trained on the log `file` of `channel`
+  pub fn input(&mut self, channel: &str, file: &str, contents: &str) {
+    self.inputs.insert(
+      (channel.to_owned(), file.to_owned()),
+      InputFile {
+        channel: channel.to_owned(),
+        file: file.to_owned(),
+        bytes: contents.len(),
+        sha256: hex::encode(Sha256::digest(contents.as_bytes())),
+      },
+    );
+  }
+}
+
+#[derive(Serialize)]
+struct Manifest<'a> {
+  model: &'a str,
+  order: usize,
+  started_at: DateTime<Utc>,
+  finished_at: DateTime<Utc>,
+  duration_secs: f64,
+  version: &'static str,
+  /// Set with the `SCS_GIT_COMMIT` environment variable when the trainer is built
+  git_commit: Option<&'static str>,
+  machine: Machine,
+  config: &'a TrainingConfig,
+  /// Short hash of the contents of `config.phrase_blocklist`
+  phrase_blocklist: Option<&'a str>,
+  fine_tuned_from: Option<&'a str>,
+  /// Hash of the model the delta was saved against, in incremental mode
+  base_checkpoint: Option<String>,
+  /// Sorted by channel and file name
+  inputs: Vec<&'a InputFile>,
+}
+
+/// Renders the manifest of the model called `name` trained during `run`, along with the short hash stored in the
+/// model's metadata
+pub fn render(
+  config: &TrainingConfig,
+  run: &Run,
+  name: &str,
+  order: usize,
+  base_hash: Option<u64>,
+) -> anyhow::Result<(String, String)> {
+  let manifest = Manifest {
+    model: name,
+    order,
+    started_at: run.started_at,
+    finished_at: Utc::now(),
+    duration_secs: run.started.elapsed().as_secs_f64(),
+    version: env!("CARGO_PKG_VERSION"),
+    git_commit: option_env!("SCS_GIT_COMMIT"),
+    machine: Machine::current(),
+    config,
+    phrase_blocklist: config.phrase_blocklist_hash(),
+    fine_tuned_from: run.fine_tuned_from.as_deref(),
+    base_checkpoint: base_hash.map(|hash| format!("{hash:016x}")),
+    inputs: run.inputs.values().collect(),
+  };
+  let json = serde_json::to_string_pretty(&manifest)?;
+  let hash = scs_config::hash(&json);
+  Ok((json, hash))
+}
+
+/// Path of the manifest of the model or delta at `path`
+pub fn path(model: &Path) -> PathBuf {
+  if model.extension() == Some(OsStr::new("chain")) {
+    let mut path = model.as_os_str().to_owned();
+    path.push(".manifest.json");
+    PathBuf::from(path)
+  } else {
+    model.with_extension("manifest.json")
+  }
+}
+
+/// SHA-256 of the file at `path`
+pub fn hash_file(path: &Path) -> anyhow::Result<String> {
+  Ok(hex::encode(Sha256::digest(std::fs::read(path)?)))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_paths() {
+    assert_eq!(
+      path(Path::new("models/forsen.chain")),
+      Path::new("models/forsen.chain.manifest.json")
+    );
+    assert_eq!(
+      path(Path::new("models/forsen-order3.chain")),
+      Path::new("models/forsen-order3.chain.manifest.json")
+    );
+    assert_eq!(
+      path(Path::new("models/forsen.chain.0001.delta")),
+      Path::new("models/forsen.chain.0001.manifest.json")
+    );
+  }
+
+  #[test]
+  fn test_inputs_are_sorted_and_hashed() {
+    let mut run = Run::start(None);
+    run.input("b", "b-2022-01-01.log", "x,hi\n");
+    run.input("a", "a-2022-01-02.log", "");
+    run.input("b", "b-2022-01-01.log", "x,hi\n");
+    let inputs = run.inputs.values().collect::<Vec<_>>();
+    let files = inputs.iter().map(|input| input.file.as_str()).collect::<Vec<_>>();
+    assert_eq!(files, vec!["a-2022-01-02.log", "b-2022-01-01.log"]);
+    assert_eq!(
+      inputs[0].sha256,
+      "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    );
+  }
+}
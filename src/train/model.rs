@@ -0,0 +1,111 @@
+use std::path::{Path, PathBuf};
+
+/// Orders of the chains the trainer can produce
+pub const SUPPORTED_ORDERS: &[usize] = &[1, 2, 3];
+
+/// A chain of any of the [`SUPPORTED_ORDERS`]
+#[derive(Clone)]
+pub enum Model {
+  Order1(chain::Chain<1>),
+  Order2(chain::Chain<2>),
+  Order3(chain::Chain<3>),
+}
+
+macro_rules! dispatch {
+  ($self:expr, $chain:ident => $body:expr) => {
+    match $self {
+      Model::Order1($chain) => $body,
+      Model::Order2($chain) => $body,
+      Model::Order3($chain) => $body,
+    }
+  };
+}
+
+macro_rules! construct {
+  ($order:expr, $chain:ident => $body:expr) => {
+    match $order {
+      1 => Model::Order1({
+        type $chain = chain::Chain<1>;
+        $body
+      }),
+      2 => Model::Order2({
+        type $chain = chain::Chain<2>;
+        $body
+      }),
+      3 => Model::Order3({
+        type $chain = chain::Chain<3>;
+        $body
+      }),
+      order => anyhow::bail!("Unsupported chain order: {order}"),
+    }
+  };
+}
+
+impl Model {
+  pub fn new(order: usize) -> anyhow::Result<Self> {
+    Ok(construct!(order, C => C::new()))
+  }
+
+  pub fn load(path: &Path, order: usize) -> anyhow::Result<Self> {
+    Ok(construct!(order, C => C::load(path)?))
+  }
+
+  /// Loads the model at `path` with its deltas, along with the hash of its checkpoint.
+  pub fn load_with_deltas(path: &Path, order: usize) -> anyhow::Result<(Self, u64)> {
+    let mut base_hash = 0;
+    let model = construct!(order, C => {
+      let (chain, hash) = C::load_with_deltas(path)?;
+      base_hash = hash;
+      chain
+    });
+    Ok((model, base_hash))
+  }
+
+  pub fn order(&self) -> usize {
+    match self {
+      Model::Order1(_) => 1,
+      Model::Order2(_) => 2,
+      Model::Order3(_) => 3,
+    }
+  }
+
+  pub fn with_metadata(self, metadata: impl Into<String>) -> Self {
+    let metadata = metadata.into();
+    match self {
+      Model::Order1(chain) => Model::Order1(chain.with_metadata(metadata)),
+      Model::Order2(chain) => Model::Order2(chain.with_metadata(metadata)),
+      Model::Order3(chain) => Model::Order3(chain.with_metadata(metadata)),
+    }
+  }
+
+  pub fn set_metadata(&mut self, metadata: impl Into<String>) {
+    let metadata = metadata.into();
+    dispatch!(self, chain => *chain = std::mem::replace(chain, chain::Chain::new()).with_metadata(metadata))
+  }
+
+  pub fn metadata(&self) -> &str {
+    use chain::TextGenerator;
+    dispatch!(self, chain => chain.model_meta_data())
+  }
+
+  #[inline]
+  pub fn feed_str(&mut self, s: &str) {
+    dispatch!(self, chain => chain.feed_str(s))
+  }
+
+  pub fn feed_parallel(&mut self, corpus: &[String], n_threads: usize) {
+    dispatch!(self, chain => chain.feed_parallel(corpus, n_threads))
+  }
+
+  pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+    dispatch!(self, chain => chain.save(path))
+  }
+
+  pub fn save_delta(&mut self, path: &Path, base_hash: u64) -> anyhow::Result<PathBuf> {
+    dispatch!(self, chain => chain.save_delta(path, base_hash))
+  }
+
+  pub fn bigram_fingerprint(&self, size: usize) -> chain::Fingerprint {
+    dispatch!(self, chain => chain.bigram_fingerprint(size))
+  }
+}
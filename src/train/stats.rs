@@ -0,0 +1,177 @@
+//! Statistics of the corpus a model was trained on, saved next to the model as `<name>.stats.json`.
+//!
+//! They only cover the logs read by the run which saved the model, so in incremental mode they describe the
+//! latest delta, and a resumed run only counts the files read after the checkpoint. The report's `partial` field
+//! is set in both cases, since the counts can't be merged with the ones of earlier runs without their full vocabulary
+//! and chatter sets.
+
+use std::{
+  collections::{BTreeMap, HashMap, HashSet},
+  path::Path,
+};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use scs_config::sinks::LogFileName;
+use serde::Serialize;
+
+/// Number of most frequent tokens included in the statistics
+const TOP_TOKENS: usize = 50;
+
+#[derive(Debug, Default)]
+pub struct CorpusStats {
+  messages: usize,
+  chatters: HashSet<String>,
+  tokens: usize,
+  vocabulary: HashMap<String, usize>,
+  channels: BTreeMap<String, ChannelCoverage>,
+  /// Number of logs skipped because a resumed run was already trained on them
+  skipped_files: usize,
+}
+
+/// Logs read from a single source channel
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ChannelCoverage {
+  pub files: usize,
+  pub messages: usize,
+  /// Date of the oldest log, from its file name
+  pub first_date: Option<NaiveDate>,
+  /// Date of the newest log, from its file name
+  pub last_date: Option<NaiveDate>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenCount<'a> {
+  pub token: &'a str,
+  pub count: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct Report<'a> {
+  generated_at: DateTime<Utc>,
+  /// Whether the model was also trained on logs which aren't counted, see the module docs
+  partial: bool,
+  messages: usize,
+  unique_chatters: usize,
+  tokens: usize,
+  vocabulary_size: usize,
+  top_tokens: Vec<TokenCount<'a>>,
+  channels: &'a BTreeMap<String, ChannelCoverage>,
+}
+
+/// Parses the date of a log file name, e.g. `forsen-2023-07-01.log` or `forsen-2023-07-01-13.log`
+fn date_of(filename: &str) -> Option<NaiveDate> {
+  let name = LogFileName::parse(filename)?;
+  NaiveDate::parse_from_str(name.date, "%Y-%m-%d").ok()
+}
+
+impl CorpusStats {
+  /// Records that the log `filename` of `channel` is being read
+  pub fn file(&mut self, channel: &str, filename: &str) {
+    let coverage = self.channels.entry(channel.to_owned()).or_default();
+    coverage.files += 1;
+    if let Some(date) = date_of(filename) {
+      coverage.first_date = Some(coverage.first_date.map_or(date, |first| first.min(date)));
+      coverage.last_date = Some(coverage.last_date.map_or(date, |last| last.max(date)));
+    }
+  }
+
+  /// Records that a log was skipped because the checkpointed models were already trained on it
+  pub fn skip_file(&mut self) {
+    self.skipped_files += 1;
+  }
+
+  /// Records a message fed to the models, before any tags are added to it
+  pub fn message(&mut self, channel: &str, chatter: &str, message: &str) {
+    self.messages += 1;
+    if !self.chatters.contains(chatter) {
+      self.chatters.insert(chatter.to_owned());
+    }
+    self.channels.entry(channel.to_owned()).or_default().messages += 1;
+    for token in message.split_whitespace() {
+      self.tokens += 1;
+      match self.vocabulary.get_mut(token) {
+        Some(count) => *count += 1,
+        None => {
+          self.vocabulary.insert(token.to_owned(), 1);
+        }
+      }
+    }
+  }
+
+  /// The `TOP_TOKENS` most frequent tokens, most frequent first. Ties are ordered alphabetically.
+  pub fn top_tokens(&self) -> Vec<TokenCount<'_>> {
+    let mut tokens = self
+      .vocabulary
+      .iter()
+      .map(|(token, &count)| TokenCount { token, count })
+      .collect::<Vec<_>>();
+    tokens.sort_unstable_by(|a, b| b.count.cmp(&a.count).then_with(|| a.token.cmp(b.token)));
+    tokens.truncate(TOP_TOKENS);
+    tokens
+  }
+
+  /// Whether the statistics leave out logs the model was trained on, `incremental` if it continues an existing model
+  pub fn is_partial(&self, incremental: bool) -> bool {
+    incremental || self.skipped_files > 0
+  }
+
+  /// Writes the statistics to `<name>.stats.json` in `directory`, `incremental` if the model continues an existing one
+  pub fn save(&self, directory: &Path, name: &str, incremental: bool) -> anyhow::Result<()> {
+    let report = Report {
+      generated_at: Utc::now(),
+      partial: self.is_partial(incremental),
+      messages: self.messages,
+      unique_chatters: self.chatters.len(),
+      tokens: self.tokens,
+      vocabulary_size: self.vocabulary.len(),
+      top_tokens: self.top_tokens(),
+      channels: &self.channels,
+    };
+    std::fs::write(
+      directory.join(format!("{}.stats.json", name)),
+      serde_json::to_string_pretty(&report)?,
+    )?;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_corpus_stats() {
+    let mut stats = CorpusStats::default();
+    stats.file("forsen", "forsen-2023-07-02.log");
+    stats.message("forsen", "a", "hello chat");
+    stats.message("forsen", "b", "hello  forsen");
+    stats.file("forsen", "forsen-2023-07-01.log");
+    stats.message("forsen", "a", "LUL");
+    stats.file("forsen", "forsen-2023-07-03-13.log");
+    stats.file("xqc", "xqc.log");
+
+    assert_eq!(stats.messages, 3);
+    assert_eq!(stats.chatters.len(), 2);
+    assert_eq!(stats.tokens, 5);
+    assert_eq!(stats.vocabulary.len(), 4);
+
+    let top = stats.top_tokens();
+    assert_eq!((top[0].token, top[0].count), ("hello", 2));
+    assert_eq!(
+      top.iter().map(|t| t.token).collect::<Vec<_>>(),
+      vec!["hello", "LUL", "chat", "forsen"]
+    );
+
+    let forsen = &stats.channels["forsen"];
+    assert_eq!((forsen.files, forsen.messages), (3, 3));
+    assert_eq!(forsen.first_date, NaiveDate::from_ymd_opt(2023, 7, 1));
+    assert_eq!(forsen.last_date, NaiveDate::from_ymd_opt(2023, 7, 3));
+    let xqc = &stats.channels["xqc"];
+    assert_eq!((xqc.files, xqc.messages, xqc.first_date), (1, 0, None));
+
+    assert!(!stats.is_partial(false));
+    assert!(stats.is_partial(true));
+    stats.skip_file();
+    assert!(stats.is_partial(false));
+  }
+}